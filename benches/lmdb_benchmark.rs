@@ -228,24 +228,20 @@ fn main() {
 
     let redb_latency_results = {
         let tmpfile: NamedTempFile = NamedTempFile::new_in(&tmpdir).unwrap();
-        let db = unsafe {
-            redb::Database::builder()
-                .set_write_strategy(WriteStrategy::Checksum)
-                .create(tmpfile.path())
-                .unwrap()
-        };
+        let db = redb::Database::builder()
+            .set_write_strategy(WriteStrategy::Checksum)
+            .create(tmpfile.path())
+            .unwrap();
         let table = RedbBenchDatabase::new(&db);
         benchmark(table)
     };
 
     let redb_throughput_results = {
         let tmpfile: NamedTempFile = NamedTempFile::new_in(&tmpdir).unwrap();
-        let db = unsafe {
-            redb::Database::builder()
-                .set_write_strategy(WriteStrategy::TwoPhase)
-                .create(tmpfile.path())
-                .unwrap()
-        };
+        let db = redb::Database::builder()
+            .set_write_strategy(WriteStrategy::TwoPhase)
+            .create(tmpfile.path())
+            .unwrap();
         let table = RedbBenchDatabase::new(&db);
         benchmark(table)
     };
@@ -0,0 +1,98 @@
+use redb::{Database, ReadableTable, TableDefinition};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct Counting;
+static ALLOCS: AtomicUsize = AtomicUsize::new(0);
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, l: Layout) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(l)
+    }
+    unsafe fn dealloc(&self, p: *mut u8, l: Layout) {
+        System.dealloc(p, l)
+    }
+    unsafe fn realloc(&self, p: *mut u8, l: Layout, n: usize) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(p, l, n)
+    }
+}
+#[global_allocator]
+static A: Counting = Counting;
+
+const U64: TableDefinition<u64, &[u8]> = TableDefinition::new("u64");
+const STR: TableDefinition<&str, (u64, &str)> = TableDefinition::new("str");
+const N: u64 = 100_000;
+
+fn count(name: &str, n: u64, f: impl FnOnce()) {
+    let before = ALLOCS.load(Ordering::Relaxed);
+    f();
+    let allocs = ALLOCS.load(Ordering::Relaxed) - before;
+    println!(
+        "{:<40} {:>9} allocations, {:.3} per op",
+        name,
+        allocs,
+        allocs as f64 / n as f64
+    );
+}
+
+fn main() {
+    let tmpfile = tempfile::NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let value = [1u8; 64];
+    let keys: Vec<u64> = (0..N)
+        .map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+        .collect();
+    let strs: Vec<String> = keys.iter().map(|k| format!("key{}", k)).collect();
+    let txn = db.begin_write().unwrap();
+    txn.open_table(U64).unwrap();
+    txn.open_table(STR).unwrap();
+    txn.commit().unwrap();
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64).unwrap();
+        count("random insert", N, || {
+            for k in keys.iter() {
+                table.insert(k, value.as_slice()).unwrap();
+            }
+        });
+        let mut table = txn.open_table(STR).unwrap();
+        count("random insert, tuple value", N, || {
+            for (k, s) in keys.iter().zip(strs.iter()) {
+                table.insert(s.as_str(), &(*k, s.as_str())).unwrap();
+            }
+        });
+    }
+    count("commit", 1, || txn.commit().unwrap());
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64).unwrap();
+        count("overwrite committed keys", N, || {
+            for k in keys.iter() {
+                table.insert(k, value.as_slice()).unwrap();
+            }
+        });
+        count("range scan, write txn (per entry)", N, || {
+            let mut n = 0;
+            for entry in table.iter().unwrap() {
+                n += entry.1.len();
+            }
+            assert!(n > 0);
+        });
+        count("short range scans, write txn (per scan)", 10_000, || {
+            for k in keys.iter().take(10_000) {
+                let mut iter = table.range::<u64>(k..).unwrap();
+                iter.next();
+                iter.next();
+            }
+        });
+        count("remove", N / 2, || {
+            for k in keys.iter().take((N / 2) as usize) {
+                table.remove(k).unwrap();
+            }
+        });
+    }
+    count("commit, after freeing pages", 1, || txn.commit().unwrap());
+}
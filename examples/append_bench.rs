@@ -0,0 +1,34 @@
+use redb::{Database, TableDefinition};
+use std::time::Instant;
+
+const TABLE: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+
+fn main() {
+    let value = [1u8; 64];
+    for &(txns, per_txn) in &[(1u64, 1_000_000u64), (100, 10_000)] {
+        let mut best = f64::MAX;
+        for _ in 0..5 {
+            let tmpfile = tempfile::NamedTempFile::new().unwrap();
+            let db = Database::create(tmpfile.path()).unwrap();
+            let start = Instant::now();
+            for t in 0..txns {
+                let txn = db.begin_write().unwrap();
+                {
+                    let mut table = txn.open_table(TABLE).unwrap();
+                    for i in (t * per_txn)..((t + 1) * per_txn) {
+                        table.insert(&i, value.as_slice()).unwrap();
+                    }
+                }
+                txn.commit().unwrap();
+            }
+            best = best.min(start.elapsed().as_secs_f64());
+        }
+        println!(
+            "{} txns x {} sequential inserts: {:.0}ms ({:.0}ns/insert)",
+            txns,
+            per_txn,
+            best * 1000.0,
+            best * 1e9 / (txns * per_txn) as f64
+        );
+    }
+}
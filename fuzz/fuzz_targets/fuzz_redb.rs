@@ -17,12 +17,22 @@ const TABLE_DEF: TableDefinition<u64, &[u8]> = TableDefinition::new("fuzz_table"
 const MULTIMAP_TABLE_DEF: MultimapTableDefinition<u64, &[u8]> =
     MultimapTableDefinition::new("fuzz_multimap_table");
 
-fn exec_table(db: Arc<Database>, transactions: &[FuzzTransaction], reference: Arc<Mutex<BTreeMap<u64, usize>>>, barrier: Arc<CustomBarrier>) {
+fn exec_table(
+    db: Arc<Database>,
+    transactions: &[FuzzTransaction],
+    reference: Arc<Mutex<BTreeMap<u64, usize>>>,
+    barrier: Arc<CustomBarrier>,
+) {
     exec_table_inner(db, &transactions, reference, barrier.clone()).unwrap();
     barrier.decrement_waiters();
 }
 
-fn exec_table_inner(db: Arc<Database>, transactions: &[FuzzTransaction], reference: Arc<Mutex<BTreeMap<u64, usize>>>, barrier: Arc<CustomBarrier>) -> Result<(), redb::Error> {
+fn exec_table_inner(
+    db: Arc<Database>,
+    transactions: &[FuzzTransaction],
+    reference: Arc<Mutex<BTreeMap<u64, usize>>>,
+    barrier: Arc<CustomBarrier>,
+) -> Result<(), redb::Error> {
     let mut savepoints = vec![];
     let mut reference_savepoints = vec![];
 
@@ -155,12 +165,22 @@ fn assert_multimap_value_eq(
     assert!(iter.next().is_none());
 }
 
-fn exec_multimap_table(db: Arc<Database>, transactions: &[FuzzTransaction], reference: Arc<Mutex<BTreeMap<u64, BTreeSet<usize>>>>, barrier: Arc<CustomBarrier>) {
+fn exec_multimap_table(
+    db: Arc<Database>,
+    transactions: &[FuzzTransaction],
+    reference: Arc<Mutex<BTreeMap<u64, BTreeSet<usize>>>>,
+    barrier: Arc<CustomBarrier>,
+) {
     exec_multimap_table_inner(db, &transactions, reference, barrier.clone()).unwrap();
     barrier.decrement_waiters();
 }
 
-fn exec_multimap_table_inner(db: Arc<Database>, transactions: &[FuzzTransaction], reference: Arc<Mutex<BTreeMap<u64, BTreeSet<usize>>>>, barrier: Arc<CustomBarrier>) -> Result<(), redb::Error> {
+fn exec_multimap_table_inner(
+    db: Arc<Database>,
+    transactions: &[FuzzTransaction],
+    reference: Arc<Mutex<BTreeMap<u64, BTreeSet<usize>>>>,
+    barrier: Arc<CustomBarrier>,
+) -> Result<(), redb::Error> {
     let mut savepoints = vec![];
     let mut reference_savepoints = vec![];
 
@@ -253,11 +273,12 @@ fn exec_multimap_table_inner(db: Arc<Database>, transactions: &[FuzzTransaction]
                             } else {
                                 Box::new(local_reference.range(start..end))
                             };
-                        let mut iter: Box<dyn Iterator<Item = (u64, MultimapValueIter<&[u8]>)>> = if *reversed {
-                            Box::new(table.range(&start..&end).unwrap().rev())
-                        } else {
-                            Box::new(table.range(&start..&end).unwrap())
-                        };
+                        let mut iter: Box<dyn Iterator<Item = (u64, MultimapValueIter<&[u8]>)>> =
+                            if *reversed {
+                                Box::new(table.range(&start..&end).unwrap().rev())
+                            } else {
+                                Box::new(table.range(&start..&end).unwrap())
+                            };
                         while let Some((ref_key, ref_values)) = reference_iter.next() {
                             let (key, value_iter) = iter.next().unwrap();
                             assert_eq!(*ref_key, key);
@@ -287,12 +308,10 @@ fuzz_target!(|config: FuzzConfig| {
     } else {
         WriteStrategy::TwoPhase
     };
-    let db = unsafe {
-        Database::builder()
-            .set_write_strategy(write_strategy)
-            .set_page_size(config.page_size.value)
-            .create(redb_file.path())
-    };
+    let db = Database::builder()
+        .set_write_strategy(write_strategy)
+        .set_page_size(config.page_size.value)
+        .create(redb_file.path());
 
     let db = Arc::new(db.unwrap());
 
@@ -328,5 +347,4 @@ fuzz_target!(|config: FuzzConfig| {
         assert!(t0.join().is_ok());
         assert!(t1.join().is_ok());
     };
-
 });
@@ -0,0 +1,293 @@
+use crate::table::{RangeIter, ReadableTable, Table};
+use crate::types::RedbKey;
+use crate::Result;
+use std::borrow::Borrow;
+use std::io;
+use std::io::{Read, Write};
+use std::mem;
+
+// Size of the chunks that blobs are split into. This spans many pages, so that each chunk is
+// stored as a single large value
+const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// A table of large values ("blobs"), which are written and read as streams
+///
+/// Each blob is split into chunks, which are stored in a table with key type `(u64, u64)`: the id
+/// of the blob, and the index of the chunk. An index table maps each key to the id of its blob.
+/// Only one chunk is buffered in memory at a time when writing, and chunks are read directly from
+/// the database file, so blobs may be much larger than the available memory.
+///
+/// The chunks table must only be modified through this type.
+///
+/// # Examples
+///
+/// ```rust
+/// use redb::*;
+/// use std::io::{Read, Write};
+/// # use tempfile::NamedTempFile;
+/// const FILES: TableDefinition<&str, u64> = TableDefinition::new("files");
+/// const FILE_CHUNKS: TableDefinition<(u64, u64), &[u8]> = TableDefinition::new("file_chunks");
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+/// # let filename = tmpfile.path();
+/// let db = Database::create(filename)?;
+/// let write_txn = db.begin_write()?;
+/// {
+///     let mut files = BlobTable::new(
+///         write_txn.open_table(FILES)?,
+///         write_txn.open_table(FILE_CHUNKS)?,
+///     );
+///     let mut writer = files.writer("hello.txt")?;
+///     writer.write_all(b"hello ")?;
+///     writer.write_all(b"world")?;
+///     writer.finish()?;
+/// }
+/// write_txn.commit()?;
+///
+/// let read_txn = db.begin_read()?;
+/// let files = read_txn.open_table(FILES)?;
+/// let chunks = read_txn.open_table(FILE_CHUNKS)?;
+/// let mut contents = String::new();
+/// BlobReader::new(&files, &chunks, "hello.txt")?
+///     .unwrap()
+///     .read_to_string(&mut contents)?;
+/// assert_eq!(contents, "hello world");
+/// # Ok(())
+/// # }
+/// ```
+pub struct BlobTable<'db, 'txn, K: RedbKey + ?Sized + 'txn> {
+    index: Table<'db, 'txn, K, u64>,
+    chunks: Table<'db, 'txn, (u64, u64), &'static [u8]>,
+    chunk_size: usize,
+}
+
+impl<'db, 'txn, K: RedbKey + ?Sized + 'txn> BlobTable<'db, 'txn, K> {
+    pub fn new(
+        index: Table<'db, 'txn, K, u64>,
+        chunks: Table<'db, 'txn, (u64, u64), &'static [u8]>,
+    ) -> Self {
+        Self {
+            index,
+            chunks,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// Set the size of the chunks that blobs are split into, when they are written
+    ///
+    /// Defaults to 1MiB
+    pub fn set_chunk_size(&mut self, size: usize) -> &mut Self {
+        assert!(size > 0);
+        self.chunk_size = size;
+        self
+    }
+
+    /// Returns the underlying index and chunks tables
+    #[allow(clippy::type_complexity)]
+    pub fn into_inner(
+        self,
+    ) -> (
+        Table<'db, 'txn, K, u64>,
+        Table<'db, 'txn, (u64, u64), &'static [u8]>,
+    ) {
+        (self.index, self.chunks)
+    }
+
+    /// Returns a writer for the blob with the given key, replacing any existing blob
+    ///
+    /// The blob is complete once [`BlobWriter::finish`] is called, or the writer is dropped
+    pub fn writer<'a, 'b: 'a, AK>(&mut self, key: &'a AK) -> Result<BlobWriter<'_, 'db, 'txn>>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        let id = if let Some(id) = self.index.get(key)? {
+            self.chunks.remove_range((id, 0)..=(id, u64::MAX))?;
+            id
+        } else {
+            let id = match self.chunks.last()? {
+                Some(((last, _), _)) => last.checked_add(1).expect("Blob ids exhausted"),
+                None => 0,
+            };
+            self.index.insert(key, &id)?;
+            id
+        };
+
+        Ok(BlobWriter {
+            chunks: &mut self.chunks,
+            id,
+            next_chunk: 0,
+            buffer: Vec::with_capacity(self.chunk_size),
+            chunk_size: self.chunk_size,
+        })
+    }
+
+    /// Insert a blob with the given key, containing everything read from `reader`
+    ///
+    /// Returns the length of the blob
+    pub fn insert<'a, 'b: 'a, AK>(&mut self, key: &'a AK, mut reader: impl Read) -> Result<u64>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        let mut writer = self.writer(key)?;
+        let len = io::copy(&mut reader, &mut writer)?;
+        writer.finish()?;
+        Ok(len)
+    }
+
+    /// Returns a reader for the blob with the given key
+    pub fn reader<'a, 'b: 'a, AK>(&self, key: &'a AK) -> Result<Option<BlobReader<'_>>>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        BlobReader::new(&self.index, &self.chunks, key)
+    }
+
+    /// Returns the length of the blob with the given key
+    ///
+    /// This visits every chunk of the blob, but does not read their contents
+    pub fn blob_len<'a, 'b: 'a, AK>(&self, key: &'a AK) -> Result<Option<u64>>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        if let Some(id) = self.index.get(key)? {
+            let mut len = 0;
+            let mut iter = self.chunks.range((id, 0)..=(id, u64::MAX))?;
+            while let Some((_, chunk)) = iter.try_next()? {
+                len += u64::try_from(chunk.len()).unwrap();
+            }
+            Ok(Some(len))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Removes the blob with the given key
+    ///
+    /// Returns `true` if the blob was present
+    pub fn remove<'a, 'b: 'a, AK>(&mut self, key: &'a AK) -> Result<bool>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        if let Some(old) = self.index.remove(key)? {
+            let id = old.to_value();
+            self.chunks.remove_range((id, 0)..=(id, u64::MAX))?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Returns the number of blobs in the table
+    pub fn len(&self) -> Result<usize> {
+        self.index.len()
+    }
+
+    /// Returns `true` if the table is empty
+    pub fn is_empty(&self) -> Result<bool> {
+        self.index.is_empty()
+    }
+}
+
+/// Writes a blob to a [`BlobTable`], one chunk at a time
+///
+/// Dropping the writer flushes any buffered data, but ignores errors, so prefer calling
+/// [`BlobWriter::finish`]
+pub struct BlobWriter<'a, 'db, 'txn> {
+    chunks: &'a mut Table<'db, 'txn, (u64, u64), &'static [u8]>,
+    id: u64,
+    next_chunk: u64,
+    buffer: Vec<u8>,
+    chunk_size: usize,
+}
+
+impl<'a, 'db, 'txn> BlobWriter<'a, 'db, 'txn> {
+    /// Writes any buffered data to the table
+    pub fn finish(mut self) -> Result {
+        self.write_chunk()
+    }
+
+    fn write_chunk(&mut self) -> Result {
+        // Empty blobs are stored as a single empty chunk
+        if self.buffer.is_empty() && self.next_chunk > 0 {
+            return Ok(());
+        }
+        self.chunks
+            .insert(&(self.id, self.next_chunk), self.buffer.as_slice())?;
+        self.next_chunk += 1;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+impl<'a, 'db, 'txn> Write for BlobWriter<'a, 'db, 'txn> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.chunk_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() == self.chunk_size {
+            self.write_chunk()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a, 'db, 'txn> Drop for BlobWriter<'a, 'db, 'txn> {
+    fn drop(&mut self) {
+        let _ = self.write_chunk();
+    }
+}
+
+/// Reads a blob, one chunk at a time, directly from the database file
+pub struct BlobReader<'a> {
+    chunks: RangeIter<'a, (u64, u64), &'static [u8]>,
+    current: &'a [u8],
+}
+
+impl<'a> BlobReader<'a> {
+    /// Returns a reader for the blob with the given key, from tables written by a [`BlobTable`]
+    ///
+    /// This can be used to read blobs in a read transaction
+    pub fn new<'b, 'c: 'b, K, AK>(
+        index: &impl ReadableTable<K, u64>,
+        chunks: &'a impl ReadableTable<(u64, u64), &'static [u8]>,
+        key: &'b AK,
+    ) -> Result<Option<Self>>
+    where
+        K: RedbKey + ?Sized + 'c,
+        AK: Borrow<K::RefBaseType<'c>> + ?Sized,
+    {
+        if let Some(id) = index.get(key)? {
+            Ok(Some(Self {
+                chunks: chunks.range((id, 0)..=(id, u64::MAX))?,
+                current: &[],
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<'a> Read for BlobReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.current.is_empty() {
+            match self.chunks.try_next()? {
+                Some((_, chunk)) => self.current = chunk,
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.current.len());
+        let (head, tail) = mem::take(&mut self.current).split_at(len);
+        buf[..len].copy_from_slice(head);
+        self.current = tail;
+        Ok(len)
+    }
+}
@@ -1,25 +1,45 @@
+use crate::error::IoResultExt;
+use crate::key_changes::{KeyChangeBroadcaster, KeyChangeReceiver};
+use crate::simulation::Clock;
 use crate::transaction_tracker::{SavepointId, TransactionId, TransactionTracker};
 use crate::tree_store::{
-    AllPageNumbersBtreeIter, BtreeRangeIter, FreedTableKey, InternalTableDefinition, RawBtree,
-    TableType, TransactionalMemory,
+    check_integrity, find_corrupted_tables, make_sparse, parse_table_name,
+    read_file_format_version, upgrade_file_format, AllPageNumbersBtreeIter, BtreeRangeIter,
+    FreedTableKey, IntegrityReport, InternalTableDefinition, RawBtree, Savepoint, Scrubber,
+    Storage, TableTree, TableType, TransactionalMemory,
 };
 use crate::types::{RedbKey, RedbValue};
-use crate::Error;
-use crate::{ReadTransaction, Result, WriteTransaction};
+#[cfg(feature = "fault_injection")]
+use crate::FaultInjector;
+use crate::{
+    CommitStats, OwnedReadTransaction, OwnedWriteTransaction, ReadTransaction, ReadTransactionInfo,
+    Result, StorageDevice, WriteBatch, WriteTransaction,
+};
+use crate::{Error, IoOperation};
+use std::cell::RefCell;
+use std::cmp::min;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fmt::{Display, Formatter};
 use std::fs::{File, OpenOptions};
 use std::io;
 use std::io::ErrorKind;
 use std::marker::PhantomData;
+use std::mem;
 use std::ops::RangeFull;
 use std::path::Path;
+use std::rc::Rc;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
 
 use crate::multimap_table::parse_subtree_roots;
+use crate::tree_store::{Btree, Checksum, PageNumber};
 #[cfg(feature = "logging")]
 use log::{info, warn};
 
+// Internal table which stores the persistent savepoints. Maps savepoint id -> serialized savepoint
+pub(crate) const PERSISTENT_SAVEPOINT_TABLE: &str = "redb::persistent_savepoints";
+
 struct AtomicTransactionId {
     inner: AtomicU64,
 }
@@ -42,6 +62,7 @@ impl AtomicTransactionId {
 /// A [`TableDefinition`] should be opened for use by calling [`ReadTransaction::open_table`] or [`WriteTransaction::open_table`]
 pub struct TableDefinition<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized> {
     name: &'a str,
+    counted: bool,
     _key_type: PhantomData<K>,
     _value_type: PhantomData<V>,
 }
@@ -51,6 +72,22 @@ impl<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized> TableDefinition<'a, K, V> {
         assert!(!name.is_empty());
         Self {
             name,
+            counted: false,
+            _key_type: PhantomData,
+            _value_type: PhantomData,
+        }
+    }
+
+    /// Store the number of entries beneath each child of the table's branch pages, so that
+    /// [`ReadableTable::len`], [`ReadableTable::count_range`], [`ReadableTable::nth`], and
+    /// [`ReadableTable::rank`] take O(log n) time, at the cost of some extra space and work on
+    /// each write
+    ///
+    /// This only takes effect when the table is created, and is ignored if it already exists
+    pub const fn with_entry_counts(self) -> Self {
+        Self {
+            name: self.name,
+            counted: true,
             _key_type: PhantomData,
             _value_type: PhantomData,
         }
@@ -59,6 +96,10 @@ impl<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized> TableDefinition<'a, K, V> {
     pub fn name(&self) -> &str {
         self.name
     }
+
+    pub(crate) fn is_counted(&self) -> bool {
+        self.counted
+    }
 }
 
 impl<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized> Clone for TableDefinition<'a, K, V> {
@@ -148,7 +189,7 @@ impl<'a, K: RedbKey + ?Sized, V: RedbKey + ?Sized> Display for MultimapTableDefi
 /// # fn main() -> Result<(), Error> {
 /// # let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 /// # let filename = tmpfile.path();
-/// let db = unsafe { Database::create(filename)? };
+/// let db = Database::create(filename)?;
 /// let write_txn = db.begin_write()?;
 /// {
 ///     let mut table = write_txn.open_table(TABLE)?;
@@ -162,7 +203,83 @@ pub struct Database {
     mem: TransactionalMemory,
     next_transaction_id: AtomicTransactionId,
     transaction_tracker: Arc<Mutex<TransactionTracker>>,
-    pub(crate) live_write_transaction: Mutex<Option<TransactionId>>,
+    live_write_transaction: Mutex<Option<TransactionId>>,
+    compaction_policy: Option<CompactionPolicy>,
+    last_compaction: Mutex<Instant>,
+    clock: Clock,
+    key_changes: KeyChangeBroadcaster,
+    merge_operators: HashMap<String, MergeOperator>,
+    retention_policy: Option<RetentionPolicy>,
+    write_transaction_memory_limit: Option<u64>,
+    long_read_transaction_hook: Option<(Duration, LongReadTransactionHook)>,
+    // Snapshots which are retained by the retention policy, oldest first. Each holds a read
+    // transaction, so that its pages are not freed
+    retained_snapshots: Mutex<VecDeque<RetainedSnapshot>>,
+    // Callbacks of transactions which were committed, but are not yet durable
+    deferred_commit_callbacks: Mutex<Vec<CommitCallback>>,
+    counters: Counters,
+}
+
+// Totals reported by Database::metrics(), since the database was opened
+#[derive(Default)]
+pub(crate) struct Counters {
+    commits: AtomicU64,
+    aborts: AtomicU64,
+    pages_allocated: AtomicU64,
+    pages_freed: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn record_commit(&self, stats: &CommitStats) {
+        self.commits.fetch_add(1, Ordering::Relaxed);
+        self.pages_allocated
+            .fetch_add(stats.pages_allocated(), Ordering::Relaxed);
+        self.pages_freed
+            .fetch_add(stats.pages_freed(), Ordering::Relaxed);
+        self.bytes_written
+            .fetch_add(stats.bytes_written(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_abort(&self) {
+        self.aborts.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+struct RetainedSnapshot {
+    transaction_id: TransactionId,
+    root: Option<(PageNumber, Checksum)>,
+    committed: Instant,
+}
+
+/// The result of [`Database::repair`]
+#[derive(Clone, Debug)]
+pub struct RepairReport {
+    transaction_id: u64,
+    discarded_transaction_id: Option<u64>,
+    discarded_tables: Vec<String>,
+}
+
+impl RepairReport {
+    /// Returns the id of the commit which the database was recovered to
+    pub fn transaction_id(&self) -> u64 {
+        self.transaction_id
+    }
+
+    /// Returns the id of the latest commit, if it was discarded because it was corrupted
+    pub fn discarded_transaction_id(&self) -> Option<u64> {
+        self.discarded_transaction_id
+    }
+
+    /// Returns the names of the tables which were corrupted, and have been deleted
+    pub fn discarded_tables(&self) -> &[String] {
+        &self.discarded_tables
+    }
+
+    /// Returns `true` if no data was discarded
+    pub fn is_lossless(&self) -> bool {
+        self.discarded_transaction_id.is_none() && self.discarded_tables.is_empty()
+    }
 }
 
 impl Database {
@@ -171,66 +288,181 @@ impl Database {
     /// * if the file is a valid redb database, it will be opened
     /// * otherwise this function will return an error
     ///
-    /// # Safety
+    /// There is no maximum size: the file, and its mapping, grow on demand as data is written,
+    /// and shrink when space at the end of the file is freed. On Windows, the file never shrinks,
+    /// since a file can't be truncated while it's mapped. See [`Builder::set_initial_size`] to
+    /// preallocate space
+    ///
+    /// The file is locked while the database is open, so opening it again, from this or another
+    /// process, returns [`Error::LockContention`]. The lock is advisory, and does not protect
+    /// against other programs which modify the file without taking it
+    pub fn open_or_create(path: impl AsRef<Path>) -> Result<Database> {
+        Self::builder().open_or_create(path)
+    }
+
+    /// Same as [`Database::open_or_create`]
     ///
-    /// The file referenced by `path` must not be concurrently modified by any other process
-    pub unsafe fn create(path: impl AsRef<Path>) -> Result<Database> {
-        Self::builder().create(path)
+    /// Prefer [`Database::open`] or [`Database::create_new`], which cannot accidentally create an
+    /// empty database at a mistyped path, or open an existing one
+    pub fn create(path: impl AsRef<Path>) -> Result<Database> {
+        Self::builder().open_or_create(path)
     }
 
     /// Opens an existing redb database.
     ///
-    /// # Safety
+    /// Returns an [`io::ErrorKind::NotFound`] error if the file does not exist, and an
+    /// [`io::ErrorKind::InvalidData`] error if it is empty
+    pub fn open(path: impl AsRef<Path>) -> Result<Database> {
+        Self::builder().open(path)
+    }
+
+    /// Creates a new redb database
+    ///
+    /// Returns an [`io::ErrorKind::AlreadyExists`] error if the file already exists
+    pub fn create_new(path: impl AsRef<Path>) -> Result<Database> {
+        Self::builder().create_new(path)
+    }
+
+    /// Repairs a corrupted database, which must not be open
     ///
-    /// The file referenced by `path` must not be concurrently modified by any other process
-    pub unsafe fn open(path: impl AsRef<Path>) -> Result<Database> {
-        if !path.as_ref().exists() {
-            Err(Error::Io(ErrorKind::NotFound.into()))
-        } else if File::open(path.as_ref())?.metadata()?.len() > 0 {
-            let file = OpenOptions::new().read(true).write(true).open(path)?;
-            Database::new(file, None, None, None, None)
+    /// The database is rolled back to the latest commit in which every table is intact. If there
+    /// is no such commit, the tables of the latest commit whose table index is intact are
+    /// salvaged, and those which are corrupted are deleted. The returned [`RepairReport`] lists
+    /// what was discarded. Returns [`Error::Corrupted`] if nothing can be recovered.
+    ///
+    /// Corruption is only detected in databases which use [`WriteStrategy::Checksum`]. All
+    /// persistent savepoints are deleted
+    pub fn repair(path: impl AsRef<Path>) -> Result<RepairReport> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path.as_ref())
+            .during(IoOperation::Open)?;
+        let mut mem = TransactionalMemory::new(
+            Storage::File(file),
+            None,
+            None,
+            None,
+            None,
+            true,
+            StorageBackend::Mmap,
+        )?;
+
+        let latest = mem
+            .latest_intact_commit()?
+            .ok_or_else(|| Error::corrupted("Both commit slots are corrupted"))?;
+        let mut discarded_tables = vec![];
+        let recovered = if let Some(id) = mem.select_valid_commit(|mem| {
+            Ok(matches!(find_corrupted_tables(mem)?, Some(tables) if tables.is_empty()))
+        })? {
+            id
+        } else if let Some(id) =
+            mem.select_valid_commit(|mem| Ok(find_corrupted_tables(mem)?.is_some()))?
+        {
+            discarded_tables = find_corrupted_tables(&mem)?.unwrap();
+            id
         } else {
-            Err(Error::Io(io::Error::from(ErrorKind::InvalidData)))
+            return Err(Error::corrupted(
+                "The table index of every commit is corrupted",
+            ));
+        };
+
+        mem.begin_forced_repair()?;
+        Self::rebuild_allocator_state(&mut mem, &discarded_tables, false)?;
+        drop(mem);
+
+        // The pages of the discarded tables, and of the persistent savepoints, were not marked as
+        // allocated, so remove all references to them
+        let db = Self::open(path)?;
+        let txn = db.begin_write()?;
+        for name in discarded_tables.iter() {
+            txn.discard_table(name)?;
         }
+        txn.delete_persistent_savepoints()?;
+        txn.commit()?;
+
+        Ok(RepairReport {
+            transaction_id: recovered.0,
+            discarded_transaction_id: if latest > recovered {
+                Some(latest.0)
+            } else {
+                None
+            },
+            discarded_tables,
+        })
+    }
+
+    /// Returns the file format version of the database at `path`, without opening it
+    ///
+    /// Returns an [`io::ErrorKind::InvalidData`] error if the file is not a redb database
+    pub fn file_format_version(path: impl AsRef<Path>) -> Result<u8> {
+        let file = File::open(path).during(IoOperation::Open)?;
+        read_file_format_version(&file)
+    }
+
+    /// Upgrades the database at `path`, which must not be open, to the file format version used
+    /// by this release of redb
+    ///
+    /// Opening a file written with an older format returns [`Error::UpgradeRequired`]. Returns
+    /// `false` if the file already uses the current format, and [`Error::Corrupted`] if it uses
+    /// a format which is too old to be upgraded, or one which is newer than this release
+    pub fn upgrade(path: impl AsRef<Path>) -> Result<bool> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .during(IoOperation::Open)?;
+        upgrade_file_format(&file)
     }
 
     pub(crate) fn get_memory(&self) -> &TransactionalMemory {
         &self.mem
     }
 
-    fn verify_primary_checksums(mem: &TransactionalMemory) -> bool {
-        let (root, root_checksum) = mem
-            .get_data_root()
-            .expect("Tried to repair an empty database");
-        if !RawBtree::new(
-            Some((root, root_checksum)),
-            <&str>::fixed_width(),
-            InternalTableDefinition::fixed_width(),
-            mem,
-        )
-        .verify_checksum()
-        {
-            return false;
-        }
-
-        if let Some((freed_root, freed_checksum)) = mem.get_freed_root() {
+    fn verify_primary_checksums(mem: &TransactionalMemory) -> Result<bool> {
+        if let Some((freed_root, freed_checksum)) = mem.get_freed_root()? {
             if !RawBtree::new(
                 Some((freed_root, freed_checksum)),
                 FreedTableKey::fixed_width(),
                 None,
                 mem,
             )
-            .verify_checksum()
+            .verify_checksum()?
             {
-                return false;
+                return Ok(false);
             }
         }
 
+        // A database which has never had a table committed has no data root
+        let (root, root_checksum) = if let Some(root) = mem.get_data_root()? {
+            root
+        } else {
+            return Ok(true);
+        };
+        if !RawBtree::new(
+            Some((root, root_checksum)),
+            <&str>::fixed_width(),
+            InternalTableDefinition::fixed_width(),
+            mem,
+        )
+        .verify_checksum()?
+        {
+            return Ok(false);
+        }
+
         // Iterate over all other tables
-        let iter: BtreeRangeIter<&str, InternalTableDefinition> =
-            BtreeRangeIter::new::<RangeFull, &str>(.., Some(root), mem);
-        for entry in iter {
-            let definition = InternalTableDefinition::from_bytes(entry.value());
+        let mut iter: BtreeRangeIter<&str, InternalTableDefinition> =
+            BtreeRangeIter::new::<RangeFull, &str>(.., Some((root, root_checksum)), mem, None);
+        loop {
+            let definition = match iter.try_next() {
+                Ok(Some(entry)) => match InternalTableDefinition::try_from_bytes(entry.value()) {
+                    Ok(definition) => definition,
+                    Err(_) => return Ok(false),
+                },
+                Ok(None) => break,
+                Err(Error::Corrupted { .. }) => return Ok(false),
+                Err(err) => return Err(err),
+            };
             if let Some((table_root, table_checksum)) = definition.get_root() {
                 if !RawBtree::new(
                     Some((table_root, table_checksum)),
@@ -238,108 +470,263 @@ impl Database {
                     definition.get_fixed_value_size(),
                     mem,
                 )
-                .verify_checksum()
+                .verify_checksum()?
                 {
-                    return false;
+                    return Ok(false);
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    // Adds the pages of the master table with the given root, and of every table in it except for
+    // the discarded tables, to pages
+    fn collect_table_pages(
+        mem: &TransactionalMemory,
+        data_root: Option<(PageNumber, Checksum)>,
+        discarded_tables: &[String],
+        pages: &mut HashSet<PageNumber>,
+    ) -> Result {
+        let (root, root_checksum) = match data_root {
+            Some(root) => root,
+            None => return Ok(()),
+        };
+        // All pages in the master table
+        for page in AllPageNumbersBtreeIter::new(root, None, None, mem)? {
+            pages.insert(page?);
+        }
+
+        // Iterate over all other tables
+        let mut iter: BtreeRangeIter<&str, InternalTableDefinition> =
+            BtreeRangeIter::new::<RangeFull, &str>(.., Some((root, root_checksum)), mem, None);
+        while let Some(entry) = iter.try_next()? {
+            let entry_name = parse_table_name(entry.key())?;
+            if discarded_tables
+                .iter()
+                .any(|name| name.as_str() == entry_name)
+            {
+                continue;
+            }
+            let definition = InternalTableDefinition::try_from_bytes(entry.value())?;
+            if let Some((table_root, _)) = definition.get_root() {
+                let table_pages_iter = AllPageNumbersBtreeIter::new(
+                    table_root,
+                    definition.get_fixed_key_size(),
+                    definition.get_fixed_value_size(),
+                    mem,
+                )?;
+                for table_page in table_pages_iter {
+                    let table_page = table_page?;
+                    pages.insert(table_page);
+
+                    // Multimap tables may have additional subtrees in their values
+                    if definition.get_type() == TableType::Multimap {
+                        let page = mem.get_page(table_page)?;
+                        // The values of the table reference the subtrees, and have no fixed width
+                        let subtree_roots =
+                            parse_subtree_roots(&page, definition.get_fixed_key_size(), None);
+                        drop(page);
+                        for subtree_root in subtree_roots {
+                            let subtree_pages_iter = AllPageNumbersBtreeIter::new(
+                                subtree_root,
+                                definition.get_fixed_value_size(),
+                                <()>::fixed_width(),
+                                mem,
+                            )?;
+                            for page in subtree_pages_iter {
+                                pages.insert(page?);
+                            }
+                        }
+                    }
                 }
             }
         }
 
-        true
+        Ok(())
+    }
+
+    // Rebuilds the allocator state of a database being repaired, by walking all the pages of the
+    // primary commit, except for those of the discarded tables, and commits it.
+    //
+    // If keep_savepoints is true, the pages of the snapshots of the persistent savepoints are
+    // also kept, and those which are not used by the primary commit are returned. They must be
+    // freed once the savepoints are deleted
+    fn rebuild_allocator_state(
+        mem: &mut TransactionalMemory,
+        discarded_tables: &[String],
+        keep_savepoints: bool,
+    ) -> Result<Vec<PageNumber>> {
+        let data_root = mem.get_data_root()?;
+        let mut pages = HashSet::new();
+        Self::collect_table_pages(mem, data_root, discarded_tables, &mut pages)?;
+
+        let mut savepoint_pages = HashSet::new();
+        if keep_savepoints {
+            let mut roots = vec![];
+            Self::for_each_persistent_savepoint(mem, |_, data| {
+                roots.push(Savepoint::roots_from_bytes(data)?);
+                Ok(())
+            })?;
+            for (root, freed_root) in roots {
+                Self::collect_table_pages(mem, root, &[], &mut savepoint_pages)?;
+                // The freed table is restored along with the savepoint. The pages listed in it
+                // have already been freed, so are not kept
+                if let Some((freed_root, _)) = freed_root {
+                    let freed_pages_iter = AllPageNumbersBtreeIter::new(
+                        freed_root,
+                        FreedTableKey::fixed_width(),
+                        None,
+                        mem,
+                    )?;
+                    for page in freed_pages_iter {
+                        savepoint_pages.insert(page?);
+                    }
+                }
+            }
+        }
+        let savepoint_only_pages: Vec<PageNumber> =
+            savepoint_pages.difference(&pages).copied().collect();
+        mem.mark_pages_allocated(
+            pages
+                .into_iter()
+                .chain(savepoint_only_pages.iter().copied())
+                .map(Ok),
+        )?;
+
+        mem.end_repair()?;
+
+        // Clear the freed table. We just rebuilt the allocator state by walking all the
+        // reachable data pages, which implicitly frees the pages for the freed table
+        let transaction_id = mem.get_last_committed_transaction_id()?.next();
+        mem.commit(data_root, None, transaction_id, false, None)?;
+
+        Ok(savepoint_only_pages)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn new(
-        file: File,
+        storage: Storage,
         page_size: Option<usize>,
         region_size: Option<usize>,
         initial_size: Option<u64>,
         write_strategy: Option<WriteStrategy>,
+        compaction_policy: Option<CompactionPolicy>,
+        prepared_transaction_resolver: Option<&PreparedTransactionResolver>,
+        lock_file: bool,
+        storage_backend: StorageBackend,
+        clock: Clock,
     ) -> Result<Self> {
         #[cfg(feature = "logging")]
-        let file_path = format!("{:?}", &file);
+        let file_path = format!("{:?}", &storage);
         #[cfg(feature = "logging")]
         info!("Opening database {:?}", &file_path);
-        let mut mem =
-            TransactionalMemory::new(file, page_size, region_size, initial_size, write_strategy)?;
-        if mem.needs_repair()? {
+        let mut mem = TransactionalMemory::new(
+            storage,
+            page_size,
+            region_size,
+            initial_size,
+            write_strategy,
+            lock_file,
+            storage_backend,
+        )?;
+        let repaired = mem.needs_repair()?;
+        let mut savepoint_only_pages = vec![];
+        if repaired {
             #[cfg(feature = "logging")]
             warn!("Database {:?} not shutdown cleanly. Repairing", &file_path);
 
-            if mem.needs_checksum_verification()? && !Self::verify_primary_checksums(&mem) {
-                mem.repair_primary_corrupted();
-                assert!(Self::verify_primary_checksums(&mem));
-            }
-
-            mem.begin_repair()?;
-
-            let (root, root_checksum) = mem
-                .get_data_root()
-                .expect("Tried to repair an empty database");
-
-            // Repair the allocator state
-            // All pages in the master table
-            let master_pages_iter = AllPageNumbersBtreeIter::new(root, None, None, &mem);
-            mem.mark_pages_allocated(master_pages_iter)?;
-
-            // Iterate over all other tables
-            let iter: BtreeRangeIter<&str, InternalTableDefinition> =
-                BtreeRangeIter::new::<RangeFull, &str>(.., Some(root), &mem);
-
-            // Chain all the other tables to the master table iter
-            for entry in iter {
-                let definition = InternalTableDefinition::from_bytes(entry.value());
-                if let Some((table_root, _)) = definition.get_root() {
-                    let table_pages_iter = AllPageNumbersBtreeIter::new(
-                        table_root,
-                        definition.get_fixed_key_size(),
-                        definition.get_fixed_value_size(),
-                        &mem,
-                    );
-                    mem.mark_pages_allocated(table_pages_iter)?;
-
-                    // Multimap tables may have additional subtrees in their values
-                    if definition.get_type() == TableType::Multimap {
-                        let table_pages_iter = AllPageNumbersBtreeIter::new(
-                            table_root,
-                            definition.get_fixed_key_size(),
-                            definition.get_fixed_value_size(),
-                            &mem,
-                        );
-                        for table_page in table_pages_iter {
-                            let page = mem.get_page(table_page);
-                            let mut subtree_roots = parse_subtree_roots(
-                                &page,
-                                definition.get_fixed_key_size(),
-                                definition.get_fixed_value_size(),
-                            );
-                            mem.mark_pages_allocated(subtree_roots.drain(..))?;
-                        }
-                    }
+            let primary_corrupted =
+                mem.needs_checksum_verification()? && !Self::verify_primary_checksums(&mem)?;
+            if primary_corrupted {
+                mem.repair_primary_corrupted()?;
+                if !Self::verify_primary_checksums(&mem)? {
+                    return Err(Error::corrupted(
+                        "Both commits are corrupted. Database::repair() may be able to salvage some tables",
+                    ));
                 }
             }
 
-            mem.end_repair()?;
-
-            // Clear the freed table. We just rebuilt the allocator state by walking all the
-            // reachable data pages, which implicitly frees the pages for the freed table
-            let transaction_id = mem.get_last_committed_transaction_id()?.next();
-            mem.commit(
-                Some((root, root_checksum)),
-                None,
-                transaction_id,
-                false,
-                None,
+            mem.begin_repair(
+                |id| {
+                    let commit = if let Some(resolver) = prepared_transaction_resolver {
+                        resolver(id.0)
+                    } else {
+                        false
+                    };
+                    #[cfg(feature = "logging")]
+                    warn!(
+                        "Found prepared transaction id={:?}. Committing: {}",
+                        id, commit
+                    );
+                    commit
+                },
+                primary_corrupted,
             )?;
+            savepoint_only_pages = Self::rebuild_allocator_state(&mut mem, &[], true)?;
         }
 
         let next_transaction_id = mem.get_last_committed_transaction_id()?.next();
+        let mut transaction_tracker = TransactionTracker::new();
+        transaction_tracker.set_persistent_savepoints(Self::read_persistent_savepoints(&mem)?);
 
-        Ok(Database {
+        let db = Database {
             mem,
             next_transaction_id: AtomicTransactionId::new(next_transaction_id),
-            transaction_tracker: Arc::new(Mutex::new(TransactionTracker::new())),
+            transaction_tracker: Arc::new(Mutex::new(transaction_tracker)),
             live_write_transaction: Mutex::new(None),
-        })
+            compaction_policy,
+            last_compaction: Mutex::new(clock.now()),
+            clock,
+            key_changes: Default::default(),
+            merge_operators: HashMap::new(),
+            retention_policy: None,
+            write_transaction_memory_limit: None,
+            long_read_transaction_hook: None,
+            retained_snapshots: Mutex::new(VecDeque::new()),
+            deferred_commit_callbacks: Mutex::new(vec![]),
+            counters: Default::default(),
+        };
+
+        if !savepoint_only_pages.is_empty() {
+            // The pages which are only used by the persistent savepoints are no longer in the
+            // freed table, so free them again once the savepoints are deleted
+            let txn = db.begin_write()?;
+            txn.free_on_commit(savepoint_only_pages);
+            txn.commit()?;
+        }
+
+        Ok(db)
+    }
+
+    // Calls f with the id and serialized form of each persistent savepoint of the latest commit
+    fn for_each_persistent_savepoint(
+        mem: &TransactionalMemory,
+        mut f: impl FnMut(SavepointId, &[u8]) -> Result,
+    ) -> Result {
+        let table_tree = TableTree::new(mem.get_data_root()?, mem, Rc::new(RefCell::new(vec![])));
+        if let Some(definition) =
+            table_tree.get_table::<u64, &[u8]>(PERSISTENT_SAVEPOINT_TABLE, TableType::System)?
+        {
+            let tree: Btree<u64, &[u8]> = Btree::new(definition.get_root(), mem);
+            let mut iter = tree.range::<RangeFull, u64>(..)?;
+            while let Some(entry) = iter.try_next()? {
+                f(SavepointId(u64::from_bytes(entry.key())), entry.value())?;
+            }
+        }
+        Ok(())
+    }
+
+    // Reads the persistent savepoints of the latest commit, and the read transaction id of each
+    pub(crate) fn read_persistent_savepoints(
+        mem: &TransactionalMemory,
+    ) -> Result<BTreeMap<SavepointId, TransactionId>> {
+        let mut result = BTreeMap::new();
+        Self::for_each_persistent_savepoint(mem, |id, data| {
+            result.insert(id, Savepoint::transaction_id_from_bytes(data)?);
+            Ok(())
+        })?;
+        Ok(result)
     }
 
     // TODO: we could probably remove this method and pass this clone into the Transaction objects
@@ -364,10 +751,47 @@ impl Database {
         Ok((id, self.allocate_read_transaction()?))
     }
 
+    // Returns the current time, which is virtual in simulation mode
+    pub(crate) fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
     pub(crate) fn increment_transaction_id(&self) -> TransactionId {
         self.next_transaction_id.next()
     }
 
+    // Returns the number of bytes that may be relocated by the compaction policy. The budget
+    // accrues over time, and is reset each time that it is returned
+    fn compaction_budget(&self) -> Result<usize> {
+        let policy = if let Some(policy) = self.compaction_policy {
+            policy
+        } else {
+            return Ok(0);
+        };
+        let mut last_compaction = self.last_compaction.lock().unwrap();
+        let now = self.clock.now();
+        let per_sec = u128::from(policy.io_budget_per_sec);
+        let budget = min(
+            per_sec,
+            now.duration_since(*last_compaction).as_millis() * per_sec / 1000,
+        );
+        if budget == 0 {
+            return Ok(0);
+        }
+
+        let total_bytes = self.mem.get_usable_bytes();
+        let allocated_bytes: u64 = (self.mem.count_allocated_pages()? * self.mem.get_page_size())
+            .try_into()
+            .unwrap();
+        let unused_percent = 100 * total_bytes.saturating_sub(allocated_bytes) / total_bytes;
+        if unused_percent < policy.trigger_fragmentation.into() {
+            return Ok(0);
+        }
+        *last_compaction = now;
+
+        Ok(budget.try_into().unwrap_or(usize::MAX))
+    }
+
     /// Convenience method for [`Builder::new`]
     pub fn builder() -> Builder {
         Builder::new()
@@ -383,14 +807,14 @@ impl Database {
         let mut tracker = self.transaction_tracker.lock().unwrap();
         tracker.invalidate_all_savepoints();
 
-        let guard = self.live_write_transaction.lock().unwrap();
+        let guard = self.lock_live_write_transaction();
         assert!(guard.is_none());
         // TODO: implement switching to checksum strategy
         assert!(matches!(strategy, WriteStrategy::TwoPhase));
 
         let id = self.increment_transaction_id();
-        let root_page = self.mem.get_data_root();
-        let freed_root = self.mem.get_freed_root();
+        let root_page = self.mem.get_data_root()?;
+        let freed_root = self.mem.get_freed_root()?;
         self.mem
             .commit(root_page, freed_root, id, false, Some(strategy.into()))?;
         drop(guard);
@@ -400,6 +824,71 @@ impl Database {
         Ok(())
     }
 
+    // Makes the transaction with the given id, and all transactions before it, durable, if they
+    // are not already. This waits for the write lock, so when several threads call this
+    // concurrently, the first to acquire the lock makes the transactions of all the others durable
+    // with a single sync
+    pub(crate) fn sync_transaction(&self, id: TransactionId) -> Result {
+        if self.mem.get_last_durable_transaction_id()? >= id {
+            return Ok(());
+        }
+        let mut txn = self.begin_write()?;
+        if self.mem.get_last_durable_transaction_id()? >= id {
+            // Not reported as an abort by metrics(), since the application didn't begin it
+            return txn.abort_inner();
+        }
+        // An empty durable commit, which also frees the pages released by the non-durable commits
+        txn.commit()
+    }
+
+    // Defers the callbacks of a commit, until a later commit makes it durable
+    pub(crate) fn defer_commit_callbacks(&self, callbacks: Vec<CommitCallback>) {
+        self.deferred_commit_callbacks
+            .lock()
+            .unwrap()
+            .extend(callbacks);
+    }
+
+    // Returns the deferred callbacks. Must only be called once the commits which registered them
+    // are durable
+    pub(crate) fn take_commit_callbacks(&self) -> Vec<CommitCallback> {
+        mem::take(&mut *self.deferred_commit_callbacks.lock().unwrap())
+    }
+
+    pub(crate) fn merge_operator(&self, table: &str) -> Option<&MergeOperator> {
+        self.merge_operators.get(table)
+    }
+
+    // Waits for the write transaction in progress, if any, to finish
+    pub(crate) fn lock_live_write_transaction(&self) -> MutexGuard<Option<TransactionId>> {
+        // The lock is poisoned if a write transaction was dropped by a panic. The transaction was
+        // rolled back when dropped, so the database can still be used
+        self.live_write_transaction
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn write_transaction_memory_limit(&self) -> Option<u64> {
+        self.write_transaction_memory_limit
+    }
+
+    pub(crate) fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    pub(crate) fn key_changes(&self) -> &KeyChangeBroadcaster {
+        &self.key_changes
+    }
+
+    /// Subscribe to notifications of the keys modified by committed write transactions
+    ///
+    /// At most `capacity` notifications are buffered; older ones are discarded when the buffer
+    /// is full. Notifications are only collected while a [`KeyChangeReceiver`] exists, so there
+    /// is no overhead without subscribers
+    pub fn subscribe_key_changes(&self, capacity: usize) -> KeyChangeReceiver {
+        self.key_changes.subscribe(capacity)
+    }
+
     /// Begins a write transaction
     ///
     /// Returns a [`WriteTransaction`] which may be used to read/write to the database. Only a single
@@ -420,10 +909,365 @@ impl Database {
         let id = self.allocate_read_transaction()?;
         #[cfg(feature = "logging")]
         info!("Beginning read transaction id={:?}", id);
-        Ok(ReadTransaction::new(self, id))
+        Ok(ReadTransaction::new(self, id, self.mem.get_data_root()?))
+    }
+
+    /// Begins a read transaction of the snapshot committed by the write transaction with the given
+    /// [`WriteTransaction::transaction_id`]
+    ///
+    /// Returns `None` if the snapshot is neither the latest, nor retained by the
+    /// [`RetentionPolicy`]
+    pub fn begin_read_at(&self, transaction_id: u64) -> Result<Option<ReadTransaction>> {
+        let retained = self.retained_snapshots.lock().unwrap();
+        if let Some(snapshot) = retained
+            .iter()
+            .find(|snapshot| snapshot.transaction_id.0 == transaction_id)
+        {
+            self.transaction_tracker
+                .lock()
+                .unwrap()
+                .register_read_transaction(snapshot.transaction_id);
+            return Ok(Some(ReadTransaction::new(
+                self,
+                snapshot.transaction_id,
+                snapshot.root,
+            )));
+        }
+        drop(retained);
+
+        let txn = self.begin_read()?;
+        if txn.transaction_id() == transaction_id {
+            Ok(Some(txn))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Releases the snapshots which are no longer retained by the retention policy. This must be
+    // called before a commit, since the snapshot of the commit is retained by the next one
+    pub(crate) fn expire_retained_snapshots(&self) {
+        let policy = if let Some(policy) = self.retention_policy {
+            policy
+        } else {
+            return;
+        };
+        let mut retained = self.retained_snapshots.lock().unwrap();
+        while retained.len() > policy.commits {
+            let now = self.clock.now();
+            if now.duration_since(retained.front().unwrap().committed) < policy.duration {
+                break;
+            }
+            let snapshot = retained.pop_front().unwrap();
+            self.transaction_tracker
+                .lock()
+                .unwrap()
+                .deallocate_read_transaction(snapshot.transaction_id);
+        }
+    }
+
+    // Reports the read transactions which have been open for longer than the threshold of the
+    // long read transaction hook, and have not been reported yet
+    pub(crate) fn report_long_read_transactions(&self) {
+        let (threshold, hook) = if let Some((threshold, ref hook)) = self.long_read_transaction_hook
+        {
+            (threshold, hook)
+        } else {
+            return;
+        };
+        let readers = self
+            .transaction_tracker
+            .lock()
+            .unwrap()
+            .take_long_lived_readers(self.clock.now(), threshold);
+        for info in readers {
+            #[cfg(feature = "logging")]
+            warn!(
+                "Read transaction of snapshot {} has been open for {:?}, which prevents pages from being reused",
+                info.transaction_id(),
+                info.age()
+            );
+            hook(&info);
+        }
+    }
+
+    // Retains the snapshot of the commit which just completed, if there is a retention policy
+    pub(crate) fn retain_snapshot(&self, transaction_id: TransactionId) -> Result {
+        if self.retention_policy.is_none() {
+            return Ok(());
+        }
+        let root = self.mem.get_data_root()?;
+        let mut retained = self.retained_snapshots.lock().unwrap();
+        self.transaction_tracker
+            .lock()
+            .unwrap()
+            .register_read_transaction(transaction_id);
+        retained.push_back(RetainedSnapshot {
+            transaction_id,
+            root,
+            committed: self.clock.now(),
+        });
+
+        Ok(())
+    }
+
+    /// Applies all the operations in `batch` atomically, in a single write transaction
+    ///
+    /// The write transaction is begun and committed by this method. See [`WriteBatch`]
+    pub fn apply(&self, batch: WriteBatch) -> Result {
+        let txn = self.begin_write()?;
+        txn.apply_batch(batch)?;
+        txn.commit()
+    }
+
+    /// Compacts the database file
+    ///
+    /// Live pages are relocated toward the start of the file, in a series of durable commits, and
+    /// the free space at its end is truncated. Pages referenced by persistent savepoints, or
+    /// snapshots retained by the [`RetentionPolicy`], can't be relocated or reused, so they should
+    /// be removed first to reclaim as much space as possible. The file is not truncated on
+    /// Windows.
+    ///
+    /// Returns `true` if the file shrank
+    pub fn compact(&mut self) -> Result<bool> {
+        let original_bytes = self.mem.get_usable_bytes();
+        let mut previous_relocated = 0;
+        loop {
+            let usable_bytes = self.mem.get_usable_bytes();
+            let mut txn = self.begin_write()?;
+            txn.set_compact();
+            let relocated = txn.commit_with_stats()?.bytes_relocated();
+            // Pages freed by one commit are only reused after the following commit, so stop once
+            // two consecutive commits made no progress
+            if relocated == 0
+                && previous_relocated == 0
+                && self.mem.get_usable_bytes() >= usable_bytes
+            {
+                break;
+            }
+            previous_relocated = relocated;
+        }
+
+        Ok(self.mem.get_usable_bytes() < original_bytes)
+    }
+
+    /// Incrementally compacts the database file
+    ///
+    /// Relocates at most `max_pages` pages toward the start of the file, in a single durable
+    /// commit, and truncates any free space left at its end. Unlike [`Database::compact`], this
+    /// does a bounded amount of work, so it can be called periodically, for example between
+    /// batches of writes, to reduce fragmentation gradually. See also
+    /// [`WriteTransaction::vacuum_on_commit`]
+    ///
+    /// Returns the number of pages relocated. Pages freed by one call can only be reused after
+    /// the following commit, so a call which relocates nothing may be followed by one that does
+    pub fn vacuum(&self, max_pages: usize) -> Result<u64> {
+        let mut txn = self.begin_write()?;
+        txn.vacuum_on_commit(max_pages);
+        let relocated = txn.commit_with_stats()?.bytes_relocated();
+
+        Ok(relocated / u64::try_from(self.mem.get_page_size()).unwrap())
+    }
+
+    /// Relocates pages toward the start of the file, according to the [`CompactionPolicy`] set
+    /// with [`Builder::set_compaction_policy`]
+    ///
+    /// Call this when the database is idle. If enough of the file is unused, a durable commit
+    /// relocates as many bytes as the policy's IO budget has accrued since the previous
+    /// compaction, and a second commit frees the pages that were copied and truncates any free
+    /// space left at the end of the file. Otherwise, or if no policy is set, nothing is written.
+    /// Waits for any in-progress write transaction to complete
+    ///
+    /// Returns the number of pages relocated
+    pub fn run_compaction_policy(&self) -> Result<u64> {
+        let mut txn = self.begin_write()?;
+        let budget = self.compaction_budget()?;
+        if budget == 0 {
+            // Not reported as an abort by metrics(), since the application didn't begin it
+            txn.abort_inner()?;
+            return Ok(0);
+        }
+        txn.add_relocation_budget(budget);
+        let relocated = txn.commit_with_stats()?.bytes_relocated();
+        if relocated > 0 {
+            // The pages which were copied are only freed by the following commit, which also
+            // truncates the file
+            self.begin_write()?.commit()?;
+        }
+
+        Ok(relocated / u64::try_from(self.mem.get_page_size()).unwrap())
+    }
+
+    /// Checks the consistency of the database file, and returns a report of every problem found
+    ///
+    /// Every page of every table is visited, so this can take a long time for a large database.
+    /// The checksums, structure, and reference counts of all pages are verified, and every
+    /// allocated page must be referenced by the latest commit, or be waiting to be freed. The
+    /// ordering of keys is only verified for tables whose key type is a built-in integer, `&str`,
+    /// or `&[u8]`, since custom [`RedbKey::compare`] implementations are not known to the
+    /// database.
+    ///
+    /// Waits for any in-progress write transaction to complete, and blocks new ones until the check
+    /// is finished
+    pub fn check_integrity(&self) -> Result<IntegrityReport> {
+        let guard = self.lock_live_write_transaction();
+        assert!(guard.is_none());
+        let report = check_integrity(&self.mem);
+        drop(guard);
+
+        report
+    }
+
+    /// Returns a [`Scrubber`], which verifies the checksums of the database's pages incrementally
+    pub fn scrubber(&self) -> Scrubber<'_> {
+        Scrubber::new(self)
+    }
+
+    /// Writes a copy of the latest committed snapshot of the database to a new file at `path`
+    ///
+    /// Only the live pages of each table are copied, so the new file contains no free space,
+    /// savepoints or retained snapshots. It has the same page size, region size and
+    /// [`WriteStrategy`] as this database. Transactions may run concurrently with the copy, but
+    /// commits made after it begins are not included.
+    ///
+    /// Returns an [`io::ErrorKind::AlreadyExists`] error if the file already exists
+    pub fn copy_to(&self, path: impl AsRef<Path>) -> Result {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .during(IoOperation::Open)?;
+        let dest = Database::new(
+            Storage::File(file),
+            Some(self.mem.get_page_size()),
+            Some(self.mem.get_region_size()),
+            None,
+            Some(self.mem.get_write_strategy()),
+            None,
+            None,
+            true,
+            StorageBackend::Mmap,
+            Clock::real(),
+        )?;
+
+        let source = self.begin_read()?;
+        let txn = dest.begin_write()?;
+        txn.copy_tables_from(&source)?;
+        txn.commit()?;
+        source.close()
+    }
+
+    /// Returns the hit, miss, and eviction counts of the cache used by
+    /// [`StorageBackend::Buffered`]
+    pub fn cache_stats(&self) -> CacheStats {
+        self.mem.cache_stats()
+    }
+
+    /// Returns counters and gauges which describe the activity and health of the database, for
+    /// export to a monitoring system
+    pub fn metrics(&self) -> Result<Metrics> {
+        let counters = &self.counters;
+        let open_read_transactions = self
+            .transaction_tracker
+            .lock()
+            .unwrap()
+            .open_readers()
+            .count();
+
+        Ok(Metrics {
+            commits: counters.commits.load(Ordering::Relaxed),
+            aborts: counters.aborts.load(Ordering::Relaxed),
+            pages_allocated: counters.pages_allocated.load(Ordering::Relaxed),
+            pages_freed: counters.pages_freed.load(Ordering::Relaxed),
+            bytes_written: counters.bytes_written.load(Ordering::Relaxed),
+            syncs: self.mem.get_sync_count(),
+            cache: self.mem.cache_stats(),
+            file_size: self.mem.get_file_len(),
+            allocated_bytes: u64::try_from(self.mem.count_allocated_pages()?).unwrap()
+                * u64::try_from(self.mem.get_page_size()).unwrap(),
+            open_read_transactions,
+        })
+    }
+
+    /// Advances the database's clock by `duration`, if it was opened in simulation mode. See
+    /// [`Builder::set_simulation_seed`]
+    ///
+    /// Has no effect otherwise
+    pub fn advance_simulated_time(&self, duration: Duration) {
+        self.clock.advance(duration);
+    }
+
+    /// Returns information about every open [`ReadTransaction`], oldest snapshot first
+    ///
+    /// Pages which are referenced by the snapshot of an open read transaction cannot be reused,
+    /// so this can be used to find read transactions which were leaked, or held for too long.
+    /// Savepoints also retain their snapshots, but are not included
+    pub fn open_read_transactions(&self) -> Vec<ReadTransactionInfo> {
+        let mut result: Vec<ReadTransactionInfo> = self
+            .transaction_tracker
+            .lock()
+            .unwrap()
+            .open_readers()
+            .cloned()
+            .collect();
+        let now = self.clock.now();
+        for info in result.iter_mut() {
+            info.age = now.duration_since(info.opened);
+        }
+        result.sort_by_key(|info| (info.transaction_id, info.opened));
+        result
+    }
+
+    /// Begins a write transaction, which holds a reference to the database rather than borrowing
+    /// it
+    ///
+    /// The returned transaction has no lifetime parameter, so it can be stored in a struct together
+    /// with the database. Otherwise, this is the same as [`Self::begin_write`]
+    pub fn begin_write_owned(self: &Arc<Self>) -> Result<OwnedWriteTransaction> {
+        OwnedWriteTransaction::new(self.clone())
+    }
+
+    /// Begins a read transaction, which holds a reference to the database rather than borrowing it
+    ///
+    /// The returned transaction has no lifetime parameter, so it can be stored in a struct together
+    /// with the database. Otherwise, this is the same as [`Self::begin_read`]
+    pub fn begin_read_owned(self: &Arc<Self>) -> Result<OwnedReadTransaction> {
+        OwnedReadTransaction::new(self.clone())
     }
 }
 
+/// Configures how long the snapshots of earlier commits are retained
+///
+/// By default, the pages of a snapshot are freed as soon as a newer commit exists, and no read
+/// transaction or savepoint references it. A snapshot is retained if it is one of the `commits`
+/// most recent commits before the latest, or if it was committed less than `duration` ago.
+/// Retained snapshots can be read with [`Database::begin_read_at`], for example to run several
+/// passes of an analysis over the same version of the data. Snapshots are only retained while the
+/// database is open, and are only expired when a transaction commits
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RetentionPolicy {
+    /// Number of commits, before the latest, whose snapshots are retained
+    pub commits: usize,
+    /// Minimum time to retain the snapshot of each commit
+    pub duration: Duration,
+}
+
+/// Configures opportunistic compaction, which relocates pages toward the start of the database
+/// file so that the file can be shrunk
+///
+/// Compaction is never performed by the application's own commits. Instead, it is performed by
+/// [`Database::run_compaction_policy`], which should be called when the database is idle, for
+/// example from a timer. The IO budget accrues between calls, so that the work done by each call
+/// is bounded by the time since the previous one.
+#[derive(Copy, Clone, Debug)]
+pub struct CompactionPolicy {
+    /// Percentage of the database file, from 0 to 100, which must be unused before any pages are
+    /// relocated
+    pub trigger_fragmentation: u8,
+    /// Maximum number of bytes to relocate per second
+    pub io_budget_per_sec: u64,
+}
+
 /// redb can be configured to use one of two write-and-commit strategies.
 ///
 /// Both strategies have security tradeoffs in situations where an attacker has a high degree of
@@ -477,11 +1321,247 @@ pub enum WriteStrategy {
     TwoPhase,
 }
 
+/// How the database file is read and written
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StorageBackend {
+    /// Map the file into memory. This is usually the fastest backend, and is the default
+    ///
+    /// On targets without memory mapping, such as `wasm32`, [`StorageBackend::Buffered`] is used
+    /// instead
+    Mmap,
+    /// Read the file with `pread` and write it with `pwrite`, through an in-memory copy of the
+    /// file
+    ///
+    /// This avoids the drawbacks of memory mapping: for example, a process which maps a file
+    /// receives `SIGBUS` if the file is truncated, or if an I/O error occurs, and memory mapping
+    /// is unreliable on network filesystems. The file is read in 4KiB blocks the first time they
+    /// are accessed, and modified blocks are written back when the database is flushed. Blocks
+    /// which have been read remain in memory until the database is closed, unless a limit is set
+    /// with [`Builder::set_cache_size`]
+    ///
+    /// On targets without memory mapping, the in-memory copy is allocated at twice the length of
+    /// the file, and enlarged once no transactions are open. Until then, a transaction which grows
+    /// the file beyond it fails with [`Error::StorageFull`], and can be retried
+    Buffered,
+}
+
+/// How the database is expected to access its file, which is passed to the operating system as a
+/// hint for how much of the file to read ahead of each access
+///
+/// Only used by [`StorageBackend::Mmap`], on platforms with `madvise()`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AccessPattern {
+    /// Pages are accessed in no particular order, as they are by point lookups, so nothing is
+    /// read ahead. This is the default, and performs about twice as well as the others once the
+    /// database no longer fits in memory
+    Random,
+    /// Pages are accessed in the order they appear in the file, so the operating system reads
+    /// ahead aggressively. Sibling leaves are only adjacent in the file if they were written
+    /// together, for example by [`crate::Table::load_sorted`] or [`Database::compact`], so this
+    /// mostly benefits scans of such tables
+    Sequential,
+    /// Use the operating system's default amount of read ahead
+    Normal,
+}
+
+/// Statistics about the cache of [`StorageBackend::Buffered`], returned by
+/// [`Database::cache_stats`]
+///
+/// All the counters are zero for [`StorageBackend::Mmap`], since its memory is managed by the
+/// operating system's page cache
+#[derive(Clone, Debug, Default)]
+pub struct CacheStats {
+    pub(crate) hits: u64,
+    pub(crate) misses: u64,
+    pub(crate) evictions: u64,
+    pub(crate) cached_bytes: usize,
+}
+
+impl CacheStats {
+    /// Number of reads of the database file which were served entirely from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Number of reads of the database file which had to read at least one block from disk
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Number of 4KiB blocks which have been evicted from the cache
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    /// Number of bytes of the database file currently held in the cache
+    pub fn cached_bytes(&self) -> usize {
+        self.cached_bytes
+    }
+}
+
+/// Counters and gauges describing the activity and health of a database, returned by
+/// [`Database::metrics`]
+///
+/// The counters are totals since the database was opened, so a monitoring system should compute
+/// their rate of change, and the gauges describe its current state
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    pub(crate) commits: u64,
+    pub(crate) aborts: u64,
+    pub(crate) pages_allocated: u64,
+    pub(crate) pages_freed: u64,
+    pub(crate) bytes_written: u64,
+    pub(crate) syncs: u64,
+    pub(crate) cache: CacheStats,
+    pub(crate) file_size: u64,
+    pub(crate) allocated_bytes: u64,
+    pub(crate) open_read_transactions: usize,
+}
+
+impl Metrics {
+    /// Number of write transactions committed, including those committed by operations such as
+    /// [`Database::compact`]
+    pub fn commits(&self) -> u64 {
+        self.commits
+    }
+
+    /// Number of write transactions aborted, or dropped without being committed, and of prepared
+    /// transactions which were rolled back
+    pub fn aborts(&self) -> u64 {
+        self.aborts
+    }
+
+    /// Number of pages allocated by committed transactions. See [`CommitStats::pages_allocated`]
+    pub fn pages_allocated(&self) -> u64 {
+        self.pages_allocated
+    }
+
+    /// Number of pages freed by committed transactions. See [`CommitStats::pages_freed`]
+    pub fn pages_freed(&self) -> u64 {
+        self.pages_freed
+    }
+
+    /// Number of bytes written by committed transactions. See [`CommitStats::bytes_written`]
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Number of times the database file has been synced to disk
+    pub fn syncs(&self) -> u64 {
+        self.syncs
+    }
+
+    /// Statistics about the cache of [`StorageBackend::Buffered`]
+    pub fn cache(&self) -> &CacheStats {
+        &self.cache
+    }
+
+    /// Fraction of reads which were served from the cache of [`StorageBackend::Buffered`], or
+    /// `None` if there have been no reads from it
+    // The loss of precision of very large counts is insignificant for a ratio
+    #[allow(clippy::cast_precision_loss)]
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        let reads = self.cache.hits + self.cache.misses;
+        if reads == 0 {
+            None
+        } else {
+            Some(self.cache.hits as f64 / reads as f64)
+        }
+    }
+
+    /// Size of the database file in bytes
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    /// Number of bytes in allocated pages, including those allocated by a write transaction which
+    /// is in progress. The rest of the file is free space, or metadata
+    pub fn allocated_bytes(&self) -> u64 {
+        self.allocated_bytes
+    }
+
+    /// Number of read transactions which are open. See [`Database::open_read_transactions`]
+    pub fn open_read_transactions(&self) -> usize {
+        self.open_read_transactions
+    }
+}
+
+/// Whether an [`AllocationEvent`] reports that a page was allocated or freed
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AllocationEventKind {
+    /// The page was allocated
+    Allocate,
+    /// The page was freed
+    Free,
+}
+
+/// A page which was allocated or freed, as reported to the hook set with
+/// [`Builder::set_allocation_hook`]
+#[derive(Clone, Debug)]
+pub struct AllocationEvent<'a> {
+    pub(crate) kind: AllocationEventKind,
+    pub(crate) page_number: u64,
+    pub(crate) size: u64,
+    pub(crate) table: Option<&'a str>,
+}
+
+impl<'a> AllocationEvent<'a> {
+    /// Whether the page was allocated or freed
+    pub fn kind(&self) -> AllocationEventKind {
+        self.kind
+    }
+
+    /// Number which identifies the page in the database file, as in [`crate::PageInfo`]
+    pub fn page_number(&self) -> u64 {
+        self.page_number
+    }
+
+    /// Size of the page in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Name of the table whose operation allocated or freed the page, or `None` if the page
+    /// belongs to the database's own metadata, or the event is not attributed to a table
+    pub fn table(&self) -> Option<&'a str> {
+        self.table
+    }
+}
+
+// Combines the key, its existing value if any, and a merge operand into the new value
+pub(crate) type MergeOperator = Arc<dyn Fn(&[u8], Option<&[u8]>, &[u8]) -> Vec<u8> + Send + Sync>;
+pub(crate) type CommitCallback = Box<dyn FnOnce() + Send>;
+type PreparedTransactionResolver = Arc<dyn Fn(u64) -> bool + Send + Sync>;
+pub(crate) type AllocationHook = Arc<dyn Fn(&AllocationEvent) + Send + Sync>;
+type LongReadTransactionHook = Arc<dyn Fn(&ReadTransactionInfo) + Send + Sync>;
+
 pub struct Builder {
     page_size: Option<usize>,
     region_size: Option<usize>,
     initial_size: Option<u64>,
     write_strategy: Option<WriteStrategy>,
+    compaction_policy: Option<CompactionPolicy>,
+    retention_policy: Option<RetentionPolicy>,
+    merge_operators: HashMap<String, MergeOperator>,
+    prepared_transaction_resolver: Option<PreparedTransactionResolver>,
+    allocation_hook: Option<AllocationHook>,
+    long_read_transaction_hook: Option<(Duration, LongReadTransactionHook)>,
+    lock_file: bool,
+    storage_backend: StorageBackend,
+    cache_size: Option<usize>,
+    preallocate: bool,
+    sparse: bool,
+    verify_checksums_on_read: bool,
+    large_value_threshold: Option<usize>,
+    write_transaction_memory_limit: Option<u64>,
+    hole_punch_threshold: Option<usize>,
+    background_flush_threshold: Option<usize>,
+    read_ahead: usize,
+    access_pattern: AccessPattern,
+    populate_on_open: bool,
+    simulation_seed: Option<u64>,
+    #[cfg(feature = "fault_injection")]
+    fault_injector: Option<FaultInjector>,
 }
 
 impl Builder {
@@ -495,6 +1575,28 @@ impl Builder {
             region_size: None,
             initial_size: None,
             write_strategy: None,
+            compaction_policy: None,
+            retention_policy: None,
+            write_transaction_memory_limit: None,
+            merge_operators: HashMap::new(),
+            prepared_transaction_resolver: None,
+            allocation_hook: None,
+            long_read_transaction_hook: None,
+            lock_file: true,
+            storage_backend: StorageBackend::Mmap,
+            cache_size: None,
+            preallocate: false,
+            sparse: false,
+            verify_checksums_on_read: false,
+            large_value_threshold: None,
+            hole_punch_threshold: None,
+            background_flush_threshold: None,
+            read_ahead: 0,
+            access_pattern: AccessPattern::Random,
+            populate_on_open: false,
+            simulation_seed: None,
+            #[cfg(feature = "fault_injection")]
+            fault_injector: None,
         }
     }
 
@@ -513,9 +1615,16 @@ impl Builder {
         self
     }
 
-    #[cfg(test)]
-    #[cfg(unix)]
-    fn set_region_size(&mut self, size: usize) -> &mut Self {
+    /// Set the amount of usable space in each region of the database file
+    ///
+    /// The file is divided into regions, each of which starts with a header tracking the
+    /// allocation of its pages. Headers are sized for a full region, so smaller regions reduce the
+    /// overhead of small databases. However, a database has at most 1000 regions, so this also limits its maximum size, and values larger
+    /// than a region can't be stored: inserting them returns [`Error::ValueTooLarge`].
+    ///
+    /// Must be a power of two. Defaults to 4GiB. Only used when the database is created, since
+    /// the region size is part of the file format
+    pub fn set_region_size(&mut self, size: usize) -> &mut Self {
         assert!(size.is_power_of_two());
         self.region_size = Some(size);
         self
@@ -530,28 +1639,456 @@ impl Builder {
         self
     }
 
+    /// Enable opportunistic compaction of the database file, which is performed by
+    /// [`Database::run_compaction_policy`]
+    ///
+    /// By default, pages are never relocated, and the file only shrinks if there is free space
+    /// at its end
+    pub fn set_compaction_policy(&mut self, policy: CompactionPolicy) -> &mut Self {
+        self.compaction_policy = Some(policy);
+        self
+    }
+
+    /// Retain the snapshots of earlier commits, so that they can be read with
+    /// [`Database::begin_read_at`]
+    ///
+    /// By default, no snapshots are retained
+    pub fn set_retention_policy(&mut self, policy: RetentionPolicy) -> &mut Self {
+        self.retention_policy = Some(policy);
+        self
+    }
+
+    /// Register the merge operator used by [`crate::Table::merge_in_place`] for the table named
+    /// `table`
+    ///
+    /// The operator is called with the key, its existing value if there is one, and the operand,
+    /// and returns the new value. Keys and values are in their serialized form, as produced by
+    /// [`RedbKey::as_bytes`] and [`RedbValue::as_bytes`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use redb::*;
+    /// # use tempfile::NamedTempFile;
+    /// const COUNTERS: TableDefinition<&str, u64> = TableDefinition::new("counters");
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// # let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    /// # let filename = tmpfile.path();
+    /// let db = Database::builder()
+    ///     .set_merge_operator(COUNTERS.name(), |_key, existing, operand| {
+    ///         let existing = existing.map(u64::from_bytes).unwrap_or_default();
+    ///         (existing + u64::from_bytes(operand)).to_le_bytes().to_vec()
+    ///     })
+    ///     .create(filename)?;
+    /// let write_txn = db.begin_write()?;
+    /// {
+    ///     let mut table = write_txn.open_table(COUNTERS)?;
+    ///     table.merge_in_place("visits", &1)?;
+    ///     table.merge_in_place("visits", &2)?;
+    ///     assert_eq!(table.get("visits")?.unwrap(), 3);
+    /// }
+    /// write_txn.commit()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_merge_operator(
+        &mut self,
+        table: &str,
+        operator: impl Fn(&[u8], Option<&[u8]>, &[u8]) -> Vec<u8> + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.merge_operators
+            .insert(table.to_string(), Arc::new(operator));
+        self
+    }
+
+    /// Decide the outcome of a transaction which was prepared with
+    /// [`WriteTransaction::prepare`], but neither committed nor rolled back before a crash
+    ///
+    /// When the database is next opened, `resolver` is called with the id of the prepared
+    /// transaction, and returns `true` to commit it, or `false` to roll it back. This is usually
+    /// decided by asking the coordinator of the distributed transaction. By default, prepared
+    /// transactions are rolled back
+    pub fn set_prepared_transaction_resolver(
+        &mut self,
+        resolver: impl Fn(u64) -> bool + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.prepared_transaction_resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Call `hook` each time that a page of the database file is allocated or freed, for example
+    /// to attribute disk usage to tables, or to find space leaks
+    ///
+    /// Events are attributed to the table whose operation caused them. Pages which are still
+    /// referenced by the last commit, or by a read transaction, are freed by a later commit, once
+    /// they are no longer needed, and those events are not attributed to a table. When a
+    /// transaction is aborted, or a nested transaction is rolled back, the inverse event is
+    /// reported, without a table, for each of its allocations and frees, so that the total kept by
+    /// the hook remains correct.
+    ///
+    /// The hook is called while the database's internal locks are held, so it must not access
+    /// the database
+    pub fn set_allocation_hook(
+        &mut self,
+        hook: impl Fn(&AllocationEvent) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.allocation_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Call `hook` with each read transaction which has been open for longer than `threshold`, so
+    /// that the component which holds it can be found. Pages which are referenced by the snapshot
+    /// of an open read transaction can't be reused, so a read transaction which is held for too
+    /// long makes the database file grow.
+    ///
+    /// Open read transactions are checked each time that a write transaction commits, and each is
+    /// reported once. They are also logged, if the `logging` feature is enabled. Enable the
+    /// `read_transaction_backtraces` feature to find where each transaction began, with
+    /// [`ReadTransactionInfo::backtrace`]
+    pub fn set_long_read_transaction_hook(
+        &mut self,
+        threshold: Duration,
+        hook: impl Fn(&ReadTransactionInfo) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.long_read_transaction_hook = Some((threshold, Arc::new(hook)));
+        self
+    }
+
+    /// Set how the database file is read and written
+    ///
+    /// Defaults to [`StorageBackend::Mmap`]
+    pub fn set_storage_backend(&mut self, backend: StorageBackend) -> &mut Self {
+        self.storage_backend = backend;
+        self
+    }
+
+    /// Set a [`FaultInjector`], which injects faults into the database's I/O
+    ///
+    /// The database uses [`StorageBackend::Buffered`], regardless of
+    /// [`Builder::set_storage_backend`], since writes to a memory map can't be intercepted
+    #[cfg(feature = "fault_injection")]
+    pub fn set_fault_injector(&mut self, injector: FaultInjector) -> &mut Self {
+        self.fault_injector = Some(injector);
+        self
+    }
+
+    /// Set the maximum number of bytes of the database file which are cached in memory by
+    /// [`StorageBackend::Buffered`]
+    ///
+    /// Whenever the last open transaction ends, and more than `bytes` are cached, modified pages
+    /// are written back to the file and the least recently used parts of the cache are evicted
+    /// until it fits. Pages cannot be evicted while any transaction is open, since it may hold
+    /// references to them, so long running transactions can exceed the limit. Use
+    /// [`Database::cache_stats`] to measure how well the cache fits the workload. By default, the cache is unbounded. This has no effect on
+    /// [`StorageBackend::Mmap`], whose memory is managed by the operating system's page cache
+    pub fn set_cache_size(&mut self, bytes: usize) -> &mut Self {
+        self.cache_size = Some(bytes);
+        self
+    }
+
+    /// Return the disk space of free blocks of at least `bytes` bytes to the filesystem
+    ///
+    /// Freed pages are normally kept in the file for reuse, so its disk usage only drops when it
+    /// is compacted. With this set, whenever a durable commit leaves a contiguous free block of
+    /// at least `bytes` in the middle of the file, a hole is punched in the file with
+    /// `fallocate(FALLOC_FL_PUNCH_HOLE)`, and the filesystem reports the space as free. The
+    /// length of the file is unchanged, and the space is allocated again when the block is
+    /// reused. Supported on Linux, and on Windows for files created with [`Self::set_sparse`],
+    /// by filesystems which implement hole punching. By default, holes are never punched
+    pub fn set_hole_punch_threshold(&mut self, bytes: usize) -> &mut Self {
+        self.hole_punch_threshold = Some(bytes);
+        self
+    }
+
+    /// Write back modified pages on a background thread, each time that write transactions have
+    /// modified `bytes` of the database file
+    ///
+    /// Normally, the pages written by a transaction are written back by the operating system
+    /// whenever it chooses, and any remaining ones all at once when the transaction commits. For
+    /// large transactions, this makes the latency of [`crate::WriteTransaction::commit`] spike.
+    /// With this set, write back is started in the background as the transaction runs, so commit
+    /// only has to write the remainder. Only supported by [`StorageBackend::Mmap`] on Linux,
+    /// using `sync_file_range()`. By default, pages are not written back early
+    pub fn set_background_flush_threshold(&mut self, bytes: usize) -> &mut Self {
+        self.background_flush_threshold = Some(bytes);
+        self
+    }
+
+    /// Open the database in simulation mode, in which its behavior is reproducible from `seed`
+    ///
+    /// Time is virtual: it only advances by a pseudo-random amount, derived from the seed, each
+    /// time that the database reads the clock, and when [`Database::advance_simulated_time`] is
+    /// called. This drives the [`CompactionPolicy`], the [`RetentionPolicy`], and reported
+    /// timings. Background flushing is disabled, since it runs on another thread. A workload
+    /// which makes the same calls in the same order, from one thread or with its own
+    /// deterministic scheduling, therefore behaves the same way on every run with the same seed.
+    /// Combined with a [`crate::FaultInjector`], crashes can be reproduced exactly
+    pub fn set_simulation_seed(&mut self, seed: u64) -> &mut Self {
+        self.simulation_seed = Some(seed);
+        self
+    }
+
+    /// Set the expected access pattern of the database, which decides how much of the file the
+    /// operating system reads ahead
+    ///
+    /// Defaults to [`AccessPattern::Random`]
+    pub fn set_access_pattern(&mut self, pattern: AccessPattern) -> &mut Self {
+        self.access_pattern = pattern;
+        self
+    }
+
+    /// Prefetch the next `pages` pages of range scans, while the current one is being read
+    ///
+    /// Pages are only read from disk when they are first accessed, so a scan of a table which
+    /// is not in memory waits for each leaf in turn. With this set, iterators ask the operating
+    /// system to start reading the following sibling pages, with `madvise(MADV_WILLNEED)`, so
+    /// that they are in memory by the time they are reached. Each hint is a system call, so this
+    /// slows down scans of tables which are already in memory. Only supported by
+    /// [`StorageBackend::Mmap`] on Unix. Defaults to 0, which disables prefetching
+    pub fn set_read_ahead(&mut self, pages: usize) -> &mut Self {
+        self.read_ahead = pages;
+        self
+    }
+
+    /// Start reading the whole database file into memory when it is opened
+    ///
+    /// This warms the cache of a database which is about to be read heavily, instead of each page
+    /// being read the first time it is accessed. With [`StorageBackend::Mmap`] the file is read
+    /// in the background, using `madvise(MADV_WILLNEED)`, and this has no effect on Windows. With
+    /// [`StorageBackend::Buffered`] the whole file is read before the database is returned,
+    /// although it may be evicted again if it is larger than the limit set by
+    /// [`Self::set_cache_size`]
+    pub fn set_populate_on_open(&mut self, populate: bool) -> &mut Self {
+        self.populate_on_open = populate;
+        self
+    }
+
+    /// Allocate disk space for the database file as soon as it grows, instead of extending it
+    /// lazily
+    ///
+    /// By default, the file is extended without allocating space, and the filesystem allocates it
+    /// as pages are first written. Preallocating, with `posix_fallocate()`, keeps the file
+    /// contiguous on hard drives, and makes running out of disk space return an error when the
+    /// database grows, rather than when pages are written back, which crashes the process when
+    /// they are written through a memory map. It gives little benefit on SSDs, and none on
+    /// copy-on-write filesystems, which allocate new space for every write anyway. Extending a
+    /// file always allocates its space on Windows, and this has no effect on platforms without
+    /// `posix_fallocate()`
+    pub fn set_preallocate(&mut self, preallocate: bool) -> &mut Self {
+        self.preallocate = preallocate;
+        self
+    }
+
+    /// Create the database file as a sparse file, so that disk space is only allocated for the
+    /// parts of it which have been written
+    ///
+    /// This allows a large [`Self::set_initial_size`] to be reserved without consuming the disk
+    /// space up front. Files are already sparse on Unix, unless [`Self::set_preallocate`] is
+    /// used, so this only has an effect on Windows, where extending a file normally allocates
+    /// its space. Sparse files on Windows also allow [`Self::set_hole_punch_threshold`] to
+    /// deallocate free blocks. Has no effect if preallocation is enabled
+    pub fn set_sparse(&mut self, sparse: bool) -> &mut Self {
+        self.sparse = sparse;
+        self
+    }
+
+    /// Verify the checksum of every page as it is read, and return [`Error::Corrupted`] if it does
+    /// not match
+    ///
+    /// By default, checksums are only verified when the database is recovered after a crash, so
+    /// pages which are corrupted on disk, or by a stray write to the memory map, while the
+    /// database is open go undetected. This paranoid mode detects them whenever a table is read,
+    /// in both read and write transactions, at the cost of hashing every page visited. It has no
+    /// effect on databases which use [`WriteStrategy::TwoPhase`], since they do not store
+    /// checksums
+    ///
+    /// A corrupted or truncated file header is always reported as [`Error::Corrupted`]. Enable
+    /// this to also open untrusted files defensively, so that corrupted pages are reported
+    /// instead of being read
+    pub fn set_verify_checksums_on_read(&mut self, verify: bool) -> &mut Self {
+        self.verify_checksums_on_read = verify;
+        self
+    }
+
+    /// Store values of at least `bytes` bytes in leaf pages of their own
+    ///
+    /// Smaller values are stored alongside their neighbors, so leaves stay densely packed even
+    /// when some values are very large, and a large value is not copied when the entries near it
+    /// are modified. A leaf holding a large value is allocated with as many pages as it needs.
+    /// By default, there is no threshold, and values are stored alongside their neighbors
+    /// regardless of their size. The threshold is not stored in the file, so it can be changed
+    /// each time the database is opened, and existing leaves are split as they are rewritten
+    pub fn set_large_value_threshold(&mut self, bytes: usize) -> &mut Self {
+        self.large_value_threshold = Some(bytes);
+        self
+    }
+
+    /// Limit the memory that a write transaction can hold to approximately `bytes` bytes
+    ///
+    /// Once a write transaction holds more than this, as reported by
+    /// [`WriteTransaction::memory_usage`], operations which insert into its tables return
+    /// [`Error::TransactionMemoryLimitExceeded`], so that a runaway bulk operation fails rather
+    /// than exhausting the memory of the process. The limit is checked before each operation, so
+    /// it can be exceeded by the size of one operation. A failed operation leaves the table
+    /// unchanged, except that bulk operations such as [`crate::Table::extend`] are also checked
+    /// part way through, and keep the entries which they already inserted. The transaction can
+    /// still be committed or aborted. Defaults to no limit
+    pub fn set_write_transaction_memory_limit(&mut self, bytes: u64) -> &mut Self {
+        self.write_transaction_memory_limit = Some(bytes);
+        self
+    }
+
+    /// Do not lock the database file
+    ///
+    /// # Safety
+    ///
+    /// The file must not be opened by any other process, or by another [`Database`] in this
+    /// process, while the database is open
+    pub unsafe fn disable_file_lock(&mut self) -> &mut Self {
+        self.lock_file = false;
+        self
+    }
+
     /// Opens the specified file as a redb database.
     /// * if the file does not exist, or is an empty file, a new database will be initialized in it
     /// * if the file is a valid redb database, it will be opened
     /// * otherwise this function will return an error
     ///
-    /// # Safety
-    ///
-    /// The file referenced by `path` must not be concurrently modified by any other process
-    pub unsafe fn create(&self, path: impl AsRef<Path>) -> Result<Database> {
+    /// Unless [`Builder::disable_file_lock`] is used, the file is locked while the database is
+    /// open, so opening it again, from this or another process, returns
+    /// [`Error::LockContention`]. The lock is advisory, and does not protect against other
+    /// programs which modify the file without taking it
+    pub fn open_or_create(&self, path: impl AsRef<Path>) -> Result<Database> {
         let file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .open(path)?;
+            .open(path)
+            .during(IoOperation::Open)?;
+
+        self.open_file(file, self.page_size)
+    }
 
-        Database::new(
-            file,
-            self.page_size,
+    /// Same as [`Builder::open_or_create`]
+    pub fn create(&self, path: impl AsRef<Path>) -> Result<Database> {
+        self.open_or_create(path)
+    }
+
+    /// Opens an existing redb database
+    ///
+    /// Returns an [`io::ErrorKind::NotFound`] error if the file does not exist, and an
+    /// [`io::ErrorKind::InvalidData`] error if it is empty
+    pub fn open(&self, path: impl AsRef<Path>) -> Result<Database> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .during(IoOperation::Open)?;
+        if file.metadata().during(IoOperation::Open)?.len() == 0 {
+            return Err(Error::io(
+                IoOperation::Open,
+                io::Error::from(ErrorKind::InvalidData),
+            ));
+        }
+
+        // The page size is read from the file
+        self.open_file(file, None)
+    }
+
+    /// Creates a new redb database
+    ///
+    /// Returns an [`io::ErrorKind::AlreadyExists`] error if the file already exists
+    pub fn create_new(&self, path: impl AsRef<Path>) -> Result<Database> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .during(IoOperation::Open)?;
+
+        self.open_file(file, self.page_size)
+    }
+
+    /// Opens a redb database stored in a [`StorageDevice`], instead of a file
+    /// * if the device is empty, a new database will be initialized in it
+    /// * if the device holds a valid redb database, it will be opened
+    /// * otherwise this function will return an error
+    ///
+    /// The device is always accessed through [`StorageBackend::Buffered`], and is not locked.
+    /// Options which only apply to files, such as [`Builder::set_preallocate`], have no effect
+    pub fn create_with_storage(&self, storage: impl StorageDevice + 'static) -> Result<Database> {
+        self.open_storage(Storage::Device(Box::new(storage)), self.page_size)
+    }
+
+    fn open_file(&self, file: File, page_size: Option<usize>) -> Result<Database> {
+        // Must be done before the file is extended, since space which is already allocated is
+        // not released
+        if self.sparse && !self.preallocate {
+            make_sparse(&file)?;
+        }
+        self.open_storage(Storage::File(file), page_size)
+    }
+
+    fn open_storage(&self, storage: Storage, page_size: Option<usize>) -> Result<Database> {
+        #[allow(unused_mut)]
+        let mut storage_backend = self.storage_backend;
+        #[cfg(feature = "fault_injection")]
+        if self.fault_injector.is_some() {
+            storage_backend = StorageBackend::Buffered;
+        }
+        let mut db = Database::new(
+            storage,
+            page_size,
             self.region_size,
             self.initial_size,
             self.write_strategy,
-        )
+            self.compaction_policy,
+            self.prepared_transaction_resolver.as_ref(),
+            self.lock_file,
+            storage_backend,
+            self.simulation_seed
+                .map(Clock::simulated)
+                .unwrap_or_else(Clock::real),
+        )?;
+        #[cfg(feature = "fault_injection")]
+        if let Some(ref injector) = self.fault_injector {
+            db.mem.set_fault_injector(injector.clone());
+        }
+        if let Some(bytes) = self.cache_size {
+            db.mem.set_cache_size(bytes);
+        }
+        if let Some(ref hook) = self.allocation_hook {
+            db.mem.set_allocation_hook(hook.clone());
+        }
+        if self.preallocate {
+            db.mem.enable_preallocation()?;
+        }
+        if self.verify_checksums_on_read {
+            db.mem.enable_read_verification();
+        }
+        if let Some(bytes) = self.large_value_threshold {
+            db.mem.set_large_value_threshold(bytes);
+        }
+        if let Some(bytes) = self.hole_punch_threshold {
+            db.mem.set_hole_punch_threshold(bytes);
+        }
+        if let Some(bytes) = self.background_flush_threshold {
+            if !db.clock.is_simulated() {
+                db.mem.enable_background_flush(bytes)?;
+            }
+        }
+        db.mem.set_read_ahead(self.read_ahead);
+        db.mem.set_access_pattern(self.access_pattern)?;
+        if self.populate_on_open {
+            db.mem.populate()?;
+        }
+        db.merge_operators = self.merge_operators.clone();
+        db.retention_policy = self.retention_policy;
+        db.write_transaction_memory_limit = self.write_transaction_memory_limit;
+        db.long_read_transaction_hook = self.long_read_transaction_hook.clone();
+
+        Ok(db)
     }
 }
 
@@ -568,7 +2105,7 @@ mod test {
     use tempfile::NamedTempFile;
 
     #[cfg(unix)]
-    use crate::{Database, TableDefinition};
+    use crate::{CompactionPolicy, Database, ReadableTable, TableDefinition};
 
     #[test]
     #[cfg(unix)]
@@ -577,12 +2114,10 @@ mod test {
         let table_definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
         let big_value = vec![0u8; 1024];
 
-        let db = unsafe {
-            Database::builder()
-                .set_region_size(1024 * 1024)
-                .create(tmpfile.path())
-                .unwrap()
-        };
+        let db = Database::builder()
+            .set_region_size(1024 * 1024)
+            .create(tmpfile.path())
+            .unwrap();
 
         let txn = db.begin_write().unwrap();
         {
@@ -623,4 +2158,57 @@ mod test {
         let final_file_size = tmpfile.as_file().metadata().unwrap().len();
         assert!(final_file_size < file_size);
     }
+
+    #[test]
+    #[cfg(unix)]
+    fn opportunistic_compaction() {
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        let table1: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let table2: TableDefinition<u64, &[u8]> = TableDefinition::new("y");
+        let big_value = vec![0u8; 1024];
+
+        let db = Database::builder()
+            .set_region_size(1024 * 1024)
+            .set_compaction_policy(CompactionPolicy {
+                trigger_fragmentation: 25,
+                io_budget_per_sec: 1024 * 1024 * 1024,
+            })
+            .create(tmpfile.path())
+            .unwrap();
+
+        let txn = db.begin_write().unwrap();
+        for definition in [table1, table2] {
+            let mut table = txn.open_table(definition).unwrap();
+            for i in 0..2048 {
+                table.insert(&i, &big_value).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+
+        let file_size = tmpfile.as_file().metadata().unwrap().len();
+
+        let txn = db.begin_write().unwrap();
+        txn.delete_table(table1).unwrap();
+        txn.commit().unwrap();
+
+        // Commits by the application never relocate pages
+        for _ in 0..5 {
+            let txn = db.begin_write().unwrap();
+            assert_eq!(txn.commit_with_stats().unwrap().bytes_relocated(), 0);
+        }
+
+        // Give the IO budget time to accrue, and the freed pages a chance to be reused
+        for _ in 0..15 {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            db.run_compaction_policy().unwrap();
+        }
+
+        let final_file_size = tmpfile.as_file().metadata().unwrap().len();
+        assert!(final_file_size < file_size * 3 / 4);
+
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table2).unwrap();
+        assert_eq!(table.len().unwrap(), 2048);
+        assert_eq!(table.get(&2047).unwrap().unwrap(), big_value);
+    }
 }
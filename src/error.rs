@@ -1,20 +1,130 @@
+use crate::tree_store::PageNumber;
 use std::fmt::{Display, Formatter};
 use std::sync::PoisonError;
 use std::{io, panic};
 
+/// The operation which failed, in an [`Error::Io`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IoOperation {
+    /// Opening or creating the database file
+    Open,
+    /// Acquiring or releasing the lock on the database file
+    Lock,
+    /// Reading from the database file
+    Read,
+    /// Writing to the database file
+    Write,
+    /// Syncing the database file to disk
+    Sync,
+    /// Changing the length of the database file, or of its memory map
+    Resize,
+    /// Reading from a reader, or writing to a writer, which was passed to redb
+    Other,
+}
+
+impl IoOperation {
+    fn name(self) -> &'static str {
+        match self {
+            IoOperation::Open => "open",
+            IoOperation::Lock => "lock",
+            IoOperation::Read => "read",
+            IoOperation::Write => "write",
+            IoOperation::Sync => "sync",
+            IoOperation::Resize => "resize",
+            IoOperation::Other => "other",
+        }
+    }
+}
+
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
-    DatabaseAlreadyOpen,
+    /// The database file is locked by another [`crate::Database`], in this process or another one
+    LockContention,
     /// This savepoint is invalid because an older savepoint was restored after it was created
     InvalidSavepoint,
-    Corrupted(String),
-    TableTypeMismatch(String),
+    /// The database file is corrupted
+    Corrupted {
+        /// Description of the corruption
+        message: String,
+        /// Number of the corrupted page, as returned by [`crate::PageInfo::page_number`], if known
+        page: Option<u64>,
+        /// Offset of the corrupted data in the file, if known
+        offset: Option<u64>,
+    },
+    /// The database file was written with the given, older, file format version. It can be
+    /// converted to the current version with [`crate::Database::upgrade`]
+    UpgradeRequired(u8),
+    /// The table was opened as a different type than the one it is stored as. Each type is
+    /// formatted like `Table<u64, &str>`, or `MultimapTable` if its key and value types are not
+    /// known
+    TableTypeMismatch {
+        /// Name of the table
+        table: String,
+        /// Type that the table was opened as
+        expected: String,
+        /// Type that the table is stored as
+        actual: String,
+    },
     TableDoesNotExist(String),
     // Tables cannot be opened for writing multiple times, since they could retrieve immutable &
     // mutable references to the same dirty pages, or multiple mutable references via insert_reserve()
     TableAlreadyOpen(String, &'static panic::Location<'static>),
-    Io(io::Error),
+    /// An I/O operation failed
+    Io {
+        /// Operation which failed
+        operation: IoOperation,
+        /// Error returned by the operation
+        source: io::Error,
+    },
+    /// The database file could not be written, or grown, because the disk is full, or a quota
+    /// was exceeded. The transaction which was being written is not committed, and the database
+    /// remains at the previous commit, so the transaction can be retried once space is freed.
+    ///
+    /// Writes are only retried by [`crate::StorageBackend::Buffered`]. With
+    /// [`crate::StorageBackend::Mmap`], the disk must have space for the file when it's grown,
+    /// which [`crate::Builder::set_preallocate`] ensures. Otherwise, a write to a page of the file
+    /// for which the filesystem can't allocate space may raise `SIGBUS`
+    ///
+    /// On targets without memory mapping, this is also returned when the in-memory copy of the
+    /// file must be enlarged while transactions are open. See [`crate::StorageBackend::Buffered`]
+    StorageFull {
+        /// Operation which failed
+        operation: IoOperation,
+        /// Error returned by the operation
+        source: io::Error,
+    },
     LockPoisoned(&'static panic::Location<'static>),
+    /// The value being inserted is already indexed under a different key, in the unique index
+    /// with the given table name
+    UniqueConstraintViolation(String),
+    /// The value being inserted, of the given size in bytes, is larger than a region of the
+    /// database file. See [`crate::Builder::set_region_size`]
+    ValueTooLarge(usize),
+    /// The keys passed to [`crate::Table::load_sorted`] or [`crate::Table::append`], for the table
+    /// with the given name, were not in strictly increasing order, or were not greater than all the
+    /// keys in the table
+    KeysNotSorted(String),
+    /// [`crate::Table::merge_in_place`] was called on the table with the given name, but no merge
+    /// operator is registered for it. See [`crate::Builder::set_merge_operator`]
+    MergeOperatorNotRegistered(String),
+    /// [`crate::Table::increment`] would have overflowed the value of a key in the table with the
+    /// given name
+    IntegerOverflow(String),
+    /// The write transaction holds the given number of bytes, which is more than its limit. See
+    /// [`crate::Builder::set_write_transaction_memory_limit`]
+    TransactionMemoryLimitExceeded(u64),
+    /// A panic occurred during an operation on a table of the write transaction, or while one of
+    /// its tables was open, or a table could not be updated when it was closed, which may have
+    /// left the tables inconsistent. The transaction can only be aborted
+    TransactionPoisoned,
+    /// The transaction was committed, and is visible to later transactions, but making it durable
+    /// failed with the given error. It may be lost if the system crashes before a later commit is
+    /// made durable
+    NotDurable(Box<Error>),
+    /// [`crate::ReadOnlyTable::export_changes`] was passed a base table from a different database
+    DatabaseMismatch,
 }
 
 impl<T> From<PoisonError<T>> for Error {
@@ -25,18 +135,124 @@ impl<T> From<PoisonError<T>> for Error {
 
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Error {
-        Error::Io(err)
+        Error::io(IoOperation::Other, err)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        let kind = match err {
+            Error::Io { source, .. } | Error::StorageFull { source, .. } => return source,
+            Error::Corrupted { .. } => io::ErrorKind::InvalidData,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+impl Error {
+    pub(crate) fn io(operation: IoOperation, source: io::Error) -> Error {
+        if operation != IoOperation::Other && is_storage_full(&source) {
+            Error::StorageFull { operation, source }
+        } else {
+            Error::Io { operation, source }
+        }
+    }
+
+    pub(crate) fn corrupted(message: impl Into<String>) -> Error {
+        Error::Corrupted {
+            message: message.into(),
+            page: None,
+            offset: None,
+        }
+    }
+
+    // The given page is corrupted
+    pub(crate) fn corrupted_page(page: PageNumber, message: impl Into<String>) -> Error {
+        Error::Corrupted {
+            message: message.into(),
+            page: Some(u64::from_le_bytes(page.to_le_bytes())),
+            offset: None,
+        }
+    }
+
+    // The data at the given offset in the file is corrupted
+    pub(crate) fn corrupted_at(offset: usize, message: impl Into<String>) -> Error {
+        Error::Corrupted {
+            message: message.into(),
+            page: None,
+            offset: Some(offset.try_into().unwrap()),
+        }
+    }
+
+    // Prefixes the message of a Corrupted error with `context`, such as the table which is
+    // corrupted. Other errors are returned unchanged
+    pub(crate) fn with_context(self, context: impl Display) -> Error {
+        match self {
+            Error::Corrupted {
+                message,
+                page,
+                offset,
+            } => Error::Corrupted {
+                message: format!("{}: {}", context, message),
+                page,
+                offset,
+            },
+            err => err,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_storage_full(err: &io::Error) -> bool {
+    matches!(err.raw_os_error(), Some(libc::ENOSPC | libc::EDQUOT))
+}
+
+#[cfg(windows)]
+fn is_storage_full(err: &io::Error) -> bool {
+    // ERROR_HANDLE_DISK_FULL and ERROR_DISK_FULL
+    matches!(err.raw_os_error(), Some(39 | 112))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_storage_full(_err: &io::Error) -> bool {
+    false
+}
+
+// Adds the operation which failed to I/O errors
+pub(crate) trait IoResultExt<T> {
+    fn during(self, operation: IoOperation) -> Result<T, Error>;
+}
+
+impl<T> IoResultExt<T> for io::Result<T> {
+    fn during(self, operation: IoOperation) -> Result<T, Error> {
+        self.map_err(|err| Error::io(operation, err))
     }
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            Error::Corrupted(msg) => {
-                write!(f, "DB corrupted: {}", msg)
+            Error::Corrupted { message, page, .. } => {
+                if let Some(page) = page {
+                    write!(f, "DB corrupted at page {}: {}", page, message)
+                } else {
+                    write!(f, "DB corrupted: {}", message)
+                }
             }
-            Error::TableTypeMismatch(msg) => {
-                write!(f, "{}", msg)
+            Error::UpgradeRequired(version) => {
+                write!(
+                    f,
+                    "File format version {} is outdated. Use Database::upgrade()",
+                    version
+                )
+            }
+            Error::TableTypeMismatch {
+                table,
+                expected,
+                actual,
+            } => {
+                write!(f, "{} is of type {} not {}", table, actual, expected)
             }
             Error::TableDoesNotExist(table) => {
                 write!(f, "Table '{}' does not exist", table)
@@ -44,13 +260,62 @@ impl Display for Error {
             Error::TableAlreadyOpen(name, location) => {
                 write!(f, "Table '{}' already opened at: {}", name, location)
             }
-            Error::Io(err) => {
-                write!(f, "I/O error: {}", err)
+            Error::Io { operation, source } => {
+                if *operation == IoOperation::Other {
+                    write!(f, "I/O error: {}", source)
+                } else {
+                    write!(f, "I/O error during {}: {}", operation.name(), source)
+                }
+            }
+            Error::StorageFull { operation, source } => {
+                write!(f, "Storage full during {}: {}", operation.name(), source)
             }
             Error::LockPoisoned(location) => {
                 write!(f, "Poisoned internal lock: {}", location)
             }
-            Error::DatabaseAlreadyOpen => {
+            Error::UniqueConstraintViolation(table) => {
+                write!(f, "Unique constraint violated in index '{}'", table)
+            }
+            Error::ValueTooLarge(len) => {
+                write!(f, "Value of {} bytes is larger than the region size", len)
+            }
+            Error::KeysNotSorted(table) => {
+                write!(
+                    f,
+                    "Keys loaded into table '{}' are not sorted, or are not greater than its existing keys",
+                    table
+                )
+            }
+            Error::MergeOperatorNotRegistered(table) => {
+                write!(f, "No merge operator is registered for table '{}'", table)
+            }
+            Error::IntegerOverflow(table) => {
+                write!(f, "Incremented value in table '{}' overflowed", table)
+            }
+            Error::TransactionMemoryLimitExceeded(bytes) => {
+                write!(
+                    f,
+                    "Write transaction holds {} bytes, which exceeds its memory limit",
+                    bytes
+                )
+            }
+            Error::TransactionPoisoned => {
+                write!(
+                    f,
+                    "Write transaction was poisoned by a panic, and can only be aborted"
+                )
+            }
+            Error::NotDurable(err) => {
+                write!(
+                    f,
+                    "Transaction committed, but could not be made durable: {}",
+                    err
+                )
+            }
+            Error::DatabaseMismatch => {
+                write!(f, "Tables belong to different databases")
+            }
+            Error::LockContention => {
                 write!(f, "Database already open. Cannot acquire lock.")
             }
             Error::InvalidSavepoint => {
@@ -63,4 +328,12 @@ impl Display for Error {
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } | Error::StorageFull { source, .. } => Some(source),
+            Error::NotDurable(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
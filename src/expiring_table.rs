@@ -0,0 +1,148 @@
+use crate::table::{ReadableTable, Table};
+use crate::types::{RedbKey, RedbValue};
+use crate::{Error, Result};
+use std::borrow::Borrow;
+use std::marker::PhantomData;
+use std::mem::size_of;
+
+/// A table whose entries expire at a given time, stored in a table with value type `&[u8]`
+///
+/// Each stored value is the expiration time, as a little-endian `u64`, followed by the serialized
+/// value. Times are supplied by the caller, in whatever unit it chooses. Expired entries are
+/// treated as absent, and remain in the table until removed by
+/// [`ExpiringTable::purge_expired`].
+///
+/// # Examples
+///
+/// ```rust
+/// use redb::*;
+/// # use tempfile::NamedTempFile;
+/// const SESSIONS: TableDefinition<&str, &[u8]> = TableDefinition::new("sessions");
+///
+/// # fn main() -> Result<(), Error> {
+/// # let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+/// # let filename = tmpfile.path();
+/// let db = Database::create(filename)?;
+/// let write_txn = db.begin_write()?;
+/// {
+///     let mut sessions: ExpiringTable<&str, u64> =
+///         ExpiringTable::new(write_txn.open_table(SESSIONS)?);
+///     sessions.insert("alice", &1, 100)?;
+///     sessions.insert("bob", &2, 200)?;
+///     assert_eq!(sessions.get("alice", 150)?, None);
+///     assert_eq!(sessions.get("bob", 150)?, Some(2));
+///     assert_eq!(sessions.purge_expired(150)?, 1);
+/// }
+/// write_txn.commit()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ExpiringTable<'db, 'txn, K: RedbKey + ?Sized + 'txn, V: RedbValue + ?Sized> {
+    table: Table<'db, 'txn, K, &'static [u8]>,
+    _value_type: PhantomData<V>,
+}
+
+impl<'db, 'txn, K: RedbKey + ?Sized + 'txn, V: RedbValue + ?Sized> ExpiringTable<'db, 'txn, K, V> {
+    pub fn new(table: Table<'db, 'txn, K, &'static [u8]>) -> Self {
+        Self {
+            table,
+            _value_type: Default::default(),
+        }
+    }
+
+    /// Returns the underlying table
+    pub fn into_inner(self) -> Table<'db, 'txn, K, &'static [u8]> {
+        self.table
+    }
+
+    /// Insert mapping of the given key to the given value, which expires at `expires_at`
+    pub fn insert<'a, 'b: 'a, AK, AV>(
+        &mut self,
+        key: &'a AK,
+        value: &'a AV,
+        expires_at: u64,
+    ) -> Result
+    where
+        K: 'b,
+        V: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+        AV: Borrow<V::RefBaseType<'b>> + ?Sized,
+    {
+        let value_bytes = V::as_bytes(value.borrow());
+        let mut stored = Vec::with_capacity(size_of::<u64>() + value_bytes.as_ref().len());
+        stored.extend_from_slice(&expires_at.to_le_bytes());
+        stored.extend_from_slice(value_bytes.as_ref());
+        self.table.insert(key, stored.as_slice())?;
+        Ok(())
+    }
+
+    /// Returns the value corresponding to the given key, if it has not expired at time `now`
+    pub fn get<'a, 'b: 'a, AK>(&self, key: &'a AK, now: u64) -> Result<Option<V::SelfType<'_>>>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        match self.table.get(key)? {
+            Some(stored) if expiration(stored)? > now => {
+                Ok(Some(V::from_bytes(&stored[size_of::<u64>()..])))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the expiration time of the given key, even if it has already expired
+    pub fn expiration<'a, 'b: 'a, AK>(&self, key: &'a AK) -> Result<Option<u64>>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        self.table.get(key)?.map(expiration).transpose()
+    }
+
+    /// Removes the given key
+    ///
+    /// Returns `true` if the key was present, even if it had expired
+    pub fn remove<'a, 'b: 'a, AK>(&mut self, key: &'a AK) -> Result<bool>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        Ok(self.table.remove(key)?.is_some())
+    }
+
+    /// Removes all entries which have expired at time `now`
+    ///
+    /// This visits every entry in the table. Returns the number of entries removed.
+    ///
+    /// If a stored value is too short to hold an expiration time, [`Error::Corrupted`] is
+    /// returned, and only the expired entries visited before it will have been removed
+    pub fn purge_expired(&mut self, now: u64) -> Result<u64> {
+        let mut error = None;
+        let removed = self.table.retain(|_, stored| {
+            if error.is_some() {
+                return true;
+            }
+            match expiration(stored) {
+                Ok(expires_at) => expires_at > now,
+                Err(err) => {
+                    error = Some(err);
+                    true
+                }
+            }
+        })?;
+        match error {
+            Some(err) => Err(err),
+            None => Ok(removed),
+        }
+    }
+}
+
+fn expiration(stored: &[u8]) -> Result<u64> {
+    match stored.get(..size_of::<u64>()) {
+        Some(bytes) => Ok(u64::from_le_bytes(bytes.try_into().unwrap())),
+        None => Err(Error::corrupted(format!(
+            "Expiring table value of {} bytes is too short to hold an expiration time",
+            stored.len()
+        ))),
+    }
+}
@@ -0,0 +1,220 @@
+use crate::tree_store::{RawBtree, TableTree, TableType, TransactionalMemory};
+use crate::{Error, Result};
+use std::io::{BufWriter, Write};
+
+/// Text formats which tables can be exported to, by [`crate::ReadTransaction::export_table`]
+///
+/// Keys and values are formatted according to the type names stored with the table:
+/// integers and floats are written as numbers, `str` as a string, and `()` as `null` in JSON or
+/// an empty field in CSV. All other types, including byte slices and tuples, are written as a
+/// string of their stored bytes in lowercase hex
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DataFormat {
+    /// One JSON object per line, with `key` and `value` fields
+    JsonLines,
+    /// Comma separated values, with a `key,value` header row. Fields are quoted if they contain
+    /// a comma, quote, or line break
+    Csv,
+}
+
+// How the stored bytes of a type are formatted
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum FieldType {
+    Unit,
+    Unsigned(usize),
+    Signed(usize),
+    Float(usize),
+    Str,
+    Bytes,
+}
+
+impl FieldType {
+    pub(crate) fn from_type_name(name: &str) -> Self {
+        match name {
+            "()" => FieldType::Unit,
+            "u8" => FieldType::Unsigned(1),
+            "u16" => FieldType::Unsigned(2),
+            "u32" => FieldType::Unsigned(4),
+            "u64" => FieldType::Unsigned(8),
+            "u128" => FieldType::Unsigned(16),
+            "i8" => FieldType::Signed(1),
+            "i16" => FieldType::Signed(2),
+            "i32" => FieldType::Signed(4),
+            "i64" => FieldType::Signed(8),
+            "i128" => FieldType::Signed(16),
+            "f32" => FieldType::Float(4),
+            "f64" => FieldType::Float(8),
+            "str" => FieldType::Str,
+            _ => FieldType::Bytes,
+        }
+    }
+
+    // Formats the stored bytes as a JSON value
+    fn write_json(self, data: &[u8], output: &mut Vec<u8>) -> Result {
+        match self {
+            FieldType::Unit => output.extend_from_slice(b"null"),
+            FieldType::Float(_) => {
+                let value = to_f64(data);
+                if value.is_finite() {
+                    output.extend_from_slice(format_float(data).as_bytes());
+                } else {
+                    output.extend_from_slice(b"null");
+                }
+            }
+            FieldType::Unsigned(_) | FieldType::Signed(_) => {
+                output.extend_from_slice(self.format_number(data).as_bytes());
+            }
+            FieldType::Str => write_json_string(to_str(data)?, output),
+            FieldType::Bytes => {
+                output.push(b'"');
+                write_hex(data, output);
+                output.push(b'"');
+            }
+        }
+
+        Ok(())
+    }
+
+    // Formats the stored bytes as a CSV field
+    fn write_csv(self, data: &[u8], output: &mut Vec<u8>) -> Result {
+        match self {
+            FieldType::Unit => {}
+            FieldType::Float(_) => output.extend_from_slice(format_float(data).as_bytes()),
+            FieldType::Unsigned(_) | FieldType::Signed(_) => {
+                output.extend_from_slice(self.format_number(data).as_bytes());
+            }
+            FieldType::Str => write_csv_string(to_str(data)?, output),
+            FieldType::Bytes => write_hex(data, output),
+        }
+
+        Ok(())
+    }
+
+    fn format_number(self, data: &[u8]) -> String {
+        let mut bytes = [0u8; 16];
+        bytes[..data.len()].copy_from_slice(data);
+        match self {
+            FieldType::Unsigned(_) => u128::from_le_bytes(bytes).to_string(),
+            FieldType::Signed(width) => {
+                // Sign extend
+                if data[width - 1] & 0x80 != 0 {
+                    bytes[width..].fill(0xFF);
+                }
+                i128::from_le_bytes(bytes).to_string()
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn to_str(data: &[u8]) -> Result<&str> {
+    std::str::from_utf8(data).map_err(|_| Error::corrupted("Stored str is not valid UTF-8"))
+}
+
+fn to_f64(data: &[u8]) -> f64 {
+    if data.len() == 4 {
+        f32::from_le_bytes(data.try_into().unwrap()).into()
+    } else {
+        f64::from_le_bytes(data.try_into().unwrap())
+    }
+}
+
+// Formats the value with the shortest representation which round trips through its own type
+fn format_float(data: &[u8]) -> String {
+    if data.len() == 4 {
+        f32::from_le_bytes(data.try_into().unwrap()).to_string()
+    } else {
+        f64::from_le_bytes(data.try_into().unwrap()).to_string()
+    }
+}
+
+fn write_hex(data: &[u8], output: &mut Vec<u8>) {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    for byte in data {
+        output.push(DIGITS[usize::from(byte >> 4)]);
+        output.push(DIGITS[usize::from(byte & 0xF)]);
+    }
+}
+
+pub(crate) fn write_json_string(value: &str, output: &mut Vec<u8>) {
+    output.push(b'"');
+    for c in value.chars() {
+        match c {
+            '"' => output.extend_from_slice(b"\\\""),
+            '\\' => output.extend_from_slice(b"\\\\"),
+            '\n' => output.extend_from_slice(b"\\n"),
+            '\r' => output.extend_from_slice(b"\\r"),
+            '\t' => output.extend_from_slice(b"\\t"),
+            c if u32::from(c) < 0x20 => {
+                output.extend_from_slice(format!("\\u{:04x}", u32::from(c)).as_bytes());
+            }
+            c => {
+                let mut buffer = [0u8; 4];
+                output.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            }
+        }
+    }
+    output.push(b'"');
+}
+
+fn write_csv_string(value: &str, output: &mut Vec<u8>) {
+    if value.contains([',', '"', '\n', '\r']) {
+        output.push(b'"');
+        output.extend_from_slice(value.replace('"', "\"\"").as_bytes());
+        output.push(b'"');
+    } else {
+        output.extend_from_slice(value.as_bytes());
+    }
+}
+
+// Writes every entry of the table to writer, and returns the number of entries written
+pub(crate) fn export_table(
+    tree: &TableTree,
+    mem: &TransactionalMemory,
+    name: &str,
+    format: DataFormat,
+    writer: impl Write,
+) -> Result<u64> {
+    let definition = tree
+        .get_table_untyped(name, TableType::Normal)?
+        .ok_or_else(|| Error::TableDoesNotExist(name.to_string()))?;
+    let key_type = FieldType::from_type_name(definition.get_key_type());
+    let value_type = FieldType::from_type_name(definition.get_value_type());
+
+    let mut writer = BufWriter::new(writer);
+    if format == DataFormat::Csv {
+        writer.write_all(b"key,value\n")?;
+    }
+    let mut line = vec![];
+    let mut entries = 0;
+    let btree = RawBtree::new(
+        definition.get_root(),
+        definition.get_fixed_key_size(),
+        definition.get_fixed_value_size(),
+        mem,
+    );
+    btree.for_each_entry(&mut |key, value| {
+        line.clear();
+        match format {
+            DataFormat::JsonLines => {
+                line.extend_from_slice(b"{\"key\":");
+                key_type.write_json(key, &mut line)?;
+                line.extend_from_slice(b",\"value\":");
+                value_type.write_json(value, &mut line)?;
+                line.extend_from_slice(b"}\n");
+            }
+            DataFormat::Csv => {
+                key_type.write_csv(key, &mut line)?;
+                line.push(b',');
+                value_type.write_csv(value, &mut line)?;
+                line.push(b'\n');
+            }
+        }
+        writer.write_all(&line)?;
+        entries += 1;
+        Ok(())
+    })?;
+    writer.flush()?;
+
+    Ok(entries)
+}
@@ -0,0 +1,135 @@
+use std::cmp::min;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// A fault which [`FaultInjector`] injects into an I/O operation
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// The operation fails with an error of the given kind, without modifying the file
+    Error(io::ErrorKind),
+    /// The operation fails with the operating system's error for a full disk (`ENOSPC`), without
+    /// modifying the file
+    StorageFull,
+    /// Simulates a power failure. If the operation is a write, only the given number of bytes
+    /// from the start of it reach the file. The operation fails, and so does every operation after
+    /// it, without modifying the file
+    Crash(usize),
+}
+
+#[derive(Default)]
+struct State {
+    operations: u64,
+    fault: Option<(u64, Fault)>,
+    crashed: bool,
+}
+
+/// Injects faults into the I/O of a database, so that applications can test how they handle
+/// write errors, full disks, and power failures
+///
+/// The operations which are counted, and may fail, are each write to the database file, each
+/// call to `fsync`, and each change to the length of the file. Operations are numbered from 0,
+/// starting once the database has been opened. To test every point at which a workload could
+/// crash, run it once to find the number of operations it performs, with
+/// [`FaultInjector::operations`], and then run it again with a [`Fault::Crash`] injected at each
+/// of them, reopening the database each time to check its contents.
+///
+/// A failure to write or `fsync` the file is not recoverable, so after any fault other than
+/// [`Fault::StorageFull`] is injected into one, every later commit fails until the database is
+/// reopened. A full disk is reported as [`crate::Error::StorageFull`], and the transaction can be
+/// retried
+///
+/// Cloned handles refer to the same injector. See [`crate::Builder::set_fault_injector`]
+#[derive(Clone, Default)]
+pub struct FaultInjector {
+    state: Arc<Mutex<State>>,
+}
+
+impl FaultInjector {
+    /// Creates an injector which has not performed any operations, and has no fault to inject
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Injects `fault` into the operation with the given index. Replaces any fault which has not
+    /// been injected yet
+    pub fn inject(&self, operation: u64, fault: Fault) {
+        self.state.lock().unwrap().fault = Some((operation, fault));
+    }
+
+    /// Number of operations which have been performed, or have failed, so far
+    pub fn operations(&self) -> u64 {
+        self.state.lock().unwrap().operations
+    }
+
+    /// Returns `true` if a [`Fault::Crash`] has been injected
+    pub fn has_crashed(&self) -> bool {
+        self.state.lock().unwrap().crashed
+    }
+
+    // Performs a write of `buf` with the given function, unless a fault is injected into it
+    pub(crate) fn write(
+        &self,
+        buf: &[u8],
+        write: impl FnOnce(&[u8]) -> io::Result<()>,
+    ) -> io::Result<()> {
+        match self.next_fault()? {
+            None => write(buf),
+            Some(Fault::Crash(bytes)) => {
+                write(&buf[..min(bytes, buf.len())])?;
+                Err(crashed())
+            }
+            Some(fault) => Err(to_error(fault)),
+        }
+    }
+
+    // Performs an operation which is not a write, unless a fault is injected into it
+    pub(crate) fn operation<T>(&self, operation: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+        match self.next_fault()? {
+            None => operation(),
+            Some(fault) => Err(to_error(fault)),
+        }
+    }
+
+    // Counts an operation, and returns the fault to inject into it, if any
+    fn next_fault(&self) -> io::Result<Option<Fault>> {
+        let mut state = self.state.lock().unwrap();
+        if state.crashed {
+            return Err(crashed());
+        }
+        let index = state.operations;
+        state.operations += 1;
+        match state.fault {
+            Some((operation, fault)) if operation == index => {
+                state.fault = None;
+                if matches!(fault, Fault::Crash(_)) {
+                    state.crashed = true;
+                }
+                Ok(Some(fault))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+fn to_error(fault: Fault) -> io::Error {
+    match fault {
+        Fault::Error(kind) => io::Error::new(kind, "injected fault"),
+        Fault::StorageFull => storage_full(),
+        Fault::Crash(_) => crashed(),
+    }
+}
+
+fn crashed() -> io::Error {
+    io::Error::from(io::ErrorKind::Other)
+}
+
+#[cfg(unix)]
+fn storage_full() -> io::Error {
+    io::Error::from_raw_os_error(libc::ENOSPC)
+}
+
+#[cfg(windows)]
+fn storage_full() -> io::Error {
+    // ERROR_DISK_FULL
+    io::Error::from_raw_os_error(112)
+}
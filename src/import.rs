@@ -0,0 +1,404 @@
+use crate::export::FieldType;
+use crate::{DataFormat, Result};
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+
+// A field as parsed from the input, before it's converted to the stored format of its type
+enum Field {
+    Null,
+    Number(String),
+    String(String),
+    // CSV doesn't distinguish between numbers and strings
+    Text(String),
+}
+
+// Reads every record from reader, and converts its key and value to their stored format. The
+// input is read one line at a time, and each record is checked as soon as it has been read.
+// Returns an error, which includes the line number of the record, if any record can't be parsed
+// or doesn't match the given types
+pub(crate) fn read_records(
+    format: DataFormat,
+    reader: impl Read,
+    key_type: (&str, Option<usize>),
+    value_type: (&str, Option<usize>),
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut reader = RecordReader {
+        reader: BufReader::new(reader),
+        line: 0,
+    };
+    if format == DataFormat::Csv {
+        match reader.read_csv_fields()? {
+            Some((_, fields)) if fields == ["key", "value"] => {}
+            Some((line, _)) => {
+                return Err(invalid_data((line, "expected a key,value header".to_string())).into())
+            }
+            None => return Err(invalid_data((1, "expected a key,value header".to_string())).into()),
+        }
+    }
+
+    let mut result = vec![];
+    loop {
+        let record = match format {
+            DataFormat::JsonLines => reader.read_json_record()?,
+            DataFormat::Csv => reader.read_csv_record()?,
+        };
+        let (line, key, value) = match record {
+            Some(record) => record,
+            None => break,
+        };
+        let key = to_stored_bytes(key, key_type.0, key_type.1)
+            .map_err(|err| invalid_data((line, format!("key {}", err))))?;
+        let value = to_stored_bytes(value, value_type.0, value_type.1)
+            .map_err(|err| invalid_data((line, format!("value {}", err))))?;
+        result.push((key, value));
+    }
+
+    Ok(result)
+}
+
+// Reads records from the input one line at a time, and counts the lines for error messages
+struct RecordReader<R: BufRead> {
+    reader: R,
+    line: usize,
+}
+
+impl<R: BufRead> RecordReader<R> {
+    // Returns the next line, including its line break, or None at the end of the input
+    fn read_line(&mut self) -> Result<Option<String>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        self.line += 1;
+        Ok(Some(line))
+    }
+
+    // Returns the line number, key, and value of the next record, skipping blank lines
+    fn read_json_record(&mut self) -> Result<Option<(usize, Field, Field)>> {
+        while let Some(line) = self.read_line()? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut parser = JsonParser {
+                input: line.as_bytes(),
+                position: 0,
+            };
+            let (key, value) = parser
+                .parse_record()
+                .map_err(|err| invalid_data((self.line, err)))?;
+            return Ok(Some((self.line, key, value)));
+        }
+
+        Ok(None)
+    }
+
+    // Returns the line number, key, and value of the next record, skipping blank lines
+    fn read_csv_record(&mut self) -> Result<Option<(usize, Field, Field)>> {
+        while let Some((line, mut fields)) = self.read_csv_fields()? {
+            if fields.len() == 1 && fields[0].is_empty() {
+                continue;
+            }
+            if fields.len() != 2 {
+                return Err(invalid_data((
+                    line,
+                    format!("expected 2 fields, found {}", fields.len()),
+                ))
+                .into());
+            }
+            let value = fields.pop().unwrap();
+            let key = fields.pop().unwrap();
+            return Ok(Some((line, Field::Text(key), Field::Text(value))));
+        }
+
+        Ok(None)
+    }
+
+    // Returns the line number and fields of the next CSV row. Quoted fields may contain commas,
+    // line breaks, and doubled quotes
+    fn read_csv_fields(&mut self) -> Result<Option<(usize, Vec<String>)>> {
+        let mut chars: Vec<char> = match self.read_line()? {
+            Some(line) => line.chars().collect(),
+            None => return Ok(None),
+        };
+        let record_line = self.line;
+        let mut i = 0;
+        let mut fields = vec![];
+        let mut field = String::new();
+        let mut quoted = false;
+        loop {
+            if i == chars.len() {
+                if !quoted {
+                    fields.push(field);
+                    break;
+                }
+                // The quoted field continues on the next line
+                match self.read_line()? {
+                    Some(line) => {
+                        chars = line.chars().collect();
+                        i = 0;
+                        continue;
+                    }
+                    None => {
+                        return Err(invalid_data((
+                            record_line,
+                            "unterminated quoted field".to_string(),
+                        ))
+                        .into())
+                    }
+                }
+            }
+            let c = chars[i];
+            i += 1;
+            if quoted {
+                match c {
+                    '"' if chars.get(i) == Some(&'"') => {
+                        i += 1;
+                        field.push('"');
+                    }
+                    '"' => quoted = false,
+                    c => field.push(c),
+                }
+            } else {
+                match c {
+                    '"' if field.is_empty() => quoted = true,
+                    ',' => fields.push(std::mem::take(&mut field)),
+                    '\r' if chars.get(i) == Some(&'\n') => {}
+                    '\n' => {
+                        fields.push(field);
+                        break;
+                    }
+                    c => field.push(c),
+                }
+            }
+        }
+
+        Ok(Some((record_line, fields)))
+    }
+}
+
+fn invalid_data((line, message): (usize, String)) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line, message),
+    )
+}
+
+fn to_stored_bytes(
+    field: Field,
+    type_name: &str,
+    fixed_width: Option<usize>,
+) -> std::result::Result<Vec<u8>, String> {
+    let bytes = match (FieldType::from_type_name(type_name), field) {
+        (FieldType::Unit, Field::Null) => vec![],
+        (FieldType::Unit, Field::Text(text)) if text.is_empty() => vec![],
+        (FieldType::Unsigned(width), Field::Number(text) | Field::Text(text)) => {
+            let value: u128 = text
+                .parse()
+                .map_err(|_| format!("{:?} is not an unsigned integer", text))?;
+            if width < 16 && value >> (8 * width) != 0 {
+                return Err(format!("{} does not fit in {} bytes", value, width));
+            }
+            value.to_le_bytes()[..width].to_vec()
+        }
+        (FieldType::Signed(width), Field::Number(text) | Field::Text(text)) => {
+            let value: i128 = text
+                .parse()
+                .map_err(|_| format!("{:?} is not an integer", text))?;
+            // The bits above the stored width must all be copies of the sign bit
+            if width < 16 && (value >> (8 * width - 1)) != 0 && (value >> (8 * width - 1)) != -1 {
+                return Err(format!("{} does not fit in {} bytes", value, width));
+            }
+            value.to_le_bytes()[..width].to_vec()
+        }
+        (FieldType::Float(4), Field::Null) => f32::NAN.to_le_bytes().to_vec(),
+        (FieldType::Float(4), Field::Number(text) | Field::Text(text)) => text
+            .parse::<f32>()
+            .map_err(|_| format!("{:?} is not a number", text))?
+            .to_le_bytes()
+            .to_vec(),
+        (FieldType::Float(_), Field::Null) => f64::NAN.to_le_bytes().to_vec(),
+        (FieldType::Float(_), Field::Number(text) | Field::Text(text)) => text
+            .parse::<f64>()
+            .map_err(|_| format!("{:?} is not a number", text))?
+            .to_le_bytes()
+            .to_vec(),
+        (FieldType::Str, Field::String(text) | Field::Text(text)) => text.into_bytes(),
+        (FieldType::Bytes, Field::String(text) | Field::Text(text)) => parse_hex(&text)?,
+        _ => {
+            return Err(format!("does not match the table's type, {}", type_name));
+        }
+    };
+    if let Some(width) = fixed_width {
+        if bytes.len() != width {
+            return Err(format!(
+                "is {} bytes long, but the table stores {} bytes",
+                bytes.len(),
+                width
+            ));
+        }
+    }
+
+    Ok(bytes)
+}
+
+fn parse_hex(text: &str) -> std::result::Result<Vec<u8>, String> {
+    let digits = text.as_bytes();
+    if digits.len() % 2 == 1 {
+        return Err(format!("{:?} has an odd number of hex digits", text));
+    }
+    let mut result = Vec::with_capacity(digits.len() / 2);
+    for pair in digits.chunks(2) {
+        let pair = std::str::from_utf8(pair).map_err(|_| format!("{:?} is not hex", text))?;
+        result.push(u8::from_str_radix(pair, 16).map_err(|_| format!("{:?} is not hex", text))?);
+    }
+
+    Ok(result)
+}
+
+// Parses objects of the form written by export_table(), with a key and value field which are
+// each a number, string, or null
+struct JsonParser<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> JsonParser<'a> {
+    fn parse_record(&mut self) -> std::result::Result<(Field, Field), String> {
+        let mut key = None;
+        let mut value = None;
+        self.expect(b'{')?;
+        loop {
+            let name = self.parse_string()?;
+            self.expect(b':')?;
+            let field = self.parse_field()?;
+            match name.as_str() {
+                "key" if key.is_none() => key = Some(field),
+                "value" if value.is_none() => value = Some(field),
+                _ => return Err(format!("unexpected field {:?}", name)),
+            }
+            self.skip_whitespace();
+            match self.next() {
+                Some(b',') => {}
+                Some(b'}') => break,
+                _ => return Err("expected ',' or '}'".to_string()),
+            }
+        }
+        self.skip_whitespace();
+        if self.position < self.input.len() {
+            return Err("unexpected data after the end of the object".to_string());
+        }
+
+        match (key, value) {
+            (Some(key), Some(value)) => Ok((key, value)),
+            _ => Err("expected a key and a value field".to_string()),
+        }
+    }
+
+    fn parse_field(&mut self) -> std::result::Result<Field, String> {
+        self.skip_whitespace();
+        match self.input.get(self.position) {
+            Some(b'"') => Ok(Field::String(self.parse_string()?)),
+            Some(b'n') => {
+                if self.input[self.position..].starts_with(b"null") {
+                    self.position += 4;
+                    Ok(Field::Null)
+                } else {
+                    Err("expected a value".to_string())
+                }
+            }
+            Some(c) if *c == b'-' || c.is_ascii_digit() => {
+                let start = self.position;
+                while self.position < self.input.len()
+                    && matches!(
+                        self.input[self.position],
+                        b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'
+                    )
+                {
+                    self.position += 1;
+                }
+                let number = std::str::from_utf8(&self.input[start..self.position]).unwrap();
+                Ok(Field::Number(number.to_string()))
+            }
+            _ => Err("expected a number, string, or null".to_string()),
+        }
+    }
+
+    fn parse_string(&mut self) -> std::result::Result<String, String> {
+        self.expect(b'"')?;
+        let mut result = vec![];
+        loop {
+            match self.next() {
+                None => return Err("unterminated string".to_string()),
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    let escaped = match self.next() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let high = self.parse_hex4()?;
+                            let code = if (0xD800..0xDC00).contains(&high) {
+                                // A surrogate pair
+                                if self.next() != Some(b'\\') || self.next() != Some(b'u') {
+                                    return Err("unpaired surrogate".to_string());
+                                }
+                                let low = self.parse_hex4()?;
+                                if !(0xDC00..0xE000).contains(&low) {
+                                    return Err("unpaired surrogate".to_string());
+                                }
+                                0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                            } else {
+                                high
+                            };
+                            char::from_u32(code).ok_or("invalid unicode escape")?
+                        }
+                        _ => return Err("invalid escape".to_string()),
+                    };
+                    let mut buffer = [0u8; 4];
+                    result.extend_from_slice(escaped.encode_utf8(&mut buffer).as_bytes());
+                }
+                Some(c) => result.push(c),
+            }
+        }
+
+        // The input was a str, and escapes are only split at ASCII characters, so this is valid
+        Ok(String::from_utf8(result).unwrap())
+    }
+
+    fn parse_hex4(&mut self) -> std::result::Result<u32, String> {
+        let digits = self
+            .input
+            .get(self.position..(self.position + 4))
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or("invalid unicode escape")?;
+        self.position += 4;
+        Ok(digits)
+    }
+
+    fn expect(&mut self, c: u8) -> std::result::Result<(), String> {
+        self.skip_whitespace();
+        if self.next() == Some(c) {
+            Ok(())
+        } else {
+            Err(format!("expected '{}'", char::from(c)))
+        }
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let c = self.input.get(self.position).copied();
+        self.position += 1;
+        c
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.position < self.input.len() && self.input[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use crate::types::RedbKey;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hasher;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::time::Duration;
+
+/// Notification that a key was modified by a committed transaction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyChange {
+    table: Arc<str>,
+    key_hash: Option<u64>,
+}
+
+impl KeyChange {
+    /// Name of the table that was modified
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    /// Hash of the modified key, as computed by [`KeyChange::hash_key`], or `None` if any key in
+    /// the table may have been modified
+    pub fn key_hash(&self) -> Option<u64> {
+        self.key_hash
+    }
+
+    /// Computes the hash used to identify `key` in notifications
+    ///
+    /// The hash is stable for the lifetime of the process, but may change between versions of redb
+    pub fn hash_key<K: RedbKey + ?Sized>(key: &K::RefBaseType<'_>) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(K::as_bytes(key).as_ref());
+        hasher.finish()
+    }
+}
+
+struct SubscriberState {
+    queue: VecDeque<KeyChange>,
+    missed: u64,
+}
+
+struct Subscriber {
+    capacity: usize,
+    state: Mutex<SubscriberState>,
+    available: Condvar,
+}
+
+impl Subscriber {
+    fn push(&self, changes: &[KeyChange], invalidate_all: bool) {
+        let mut state = self.state.lock().unwrap();
+        if invalidate_all {
+            state.missed += 1;
+        }
+        for change in changes {
+            if state.queue.len() == self.capacity {
+                state.queue.pop_front();
+                state.missed += 1;
+            }
+            state.queue.push_back(change.clone());
+        }
+        drop(state);
+        self.available.notify_all();
+    }
+}
+
+/// Receives notifications of the keys modified by each committed write transaction
+///
+/// Notifications are buffered up to a fixed capacity. When the buffer is full the oldest
+/// notification is discarded, and counted by [`KeyChangeReceiver::take_missed`], so that caches
+/// can fall back to invalidating all their entries
+pub struct KeyChangeReceiver {
+    subscriber: Arc<Subscriber>,
+}
+
+impl KeyChangeReceiver {
+    /// Returns the next notification, if one is available
+    pub fn try_recv(&self) -> Option<KeyChange> {
+        self.subscriber.state.lock().unwrap().queue.pop_front()
+    }
+
+    /// Waits up to `timeout` for the next notification
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<KeyChange> {
+        let state = self.subscriber.state.lock().unwrap();
+        let (mut state, _) = self
+            .subscriber
+            .available
+            .wait_timeout_while(state, timeout, |state| state.queue.is_empty())
+            .unwrap();
+        state.queue.pop_front()
+    }
+
+    /// Returns the number of notifications which have been discarded since the last call, and
+    /// resets it to zero
+    ///
+    /// Restoring a savepoint also counts as a missed notification, since it may modify any key
+    pub fn take_missed(&self) -> u64 {
+        let mut state = self.subscriber.state.lock().unwrap();
+        let missed = state.missed;
+        state.missed = 0;
+        missed
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct KeyChangeBroadcaster {
+    active: AtomicBool,
+    subscribers: Mutex<Vec<Weak<Subscriber>>>,
+}
+
+impl KeyChangeBroadcaster {
+    pub(crate) fn subscribe(&self, capacity: usize) -> KeyChangeReceiver {
+        assert!(capacity > 0);
+        let subscriber = Arc::new(Subscriber {
+            capacity,
+            state: Mutex::new(SubscriberState {
+                queue: VecDeque::with_capacity(capacity),
+                missed: 0,
+            }),
+            available: Condvar::new(),
+        });
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.push(Arc::downgrade(&subscriber));
+        self.active.store(true, Ordering::Release);
+
+        KeyChangeReceiver { subscriber }
+    }
+
+    // Fast check used by write transactions to decide whether to record their changes
+    pub(crate) fn has_subscribers(&self) -> bool {
+        self.active.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn publish(&self, changes: &PendingKeyChanges) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|x| x.strong_count() > 0);
+        if subscribers.is_empty() {
+            self.active.store(false, Ordering::Release);
+            return;
+        }
+        let mut notifications = vec![];
+        for (table, hashes) in changes.tables.iter() {
+            let table: Arc<str> = Arc::from(table.as_str());
+            for key_hash in hashes {
+                notifications.push(KeyChange {
+                    table: table.clone(),
+                    key_hash: *key_hash,
+                });
+            }
+        }
+        for subscriber in subscribers.iter().filter_map(|x| x.upgrade()) {
+            subscriber.push(&notifications, changes.invalidate_all);
+        }
+    }
+}
+
+// Changes made by a write transaction, which are published once it commits
+#[derive(Clone, Default)]
+pub(crate) struct PendingKeyChanges {
+    tables: HashMap<String, Vec<Option<u64>>>,
+    invalidate_all: bool,
+}
+
+impl PendingKeyChanges {
+    pub(crate) fn record(&mut self, table: &str, key_hash: Option<u64>) {
+        if let Some(hashes) = self.tables.get_mut(table) {
+            hashes.push(key_hash);
+        } else {
+            self.tables.insert(table.to_string(), vec![key_hash]);
+        }
+    }
+
+    pub(crate) fn invalidate_all(&mut self) {
+        self.invalidate_all = true;
+    }
+
+    // Returns the approximate number of bytes used to store the changes
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.tables
+            .iter()
+            .map(|(table, hashes)| table.len() + hashes.len() * size_of::<Option<u64>>())
+            .sum()
+    }
+}
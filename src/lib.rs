@@ -8,29 +8,70 @@
 
 extern crate core;
 
-pub use db::{Builder, Database, MultimapTableDefinition, TableDefinition, WriteStrategy};
-pub use error::Error;
+pub use blob_table::{BlobReader, BlobTable, BlobWriter};
+pub use db::{
+    AccessPattern, AllocationEvent, AllocationEventKind, Builder, CacheStats, CompactionPolicy,
+    Database, Metrics, MultimapTableDefinition, RepairReport, RetentionPolicy, StorageBackend,
+    TableDefinition, WriteStrategy,
+};
+pub use error::{Error, IoOperation};
+pub use expiring_table::ExpiringTable;
+pub use export::DataFormat;
+#[cfg(feature = "fault_injection")]
+pub use fault_injection::{Fault, FaultInjector};
+pub use key_changes::{KeyChange, KeyChangeReceiver};
+pub use log_table::LogTable;
 pub use multimap_table::{
     MultimapRangeIter, MultimapTable, MultimapValueIter, ReadOnlyMultimapTable,
     ReadableMultimapTable,
 };
-pub use table::{RangeIter, ReadOnlyTable, ReadableTable, Table};
-pub use transactions::{DatabaseStats, Durability, ReadTransaction, WriteTransaction};
-pub use tree_store::{AccessGuard, Savepoint};
+pub use set_table::SetTable;
+pub use storage::{InMemoryStorage, StorageDevice};
+pub use table::{
+    Cursor, Drain, KeyIter, RangeIter, ReadOnlyTable, ReadableTable, Table, ValueIter,
+};
+pub use time_series::TimeSeriesTable;
+pub use transactions::{
+    CommitStats, DatabaseStats, Durability, NestedWriteTransaction, OwnedReadTransaction,
+    OwnedWriteTransaction, PendingCommit, PreparedTransaction, ReadTransaction,
+    ReadTransactionInfo, WriteTransaction,
+};
+pub use tree_store::{
+    AccessGuard, IntegrityIssue, IntegrityIssueKind, IntegrityReport, PageInfo, Savepoint,
+    Scrubber, TableSchema, TreeDumpFormat, TreePageIter,
+};
+pub use types::{Bytes, IntegerValue, KeyOrder, OrderedKey, PrefixKey, RedbKey, RedbValue};
+pub use unique_table::UniqueIndexedTable;
+pub use write_batch::WriteBatch;
 
 type Result<T = (), E = Error> = std::result::Result<T, E>;
 
 #[cfg(feature = "python")]
 pub use crate::python::redb;
 
+mod blob_table;
 mod db;
 mod error;
+mod expiring_table;
+mod export;
+#[cfg(feature = "fault_injection")]
+mod fault_injection;
+mod import;
+mod key_changes;
+mod log_table;
 mod multimap_table;
 #[cfg(feature = "python")]
 mod python;
+mod set_table;
+mod simulation;
+mod storage;
 mod table;
+mod tagged_union;
+mod time_series;
 mod transaction_tracker;
 mod transactions;
 mod tree_store;
 mod tuple_types;
 mod types;
+mod unique_table;
+mod write_batch;
@@ -0,0 +1,167 @@
+use crate::table::{RangeIter, ReadableTable, Table};
+use crate::types::RedbValue;
+use crate::{AccessGuard, Result};
+use std::borrow::Borrow;
+use std::ops::{Range, RangeBounds};
+
+/// An append-only log of values, stored in a table with key type `u64`
+///
+/// Each appended value is assigned the next sequence number, starting at zero. The next sequence
+/// number is stored in a second table, under the name of the log table, so sequence numbers are
+/// never reused, even if the most recent entries are removed. One sequences table may be shared
+/// by many logs.
+///
+/// # Examples
+///
+/// ```rust
+/// use redb::*;
+/// # use tempfile::NamedTempFile;
+/// const EVENTS: TableDefinition<u64, &str> = TableDefinition::new("events");
+/// const SEQUENCES: TableDefinition<&str, u64> = TableDefinition::new("sequences");
+///
+/// # fn main() -> Result<(), Error> {
+/// # let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+/// # let filename = tmpfile.path();
+/// let db = Database::create(filename)?;
+/// let write_txn = db.begin_write()?;
+/// {
+///     let mut log = LogTable::new(
+///         write_txn.open_table(EVENTS)?,
+///         write_txn.open_table(SEQUENCES)?,
+///     );
+///     assert_eq!(log.append("created")?, 0);
+///     assert_eq!(log.append("renamed")?, 1);
+///     assert_eq!(log.extend(["updated", "deleted"])?, 2..4);
+///     assert_eq!(log.get(1)?, Some("renamed"));
+/// }
+/// write_txn.commit()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct LogTable<'db, 'txn, V: RedbValue + ?Sized> {
+    table: Table<'db, 'txn, u64, V>,
+    sequences: Table<'db, 'txn, &'static str, u64>,
+    // Cached, so that appending does not need to look up the next sequence number each time
+    next_sequence: Option<u64>,
+}
+
+impl<'db, 'txn, V: RedbValue + ?Sized + 'txn> LogTable<'db, 'txn, V> {
+    pub fn new(
+        table: Table<'db, 'txn, u64, V>,
+        sequences: Table<'db, 'txn, &'static str, u64>,
+    ) -> Self {
+        Self {
+            table,
+            sequences,
+            next_sequence: None,
+        }
+    }
+
+    /// Returns the underlying log and sequences tables
+    pub fn into_inner(
+        self,
+    ) -> (
+        Table<'db, 'txn, u64, V>,
+        Table<'db, 'txn, &'static str, u64>,
+    ) {
+        (self.table, self.sequences)
+    }
+
+    /// Returns the sequence number which will be assigned to the next appended value
+    pub fn next_sequence(&mut self) -> Result<u64> {
+        if let Some(sequence) = self.next_sequence {
+            return Ok(sequence);
+        }
+        let stored = self.sequences.get(self.table.name())?.unwrap_or_default();
+        // Entries may also have been inserted into the table directly
+        let sequence = match self.table.last()? {
+            Some((last, _)) => last
+                .checked_add(1)
+                .expect("Sequence numbers exhausted")
+                .max(stored),
+            None => stored,
+        };
+        self.next_sequence = Some(sequence);
+        Ok(sequence)
+    }
+
+    fn set_next_sequence(&mut self, sequence: u64) -> Result {
+        self.sequences.insert(self.table.name(), &sequence)?;
+        self.next_sequence = Some(sequence);
+        Ok(())
+    }
+
+    /// Append a value to the log
+    ///
+    /// Returns the sequence number assigned to it
+    pub fn append<'a, 'b: 'a, AV>(&mut self, value: &'a AV) -> Result<u64>
+    where
+        V: 'b,
+        AV: Borrow<V::RefBaseType<'b>> + ?Sized,
+    {
+        let sequence = self.next_sequence()?;
+        self.table.insert(&sequence, value)?;
+        self.set_next_sequence(sequence + 1)?;
+        Ok(sequence)
+    }
+
+    /// Append each of the given values to the log
+    ///
+    /// The values are added to the right edge of the table with [`Table::load_sorted`], which is
+    /// much faster than appending them one at a time. Returns the range of sequence numbers
+    /// assigned to them
+    pub fn extend<'a, 'b: 'a, AV>(
+        &mut self,
+        values: impl IntoIterator<Item = &'a AV>,
+    ) -> Result<Range<u64>>
+    where
+        V: 'b,
+        AV: Borrow<V::RefBaseType<'b>> + ?Sized + 'a,
+    {
+        let start = self.next_sequence()?;
+        let entries: Vec<(u64, &AV)> = (start..).zip(values).collect();
+        let end = entries.last().map_or(start, |(sequence, _)| sequence + 1);
+        self.table
+            .load_sorted(entries.iter().map(|(sequence, value)| (sequence, *value)))?;
+        self.set_next_sequence(end)?;
+        Ok(start..end)
+    }
+
+    /// Returns the value with the given sequence number
+    pub fn get(&self, sequence: u64) -> Result<Option<V::SelfType<'_>>> {
+        self.table.get(&sequence)
+    }
+
+    /// Returns the entry with the largest sequence number, or `None` if the log is empty
+    pub fn last(&self) -> Result<Option<(u64, V::SelfType<'_>)>> {
+        self.table.last()
+    }
+
+    /// Returns a double-ended iterator over the entries whose sequence numbers are within `range`
+    pub fn range(&self, range: impl RangeBounds<u64>) -> Result<RangeIter<'_, u64, V>> {
+        self.table
+            .range::<u64>((range.start_bound().cloned(), range.end_bound().cloned()))
+    }
+
+    /// Removes all entries with a sequence number less than `sequence`
+    ///
+    /// Returns the number of entries removed
+    pub fn truncate_before(&mut self, sequence: u64) -> Result<u64> {
+        self.table.remove_before(&sequence)
+    }
+
+    /// Removes the entry with the given sequence number, returning its value
+    pub fn remove(&mut self, sequence: u64) -> Result<Option<AccessGuard<'_, V>>> {
+        self.table.remove(&sequence)
+    }
+
+    /// Returns the number of entries in the log
+    pub fn len(&self) -> Result<usize> {
+        self.table.len()
+    }
+
+    /// Returns `true` if the log is empty
+    pub fn is_empty(&self) -> Result<bool> {
+        self.table.is_empty()
+    }
+}
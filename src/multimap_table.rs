@@ -1,12 +1,18 @@
 use crate::multimap_table::DynamicCollectionType::{Inline, Subtree};
+#[cfg(feature = "integrity_checks")]
+use crate::tree_store::verify_tree;
 use crate::tree_store::{
-    AllPageNumbersBtreeIter, Btree, BtreeMut, BtreeRangeIter, Checksum, LeafAccessor, LeafKeyIter,
-    Page, PageNumber, RawLeafBuilder, TransactionalMemory, BRANCH, LEAF,
+    copy_tree, AllPageNumbersBtreeIter, Btree, BtreeMut, BtreeRangeIter, Checksum, LeafAccessor,
+    LeafKeyIter, Page, PageNumber, RawLeafBuilder, TransactionalMemory, BRANCH, LEAF,
 };
-use crate::types::{RedbKey, RedbValue};
+use crate::types::{from_stored_bytes, RedbKey, RedbValue};
+#[cfg(feature = "integrity_checks")]
+use crate::Error;
 use crate::{Result, WriteTransaction};
 use std::borrow::Borrow;
 use std::cell::RefCell;
+#[cfg(feature = "integrity_checks")]
+use std::cmp::Ordering;
 use std::convert::TryInto;
 use std::marker::PhantomData;
 use std::mem;
@@ -40,6 +46,41 @@ pub(crate) fn parse_subtree_roots<T: Page>(
     }
 }
 
+// Copies the subtree referenced by a value of a multimap table, if its values are not stored
+// inline, from source into dest, and updates the reference in place
+pub(crate) fn copy_subtree_value(
+    value: &mut [u8],
+    fixed_value_size: Option<usize>,
+    source: &TransactionalMemory,
+    dest: &TransactionalMemory,
+) -> Result {
+    let collection = DynamicCollection::new(value);
+    if matches!(collection.collection_type(), Subtree) {
+        let (root, checksum) = copy_tree(
+            collection.as_subtree(),
+            fixed_value_size,
+            <() as RedbValue>::fixed_width(),
+            source,
+            dest,
+            &mut |_| Ok(()),
+        )?;
+        value.copy_from_slice(&DynamicCollection::make_subtree_data(root, checksum));
+    }
+    Ok(())
+}
+
+// Returns the root of the subtree referenced by a value of a multimap table, or None if its values
+// are stored inline, or it is malformed
+pub(crate) fn parse_subtree_root(value: &[u8]) -> Option<(PageNumber, Checksum)> {
+    let subtree_type: u8 = Subtree.into();
+    let subtree_len = 1 + PageNumber::serialized_size() + size_of::<Checksum>();
+    if value.len() == subtree_len && value[0] == subtree_type {
+        Some(DynamicCollection::new(value).as_subtree())
+    } else {
+        None
+    }
+}
+
 enum DynamicCollectionType {
     Inline,
     Subtree,
@@ -159,9 +200,14 @@ impl DynamicCollection {
                 MultimapValueIter::new_inline(leaf_iter)
             }
             Subtree => {
-                let root = self.as_subtree().0;
+                let root = self.as_subtree();
                 MultimapValueIter::new_subtree(
-                    BtreeRangeIter::new::<RangeFull, &V::RefBaseType<'_>>(.., Some(root), mem),
+                    BtreeRangeIter::new::<RangeFull, &V::RefBaseType<'_>>(
+                        ..,
+                        Some(root),
+                        mem,
+                        None,
+                    ),
                 )
             }
         }
@@ -183,9 +229,13 @@ impl DynamicCollection {
                 MultimapValueIter::new_inline(leaf_iter)
             }
             Subtree => {
-                let root = self.as_subtree().0;
-                let inner =
-                    BtreeRangeIter::new::<RangeFull, &V::RefBaseType<'_>>(.., Some(root), mem);
+                let root = self.as_subtree();
+                let inner = BtreeRangeIter::new::<RangeFull, &V::RefBaseType<'_>>(
+                    ..,
+                    Some(root),
+                    mem,
+                    None,
+                );
                 MultimapValueIter::new_subtree_free_on_drop(inner, freed_pages, pages, mem)
             }
         }
@@ -212,6 +262,11 @@ enum ValueIterState<'a, V: RedbKey + ?Sized + 'a> {
     InlineLeaf(LeafKeyIter<'a>),
 }
 
+/// An iterator over the values of a key in a multimap table
+///
+/// # Panics
+///
+/// Iteration panics if a corrupted page is encountered
 pub struct MultimapValueIter<'a, V: RedbKey + ?Sized + 'a> {
     inner: ValueIterState<'a, V>,
     freed_pages: Option<Rc<RefCell<Vec<PageNumber>>>>,
@@ -262,24 +317,20 @@ impl<'a, V: RedbKey + ?Sized> Iterator for MultimapValueIter<'a, V> {
 
     fn next(&mut self) -> Option<Self::Item> {
         match self.inner {
-            ValueIterState::Subtree(ref mut iter) => iter.next().map(|e| V::from_bytes(e.key())),
-            ValueIterState::InlineLeaf(ref mut iter) => {
-                iter.next_key().map(|key| V::from_bytes(key))
-            }
+            ValueIterState::Subtree(ref mut iter) => iter.next_or_panic().map(|e| e.key()),
+            ValueIterState::InlineLeaf(ref mut iter) => iter.next_key(),
         }
+        .map(|data| from_stored_bytes::<V>(data).unwrap_or_else(|err| panic!("{}", err)))
     }
 }
 
 impl<'a, V: RedbKey + ?Sized> DoubleEndedIterator for MultimapValueIter<'a, V> {
     fn next_back(&mut self) -> Option<Self::Item> {
         match self.inner {
-            ValueIterState::Subtree(ref mut iter) => {
-                iter.next_back().map(|e| V::from_bytes(e.key()))
-            }
-            ValueIterState::InlineLeaf(ref mut iter) => {
-                iter.next_key_back().map(|key| V::from_bytes(key))
-            }
+            ValueIterState::Subtree(ref mut iter) => iter.next_back_or_panic().map(|e| e.key()),
+            ValueIterState::InlineLeaf(ref mut iter) => iter.next_key_back(),
         }
+        .map(|data| from_stored_bytes::<V>(data).unwrap_or_else(|err| panic!("{}", err)))
     }
 }
 
@@ -308,6 +359,11 @@ impl<'a, V: RedbKey + ?Sized> Drop for MultimapValueIter<'a, V> {
     }
 }
 
+/// An iterator over a range of keys of a multimap table, and their values
+///
+/// # Panics
+///
+/// Iteration panics if a corrupted page is encountered
 pub struct MultimapRangeIter<'a, K: RedbKey + ?Sized + 'a, V: RedbKey + ?Sized + 'a> {
     inner: BtreeRangeIter<'a, K, DynamicCollection>,
     mem: &'a TransactionalMemory,
@@ -332,7 +388,7 @@ impl<'a, K: RedbKey + ?Sized + 'a, V: RedbKey + ?Sized + 'a> Iterator
     type Item = (K::SelfType<'a>, MultimapValueIter<'a, V>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        let entry = self.inner.next()?;
+        let entry = self.inner.next_or_panic()?;
         let key = K::from_bytes(entry.key());
         let collection = DynamicCollection::from_bytes(entry.value());
         let iter = collection.iter(self.mem);
@@ -345,7 +401,7 @@ impl<'a, K: RedbKey + ?Sized + 'a, V: RedbKey + ?Sized + 'a> DoubleEndedIterator
     for MultimapRangeIter<'a, K, V>
 {
     fn next_back(&mut self) -> Option<Self::Item> {
-        let entry = self.inner.next_back()?;
+        let entry = self.inner.next_back_or_panic()?;
         let key = K::from_bytes(entry.key());
         let collection = DynamicCollection::from_bytes(entry.value());
         let iter = collection.iter(self.mem);
@@ -387,8 +443,8 @@ impl<'db, 'txn, K: RedbKey + ?Sized + 'txn, V: RedbKey + ?Sized + 'txn>
     }
 
     #[allow(dead_code)]
-    pub(crate) fn print_debug(&self, include_values: bool) {
-        self.tree.print_debug(include_values);
+    pub(crate) fn print_debug(&self, include_values: bool) -> Result {
+        self.tree.print_debug(include_values)
     }
 
     /// Add the given value to the mapping of the key
@@ -403,6 +459,8 @@ impl<'db, 'txn, K: RedbKey + ?Sized + 'txn, V: RedbKey + ?Sized + 'txn>
         K: 'b,
         V: 'b,
     {
+        self.transaction.check_memory_limit()?;
+        let _operation = self.transaction.begin_operation(&self.name)?;
         let value_bytes = V::as_bytes(value.borrow());
         let value_bytes_ref = value_bytes.as_ref();
         let existed = if let Some(v) = self.tree.get(key.borrow())? {
@@ -537,6 +595,8 @@ impl<'db, 'txn, K: RedbKey + ?Sized + 'txn, V: RedbKey + ?Sized + 'txn>
             false
         };
 
+        self.transaction
+            .record_key_change::<K>(&self.name, key.borrow());
         Ok(existed)
     }
 
@@ -545,6 +605,7 @@ impl<'db, 'txn, K: RedbKey + ?Sized + 'txn, V: RedbKey + ?Sized + 'txn>
     /// Returns `true` if the key-value pair was present
     // TODO: should take a Borrow instead of a &
     pub fn remove(&mut self, key: &K::RefBaseType<'_>, value: &V::RefBaseType<'_>) -> Result<bool> {
+        let _operation = self.transaction.begin_operation(&self.name)?;
         let existed = if let Some(v) = self.tree.get(key)? {
             match v.collection_type() {
                 Inline => {
@@ -603,7 +664,7 @@ impl<'db, 'txn, K: RedbKey + ?Sized + 'txn, V: RedbKey + ?Sized + 'txn>
                     let existed = unsafe { subtree.remove(value)?.is_some() };
 
                     if let Some((new_root, new_checksum)) = subtree.get_root() {
-                        let page = self.mem.get_page(new_root);
+                        let page = self.mem.get_page(new_root)?;
                         match page.memory()[0] {
                             LEAF => {
                                 let accessor = LeafAccessor::new(
@@ -659,6 +720,7 @@ impl<'db, 'txn, K: RedbKey + ?Sized + 'txn, V: RedbKey + ?Sized + 'txn>
             false
         };
 
+        self.transaction.record_key_change::<K>(&self.name, key);
         Ok(existed)
     }
 
@@ -667,6 +729,7 @@ impl<'db, 'txn, K: RedbKey + ?Sized + 'txn, V: RedbKey + ?Sized + 'txn>
     /// Returns an iterator over the removed values. Values are in ascending order.
     // TODO: should take a Borrow instead of a &
     pub fn remove_all(&mut self, key: &K::RefBaseType<'_>) -> Result<MultimapValueIter<V>> {
+        let _operation = self.transaction.begin_operation(&self.name)?;
         // Safety: No other references to this table can exist.
         // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
         // and we borrow &mut self.
@@ -682,7 +745,7 @@ impl<'db, 'txn, K: RedbKey + ?Sized + 'txn, V: RedbKey + ?Sized + 'txn>
                 tmp_page.memory_mut()[..len].copy_from_slice(collection_bytes.as_ref());
                 let mut pages = vec![tmp_page_number];
                 drop(tmp_page);
-                let tmp_page = self.mem.get_page(tmp_page_number);
+                let tmp_page = self.mem.get_page(tmp_page_number)?;
                 let collection = DynamicCollection::new(&tmp_page.memory_full_lifetime()[..len]);
 
                 if matches!(collection.collection_type(), DynamicCollectionType::Subtree) {
@@ -692,17 +755,18 @@ impl<'db, 'txn, K: RedbKey + ?Sized + 'txn, V: RedbKey + ?Sized + 'txn>
                         V::fixed_width(),
                         <() as RedbValue>::fixed_width(),
                         self.mem,
-                    );
+                    )?;
                     for page in all_pages {
-                        pages.push(page);
+                        pages.push(page?);
                     }
                 }
                 collection.iter_free_on_drop(pages, self.freed_pages.clone(), self.mem)
             } else {
                 MultimapValueIter::new_subtree(
-                    BtreeRangeIter::new::<RangeFull, &V::RefBaseType<'_>>(.., None, self.mem),
+                    BtreeRangeIter::new::<RangeFull, &V::RefBaseType<'_>>(.., None, self.mem, None),
                 )
             };
+        self.transaction.record_key_change::<K>(&self.name, key);
 
         Ok(iter)
     }
@@ -713,12 +777,13 @@ impl<'db, 'txn, K: RedbKey + ?Sized, V: RedbKey + ?Sized> ReadableMultimapTable<
 {
     /// Returns an iterator over all values for the given key. Values are in ascending order.
     fn get<'a>(&'a self, key: impl Borrow<K::RefBaseType<'a>>) -> Result<MultimapValueIter<'a, V>> {
+        self.transaction.check_poisoned()?;
         let iter =
             if let Some(collection) = self.tree.get(key.borrow())? {
                 collection.iter(self.mem)
             } else {
                 MultimapValueIter::new_subtree(
-                    BtreeRangeIter::new::<RangeFull, &V::RefBaseType<'_>>(.., None, self.mem),
+                    BtreeRangeIter::new::<RangeFull, &V::RefBaseType<'_>>(.., None, self.mem, None),
                 )
             };
 
@@ -730,6 +795,7 @@ impl<'db, 'txn, K: RedbKey + ?Sized, V: RedbKey + ?Sized> ReadableMultimapTable<
         &'a self,
         range: T,
     ) -> Result<MultimapRangeIter<'a, K, V>> {
+        self.transaction.check_poisoned()?;
         let inner = self.tree.range(range)?;
         Ok(MultimapRangeIter::new(inner, self.mem))
     }
@@ -753,8 +819,50 @@ impl<'db, 'txn, K: RedbKey + ?Sized, V: RedbKey + ?Sized> ReadableMultimapTable<
 
 impl<'db, 'txn, K: RedbKey + ?Sized, V: RedbKey + ?Sized> Drop for MultimapTable<'db, 'txn, K, V> {
     fn drop(&mut self) {
+        self.transaction.poison_if_panicking();
         self.transaction.close_table(&self.name, &mut self.tree);
+        #[cfg(feature = "integrity_checks")]
+        self.transaction
+            .stage_integrity_check(&self.name, verify_multimap_tree::<K, V>);
+    }
+}
+
+// Checks the tree with the given root, like verify_tree(), and also the values of each key
+#[cfg(feature = "integrity_checks")]
+fn verify_multimap_tree<K: RedbKey + ?Sized, V: RedbKey + ?Sized>(
+    root: Option<(PageNumber, Checksum)>,
+    mem: &TransactionalMemory,
+) -> Result {
+    verify_tree::<K, DynamicCollection>(root, mem)?;
+    let mut iter = BtreeRangeIter::<K, DynamicCollection>::new::<RangeFull, &K::RefBaseType<'_>>(
+        ..,
+        root,
+        mem,
+        None,
+    );
+    while let Some(entry) = iter.try_next()? {
+        let collection = DynamicCollection::new(entry.value());
+        match collection.collection_type() {
+            Inline => {
+                let accessor = LeafAccessor::new(
+                    collection.as_inline(),
+                    V::fixed_width(),
+                    <() as RedbValue>::fixed_width(),
+                );
+                for i in 1..accessor.num_pairs() {
+                    let previous = accessor.entry(i - 1).unwrap().key();
+                    if V::compare(previous, accessor.entry(i).unwrap().key()) != Ordering::Less {
+                        return Err(Error::corrupted(format!(
+                            "values of key {:?} are out of order",
+                            K::from_bytes(entry.key())
+                        )));
+                    }
+                }
+            }
+            Subtree => verify_tree::<V, ()>(Some(collection.as_subtree()), mem)?,
+        }
     }
+    Ok(())
 }
 
 pub trait ReadableMultimapTable<K: RedbKey + ?Sized, V: RedbKey + ?Sized> {
@@ -810,7 +918,7 @@ impl<'txn, K: RedbKey + ?Sized, V: RedbKey + ?Sized> ReadableMultimapTable<K, V>
                 collection.iter(self.mem)
             } else {
                 MultimapValueIter::new_subtree(
-                    BtreeRangeIter::new::<RangeFull, &V::RefBaseType<'_>>(.., None, self.mem),
+                    BtreeRangeIter::new::<RangeFull, &V::RefBaseType<'_>>(.., None, self.mem, None),
                 )
             };
 
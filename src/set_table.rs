@@ -0,0 +1,122 @@
+use crate::table::{KeyIter, ReadableTable, Table};
+use crate::types::RedbKey;
+use crate::Result;
+use std::borrow::Borrow;
+use std::ops::RangeBounds;
+
+/// A set of keys, stored in a table with value type `()`
+///
+/// `()` is zero-width, so entries take no space beyond their key
+///
+/// # Examples
+///
+/// ```rust
+/// use redb::*;
+/// # use tempfile::NamedTempFile;
+/// const TAGS: TableDefinition<&str, ()> = TableDefinition::new("tags");
+///
+/// # fn main() -> Result<(), Error> {
+/// # let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+/// # let filename = tmpfile.path();
+/// let db = Database::create(filename)?;
+/// let write_txn = db.begin_write()?;
+/// {
+///     let mut tags = SetTable::new(write_txn.open_table(TAGS)?);
+///     assert!(tags.insert("red")?);
+///     assert!(tags.insert("blue")?);
+///     assert!(!tags.insert("red")?);
+///     assert!(tags.contains("blue")?);
+///     assert_eq!(tags.iter()?.collect::<Vec<_>>(), vec!["blue", "red"]);
+/// }
+/// write_txn.commit()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct SetTable<'db, 'txn, K: RedbKey + ?Sized> {
+    table: Table<'db, 'txn, K, ()>,
+}
+
+impl<'db, 'txn, K: RedbKey + ?Sized + 'txn> SetTable<'db, 'txn, K> {
+    pub fn new(table: Table<'db, 'txn, K, ()>) -> Self {
+        Self { table }
+    }
+
+    /// Returns the underlying table
+    pub fn into_inner(self) -> Table<'db, 'txn, K, ()> {
+        self.table
+    }
+
+    /// Add the given key to the set
+    ///
+    /// Returns `true` if the key was not already present
+    pub fn insert<'a, 'b: 'a, AK>(&mut self, key: &'a AK) -> Result<bool>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        Ok(self.table.insert_if_absent(key, &())?.is_none())
+    }
+
+    /// Add each of the given keys to the set
+    pub fn extend<'a, 'b: 'a, AK>(&mut self, keys: impl IntoIterator<Item = &'a AK>) -> Result
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized + 'a,
+    {
+        self.table.extend(keys.into_iter().map(|key| (key, &())))
+    }
+
+    /// Remove the given key from the set
+    ///
+    /// Returns `true` if the key was present
+    pub fn remove<'a, 'b: 'a, AK>(&mut self, key: &'a AK) -> Result<bool>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        Ok(self.table.remove(key)?.is_some())
+    }
+
+    /// Returns `true` if the set contains the given key
+    pub fn contains<'a, 'b: 'a, AK>(&self, key: &'a AK) -> Result<bool>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        self.table.contains_key(key)
+    }
+
+    /// Returns a double-ended iterator over the keys in the set, in ascending order
+    pub fn iter(&self) -> Result<KeyIter<'_, K, ()>> {
+        self.table.keys::<K::RefBaseType<'_>>(..)
+    }
+
+    /// Returns a double-ended iterator over the keys in the set which are within `range`
+    pub fn range<'a, KR>(&'a self, range: impl RangeBounds<KR> + 'a) -> Result<KeyIter<'a, K, ()>>
+    where
+        K: 'a,
+        KR: Borrow<K::RefBaseType<'a>> + ?Sized + 'a,
+    {
+        self.table.keys(range)
+    }
+
+    /// Returns the smallest key in the set, or `None` if the set is empty
+    pub fn first(&self) -> Result<Option<K::SelfType<'_>>> {
+        Ok(self.table.first()?.map(|(key, _)| key))
+    }
+
+    /// Returns the largest key in the set, or `None` if the set is empty
+    pub fn last(&self) -> Result<Option<K::SelfType<'_>>> {
+        Ok(self.table.last()?.map(|(key, _)| key))
+    }
+
+    /// Returns the number of keys in the set
+    pub fn len(&self) -> Result<usize> {
+        self.table.len()
+    }
+
+    /// Returns `true` if the set is empty
+    pub fn is_empty(&self) -> Result<bool> {
+        self.table.is_empty()
+    }
+}
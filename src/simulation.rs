@@ -0,0 +1,69 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Simulated time advances by up to this much each time it's read
+const MAX_TICK_NANOS: u64 = 1_000_000;
+
+struct SimulatedTime {
+    start: Instant,
+    elapsed: Duration,
+    random_state: u64,
+}
+
+// The source of time for a database, which drives the compaction and retention policies, and the
+// timings that are reported in statistics.
+//
+// In simulation mode, time is virtual. It starts when the database is opened, and advances by a
+// pseudo-random amount derived from the seed each time that it's read, and when
+// Database::advance_simulated_time() is called, so that a workload which makes the same calls
+// observes the same times on every run
+pub(crate) struct Clock {
+    simulated: Option<Mutex<SimulatedTime>>,
+}
+
+impl Clock {
+    pub(crate) fn real() -> Self {
+        Self { simulated: None }
+    }
+
+    pub(crate) fn simulated(seed: u64) -> Self {
+        Self {
+            simulated: Some(Mutex::new(SimulatedTime {
+                start: Instant::now(),
+                elapsed: Duration::ZERO,
+                random_state: seed,
+            })),
+        }
+    }
+
+    pub(crate) fn is_simulated(&self) -> bool {
+        self.simulated.is_some()
+    }
+
+    pub(crate) fn now(&self) -> Instant {
+        if let Some(ref simulated) = self.simulated {
+            let mut simulated = simulated.lock().unwrap();
+            let tick = next_random(&mut simulated.random_state) % MAX_TICK_NANOS;
+            simulated.elapsed += Duration::from_nanos(tick);
+            simulated.start + simulated.elapsed
+        } else {
+            Instant::now()
+        }
+    }
+
+    // Has no effect unless time is simulated
+    pub(crate) fn advance(&self, duration: Duration) {
+        if let Some(ref simulated) = self.simulated {
+            simulated.lock().unwrap().elapsed += duration;
+        }
+    }
+}
+
+// splitmix64
+fn next_random(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
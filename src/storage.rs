@@ -0,0 +1,83 @@
+use std::io;
+use std::sync::RwLock;
+
+/// Storage for a database which is not a file, such as memory, or storage provided by the host
+/// of a `wasm32` module
+///
+/// A database is opened in a device with [`crate::Builder::create_with_storage`], and is read and
+/// written through [`crate::StorageBackend::Buffered`]. Offsets and lengths are in bytes
+#[allow(clippy::len_without_is_empty)]
+pub trait StorageDevice: Send + Sync {
+    /// Returns the length of the device
+    fn len(&self) -> io::Result<u64>;
+
+    /// Fills `buf` with the bytes starting at `offset`. Never reads beyond the length of the
+    /// device
+    fn read(&self, offset: u64, buf: &mut [u8]) -> io::Result<()>;
+
+    /// Writes `data` starting at `offset`. Never writes beyond the length of the device
+    fn write(&self, offset: u64, data: &[u8]) -> io::Result<()>;
+
+    /// Grows or truncates the device. Bytes which are added by growing it must read as zero
+    fn set_len(&self, len: u64) -> io::Result<()>;
+
+    /// Makes all the writes which have returned durable
+    fn sync_data(&self) -> io::Result<()>;
+}
+
+/// A [`StorageDevice`] which holds the database in memory. Its contents are lost when the
+/// [`crate::Database`] is dropped
+#[derive(Debug, Default)]
+pub struct InMemoryStorage {
+    data: RwLock<Vec<u8>>,
+}
+
+impl InMemoryStorage {
+    /// Creates an empty device, in which a new database is initialized when it's opened
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+fn out_of_bounds() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "Access beyond the end of the storage",
+    )
+}
+
+impl StorageDevice for InMemoryStorage {
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.data.read().unwrap().len() as u64)
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let data = self.data.read().unwrap();
+        let start = usize::try_from(offset).map_err(|_| out_of_bounds())?;
+        let source = data
+            .get(start..(start + buf.len()))
+            .ok_or_else(out_of_bounds)?;
+        buf.copy_from_slice(source);
+        Ok(())
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let mut contents = self.data.write().unwrap();
+        let start = usize::try_from(offset).map_err(|_| out_of_bounds())?;
+        contents
+            .get_mut(start..(start + data.len()))
+            .ok_or_else(out_of_bounds)?
+            .copy_from_slice(data);
+        Ok(())
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        let len = usize::try_from(len).map_err(|_| io::Error::from(io::ErrorKind::OutOfMemory))?;
+        self.data.write().unwrap().resize(len, 0);
+        Ok(())
+    }
+
+    fn sync_data(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,14 +1,24 @@
+use crate::import::read_records;
+use crate::transactions::ReadOnlyTableHandle;
 use crate::tree_store::{
-    AccessGuardMut, Btree, BtreeMut, BtreeRangeIter, Checksum, PageNumber, TransactionalMemory,
+    AccessGuardMut, Btree, BtreeCursor, BtreeMut, BtreeRangeIter, Checksum, EntryAccessor,
+    PageNumber, TransactionalMemory,
 };
-use crate::types::{RedbKey, RedbValue};
+use crate::types::{from_stored_bytes, IntegerValue, PrefixKey, RedbKey, RedbValue};
 use crate::Result;
-use crate::{AccessGuard, WriteTransaction};
+use crate::{AccessGuard, DataFormat, Error, TreeDumpFormat, TreePageIter, WriteTransaction};
 use std::borrow::Borrow;
 use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::mem::size_of;
 use std::ops::RangeBounds;
 use std::rc::Rc;
 
+// Number of entries which extend() sorts and inserts together
+const EXTEND_BATCH_SIZE: usize = 64 * 1024;
+
 /// A table containing key-value mappings
 pub struct Table<'db, 'txn, K: RedbKey + ?Sized + 'txn, V: RedbValue + ?Sized + 'txn> {
     name: String,
@@ -20,20 +30,45 @@ impl<'db, 'txn, K: RedbKey + ?Sized + 'txn, V: RedbValue + ?Sized + 'txn> Table<
     pub(crate) fn new(
         name: &str,
         table_root: Option<(PageNumber, Checksum)>,
+        counted: bool,
         freed_pages: Rc<RefCell<Vec<PageNumber>>>,
         mem: &'db TransactionalMemory,
         transaction: &'txn WriteTransaction<'db>,
     ) -> Table<'db, 'txn, K, V> {
+        let mut tree = BtreeMut::new(table_root, mem, freed_pages);
+        tree.set_counted(counted);
+        tree.enable_append_path();
         Table {
             name: name.to_string(),
             transaction,
-            tree: BtreeMut::new(table_root, mem, freed_pages),
+            tree,
         }
     }
 
     #[allow(dead_code)]
-    pub(crate) fn print_debug(&self, include_values: bool) {
-        self.tree.print_debug(include_values);
+    pub(crate) fn print_debug(&self, include_values: bool) -> Result {
+        self.tree.print_debug(include_values)
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Returns a copy of the serialized value of the serialized key
+    pub(crate) fn get_serialized(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.tree.get_serialized(key)
+    }
+
+    // Removes the serialized key, and returns true if it was present
+    pub(crate) fn remove_serialized(&mut self, key: &[u8]) -> Result<bool> {
+        let _operation = self.transaction.begin_operation(&self.name)?;
+        // Safety: No other references to this table can exist.
+        // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
+        // and we borrow &mut self.
+        let removed = unsafe { self.tree.remove_serialized(key)? };
+        // &[u8] is serialized as itself, so this records the same hash as the deserialized key
+        self.transaction.record_key_change::<&[u8]>(&self.name, key);
+        Ok(removed)
     }
 
     /// Insert mapping of the given key to the given value
@@ -50,10 +85,332 @@ impl<'db, 'txn, K: RedbKey + ?Sized + 'txn, V: RedbValue + ?Sized + 'txn> Table<
         AK: Borrow<K::RefBaseType<'b>> + ?Sized,
         AV: Borrow<V::RefBaseType<'b>> + ?Sized,
     {
+        self.transaction.check_memory_limit()?;
+        let _operation = self.transaction.begin_operation(&self.name)?;
+        // Safety: No other references to this table can exist.
+        // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
+        // and we borrow &mut self.
+        let old = unsafe { self.tree.insert(key.borrow(), value.borrow())? };
+        self.transaction
+            .record_key_change::<K>(&self.name, key.borrow());
+        Ok(old)
+    }
+
+    /// Insert mapping of the given key to the given value, where the key is greater than every key
+    /// in the table
+    ///
+    /// Each appended key is written directly into the last page of the table, without a search
+    /// from the root, and pages are left full when the next one is started. [`Table::insert`]
+    /// does the same once it sees that keys are being appended, so this mostly serves to check
+    /// that they are.
+    ///
+    /// Returns [`Error::KeysNotSorted`], and leaves the table unchanged, if the key is not greater
+    /// than every key already in the table
+    pub fn append<'a, 'b: 'a, AK, AV>(&mut self, key: &'a AK, value: &'a AV) -> Result
+    where
+        K: 'b,
+        V: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+        AV: Borrow<V::RefBaseType<'b>> + ?Sized,
+    {
+        self.transaction.check_memory_limit()?;
+        let _operation = self.transaction.begin_operation(&self.name)?;
+        // Safety: No other references to this table can exist.
+        // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
+        // and we borrow &mut self.
+        if !unsafe { self.tree.append(key.borrow(), value.borrow())? } {
+            return Err(Error::KeysNotSorted(self.name.clone()));
+        }
+        self.transaction
+            .record_key_change::<K>(&self.name, key.borrow());
+        Ok(())
+    }
+
+    /// Insert all the key-value pairs produced by `entries`
+    ///
+    /// Entries are sorted and inserted in large batches, so that each page is rewritten at most
+    /// once per batch, which is much faster than calling [`Table::insert`] for each entry.
+    /// If the same key is given more than once, the last value is kept
+    pub fn extend<'a, 'b: 'a, AK, AV>(
+        &mut self,
+        entries: impl IntoIterator<Item = (&'a AK, &'a AV)>,
+    ) -> Result
+    where
+        K: 'b,
+        V: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized + 'a,
+        AV: Borrow<V::RefBaseType<'b>> + ?Sized + 'a,
+    {
+        self.transaction.check_memory_limit()?;
+        let _operation = self.transaction.begin_operation(&self.name)?;
+        let mut batch = vec![];
+        for (key, value) in entries {
+            batch.push((
+                K::as_bytes(key.borrow()).as_ref().to_vec(),
+                V::as_bytes(value.borrow()).as_ref().to_vec(),
+            ));
+            if batch.len() == EXTEND_BATCH_SIZE {
+                self.transaction.check_memory_limit()?;
+                self.insert_batch(&mut batch)?;
+            }
+        }
+        self.insert_batch(&mut batch)
+    }
+
+    // Inserts the serialized pairs in batch, and then empties it
+    fn insert_batch(&mut self, batch: &mut Vec<(Vec<u8>, Vec<u8>)>) -> Result {
+        // Safety: No other references to this table can exist.
+        // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
+        // and we borrow &mut self.
+        unsafe { self.tree.insert_batch(batch)? };
+        for (key, _) in batch.drain(..) {
+            self.transaction
+                .record_key_change::<&[u8]>(&self.name, &key);
+        }
+        Ok(())
+    }
+
+    /// Replace the value of the given key with `new`, if its current value is equal to `expected`
+    ///
+    /// An `expected` value of `None` requires the key to be absent, and a `new` value of `None`
+    /// removes the key. Values are compared in their serialized form.
+    ///
+    /// Returns `true` if the value was replaced
+    pub fn compare_and_swap<'a, 'b: 'a, AK, AV>(
+        &mut self,
+        key: &'a AK,
+        expected: Option<&'a AV>,
+        new: Option<&'a AV>,
+    ) -> Result<bool>
+    where
+        K: 'b,
+        V: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+        AV: Borrow<V::RefBaseType<'b>> + ?Sized,
+    {
+        self.transaction.check_memory_limit()?;
+        let _operation = self.transaction.begin_operation(&self.name)?;
+        // Safety: No other references to this table can exist.
+        // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
+        // and we borrow &mut self.
+        let swapped = unsafe {
+            self.tree.compare_and_swap(
+                key.borrow(),
+                expected.map(|value| value.borrow()),
+                new.map(|value| value.borrow()),
+            )?
+        };
+        if swapped {
+            self.transaction
+                .record_key_change::<K>(&self.name, key.borrow());
+        }
+        Ok(swapped)
+    }
+
+    /// Combine `operand` with the value of the given key, using the merge operator registered for
+    /// this table with [`crate::Builder::set_merge_operator`]
+    ///
+    /// The merge is applied immediately, as part of a single descent of the table, and the result
+    /// is stored in place of the existing value. Operands are not stored for later merging.
+    ///
+    /// Returns [`Error::MergeOperatorNotRegistered`] if no merge operator is registered for this
+    /// table
+    pub fn merge_in_place<'a, 'b: 'a, AK, AV>(&mut self, key: &'a AK, operand: &'a AV) -> Result
+    where
+        K: 'b,
+        V: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+        AV: Borrow<V::RefBaseType<'b>> + ?Sized,
+    {
+        self.transaction.check_memory_limit()?;
+        let _operation = self.transaction.begin_operation(&self.name)?;
+        let operator = self
+            .transaction
+            .merge_operator(&self.name)
+            .ok_or_else(|| Error::MergeOperatorNotRegistered(self.name.clone()))?;
+        let key_bytes = K::as_bytes(key.borrow());
+        let operand_bytes = V::as_bytes(operand.borrow());
+        // Safety: No other references to this table can exist.
+        // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
+        // and we borrow &mut self.
+        unsafe {
+            self.tree.merge(key.borrow(), |existing| {
+                Ok(operator(
+                    key_bytes.as_ref(),
+                    existing,
+                    operand_bytes.as_ref(),
+                ))
+            })?;
+        }
+        self.transaction
+            .record_key_change::<K>(&self.name, key.borrow());
+        Ok(())
+    }
+
+    /// Insert the entries read from `reader`, in the format written by
+    /// [`crate::ReadTransaction::export_table`]
+    ///
+    /// Every entry is parsed, and checked against the key and value types of this table, before
+    /// any are inserted, so the table is unchanged if an error is returned. Types which are
+    /// exported as hex are only checked to have the right length. Entries are inserted as by
+    /// [`Table::extend`]
+    ///
+    /// Returns the number of entries read
+    pub fn import(&mut self, format: DataFormat, reader: impl Read) -> Result<u64> {
+        self.transaction.check_memory_limit()?;
+        let _operation = self.transaction.begin_operation(&self.name)?;
+        let mut pairs = read_records(
+            format,
+            reader,
+            (&K::redb_type_name(), K::fixed_width()),
+            (&V::redb_type_name(), V::fixed_width()),
+        )?;
+        let entries = pairs.len().try_into().unwrap();
+        // Safety: No other references to this table can exist.
+        // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
+        // and we borrow &mut self.
+        unsafe { self.tree.insert_batch(&mut pairs)? };
+        self.transaction.record_table_change(&self.name);
+
+        Ok(entries)
+    }
+
+    /// Append the key-value pairs produced by `entries`, which must be sorted by key
+    ///
+    /// Pages are filled completely and built bottom-up, without the cost of splitting them as
+    /// entries are added, which makes this the fastest way to load a large number of entries.
+    /// All the entries are held in memory until they have been checked.
+    ///
+    /// Returns [`Error::KeysNotSorted`], and leaves the table unchanged, if the keys are not in
+    /// strictly increasing order, or are not greater than all keys already in the table
+    pub fn load_sorted<'a, 'b: 'a, AK, AV>(
+        &mut self,
+        entries: impl IntoIterator<Item = (&'a AK, &'a AV)>,
+    ) -> Result
+    where
+        K: 'b,
+        V: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized + 'a,
+        AV: Borrow<V::RefBaseType<'b>> + ?Sized + 'a,
+    {
+        self.transaction.check_memory_limit()?;
+        let _operation = self.transaction.begin_operation(&self.name)?;
+        let mut previous_key = self.tree.last_key_bytes()?;
+        let mut pairs = vec![];
+        for (key, value) in entries {
+            let key = K::as_bytes(key.borrow()).as_ref().to_vec();
+            if let Some(previous) = previous_key {
+                if K::compare(&previous, &key) != Ordering::Less {
+                    return Err(Error::KeysNotSorted(self.name.clone()));
+                }
+            }
+            previous_key = Some(key.clone());
+            pairs.push((key, V::as_bytes(value.borrow()).as_ref().to_vec()));
+        }
+        // Safety: No other references to this table can exist.
+        // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
+        // and we borrow &mut self.
+        unsafe {
+            self.tree.append_sorted(
+                pairs
+                    .iter()
+                    .map(|(key, value)| (key.as_slice(), value.as_slice())),
+            )?;
+        }
+        for (key, _) in pairs.iter() {
+            self.transaction.record_key_change::<&[u8]>(&self.name, key);
+        }
+        Ok(())
+    }
+
+    /// Replace the contents of this table with a copy of `source`, which may be a table in another
+    /// database
+    ///
+    /// Entries are copied without being deserialized, and the pages of this table are built
+    /// bottom-up, as by [`Table::load_sorted`]
+    pub fn copy_from(&mut self, source: &impl ReadableTable<K, V>) -> Result {
+        self.transaction.check_memory_limit()?;
+        let _operation = self.transaction.begin_operation(&self.name)?;
+        self.clear()?;
+        let mut iter = source.iter()?;
+        let mut error = None;
+        let pairs = std::iter::from_fn(|| match iter.inner.try_next() {
+            Ok(entry) => entry.map(|entry| (entry.key().to_vec(), entry.value().to_vec())),
+            Err(err) => {
+                error = Some(err);
+                None
+            }
+        });
+        // Safety: No other references to this table can exist.
+        // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
+        // and we borrow &mut self.
+        unsafe { self.tree.append_sorted(pairs)? };
+        if let Some(err) = error {
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Insert mapping of the given key to the given value, if the key is not present in the table
+    ///
+    /// Returns the existing value, and leaves it unchanged, if the key was present
+    pub fn insert_if_absent<'a, 'b: 'a, AK, AV>(
+        &mut self,
+        key: &'a AK,
+        value: &'a AV,
+    ) -> Result<Option<AccessGuard<V>>>
+    where
+        K: 'b,
+        V: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+        AV: Borrow<V::RefBaseType<'b>> + ?Sized,
+    {
+        self.transaction.check_memory_limit()?;
+        let _operation = self.transaction.begin_operation(&self.name)?;
+        // Safety: No other references to this table can exist.
+        // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
+        // and we borrow &mut self.
+        let existing = unsafe { self.tree.insert_if_absent(key.borrow(), value.borrow())? };
+        if existing.is_none() {
+            self.transaction
+                .record_key_change::<K>(&self.name, key.borrow());
+        }
+        Ok(existing)
+    }
+
+    /// Returns the value of the given key, first inserting the value returned by `default` if the
+    /// key is not present
+    ///
+    /// `default` is only called if the key is absent, and the table is only searched once
+    pub fn get_or_insert_with<'a, 'b: 'a, AK, AV, F>(
+        &mut self,
+        key: &'a AK,
+        default: F,
+    ) -> Result<AccessGuard<V>>
+    where
+        K: 'b,
+        V: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+        AV: Borrow<V::RefBaseType<'b>>,
+        F: FnOnce() -> AV,
+    {
+        self.transaction.check_memory_limit()?;
+        let _operation = self.transaction.begin_operation(&self.name)?;
         // Safety: No other references to this table can exist.
         // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
         // and we borrow &mut self.
-        unsafe { self.tree.insert(key.borrow(), value.borrow()) }
+        let (value, inserted) = unsafe {
+            self.tree.get_or_insert_with(key.borrow(), || {
+                let value = default();
+                let bytes = V::as_bytes(value.borrow()).as_ref().to_vec();
+                bytes
+            })?
+        };
+        if inserted {
+            self.transaction
+                .record_key_change::<K>(&self.name, key.borrow());
+        }
+        Ok(value)
     }
 
     /// Reserve space to insert a key-value pair
@@ -68,10 +425,15 @@ impl<'db, 'txn, K: RedbKey + ?Sized + 'txn, V: RedbValue + ?Sized + 'txn> Table<
         K: 'b,
         AK: Borrow<K::RefBaseType<'b>> + ?Sized,
     {
+        self.transaction.check_memory_limit()?;
+        let _operation = self.transaction.begin_operation(&self.name)?;
         // Safety: No other references to this table can exist.
         // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
         // and we borrow &mut self.
-        unsafe { self.tree.insert_reserve(key.borrow(), value_length) }
+        let guard = unsafe { self.tree.insert_reserve(key.borrow(), value_length)? };
+        self.transaction
+            .record_key_change::<K>(&self.name, key.borrow());
+        Ok(guard)
     }
 
     /// Removes the given key
@@ -82,10 +444,275 @@ impl<'db, 'txn, K: RedbKey + ?Sized + 'txn, V: RedbValue + ?Sized + 'txn> Table<
         K: 'b,
         AK: Borrow<K::RefBaseType<'b>> + ?Sized,
     {
+        let _operation = self.transaction.begin_operation(&self.name)?;
+        // Safety: No other references to this table can exist.
+        // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
+        // and we borrow &mut self.
+        let removed = unsafe { self.tree.remove(key.borrow())? };
+        self.transaction
+            .record_key_change::<K>(&self.name, key.borrow());
+        Ok(removed)
+    }
+
+    /// Removes all entries
+    ///
+    /// The pages of the table are freed without visiting its entries individually, which makes
+    /// this much faster than removing the keys one at a time.
+    pub fn clear(&mut self) -> Result {
+        let _operation = self.transaction.begin_operation(&self.name)?;
+        // Safety: No other references to this table can exist.
+        // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
+        // and we borrow &mut self.
+        unsafe { self.tree.clear()? };
+        self.transaction.record_table_change(&self.name);
+        Ok(())
+    }
+
+    /// Removes all entries with keys less than the given key
+    ///
+    /// Subtrees which lie entirely before `key` are freed without visiting their entries
+    /// individually, which makes this much faster than removing the keys one at a time.
+    ///
+    /// Returns the number of entries removed
+    pub fn remove_before<'a, 'b: 'a, AK>(&mut self, key: &'a AK) -> Result<u64>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        let _operation = self.transaction.begin_operation(&self.name)?;
+        // Safety: No other references to this table can exist.
+        // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
+        // and we borrow &mut self.
+        let removed = unsafe { self.tree.remove_before(key.borrow())? };
+        self.transaction.record_table_change(&self.name);
+        Ok(removed)
+    }
+
+    /// Removes all entries in `range`
+    ///
+    /// Subtrees which lie entirely within `range` are freed without visiting their entries
+    /// individually, which makes this much faster than removing the keys one at a time.
+    ///
+    /// Returns the number of entries removed
+    pub fn remove_range<'a, KR>(&mut self, range: impl RangeBounds<KR> + 'a) -> Result<u64>
+    where
+        'txn: 'a,
+        K: 'a,
+        KR: Borrow<K::RefBaseType<'a>> + ?Sized + 'a,
+    {
+        let _operation = self.transaction.begin_operation(&self.name)?;
+        // Safety: No other references to this table can exist.
+        // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
+        // and we borrow &mut self.
+        let removed = unsafe { self.tree.remove_range(range)? };
+        self.transaction.record_table_change(&self.name);
+        Ok(removed)
+    }
+
+    /// Removes all entries in `range`, and returns an iterator over the removed entries
+    ///
+    /// The entries are removed even if the iterator is not consumed
+    pub fn drain<'a, KR>(&mut self, range: impl RangeBounds<KR> + 'a) -> Result<Drain<K, V>>
+    where
+        'txn: 'a,
+        K: 'a,
+        KR: Borrow<K::RefBaseType<'a>> + ?Sized + 'a,
+    {
+        let _operation = self.transaction.begin_operation(&self.name)?;
+        // Safety: No other references to this table can exist.
+        // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
+        // and we borrow &mut self.
+        let drained = unsafe { self.tree.drain(range)? };
+        self.transaction.record_table_change(&self.name);
+        Ok(Drain {
+            inner: drained.into_iter(),
+        })
+    }
+
+    /// Removes all entries for which `predicate` returns `false`
+    ///
+    /// Entries are visited in key order, in a single traversal of the table, and only the pages
+    /// from which entries are removed are rewritten.
+    ///
+    /// Returns the number of entries removed
+    pub fn retain<F>(&mut self, predicate: F) -> Result<u64>
+    where
+        F: for<'f> FnMut(K::SelfType<'f>, V::SelfType<'f>) -> bool,
+    {
+        let _operation = self.transaction.begin_operation(&self.name)?;
+        // Safety: No other references to this table can exist.
+        // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
+        // and we borrow &mut self.
+        let removed = unsafe { self.tree.retain(predicate)? };
+        self.transaction.record_table_change(&self.name);
+        Ok(removed)
+    }
+
+    /// Returns a double-ended iterator over a range of elements, as they are when this is called
+    ///
+    /// Unlike [`ReadableTable::range`], the iterator does not borrow the table, so the table can
+    /// be modified while iterating, or closed and reopened, for example to update each entry in a
+    /// sweep. These modifications are not visible to the iterator
+    pub fn snapshot_range<'a, KR>(
+        &self,
+        range: impl RangeBounds<KR> + 'a,
+    ) -> Result<RangeIter<'txn, K, V>>
+    where
+        'txn: 'a,
+        K: 'a,
+        KR: Borrow<K::RefBaseType<'a>> + ?Sized + 'a,
+    {
+        self.transaction.check_poisoned()?;
+        self.tree.snapshot_range(range).map(RangeIter::new)
+    }
+
+    /// Inserts all the records from `reader`, in the format written by
+    /// [`ReadableTable::export_range`] or [`ReadOnlyTable::export_changes`]
+    ///
+    /// Returns the number of records inserted or removed
+    pub fn import_records(&mut self, mut reader: impl Read) -> Result<u64> {
+        let _operation = self.transaction.begin_operation(&self.name)?;
+        let mut count = 0;
+        let mut key = vec![];
+        let mut value = vec![];
+        while let Some(len) = read_record_len(&mut reader, true)? {
+            self.transaction.check_memory_limit()?;
+            read_record_data(&mut reader, &mut key, len, K::fixed_width())?;
+            let len = read_record_len(&mut reader, false)?.unwrap();
+            // Safety: No other references to this table can exist.
+            // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
+            // and we borrow &mut self.
+            if len == REMOVED_RECORD_LEN {
+                let key = decode_record::<K>(&key)?;
+                unsafe {
+                    self.tree.remove(key.borrow())?;
+                }
+            } else {
+                read_record_data(&mut reader, &mut value, len, V::fixed_width())?;
+                let key = decode_record::<K>(&key)?;
+                let value = decode_record::<V>(&value)?;
+                unsafe {
+                    self.tree.insert(key.borrow(), value.borrow())?;
+                }
+            }
+            self.transaction
+                .record_key_change::<&[u8]>(&self.name, &key);
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Writes the structure of the table's b-tree to `writer`, for debugging. See
+    /// [`TreeDumpFormat`] for a description of the output
+    pub fn dump_tree(&self, format: TreeDumpFormat, writer: impl Write) -> Result {
+        self.transaction.check_poisoned()?;
+        self.tree.dump(format, writer)
+    }
+
+    /// Returns an iterator over the pages of the table's b-tree, which describes the size and
+    /// position in the tree of each page. If a page can't be read, the error is returned and the
+    /// iteration ends
+    pub fn pages(&self) -> Result<TreePageIter> {
+        self.transaction.check_poisoned()?;
+        self.tree.pages()
+    }
+}
+
+// Written in place of the value length, for records which were removed
+const REMOVED_RECORD_LEN: u32 = u32::MAX;
+
+fn write_record_field(writer: &mut impl Write, data: &[u8]) -> Result {
+    let len = u32::try_from(data.len()).unwrap();
+    assert_ne!(len, REMOVED_RECORD_LEN);
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(data)?;
+    Ok(())
+}
+
+// Reads the length prefix of a field. Returns None, if the reader was already at EOF and
+// eof_allowed is true
+fn read_record_len(reader: &mut impl Read, eof_allowed: bool) -> Result<Option<u32>> {
+    let mut len = [0u8; size_of::<u32>()];
+    let mut read = 0;
+    while read < len.len() {
+        match reader.read(&mut len[read..]) {
+            Ok(0) if read == 0 && eof_allowed => return Ok(None),
+            Ok(0) => return Err(io::Error::from(ErrorKind::UnexpectedEof).into()),
+            Ok(n) => read += n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(Some(u32::from_le_bytes(len)))
+}
+
+// Reads the data of a field, whose length prefix has already been read, into buffer
+fn read_record_data(
+    reader: &mut impl Read,
+    buffer: &mut Vec<u8>,
+    len: u32,
+    fixed_width: Option<usize>,
+) -> Result {
+    let len = len as usize;
+    if fixed_width.map(|x| x != len).unwrap_or(false) {
+        return Err(
+            io::Error::new(ErrorKind::InvalidData, "record does not match table type").into(),
+        );
+    }
+    // The length comes from untrusted input, so the buffer only grows as data is actually read
+    buffer.clear();
+    let read = reader.take(len as u64).read_to_end(buffer)?;
+    if read != len {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "record is longer than the remaining input",
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+// Deserializes a field, which comes from untrusted input, so it may not be valid for the type
+fn decode_record<T: RedbValue + ?Sized>(data: &[u8]) -> Result<T::SelfType<'_>> {
+    T::try_from_bytes(data).ok_or_else(|| {
+        io::Error::new(ErrorKind::InvalidData, "record does not match table type").into()
+    })
+}
+
+impl<'db, 'txn, K: RedbKey + ?Sized + 'txn, V: IntegerValue + 'txn> Table<'db, 'txn, K, V> {
+    /// Add `delta` to the value of the given key, which is treated as zero if the key is not
+    /// present, and return the new value
+    ///
+    /// The value is read and written in a single descent of the table.
+    ///
+    /// Returns [`Error::IntegerOverflow`], and leaves the value unchanged, if the new value
+    /// overflows
+    pub fn increment<'a, 'b: 'a, AK>(&mut self, key: &'a AK, delta: V) -> Result<V>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        self.transaction.check_memory_limit()?;
+        let _operation = self.transaction.begin_operation(&self.name)?;
+        let mut result = V::default();
         // Safety: No other references to this table can exist.
         // Tables can only be opened mutably in one location (see Error::TableAlreadyOpen),
         // and we borrow &mut self.
-        unsafe { self.tree.remove(key.borrow()) }
+        unsafe {
+            self.tree.merge(key.borrow(), |existing| {
+                let existing = existing.map(V::from_stored_bytes).unwrap_or_default();
+                result = existing
+                    .checked_add(delta)
+                    .ok_or_else(|| Error::IntegerOverflow(self.name.clone()))?;
+                Ok(result.to_stored_bytes())
+            })?;
+        }
+        self.transaction
+            .record_key_change::<K>(&self.name, key.borrow());
+        Ok(result)
     }
 }
 
@@ -97,18 +724,93 @@ impl<'db, 'txn, K: RedbKey + ?Sized, V: RedbValue + ?Sized> ReadableTable<K, V>
         K: 'b,
         AK: Borrow<K::RefBaseType<'b>> + ?Sized,
     {
+        self.transaction.check_poisoned()?;
         self.tree.get(key.borrow())
     }
 
+    fn get_multi<'a, 'b: 'a, AK>(
+        &self,
+        keys: impl IntoIterator<Item = &'a AK>,
+    ) -> Result<Vec<Option<V::SelfType<'_>>>>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized + 'a,
+    {
+        self.transaction.check_poisoned()?;
+        let serialized: Vec<K::AsBytes<'_>> = keys
+            .into_iter()
+            .map(|key| K::as_bytes(key.borrow()))
+            .collect();
+        let queries: Vec<&[u8]> = serialized.iter().map(|key| key.as_ref()).collect();
+        self.tree.get_multi(&queries)
+    }
+
+    fn contains_key<'a, 'b: 'a, AK>(&self, key: &'a AK) -> Result<bool>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        self.transaction.check_poisoned()?;
+        self.tree.contains_key(key.borrow())
+    }
+
     fn range<'a, KR>(&'a self, range: impl RangeBounds<KR> + 'a) -> Result<RangeIter<'a, K, V>>
     where
         K: 'a,
         KR: Borrow<K::RefBaseType<'a>> + ?Sized + 'a,
     {
+        self.transaction.check_poisoned()?;
         self.tree.range(range).map(RangeIter::new)
     }
 
+    fn range_prefix<'a>(&'a self, prefix: &K::RefBaseType<'_>) -> Result<RangeIter<'a, K, V>>
+    where
+        K: PrefixKey + 'a,
+    {
+        self.transaction.check_poisoned()?;
+        self.tree.range_prefix(prefix).map(RangeIter::new)
+    }
+
+    fn cursor(&self) -> Result<Cursor<K, V>> {
+        self.transaction.check_poisoned()?;
+        Ok(Cursor::new(self.tree.cursor()?))
+    }
+
+    fn first(&self) -> Result<Option<(K::SelfType<'_>, V::SelfType<'_>)>> {
+        self.transaction.check_poisoned()?;
+        self.tree.first()
+    }
+
+    fn last(&self) -> Result<Option<(K::SelfType<'_>, V::SelfType<'_>)>> {
+        self.transaction.check_poisoned()?;
+        self.tree.last()
+    }
+
+    fn nth(&self, index: usize) -> Result<Option<(K::SelfType<'_>, V::SelfType<'_>)>> {
+        self.transaction.check_poisoned()?;
+        self.tree.nth(index)
+    }
+
+    fn rank<'a, 'b: 'a, AK>(&self, key: &'a AK) -> Result<usize>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        self.transaction.check_poisoned()?;
+        self.tree.rank(key.borrow())
+    }
+
+    fn count_range<'a, KR>(&'a self, range: impl RangeBounds<KR> + 'a) -> Result<usize>
+    where
+        K: 'a,
+        KR: Borrow<K::RefBaseType<'a>> + ?Sized + 'a,
+    {
+        self.transaction.check_poisoned()?;
+        self.tree.count_range(range)
+    }
+
     fn len(&self) -> Result<usize> {
+        self.transaction.check_poisoned()?;
         self.tree.len()
     }
 
@@ -119,6 +821,7 @@ impl<'db, 'txn, K: RedbKey + ?Sized, V: RedbValue + ?Sized> ReadableTable<K, V>
 
 impl<'db, 'txn, K: RedbKey + ?Sized, V: RedbValue + ?Sized> Drop for Table<'db, 'txn, K, V> {
     fn drop(&mut self) {
+        self.transaction.poison_if_panicking();
         self.transaction.close_table(&self.name, &mut self.tree);
     }
 }
@@ -130,6 +833,24 @@ pub trait ReadableTable<K: RedbKey + ?Sized, V: RedbValue + ?Sized> {
         K: 'b,
         AK: Borrow<K::RefBaseType<'b>> + ?Sized;
 
+    /// Returns the values corresponding to each of the given keys, in the same order
+    ///
+    /// This is faster than calling [`Self::get`] for each key, when many of the keys are stored
+    /// near each other
+    fn get_multi<'a, 'b: 'a, AK>(
+        &self,
+        keys: impl IntoIterator<Item = &'a AK>,
+    ) -> Result<Vec<Option<V::SelfType<'_>>>>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized + 'a;
+
+    /// Returns `true` if the table contains the given key, without reading its value
+    fn contains_key<'a, 'b: 'a, AK>(&self, key: &'a AK) -> Result<bool>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized;
+
     /// Returns a double-ended iterator over a range of elements in the table
     ///
     /// # Examples
@@ -143,7 +864,7 @@ pub trait ReadableTable<K: RedbKey + ?Sized, V: RedbValue + ?Sized> {
     /// # fn main() -> Result<(), Error> {
     /// # let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
     /// # let filename = tmpfile.path();
-    /// let db = unsafe { Database::create(filename)? };
+    /// let db = Database::create(filename)?;
     /// let write_txn = db.begin_write()?;
     /// {
     ///     let mut table = write_txn.open_table(TABLE)?;
@@ -165,6 +886,42 @@ pub trait ReadableTable<K: RedbKey + ?Sized, V: RedbValue + ?Sized> {
         K: 'a,
         KR: Borrow<K::RefBaseType<'a>> + ?Sized + 'a;
 
+    /// Returns a double-ended iterator over all elements whose key starts with `prefix`
+    fn range_prefix<'a>(&'a self, prefix: &K::RefBaseType<'_>) -> Result<RangeIter<'a, K, V>>
+    where
+        K: PrefixKey + 'a;
+
+    /// Returns a [`Cursor`] over the table, which is initially unpositioned
+    fn cursor(&self) -> Result<Cursor<K, V>>;
+
+    /// Returns the entry with the smallest key, or `None` if the table is empty
+    fn first(&self) -> Result<Option<(K::SelfType<'_>, V::SelfType<'_>)>>;
+
+    /// Returns the entry with the largest key, or `None` if the table is empty
+    fn last(&self) -> Result<Option<(K::SelfType<'_>, V::SelfType<'_>)>>;
+
+    /// Returns the entry at position `index` in key order, or `None` if `index` is out of bounds
+    ///
+    /// This skips over whole leaf pages, rather than reading every entry before `index`, but
+    /// still takes time proportional to `index`
+    fn nth(&self, index: usize) -> Result<Option<(K::SelfType<'_>, V::SelfType<'_>)>>;
+
+    /// Returns the number of keys in the table which are less than `key`. This is the position of
+    /// `key` in key order, if it is present
+    fn rank<'a, 'b: 'a, AK>(&self, key: &'a AK) -> Result<usize>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized;
+
+    /// Returns the number of entries whose keys are within `range`
+    ///
+    /// This takes O(log n) time if the table was created with
+    /// [`TableDefinition::with_entry_counts`], and otherwise visits every leaf in the range
+    fn count_range<'a, KR>(&'a self, range: impl RangeBounds<KR> + 'a) -> Result<usize>
+    where
+        K: 'a,
+        KR: Borrow<K::RefBaseType<'a>> + ?Sized + 'a;
+
     /// Returns the number of entries in the table
     fn len(&self) -> Result<usize>;
 
@@ -175,22 +932,125 @@ pub trait ReadableTable<K: RedbKey + ?Sized, V: RedbValue + ?Sized> {
     fn iter(&self) -> Result<RangeIter<K, V>> {
         self.range::<K::RefBaseType<'_>>(..)
     }
+
+    /// Returns a double-ended iterator over the keys in a range of the table, without
+    /// deserializing the values
+    fn keys<'a, KR>(&'a self, range: impl RangeBounds<KR> + 'a) -> Result<KeyIter<'a, K, V>>
+    where
+        K: 'a,
+        V: 'a,
+        KR: Borrow<K::RefBaseType<'a>> + ?Sized + 'a,
+    {
+        self.range(range).map(|iter| KeyIter { inner: iter.inner })
+    }
+
+    /// Returns a double-ended iterator over the values in a range of the table, without
+    /// deserializing the keys
+    fn values<'a, KR>(&'a self, range: impl RangeBounds<KR> + 'a) -> Result<ValueIter<'a, K, V>>
+    where
+        K: 'a,
+        V: 'a,
+        KR: Borrow<K::RefBaseType<'a>> + ?Sized + 'a,
+    {
+        self.range(range)
+            .map(|iter| ValueIter { inner: iter.inner })
+    }
+
+    /// Writes the elements in the given range to `writer`, which can be loaded into another table
+    /// with [`Table::import_records`]
+    ///
+    /// Each record is written as the length of the key as a little-endian u32, the serialized key,
+    /// the length of the value as a little-endian u32, and then the serialized value.
+    ///
+    /// Returns the number of records written
+    fn export_range<'a, KR>(
+        &'a self,
+        range: impl RangeBounds<KR> + 'a,
+        mut writer: impl Write,
+    ) -> Result<u64>
+    where
+        K: 'a,
+        V: 'a,
+        KR: Borrow<K::RefBaseType<'a>> + ?Sized + 'a,
+    {
+        let mut count = 0;
+        let mut iter = self.range(range)?.inner;
+        while let Some(entry) = iter.try_next()? {
+            write_record_field(&mut writer, entry.key())?;
+            write_record_field(&mut writer, entry.value())?;
+            count += 1;
+        }
+        writer.flush()?;
+
+        Ok(count)
+    }
 }
 
 /// A read-only table
 pub struct ReadOnlyTable<'txn, K: RedbKey + ?Sized, V: RedbValue + ?Sized> {
     tree: Btree<'txn, K, V>,
+    _handle: Option<ReadOnlyTableHandle<'txn>>,
 }
 
 impl<'txn, K: RedbKey + ?Sized, V: RedbValue + ?Sized> ReadOnlyTable<'txn, K, V> {
     pub(crate) fn new(
         root_page: Option<(PageNumber, Checksum)>,
         mem: &'txn TransactionalMemory,
+        handle: Option<ReadOnlyTableHandle<'txn>>,
     ) -> ReadOnlyTable<'txn, K, V> {
         ReadOnlyTable {
             tree: Btree::new(root_page, mem),
+            _handle: handle,
         }
     }
+
+    /// Writes the records which were inserted, modified, or removed since `base`, which is this
+    /// table in an earlier snapshot of the same database, in the format read by
+    /// [`Table::import_records`]
+    ///
+    /// Removed records are written as the key, followed by a value length of `u32::MAX` and no
+    /// value. Pages which are shared by both snapshots are skipped without being read, so this is
+    /// much faster than [`ReadableTable::export_range`] when few records changed. For example, an
+    /// incremental backup can be taken by exporting the changes since the
+    /// [`ReadTransaction::transaction_id`](crate::ReadTransaction::transaction_id) of the previous
+    /// backup, whose snapshot is retained by a [`RetentionPolicy`](crate::RetentionPolicy), and
+    /// read with [`Database::begin_read_at`](crate::Database::begin_read_at)
+    ///
+    /// Returns the number of records written, or [`Error::DatabaseMismatch`] if `base` is from a
+    /// different database
+    pub fn export_changes(
+        &self,
+        base: &ReadOnlyTable<K, V>,
+        mut writer: impl Write,
+    ) -> Result<u64> {
+        let mut count = 0;
+        self.tree.diff(&base.tree, |key, value| {
+            write_record_field(&mut writer, key)?;
+            if let Some(value) = value {
+                write_record_field(&mut writer, value)?;
+            } else {
+                writer.write_all(&REMOVED_RECORD_LEN.to_le_bytes())?;
+            }
+            count += 1;
+            Ok(())
+        })?;
+        writer.flush()?;
+
+        Ok(count)
+    }
+
+    /// Writes the structure of the table's b-tree to `writer`, for debugging. See
+    /// [`TreeDumpFormat`] for a description of the output
+    pub fn dump_tree(&self, format: TreeDumpFormat, writer: impl Write) -> Result {
+        self.tree.dump(format, writer)
+    }
+
+    /// Returns an iterator over the pages of the table's b-tree, which describes the size and
+    /// position in the tree of each page. If a page can't be read, the error is returned and the
+    /// iteration ends
+    pub fn pages(&self) -> Result<TreePageIter> {
+        Ok(self.tree.pages())
+    }
 }
 
 impl<'txn, K: RedbKey + ?Sized, V: RedbValue + ?Sized> ReadableTable<K, V>
@@ -204,6 +1064,30 @@ impl<'txn, K: RedbKey + ?Sized, V: RedbValue + ?Sized> ReadableTable<K, V>
         self.tree.get(key.borrow())
     }
 
+    fn get_multi<'a, 'b: 'a, AK>(
+        &self,
+        keys: impl IntoIterator<Item = &'a AK>,
+    ) -> Result<Vec<Option<V::SelfType<'_>>>>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized + 'a,
+    {
+        let serialized: Vec<K::AsBytes<'_>> = keys
+            .into_iter()
+            .map(|key| K::as_bytes(key.borrow()))
+            .collect();
+        let queries: Vec<&[u8]> = serialized.iter().map(|key| key.as_ref()).collect();
+        self.tree.get_multi(&queries)
+    }
+
+    fn contains_key<'a, 'b: 'a, AK>(&self, key: &'a AK) -> Result<bool>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        self.tree.contains_key(key.borrow())
+    }
+
     fn range<'a, KR>(&'a self, range: impl RangeBounds<KR> + 'a) -> Result<RangeIter<'a, K, V>>
     where
         K: 'a,
@@ -212,6 +1096,45 @@ impl<'txn, K: RedbKey + ?Sized, V: RedbValue + ?Sized> ReadableTable<K, V>
         self.tree.range(range).map(RangeIter::new)
     }
 
+    fn range_prefix<'a>(&'a self, prefix: &K::RefBaseType<'_>) -> Result<RangeIter<'a, K, V>>
+    where
+        K: PrefixKey + 'a,
+    {
+        self.tree.range_prefix(prefix).map(RangeIter::new)
+    }
+
+    fn cursor(&self) -> Result<Cursor<K, V>> {
+        Ok(Cursor::new(self.tree.cursor()))
+    }
+
+    fn first(&self) -> Result<Option<(K::SelfType<'_>, V::SelfType<'_>)>> {
+        self.tree.first()
+    }
+
+    fn last(&self) -> Result<Option<(K::SelfType<'_>, V::SelfType<'_>)>> {
+        self.tree.last()
+    }
+
+    fn nth(&self, index: usize) -> Result<Option<(K::SelfType<'_>, V::SelfType<'_>)>> {
+        self.tree.nth(index)
+    }
+
+    fn rank<'a, 'b: 'a, AK>(&self, key: &'a AK) -> Result<usize>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        self.tree.rank(key.borrow())
+    }
+
+    fn count_range<'a, KR>(&'a self, range: impl RangeBounds<KR> + 'a) -> Result<usize>
+    where
+        K: 'a,
+        KR: Borrow<K::RefBaseType<'a>> + ?Sized + 'a,
+    {
+        self.tree.count_range(range)
+    }
+
     fn len(&self) -> Result<usize> {
         self.tree.len()
     }
@@ -221,6 +1144,12 @@ impl<'txn, K: RedbKey + ?Sized, V: RedbValue + ?Sized> ReadableTable<K, V>
     }
 }
 
+/// An iterator over a range of entries of a table
+///
+/// # Panics
+///
+/// Iteration panics if a corrupted page is encountered. [`RangeIter::try_next`] and
+/// [`RangeIter::try_next_back`] return an error instead
 pub struct RangeIter<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> {
     inner: BtreeRangeIter<'a, K, V>,
 }
@@ -229,19 +1158,32 @@ impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> RangeIter<'a, K,
     fn new(inner: BtreeRangeIter<'a, K, V>) -> Self {
         Self { inner }
     }
+
+    /// Returns the next entry, or an error if a corrupted page is encountered
+    ///
+    /// [`Iterator::next`] panics on such errors, so prefer this method for long scans over
+    /// data which may be damaged
+    pub fn try_next(&mut self) -> Result<Option<(K::SelfType<'a>, V::SelfType<'a>)>> {
+        self.inner
+            .try_next()?
+            .map(|entry| entry.deserialize::<K, V>())
+            .transpose()
+    }
+
+    /// Returns the next entry from the end, or an error if a corrupted page is encountered
+    pub fn try_next_back(&mut self) -> Result<Option<(K::SelfType<'a>, V::SelfType<'a>)>> {
+        self.inner
+            .try_next_back()?
+            .map(|entry| entry.deserialize::<K, V>())
+            .transpose()
+    }
 }
 
 impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> Iterator for RangeIter<'a, K, V> {
     type Item = (K::SelfType<'a>, V::SelfType<'a>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(entry) = self.inner.next() {
-            let key = K::from_bytes(entry.key());
-            let value = V::from_bytes(entry.value());
-            Some((key, value))
-        } else {
-            None
-        }
+        self.try_next().unwrap_or_else(|err| panic!("{}", err))
     }
 }
 
@@ -249,13 +1191,155 @@ impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> DoubleEndedIterat
     for RangeIter<'a, K, V>
 {
     fn next_back(&mut self) -> Option<Self::Item> {
-        if let Some(entry) = self.inner.next_back() {
-            let key = K::from_bytes(entry.key());
-            let value = V::from_bytes(entry.value());
-            Some((key, value))
-        } else {
-            None
-        }
+        self.try_next_back().unwrap_or_else(|err| panic!("{}", err))
+    }
+}
+
+/// A cursor over the entries of a table, which can be repositioned without rebuilding an iterator
+///
+/// A new cursor is unpositioned: [`Cursor::next`] moves it to the first entry, and
+/// [`Cursor::prev`] to the last. Moving past either end of the table leaves the cursor before the
+/// first entry, or after the last, from where it can move back in the opposite direction.
+pub struct Cursor<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> {
+    inner: BtreeCursor<'a, K, V>,
+}
+
+impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> Cursor<'a, K, V> {
+    fn new(inner: BtreeCursor<'a, K, V>) -> Self {
+        Self { inner }
+    }
+
+    fn deserialize(
+        entry: Option<EntryAccessor<'a>>,
+    ) -> Result<Option<(K::SelfType<'a>, V::SelfType<'a>)>> {
+        entry.map(|entry| entry.deserialize::<K, V>()).transpose()
+    }
+
+    /// Returns the entry at the current position of the cursor
+    ///
+    /// # Panics
+    ///
+    /// Panics if the entry is corrupted, in which case the move to it returned an error
+    pub fn current(&self) -> Option<(K::SelfType<'a>, V::SelfType<'a>)> {
+        Self::deserialize(self.inner.current()).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Moves the cursor to the first entry with a key greater than or equal to `key`, and
+    /// returns it
+    pub fn seek<'b, AK>(&mut self, key: &AK) -> Result<Option<(K::SelfType<'a>, V::SelfType<'a>)>>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        let entry = self.inner.seek(K::as_bytes(key.borrow()).as_ref())?;
+        Self::deserialize(entry)
+    }
+
+    /// Moves the cursor to the last entry with a key less than or equal to `key`, and returns it
+    pub fn seek_for_prev<'b, AK>(
+        &mut self,
+        key: &AK,
+    ) -> Result<Option<(K::SelfType<'a>, V::SelfType<'a>)>>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        let entry = self
+            .inner
+            .seek_for_prev(K::as_bytes(key.borrow()).as_ref())?;
+        Self::deserialize(entry)
+    }
+
+    /// Moves the cursor to the next entry, and returns it
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<(K::SelfType<'a>, V::SelfType<'a>)>> {
+        let entry = self.inner.next()?;
+        Self::deserialize(entry)
+    }
+
+    /// Moves the cursor to the previous entry, and returns it
+    pub fn prev(&mut self) -> Result<Option<(K::SelfType<'a>, V::SelfType<'a>)>> {
+        let entry = self.inner.prev()?;
+        Self::deserialize(entry)
+    }
+}
+
+/// Iterator over the entries removed by [`Table::drain`]
+pub struct Drain<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> {
+    inner: std::vec::IntoIter<(AccessGuard<'a, K>, AccessGuard<'a, V>)>,
+}
+
+impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> Iterator for Drain<'a, K, V> {
+    type Item = (AccessGuard<'a, K>, AccessGuard<'a, V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> DoubleEndedIterator
+    for Drain<'a, K, V>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back()
+    }
+}
+
+/// An iterator over the keys of a table, returned by [`ReadableTable::keys`]
+///
+/// # Panics
+///
+/// Iteration panics if a corrupted page is encountered
+pub struct KeyIter<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> {
+    inner: BtreeRangeIter<'a, K, V>,
+}
+
+impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> Iterator for KeyIter<'a, K, V> {
+    type Item = K::SelfType<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next_or_panic().map(|entry| {
+            from_stored_bytes::<K>(entry.key()).unwrap_or_else(|err| panic!("{}", err))
+        })
+    }
+}
+
+impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> DoubleEndedIterator
+    for KeyIter<'a, K, V>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back_or_panic().map(|entry| {
+            from_stored_bytes::<K>(entry.key()).unwrap_or_else(|err| panic!("{}", err))
+        })
+    }
+}
+
+/// An iterator over the values of a table, returned by [`ReadableTable::values`]
+///
+/// # Panics
+///
+/// Iteration panics if a corrupted page is encountered
+pub struct ValueIter<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> {
+    inner: BtreeRangeIter<'a, K, V>,
+}
+
+impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> Iterator for ValueIter<'a, K, V> {
+    type Item = V::SelfType<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next_or_panic().map(|entry| {
+            from_stored_bytes::<V>(entry.value()).unwrap_or_else(|err| panic!("{}", err))
+        })
+    }
+}
+
+impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> DoubleEndedIterator
+    for ValueIter<'a, K, V>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back_or_panic().map(|entry| {
+            from_stored_bytes::<V>(entry.value()).unwrap_or_else(|err| panic!("{}", err))
+        })
     }
 }
 
@@ -315,7 +1399,7 @@ mod test {
         let definition: TableDefinition<ReverseKey, &[u8]> = TableDefinition::new("x");
 
         let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-        let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+        let db = Database::create(tmpfile.path()).unwrap();
         let write_txn = db.begin_write().unwrap();
         {
             let mut table = write_txn.open_table(definition).unwrap();
@@ -338,4 +1422,236 @@ mod test {
         }
         assert!(iter.next().is_none());
     }
+
+    #[test]
+    fn remove_range() {
+        use rand::Rng;
+        use std::collections::BTreeMap;
+        use std::ops::{Bound, RangeBounds};
+
+        let definition: TableDefinition<u64, u64> = TableDefinition::new("x");
+
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        // Use small pages, so that the tree is several levels deep
+        let db = Database::builder()
+            .set_page_size(512)
+            .create(tmpfile.path())
+            .unwrap();
+        let mut rng = rand::thread_rng();
+        let mut expected = BTreeMap::new();
+        let random_bound = |rng: &mut rand::rngs::ThreadRng| match rng.gen_range(0..3) {
+            0 => Bound::Included(rng.gen_range(0..10_000u64)),
+            1 => Bound::Excluded(rng.gen_range(0..10_000u64)),
+            _ => Bound::Unbounded,
+        };
+        for _ in 0..50 {
+            let write_txn = db.begin_write().unwrap();
+            {
+                let mut table = write_txn.open_table(definition).unwrap();
+                while expected.len() < 2000 {
+                    let key = rng.gen_range(0..10_000u64);
+                    table.insert(&key, &key).unwrap();
+                    expected.insert(key, key);
+                }
+                let start = random_bound(&mut rng);
+                let end = random_bound(&mut rng);
+                let before = expected.len();
+                expected.retain(|k, _| !(start, end).contains(k));
+                let removed = table.remove_range((start, end)).unwrap();
+                assert_eq!(usize::try_from(removed).unwrap(), before - expected.len());
+            }
+            write_txn.commit().unwrap();
+
+            let read_txn = db.begin_read().unwrap();
+            let table = read_txn.open_table(definition).unwrap();
+            let actual: Vec<u64> = table.iter().unwrap().map(|(k, _)| k).collect();
+            let expected_keys: Vec<u64> = expected.keys().copied().collect();
+            assert_eq!(actual, expected_keys);
+        }
+    }
+
+    #[test]
+    fn extend() {
+        use rand::Rng;
+        use std::collections::BTreeMap;
+
+        let definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        // Use small pages, so that batches split pages several times over
+        let db = Database::builder()
+            .set_page_size(512)
+            .create(tmpfile.path())
+            .unwrap();
+        let mut rng = rand::thread_rng();
+        let mut expected = BTreeMap::new();
+        for _ in 0..20 {
+            let write_txn = db.begin_write().unwrap();
+            {
+                let mut table = write_txn.open_table(definition).unwrap();
+                let batch: Vec<(u64, Vec<u8>)> = (0..rng.gen_range(0..2000))
+                    .map(|_| {
+                        let key = rng.gen_range(0..20_000u64);
+                        let value = vec![rng.gen(); rng.gen_range(0..100)];
+                        (key, value)
+                    })
+                    .collect();
+                table
+                    .extend(batch.iter().map(|(k, v)| (k, v.as_slice())))
+                    .unwrap();
+                expected.extend(batch);
+                for _ in 0..100 {
+                    let key = rng.gen_range(0..20_000u64);
+                    table.remove(&key).unwrap();
+                    expected.remove(&key);
+                }
+            }
+            write_txn.commit().unwrap();
+
+            let read_txn = db.begin_read().unwrap();
+            let table = read_txn.open_table(definition).unwrap();
+            let actual: Vec<(u64, Vec<u8>)> = table
+                .iter()
+                .unwrap()
+                .map(|(k, v)| (k, v.to_vec()))
+                .collect();
+            let expected_entries: Vec<(u64, Vec<u8>)> =
+                expected.iter().map(|(k, v)| (*k, v.clone())).collect();
+            assert_eq!(actual, expected_entries);
+        }
+    }
+
+    #[test]
+    fn load_sorted() {
+        use rand::Rng;
+
+        let definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        // Use small pages, so that the loaded subtrees have a range of heights
+        let db = Database::builder()
+            .set_page_size(512)
+            .create(tmpfile.path())
+            .unwrap();
+        let mut rng = rand::thread_rng();
+        let mut expected: Vec<(u64, Vec<u8>)> = vec![];
+        for _ in 0..30 {
+            let write_txn = db.begin_write().unwrap();
+            {
+                let mut table = write_txn.open_table(definition).unwrap();
+                let mut next_key = expected.last().map(|(k, _)| *k + 1).unwrap_or_default();
+                let batch: Vec<(u64, Vec<u8>)> = (0..rng.gen_range(0..3000))
+                    .map(|_| {
+                        let key = next_key;
+                        next_key += rng.gen_range(1..5);
+                        (key, vec![rng.gen(); rng.gen_range(0..100)])
+                    })
+                    .collect();
+                table
+                    .load_sorted(batch.iter().map(|(k, v)| (k, v.as_slice())))
+                    .unwrap();
+                expected.extend(batch);
+            }
+            write_txn.commit().unwrap();
+
+            let read_txn = db.begin_read().unwrap();
+            let table = read_txn.open_table(definition).unwrap();
+            let actual: Vec<(u64, Vec<u8>)> = table
+                .iter()
+                .unwrap()
+                .map(|(k, v)| (k, v.to_vec()))
+                .collect();
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn entry_counts() {
+        use rand::Rng;
+        use std::collections::BTreeMap;
+        use std::ops::{Bound, RangeBounds};
+
+        let definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x").with_entry_counts();
+
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        // Use small pages, so that the tree is several levels deep
+        let db = Database::builder()
+            .set_page_size(512)
+            .create(tmpfile.path())
+            .unwrap();
+        let mut rng = rand::thread_rng();
+        let mut expected: BTreeMap<u64, Vec<u8>> = BTreeMap::new();
+        let random_bound = |rng: &mut rand::rngs::ThreadRng| match rng.gen_range(0..3) {
+            0 => Bound::Included(rng.gen_range(0..10_000u64)),
+            1 => Bound::Excluded(rng.gen_range(0..10_000u64)),
+            _ => Bound::Unbounded,
+        };
+        for _ in 0..30 {
+            let write_txn = db.begin_write().unwrap();
+            {
+                let mut table = write_txn.open_table(definition).unwrap();
+                for _ in 0..rng.gen_range(0..1000) {
+                    let key = rng.gen_range(0..10_000u64);
+                    if rng.gen_bool(0.7) {
+                        let value = vec![rng.gen(); rng.gen_range(0..50)];
+                        table.insert(&key, value.as_slice()).unwrap();
+                        expected.insert(key, value);
+                    } else {
+                        table.remove(&key).unwrap();
+                        expected.remove(&key);
+                    }
+                }
+                match rng.gen_range(0..3) {
+                    0 => {
+                        let start = random_bound(&mut rng);
+                        let end = random_bound(&mut rng);
+                        expected.retain(|k, _| !(start, end).contains(k));
+                        table.remove_range((start, end)).unwrap();
+                    }
+                    1 => {
+                        let batch: Vec<(u64, Vec<u8>)> = (0..rng.gen_range(0..500))
+                            .map(|_| (rng.gen_range(0..10_000u64), vec![rng.gen(); 10]))
+                            .collect();
+                        table
+                            .extend(batch.iter().map(|(k, v)| (k, v.as_slice())))
+                            .unwrap();
+                        expected.extend(batch);
+                    }
+                    _ => {
+                        let mut next_key = expected.keys().last().map_or(0, |k| k + 1);
+                        let batch: Vec<(u64, Vec<u8>)> = (0..rng.gen_range(0..500))
+                            .map(|_| {
+                                let key = next_key;
+                                next_key += rng.gen_range(1..5);
+                                (key, vec![rng.gen(); 10])
+                            })
+                            .collect();
+                        table
+                            .load_sorted(batch.iter().map(|(k, v)| (k, v.as_slice())))
+                            .unwrap();
+                        expected.extend(batch);
+                    }
+                }
+                assert_eq!(table.len().unwrap(), expected.len());
+            }
+            write_txn.commit().unwrap();
+
+            let read_txn = db.begin_read().unwrap();
+            let table = read_txn.open_table(definition).unwrap();
+            assert_eq!(table.len().unwrap(), expected.len());
+            for _ in 0..100 {
+                let start = random_bound(&mut rng);
+                let end = random_bound(&mut rng);
+                let count = expected.keys().filter(|k| (start, end).contains(k)).count();
+                assert_eq!(table.count_range((start, end)).unwrap(), count);
+
+                let key = rng.gen_range(0..10_000u64);
+                assert_eq!(table.rank(&key).unwrap(), expected.range(..key).count());
+                let index = rng.gen_range(0..=expected.len());
+                let actual = table.nth(index).unwrap().map(|(k, v)| (k, v.to_vec()));
+                let expected_entry = expected.iter().nth(index).map(|(k, v)| (*k, v.clone()));
+                assert_eq!(actual, expected_entry);
+            }
+        }
+    }
 }
@@ -0,0 +1,115 @@
+/// Defines an enum which can be stored as a value, encoded as a one byte tag followed by the
+/// serialized payload of the variant
+///
+/// Every variant must have exactly one payload field, whose type implements [`crate::RedbValue`],
+/// and an explicit `u8` tag. Tags are part of the file format, so must not be changed once data
+/// has been written.
+///
+/// If a lifetime parameter is declared, the payloads are views into the stored data, and payload
+/// types should be written with a `'static` lifetime, such as `&'static str`.
+///
+/// # Examples
+///
+/// ```rust
+/// use redb::*;
+///
+/// tagged_union! {
+///     #[derive(Debug, PartialEq)]
+///     pub enum Event<'a> {
+///         Created(u64) = 0,
+///         Renamed(&'static str) = 1,
+///         Deleted(()) = 2,
+///     }
+/// }
+///
+/// const TABLE: TableDefinition<u64, Event> = TableDefinition::new("events");
+/// ```
+#[macro_export]
+macro_rules! tagged_union {
+    (
+        $(#[$attr:meta])*
+        $vis:vis enum $name:ident<$lt:lifetime> {
+            $( $variant:ident($t:ty) = $tag:literal ),+ $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        $vis enum $name<$lt> {
+            $( $variant(<$t as $crate::RedbValue>::SelfType<$lt>), )+
+        }
+
+        impl $crate::RedbValue for $name<'_> {
+            type SelfType<'a> = $name<'a>
+            where
+                Self: 'a;
+            type RefBaseType<'a> = $name<'a>
+            where
+                Self: 'a;
+            type AsBytes<'a> = Vec<u8>
+            where
+                Self: 'a;
+
+            $crate::tagged_union!(@common $name, $( $variant, $t, $tag ),+);
+        }
+    };
+    (
+        $(#[$attr:meta])*
+        $vis:vis enum $name:ident {
+            $( $variant:ident($t:ty) = $tag:literal ),+ $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        $vis enum $name {
+            $( $variant($t), )+
+        }
+
+        impl $crate::RedbValue for $name {
+            type SelfType<'a> = $name;
+            type RefBaseType<'a> = $name;
+            type AsBytes<'a> = Vec<u8>;
+
+            $crate::tagged_union!(@common $name, $( $variant, $t, $tag ),+);
+        }
+    };
+    (@common $name:ident, $( $variant:ident, $t:ty, $tag:literal ),+) => {
+        fn fixed_width() -> Option<usize> {
+            None
+        }
+
+        fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+        where
+            Self: 'a,
+        {
+            match data[0] {
+                $( $tag => $name::$variant(<$t as $crate::RedbValue>::from_bytes(&data[1..])), )+
+                tag => panic!("Unknown tag {} for {}", tag, stringify!($name)),
+            }
+        }
+
+        fn as_bytes<'a, 'b: 'a>(value: &'a Self::RefBaseType<'b>) -> Vec<u8>
+        where
+            Self: 'a,
+            Self: 'b,
+        {
+            match value {
+                $(
+                    $name::$variant(payload) => {
+                        let payload: &<$t as $crate::RedbValue>::RefBaseType<'_> =
+                            ::std::borrow::Borrow::borrow(payload);
+                        let mut result = vec![$tag];
+                        result.extend_from_slice(
+                            <$t as $crate::RedbValue>::as_bytes(payload).as_ref(),
+                        );
+                        result
+                    }
+                )+
+            }
+        }
+
+        fn redb_type_name() -> String {
+            let variants: Vec<String> = vec![
+                $( format!("{}:{}", $tag, <$t as $crate::RedbValue>::redb_type_name()), )+
+            ];
+            format!("{}{{{}}}", stringify!($name), variants.join(","))
+        }
+    };
+}
@@ -0,0 +1,125 @@
+use crate::table::{RangeIter, ReadableTable, Table};
+use crate::types::RedbValue;
+use crate::{AccessGuard, Result};
+use std::borrow::Borrow;
+use std::ops::{Bound, RangeBounds};
+
+/// A table of values keyed by `(timestamp, id)`, stored in a table with key type `(u64, u64)`
+///
+/// Entries are ordered by timestamp, and then by id, so that several entries may share a
+/// timestamp.
+///
+/// # Examples
+///
+/// ```rust
+/// use redb::*;
+/// # use tempfile::NamedTempFile;
+/// const SAMPLES: TableDefinition<(u64, u64), f64> = TableDefinition::new("samples");
+///
+/// # fn main() -> Result<(), Error> {
+/// # let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+/// # let filename = tmpfile.path();
+/// let db = Database::create(filename)?;
+/// let write_txn = db.begin_write()?;
+/// {
+///     let mut samples = TimeSeriesTable::new(write_txn.open_table(SAMPLES)?);
+///     samples.append(100, 0, &1.5)?;
+///     samples.append(200, 0, &2.5)?;
+///     samples.append(300, 0, &3.5)?;
+///     // Keep only the samples from the last 150 time units
+///     assert_eq!(samples.retain_recent(300, 150)?, 1);
+///     assert_eq!(samples.range(..)?.count(), 2);
+/// }
+/// write_txn.commit()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct TimeSeriesTable<'db, 'txn, V: RedbValue + ?Sized> {
+    table: Table<'db, 'txn, (u64, u64), V>,
+}
+
+impl<'db, 'txn, V: RedbValue + ?Sized + 'txn> TimeSeriesTable<'db, 'txn, V> {
+    pub fn new(table: Table<'db, 'txn, (u64, u64), V>) -> Self {
+        Self { table }
+    }
+
+    /// Returns the underlying table
+    pub fn into_inner(self) -> Table<'db, 'txn, (u64, u64), V> {
+        self.table
+    }
+
+    /// Insert a value at the given timestamp and id
+    ///
+    /// Returns the old value, if an entry with the same timestamp and id was present
+    pub fn append<'a, 'b: 'a, AV>(
+        &mut self,
+        timestamp: u64,
+        id: u64,
+        value: &'a AV,
+    ) -> Result<Option<AccessGuard<'_, V>>>
+    where
+        V: 'b,
+        AV: Borrow<V::RefBaseType<'b>> + ?Sized,
+    {
+        self.table.insert(&(timestamp, id), value)
+    }
+
+    /// Returns the value at the given timestamp and id
+    pub fn get(&self, timestamp: u64, id: u64) -> Result<Option<V::SelfType<'_>>> {
+        self.table.get(&(timestamp, id))
+    }
+
+    /// Returns a double-ended iterator over all entries whose timestamp is within `time_range`
+    pub fn range(&self, time_range: impl RangeBounds<u64>) -> Result<RangeIter<'_, (u64, u64), V>> {
+        let start = match time_range.start_bound() {
+            Bound::Included(&t) => Bound::Included((t, 0)),
+            Bound::Excluded(&t) => Bound::Excluded((t, u64::MAX)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let end = match time_range.end_bound() {
+            Bound::Included(&t) => Bound::Included((t, u64::MAX)),
+            Bound::Excluded(&t) => Bound::Excluded((t, 0)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        self.table.range::<(u64, u64)>((start, end))
+    }
+
+    /// Aggregates the entries within `time_range` into buckets of `bucket_width` time units
+    ///
+    /// Bucket boundaries are multiples of `bucket_width`. `init` is called with the start of each
+    /// non-empty bucket, and `fold` with the aggregate of the bucket, and the timestamp, id, and
+    /// value of each entry in it. Returns the start and aggregate of each non-empty bucket, in
+    /// order
+    pub fn downsample<A>(
+        &self,
+        time_range: impl RangeBounds<u64>,
+        bucket_width: u64,
+        mut init: impl FnMut(u64) -> A,
+        mut fold: impl FnMut(&mut A, u64, u64, V::SelfType<'_>),
+    ) -> Result<Vec<(u64, A)>> {
+        assert!(bucket_width > 0);
+        let mut buckets: Vec<(u64, A)> = vec![];
+        for ((timestamp, id), value) in self.range(time_range)? {
+            let bucket_start = timestamp - timestamp % bucket_width;
+            if buckets.last().map(|(start, _)| *start) != Some(bucket_start) {
+                buckets.push((bucket_start, init(bucket_start)));
+            }
+            fold(&mut buckets.last_mut().unwrap().1, timestamp, id, value);
+        }
+        Ok(buckets)
+    }
+
+    /// Removes all entries with a timestamp less than `timestamp`
+    ///
+    /// Returns the number of entries removed
+    pub fn remove_before(&mut self, timestamp: u64) -> Result<u64> {
+        self.table.remove_before(&(timestamp, 0))
+    }
+
+    /// Removes all entries which are more than `max_age` older than `now`
+    ///
+    /// Returns the number of entries removed
+    pub fn retain_recent(&mut self, now: u64, max_age: u64) -> Result<u64> {
+        self.remove_before(now.saturating_sub(max_age))
+    }
+}
@@ -1,6 +1,9 @@
-use crate::Savepoint;
+use crate::{ReadTransactionInfo, Savepoint};
 use std::collections::btree_map::BTreeMap;
 use std::collections::btree_set::BTreeSet;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::time::{Duration, Instant};
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
 pub(crate) struct TransactionId(pub u64);
@@ -25,6 +28,13 @@ pub(crate) struct TransactionTracker {
     // reference count of read transactions per transaction id
     live_read_transactions: BTreeMap<TransactionId, u64>,
     valid_savepoints: BTreeSet<SavepointId>,
+    // persistent savepoints which have been committed, and the read transaction each one holds
+    persistent_savepoints: BTreeMap<SavepointId, TransactionId>,
+    next_reader_id: u64,
+    // every open ReadTransaction, by reader id
+    open_readers: HashMap<u64, ReadTransactionInfo>,
+    // readers which have been reported by take_long_lived_readers()
+    reported_readers: HashSet<u64>,
 }
 
 impl TransactionTracker {
@@ -33,6 +43,10 @@ impl TransactionTracker {
             next_savepoint_id: SavepointId(0),
             live_read_transactions: Default::default(),
             valid_savepoints: Default::default(),
+            persistent_savepoints: Default::default(),
+            next_reader_id: 0,
+            open_readers: Default::default(),
+            reported_readers: Default::default(),
         }
     }
 
@@ -51,6 +65,43 @@ impl TransactionTracker {
         }
     }
 
+    // Records an open ReadTransaction, for diagnostics. Returns its reader id
+    pub(crate) fn register_reader(&mut self, info: ReadTransactionInfo) -> u64 {
+        let id = self.next_reader_id;
+        self.next_reader_id += 1;
+        self.open_readers.insert(id, info);
+        id
+    }
+
+    pub(crate) fn deregister_reader(&mut self, reader_id: u64) {
+        self.open_readers.remove(&reader_id);
+        self.reported_readers.remove(&reader_id);
+    }
+
+    // Returns the readers which have been open for at least `threshold`, and have not been
+    // returned before
+    pub(crate) fn take_long_lived_readers(
+        &mut self,
+        now: Instant,
+        threshold: Duration,
+    ) -> Vec<ReadTransactionInfo> {
+        let mut result = vec![];
+        for (id, info) in self.open_readers.iter() {
+            let age = now.duration_since(info.opened);
+            if age >= threshold && self.reported_readers.insert(*id) {
+                let mut info = info.clone();
+                info.age = age;
+                result.push(info);
+            }
+        }
+        result.sort_by_key(|info| (info.transaction_id, info.opened));
+        result
+    }
+
+    pub(crate) fn open_readers(&self) -> impl Iterator<Item = &ReadTransactionInfo> {
+        self.open_readers.values()
+    }
+
     pub(crate) fn allocate_savepoint(&mut self) -> SavepointId {
         let id = self.next_savepoint_id.next();
         self.next_savepoint_id = id;
@@ -63,6 +114,32 @@ impl TransactionTracker {
         self.deallocate_read_transaction(savepoint.get_transaction_id());
     }
 
+    // Replaces the set of committed persistent savepoints. Each one holds a read transaction, so
+    // that the pages it references are not freed, until it is deleted
+    pub(crate) fn set_persistent_savepoints(
+        &mut self,
+        savepoints: BTreeMap<SavepointId, TransactionId>,
+    ) {
+        let previous = mem::replace(&mut self.persistent_savepoints, savepoints);
+        for (id, transaction_id) in previous.iter() {
+            if !self.persistent_savepoints.contains_key(id) {
+                self.valid_savepoints.remove(id);
+                self.deallocate_read_transaction(*transaction_id);
+            }
+        }
+        for (id, transaction_id) in self.persistent_savepoints.clone() {
+            if !previous.contains_key(&id) {
+                self.valid_savepoints.insert(id);
+                self.register_read_transaction(transaction_id);
+            }
+        }
+        if let Some(max_id) = self.persistent_savepoints.keys().next_back() {
+            if *max_id > self.next_savepoint_id {
+                self.next_savepoint_id = *max_id;
+            }
+        }
+    }
+
     pub(crate) fn is_valid_savepoint(&self, id: SavepointId) -> bool {
         self.valid_savepoints.contains(&id)
     }
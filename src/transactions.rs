@@ -1,24 +1,35 @@
-use crate::transaction_tracker::{TransactionId, TransactionTracker};
+use crate::db::{CommitCallback, MergeOperator, PERSISTENT_SAVEPOINT_TABLE};
+use crate::export::export_table;
+use crate::key_changes::{KeyChange, PendingKeyChanges};
+use crate::transaction_tracker::{SavepointId, TransactionId, TransactionTracker};
 use crate::tree_store::{
-    Btree, BtreeMut, FreedTableKey, InternalTableDefinition, PageNumber, TableTree, TableType,
+    table_type_name, AllocationScope, Btree, BtreeMut, Checksum, FreedTableKey,
+    InternalTableDefinition, NestedAllocationState, PageNumber, TableSchema, TableTree, TableType,
     TransactionalMemory,
 };
+#[cfg(feature = "integrity_checks")]
+use crate::tree_store::{verify_tree, IntegrityCheck};
 use crate::types::{RedbKey, RedbValue};
 use crate::{
-    Database, Error, MultimapTable, MultimapTableDefinition, ReadOnlyMultimapTable, ReadOnlyTable,
-    Result, Savepoint, Table, TableDefinition,
+    DataFormat, Database, Error, MultimapTable, MultimapTableDefinition, ReadOnlyMultimapTable,
+    ReadOnlyTable, ReadableTable, Result, Savepoint, Table, TableDefinition, WriteBatch,
 };
 #[cfg(feature = "logging")]
 use log::{info, warn};
+use std::backtrace::Backtrace;
 use std::cell::RefCell;
 use std::cmp::min;
 use std::collections::HashMap;
+use std::io::Write;
+use std::mem;
 use std::mem::size_of;
-use std::ops::RangeFull;
+use std::ops::{Deref, RangeFull};
 use std::panic;
 use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
 
 /// Informational storage stats about the database
 #[derive(Debug)]
@@ -76,6 +87,121 @@ impl DatabaseStats {
     }
 }
 
+/// Information about an open [`ReadTransaction`], returned by
+/// [`Database::open_read_transactions`]
+#[derive(Clone, Debug)]
+pub struct ReadTransactionInfo {
+    pub(crate) transaction_id: TransactionId,
+    pub(crate) opened: Instant,
+    pub(crate) age: Duration,
+    pub(crate) backtrace: Option<Arc<Backtrace>>,
+}
+
+impl ReadTransactionInfo {
+    fn new(transaction_id: TransactionId, opened: Instant) -> Self {
+        #[cfg(feature = "read_transaction_backtraces")]
+        let backtrace = Some(Arc::new(Backtrace::force_capture()));
+        #[cfg(not(feature = "read_transaction_backtraces"))]
+        let backtrace = None;
+        Self {
+            transaction_id,
+            opened,
+            age: Duration::ZERO,
+            backtrace,
+        }
+    }
+
+    /// Id of the snapshot which the transaction reads, see [`ReadTransaction::transaction_id`]
+    pub fn transaction_id(&self) -> u64 {
+        self.transaction_id.0
+    }
+
+    /// Time from when the transaction began, until this information was returned by
+    /// [`crate::Database::open_read_transactions`]
+    pub fn age(&self) -> Duration {
+        self.age
+    }
+
+    /// Where the transaction began. Only captured if the `read_transaction_backtraces` feature is
+    /// enabled
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        self.backtrace.as_deref()
+    }
+}
+
+/// Statistics about a commit, returned by [`WriteTransaction::commit_with_stats`]
+#[derive(Clone, Debug, Default)]
+pub struct CommitStats {
+    pub(crate) pages_allocated: u64,
+    pub(crate) pages_freed: u64,
+    pub(crate) bytes_written: u64,
+    pub(crate) syncs: u64,
+    pub(crate) table_update_time: Duration,
+    pub(crate) compaction_time: Duration,
+    pub(crate) bytes_relocated: u64,
+    pub(crate) freed_pages_time: Duration,
+    pub(crate) write_time: Duration,
+    pub(crate) total_time: Duration,
+}
+
+impl CommitStats {
+    /// Number of pages allocated by the transaction, including pages which it later freed
+    pub fn pages_allocated(&self) -> u64 {
+        self.pages_allocated
+    }
+
+    /// Number of pages returned to the allocator by the transaction. This includes pages released
+    /// by earlier transactions, which are no longer referenced by any reader
+    pub fn pages_freed(&self) -> u64 {
+        self.pages_freed
+    }
+
+    /// Number of bytes in the pages written by the transaction. Does not include the database
+    /// header
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Number of times the commit synced the database file to disk
+    pub fn syncs(&self) -> u64 {
+        self.syncs
+    }
+
+    /// Time spent writing the roots of modified tables to the table of tables
+    pub fn table_update_time(&self) -> Duration {
+        self.table_update_time
+    }
+
+    /// Time spent relocating pages, see [`WriteTransaction::vacuum_on_commit`]
+    pub fn compaction_time(&self) -> Duration {
+        self.compaction_time
+    }
+
+    /// Number of bytes in the pages copied toward the start of the file by compaction
+    pub fn bytes_relocated(&self) -> u64 {
+        self.bytes_relocated
+    }
+
+    /// Time spent freeing pages, and recording the pages freed by the transaction
+    pub fn freed_pages_time(&self) -> Duration {
+        self.freed_pages_time
+    }
+
+    /// Time spent writing the database header and syncing the file
+    pub fn write_time(&self) -> Duration {
+        self.write_time
+    }
+
+    /// Total time spent committing
+    pub fn total_time(&self) -> Duration {
+        self.total_time
+    }
+}
+
+/// The durability of a [`WriteTransaction`]'s commit, set with [`WriteTransaction::set_durability`]
+///
+/// Syncing to disk dominates the cost of small commits, so bulk loads can use a lower level for
+/// most of their commits, and finish with a [`Durability::Immediate`] commit
 #[derive(Copy, Clone, Debug)]
 pub enum Durability {
     /// Commits with this durability level will not be persisted to disk unless followed by a
@@ -84,17 +210,32 @@ pub enum Durability {
     /// Note: Pages are only freed during commits with higher durability levels. Exclusively using
     /// this function may result in Error::OutOfSpace.
     None,
-    /// Commits with this durability level have been queued for persitance to disk, and should be
+    /// Commits with this durability level have been queued for persistence to disk, and should be
     /// persistent some time after [WriteTransaction::commit] returns.
     Eventual,
     /// Commits with this durability level are guaranteed to be persistent as soon as
     /// [WriteTransaction::commit] returns.
     Immediate,
+    /// Commits with this durability level are guaranteed to be persistent as soon as
+    /// [WriteTransaction::commit] returns, but the write lock is released before syncing.
+    ///
+    /// When several threads commit concurrently, the commits written while a sync is in
+    /// progress are all made persistent by the next sync (group commit). This improves the
+    /// throughput of small concurrent transactions, at the cost of an extra commit per sync.
+    ///
+    /// The commit is visible to other transactions before it is synced. If the sync fails,
+    /// [WriteTransaction::commit] returns [`Error::NotDurable`], and the commit is not undone.
+    Grouped,
 }
 
 /// A read/write transaction
 ///
 /// Only a single [`WriteTransaction`] may exist at a time
+///
+/// If a panic occurs while one of its tables is open, for example in a closure passed to
+/// [`Table::retain`], the transaction is poisoned, since the operation may have been left
+/// incomplete. It is rolled back when it's dropped during unwinding, and if the panic is caught,
+/// further operations on it and on its tables return [`Error::TransactionPoisoned`]
 pub struct WriteTransaction<'db> {
     db: &'db Database,
     transaction_tracker: Arc<Mutex<TransactionTracker>>,
@@ -107,15 +248,29 @@ pub struct WriteTransaction<'db> {
     freed_tree: BtreeMut<'db, FreedTableKey, &'static [u8]>,
     freed_pages: Rc<RefCell<Vec<PageNumber>>>,
     open_tables: RefCell<HashMap<String, &'static panic::Location<'static>>>,
+    // Number of read-only handles to each table, and where the first was opened
+    read_only_tables: RefCell<HashMap<String, (usize, &'static panic::Location<'static>)>>,
     completed: bool,
     dirty: AtomicBool,
+    // Set if a panic occurred during an operation on one of the transaction's tables, or while a
+    // table was open, which may have left the tables inconsistent
+    poisoned: AtomicBool,
+    // Set if the persistent savepoints may have changed, so that the tracker must be updated
+    // after commit
+    persistent_savepoints_changed: AtomicBool,
     durability: Durability,
+    // Maximum number of bytes to relocate during commit. See Database::compact(),
+    // Database::run_compaction_policy() and WriteTransaction::vacuum_on_commit()
+    relocation_budget: usize,
+    commit_stats: CommitStats,
+    key_changes: RefCell<Option<PendingKeyChanges>>,
+    commit_callbacks: RefCell<Vec<CommitCallback>>,
     live_write_transaction: MutexGuard<'db, Option<TransactionId>>,
 }
 
 impl<'db> WriteTransaction<'db> {
     pub(crate) fn new(db: &'db Database) -> Result<Self> {
-        let mut live_write_transaction = db.live_write_transaction.lock().unwrap();
+        let mut live_write_transaction = db.lock_live_write_transaction();
         assert!(live_write_transaction.is_none());
         let transaction_id = db.increment_transaction_id();
         #[cfg(feature = "logging")]
@@ -126,9 +281,10 @@ impl<'db> WriteTransaction<'db> {
         unsafe {
             db.get_memory().mark_transaction(transaction_id);
         }
+        db.get_memory().pin();
 
-        let root_page = db.get_memory().get_data_root();
-        let freed_root = db.get_memory().get_freed_root();
+        let root_page = db.get_memory().get_data_root()?;
+        let freed_root = db.get_memory().get_freed_root()?;
         let freed_pages = Rc::new(RefCell::new(vec![]));
         Ok(Self {
             db,
@@ -143,13 +299,92 @@ impl<'db> WriteTransaction<'db> {
             freed_tree: BtreeMut::new(freed_root, db.get_memory(), freed_pages.clone()),
             freed_pages,
             open_tables: RefCell::new(Default::default()),
+            read_only_tables: RefCell::new(Default::default()),
             completed: false,
             dirty: AtomicBool::new(false),
+            poisoned: AtomicBool::new(false),
+            persistent_savepoints_changed: AtomicBool::new(false),
             durability: Durability::Immediate,
+            relocation_budget: 0,
+            commit_stats: Default::default(),
+            key_changes: RefCell::new(
+                db.key_changes()
+                    .has_subscribers()
+                    .then(PendingKeyChanges::default),
+            ),
+            commit_callbacks: RefCell::new(vec![]),
             live_write_transaction,
         })
     }
 
+    /// Returns the id of this transaction, which identifies the database version it produces once
+    /// committed
+    ///
+    /// Ids increase monotonically, and persist across restarts, but are not contiguous
+    pub fn transaction_id(&self) -> u64 {
+        self.transaction_id.0
+    }
+
+    /// Register a callback, which is called once after this transaction has been committed
+    /// durably
+    ///
+    /// Callbacks are called in the order they were registered, after the write lock is released.
+    /// If the transaction is aborted, they are dropped without being called. The callbacks of a
+    /// transaction committed with [`Durability::None`] or [`Durability::Eventual`] are called when
+    /// a later transaction is committed with [`Durability::Immediate`], or by
+    /// [`PendingCommit::sync`], and are dropped if the database is closed first
+    pub fn on_commit(&self, callback: impl FnOnce() + Send + 'static) {
+        self.commit_callbacks.borrow_mut().push(Box::new(callback));
+    }
+
+    /// Begins a nested transaction
+    ///
+    /// The writes performed through the nested transaction become part of this transaction when
+    /// it is committed with [`NestedWriteTransaction::commit`], and are discarded if it is
+    /// aborted or dropped. Nested transactions may themselves be nested
+    pub fn begin_nested(&mut self) -> Result<NestedWriteTransaction<'_, 'db>> {
+        self.check_poisoned()?;
+        let root = self.table_tree.borrow_mut().flush_table_root_updates()?;
+        let state = NestedState {
+            root,
+            allocations: self.mem.begin_nested(),
+            freed_pages: self.freed_pages.borrow().len(),
+            key_changes: self.key_changes.borrow().clone(),
+            commit_callbacks: self.commit_callbacks.borrow().len(),
+        };
+
+        Ok(NestedWriteTransaction {
+            transaction: self,
+            state: Some(state),
+        })
+    }
+
+    fn rollback_nested(&mut self, state: NestedState) -> Result {
+        *self.table_tree.get_mut() = TableTree::new(state.root, self.mem, self.freed_pages.clone());
+        self.freed_pages.borrow_mut().truncate(state.freed_pages);
+        *self.key_changes.get_mut() = state.key_changes;
+        self.commit_callbacks
+            .get_mut()
+            .truncate(state.commit_callbacks);
+        self.mem.rollback_nested(state.allocations)
+    }
+
+    fn commit_nested(&mut self, state: NestedState) -> Result {
+        self.mem.commit_nested(state.allocations);
+        // The pages of the enclosing transaction which were freed by the nested transaction have
+        // never been committed, so they can be reused immediately
+        let mut freed_pages = self.freed_pages.borrow_mut();
+        let nested_freed = freed_pages.split_off(state.freed_pages);
+        for page in nested_freed {
+            // Safety: the nested transaction has ended, so no references to its freed pages exist
+            if !unsafe { self.mem.free_if_uncommitted(page)? } {
+                freed_pages.push(page);
+            }
+        }
+
+        Ok(())
+    }
+
     /// Creates a snapshot of the current database state, which can be used to rollback the database
     ///
     /// Returns `[Error::InvalidSavepoint`], if the transaction is "dirty" (any tables have been openned)
@@ -158,6 +393,11 @@ impl<'db> WriteTransaction<'db> {
             return Err(Error::InvalidSavepoint);
         }
 
+        let version = self.mem.get_version()?;
+        let regional_allocators = self.mem.get_raw_allocator_states()?;
+        let root = self.mem.get_data_root()?;
+        let freed_root = self.mem.get_freed_root()?;
+
         let (id, transaction_id) = self.db.allocate_savepoint()?;
         #[cfg(feature = "logging")]
         info!(
@@ -165,13 +405,11 @@ impl<'db> WriteTransaction<'db> {
             id, transaction_id
         );
 
-        let regional_allocators = self.mem.get_raw_allocator_states();
-        let root = self.mem.get_data_root();
-        let freed_root = self.mem.get_freed_root();
         let savepoint = Savepoint::new(
             self.db,
             id,
             transaction_id,
+            version,
             root,
             freed_root,
             regional_allocators,
@@ -180,10 +418,125 @@ impl<'db> WriteTransaction<'db> {
         Ok(savepoint)
     }
 
+    /// Creates a persistent snapshot of the current database state, which can be used to rollback
+    /// the database, even after it has been closed and reopened
+    ///
+    /// The savepoint is stored when this transaction commits, and remains valid until it is deleted
+    /// with [`Self::delete_persistent_savepoint`], or an older savepoint is restored. Pages
+    /// referenced by the savepoint cannot be reused while it exists. Returns the id of the
+    /// savepoint, which can be passed to [`Self::get_persistent_savepoint`]
+    ///
+    /// Returns `[Error::InvalidSavepoint`], if the transaction is "dirty" (any tables have been openned)
+    pub fn persistent_savepoint(&self) -> Result<u64> {
+        self.check_poisoned()?;
+        let mut savepoint = self.savepoint()?;
+        savepoint.set_persistent();
+        let data = savepoint.to_bytes();
+        self.update_persistent_savepoints(|tree| {
+            // Safety: References into the savepoint table are never returned to the user
+            unsafe { tree.insert(&savepoint.get_id().0, data.as_slice())? };
+            Ok(())
+        })?;
+
+        Ok(savepoint.get_id().0)
+    }
+
+    /// Returns the persistent savepoint with the given id
+    ///
+    /// Returns `[Error::InvalidSavepoint`], if no such savepoint exists
+    pub fn get_persistent_savepoint(&self, id: u64) -> Result<Savepoint> {
+        let definition = self
+            .table_tree
+            .borrow()
+            .get_table::<u64, &[u8]>(PERSISTENT_SAVEPOINT_TABLE, TableType::System)?;
+        let tree: Btree<u64, &[u8]> = Btree::new(
+            definition.and_then(|definition| definition.get_root()),
+            self.mem,
+        );
+        if let Some(data) = tree.get(&id)? {
+            let savepoint = Savepoint::from_bytes(self.db, SavepointId(id), data)?;
+            self.transaction_tracker
+                .lock()
+                .unwrap()
+                .register_read_transaction(savepoint.get_transaction_id());
+            Ok(savepoint)
+        } else {
+            Err(Error::InvalidSavepoint)
+        }
+    }
+
+    /// Deletes the persistent savepoint with the given id, when this transaction commits
+    ///
+    /// Returns `true` if the savepoint existed
+    pub fn delete_persistent_savepoint(&self, id: u64) -> Result<bool> {
+        self.update_persistent_savepoints(|tree| {
+            // Safety: References into the savepoint table are never returned to the user
+            Ok(unsafe { tree.remove(&id)?.is_some() })
+        })
+    }
+
+    /// Returns the ids of all persistent savepoints, in the order they were created
+    pub fn list_persistent_savepoints(&self) -> Result<impl Iterator<Item = u64>> {
+        let definition = self
+            .table_tree
+            .borrow()
+            .get_table::<u64, &[u8]>(PERSISTENT_SAVEPOINT_TABLE, TableType::System)?;
+        let tree: Btree<u64, &[u8]> = Btree::new(
+            definition.and_then(|definition| definition.get_root()),
+            self.mem,
+        );
+        let mut ids = vec![];
+        let mut iter = tree.range::<RangeFull, u64>(..)?;
+        while let Some(entry) = iter.try_next()? {
+            ids.push(u64::from_bytes(entry.key()));
+        }
+        Ok(ids.into_iter())
+    }
+
+    // Deletes every persistent savepoint
+    pub(crate) fn delete_persistent_savepoints(&self) -> Result {
+        self.table_tree
+            .borrow_mut()
+            .delete_table::<u64, &[u8]>(PERSISTENT_SAVEPOINT_TABLE, TableType::System)?;
+        self.persistent_savepoints_changed
+            .store(true, Ordering::Release);
+        Ok(())
+    }
+
+    // Frees the given committed pages, once every read transaction and savepoint which is older
+    // than this transaction has ended
+    pub(crate) fn free_on_commit(&self, pages: impl IntoIterator<Item = PageNumber>) {
+        self.freed_pages.borrow_mut().extend(pages);
+    }
+
+    fn update_persistent_savepoints<T>(
+        &self,
+        update: impl FnOnce(&mut BtreeMut<'db, u64, &'static [u8]>) -> Result<T>,
+    ) -> Result<T> {
+        let definition = self
+            .table_tree
+            .borrow_mut()
+            .get_or_create_table::<u64, &[u8]>(
+                PERSISTENT_SAVEPOINT_TABLE,
+                TableType::System,
+                false,
+            )?;
+        let mut tree = BtreeMut::new(definition.get_root(), self.mem, self.freed_pages.clone());
+        let result = update(&mut tree)?;
+        self.table_tree
+            .borrow_mut()
+            .stage_update_table_root(PERSISTENT_SAVEPOINT_TABLE, tree.get_root());
+        self.persistent_savepoints_changed
+            .store(true, Ordering::Release);
+
+        Ok(result)
+    }
+
     /// Restore the state of the database to the given [`Savepoint`]
     ///
     /// Calling this method invalidates all [`Savepoint`]s created after savepoint
     pub fn restore_savepoint(&mut self, savepoint: &Savepoint) -> Result {
+        self.check_poisoned()?;
         // Ensure that user does not try to restore a Savepoint that is from a different Database
         assert_eq!(
             self.db.transaction_tracker().as_ref() as *const _,
@@ -206,16 +559,19 @@ impl<'db> WriteTransaction<'db> {
         );
         // Restoring a savepoint that reverted a file format or checksum type change could corrupt
         // the database
-        assert_eq!(self.db.get_memory().get_version(), savepoint.get_version());
+        assert_eq!(self.db.get_memory().get_version()?, savepoint.get_version());
         assert_eq!(
             self.db.get_memory().checksum_type(),
             savepoint.get_checksum_type()
         );
         self.dirty.store(true, Ordering::Release);
+        if let Some(changes) = self.key_changes.get_mut() {
+            changes.invalidate_all();
+        }
 
         let allocated_since_savepoint = self
             .mem
-            .pages_allocated_since_raw_state(savepoint.get_regional_allocator_states());
+            .pages_allocated_since_raw_state(savepoint.get_regional_allocator_states())?;
         let mut freed_pages = vec![];
         for page in allocated_since_savepoint {
             if self.mem.uncommitted(page) {
@@ -239,7 +595,7 @@ impl<'db> WriteTransaction<'db> {
         let oldest_unprocessed_transaction = if let Some(entry) = self
             .freed_tree
             .range::<RangeFull, FreedTableKey>(..)?
-            .next()
+            .try_next()?
         {
             FreedTableKey::from_bytes(entry.key()).transaction_id
         } else {
@@ -256,14 +612,29 @@ impl<'db> WriteTransaction<'db> {
             pagination_id: 0,
         };
         let mut to_remove = vec![];
-        for entry in self.freed_tree.range(..lookup_key)? {
+        let mut iter = self.freed_tree.range(..lookup_key)?;
+        while let Some(entry) = iter.try_next()? {
             to_remove.push(FreedTableKey::from_bytes(entry.key()));
         }
+        drop(iter);
         for key in to_remove {
             // Safety: all references to the freed table above have already been dropped.
             unsafe { self.freed_tree.remove(&key)? };
         }
 
+        // The record of a persistent savepoint is stored after its snapshot was taken, so must be
+        // written again. Any newer persistent savepoints are removed by restoring the snapshot
+        if savepoint.is_persistent() {
+            let data = savepoint.to_bytes();
+            self.update_persistent_savepoints(|tree| {
+                // Safety: References into the savepoint table are never returned to the user
+                unsafe { tree.insert(&savepoint.get_id().0, data.as_slice())? };
+                Ok(())
+            })?;
+        }
+        self.persistent_savepoints_changed
+            .store(true, Ordering::Release);
+
         // Invalidate all savepoints that are newer than the one being applied to prevent the user
         // from later trying to restore a savepoint "on another timeline"
         self.transaction_tracker
@@ -280,6 +651,97 @@ impl<'db> WriteTransaction<'db> {
         self.durability = durability;
     }
 
+    /// Relocate up to `max_pages` pages toward the start of the database file when this
+    /// transaction commits, so that fragmentation is reduced gradually instead of by a single
+    /// long call to [`Database::compact`]
+    ///
+    /// Pages larger than the page size count as several pages. Pages are only relocated by
+    /// durable commits, so this has no effect if the durability is [`Durability::None`]
+    pub fn vacuum_on_commit(&mut self, max_pages: usize) {
+        self.relocation_budget = self
+            .relocation_budget
+            .saturating_add(max_pages.saturating_mul(self.mem.get_page_size()));
+    }
+
+    /// Returns an estimate of the number of bytes of memory held by this transaction: the pages
+    /// which it has written, the list of pages which it has freed, and the changes which are
+    /// pending for key change subscribers
+    ///
+    /// See [`crate::Builder::set_write_transaction_memory_limit`]
+    pub fn memory_usage(&self) -> u64 {
+        let freed_pages = self.freed_pages.borrow().len() * PageNumber::serialized_size();
+        let key_changes = self
+            .key_changes
+            .borrow()
+            .as_ref()
+            .map_or(0, |changes| changes.memory_usage());
+        self.mem.get_dirty_bytes() + u64::try_from(freed_pages + key_changes).unwrap()
+    }
+
+    // Returns an error if this transaction holds more memory than the database's limit. Called
+    // before each operation which inserts into a table
+    pub(crate) fn check_memory_limit(&self) -> Result {
+        if let Some(limit) = self.db.write_transaction_memory_limit() {
+            let usage = self.memory_usage();
+            if usage > limit {
+                return Err(Error::TransactionMemoryLimitExceeded(usage));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Begins an operation on the given table, which is ended when the returned guard is dropped.
+    // Fails if the transaction has been poisoned
+    pub(crate) fn begin_operation(&self, table: &str) -> Result<TableOperation<'_, 'db>> {
+        self.check_poisoned()?;
+        Ok(TableOperation {
+            transaction: self,
+            _allocations: self.mem.attribute_allocations(table),
+        })
+    }
+
+    // Marks the transaction as poisoned, if the current thread is panicking
+    pub(crate) fn poison_if_panicking(&self) {
+        if thread::panicking() {
+            self.poisoned.store(true, Ordering::Release);
+        }
+    }
+
+    // Fails if the transaction has been poisoned. Tables check this before reads, as well as
+    // writes, since a poisoned transaction may contain a partially applied operation
+    pub(crate) fn check_poisoned(&self) -> Result {
+        if self.poisoned.load(Ordering::Acquire) {
+            Err(Error::TransactionPoisoned)
+        } else {
+            Ok(())
+        }
+    }
+
+    // Relocate pages toward the start of the file when this transaction is committed, without
+    // limiting the number of bytes copied
+    pub(crate) fn set_compact(&mut self) {
+        self.relocation_budget = usize::MAX;
+    }
+
+    // Relocate up to `bytes` more bytes of pages when this transaction is committed
+    pub(crate) fn add_relocation_budget(&mut self, bytes: usize) {
+        self.relocation_budget = self.relocation_budget.saturating_add(bytes);
+    }
+
+    // Removes a table which was discarded by Database::repair(), without freeing its pages
+    pub(crate) fn discard_table(&self, name: &str) -> Result<bool> {
+        self.dirty.store(true, Ordering::Release);
+        self.table_tree.borrow_mut().discard_table(name)
+    }
+
+    // Copies all user tables in the snapshot of source, which may belong to a different database
+    pub(crate) fn copy_tables_from(&self, source: &ReadTransaction) -> Result {
+        self.table_tree
+            .borrow_mut()
+            .copy_tables_from(&source.tree())
+    }
+
     /// Open the given table
     ///
     /// The table will be created if it does not exist
@@ -287,6 +749,7 @@ impl<'db> WriteTransaction<'db> {
         &'txn self,
         definition: TableDefinition<K, V>,
     ) -> Result<Table<'db, 'txn, K, V>> {
+        self.check_poisoned()?;
         #[cfg(feature = "logging")]
         info!("Opening table: {}", definition);
         if let Some(location) = self.open_tables.borrow().get(definition.name()) {
@@ -295,25 +758,76 @@ impl<'db> WriteTransaction<'db> {
                 location,
             ));
         }
+        if let Some((_, location)) = self.read_only_tables.borrow().get(definition.name()) {
+            return Err(Error::TableAlreadyOpen(
+                definition.name().to_string(),
+                location,
+            ));
+        }
         self.dirty.store(true, Ordering::Release);
         self.open_tables
             .borrow_mut()
             .insert(definition.name().to_string(), panic::Location::caller());
 
-        let internal_table = self
-            .table_tree
-            .borrow_mut()
-            .get_or_create_table::<K, V>(definition.name(), TableType::Normal)?;
+        let internal_table = self.table_tree.borrow_mut().get_or_create_table::<K, V>(
+            definition.name(),
+            TableType::Normal,
+            definition.is_counted(),
+        )?;
 
         Ok(Table::new(
             definition.name(),
             internal_table.get_root(),
+            internal_table.is_counted(),
             self.freed_pages.clone(),
             self.mem,
             self,
         ))
     }
 
+    /// Open the given table for reading only
+    ///
+    /// Any number of read-only handles to a table may be open at the same time, and they observe
+    /// the uncommitted changes made by this transaction. The table cannot be opened with
+    /// [`Self::open_table`] until they have all been dropped.
+    ///
+    /// Returns [`Error::TableDoesNotExist`] if the table does not exist
+    pub fn open_table_read_only<'txn, K: RedbKey + ?Sized, V: RedbValue + ?Sized>(
+        &'txn self,
+        definition: TableDefinition<K, V>,
+    ) -> Result<ReadOnlyTable<'txn, K, V>> {
+        self.check_poisoned()?;
+        #[cfg(feature = "logging")]
+        info!("Opening table read-only: {}", definition);
+        if let Some(location) = self.open_tables.borrow().get(definition.name()) {
+            return Err(Error::TableAlreadyOpen(
+                definition.name().to_string(),
+                location,
+            ));
+        }
+        let header = self
+            .table_tree
+            .borrow()
+            .get_table::<K, V>(definition.name(), TableType::Normal)?
+            .ok_or_else(|| Error::TableDoesNotExist(definition.name().to_string()))?;
+
+        self.read_only_tables
+            .borrow_mut()
+            .entry(definition.name().to_string())
+            .or_insert((0, panic::Location::caller()))
+            .0 += 1;
+        let handle = ReadOnlyTableHandle {
+            name: definition.name().to_string(),
+            read_only_tables: &self.read_only_tables,
+        };
+
+        Ok(ReadOnlyTable::new(
+            header.get_root(),
+            self.mem,
+            Some(handle),
+        ))
+    }
+
     /// Open the given table
     ///
     /// The table will be created if it does not exist
@@ -321,6 +835,7 @@ impl<'db> WriteTransaction<'db> {
         &'txn self,
         definition: MultimapTableDefinition<K, V>,
     ) -> Result<MultimapTable<'db, 'txn, K, V>> {
+        self.check_poisoned()?;
         #[cfg(feature = "logging")]
         info!("Opening multimap table: {}", definition);
         if let Some(location) = self.open_tables.borrow().get(definition.name()) {
@@ -334,10 +849,11 @@ impl<'db> WriteTransaction<'db> {
             .borrow_mut()
             .insert(definition.name().to_string(), panic::Location::caller());
 
-        let internal_table = self
-            .table_tree
-            .borrow_mut()
-            .get_or_create_table::<K, V>(definition.name(), TableType::Multimap)?;
+        let internal_table = self.table_tree.borrow_mut().get_or_create_table::<K, V>(
+            definition.name(),
+            TableType::Multimap,
+            false,
+        )?;
 
         Ok(MultimapTable::new(
             definition.name(),
@@ -354,9 +870,153 @@ impl<'db> WriteTransaction<'db> {
         table: &mut BtreeMut<K, V>,
     ) {
         self.open_tables.borrow_mut().remove(name).unwrap();
+        // The error can't be returned from here, so the transaction can no longer be committed
+        if table.finish_appends().is_err() {
+            self.poisoned.store(true, Ordering::Release);
+        }
         self.table_tree
             .borrow_mut()
             .stage_update_table_root(name, table.get_root());
+        #[cfg(feature = "integrity_checks")]
+        self.stage_integrity_check(name, verify_tree::<K, V>);
+    }
+
+    // Queues a validation of the table, which is run when the transaction commits. Replaces any
+    // validation already queued for it
+    #[cfg(feature = "integrity_checks")]
+    pub(crate) fn stage_integrity_check(&self, name: &str, check: IntegrityCheck) {
+        self.table_tree
+            .borrow_mut()
+            .stage_integrity_check(name, check);
+    }
+
+    // Records that `key` was modified in the given table, if there are any subscribers to key
+    // changes
+    pub(crate) fn record_key_change<K: RedbKey + ?Sized>(
+        &self,
+        table: &str,
+        key: &K::RefBaseType<'_>,
+    ) {
+        if let Some(changes) = self.key_changes.borrow_mut().as_mut() {
+            changes.record(table, Some(KeyChange::hash_key::<K>(key)));
+        }
+    }
+
+    // Records that any key in the given table may have been modified
+    pub(crate) fn record_table_change(&self, table: &str) {
+        if let Some(changes) = self.key_changes.borrow_mut().as_mut() {
+            changes.record(table, None);
+        }
+    }
+
+    pub(crate) fn merge_operator(&self, table: &str) -> Option<&'db MergeOperator> {
+        self.db.merge_operator(table)
+    }
+
+    /// Applies all the operations in `batch`
+    ///
+    /// See [`WriteBatch`]
+    pub fn apply_batch(&self, batch: WriteBatch) -> Result {
+        batch.apply(self)
+    }
+
+    /// Rewrites the table `old_definition` as `new_definition`, converting each value with `f`
+    ///
+    /// This changes the value type stored for the table, so that it can be opened with
+    /// `new_definition`. Keys are copied in their serialized form, so both definitions must have
+    /// the same key type. If the names of the definitions differ, the table is also renamed.
+    ///
+    /// All entries are held in memory while the table is rewritten. Returns
+    /// [`Error::TableDoesNotExist`] if the table does not exist, and [`Error::TableTypeMismatch`]
+    /// if the key types differ, or if the table is being renamed, and a table with the new name
+    /// already exists
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use redb::*;
+    /// # use tempfile::NamedTempFile;
+    /// const OLD: TableDefinition<u64, u32> = TableDefinition::new("prices");
+    /// const NEW: TableDefinition<u64, &str> = TableDefinition::new("prices");
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// # let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    /// # let filename = tmpfile.path();
+    /// let db = Database::create(filename)?;
+    /// let write_txn = db.begin_write()?;
+    /// write_txn.open_table(OLD)?.insert(&1, &250)?;
+    /// write_txn.migrate_table(OLD, NEW, |cents| format!("${}.{:02}", cents / 100, cents % 100))?;
+    /// assert_eq!(write_txn.open_table(NEW)?.get(&1)?.unwrap(), "$2.50");
+    /// write_txn.commit()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn migrate_table<'b, K1, V1, K2, V2, NV>(
+        &self,
+        old_definition: TableDefinition<K1, V1>,
+        new_definition: TableDefinition<K2, V2>,
+        mut f: impl FnMut(V1::SelfType<'_>) -> NV,
+    ) -> Result
+    where
+        K1: RedbKey + ?Sized,
+        V1: RedbValue + ?Sized,
+        K2: RedbKey + ?Sized + 'b,
+        V2: RedbValue + ?Sized + 'b,
+        NV: std::borrow::Borrow<V2::RefBaseType<'b>>,
+    {
+        if K1::fixed_width() != K2::fixed_width() || K1::redb_type_name() != K2::redb_type_name() {
+            return Err(Error::TableTypeMismatch {
+                table: old_definition.name().to_string(),
+                expected: table_type_name(
+                    TableType::Normal,
+                    &K2::redb_type_name(),
+                    &V2::redb_type_name(),
+                ),
+                actual: table_type_name(
+                    TableType::Normal,
+                    &K1::redb_type_name(),
+                    &V1::redb_type_name(),
+                ),
+            });
+        }
+        if old_definition.name() != new_definition.name() {
+            if let Some(schema) = self.table_schema(new_definition.name())? {
+                return Err(Error::TableTypeMismatch {
+                    table: new_definition.name().to_string(),
+                    expected: table_type_name(
+                        TableType::Normal,
+                        &K2::redb_type_name(),
+                        &V2::redb_type_name(),
+                    ),
+                    actual: schema.type_name(),
+                });
+            }
+        }
+
+        let mut entries = vec![];
+        {
+            let table = self.open_table_read_only(old_definition)?;
+            let mut iter = table.iter()?;
+            while let Some((key, value)) = iter.try_next()? {
+                let new_value = f(value);
+                entries.push((
+                    K1::as_bytes(std::borrow::Borrow::borrow(&key))
+                        .as_ref()
+                        .to_vec(),
+                    V2::as_bytes(std::borrow::Borrow::borrow(&new_value))
+                        .as_ref()
+                        .to_vec(),
+                ));
+            }
+        }
+        self.delete_table(old_definition)?;
+
+        let mut table = self.open_table(new_definition)?;
+        for (key, value) in entries.iter() {
+            table.insert(&K2::from_bytes(key), &V2::from_bytes(value))?;
+        }
+
+        Ok(())
     }
 
     /// Delete the given table
@@ -366,12 +1026,22 @@ impl<'db> WriteTransaction<'db> {
         &self,
         definition: TableDefinition<K, V>,
     ) -> Result<bool> {
+        self.check_poisoned()?;
         #[cfg(feature = "logging")]
         info!("Deleting table: {}", definition);
+        if let Some((_, location)) = self.read_only_tables.borrow().get(definition.name()) {
+            return Err(Error::TableAlreadyOpen(
+                definition.name().to_string(),
+                location,
+            ));
+        }
         self.dirty.store(true, Ordering::Release);
-        self.table_tree
+        let existed = self
+            .table_tree
             .borrow_mut()
-            .delete_table::<K, V>(definition.name(), TableType::Normal)
+            .delete_table::<K, V>(definition.name(), TableType::Normal)?;
+        self.record_table_change(definition.name());
+        Ok(existed)
     }
 
     /// Delete the given table
@@ -381,12 +1051,16 @@ impl<'db> WriteTransaction<'db> {
         &self,
         definition: MultimapTableDefinition<K, V>,
     ) -> Result<bool> {
+        self.check_poisoned()?;
         #[cfg(feature = "logging")]
         info!("Deleting multimap table: {}", definition);
         self.dirty.store(true, Ordering::Release);
-        self.table_tree
+        let existed = self
+            .table_tree
             .borrow_mut()
-            .delete_table::<K, V>(definition.name(), TableType::Multimap)
+            .delete_table::<K, V>(definition.name(), TableType::Multimap)?;
+        self.record_table_change(definition.name());
+        Ok(existed)
     }
 
     /// List all the tables
@@ -405,48 +1079,158 @@ impl<'db> WriteTransaction<'db> {
             .map(|x| x.into_iter())
     }
 
+    /// List all the tables and multimap tables, along with their key and value types
+    pub fn list_table_schemas(&self) -> Result<impl Iterator<Item = TableSchema> + '_> {
+        self.table_tree
+            .borrow()
+            .list_table_schemas()
+            .map(|x| x.into_iter())
+    }
+
+    /// Returns the key and value types stored for the table with the given name, or `None` if it
+    /// does not exist
+    ///
+    /// This can be used to detect tables which were created with an older schema, and would fail
+    /// to open with [`Error::TableTypeMismatch`]
+    pub fn table_schema(&self, name: &str) -> Result<Option<TableSchema>> {
+        self.table_tree.borrow().get_table_schema(name)
+    }
+
     /// Commit the transaction
     ///
     /// All writes performed in this transaction will be visible to future transactions, and are
     /// durable as consistent with the [`Durability`] level set by [`Self::set_durability`]
-    pub fn commit(mut self) -> Result {
+    pub fn commit(self) -> Result {
+        self.commit_with_stats()?;
+        Ok(())
+    }
+
+    /// Commit the transaction, and return statistics about the commit
+    ///
+    /// This is the same as [`Self::commit`], and can be used to diagnose write amplification
+    pub fn commit_with_stats(mut self) -> Result<CommitStats> {
+        self.check_poisoned()?;
+        let start = self.db.now();
+        self.table_tree.borrow_mut().flush_table_root_updates()?;
+        self.commit_stats.table_update_time = self.db.now().duration_since(start);
+        let callbacks = self.commit_inner()?;
+        let grouped = matches!(self.durability, Durability::Grouped);
+        let db = self.db;
+        let transaction_id = self.transaction_id;
+        let mut stats = mem::take(&mut self.commit_stats);
+
+        drop(self);
+        if grouped {
+            db.sync_transaction(transaction_id)
+                .map_err(|err| Error::NotDurable(Box::new(err)))?;
+        }
+        stats.total_time = db.now().duration_since(start);
+        for callback in callbacks {
+            callback();
+        }
+
+        Ok(stats)
+    }
+
+    /// Begin committing the transaction in separate phases
+    ///
+    /// Call [`PendingCommit::write`] to make the transaction visible to future transactions, and
+    /// [`PendingCommit::sync`] to make it durable. This allows application work, or further write
+    /// transactions, to proceed between the two, and allows a single sync to persist several
+    /// transactions. Dropping the [`PendingCommit`] before it is written aborts the transaction
+    pub fn commit_begin(self) -> Result<PendingCommit<'db>> {
+        self.check_poisoned()?;
+        self.table_tree.borrow_mut().flush_table_root_updates()?;
+        Ok(PendingCommit {
+            db: self.db,
+            transaction_id: self.transaction_id,
+            transaction: Some(self),
+        })
+    }
+
+    /// Prepare the transaction to be committed, as a participant in a two-phase commit
+    ///
+    /// All writes performed in this transaction are made durable, but are not visible to other
+    /// transactions until [`PreparedTransaction::commit`] is called. Until then, no other write
+    /// transaction may begin. If the process crashes before the prepared transaction is committed
+    /// or rolled back, the outcome is decided when the database is next opened, see
+    /// [`crate::Builder::set_prepared_transaction_resolver`]
+    pub fn prepare(mut self) -> Result<PreparedTransaction<'db>> {
+        self.check_poisoned()?;
+        #[cfg(feature = "logging")]
+        info!("Preparing transaction id={:?}", self.transaction_id);
         self.table_tree.borrow_mut().flush_table_root_updates()?;
-        self.commit_inner()
+        self.db.expire_retained_snapshots();
+        self.durable_commit(false, true)?;
+
+        Ok(PreparedTransaction { transaction: self })
     }
 
-    fn commit_inner(&mut self) -> Result {
+    // Returns the commit callbacks which are ready to be called, now that the commit is durable
+    fn commit_inner(&mut self) -> Result<Vec<CommitCallback>> {
         #[cfg(feature = "logging")]
         info!(
             "Committing transaction id={:?} with durability={:?}",
             self.transaction_id, self.durability
         );
+        self.db.expire_retained_snapshots();
+        self.db.report_long_read_transactions();
         match self.durability {
-            Durability::None => self.non_durable_commit()?,
-            Durability::Eventual => self.durable_commit(true)?,
-            Durability::Immediate => self.durable_commit(false)?,
+            Durability::None | Durability::Grouped => self.non_durable_commit()?,
+            Durability::Eventual => self.durable_commit(true, false)?,
+            Durability::Immediate => self.durable_commit(false, false)?,
         }
 
+        self.finish_commit(matches!(self.durability, Durability::Immediate))
+    }
+
+    // Publishes a transaction, once it has been committed, and returns the commit callbacks
+    // which are ready to be called
+    fn finish_commit(&mut self, durable: bool) -> Result<Vec<CommitCallback>> {
         self.completed = true;
-        #[cfg(feature = "logging")]
-        info!(
+        self.db.counters().record_commit(&self.commit_stats);
+        self.db.retain_snapshot(self.transaction_id)?;
+        if self.persistent_savepoints_changed.load(Ordering::Acquire) {
+            let savepoints = Database::read_persistent_savepoints(self.mem)?;
+            self.transaction_tracker
+                .lock()
+                .unwrap()
+                .set_persistent_savepoints(savepoints);
+        }
+        if let Some(changes) = self.key_changes.get_mut().take() {
+            self.db.key_changes().publish(&changes);
+        }
+        let callbacks = mem::take(self.commit_callbacks.get_mut());
+        let ready = if durable {
+            let mut ready = self.db.take_commit_callbacks();
+            ready.extend(callbacks);
+            ready
+        } else {
+            self.db.defer_commit_callbacks(callbacks);
+            vec![]
+        };
+        #[cfg(feature = "logging")]
+        info!(
             "Finished commit of transaction id={:?}",
             self.transaction_id
         );
 
-        Ok(())
+        Ok(ready)
     }
 
     /// Abort the transaction
     ///
     /// All writes performed in this transaction will be rolled back
     pub fn abort(mut self) -> Result {
+        self.db.counters().record_abort();
         self.abort_inner()
     }
 
-    fn abort_inner(&mut self) -> Result {
+    pub(crate) fn abort_inner(&mut self) -> Result {
         #[cfg(feature = "logging")]
         info!("Aborting transaction id={:?}", self.transaction_id);
         self.table_tree.borrow_mut().clear_table_root_updates();
+        self.mem.rollback_prepared()?;
         self.mem.rollback_uncommitted_writes()?;
         self.completed = true;
         #[cfg(feature = "logging")]
@@ -454,7 +1238,8 @@ impl<'db> WriteTransaction<'db> {
         Ok(())
     }
 
-    pub(crate) fn durable_commit(&mut self, eventual: bool) -> Result {
+    // If `prepare` is true, the transaction is written durably, but is not made visible
+    pub(crate) fn durable_commit(&mut self, eventual: bool, prepare: bool) -> Result {
         let oldest_live_read = self
             .transaction_tracker
             .lock()
@@ -468,33 +1253,75 @@ impl<'db> WriteTransaction<'db> {
             self.mem.mmap_gc(oldest_live_read)?;
         }
 
+        let start = self.db.now();
+        let initial_budget = self.relocation_budget;
+        let mut compaction_budget = initial_budget;
+        if compaction_budget > 0 {
+            // Safety: all tables have been closed, since commit() takes ownership of self
+            unsafe {
+                self.table_tree
+                    .borrow_mut()
+                    .relocate_tables(&mut compaction_budget)?;
+            }
+        }
+        self.commit_stats.compaction_time = self.db.now().duration_since(start);
+        self.commit_stats.bytes_relocated = (initial_budget - compaction_budget) as u64;
+
+        let start = self.db.now();
         let root = self.table_tree.borrow_mut().flush_table_root_updates()?;
+        self.commit_stats.table_update_time += self.db.now().duration_since(start);
 
+        let start = self.db.now();
         self.process_freed_pages(oldest_live_read)?;
         self.store_freed_pages()?;
+        self.commit_stats.freed_pages_time = self.db.now().duration_since(start);
 
         let freed_root = self.freed_tree.get_root();
 
-        self.mem
-            .commit(root, freed_root, self.transaction_id, eventual, None)?;
+        self.record_allocation_stats();
+        let start = self.db.now();
+        let syncs = self.mem.get_sync_count();
+        if prepare {
+            self.mem.prepare(root, freed_root, self.transaction_id)?;
+        } else {
+            self.mem
+                .commit(root, freed_root, self.transaction_id, eventual, None)?;
+        }
+        self.commit_stats.syncs = self.mem.get_sync_count() - syncs;
+        self.commit_stats.write_time = self.db.now().duration_since(start);
         Ok(())
     }
 
     // Commit without a durability guarantee
     pub(crate) fn non_durable_commit(&mut self) -> Result {
+        let start = self.db.now();
         let root = self.table_tree.borrow_mut().flush_table_root_updates()?;
+        self.commit_stats.table_update_time += self.db.now().duration_since(start);
 
         // Store all freed pages for a future commit(), since we can't free pages during a
         // non-durable commit (it's non-durable, so could be rolled back anytime in the future)
+        let start = self.db.now();
         self.store_freed_pages()?;
+        self.commit_stats.freed_pages_time = self.db.now().duration_since(start);
 
         let freed_root = self.freed_tree.get_root();
 
+        self.record_allocation_stats();
+        let start = self.db.now();
         self.mem
             .non_durable_commit(root, freed_root, self.transaction_id)?;
+        self.commit_stats.write_time = self.db.now().duration_since(start);
         Ok(())
     }
 
+    // Must be called immediately before the commit, since committing resets the counts
+    fn record_allocation_stats(&mut self) {
+        let (allocated, freed, bytes) = self.mem.uncommitted_allocation_stats();
+        self.commit_stats.pages_allocated = allocated;
+        self.commit_stats.pages_freed = freed;
+        self.commit_stats.bytes_written = bytes;
+    }
+
     // NOTE: must be called before store_freed_pages() during commit, since this can create
     // more pages freed by the current transaction
     fn process_freed_pages(&mut self, oldest_live_read: TransactionId) -> Result {
@@ -506,15 +1333,21 @@ impl<'db> WriteTransaction<'db> {
         };
 
         let mut to_remove = vec![];
-        for entry in self.freed_tree.range(..lookup_key)? {
+        let mut iter = self.freed_tree.range(..lookup_key)?;
+        while let Some(entry) = iter.try_next()? {
             to_remove.push(FreedTableKey::from_bytes(entry.key()));
             let value = entry.value();
-            let length: usize = u64::from_le_bytes(value[..size_of::<u64>()].try_into().unwrap())
-                .try_into()
-                .unwrap();
+            let length = value
+                .get(..size_of::<u64>())
+                .and_then(|length| {
+                    usize::try_from(u64::from_le_bytes(length.try_into().unwrap())).ok()
+                })
+                .filter(|length| *length <= (value.len() - size_of::<u64>()) / 8)
+                .ok_or_else(|| Error::corrupted("Freed page list is truncated"))?;
             // 1..=length because the array is length prefixed
             for i in 1..=length {
                 let page = PageNumber::from_le_bytes(value[i * 8..(i + 1) * 8].try_into().unwrap());
+                self.mem.check_page_number(page)?;
                 // Safety: we free only pages that were marked to be freed before the oldest live transaction,
                 // therefore no one can have a reference to this page still
                 unsafe {
@@ -522,6 +1355,7 @@ impl<'db> WriteTransaction<'db> {
                 }
             }
         }
+        drop(iter);
 
         // Remove all the old transactions
         for key in to_remove {
@@ -572,7 +1406,7 @@ impl<'db> WriteTransaction<'db> {
     pub fn stats(&self) -> Result<DatabaseStats> {
         let table_tree = self.table_tree.borrow();
         let data_tree_stats = table_tree.stats()?;
-        let freed_tree_stats = self.freed_tree.stats();
+        let freed_tree_stats = self.freed_tree.stats()?;
         let total_metadata_bytes = data_tree_stats.metadata_bytes()
             + freed_tree_stats.metadata_bytes
             + freed_tree_stats.stored_leaf_bytes;
@@ -592,52 +1426,281 @@ impl<'db> WriteTransaction<'db> {
     }
 
     #[allow(dead_code)]
-    pub(crate) fn print_debug(&self) {
+    pub(crate) fn print_debug(&self) -> Result {
         // Flush any pending updates to make sure we get the latest root
-        if let Some(page) = self
-            .table_tree
-            .borrow_mut()
-            .flush_table_root_updates()
-            .unwrap()
-        {
+        if let Some(page) = self.table_tree.borrow_mut().flush_table_root_updates()? {
             eprintln!("Master tree:");
             let master_tree: Btree<&str, InternalTableDefinition> =
                 Btree::new(Some(page), self.mem);
-            master_tree.print_debug(true);
+            master_tree.print_debug(true)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Tracks a read-only handle to a table opened in a write transaction
+pub(crate) struct ReadOnlyTableHandle<'txn> {
+    name: String,
+    read_only_tables: &'txn RefCell<HashMap<String, (usize, &'static panic::Location<'static>)>>,
+}
+
+impl<'txn> Drop for ReadOnlyTableHandle<'txn> {
+    fn drop(&mut self) {
+        let mut read_only_tables = self.read_only_tables.borrow_mut();
+        let (count, _) = read_only_tables.get_mut(&self.name).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            read_only_tables.remove(&self.name);
+        }
+    }
+}
+
+/// A transaction which is in the process of being committed
+///
+/// See [`WriteTransaction::commit_begin`]
+pub struct PendingCommit<'db> {
+    db: &'db Database,
+    transaction_id: TransactionId,
+    transaction: Option<WriteTransaction<'db>>,
+}
+
+impl<'db> PendingCommit<'db> {
+    /// Write the transaction to the database, without a durability guarantee
+    ///
+    /// After this returns, the transaction is visible to future transactions, and a new write
+    /// transaction may begin
+    pub fn write(&mut self) -> Result {
+        if let Some(mut transaction) = self.transaction.take() {
+            transaction.durability = Durability::None;
+            transaction.commit_inner()?;
+        }
+        Ok(())
+    }
+
+    /// Make the transaction, and all transactions committed before it, durable
+    ///
+    /// Writes the transaction first, if [`Self::write`] has not been called. If another write
+    /// transaction is in progress, this blocks until it completes. Returns [`Error::NotDurable`]
+    /// if the transaction was written, but could not be made durable
+    pub fn sync(mut self) -> Result {
+        self.write()?;
+        self.db
+            .sync_transaction(self.transaction_id)
+            .map_err(|err| Error::NotDurable(Box::new(err)))
+    }
+}
+
+// State of a write transaction at the start of a nested transaction
+struct NestedState {
+    root: Option<(PageNumber, Checksum)>,
+    allocations: NestedAllocationState,
+    freed_pages: usize,
+    key_changes: Option<PendingKeyChanges>,
+    commit_callbacks: usize,
+}
+
+/// A transaction nested inside a [`WriteTransaction`]
+///
+/// See [`WriteTransaction::begin_nested`]. This dereferences to the enclosing
+/// [`WriteTransaction`], through which tables are opened
+pub struct NestedWriteTransaction<'txn, 'db> {
+    transaction: &'txn mut WriteTransaction<'db>,
+    state: Option<NestedState>,
+}
+
+impl<'txn, 'db> NestedWriteTransaction<'txn, 'db> {
+    /// Begins a transaction nested inside this one
+    pub fn begin_nested(&mut self) -> Result<NestedWriteTransaction<'_, 'db>> {
+        self.transaction.begin_nested()
+    }
+
+    /// Commit the nested transaction, so that its writes become part of the enclosing transaction
+    pub fn commit(mut self) -> Result {
+        self.transaction.check_poisoned()?;
+        let state = self.state.take().unwrap();
+        self.transaction.commit_nested(state)
+    }
+
+    /// Abort the nested transaction
+    ///
+    /// All writes performed in the nested transaction will be rolled back
+    pub fn abort(mut self) -> Result {
+        let state = self.state.take().unwrap();
+        self.transaction.rollback_nested(state)
+    }
+}
+
+impl<'txn, 'db> Deref for NestedWriteTransaction<'txn, 'db> {
+    type Target = WriteTransaction<'db>;
+
+    fn deref(&self) -> &Self::Target {
+        self.transaction
+    }
+}
+
+impl<'txn, 'db> Drop for NestedWriteTransaction<'txn, 'db> {
+    fn drop(&mut self) {
+        if let Some(state) = self.state.take() {
+            #[allow(unused_variables)]
+            if let Err(error) = self.transaction.rollback_nested(state) {
+                #[cfg(feature = "logging")]
+                warn!(
+                    "Failure automatically aborting nested transaction: {}",
+                    error
+                );
+            }
         }
     }
 }
 
+/// A transaction which has been prepared, but not yet committed
+///
+/// See [`WriteTransaction::prepare`]. Dropping the [`PreparedTransaction`] rolls it back
+pub struct PreparedTransaction<'db> {
+    transaction: WriteTransaction<'db>,
+}
+
+impl<'db> PreparedTransaction<'db> {
+    /// Returns the id of the prepared transaction
+    ///
+    /// This is the id passed to the resolver set with
+    /// [`crate::Builder::set_prepared_transaction_resolver`]
+    pub fn transaction_id(&self) -> u64 {
+        self.transaction.transaction_id()
+    }
+
+    /// Commit the prepared transaction, making it visible to future transactions
+    pub fn commit(mut self) -> Result {
+        #[cfg(feature = "logging")]
+        info!(
+            "Committing prepared transaction id={:?}",
+            self.transaction.transaction_id
+        );
+        self.transaction.mem.commit_prepared()?;
+        let callbacks = self.transaction.finish_commit(true)?;
+
+        drop(self);
+        for callback in callbacks {
+            callback();
+        }
+
+        Ok(())
+    }
+
+    /// Roll back the prepared transaction
+    pub fn rollback(mut self) -> Result {
+        self.transaction.db.counters().record_abort();
+        self.transaction.abort_inner()
+    }
+}
+
+// An operation on a table of a write transaction, which poisons the transaction if a panic occurs
+// before it ends
+pub(crate) struct TableOperation<'txn, 'db> {
+    transaction: &'txn WriteTransaction<'db>,
+    _allocations: AllocationScope<'db>,
+}
+
+impl<'txn, 'db> Drop for TableOperation<'txn, 'db> {
+    fn drop(&mut self) {
+        self.transaction.poison_if_panicking();
+    }
+}
+
 impl<'a> Drop for WriteTransaction<'a> {
     fn drop(&mut self) {
         *self.live_write_transaction = None;
         if !self.completed {
+            self.db.counters().record_abort();
             #[allow(unused_variables)]
             if let Err(error) = self.abort_inner() {
                 #[cfg(feature = "logging")]
                 warn!("Failure automatically aborting transaction: {}", error);
             }
         }
+        #[allow(unused_variables)]
+        if let Err(error) = self.mem.unpin() {
+            #[cfg(feature = "logging")]
+            warn!("Failure evicting cached pages: {}", error);
+        }
     }
 }
 
 /// A read-only transaction
 ///
-/// Read-only transactions may exist concurrently with writes
+/// Read-only transactions may exist concurrently with writes. They can be shared between threads,
+/// and cloned to give each thread its own handle to the same snapshot
 pub struct ReadTransaction<'a> {
     db: &'a Database,
-    tree: TableTree<'a>,
+    root: Option<(PageNumber, Checksum)>,
     transaction_id: TransactionId,
+    // Identifies this handle in Database::open_read_transactions()
+    reader_id: u64,
+    released: bool,
 }
 
 impl<'db> ReadTransaction<'db> {
-    pub(crate) fn new(db: &'db Database, transaction_id: TransactionId) -> Self {
-        let root_page = db.get_memory().get_data_root();
+    // Caller must have registered the read transaction with the tracker
+    pub(crate) fn new(
+        db: &'db Database,
+        transaction_id: TransactionId,
+        root_page: Option<(PageNumber, Checksum)>,
+    ) -> Self {
+        let reader_id = db
+            .transaction_tracker()
+            .lock()
+            .unwrap()
+            .register_reader(ReadTransactionInfo::new(transaction_id, db.now()));
+        db.get_memory().pin();
         Self {
             db,
-            tree: TableTree::new(root_page, db.get_memory(), Default::default()),
+            root: root_page,
             transaction_id,
+            reader_id,
+            released: false,
+        }
+    }
+
+    /// Returns the id of the committed transaction whose snapshot of the database this
+    /// transaction reads
+    ///
+    /// This is the [`WriteTransaction::transaction_id`] of the latest write transaction committed
+    /// before this one began, or zero for a new database. Some operations, such as
+    /// [`Database::set_write_strategy`], also commit, and are assigned their own id
+    pub fn transaction_id(&self) -> u64 {
+        self.transaction_id.0
+    }
+
+    /// Close the transaction, and release its snapshot of the database
+    ///
+    /// Pages which are only referenced by this snapshot may be reclaimed by future write
+    /// transactions. Dropping the transaction has the same effect, but any error is ignored
+    pub fn close(mut self) -> Result {
+        self.release()
+    }
+
+    // The root of the master table of this transaction's snapshot
+    pub(crate) fn get_root(&self) -> Option<(PageNumber, Checksum)> {
+        self.root
+    }
+
+    // The master table of this transaction's snapshot
+    fn tree(&self) -> TableTree<'db> {
+        TableTree::new(self.root, self.db.get_memory(), Default::default())
+    }
+
+    fn release(&mut self) -> Result {
+        if !self.released {
+            let tracker = self.db.transaction_tracker();
+            let mut tracker = tracker.lock()?;
+            tracker.deallocate_read_transaction(self.transaction_id);
+            tracker.deregister_reader(self.reader_id);
+            drop(tracker);
+            self.released = true;
+            self.db.get_memory().unpin()?;
         }
+        Ok(())
     }
 
     /// Open the given table
@@ -646,11 +1709,15 @@ impl<'db> ReadTransaction<'db> {
         definition: TableDefinition<K, V>,
     ) -> Result<ReadOnlyTable<K, V>> {
         let header = self
-            .tree
+            .tree()
             .get_table::<K, V>(definition.name(), TableType::Normal)?
             .ok_or_else(|| Error::TableDoesNotExist(definition.name().to_string()))?;
 
-        Ok(ReadOnlyTable::new(header.get_root(), self.db.get_memory()))
+        Ok(ReadOnlyTable::new(
+            header.get_root(),
+            self.db.get_memory(),
+            None,
+        ))
     }
 
     /// Open the given table
@@ -659,7 +1726,7 @@ impl<'db> ReadTransaction<'db> {
         definition: MultimapTableDefinition<K, V>,
     ) -> Result<ReadOnlyMultimapTable<K, V>> {
         let header = self
-            .tree
+            .tree()
             .get_table::<K, V>(definition.name(), TableType::Multimap)?
             .ok_or_else(|| Error::TableDoesNotExist(definition.name().to_string()))?;
 
@@ -672,7 +1739,7 @@ impl<'db> ReadTransaction<'db> {
     /// List all the tables
     // TODO: should return an iterator of &str, once GATs are available
     pub fn list_tables(&self) -> Result<impl Iterator<Item = String>> {
-        self.tree
+        self.tree()
             .list_tables(TableType::Normal)
             .map(|x| x.into_iter())
     }
@@ -680,25 +1747,406 @@ impl<'db> ReadTransaction<'db> {
     /// List all the multimap tables
     // TODO: should return an iterator of &str, once GATs are available
     pub fn list_multimap_tables(&self) -> Result<impl Iterator<Item = String>> {
-        self.tree
+        self.tree()
             .list_tables(TableType::Multimap)
             .map(|x| x.into_iter())
     }
+
+    /// List all the tables and multimap tables, along with their key and value types
+    pub fn list_table_schemas(&self) -> Result<impl Iterator<Item = TableSchema>> {
+        self.tree().list_table_schemas().map(|x| x.into_iter())
+    }
+
+    /// Returns the key and value types stored for the table with the given name, or `None` if it
+    /// does not exist
+    ///
+    /// This can be used to detect tables which were created with an older schema, and would fail
+    /// to open with [`Error::TableTypeMismatch`]
+    pub fn table_schema(&self, name: &str) -> Result<Option<TableSchema>> {
+        self.tree().get_table_schema(name)
+    }
+
+    /// Writes every entry of the table with the given name to `writer`, in key order, formatted
+    /// according to the key and value types stored with the table. See [`DataFormat`]
+    ///
+    /// Returns the number of entries written. Multimap tables can't be exported. A `str` which is
+    /// not valid UTF-8 is reported as [`Error::Corrupted`]
+    pub fn export_table(&self, name: &str, format: DataFormat, writer: impl Write) -> Result<u64> {
+        export_table(&self.tree(), self.db.get_memory(), name, format, writer)
+    }
+
+    /// Returns a copy of the page with the given number, as returned by
+    /// [`crate::PageInfo::page_number`], or `None` if it's outside the database file
+    ///
+    /// This is intended for recovery and forensic tools. The format of pages is not stable, and
+    /// may change in any release
+    ///
+    /// # Safety
+    ///
+    /// The page must not be written to while it's read. Pages which are reachable from this
+    /// transaction are never written to, but other pages may be reused by a concurrent write
+    /// transaction
+    #[cfg(feature = "raw_page_access")]
+    pub unsafe fn read_raw_page(&self, page_number: u64) -> Result<Option<Vec<u8>>> {
+        self.db
+            .get_memory()
+            .read_raw_page(PageNumber::from_le_bytes(page_number.to_le_bytes()))
+    }
+}
+
+impl<'a> Clone for ReadTransaction<'a> {
+    /// Returns another handle to the same snapshot of the database
+    ///
+    /// The snapshot is released once every handle to it has been closed or dropped
+    fn clone(&self) -> Self {
+        let tracker = self.db.transaction_tracker();
+        let mut tracker = tracker.lock().unwrap();
+        tracker.register_read_transaction(self.transaction_id);
+        let reader_id =
+            tracker.register_reader(ReadTransactionInfo::new(self.transaction_id, self.db.now()));
+        self.db.get_memory().pin();
+        Self {
+            db: self.db,
+            root: self.root,
+            transaction_id: self.transaction_id,
+            reader_id,
+            released: false,
+        }
+    }
 }
 
 impl<'a> Drop for ReadTransaction<'a> {
     fn drop(&mut self) {
-        self.db
-            .transaction_tracker()
-            .lock()
-            .unwrap()
-            .deallocate_read_transaction(self.transaction_id);
+        #[allow(unused_variables)]
+        if let Err(error) = self.release() {
+            #[cfg(feature = "logging")]
+            warn!("Failure releasing read transaction: {}", error);
+        }
+    }
+}
+
+/// A [`WriteTransaction`] which holds a reference to its [`Database`], rather than borrowing it
+///
+/// This has no lifetime parameter, so it can be stored alongside the database. It provides the
+/// methods of [`WriteTransaction`], other than those which split the transaction into another
+/// type, such as [`WriteTransaction::begin_nested`] or [`WriteTransaction::prepare`]. See
+/// [`Database::begin_write_owned`]
+pub struct OwnedWriteTransaction {
+    // Declared before db, so that it is dropped first
+    transaction: WriteTransaction<'static>,
+    db: Arc<Database>,
+}
+
+impl OwnedWriteTransaction {
+    pub(crate) fn new(db: Arc<Database>) -> Result<Self> {
+        // Safety: the transaction only references the database, which is kept alive by the Arc
+        // until after the transaction has been dropped. The transaction is private, and never
+        // moved out of this struct, or referenced outside of it. Each method below returns either
+        // owned data, or values which borrow self, so nothing which references the database can
+        // outlive the Arc
+        let db_ref: &'static Database = unsafe { &*Arc::as_ptr(&db) };
+        Ok(Self {
+            transaction: WriteTransaction::new(db_ref)?,
+            db,
+        })
+    }
+
+    /// See [`WriteTransaction::transaction_id`]
+    pub fn transaction_id(&self) -> u64 {
+        self.transaction.transaction_id()
+    }
+
+    /// See [`WriteTransaction::on_commit`]
+    pub fn on_commit(&self, callback: impl FnOnce() + Send + 'static) {
+        self.transaction.on_commit(callback);
+    }
+
+    /// See [`WriteTransaction::savepoint`]
+    pub fn savepoint(&self) -> Result<Savepoint> {
+        self.transaction.savepoint()
+    }
+
+    /// See [`WriteTransaction::persistent_savepoint`]
+    pub fn persistent_savepoint(&self) -> Result<u64> {
+        self.transaction.persistent_savepoint()
+    }
+
+    /// See [`WriteTransaction::get_persistent_savepoint`]
+    pub fn get_persistent_savepoint(&self, id: u64) -> Result<Savepoint> {
+        self.transaction.get_persistent_savepoint(id)
+    }
+
+    /// See [`WriteTransaction::delete_persistent_savepoint`]
+    pub fn delete_persistent_savepoint(&self, id: u64) -> Result<bool> {
+        self.transaction.delete_persistent_savepoint(id)
+    }
+
+    /// See [`WriteTransaction::list_persistent_savepoints`]
+    pub fn list_persistent_savepoints(&self) -> Result<impl Iterator<Item = u64>> {
+        self.transaction.list_persistent_savepoints()
+    }
+
+    /// Restore the state of the database to the given [`Savepoint`]
+    ///
+    /// See [`WriteTransaction::restore_savepoint`]
+    pub fn restore_savepoint(&mut self, savepoint: &Savepoint) -> Result {
+        self.transaction.restore_savepoint(savepoint)
+    }
+
+    /// Set the desired durability level for writes made in this transaction
+    ///
+    /// See [`WriteTransaction::set_durability`]
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.transaction.set_durability(durability);
+    }
+
+    /// See [`WriteTransaction::vacuum_on_commit`]
+    pub fn vacuum_on_commit(&mut self, max_pages: usize) {
+        self.transaction.vacuum_on_commit(max_pages);
+    }
+
+    /// See [`WriteTransaction::memory_usage`]
+    pub fn memory_usage(&self) -> u64 {
+        self.transaction.memory_usage()
+    }
+
+    /// See [`WriteTransaction::open_table`]
+    pub fn open_table<K: RedbKey + ?Sized, V: RedbValue + ?Sized>(
+        &self,
+        definition: TableDefinition<K, V>,
+    ) -> Result<Table<'static, '_, K, V>> {
+        self.transaction.open_table(definition)
+    }
+
+    /// See [`WriteTransaction::open_table_read_only`]
+    pub fn open_table_read_only<K: RedbKey + ?Sized, V: RedbValue + ?Sized>(
+        &self,
+        definition: TableDefinition<K, V>,
+    ) -> Result<ReadOnlyTable<'_, K, V>> {
+        self.transaction.open_table_read_only(definition)
+    }
+
+    /// See [`WriteTransaction::open_multimap_table`]
+    pub fn open_multimap_table<K: RedbKey + ?Sized, V: RedbKey + ?Sized>(
+        &self,
+        definition: MultimapTableDefinition<K, V>,
+    ) -> Result<MultimapTable<'static, '_, K, V>> {
+        self.transaction.open_multimap_table(definition)
+    }
+
+    /// See [`WriteTransaction::apply_batch`]
+    pub fn apply_batch(&self, batch: WriteBatch) -> Result {
+        self.transaction.apply_batch(batch)
+    }
+
+    /// See [`WriteTransaction::migrate_table`]
+    pub fn migrate_table<'b, K1, V1, K2, V2, NV>(
+        &self,
+        old_definition: TableDefinition<K1, V1>,
+        new_definition: TableDefinition<K2, V2>,
+        f: impl FnMut(V1::SelfType<'_>) -> NV,
+    ) -> Result
+    where
+        K1: RedbKey + ?Sized,
+        V1: RedbValue + ?Sized,
+        K2: RedbKey + ?Sized + 'b,
+        V2: RedbValue + ?Sized + 'b,
+        NV: std::borrow::Borrow<V2::RefBaseType<'b>>,
+    {
+        self.transaction
+            .migrate_table(old_definition, new_definition, f)
+    }
+
+    /// See [`WriteTransaction::delete_table`]
+    pub fn delete_table<K: RedbKey + ?Sized, V: RedbValue + ?Sized>(
+        &self,
+        definition: TableDefinition<K, V>,
+    ) -> Result<bool> {
+        self.transaction.delete_table(definition)
+    }
+
+    /// See [`WriteTransaction::delete_multimap_table`]
+    pub fn delete_multimap_table<K: RedbKey + ?Sized, V: RedbKey + ?Sized>(
+        &self,
+        definition: MultimapTableDefinition<K, V>,
+    ) -> Result<bool> {
+        self.transaction.delete_multimap_table(definition)
+    }
+
+    /// See [`WriteTransaction::list_tables`]
+    pub fn list_tables(&self) -> Result<impl Iterator<Item = String> + '_> {
+        self.transaction.list_tables()
+    }
+
+    /// See [`WriteTransaction::list_multimap_tables`]
+    pub fn list_multimap_tables(&self) -> Result<impl Iterator<Item = String> + '_> {
+        self.transaction.list_multimap_tables()
+    }
+
+    /// See [`WriteTransaction::list_table_schemas`]
+    pub fn list_table_schemas(&self) -> Result<impl Iterator<Item = TableSchema> + '_> {
+        self.transaction.list_table_schemas()
+    }
+
+    /// See [`WriteTransaction::table_schema`]
+    pub fn table_schema(&self, name: &str) -> Result<Option<TableSchema>> {
+        self.transaction.table_schema(name)
+    }
+
+    /// See [`WriteTransaction::stats`]
+    pub fn stats(&self) -> Result<DatabaseStats> {
+        self.transaction.stats()
+    }
+
+    /// Commit the transaction
+    ///
+    /// See [`WriteTransaction::commit`]
+    pub fn commit(self) -> Result {
+        let Self { transaction, db } = self;
+        let result = transaction.commit();
+        drop(db);
+        result
+    }
+
+    /// Commit the transaction, and return statistics about the commit
+    ///
+    /// See [`WriteTransaction::commit_with_stats`]
+    pub fn commit_with_stats(self) -> Result<CommitStats> {
+        let Self { transaction, db } = self;
+        let result = transaction.commit_with_stats();
+        drop(db);
+        result
+    }
+
+    /// Abort the transaction
+    ///
+    /// See [`WriteTransaction::abort`]
+    pub fn abort(self) -> Result {
+        let Self { transaction, db } = self;
+        let result = transaction.abort();
+        drop(db);
+        result
+    }
+}
+
+/// A [`ReadTransaction`] which holds a reference to its [`Database`], rather than borrowing it
+///
+/// This has no lifetime parameter, so it can be stored alongside the database. It provides the
+/// methods of [`ReadTransaction`]. See [`Database::begin_read_owned`]
+///
+/// Cloning it returns another `OwnedReadTransaction`, which keeps the database alive. The
+/// underlying [`ReadTransaction`] can't be reached, so it can't outlive the database:
+///
+/// ```compile_fail
+/// use redb::*;
+/// # use std::sync::Arc;
+/// # use tempfile::NamedTempFile;
+///
+/// # fn main() -> Result<(), Error> {
+/// # let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+/// # let filename = tmpfile.path();
+/// let db = Arc::new(Database::create(filename)?);
+/// let owned = db.begin_read_owned()?;
+/// let detached: ReadTransaction<'static> = (*owned).clone();
+/// # Ok(())
+/// # }
+/// ```
+pub struct OwnedReadTransaction {
+    // Declared before db, so that it is dropped first
+    transaction: ReadTransaction<'static>,
+    db: Arc<Database>,
+}
+
+impl OwnedReadTransaction {
+    pub(crate) fn new(db: Arc<Database>) -> Result<Self> {
+        // Safety: see OwnedWriteTransaction::new(). The transaction is only cloned into another
+        // OwnedReadTransaction, which holds its own Arc
+        let db_ref: &'static Database = unsafe { &*Arc::as_ptr(&db) };
+        Ok(Self {
+            transaction: db_ref.begin_read()?,
+            db,
+        })
+    }
+
+    /// See [`ReadTransaction::transaction_id`]
+    pub fn transaction_id(&self) -> u64 {
+        self.transaction.transaction_id()
+    }
+
+    /// See [`ReadTransaction::open_table`]
+    pub fn open_table<K: RedbKey + ?Sized, V: RedbValue + ?Sized>(
+        &self,
+        definition: TableDefinition<K, V>,
+    ) -> Result<ReadOnlyTable<K, V>> {
+        self.transaction.open_table(definition)
+    }
+
+    /// See [`ReadTransaction::open_multimap_table`]
+    pub fn open_multimap_table<K: RedbKey + ?Sized, V: RedbKey + ?Sized>(
+        &self,
+        definition: MultimapTableDefinition<K, V>,
+    ) -> Result<ReadOnlyMultimapTable<K, V>> {
+        self.transaction.open_multimap_table(definition)
+    }
+
+    /// See [`ReadTransaction::list_tables`]
+    pub fn list_tables(&self) -> Result<impl Iterator<Item = String>> {
+        self.transaction.list_tables()
+    }
+
+    /// See [`ReadTransaction::list_multimap_tables`]
+    pub fn list_multimap_tables(&self) -> Result<impl Iterator<Item = String>> {
+        self.transaction.list_multimap_tables()
+    }
+
+    /// See [`ReadTransaction::list_table_schemas`]
+    pub fn list_table_schemas(&self) -> Result<impl Iterator<Item = TableSchema>> {
+        self.transaction.list_table_schemas()
+    }
+
+    /// See [`ReadTransaction::table_schema`]
+    pub fn table_schema(&self, name: &str) -> Result<Option<TableSchema>> {
+        self.transaction.table_schema(name)
+    }
+
+    /// See [`ReadTransaction::export_table`]
+    pub fn export_table(&self, name: &str, format: DataFormat, writer: impl Write) -> Result<u64> {
+        self.transaction.export_table(name, format, writer)
+    }
+
+    /// See [`ReadTransaction::read_raw_page`]
+    ///
+    /// # Safety
+    ///
+    /// See [`ReadTransaction::read_raw_page`]
+    #[cfg(feature = "raw_page_access")]
+    pub unsafe fn read_raw_page(&self, page_number: u64) -> Result<Option<Vec<u8>>> {
+        self.transaction.read_raw_page(page_number)
+    }
+
+    /// Close the transaction, and release its snapshot of the database
+    ///
+    /// See [`ReadTransaction::close`]
+    pub fn close(self) -> Result {
+        let Self { transaction, db } = self;
+        let result = transaction.close();
+        drop(db);
+        result
+    }
+}
+
+impl Clone for OwnedReadTransaction {
+    fn clone(&self) -> Self {
+        Self {
+            transaction: self.transaction.clone(),
+            db: self.db.clone(),
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{Database, TableDefinition};
+    use crate::{Database, ReadableTable, TableDefinition};
     use tempfile::NamedTempFile;
 
     const X: TableDefinition<&[u8], &[u8]> = TableDefinition::new("x");
@@ -706,7 +2154,7 @@ mod test {
     #[test]
     fn transaction_id_persistence() {
         let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-        let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+        let db = Database::create(tmpfile.path()).unwrap();
         let write_txn = db.begin_write().unwrap();
         {
             let mut table = write_txn.open_table(X).unwrap();
@@ -716,8 +2164,65 @@ mod test {
         write_txn.commit().unwrap();
         drop(db);
 
-        let db2 = unsafe { Database::create(tmpfile.path()).unwrap() };
+        let db2 = Database::create(tmpfile.path()).unwrap();
         let write_txn = db2.begin_write().unwrap();
         assert!(write_txn.transaction_id > first_txn_id);
     }
+
+    #[test]
+    fn close_read_transaction() {
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        let db = Database::create(tmpfile.path()).unwrap();
+        let read_txn = db.begin_read().unwrap();
+        assert!(db
+            .transaction_tracker()
+            .lock()
+            .unwrap()
+            .oldest_live_read_transaction()
+            .is_some());
+        read_txn.close().unwrap();
+        assert!(db
+            .transaction_tracker()
+            .lock()
+            .unwrap()
+            .oldest_live_read_transaction()
+            .is_none());
+    }
+
+    #[test]
+    fn pending_commit() {
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        let db = Database::create(tmpfile.path()).unwrap();
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut table = write_txn.open_table(X).unwrap();
+            table.insert(b"hello", b"world").unwrap();
+        }
+        let mut first = write_txn.commit_begin().unwrap();
+        first.write().unwrap();
+        let first_id = first.transaction_id;
+
+        let read_txn = db.begin_read().unwrap();
+        let table = read_txn.open_table(X).unwrap();
+        assert_eq!(table.get(b"hello").unwrap().unwrap(), b"world");
+
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut table = write_txn.open_table(X).unwrap();
+            table.insert(b"hello2", b"world2").unwrap();
+        }
+        let mut second = write_txn.commit_begin().unwrap();
+        second.write().unwrap();
+        let second_id = second.transaction_id;
+        assert!(db.get_memory().get_last_durable_transaction_id().unwrap() < first_id);
+
+        second.sync().unwrap();
+        let durable_id = db.get_memory().get_last_durable_transaction_id().unwrap();
+        assert!(durable_id >= second_id);
+        first.sync().unwrap();
+        assert_eq!(
+            db.get_memory().get_last_durable_transaction_id().unwrap(),
+            durable_id
+        );
+    }
 }
@@ -1,19 +1,25 @@
 use crate::tree_store::btree_base::{
-    branch_checksum, leaf_checksum, BranchAccessor, Checksum, FreePolicy, LeafAccessor, BRANCH,
-    LEAF,
+    branch_checksum, leaf_checksum, read_page, subtree_entries, BranchAccessor, BranchMutator,
+    Checksum, EntryAccessor, FreePolicy, LeafAccessor, LeafMutator, BRANCH, LEAF,
 };
+use crate::tree_store::btree_iters::{as_slice_bound, serialize_bound};
 use crate::tree_store::btree_mutator::MutateHelper;
-use crate::tree_store::page_store::{Page, PageImpl, TransactionalMemory};
-use crate::tree_store::{AccessGuardMut, BtreeRangeIter, PageNumber};
-use crate::types::{RedbKey, RedbValue};
-use crate::{AccessGuard, Result};
+use crate::tree_store::page_store::{Arena, Page, PageImpl, TransactionalMemory};
+use crate::tree_store::{
+    dump_tree, AccessGuardMut, BtreeCursor, BtreeRangeIter, PageNumber, TreeDumpFormat,
+    TreePageIter,
+};
+use crate::types::{from_stored_bytes, PrefixKey, RedbKey, RedbValue};
+use crate::{AccessGuard, Error, Result};
 #[cfg(feature = "logging")]
 use log::trace;
 use std::borrow::Borrow;
 use std::cell::RefCell;
-use std::cmp::max;
+use std::cmp::{max, Ordering};
+use std::collections::VecDeque;
+use std::io::Write;
 use std::marker::PhantomData;
-use std::ops::{RangeBounds, RangeFull};
+use std::ops::{Bound, RangeBounds};
 use std::rc::Rc;
 
 pub(crate) struct BtreeStats {
@@ -29,10 +35,24 @@ pub(crate) struct BtreeMut<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized> {
     mem: &'a TransactionalMemory,
     root: Rc<RefCell<Option<(PageNumber, Checksum)>>>,
     freed_pages: Rc<RefCell<Vec<PageNumber>>>,
+    counted: bool,
+    // Whether keys after the last one are appended directly to the last leaf. See
+    // enable_append_path()
+    append_path: bool,
+    right_edge: RefCell<Option<RightEdge>>,
     _key_type: PhantomData<K>,
     _value_type: PhantomData<V>,
 }
 
+// The pages on the right edge of a tree, from its root to its last leaf, all of which are
+// uncommitted, so that keys can be appended to the leaf in place
+struct RightEdge {
+    pages: Vec<PageNumber>,
+    // Keys have been appended to the leaf, and the checksums and entry counts stored for it and
+    // its ancestors by the branches above them are out of date
+    pending: bool,
+}
+
 impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> BtreeMut<'a, K, V> {
     pub(crate) fn new(
         root: Option<(PageNumber, Checksum)>,
@@ -43,15 +63,175 @@ impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> BtreeMut<'a, K, V
             mem,
             root: Rc::new(RefCell::new(root)),
             freed_pages,
+            counted: false,
+            append_path: false,
+            right_edge: RefCell::new(None),
             _key_type: Default::default(),
             _value_type: Default::default(),
         }
     }
 
+    // Sets whether branches built by this tree store the number of entries in each child's
+    // subtree. This must match the branches already in the tree
+    pub(crate) fn set_counted(&mut self, counted: bool) {
+        self.counted = counted;
+    }
+
+    // Inserts keys which are greater than every key in the tree directly into the last leaf,
+    // without descending from the root, while the pages on the path to it are uncommitted. The
+    // branches on the path are updated when the tree is next used in any other way, or by
+    // finish_appends(), which must be called before get_root()
+    pub(crate) fn enable_append_path(&mut self) {
+        self.append_path = true;
+    }
+
     pub(crate) fn get_root(&self) -> Option<(PageNumber, Checksum)> {
+        debug_assert!(!matches!(
+            *self.right_edge.borrow(),
+            Some(RightEdge { pending: true, .. })
+        ));
         *(*self.root).borrow()
     }
 
+    // Updates the checksums and entry counts on the right edge of the tree, after keys were
+    // appended to its last leaf
+    pub(crate) fn finish_appends(&self) -> Result {
+        let mut right_edge = self.right_edge.borrow_mut();
+        let edge = match right_edge.as_mut() {
+            Some(edge) if edge.pending => edge,
+            _ => return Ok(()),
+        };
+        let (leaf, branches) = edge.pages.split_last().unwrap();
+        let page = self.mem.get_page(*leaf)?;
+        let mut child = *leaf;
+        let mut checksum = leaf_checksum(
+            &page,
+            K::fixed_width(),
+            V::fixed_width(),
+            self.mem.checksum_type(),
+        );
+        let mut entries =
+            LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width()).num_pairs() as u64;
+        drop(page);
+        for branch in branches.iter().rev() {
+            // Safety: the branch is uncommitted, and no references to the pages of the tree exist
+            // while appends are pending, since they were made by a mutable operation which
+            // required that, and every other operation finishes them first
+            let mut page = unsafe { self.mem.get_page_mut(*branch)? };
+            let last_child = BranchAccessor::new(&page, K::fixed_width()).count_children() - 1;
+            let mut mutator = BranchMutator::new(&mut page);
+            mutator.write_child_page(last_child, child, checksum);
+            mutator.write_child_entries(last_child, entries);
+            drop(mutator);
+            child = *branch;
+            checksum = branch_checksum(&page, K::fixed_width(), self.mem.checksum_type());
+            entries = subtree_entries(&page).unwrap_or_default();
+        }
+        *self.root.borrow_mut() = Some((child, checksum));
+        edge.pending = false;
+        Ok(())
+    }
+
+    // Finishes any appends, and forgets the right edge, before the tree is modified in some other
+    // way, or its pages are frozen
+    fn end_appends(&self) -> Result {
+        self.finish_appends()?;
+        *self.right_edge.borrow_mut() = None;
+        Ok(())
+    }
+
+    // Returns the root, for an operation which reads the tree
+    fn current_root(&self) -> Result<Option<(PageNumber, Checksum)>> {
+        self.finish_appends()?;
+        Ok(self.get_root())
+    }
+
+    // Returns the root, for an operation which modifies the tree other than by appending
+    #[allow(clippy::type_complexity)]
+    fn root_for_update(&self) -> Result<Rc<RefCell<Option<(PageNumber, Checksum)>>>> {
+        self.end_appends()?;
+        Ok(self.root.clone())
+    }
+
+    // Finds the right edge of the tree, after a key was appended to it, so that later appends can
+    // skip the descent from the root. Nothing is cached if any page on the edge is committed
+    fn find_right_edge(&self) -> Result {
+        let mut pages = vec![];
+        let mut next = self.get_root().map(|(page, _)| page);
+        while let Some(page_number) = next {
+            if !self.mem.uncommitted(page_number) {
+                return Ok(());
+            }
+            let page = self.mem.get_page(page_number)?;
+            next = if page.memory()[0] == BRANCH {
+                let accessor = BranchAccessor::new(&page, K::fixed_width());
+                accessor.child_page(accessor.count_children() - 1)
+            } else {
+                None
+            };
+            pages.push(page_number);
+        }
+        if !pages.is_empty() {
+            *self.right_edge.borrow_mut() = Some(RightEdge {
+                pages,
+                pending: false,
+            });
+        }
+        Ok(())
+    }
+
+    // Inserts the pair into the last leaf in place, if the key is greater than every key in the
+    // tree and the leaf has room for it. Returns false, and leaves the tree unchanged, otherwise
+    //
+    // Safety: caller must ensure that no uncommitted data is accessed within this tree, from other references
+    unsafe fn try_append(
+        &mut self,
+        key: &K::RefBaseType<'_>,
+        value: &V::RefBaseType<'_>,
+    ) -> Result<bool> {
+        let mut right_edge = self.right_edge.borrow_mut();
+        let edge = match right_edge.as_mut() {
+            Some(edge) => edge,
+            None => return Ok(false),
+        };
+        let key_bytes = K::as_bytes(key);
+        let value_bytes = V::as_bytes(value);
+        let (key, value) = (key_bytes.as_ref(), value_bytes.as_ref());
+        let leaf = *edge.pages.last().unwrap();
+        // The pages are frozen if a snapshot of another table was taken, in which case they're
+        // copied by the next insert, like any other committed page
+        if !self.mem.uncommitted(leaf) || self.mem.is_large_value(value.len()) {
+            return Ok(false);
+        }
+        let page = self.mem.get_page(leaf)?;
+        let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
+        let position = accessor.num_pairs();
+        let (start, end) = accessor.value_range(position - 1).unwrap();
+        if K::compare(key, accessor.last_entry().key()) != Ordering::Greater
+            || (position == 1
+                && (accessor.total_length() >= self.mem.get_page_size()
+                    || self.mem.is_large_value(end - start)))
+            || !LeafMutator::sufficient_insert_inplace_space(
+                &page,
+                position,
+                false,
+                K::fixed_width(),
+                V::fixed_width(),
+                key,
+                value,
+            )
+        {
+            return Ok(false);
+        }
+        drop(accessor);
+        drop(page);
+        let mut page = self.mem.get_page_mut(leaf)?;
+        LeafMutator::new(&mut page, K::fixed_width(), V::fixed_width())
+            .insert(position, false, key, value);
+        edge.pending = true;
+        Ok(true)
+    }
+
     // Safety: caller must ensure that no uncommitted data is accessed within this tree, from other references
     pub(crate) unsafe fn insert(
         &mut self,
@@ -65,17 +245,200 @@ impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> BtreeMut<'a, K, V
             key,
             V::as_bytes(value).as_ref().len()
         );
+        if self.try_append(key, value)? {
+            return Ok(None);
+        }
         let mut freed_pages = self.freed_pages.borrow_mut();
         let mut operation: MutateHelper<'_, '_, K, V> = MutateHelper::new(
-            self.root.clone(),
+            self.root_for_update()?,
             FreePolicy::Uncommitted,
             self.mem,
             freed_pages.as_mut(),
+            self.counted,
         );
         let (old_value, _) = operation.insert(key, value)?;
+        if self.append_path && operation.appended_key() {
+            self.find_right_edge()?;
+        }
         Ok(old_value)
     }
 
+    // Inserts a key which is greater than every key in the tree. Returns false, and leaves the
+    // tree unchanged, if it's not
+    // Safety: caller must ensure that no uncommitted data is accessed within this tree, from other references
+    pub(crate) unsafe fn append(
+        &mut self,
+        key: &K::RefBaseType<'_>,
+        value: &V::RefBaseType<'_>,
+    ) -> Result<bool> {
+        if self.try_append(key, value)? {
+            return Ok(true);
+        }
+        if let Some(last) = self.last_key_bytes()? {
+            if K::compare(&last, K::as_bytes(key).as_ref()) != Ordering::Less {
+                return Ok(false);
+            }
+        }
+        let old_value = self.insert(key, value)?;
+        debug_assert!(old_value.is_none());
+        Ok(true)
+    }
+
+    // Inserts all the given pairs. They're sorted first, so that each page is rewritten at most once
+    // Safety: caller must ensure that no uncommitted data is accessed within this tree, from other references
+    pub(crate) unsafe fn insert_batch(&mut self, pairs: &mut [(Vec<u8>, Vec<u8>)]) -> Result {
+        #[cfg(feature = "logging")]
+        trace!(
+            "Btree(root={:?}): Inserting batch of {} pairs",
+            &self.root,
+            pairs.len()
+        );
+        // This sort is stable, so the last value given for each key is kept
+        pairs.sort_by(|(a, _), (b, _)| K::compare(a, b));
+        let mut sorted: Vec<(&[u8], &[u8])> = Vec::with_capacity(pairs.len());
+        for (key, value) in pairs.iter() {
+            if let Some(last) = sorted.last_mut() {
+                if K::compare(last.0, key) == Ordering::Equal {
+                    *last = (key, value);
+                    continue;
+                }
+            }
+            sorted.push((key, value));
+        }
+        let mut freed_pages = self.freed_pages.borrow_mut();
+        let mut operation: MutateHelper<'_, '_, K, V> = MutateHelper::new(
+            self.root_for_update()?,
+            FreePolicy::Uncommitted,
+            self.mem,
+            freed_pages.as_mut(),
+            self.counted,
+        );
+        operation.insert_sorted(&sorted)
+    }
+
+    // Replaces the value of key with the result of merge_fn, which is passed the existing value
+    // Safety: caller must ensure that no uncommitted data is accessed within this tree, from other references
+    pub(crate) unsafe fn merge<F: FnOnce(Option<&[u8]>) -> Result<Vec<u8>>>(
+        &mut self,
+        key: &K::RefBaseType<'_>,
+        merge_fn: F,
+    ) -> Result {
+        #[cfg(feature = "logging")]
+        trace!("Btree(root={:?}): Merging into {:?}", &self.root, key);
+        let mut freed_pages = self.freed_pages.borrow_mut();
+        let mut operation: MutateHelper<'_, '_, K, V> = MutateHelper::new(
+            self.root_for_update()?,
+            FreePolicy::Uncommitted,
+            self.mem,
+            freed_pages.as_mut(),
+            self.counted,
+        );
+        operation.merge(key, merge_fn)?;
+        Ok(())
+    }
+
+    // Replaces the value of key with new, or removes it if new is None, if its current serialized
+    // value is equal to expected. Returns true if the value was replaced
+    // Safety: caller must ensure that no uncommitted data is accessed within this tree, from other references
+    pub(crate) unsafe fn compare_and_swap(
+        &mut self,
+        key: &K::RefBaseType<'_>,
+        expected: Option<&V::RefBaseType<'_>>,
+        new: Option<&V::RefBaseType<'_>>,
+    ) -> Result<bool> {
+        let expected = expected.map(|value| V::as_bytes(value));
+        let expected = expected.as_ref().map(|value| value.as_ref());
+        if self
+            .read_tree()?
+            .get_serialized(K::as_bytes(key).as_ref())?
+            != expected
+        {
+            return Ok(false);
+        }
+        if let Some(new) = new {
+            self.insert(key, new)?;
+        } else {
+            self.remove(key)?;
+        }
+        Ok(true)
+    }
+
+    // Appends pairs, which must be sorted, after all the existing keys
+    // Safety: caller must ensure that no uncommitted data is accessed within this tree, from other references
+    pub(crate) unsafe fn append_sorted<KB: Into<Vec<u8>>, VB: Into<Vec<u8>>>(
+        &mut self,
+        pairs: impl Iterator<Item = (KB, VB)>,
+    ) -> Result {
+        #[cfg(feature = "logging")]
+        trace!("Btree(root={:?}): Appending sorted pairs", &self.root);
+        let mut freed_pages = self.freed_pages.borrow_mut();
+        let mut operation: MutateHelper<'_, '_, K, V> = MutateHelper::new(
+            self.root_for_update()?,
+            FreePolicy::Uncommitted,
+            self.mem,
+            freed_pages.as_mut(),
+            self.counted,
+        );
+        operation.append_sorted(pairs)
+    }
+
+    // Inserts the value if key is absent, and otherwise returns the existing value
+    // Safety: caller must ensure that no uncommitted data is accessed within this tree, from other references
+    pub(crate) unsafe fn insert_if_absent(
+        &mut self,
+        key: &K::RefBaseType<'_>,
+        value: &V::RefBaseType<'_>,
+    ) -> Result<Option<AccessGuard<V>>> {
+        #[cfg(feature = "logging")]
+        trace!(
+            "Btree(root={:?}): Inserting {:?} with value of length {} if absent",
+            &self.root,
+            key,
+            V::as_bytes(value).as_ref().len()
+        );
+        let mut freed_pages = self.freed_pages.borrow_mut();
+        let mut operation: MutateHelper<'_, '_, K, V> = MutateHelper::new(
+            self.root_for_update()?,
+            FreePolicy::Uncommitted,
+            self.mem,
+            freed_pages.as_mut(),
+            self.counted,
+        );
+        let (existing, _) = operation.insert_if_absent(key, value)?;
+        Ok(existing)
+    }
+
+    // Returns the value for key, after inserting the value returned by value_fn if it was absent
+    // Safety: caller must ensure that no uncommitted data is accessed within this tree, from other references
+    pub(crate) unsafe fn get_or_insert_with<F: FnOnce() -> Vec<u8>>(
+        &mut self,
+        key: &K::RefBaseType<'_>,
+        value_fn: F,
+    ) -> Result<(AccessGuard<V>, bool)> {
+        #[cfg(feature = "logging")]
+        trace!(
+            "Btree(root={:?}): Inserting {:?} if absent",
+            &self.root,
+            key
+        );
+        let mut freed_pages = self.freed_pages.borrow_mut();
+        let mut operation: MutateHelper<'_, '_, K, V> = MutateHelper::new(
+            self.root_for_update()?,
+            FreePolicy::Uncommitted,
+            self.mem,
+            freed_pages.as_mut(),
+            self.counted,
+        );
+        match operation.insert_if_absent_with(key, value_fn)? {
+            (Some(existing), _) => Ok((existing, false)),
+            (None, Some(mut inserted)) => Ok((
+                AccessGuard::with_copied_value(inserted.as_mut(), self.mem),
+                true,
+            )),
+            (None, None) => unreachable!(),
+        }
+    }
+
     /// Reserve space to insert a key-value pair
     /// The returned reference will have length equal to value_length
     // Safety: caller must ensure that no uncommitted data is accessed within this tree, from other references
@@ -93,15 +456,17 @@ impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> BtreeMut<'a, K, V
             value_length
         );
         let mut freed_pages = self.freed_pages.borrow_mut();
-        let value = vec![0u8; value_length];
+        let value = self.mem.arena().take_bytes(value_length);
         let mut operation = MutateHelper::<K, &[u8]>::new(
-            self.root.clone(),
+            self.root_for_update()?,
             FreePolicy::Uncommitted,
             self.mem,
             freed_pages.as_mut(),
+            self.counted,
         );
         let (_, mut guard) = operation.insert(key, &value)?;
-        guard.set_root_for_drop(self.root.clone());
+        self.mem.arena().give_bytes(value);
+        guard.set_root_for_drop(self.root.clone(), K::as_bytes(key).as_ref());
         Ok(guard)
     }
 
@@ -114,15 +479,155 @@ impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> BtreeMut<'a, K, V
         trace!("Btree(root={:?}): Deleting {:?}", &self.root, key);
         let mut freed_pages = self.freed_pages.borrow_mut();
         let mut operation: MutateHelper<'_, '_, K, V> = MutateHelper::new(
-            self.root.clone(),
+            self.root_for_update()?,
             FreePolicy::Uncommitted,
             self.mem,
             freed_pages.as_mut(),
+            self.counted,
         );
         let result = operation.delete(key)?;
         Ok(result)
     }
 
+    // Removes all entries, without visiting them individually
+    // Safety: caller must ensure that no uncommitted data is accessed within this tree, from other references
+    pub(crate) unsafe fn clear(&mut self) -> Result {
+        #[cfg(feature = "logging")]
+        trace!("Btree(root={:?}): Clearing", &self.root);
+        let mut freed_pages = self.freed_pages.borrow_mut();
+        let mut operation: MutateHelper<'_, '_, K, V> = MutateHelper::new(
+            self.root_for_update()?,
+            FreePolicy::Uncommitted,
+            self.mem,
+            freed_pages.as_mut(),
+            self.counted,
+        );
+        operation.clear()
+    }
+
+    // Removes all entries with keys less than the given key, and returns the number removed
+    // Safety: caller must ensure that no uncommitted data is accessed within this tree, from other references
+    pub(crate) unsafe fn remove_before(&mut self, key: &K::RefBaseType<'_>) -> Result<u64> {
+        #[cfg(feature = "logging")]
+        trace!(
+            "Btree(root={:?}): Deleting keys before {:?}",
+            &self.root,
+            key
+        );
+        let mut freed_pages = self.freed_pages.borrow_mut();
+        let mut operation: MutateHelper<'_, '_, K, V> = MutateHelper::new(
+            self.root_for_update()?,
+            FreePolicy::Uncommitted,
+            self.mem,
+            freed_pages.as_mut(),
+            self.counted,
+        );
+        operation.delete_range(Bound::Unbounded, Bound::Excluded(K::as_bytes(key).as_ref()))
+    }
+
+    // Removes all entries in the range, and returns the number removed
+    // Safety: caller must ensure that no uncommitted data is accessed within this tree, from other references
+    pub(crate) unsafe fn remove_range<
+        'a0,
+        T: RangeBounds<KR> + 'a0,
+        KR: Borrow<K::RefBaseType<'a0>> + ?Sized + 'a0,
+    >(
+        &mut self,
+        range: T,
+    ) -> Result<u64>
+    where
+        'a: 'a0,
+    {
+        let start = serialize_bound::<K, KR>(range.start_bound());
+        let end = serialize_bound::<K, KR>(range.end_bound());
+        self.remove_range_serialized(as_slice_bound(&start), as_slice_bound(&end))
+    }
+
+    // Removes the serialized key, and returns true if it was present
+    // Safety: caller must ensure that no uncommitted data is accessed within this tree, from other references
+    pub(crate) unsafe fn remove_serialized(&mut self, key: &[u8]) -> Result<bool> {
+        Ok(self.remove_range_serialized(Bound::Included(key), Bound::Included(key))? > 0)
+    }
+
+    // Safety: caller must ensure that no uncommitted data is accessed within this tree, from other references
+    unsafe fn remove_range_serialized(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+    ) -> Result<u64> {
+        #[cfg(feature = "logging")]
+        trace!(
+            "Btree(root={:?}): Deleting range {:?}..{:?}",
+            &self.root,
+            start,
+            end
+        );
+        let mut freed_pages = self.freed_pages.borrow_mut();
+        let mut operation: MutateHelper<'_, '_, K, V> = MutateHelper::new(
+            self.root_for_update()?,
+            FreePolicy::Uncommitted,
+            self.mem,
+            freed_pages.as_mut(),
+            self.counted,
+        );
+        operation.delete_range(start, end)
+    }
+
+    // Removes all entries for which predicate returns false, and returns the number removed.
+    // The tree is traversed once, and only the leaves from which entries are removed are rewritten
+    // Safety: caller must ensure that no uncommitted data is accessed within this tree, from other references
+    pub(crate) unsafe fn retain<F>(&mut self, mut predicate: F) -> Result<u64>
+    where
+        F: for<'f> FnMut(K::SelfType<'f>, V::SelfType<'f>) -> bool,
+    {
+        let mut freed_pages = self.freed_pages.borrow_mut();
+        let mut operation: MutateHelper<'_, '_, K, V> = MutateHelper::new(
+            self.root_for_update()?,
+            FreePolicy::Uncommitted,
+            self.mem,
+            freed_pages.as_mut(),
+            self.counted,
+        );
+        operation.retain(&mut predicate)
+    }
+
+    // Removes all entries in the range, and returns copies of them
+    // Safety: caller must ensure that no uncommitted data is accessed within this tree, from other references
+    #[allow(clippy::type_complexity)]
+    pub(crate) unsafe fn drain<
+        'a0,
+        T: RangeBounds<KR> + 'a0,
+        KR: Borrow<K::RefBaseType<'a0>> + ?Sized + 'a0,
+    >(
+        &mut self,
+        range: T,
+    ) -> Result<Vec<(AccessGuard<'a, K>, AccessGuard<'a, V>)>>
+    where
+        'a: 'a0,
+    {
+        let start = serialize_bound::<K, KR>(range.start_bound());
+        let end = serialize_bound::<K, KR>(range.end_bound());
+        let mut drained = vec![];
+        let mut iter = BtreeRangeIter::<K, V>::new_serialized(
+            as_slice_bound(&start),
+            as_slice_bound(&end),
+            self.current_root()?,
+            self.mem,
+            Some(self.mem.arena()),
+        );
+        while let Some(entry) = iter.try_next()? {
+            drained.push((
+                AccessGuard::with_copied_value(entry.key(), self.mem),
+                AccessGuard::with_copied_value(entry.value(), self.mem),
+            ));
+        }
+        // The pages read by the iterator must be released before they are modified
+        drop(iter);
+        let removed = self.remove_range_serialized(as_slice_bound(&start), as_slice_bound(&end))?;
+        debug_assert_eq!(removed, u64::try_from(drained.len()).unwrap());
+        Ok(drained)
+    }
+
     // TODO: remove after verifying that this isn't needed to optimize multimap tables
     // Like remove(), but does not free uncommitted data
     #[allow(dead_code)]
@@ -132,35 +637,81 @@ impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> BtreeMut<'a, K, V
     ) -> Result<Option<AccessGuard<V>>> {
         let mut freed_pages = self.freed_pages.borrow_mut();
         let mut operation: MutateHelper<'_, '_, K, V> = MutateHelper::new(
-            self.root.clone(),
+            self.root_for_update()?,
             FreePolicy::Never,
             self.mem,
             freed_pages.as_mut(),
+            self.counted,
         );
         let result = operation.safe_delete(key)?;
         Ok(result)
     }
 
     #[allow(dead_code)]
-    pub(crate) fn print_debug(&self, include_values: bool) {
-        self.read_tree().print_debug(include_values)
+    pub(crate) fn print_debug(&self, include_values: bool) -> Result {
+        self.read_tree()?.print_debug(include_values)
     }
 
-    pub(crate) fn stats(&self) -> BtreeStats {
+    pub(crate) fn dump(&self, format: TreeDumpFormat, writer: impl Write) -> Result {
+        self.read_tree()?.dump(format, writer)
+    }
+
+    pub(crate) fn pages(&self) -> Result<TreePageIter<'a>> {
+        Ok(TreePageIter::new(
+            self.current_root()?,
+            self.mem,
+            K::fixed_width(),
+            V::fixed_width(),
+        ))
+    }
+
+    pub(crate) fn stats(&self) -> Result<BtreeStats> {
         btree_stats(
-            self.get_root().map(|(p, _)| p),
+            self.current_root()?.map(|(p, _)| p),
             self.mem,
             K::fixed_width(),
             V::fixed_width(),
         )
     }
 
-    fn read_tree(&self) -> Btree<K, V> {
-        Btree::new(self.get_root(), self.mem)
+    fn read_tree(&self) -> Result<Btree<K, V>> {
+        Ok(Btree::new(self.current_root()?, self.mem).with_arena())
     }
 
     pub(crate) fn get(&self, key: &K::RefBaseType<'_>) -> Result<Option<V::SelfType<'_>>> {
-        self.read_tree().get(key)
+        self.read_tree()?.get(key)
+    }
+
+    // Returns a copy of the serialized value of the serialized key
+    pub(crate) fn get_serialized(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .read_tree()?
+            .get_serialized(key)?
+            .map(|value| value.to_vec()))
+    }
+
+    pub(crate) fn get_multi(&self, queries: &[&[u8]]) -> Result<Vec<Option<V::SelfType<'_>>>> {
+        self.read_tree()?.get_multi(queries)
+    }
+
+    pub(crate) fn contains_key(&self, key: &K::RefBaseType<'_>) -> Result<bool> {
+        self.read_tree()?.contains_key(key)
+    }
+
+    pub(crate) fn first(&self) -> Result<Option<(K::SelfType<'_>, V::SelfType<'_>)>> {
+        self.read_tree()?.first()
+    }
+
+    pub(crate) fn last(&self) -> Result<Option<(K::SelfType<'_>, V::SelfType<'_>)>> {
+        self.read_tree()?.last()
+    }
+
+    // Returns the largest key, in its serialized form
+    pub(crate) fn last_key_bytes(&self) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .read_tree()?
+            .edge_entry(true)?
+            .map(|entry| entry.key().to_vec()))
     }
 
     pub(crate) fn range<
@@ -171,11 +722,83 @@ impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> BtreeMut<'a, K, V
         &'a0 self,
         range: T,
     ) -> Result<BtreeRangeIter<K, V>> {
-        self.read_tree().range(range)
+        self.read_tree()?.range(range)
+    }
+
+    // Returns an iterator over the current state of the tree, which is unaffected by later writes
+    // in this transaction
+    pub(crate) fn snapshot_range<
+        'a0,
+        T: RangeBounds<KR> + 'a0,
+        KR: Borrow<K::RefBaseType<'a0>> + ?Sized + 'a0,
+    >(
+        &self,
+        range: T,
+    ) -> Result<BtreeRangeIter<'a, K, V>>
+    where
+        'a: 'a0,
+    {
+        // Later writes must copy the pages of the snapshot, rather than modifying them in place.
+        // The copied pages are only freed once the transaction commits
+        self.end_appends()?;
+        self.mem.freeze_uncommitted();
+        Ok(BtreeRangeIter::new(
+            range,
+            self.get_root(),
+            self.mem,
+            Some(self.mem.arena()),
+        ))
+    }
+
+    pub(crate) fn range_prefix(&self, prefix: &K::RefBaseType<'_>) -> Result<BtreeRangeIter<K, V>>
+    where
+        K: PrefixKey,
+    {
+        self.read_tree()?.range_prefix(prefix)
+    }
+
+    pub(crate) fn cursor(&self) -> Result<BtreeCursor<K, V>> {
+        Ok(self.read_tree()?.cursor())
+    }
+
+    pub(crate) fn nth(&self, index: usize) -> Result<Option<(K::SelfType<'_>, V::SelfType<'_>)>> {
+        self.read_tree()?.nth(index)
+    }
+
+    pub(crate) fn rank(&self, key: &K::RefBaseType<'_>) -> Result<usize> {
+        self.read_tree()?.rank(key)
     }
 
     pub(crate) fn len(&self) -> Result<usize> {
-        self.read_tree().len()
+        self.read_tree()?.len()
+    }
+
+    pub(crate) fn count_range<
+        'a0,
+        T: RangeBounds<KR> + 'a0,
+        KR: Borrow<K::RefBaseType<'a0>> + ?Sized + 'a0,
+    >(
+        &'a0 self,
+        range: T,
+    ) -> Result<usize> {
+        self.read_tree()?.count_range(range)
+    }
+
+    // Moves pages of this tree to lower addresses, copying at most budget bytes
+    // Safety: caller must ensure that no references to uncommitted pages in this tree exist
+    pub(crate) unsafe fn relocate(&mut self, budget: &mut usize) -> Result {
+        self.end_appends()?;
+        if let Some(root) = self.get_root() {
+            let new_root = relocate_tree(
+                root,
+                K::fixed_width(),
+                self.mem,
+                &mut self.freed_pages.borrow_mut(),
+                budget,
+            )?;
+            *self.root.borrow_mut() = Some(new_root);
+        }
+        Ok(())
     }
 }
 
@@ -201,52 +824,102 @@ impl<'a> RawBtree<'a> {
         }
     }
 
-    pub(crate) fn verify_checksum(&self) -> bool {
+    pub(crate) fn verify_checksum(&self) -> Result<bool> {
         if let Some((root, checksum)) = self.root {
             self.verify_checksum_helper(root, checksum)
         } else {
-            true
+            Ok(true)
+        }
+    }
+
+    fn verify_checksum_helper(
+        &self,
+        page_number: PageNumber,
+        expected_checksum: Checksum,
+    ) -> Result<bool> {
+        let page = self.mem.get_page(page_number)?;
+        let node_mem = page.memory();
+        match node_mem[0] {
+            LEAF => Ok(expected_checksum
+                == leaf_checksum(
+                    &page,
+                    self.fixed_key_size,
+                    self.fixed_value_size,
+                    self.mem.checksum_type(),
+                )),
+            BRANCH => {
+                if expected_checksum
+                    != branch_checksum(&page, self.fixed_key_size, self.mem.checksum_type())
+                {
+                    return Ok(false);
+                }
+                let accessor = BranchAccessor::new(&page, self.fixed_key_size);
+                for i in 0..accessor.count_children() {
+                    if !self.verify_checksum_helper(
+                        accessor.child_page(i).unwrap(),
+                        accessor.child_checksum(i).unwrap(),
+                    )? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            // The page was not completely written before a crash
+            _ => Ok(false),
+        }
+    }
+
+    // Calls visitor with each key and value in the tree, in order
+    pub(crate) fn for_each_entry(&self, visitor: &mut dyn FnMut(&[u8], &[u8]) -> Result) -> Result {
+        if let Some((root, checksum)) = self.root {
+            self.for_each_entry_helper(root, checksum, visitor)?;
         }
+        Ok(())
     }
 
-    fn verify_checksum_helper(&self, page_number: PageNumber, expected_checksum: Checksum) -> bool {
-        let page = self.mem.get_page(page_number);
-        let node_mem = page.memory();
-        match node_mem[0] {
+    fn for_each_entry_helper(
+        &self,
+        page_number: PageNumber,
+        checksum: Checksum,
+        visitor: &mut dyn FnMut(&[u8], &[u8]) -> Result,
+    ) -> Result {
+        let page = read_page(
+            self.mem,
+            page_number,
+            checksum,
+            self.fixed_key_size,
+            self.fixed_value_size,
+        )?;
+        match page.memory()[0] {
             LEAF => {
-                expected_checksum
-                    == leaf_checksum(
-                        &page,
-                        self.fixed_key_size,
-                        self.fixed_value_size,
-                        self.mem.checksum_type(),
-                    )
+                let accessor =
+                    LeafAccessor::new(page.memory(), self.fixed_key_size, self.fixed_value_size);
+                for i in 0..accessor.num_pairs() {
+                    let entry = accessor.entry(i).unwrap();
+                    visitor(entry.key(), entry.value())?;
+                }
             }
             BRANCH => {
-                if expected_checksum
-                    != branch_checksum(&page, self.fixed_key_size, self.mem.checksum_type())
-                {
-                    return false;
-                }
                 let accessor = BranchAccessor::new(&page, self.fixed_key_size);
                 for i in 0..accessor.count_children() {
-                    if !self.verify_checksum_helper(
+                    self.for_each_entry_helper(
                         accessor.child_page(i).unwrap(),
                         accessor.child_checksum(i).unwrap(),
-                    ) {
-                        return false;
-                    }
+                        visitor,
+                    )?;
                 }
-                true
             }
             _ => unreachable!(),
         }
+        Ok(())
     }
 }
 
 pub(crate) struct Btree<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized> {
     mem: &'a TransactionalMemory,
     root: Option<(PageNumber, Checksum)>,
+    // Set for trees read within the write transaction, whose iterators can reuse its allocations
+    arena: Option<&'a Arena>,
     _key_type: PhantomData<K>,
     _value_type: PhantomData<V>,
 }
@@ -256,34 +929,366 @@ impl<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized> Btree<'a, K, V> {
         Self {
             mem,
             root,
+            arena: None,
             _key_type: Default::default(),
             _value_type: Default::default(),
         }
     }
 
+    // Must only be called from the write transaction, which owns the arena
+    pub(crate) fn with_arena(mut self) -> Self {
+        self.arena = Some(self.mem.arena());
+        self
+    }
+
+    // Reads a page of this tree, verifying its checksum if the database verifies every read
+    fn read_page(&self, page_number: PageNumber, checksum: Checksum) -> Result<PageImpl<'a>> {
+        read_page(
+            self.mem,
+            page_number,
+            checksum,
+            K::fixed_width(),
+            V::fixed_width(),
+        )
+    }
+
     pub(crate) fn get(&self, key: &K::RefBaseType<'_>) -> Result<Option<V::SelfType<'a>>> {
-        if let Some((p, _)) = self.root {
-            let root_page = self.mem.get_page(p);
-            return Ok(self.get_helper(root_page, K::as_bytes(key).as_ref()));
+        if let Some((p, checksum)) = self.root {
+            let root_page = self.read_page(p, checksum)?;
+            self.get_helper(root_page, K::as_bytes(key).as_ref())
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Returns the serialized value of the serialized key
+    pub(crate) fn get_serialized(&self, key: &[u8]) -> Result<Option<&'a [u8]>> {
+        let root = if let Some(root) = self.root {
+            root
+        } else {
+            return Ok(None);
+        };
+        let (leaf, _) = self.find_leaf(root, key)?;
+        let accessor = LeafAccessor::new(leaf, K::fixed_width(), V::fixed_width());
+        Ok(accessor.find_key::<K>(key).map(|index| {
+            let (start, end) = accessor.value_range(index).unwrap();
+            &leaf[start..end]
+        }))
+    }
+
+    // Looks up the serialized keys in queries, and returns their values in the same order
+    pub(crate) fn get_multi(&self, queries: &[&[u8]]) -> Result<Vec<Option<V::SelfType<'a>>>> {
+        let mut results: Vec<Option<V::SelfType<'a>>> = queries.iter().map(|_| None).collect();
+        let root = if let Some(root) = self.root {
+            root
+        } else {
+            return Ok(results);
+        };
+        // Probe in sorted order, so that consecutive keys in the same leaf only descend the tree once
+        let mut order: Vec<usize> = (0..queries.len()).collect();
+        order.sort_by(|a, b| K::compare(queries[*a], queries[*b]));
+
+        let mut current_leaf: Option<(&'a [u8], Option<Vec<u8>>)> = None;
+        for i in order {
+            let query = queries[i];
+            let in_current_leaf = match &current_leaf {
+                Some((_, Some(upper))) => K::compare(query, upper) != Ordering::Greater,
+                Some((_, None)) => true,
+                None => false,
+            };
+            if !in_current_leaf {
+                current_leaf = Some(self.find_leaf(root, query)?);
+            }
+            let (leaf, _) = current_leaf.as_ref().unwrap();
+            let accessor = LeafAccessor::new(leaf, K::fixed_width(), V::fixed_width());
+            if let Some(entry_index) = accessor.find_key::<K>(query) {
+                let (start, end) = accessor.value_range(entry_index).unwrap();
+                results[i] = Some(from_stored_bytes::<V>(&leaf[start..end])?);
+            }
+        }
+
+        Ok(results)
+    }
+
+    // Returns the leaf which may contain query, and the largest key which may be stored in it,
+    // or None if it is the last leaf
+    fn find_leaf(
+        &self,
+        root: (PageNumber, Checksum),
+        query: &[u8],
+    ) -> Result<(&'a [u8], Option<Vec<u8>>)> {
+        let mut page = self.read_page(root.0, root.1)?;
+        let mut upper = None;
+        loop {
+            match page.memory()[0] {
+                LEAF => {
+                    return Ok((page.into_memory(), upper));
+                }
+                BRANCH => {
+                    let accessor = BranchAccessor::new(&page, K::fixed_width());
+                    let (child_index, child_page) = accessor.child_for_key::<K>(query);
+                    if child_index < accessor.count_children() - 1 {
+                        upper = Some(accessor.key(child_index).unwrap().to_vec());
+                    }
+                    let child_checksum = accessor.child_checksum(child_index).unwrap();
+                    page = self.read_page(child_page, child_checksum)?;
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    pub(crate) fn contains_key(&self, key: &K::RefBaseType<'_>) -> Result<bool> {
+        if let Some((p, checksum)) = self.root {
+            let query = K::as_bytes(key);
+            let mut page = self.read_page(p, checksum)?;
+            loop {
+                let node_mem = page.memory();
+                match node_mem[0] {
+                    LEAF => {
+                        let accessor =
+                            LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
+                        return Ok(accessor.find_key::<K>(query.as_ref()).is_some());
+                    }
+                    BRANCH => {
+                        let accessor = BranchAccessor::new(&page, K::fixed_width());
+                        let (child_index, child_page) = accessor.child_for_key::<K>(query.as_ref());
+                        let child_checksum = accessor.child_checksum(child_index).unwrap();
+                        page = self.read_page(child_page, child_checksum)?;
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub(crate) fn first(&self) -> Result<Option<(K::SelfType<'a>, V::SelfType<'a>)>> {
+        self.edge_entry(false)?
+            .map(|entry| entry.deserialize::<K, V>())
+            .transpose()
+    }
+
+    pub(crate) fn last(&self) -> Result<Option<(K::SelfType<'a>, V::SelfType<'a>)>> {
+        self.edge_entry(true)?
+            .map(|entry| entry.deserialize::<K, V>())
+            .transpose()
+    }
+
+    // Returns the entry at position index, in key order. Whole leaves before the entry are skipped
+    // without reading their entries, but every page before it must still be visited
+    pub(crate) fn nth(&self, index: usize) -> Result<Option<(K::SelfType<'a>, V::SelfType<'a>)>> {
+        if let Some((p, checksum)) = self.root {
+            let mut remaining = index;
+            self.nth_helper(self.read_page(p, checksum)?, &mut remaining)?
+                .map(|entry| entry.deserialize::<K, V>())
+                .transpose()
         } else {
             Ok(None)
         }
     }
 
+    fn nth_helper(
+        &self,
+        page: PageImpl<'a>,
+        remaining: &mut usize,
+    ) -> Result<Option<EntryAccessor<'a>>> {
+        match page.memory()[0] {
+            LEAF => {
+                let accessor =
+                    LeafAccessor::new(page.into_memory(), K::fixed_width(), V::fixed_width());
+                if *remaining < accessor.num_pairs() {
+                    Ok(accessor.entry(*remaining))
+                } else {
+                    *remaining -= accessor.num_pairs();
+                    Ok(None)
+                }
+            }
+            BRANCH => {
+                let accessor = BranchAccessor::new(&page, K::fixed_width());
+                for i in 0..accessor.count_children() {
+                    if let Some(entries) = accessor.child_entries(i) {
+                        let entries = usize::try_from(entries).unwrap();
+                        if *remaining >= entries {
+                            // Skip the whole subtree
+                            *remaining -= entries;
+                            continue;
+                        }
+                    }
+                    let child = self.read_page(
+                        accessor.child_page(i).unwrap(),
+                        accessor.child_checksum(i).unwrap(),
+                    )?;
+                    if let Some(entry) = self.nth_helper(child, remaining)? {
+                        return Ok(Some(entry));
+                    }
+                }
+                Ok(None)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Returns the number of keys less than the given key
+    pub(crate) fn rank(&self, key: &K::RefBaseType<'_>) -> Result<usize> {
+        self.rank_serialized(K::as_bytes(key).as_ref(), false)
+    }
+
+    // Returns the number of keys less than query, or less than or equal to it if inclusive is true
+    fn rank_serialized(&self, query: &[u8], inclusive: bool) -> Result<usize> {
+        let (mut page_number, mut checksum) = if let Some(root) = self.root {
+            root
+        } else {
+            return Ok(0);
+        };
+        let mut rank = 0;
+        loop {
+            let page = self.read_page(page_number, checksum)?;
+            match page.memory()[0] {
+                LEAF => {
+                    let accessor =
+                        LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
+                    let (position, found) = accessor.position::<K>(query);
+                    if inclusive && found {
+                        return Ok(rank + position + 1);
+                    }
+                    return Ok(rank + position);
+                }
+                BRANCH => {
+                    let accessor = BranchAccessor::new(&page, K::fixed_width());
+                    let (child_index, child_page) = accessor.child_for_key::<K>(query);
+                    for i in 0..child_index {
+                        rank += match accessor.child_entries(i) {
+                            Some(entries) => usize::try_from(entries).unwrap(),
+                            None => self.count_entries(
+                                accessor.child_page(i).unwrap(),
+                                accessor.child_checksum(i).unwrap(),
+                            )?,
+                        };
+                    }
+                    page_number = child_page;
+                    checksum = accessor.child_checksum(child_index).unwrap();
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    // Returns the number of keys in the given range
+    pub(crate) fn count_range<
+        'a0,
+        T: RangeBounds<KR> + 'a0,
+        KR: Borrow<K::RefBaseType<'a0>> + ?Sized + 'a0,
+    >(
+        &self,
+        range: T,
+    ) -> Result<usize>
+    where
+        K: 'a0,
+    {
+        let start = serialize_bound::<K, KR>(range.start_bound());
+        let end = serialize_bound::<K, KR>(range.end_bound());
+        self.count_range_serialized(as_slice_bound(&start), as_slice_bound(&end))
+    }
+
+    fn count_range_serialized(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Result<usize> {
+        let lower = match start {
+            Bound::Included(key) => self.rank_serialized(key, false)?,
+            Bound::Excluded(key) => self.rank_serialized(key, true)?,
+            Bound::Unbounded => 0,
+        };
+        let upper = match end {
+            Bound::Included(key) => self.rank_serialized(key, true)?,
+            Bound::Excluded(key) => self.rank_serialized(key, false)?,
+            Bound::Unbounded => self.len()?,
+        };
+        Ok(upper.saturating_sub(lower))
+    }
+
+    // Counts the entries in the subtree, by reading the header of each leaf, unless the branches
+    // store the counts of their children
+    fn count_entries(&self, page_number: PageNumber, checksum: Checksum) -> Result<usize> {
+        let page = self.read_page(page_number, checksum)?;
+        match page.memory()[0] {
+            LEAF => Ok(
+                LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width()).num_pairs(),
+            ),
+            BRANCH => {
+                let accessor = BranchAccessor::new(&page, K::fixed_width());
+                if let Some(entries) = accessor.total_entries() {
+                    return Ok(usize::try_from(entries).unwrap());
+                }
+                (0..accessor.count_children())
+                    .map(|i| {
+                        self.count_entries(
+                            accessor.child_page(i).unwrap(),
+                            accessor.child_checksum(i).unwrap(),
+                        )
+                    })
+                    .sum()
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Returns the smallest entry, or the largest if `last` is true, by following the leftmost or
+    // rightmost child of each branch
+    fn edge_entry(&self, last: bool) -> Result<Option<EntryAccessor<'a>>> {
+        let (p, checksum) = if let Some(root) = self.root {
+            root
+        } else {
+            return Ok(None);
+        };
+        let mut page = self.read_page(p, checksum)?;
+        loop {
+            match page.memory()[0] {
+                LEAF => {
+                    let accessor =
+                        LeafAccessor::new(page.into_memory(), K::fixed_width(), V::fixed_width());
+                    return Ok(if last {
+                        Some(accessor.last_entry())
+                    } else {
+                        accessor.entry(0)
+                    });
+                }
+                BRANCH => {
+                    let accessor = BranchAccessor::new(&page, K::fixed_width());
+                    let child = if last {
+                        accessor.count_children() - 1
+                    } else {
+                        0
+                    };
+                    let child_page = accessor.child_page(child).unwrap();
+                    let child_checksum = accessor.child_checksum(child).unwrap();
+                    page = self.read_page(child_page, child_checksum)?;
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
     // Returns the value for the queried key, if present
-    fn get_helper(&self, page: PageImpl<'a>, query: &[u8]) -> Option<V::SelfType<'a>> {
+    fn get_helper(&self, page: PageImpl<'a>, query: &[u8]) -> Result<Option<V::SelfType<'a>>> {
         let node_mem = page.memory();
         match node_mem[0] {
             LEAF => {
                 let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
-                let entry_index = accessor.find_key::<K>(query)?;
+                let entry_index = if let Some(index) = accessor.find_key::<K>(query) {
+                    index
+                } else {
+                    return Ok(None);
+                };
                 let (start, end) = accessor.value_range(entry_index).unwrap();
-                Some(V::from_bytes(&page.into_memory()[start..end]))
+                Ok(Some(from_stored_bytes::<V>(
+                    &page.into_memory()[start..end],
+                )?))
             }
             BRANCH => {
                 let accessor = BranchAccessor::new(&page, K::fixed_width());
-                let (_, child_page) = accessor.child_for_key::<K>(query);
-                self.get_helper(self.mem.get_page(child_page), query)
+                let (child_index, child_page) = accessor.child_for_key::<K>(query);
+                let child_checksum = accessor.child_checksum(child_index).unwrap();
+                self.get_helper(self.read_page(child_page, child_checksum)?, query)
             }
             _ => unreachable!(),
         }
@@ -300,30 +1305,351 @@ impl<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized> Btree<'a, K, V> {
     where
         'a: 'a0,
     {
-        Ok(BtreeRangeIter::new(
-            range,
-            self.root.map(|(p, _)| p),
+        Ok(BtreeRangeIter::new(range, self.root, self.mem, self.arena))
+    }
+
+    pub(crate) fn cursor(&self) -> BtreeCursor<'a, K, V> {
+        BtreeCursor::new(self.root, self.mem, self.arena)
+    }
+
+    pub(crate) fn range_prefix(
+        &self,
+        prefix: &K::RefBaseType<'_>,
+    ) -> Result<BtreeRangeIter<'a, K, V>>
+    where
+        K: PrefixKey,
+    {
+        let start = K::as_bytes(prefix);
+        let end = K::prefix_successor(start.as_ref());
+        let end_bound = match &end {
+            Some(key) => Bound::Excluded(key.as_slice()),
+            None => Bound::Unbounded,
+        };
+        Ok(BtreeRangeIter::new_serialized(
+            Bound::Included(start.as_ref()),
+            end_bound,
+            self.root,
             self.mem,
+            self.arena,
         ))
     }
 
     pub(crate) fn len(&self) -> Result<usize> {
-        let mut iter: BtreeRangeIter<K, V> = BtreeRangeIter::new::<RangeFull, K::RefBaseType<'_>>(
-            ..,
-            self.root.map(|(p, _)| p),
-            self.mem,
-        );
-        let mut count = 0;
-        while iter.next().is_some() {
-            count += 1;
+        match self.root {
+            Some((p, checksum)) => self.count_entries(p, checksum),
+            None => Ok(0),
+        }
+    }
+
+    // Counts the entries in the subtree by reading the header of each leaf, ignoring any counts
+    // stored in the branches
+    #[allow(dead_code)]
+    #[cfg(feature = "integrity_checks")]
+    fn count_entries_slow(&self, page_number: PageNumber) -> Result<usize> {
+        let page = self.mem.get_page(page_number)?;
+        match page.memory()[0] {
+            LEAF => Ok(
+                LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width()).num_pairs(),
+            ),
+            BRANCH => {
+                let accessor = BranchAccessor::new(&page, K::fixed_width());
+                (0..accessor.count_children())
+                    .map(|i| self.count_entries_slow(accessor.child_page(i).unwrap()))
+                    .sum()
+            }
+            _ => unreachable!(),
         }
-        Ok(count)
     }
 
     #[allow(dead_code)]
-    pub(crate) fn print_debug(&self, include_values: bool) {
+    #[cfg(feature = "integrity_checks")]
+    fn verify_integrity(&self) -> Result {
+        if let Some((root, checksum)) = self.root {
+            let mut leaf_depth = None;
+            self.verify_integrity_helper(root, checksum, None, None, 0, &mut leaf_depth)?;
+        }
+        Ok(())
+    }
+
+    // Keys in the subtree must be greater than lower, and less than or equal to upper
+    #[cfg(feature = "integrity_checks")]
+    fn verify_integrity_helper(
+        &self,
+        page_number: PageNumber,
+        expected_checksum: Checksum,
+        lower: Option<&[u8]>,
+        upper: Option<&[u8]>,
+        depth: usize,
+        leaf_depth: &mut Option<usize>,
+    ) -> Result {
+        let page = self.mem.get_page(page_number)?;
+        match page.memory()[0] {
+            LEAF => {
+                let expected_depth = *leaf_depth.get_or_insert(depth);
+                if depth != expected_depth {
+                    return Err(Error::corrupted_page(
+                        page_number,
+                        format!(
+                            "leaf page {:?} is at depth {}, but other leaves are at depth {}",
+                            page_number, depth, expected_depth
+                        ),
+                    ));
+                }
+                let checksum = leaf_checksum(
+                    &page,
+                    K::fixed_width(),
+                    V::fixed_width(),
+                    self.mem.checksum_type(),
+                );
+                if checksum != expected_checksum {
+                    return Err(Error::corrupted_page(
+                        page_number,
+                        format!(
+                            "leaf page {:?} has checksum {}, but its parent expects {}",
+                            page_number, checksum, expected_checksum
+                        ),
+                    ));
+                }
+                let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
+                if accessor.num_pairs() == 0 {
+                    return Err(Error::corrupted_page(
+                        page_number,
+                        format!("leaf page {:?} is empty", page_number),
+                    ));
+                }
+                let keys: Vec<&[u8]> = (0..accessor.num_pairs())
+                    .map(|i| accessor.entry(i).unwrap().key())
+                    .collect();
+                Self::verify_keys(page_number, &keys, lower, upper)
+            }
+            BRANCH => {
+                let checksum = branch_checksum(&page, K::fixed_width(), self.mem.checksum_type());
+                if checksum != expected_checksum {
+                    return Err(Error::corrupted_page(
+                        page_number,
+                        format!(
+                            "branch page {:?} has checksum {}, but its parent expects {}",
+                            page_number, checksum, expected_checksum
+                        ),
+                    ));
+                }
+                let accessor = BranchAccessor::new(&page, K::fixed_width());
+                if accessor.count_children() < 2 {
+                    return Err(Error::corrupted_page(
+                        page_number,
+                        format!(
+                            "branch page {:?} has {} children",
+                            page_number,
+                            accessor.count_children()
+                        ),
+                    ));
+                }
+                let keys: Vec<&[u8]> = (0..(accessor.count_children() - 1))
+                    .map(|i| accessor.key(i).unwrap())
+                    .collect();
+                Self::verify_keys(page_number, &keys, lower, upper)?;
+                for i in 0..accessor.count_children() {
+                    let child_lower = if i == 0 { lower } else { Some(keys[i - 1]) };
+                    let child_upper = if i < keys.len() { Some(keys[i]) } else { upper };
+                    self.verify_integrity_helper(
+                        accessor.child_page(i).unwrap(),
+                        accessor.child_checksum(i).unwrap(),
+                        child_lower,
+                        child_upper,
+                        depth + 1,
+                        leaf_depth,
+                    )?;
+                    if let Some(entries) = accessor.child_entries(i) {
+                        let counted = self.count_entries_slow(accessor.child_page(i).unwrap())?;
+                        if usize::try_from(entries).unwrap() != counted {
+                            return Err(Error::corrupted_page(
+                                page_number,
+                                format!(
+                                "branch page {:?} records {} entries in child {}, but it has {}",
+                                page_number, entries, i, counted
+                            ),
+                            ));
+                        }
+                    }
+                }
+                Ok(())
+            }
+            page_type => Err(Error::corrupted_page(
+                page_number,
+                format!("page {:?} has invalid type {}", page_number, page_type),
+            )),
+        }
+    }
+
+    // Checks that keys are strictly increasing, and within the bounds given by the parent page
+    #[cfg(feature = "integrity_checks")]
+    fn verify_keys(
+        page_number: PageNumber,
+        keys: &[&[u8]],
+        lower: Option<&[u8]>,
+        upper: Option<&[u8]>,
+    ) -> Result {
+        for (i, key) in keys.iter().enumerate() {
+            if i > 0 && K::compare(keys[i - 1], key) != Ordering::Less {
+                return Err(Error::corrupted_page(
+                    page_number,
+                    format!(
+                        "keys {:?} and {:?} at positions {} and {} of page {:?} are out of order",
+                        K::from_bytes(keys[i - 1]),
+                        K::from_bytes(key),
+                        i - 1,
+                        i,
+                        page_number
+                    ),
+                ));
+            }
+            if let Some(lower) = lower {
+                if K::compare(key, lower) != Ordering::Greater {
+                    return Err(Error::corrupted_page(page_number, format!(
+                        "key {:?} in page {:?} is not greater than {:?}, the separator key before it in the parent page",
+                        K::from_bytes(key),
+                        page_number,
+                        K::from_bytes(lower)
+                    )));
+                }
+            }
+            if let Some(upper) = upper {
+                if K::compare(key, upper) == Ordering::Greater {
+                    return Err(Error::corrupted_page(page_number, format!(
+                        "key {:?} in page {:?} is greater than {:?}, the separator key after it in the parent page",
+                        K::from_bytes(key),
+                        page_number,
+                        K::from_bytes(upper)
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Calls f with each key whose value differs between this tree and base, along with its value
+    // in this tree, or None if it's absent. Subtrees which both trees share are skipped without
+    // being read
+    pub(crate) fn diff(
+        &self,
+        base: &Self,
+        mut f: impl FnMut(&[u8], Option<&[u8]>) -> Result,
+    ) -> Result {
+        if !std::ptr::eq(self.mem, base.mem) {
+            return Err(Error::DatabaseMismatch);
+        }
+        let mut items = self.diff_items();
+        let mut base_items = base.diff_items();
+        loop {
+            let step = match (items.front(), base_items.front()) {
+                (None, None) => return Ok(()),
+                (
+                    Some(DiffItem::Subtree(page, upper)),
+                    Some(DiffItem::Subtree(base_page, base_upper)),
+                ) => {
+                    if page == base_page {
+                        DiffStep::PopBoth
+                    } else if compare_upper_bounds::<K>(upper, base_upper) == Ordering::Less {
+                        // Expand the subtree which extends further, since the other may be shared
+                        // with one of its descendants
+                        DiffStep::ExpandBase
+                    } else {
+                        DiffStep::Expand
+                    }
+                }
+                (Some(DiffItem::Subtree(..)), _) => DiffStep::Expand,
+                (_, Some(DiffItem::Subtree(..))) => DiffStep::ExpandBase,
+                (
+                    Some(DiffItem::Entry(key, value)),
+                    Some(DiffItem::Entry(base_key, base_value)),
+                ) => match K::compare(key, base_key) {
+                    Ordering::Less => {
+                        f(key, Some(value))?;
+                        DiffStep::Pop
+                    }
+                    Ordering::Greater => {
+                        f(base_key, None)?;
+                        DiffStep::PopBase
+                    }
+                    Ordering::Equal => {
+                        if value != base_value {
+                            f(key, Some(value))?;
+                        }
+                        DiffStep::PopBoth
+                    }
+                },
+                (Some(DiffItem::Entry(key, value)), None) => {
+                    f(key, Some(value))?;
+                    DiffStep::Pop
+                }
+                (None, Some(DiffItem::Entry(base_key, _))) => {
+                    f(base_key, None)?;
+                    DiffStep::PopBase
+                }
+            };
+            match step {
+                DiffStep::Pop => {
+                    items.pop_front();
+                }
+                DiffStep::PopBase => {
+                    base_items.pop_front();
+                }
+                DiffStep::PopBoth => {
+                    items.pop_front();
+                    base_items.pop_front();
+                }
+                DiffStep::Expand => self.expand_diff_item(&mut items)?,
+                DiffStep::ExpandBase => base.expand_diff_item(&mut base_items)?,
+            }
+        }
+    }
+
+    fn diff_items(&self) -> VecDeque<DiffItem> {
+        let mut items = VecDeque::new();
+        if let Some((root, _)) = self.root {
+            items.push_back(DiffItem::Subtree(root, None));
+        }
+        items
+    }
+
+    // Replaces the subtree at the front of items with its children, or its entries if it's a leaf
+    fn expand_diff_item(&self, items: &mut VecDeque<DiffItem>) -> Result {
+        let (page_number, upper) = match items.pop_front() {
+            Some(DiffItem::Subtree(page_number, upper)) => (page_number, upper),
+            _ => unreachable!(),
+        };
+        let page = self.mem.get_page(page_number)?;
+        match page.memory()[0] {
+            LEAF => {
+                let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
+                for i in (0..accessor.num_pairs()).rev() {
+                    let entry = accessor.entry(i).unwrap();
+                    items.push_front(DiffItem::Entry(
+                        entry.key().to_vec(),
+                        entry.value().to_vec(),
+                    ));
+                }
+            }
+            BRANCH => {
+                let accessor = BranchAccessor::new(&page, K::fixed_width());
+                let last = accessor.count_children() - 1;
+                items.push_front(DiffItem::Subtree(accessor.child_page(last).unwrap(), upper));
+                for i in (0..last).rev() {
+                    items.push_front(DiffItem::Subtree(
+                        accessor.child_page(i).unwrap(),
+                        Some(accessor.key(i).unwrap().to_vec()),
+                    ));
+                }
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn print_debug(&self, include_values: bool) -> Result {
         if let Some((p, _)) = self.root {
-            let mut pages = vec![self.mem.get_page(p)];
+            let mut pages = vec![self.mem.get_page(p)?];
             while !pages.is_empty() {
                 let mut next_children = vec![];
                 for page in pages.drain(..) {
@@ -339,7 +1665,7 @@ impl<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized> Btree<'a, K, V> {
                             let accessor = BranchAccessor::new(&page, K::fixed_width());
                             for i in 0..accessor.count_children() {
                                 let child = accessor.child_page(i).unwrap();
-                                next_children.push(self.mem.get_page(child));
+                                next_children.push(self.mem.get_page(child)?);
                             }
                             accessor.print_node::<K>();
                         }
@@ -352,26 +1678,69 @@ impl<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized> Btree<'a, K, V> {
                 pages = next_children;
             }
         }
+
+        Ok(())
+    }
+
+    pub(crate) fn dump(&self, format: TreeDumpFormat, writer: impl Write) -> Result {
+        dump_tree::<K, V>(self.root, self.mem, format, writer)
+    }
+
+    pub(crate) fn pages(&self) -> TreePageIter<'a> {
+        TreePageIter::new(self.root, self.mem, K::fixed_width(), V::fixed_width())
+    }
+}
+
+// The contents of a tree, in key order, as they are expanded by Btree::diff()
+enum DiffItem {
+    // A page, and the greatest key that its subtree may contain, or None if it's unbounded
+    Subtree(PageNumber, Option<Vec<u8>>),
+    Entry(Vec<u8>, Vec<u8>),
+}
+
+enum DiffStep {
+    Pop,
+    PopBase,
+    PopBoth,
+    Expand,
+    ExpandBase,
+}
+
+fn compare_upper_bounds<K: RedbKey + ?Sized>(a: &Option<Vec<u8>>, b: &Option<Vec<u8>>) -> Ordering {
+    match (a, b) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => K::compare(a, b),
     }
 }
 
+// Checks the checksums, key ordering, and structure of the tree with the given root
+#[cfg(feature = "integrity_checks")]
+pub(crate) fn verify_tree<K: RedbKey + ?Sized, V: RedbValue + ?Sized>(
+    root: Option<(PageNumber, Checksum)>,
+    mem: &TransactionalMemory,
+) -> Result {
+    Btree::<K, V>::new(root, mem).verify_integrity()
+}
+
 pub(crate) fn btree_stats(
     root: Option<PageNumber>,
     mem: &TransactionalMemory,
     fixed_key_size: Option<usize>,
     fixed_value_size: Option<usize>,
-) -> BtreeStats {
+) -> Result<BtreeStats> {
     if let Some(root) = root {
         stats_helper(root, mem, fixed_key_size, fixed_value_size)
     } else {
-        BtreeStats {
+        Ok(BtreeStats {
             tree_height: 0,
             leaf_pages: 0,
             branch_pages: 0,
             stored_leaf_bytes: 0,
             metadata_bytes: 0,
             fragmented_bytes: 0,
-        }
+        })
     }
 }
 
@@ -380,8 +1749,8 @@ fn stats_helper(
     mem: &TransactionalMemory,
     fixed_key_size: Option<usize>,
     fixed_value_size: Option<usize>,
-) -> BtreeStats {
-    let page = mem.get_page(page_number);
+) -> Result<BtreeStats> {
+    let page = mem.get_page(page_number)?;
     let node_mem = page.memory();
     match node_mem[0] {
         LEAF => {
@@ -389,14 +1758,14 @@ fn stats_helper(
             let leaf_bytes = accessor.length_of_pairs(0, accessor.num_pairs());
             let overhead_bytes = accessor.total_length() - leaf_bytes;
             let fragmented_bytes = page.memory().len() - accessor.total_length();
-            BtreeStats {
+            Ok(BtreeStats {
                 tree_height: 1,
                 leaf_pages: 1,
                 branch_pages: 0,
                 stored_leaf_bytes: leaf_bytes,
                 metadata_bytes: overhead_bytes,
                 fragmented_bytes,
-            }
+            })
         }
         BRANCH => {
             let accessor = BranchAccessor::new(&page, fixed_key_size);
@@ -408,7 +1777,7 @@ fn stats_helper(
             let mut fragmented_bytes = page.memory().len() - accessor.total_length();
             for i in 0..accessor.count_children() {
                 if let Some(child) = accessor.child_page(i) {
-                    let stats = stats_helper(child, mem, fixed_key_size, fixed_value_size);
+                    let stats = stats_helper(child, mem, fixed_key_size, fixed_value_size)?;
                     max_child_height = max(max_child_height, stats.tree_height);
                     leaf_pages += stats.leaf_pages;
                     branch_pages += stats.branch_pages;
@@ -418,15 +1787,167 @@ fn stats_helper(
                 }
             }
 
-            BtreeStats {
+            Ok(BtreeStats {
                 tree_height: max_child_height + 1,
                 leaf_pages,
                 branch_pages,
                 stored_leaf_bytes,
                 metadata_bytes,
                 fragmented_bytes,
-            }
+            })
         }
         _ => unreachable!(),
     }
 }
+
+// Copies the pages of the tree to lower addresses, if the allocator has free space for them, and
+// returns the new root. Pages which were moved are pushed onto freed.
+// budget is the maximum number of bytes to write, and is decremented by the amount written
+//
+// Safety: caller must ensure that no references to uncommitted pages in this tree exist
+pub(crate) unsafe fn relocate_tree(
+    root: (PageNumber, Checksum),
+    fixed_key_size: Option<usize>,
+    mem: &TransactionalMemory,
+    freed: &mut Vec<PageNumber>,
+    budget: &mut usize,
+) -> Result<(PageNumber, Checksum)> {
+    if *budget == 0 {
+        return Ok(root);
+    }
+    let (page_number, checksum) = root;
+    let page = mem.get_page(page_number)?;
+    let children: Vec<(PageNumber, Checksum)> = if page.memory()[0] == BRANCH {
+        let accessor = BranchAccessor::new(&page, fixed_key_size);
+        (0..accessor.count_children())
+            .map(|i| {
+                (
+                    accessor.child_page(i).unwrap(),
+                    accessor.child_checksum(i).unwrap(),
+                )
+            })
+            .collect()
+    } else {
+        vec![]
+    };
+    drop(page);
+
+    let mut new_children = vec![];
+    let mut children_moved = false;
+    for child in children {
+        let new_child = relocate_tree(child, fixed_key_size, mem, freed, budget)?;
+        children_moved |= new_child != child;
+        new_children.push(new_child);
+    }
+
+    let mut new_page = if mem.uncommitted(page_number) {
+        if !children_moved {
+            return Ok(root);
+        }
+        // Uncommitted pages were already allocated by this transaction, so modify it in-place
+        mem.get_page_mut(page_number)?
+    } else {
+        let page = mem.get_page(page_number)?;
+        let mut new_page = mem.allocate_lowest(page.memory().len())?;
+        if new_page.get_page_number() > page_number && !children_moved {
+            // No space at a lower address
+            let new_page_number = new_page.get_page_number();
+            drop(new_page);
+            assert!(mem.free_if_uncommitted(new_page_number)?);
+            return Ok(root);
+        }
+        new_page.memory_mut().copy_from_slice(page.memory());
+        *budget = budget.saturating_sub(page.memory().len());
+        drop(page);
+        freed.push(page_number);
+        new_page
+    };
+
+    let new_checksum = if children_moved {
+        let mut mutator = BranchMutator::new(&mut new_page);
+        for (i, (child, child_checksum)) in new_children.drain(..).enumerate() {
+            mutator.write_child_page(i, child, child_checksum);
+        }
+        drop(mutator);
+        branch_checksum(&new_page, fixed_key_size, mem.checksum_type())
+    } else {
+        checksum
+    };
+
+    Ok((new_page.get_page_number(), new_checksum))
+}
+
+// Copies the tree with the given root from source into newly allocated pages of dest, which may
+// be a different database, and returns the new root. rewrite_value is called on every value in
+// the copied leaves, and may modify it in place
+pub(crate) fn copy_tree(
+    root: (PageNumber, Checksum),
+    fixed_key_size: Option<usize>,
+    fixed_value_size: Option<usize>,
+    source: &TransactionalMemory,
+    dest: &TransactionalMemory,
+    rewrite_value: &mut dyn FnMut(&mut [u8]) -> Result,
+) -> Result<(PageNumber, Checksum)> {
+    let page = source.get_page(root.0)?;
+    let children: Vec<(PageNumber, Checksum)> = if page.memory()[0] == BRANCH {
+        let accessor = BranchAccessor::new(&page, fixed_key_size);
+        (0..accessor.count_children())
+            .map(|i| {
+                (
+                    accessor.child_page(i).unwrap(),
+                    accessor.child_checksum(i).unwrap(),
+                )
+            })
+            .collect()
+    } else {
+        vec![]
+    };
+    drop(page);
+
+    // Copy the children first, so that only one page of dest is open at each level
+    let mut new_children = vec![];
+    for child in children {
+        new_children.push(copy_tree(
+            child,
+            fixed_key_size,
+            fixed_value_size,
+            source,
+            dest,
+            rewrite_value,
+        )?);
+    }
+
+    let page = source.get_page(root.0)?;
+    let mut new_page = dest.allocate(page.memory().len())?;
+    new_page.memory_mut().copy_from_slice(page.memory());
+    drop(page);
+
+    let checksum = match new_page.memory()[0] {
+        LEAF => {
+            let accessor = LeafAccessor::new(new_page.memory(), fixed_key_size, fixed_value_size);
+            let value_ranges: Vec<(usize, usize)> = (0..accessor.num_pairs())
+                .map(|i| accessor.value_range(i).unwrap())
+                .collect();
+            for (start, end) in value_ranges {
+                rewrite_value(&mut new_page.memory_mut()[start..end])?;
+            }
+            leaf_checksum(
+                &new_page,
+                fixed_key_size,
+                fixed_value_size,
+                dest.checksum_type(),
+            )
+        }
+        BRANCH => {
+            let mut mutator = BranchMutator::new(&mut new_page);
+            for (i, (child, child_checksum)) in new_children.into_iter().enumerate() {
+                mutator.write_child_page(i, child, child_checksum);
+            }
+            drop(mutator);
+            branch_checksum(&new_page, fixed_key_size, dest.checksum_type())
+        }
+        _ => unreachable!(),
+    };
+
+    Ok((new_page.get_page_number(), checksum))
+}
@@ -1,9 +1,9 @@
 use crate::tree_store::page_store::{ChecksumType, Page, PageImpl, PageMut, TransactionalMemory};
 use crate::tree_store::{page_store, PageNumber};
-use crate::types::{RedbKey, RedbValue};
-use crate::Result;
+use crate::types::{from_stored_bytes, Bytes, RedbKey, RedbValue};
+use crate::{Error, Result};
 use std::cell::RefCell;
-use std::cmp::Ordering;
+use std::cmp::{max, min, Ordering};
 use std::marker::PhantomData;
 use std::mem::size_of;
 use std::rc::Rc;
@@ -12,6 +12,10 @@ use std::{mem, thread};
 pub(crate) const LEAF: u8 = 1;
 pub(crate) const BRANCH: u8 = 2;
 
+// Flag, in the second byte of a branch page, indicating that it stores the number of entries in
+// the subtree of each child
+const BRANCH_COUNTED: u8 = 1;
+
 pub(crate) type Checksum = u128;
 
 pub(super) fn leaf_checksum<T: Page>(
@@ -30,6 +34,108 @@ pub(super) fn leaf_checksum<T: Page>(
     }
 }
 
+// Returns the page, after verifying that its checksum matches the one stored in its parent, if the
+// database verifies the checksum of every page read
+pub(crate) fn read_page(
+    mem: &TransactionalMemory,
+    page_number: PageNumber,
+    expected_checksum: Checksum,
+    fixed_key_size: Option<usize>,
+    fixed_value_size: Option<usize>,
+) -> Result<PageImpl<'_>> {
+    mem.check_page_number(page_number)?;
+    let page = mem.get_page(page_number)?;
+    if !matches!(page.memory()[0], LEAF | BRANCH) {
+        return Err(Error::corrupted_page(
+            page_number,
+            format!("page {:?} is not a b-tree page", page_number),
+        ));
+    }
+    if let Some(checksum_type) = mem.read_verification() {
+        verify_checksum(
+            &page,
+            expected_checksum,
+            fixed_key_size,
+            fixed_value_size,
+            checksum_type,
+        )?;
+    }
+
+    Ok(page)
+}
+
+// Returns an error if the checksum of the page, which must be a b-tree page, does not match the
+// one stored in its parent. Only the layout of the page can be verified if checksums are unused
+pub(crate) fn verify_checksum<T: Page>(
+    page: &T,
+    expected_checksum: Checksum,
+    fixed_key_size: Option<usize>,
+    fixed_value_size: Option<usize>,
+    checksum_type: ChecksumType,
+) -> Result {
+    // Check the layout first, so that computing the checksum of a corrupted page can't panic
+    let checksum = match page.memory()[0] {
+        LEAF => {
+            let accessor = LeafAccessor::new(page.memory(), fixed_key_size, fixed_value_size);
+            if accessor.verify_layout() && accessor.num_pairs() > 0 {
+                Some(leaf_checksum(
+                    page,
+                    fixed_key_size,
+                    fixed_value_size,
+                    checksum_type,
+                ))
+            } else {
+                None
+            }
+        }
+        BRANCH => {
+            if BranchAccessor::new(page, fixed_key_size).verify_layout() {
+                Some(branch_checksum(page, fixed_key_size, checksum_type))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    };
+    match checksum {
+        None => Err(Error::corrupted_page(
+            page.get_page_number(),
+            format!("page {:?} is malformed", page.get_page_number()),
+        )),
+        Some(checksum)
+            if checksum != expected_checksum && checksum_type != ChecksumType::Unused =>
+        {
+            Err(Error::corrupted_page(
+                page.get_page_number(),
+                format!(
+                    "page {:?} does not match the checksum stored in its parent",
+                    page.get_page_number()
+                ),
+            ))
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+// Returns the number of entries in the subtree rooted at page, or None if it is a branch which
+// does not store the counts of its children
+pub(super) fn subtree_entries<T: Page>(page: &T) -> Option<u64> {
+    match page.memory()[0] {
+        LEAF => Some(u16::from_le_bytes(page.memory()[2..4].try_into().unwrap()).into()),
+        BRANCH => BranchAccessor::new(page, None).total_entries(),
+        _ => unreachable!(),
+    }
+}
+
+// Length of the checksums, page numbers, and entry counts (if present) of a branch's children
+fn branch_children_bytes(num_children: usize, counted: bool) -> usize {
+    let mut per_child = PageNumber::serialized_size() + size_of::<Checksum>();
+    if counted {
+        per_child += size_of::<u64>();
+    }
+    per_child * num_children
+}
+
 pub(super) fn branch_checksum<T: Page>(
     page: &T,
     fixed_key_size: Option<usize>,
@@ -142,10 +248,11 @@ impl<'a, V: RedbValue + ?Sized> AccessGuard<'a, V> {
         }
     }
 
-    pub(super) fn with_owned_value(value: Vec<u8>, mem: &'a TransactionalMemory) -> Self {
+    // Copies value into a buffer from the write transaction's arena, which is returned on drop
+    pub(super) fn with_copied_value(value: &[u8], mem: &'a TransactionalMemory) -> Self {
         let len = value.len();
         Self {
-            page: EitherPage::OwnedMemory(value),
+            page: EitherPage::OwnedMemory(mem.arena().copy_bytes(value)),
             offset: 0,
             len,
             on_drop: OnDrop::None,
@@ -181,12 +288,27 @@ impl<'a, V: RedbValue + ?Sized> AccessGuard<'a, V> {
     pub fn to_value(&self) -> V::SelfType<'_> {
         V::from_bytes(&self.page.memory()[self.offset..(self.offset + self.len)])
     }
+
+    /// Copies the serialized value into a [`Bytes`], which does not borrow from the transaction
+    pub fn to_bytes(&self) -> Bytes {
+        Bytes::new(&self.page.memory()[self.offset..(self.offset + self.len)])
+    }
+}
+
+impl<'a, V: RedbValue + ?Sized> From<AccessGuard<'a, V>> for Bytes {
+    fn from(guard: AccessGuard<'a, V>) -> Self {
+        guard.to_bytes()
+    }
 }
 
 impl<'a, V: RedbValue + ?Sized> Drop for AccessGuard<'a, V> {
     fn drop(&mut self) {
         match self.on_drop {
-            OnDrop::None => {}
+            OnDrop::None => {
+                if let EitherPage::OwnedMemory(ref mut value) = self.page {
+                    self.mem.arena().give_bytes(mem::take(value));
+                }
+            }
             OnDrop::Free(page_number) => {
                 // Drop our reference to the page, so that it can be freed
                 let mut dummy = EitherPage::OwnedMemory(vec![]);
@@ -212,8 +334,11 @@ impl<'a, V: RedbValue + ?Sized> Drop for AccessGuard<'a, V> {
     }
 }
 
+#[allow(clippy::type_complexity)]
 pub struct AccessGuardMut<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized> {
-    root: Rc<RefCell<Option<(PageNumber, Checksum)>>>,
+    // The tree root and key are only needed to repair the checksums, if the guard was returned to
+    // the user. They're set by set_root_for_drop(), so that plain inserts don't allocate them
+    root: Option<Rc<RefCell<Option<(PageNumber, Checksum)>>>>,
     key: Vec<u8>,
     mem: &'a TransactionalMemory,
     page: PageMut<'a>,
@@ -227,15 +352,14 @@ pub struct AccessGuardMut<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized> {
 
 impl<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized> AccessGuardMut<'a, K, V> {
     pub(crate) fn new(
-        key: &[u8],
         page: PageMut<'a>,
         offset: usize,
         len: usize,
         mem: &'a TransactionalMemory,
     ) -> Self {
         AccessGuardMut {
-            root: Rc::new(RefCell::new(None)),
-            key: key.to_vec(),
+            root: None,
+            key: vec![],
             mem,
             page,
             offset,
@@ -245,31 +369,36 @@ impl<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized> AccessGuardMut<'a, K, V> {
         }
     }
 
-    pub(crate) fn set_root_for_drop(&mut self, root: Rc<RefCell<Option<(PageNumber, Checksum)>>>) {
-        self.root = root;
+    pub(crate) fn set_root_for_drop(
+        &mut self,
+        root: Rc<RefCell<Option<(PageNumber, Checksum)>>>,
+        key: &[u8],
+    ) {
+        self.root = Some(root);
+        self.key = key.to_vec();
     }
 
     // Repairs the checksums after the user has filled the mutable buffer. This is necessary
     // because the checksums will have been calculated with the values during .insert_reserve(),
     // but the user is given a mutable reference and will have modified the value, which invalidates
     // the checksum.
-    fn finalize_checksum(&mut self, page_number: PageNumber) -> Checksum {
+    fn finalize_checksum(&mut self, page_number: PageNumber) -> Result<Checksum> {
         if page_number == self.page.get_page_number() {
             assert_eq!(LEAF, self.page.memory()[0]);
-            self.checksum_helper(&self.page)
+            Ok(self.checksum_helper(&self.page))
         } else {
             // Safe because we're the only one with mutable access, and this is a dirty page so
             // no readers can have a reference to it
             assert!(self.mem.uncommitted(page_number));
-            let mut page = unsafe { self.mem.get_page_mut(page_number) };
+            let mut page = unsafe { self.mem.get_page_mut(page_number)? };
             assert_eq!(BRANCH, page.memory()[0]);
             let accessor = BranchAccessor::new(&page, K::fixed_width());
             let (child_index, child_page) = accessor.child_for_key::<K>(&self.key);
-            let child_checksum = self.finalize_checksum(child_page);
+            let child_checksum = self.finalize_checksum(child_page)?;
             drop(accessor);
             let mut mutator = BranchMutator::new(&mut page);
             mutator.write_child_page(child_index, child_page, child_checksum);
-            self.checksum_helper(&page)
+            Ok(self.checksum_helper(&page))
         }
     }
 
@@ -300,17 +429,23 @@ impl<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized> AsMut<[u8]> for AccessGuard
 impl<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized> Drop for AccessGuardMut<'a, K, V> {
     fn drop(&mut self) {
         // Was dropped before being returned to the user, so no clean up needed
-        if self.root.borrow().is_none() {
-            return;
+        let root = match self.root.take() {
+            Some(root) => root,
+            None => return,
+        };
+        // The pages on the path to the value are dirty, so reading them only fails if the file
+        // can no longer be accessed, in which case the transaction can't be committed either
+        let root_page = root.borrow().unwrap().0;
+        if let Ok(new_checksum) = self.finalize_checksum(root_page) {
+            let mut borrow = root.borrow_mut();
+            let (_, root_checksum_ref) = borrow.as_mut().unwrap();
+            *root_checksum_ref = new_checksum;
         }
-        let new_checksum = self.finalize_checksum((self.root.clone()).borrow().unwrap().0);
-        let mut borrow = self.root.borrow_mut();
-        let (_, root_checksum_ref) = borrow.as_mut().unwrap();
-        *root_checksum_ref = new_checksum;
     }
 }
 
 // Provides a simple zero-copy way to access entries
+#[derive(Clone)]
 pub struct EntryAccessor<'a> {
     key: &'a [u8],
     value: &'a [u8],
@@ -330,6 +465,16 @@ impl<'a: 'b, 'b> EntryAccessor<'a> {
     pub(crate) fn value(&'b self) -> &'a [u8] {
         self.value
     }
+
+    // Returns Error::Corrupted if the key or value is not valid for its type
+    pub(crate) fn deserialize<K: RedbValue + ?Sized + 'a, V: RedbValue + ?Sized + 'a>(
+        &'b self,
+    ) -> Result<(K::SelfType<'a>, V::SelfType<'a>)> {
+        Ok((
+            from_stored_bytes::<K>(self.key)?,
+            from_stored_bytes::<V>(self.value)?,
+        ))
+    }
 }
 
 // Provides a simple zero-copy way to access a leaf page
@@ -472,6 +617,30 @@ impl<'a> LeafAccessor<'a> {
         self.num_pairs
     }
 
+    // Checks that the offsets stored in the page lie within it, so that entries of a corrupted
+    // page can be accessed without panicking
+    pub(super) fn verify_layout(&self) -> bool {
+        let mut end = self.key_section_start();
+        if end > self.page.len() {
+            return false;
+        }
+        for i in 0..self.num_pairs {
+            let key_end = self.key_end(i).unwrap();
+            if key_end < end || key_end > self.page.len() {
+                return false;
+            }
+            end = key_end;
+        }
+        for i in 0..self.num_pairs {
+            let value_end = self.value_end(i).unwrap();
+            if value_end < end || value_end > self.page.len() {
+                return false;
+            }
+            end = value_end;
+        }
+        true
+    }
+
     pub(super) fn offset_of_first_value(&self) -> usize {
         self.offset_of_value(0).unwrap()
     }
@@ -582,6 +751,8 @@ pub(super) struct LeafBuilder<'a, 'b> {
     fixed_value_size: Option<usize>,
     total_key_bytes: usize,
     total_value_bytes: usize,
+    // Index of the first pair whose value should be stored in a leaf of its own
+    first_large_value: Option<usize>,
     mem: &'b TransactionalMemory,
 }
 
@@ -597,16 +768,20 @@ impl<'a, 'b> LeafBuilder<'a, 'b> {
         fixed_value_size: Option<usize>,
     ) -> Self {
         Self {
-            pairs: Vec::with_capacity(capacity),
+            pairs: mem.arena().take_pairs(capacity),
             fixed_key_size,
             fixed_value_size,
             total_key_bytes: 0,
             total_value_bytes: 0,
+            first_large_value: None,
             mem,
         }
     }
 
     pub(super) fn push(&mut self, key: &'a [u8], value: &'a [u8]) {
+        if self.first_large_value.is_none() && self.mem.is_large_value(value.len()) {
+            self.first_large_value = Some(self.pairs.len());
+        }
         self.total_key_bytes += key.len();
         self.total_value_bytes += value.len();
         self.pairs.push((key, value))
@@ -628,27 +803,50 @@ impl<'a, 'b> LeafBuilder<'a, 'b> {
         }
     }
 
+    pub(super) fn has_large_value(&self) -> bool {
+        self.first_large_value.is_some()
+    }
+
     pub(super) fn should_split(&self) -> bool {
         let required_size = Self::required_bytes(
             self.pairs.len(),
             self.total_key_bytes + self.total_value_bytes,
         );
-        required_size > self.mem.get_page_size() && self.pairs.len() > 1
-    }
-
-    pub(super) fn build_split(self) -> Result<(PageMut<'b>, &'a [u8], PageMut<'b>)> {
-        let total_size = self.total_key_bytes + self.total_value_bytes;
-        let mut division = 0;
-        let mut first_split_key_bytes = 0;
-        let mut first_split_value_bytes = 0;
-        for (key, value) in self.pairs.iter().take(self.pairs.len() - 1) {
-            first_split_key_bytes += key.len();
-            first_split_value_bytes += value.len();
-            division += 1;
-            if first_split_key_bytes + first_split_value_bytes >= total_size / 2 {
-                break;
+        (required_size > self.mem.get_page_size() || self.first_large_value.is_some())
+            && self.pairs.len() > 1
+    }
+
+    // Returns the two pages, and the key which separates them
+    pub(super) fn build_split<K: RedbKey + ?Sized>(
+        self,
+    ) -> Result<(PageMut<'b>, Vec<u8>, PageMut<'b>)> {
+        let division = match self.first_large_value {
+            // Split the large value from the pairs before it, or if there are none, from those
+            // after it. Any other large values are split off when their pages are next rebuilt
+            Some(0) => 1,
+            Some(i) => i,
+            None => {
+                let total_size = self.total_key_bytes + self.total_value_bytes;
+                let mut division = 0;
+                let mut bytes = 0;
+                for (key, value) in self.pairs.iter().take(self.pairs.len() - 1) {
+                    bytes += key.len() + value.len();
+                    division += 1;
+                    if bytes >= total_size / 2 {
+                        break;
+                    }
+                }
+                division
             }
-        }
+        };
+        let first_split_key_bytes: usize = self.pairs[..division]
+            .iter()
+            .map(|(key, _)| key.len())
+            .sum();
+        let first_split_value_bytes: usize = self.pairs[..division]
+            .iter()
+            .map(|(_, value)| value.len())
+            .sum();
 
         let required_size =
             Self::required_bytes(division, first_split_key_bytes + first_split_value_bytes);
@@ -684,7 +882,74 @@ impl<'a, 'b> LeafBuilder<'a, 'b> {
         }
         drop(builder);
 
-        Ok((page1, self.pairs[division - 1].0, page2))
+        let separator = K::separator(self.pairs[division - 1].0, self.pairs[division].0);
+        self.release();
+
+        Ok((page1, separator, page2))
+    }
+
+    // Builds as many pages as are needed to hold all the pairs, with the pairs divided evenly
+    // between them. Returns each page along with its last key
+    pub(super) fn build_many(self) -> Result<Vec<(PageMut<'b>, &'a [u8])>> {
+        let page_size = self.mem.get_page_size();
+        let total_size = Self::required_bytes(
+            self.pairs.len(),
+            self.total_key_bytes + self.total_value_bytes,
+        );
+        let num_pages = (total_size + page_size - 1) / page_size;
+        let target_size = total_size / max(num_pages, 1);
+
+        let mut result = vec![];
+        let mut start = 0;
+        let mut key_bytes = 0;
+        let mut value_bytes = 0;
+        for (i, (key, value)) in self.pairs.iter().enumerate() {
+            let size = Self::required_bytes(
+                i - start + 1,
+                key_bytes + value_bytes + key.len() + value.len(),
+            );
+            let current_size = Self::required_bytes(i - start, key_bytes + value_bytes);
+            // Large values are stored in leaves of their own
+            let large_value = self.mem.is_large_value(value.len())
+                || self.mem.is_large_value(self.pairs[max(i, 1) - 1].1.len());
+            if i > start && (size > page_size || current_size >= target_size || large_value) {
+                result.push(self.build_range(start, i, key_bytes, value_bytes)?);
+                start = i;
+                key_bytes = 0;
+                value_bytes = 0;
+            }
+            key_bytes += key.len();
+            value_bytes += value.len();
+        }
+        if start < self.pairs.len() {
+            result.push(self.build_range(start, self.pairs.len(), key_bytes, value_bytes)?);
+        }
+        self.release();
+
+        Ok(result)
+    }
+
+    fn build_range(
+        &self,
+        start: usize,
+        end: usize,
+        key_bytes: usize,
+        value_bytes: usize,
+    ) -> Result<(PageMut<'b>, &'a [u8])> {
+        let required_size = Self::required_bytes(end - start, key_bytes + value_bytes);
+        let mut page = self.mem.allocate(required_size)?;
+        let mut builder = RawLeafBuilder::new(
+            page.memory_mut(),
+            end - start,
+            self.fixed_key_size,
+            self.fixed_value_size,
+            key_bytes,
+        );
+        for (key, value) in self.pairs[start..end].iter() {
+            builder.append(key, value);
+        }
+        drop(builder);
+        Ok((page, self.pairs[end - 1].0))
     }
 
     pub(super) fn build(self) -> Result<PageMut<'b>> {
@@ -700,12 +965,18 @@ impl<'a, 'b> LeafBuilder<'a, 'b> {
             self.fixed_value_size,
             self.total_key_bytes,
         );
-        for (key, value) in self.pairs {
+        for (key, value) in self.pairs.iter() {
             builder.append(key, value);
         }
         drop(builder);
+        self.release();
         Ok(page)
     }
+
+    // Returns the builder's buffer to the arena, for reuse by the next builder
+    fn release(self) {
+        self.mem.arena().give_pairs(self.pairs);
+    }
 }
 
 // Note the caller is responsible for ensuring that the buffer is large enough
@@ -1191,6 +1462,7 @@ impl<'a: 'b, 'b> LeafMutator<'a, 'b> {
 pub(super) struct BranchAccessor<'a: 'b, 'b, T: Page + 'a> {
     page: &'b T,
     num_keys: usize,
+    counted: bool,
     fixed_key_size: Option<usize>,
     _page_lifetime: PhantomData<&'a ()>,
 }
@@ -1199,9 +1471,11 @@ impl<'a: 'b, 'b, T: Page + 'a> BranchAccessor<'a, 'b, T> {
     pub(super) fn new(page: &'b T, fixed_key_size: Option<usize>) -> Self {
         debug_assert_eq!(page.memory()[0], BRANCH);
         let num_keys = u16::from_le_bytes(page.memory()[2..4].try_into().unwrap()) as usize;
+        let counted = page.memory()[1] & BRANCH_COUNTED != 0;
         BranchAccessor {
             page,
             num_keys,
+            counted,
             fixed_key_size,
             _page_lifetime: Default::default(),
         }
@@ -1252,10 +1526,10 @@ impl<'a: 'b, 'b, T: Page + 'a> BranchAccessor<'a, 'b, T> {
 
     fn key_section_start(&self) -> usize {
         if self.fixed_key_size.is_none() {
-            8 + (PageNumber::serialized_size() + size_of::<Checksum>()) * self.count_children()
+            8 + branch_children_bytes(self.count_children(), self.counted)
                 + size_of::<u32>() * self.num_keys()
         } else {
-            8 + (PageNumber::serialized_size() + size_of::<Checksum>()) * self.count_children()
+            8 + branch_children_bytes(self.count_children(), self.counted)
         }
     }
 
@@ -1271,9 +1545,8 @@ impl<'a: 'b, 'b, T: Page + 'a> BranchAccessor<'a, 'b, T> {
         if let Some(fixed) = self.fixed_key_size {
             return self.key_section_start() + fixed * (n + 1);
         }
-        let offset = 8
-            + (PageNumber::serialized_size() + size_of::<Checksum>()) * self.count_children()
-            + size_of::<u32>() * n;
+        let offset =
+            8 + branch_children_bytes(self.count_children(), self.counted) + size_of::<u32>() * n;
         u32::from_le_bytes(
             self.page.memory()[offset..(offset + size_of::<u32>())]
                 .try_into()
@@ -1290,6 +1563,24 @@ impl<'a: 'b, 'b, T: Page + 'a> BranchAccessor<'a, 'b, T> {
         Some(&self.page.memory()[offset..end])
     }
 
+    // Checks that the offsets stored in the page lie within it, so that the keys and children of
+    // a corrupted page can be accessed without panicking
+    pub(super) fn verify_layout(&self) -> bool {
+        let len = self.page.memory().len();
+        let mut end = self.key_section_start();
+        if self.num_keys() == 0 || end > len {
+            return false;
+        }
+        for i in 0..self.num_keys() {
+            let key_end = self.key_end(i);
+            if key_end < end || key_end > len {
+                return false;
+            }
+            end = key_end;
+        }
+        true
+    }
+
     pub(super) fn count_children(&self) -> usize {
         self.num_keys() + 1
     }
@@ -1321,6 +1612,34 @@ impl<'a: 'b, 'b, T: Page + 'a> BranchAccessor<'a, 'b, T> {
         ))
     }
 
+    // Returns the number of entries in the subtree of the nth child, if this branch stores them
+    pub(super) fn child_entries(&self, n: usize) -> Option<u64> {
+        if !self.counted || n >= self.count_children() {
+            return None;
+        }
+
+        let offset = 8
+            + (PageNumber::serialized_size() + size_of::<Checksum>()) * self.count_children()
+            + size_of::<u64>() * n;
+        Some(u64::from_le_bytes(
+            self.page.memory()[offset..(offset + size_of::<u64>())]
+                .try_into()
+                .unwrap(),
+        ))
+    }
+
+    // Returns the number of entries in this subtree, if this branch stores them
+    pub(super) fn total_entries(&self) -> Option<u64> {
+        if !self.counted {
+            return None;
+        }
+        Some(
+            (0..self.count_children())
+                .map(|i| self.child_entries(i).unwrap())
+                .sum(),
+        )
+    }
+
     fn num_keys(&self) -> usize {
         self.num_keys
     }
@@ -1328,9 +1647,12 @@ impl<'a: 'b, 'b, T: Page + 'a> BranchAccessor<'a, 'b, T> {
 
 pub(super) struct BranchBuilder<'a, 'b> {
     children: Vec<(PageNumber, Checksum)>,
+    // Entry count of each child, if already known
+    entries: Vec<Option<u64>>,
     keys: Vec<&'a [u8]>,
     total_key_bytes: usize,
     fixed_key_size: Option<usize>,
+    counted: bool,
     mem: &'b TransactionalMemory,
 }
 
@@ -1339,22 +1661,39 @@ impl<'a, 'b> BranchBuilder<'a, 'b> {
         mem: &'b TransactionalMemory,
         child_capacity: usize,
         fixed_key_size: Option<usize>,
+        counted: bool,
     ) -> Self {
         Self {
-            children: Vec::with_capacity(child_capacity),
-            keys: Vec::with_capacity(child_capacity - 1),
+            children: mem.arena().take_children(child_capacity),
+            entries: mem.arena().take_entries(child_capacity),
+            keys: mem.arena().take_keys(child_capacity - 1),
             total_key_bytes: 0,
             fixed_key_size,
+            counted,
             mem,
         }
     }
 
     pub(super) fn replace_child(&mut self, index: usize, child: PageNumber, checksum: Checksum) {
         self.children[index] = (child, checksum);
+        self.entries[index] = None;
     }
 
     pub(super) fn push_child(&mut self, child: PageNumber, checksum: Checksum) {
         self.children.push((child, checksum));
+        self.entries.push(None);
+    }
+
+    // Same as push_child(), but with the number of entries in the child's subtree, which must be
+    // used when the child page can't be read, because it is mutably borrowed
+    pub(super) fn push_child_with_entries(
+        &mut self,
+        child: PageNumber,
+        checksum: Checksum,
+        entries: u64,
+    ) {
+        self.children.push((child, checksum));
+        self.entries.push(Some(entries));
     }
 
     pub(super) fn push_key(&mut self, key: &'a [u8]) {
@@ -1366,7 +1705,8 @@ impl<'a, 'b> BranchBuilder<'a, 'b> {
         for i in 0..accessor.count_children() {
             let child = accessor.child_page(i).unwrap();
             let checksum = accessor.child_checksum(i).unwrap();
-            self.push_child(child, checksum);
+            self.children.push((child, checksum));
+            self.entries.push(accessor.child_entries(i));
         }
         for i in 0..(accessor.count_children() - 1) {
             self.push_key(accessor.key(i).unwrap());
@@ -1381,46 +1721,144 @@ impl<'a, 'b> BranchBuilder<'a, 'b> {
         }
     }
 
+    // Writes the entry count of each of children[start..end], if this builder is counted
+    fn write_entries(&self, builder: &mut RawBranchBuilder, start: usize, end: usize) -> Result {
+        if self.counted {
+            for i in start..end {
+                let entries = if let Some(entries) = self.entries[i] {
+                    entries
+                } else {
+                    subtree_entries(&self.mem.get_page(self.children[i].0)?).unwrap()
+                };
+                builder.write_child_entries(i - start, entries);
+            }
+        }
+
+        Ok(())
+    }
+
     pub(super) fn build(self) -> Result<PageMut<'b>> {
         assert_eq!(self.children.len(), self.keys.len() + 1);
         let size = RawBranchBuilder::required_bytes(
             self.keys.len(),
             self.total_key_bytes,
             self.fixed_key_size,
+            self.counted,
         );
         let mut page = self.mem.allocate(size)?;
-        let mut builder = RawBranchBuilder::new(&mut page, self.keys.len(), self.fixed_key_size);
+        let mut builder = RawBranchBuilder::new(
+            &mut page,
+            self.keys.len(),
+            self.fixed_key_size,
+            self.counted,
+        );
         builder.write_first_page(self.children[0].0, self.children[0].1);
         for i in 1..self.children.len() {
             let key = &self.keys[i - 1];
             builder.write_nth_key(key.as_ref(), self.children[i].0, self.children[i].1, i - 1);
         }
+        self.write_entries(&mut builder, 0, self.children.len())?;
         drop(builder);
+        self.release();
 
         Ok(page)
     }
 
+    // Builds as many pages as are needed to hold all the children, with the children divided
+    // evenly between them. Returns the pages, and the keys which separate them
+    pub(super) fn build_many(self) -> Result<(Vec<PageMut<'b>>, Vec<&'a [u8]>)> {
+        assert_eq!(self.children.len(), self.keys.len() + 1);
+        assert!(self.children.len() >= 2);
+        let size = RawBranchBuilder::required_bytes(
+            self.keys.len(),
+            self.total_key_bytes,
+            self.fixed_key_size,
+            self.counted,
+        );
+        let page_size = self.mem.get_page_size();
+        // Every branch must have at least two children
+        let num_pages = min((size + page_size - 1) / page_size, self.children.len() / 2);
+
+        let mut pages = vec![];
+        let mut separators = vec![];
+        let mut start = 0;
+        for i in 0..num_pages {
+            let end = (i + 1) * self.children.len() / num_pages;
+            let key_bytes: usize = self.keys[start..(end - 1)].iter().map(|k| k.len()).sum();
+            let size = RawBranchBuilder::required_bytes(
+                end - start - 1,
+                key_bytes,
+                self.fixed_key_size,
+                self.counted,
+            );
+            let mut page = self.mem.allocate(size)?;
+            let mut builder = RawBranchBuilder::new(
+                &mut page,
+                end - start - 1,
+                self.fixed_key_size,
+                self.counted,
+            );
+            builder.write_first_page(self.children[start].0, self.children[start].1);
+            for j in (start + 1)..end {
+                builder.write_nth_key(
+                    self.keys[j - 1],
+                    self.children[j].0,
+                    self.children[j].1,
+                    j - start - 1,
+                );
+            }
+            self.write_entries(&mut builder, start, end)?;
+            drop(builder);
+            pages.push(page);
+            if end < self.children.len() {
+                separators.push(self.keys[end - 1]);
+            }
+            start = end;
+        }
+        self.release();
+
+        Ok((pages, separators))
+    }
+
     pub(super) fn should_split(&self) -> bool {
         let size = RawBranchBuilder::required_bytes(
             self.keys.len(),
             self.total_key_bytes,
             self.fixed_key_size,
+            self.counted,
         );
         size > self.mem.get_page_size() && self.keys.len() >= 3
     }
 
     pub(super) fn build_split(self) -> Result<(PageMut<'b>, &'a [u8], PageMut<'b>)> {
+        let division = self.keys.len() / 2;
+        self.build_split_at(division)
+    }
+
+    // Splits off only the last two children, for when a child was appended to the right edge of
+    // the tree. This leaves the first page as full as possible
+    pub(super) fn build_split_for_append(self) -> Result<(PageMut<'b>, &'a [u8], PageMut<'b>)> {
+        let division = self.keys.len() - 2;
+        self.build_split_at(division)
+    }
+
+    // The key at division separates the two pages
+    fn build_split_at(self, division: usize) -> Result<(PageMut<'b>, &'a [u8], PageMut<'b>)> {
         assert_eq!(self.children.len(), self.keys.len() + 1);
         assert!(self.keys.len() >= 3);
-        let division = self.keys.len() / 2;
         let first_split_key_len: usize = self.keys.iter().take(division).map(|k| k.len()).sum();
         let division_key = self.keys[division];
         let second_split_key_len = self.total_key_bytes - first_split_key_len - division_key.len();
 
-        let size =
-            RawBranchBuilder::required_bytes(division, first_split_key_len, self.fixed_key_size);
+        let size = RawBranchBuilder::required_bytes(
+            division,
+            first_split_key_len,
+            self.fixed_key_size,
+            self.counted,
+        );
         let mut page1 = self.mem.allocate(size)?;
-        let mut builder = RawBranchBuilder::new(&mut page1, division, self.fixed_key_size);
+        let mut builder =
+            RawBranchBuilder::new(&mut page1, division, self.fixed_key_size, self.counted);
         builder.write_first_page(self.children[0].0, self.children[0].1);
         for i in 0..division {
             let key = &self.keys[i];
@@ -1431,18 +1869,21 @@ impl<'a, 'b> BranchBuilder<'a, 'b> {
                 i,
             );
         }
+        self.write_entries(&mut builder, 0, division + 1)?;
         drop(builder);
 
         let size = RawBranchBuilder::required_bytes(
             self.keys.len() - division - 1,
             second_split_key_len,
             self.fixed_key_size,
+            self.counted,
         );
         let mut page2 = self.mem.allocate(size)?;
         let mut builder = RawBranchBuilder::new(
             &mut page2,
             self.keys.len() - division - 1,
             self.fixed_key_size,
+            self.counted,
         );
         builder.write_first_page(self.children[division + 1].0, self.children[division + 1].1);
         for i in (division + 1)..self.keys.len() {
@@ -1454,23 +1895,35 @@ impl<'a, 'b> BranchBuilder<'a, 'b> {
                 i - division - 1,
             );
         }
+        self.write_entries(&mut builder, division + 1, self.children.len())?;
         drop(builder);
+        self.release();
 
         Ok((page1, division_key, page2))
     }
+
+    // Returns the builder's buffers to the arena, for reuse by the next builder
+    fn release(self) {
+        let arena = self.mem.arena();
+        arena.give_children(self.children);
+        arena.give_entries(self.entries);
+        arena.give_keys(self.keys);
+    }
 }
 
 // Note the caller is responsible for ensuring that the buffer is large enough
 // and rewriting all fields if any dynamically sized fields are written
 // Layout is:
 // 1 byte: type
-// 1 byte: padding (padding to 16bits aligned)
+// 1 byte: flags
 // 2 bytes: num_keys (number of keys)
 // 4 byte: padding (padding to 64bits aligned)
 // repeating (num_keys + 1 times):
 // 16 bytes: child page checksum
 // repeating (num_keys + 1 times):
 // 8 bytes: page number
+// (optional, if the BRANCH_COUNTED flag is set) repeating (num_keys + 1 times):
+// 8 bytes: number of entries in the child's subtree
 // (optional) repeating (num_keys times):
 // * 4 bytes: key end. Ending offset of the key, exclusive
 // repeating (num_keys times):
@@ -1478,6 +1931,7 @@ impl<'a, 'b> BranchBuilder<'a, 'b> {
 pub(super) struct RawBranchBuilder<'a: 'b, 'b> {
     page: &'b mut PageMut<'a>,
     fixed_key_size: Option<usize>,
+    counted: bool,
     num_keys: usize,
     keys_written: usize, // used for debugging
 }
@@ -1487,35 +1941,35 @@ impl<'a: 'b, 'b> RawBranchBuilder<'a, 'b> {
         num_keys: usize,
         size_of_keys: usize,
         fixed_key_size: Option<usize>,
+        counted: bool,
     ) -> usize {
         if fixed_key_size.is_none() {
-            let fixed_size = 8
-                + (PageNumber::serialized_size() + size_of::<Checksum>()) * (num_keys + 1)
-                + size_of::<u32>() * num_keys;
+            let fixed_size =
+                8 + branch_children_bytes(num_keys + 1, counted) + size_of::<u32>() * num_keys;
             size_of_keys + fixed_size
         } else {
-            let fixed_size =
-                8 + (PageNumber::serialized_size() + size_of::<Checksum>()) * (num_keys + 1);
+            let fixed_size = 8 + branch_children_bytes(num_keys + 1, counted);
             size_of_keys + fixed_size
         }
     }
 
-    // Caller MUST write num_keys values
+    // Caller MUST write num_keys values, and the entries of every child if counted is true
     pub(super) fn new(
         page: &'b mut PageMut<'a>,
         num_keys: usize,
         fixed_key_size: Option<usize>,
+        counted: bool,
     ) -> Self {
         assert!(num_keys > 0);
         page.memory_mut()[0] = BRANCH;
+        page.memory_mut()[1] = if counted { BRANCH_COUNTED } else { 0 };
         page.memory_mut()[2..4].copy_from_slice(&u16::try_from(num_keys).unwrap().to_le_bytes());
         #[cfg(debug_assertions)]
         {
             // Poison all the child pointers & key offsets, in case the caller forgets to write them
             let start = 8 + size_of::<Checksum>() * (num_keys + 1);
-            let last = 8
-                + (PageNumber::serialized_size() + size_of::<Checksum>()) * (num_keys + 1)
-                + size_of::<u32>() * num_keys;
+            let last =
+                8 + branch_children_bytes(num_keys + 1, counted) + size_of::<u32>() * num_keys;
             for x in &mut page.memory_mut()[start..last] {
                 *x = 0xFF;
             }
@@ -1523,6 +1977,7 @@ impl<'a: 'b, 'b> RawBranchBuilder<'a, 'b> {
         RawBranchBuilder {
             page,
             fixed_key_size,
+            counted,
             num_keys,
             keys_written: 0,
         }
@@ -1537,9 +1992,19 @@ impl<'a: 'b, 'b> RawBranchBuilder<'a, 'b> {
             .copy_from_slice(&page_number.to_le_bytes());
     }
 
+    // Write the number of entries in the subtree of the nth child
+    pub(super) fn write_child_entries(&mut self, n: usize, entries: u64) {
+        assert!(self.counted);
+        assert!(n <= self.num_keys);
+        let offset = 8
+            + (PageNumber::serialized_size() + size_of::<Checksum>()) * (self.num_keys + 1)
+            + size_of::<u64>() * n;
+        self.page.memory_mut()[offset..(offset + size_of::<u64>())]
+            .copy_from_slice(&entries.to_le_bytes());
+    }
+
     fn key_section_start(&self) -> usize {
-        let mut offset =
-            8 + (PageNumber::serialized_size() + size_of::<Checksum>()) * (self.num_keys + 1);
+        let mut offset = 8 + branch_children_bytes(self.num_keys + 1, self.counted);
         if self.fixed_key_size.is_none() {
             offset += size_of::<u32>() * self.num_keys;
         }
@@ -1551,9 +2016,8 @@ impl<'a: 'b, 'b> RawBranchBuilder<'a, 'b> {
         if let Some(fixed) = self.fixed_key_size {
             return self.key_section_start() + fixed * (n + 1);
         }
-        let offset = 8
-            + (PageNumber::serialized_size() + size_of::<Checksum>()) * (self.num_keys + 1)
-            + size_of::<u32>() * n;
+        let offset =
+            8 + branch_children_bytes(self.num_keys + 1, self.counted) + size_of::<u32>() * n;
         u32::from_le_bytes(
             self.page.memory()[offset..(offset + size_of::<u32>())]
                 .try_into()
@@ -1588,9 +2052,8 @@ impl<'a: 'b, 'b> RawBranchBuilder<'a, 'b> {
             self.key_section_start()
         };
         if self.fixed_key_size.is_none() {
-            let offset = 8
-                + (PageNumber::serialized_size() + size_of::<Checksum>()) * (self.num_keys + 1)
-                + size_of::<u32>() * n;
+            let offset =
+                8 + branch_children_bytes(self.num_keys + 1, self.counted) + size_of::<u32>() * n;
             self.page.memory_mut()[offset..(offset + size_of::<u32>())].copy_from_slice(
                 &u32::try_from(data_offset + key.len())
                     .unwrap()
@@ -1640,4 +2103,18 @@ impl<'a: 'b, 'b> BranchMutator<'a, 'b> {
         self.page.memory_mut()[offset..(offset + PageNumber::serialized_size())]
             .copy_from_slice(&page_number.to_le_bytes());
     }
+
+    // Updates the number of entries in the subtree of the ith child. Does nothing if this branch
+    // does not store entry counts
+    pub(super) fn write_child_entries(&mut self, i: usize, entries: u64) {
+        debug_assert!(i <= self.num_keys());
+        if self.page.memory()[1] & BRANCH_COUNTED == 0 {
+            return;
+        }
+        let offset = 8
+            + (PageNumber::serialized_size() + size_of::<Checksum>()) * (self.num_keys() + 1)
+            + size_of::<u64>() * i;
+        self.page.memory_mut()[offset..(offset + size_of::<u64>())]
+            .copy_from_slice(&entries.to_le_bytes());
+    }
 }
@@ -1,9 +1,10 @@
-use crate::tree_store::btree_base::{BranchAccessor, EntryAccessor, LeafAccessor};
+use crate::tree_store::btree_base::{read_page, BranchAccessor, EntryAccessor, LeafAccessor};
 use crate::tree_store::btree_base::{BRANCH, LEAF};
 use crate::tree_store::btree_iters::RangeIterState::{Internal, Leaf};
-use crate::tree_store::page_store::{Page, PageImpl, TransactionalMemory};
-use crate::tree_store::PageNumber;
+use crate::tree_store::page_store::{Arena, Page, PageImpl, TransactionalMemory};
+use crate::tree_store::{Checksum, PageNumber};
 use crate::types::{RedbKey, RedbValue};
+use crate::{Error, Result};
 use std::borrow::Borrow;
 use std::collections::Bound;
 use std::marker::PhantomData;
@@ -35,7 +36,18 @@ impl<'a> RangeIterState<'a> {
         }
     }
 
-    fn next(self, reverse: bool, manager: &'a TransactionalMemory) -> Option<RangeIterState> {
+    fn into_parent(self) -> Option<Box<RangeIterState<'a>>> {
+        match self {
+            Leaf { parent, .. } | Internal { parent, .. } => parent,
+        }
+    }
+
+    fn next(
+        self,
+        reverse: bool,
+        manager: &'a TransactionalMemory,
+        arena: Option<&Arena>,
+    ) -> Result<Option<RangeIterState<'a>>> {
         match self {
             Leaf {
                 page,
@@ -48,15 +60,15 @@ impl<'a> RangeIterState<'a> {
                 let direction = if reverse { -1 } else { 1 };
                 let next_entry = isize::try_from(entry).unwrap() + direction;
                 if 0 <= next_entry && next_entry < accessor.num_pairs().try_into().unwrap() {
-                    Some(Leaf {
+                    Ok(Some(Leaf {
                         page,
                         fixed_key_size,
                         fixed_value_size,
                         entry: next_entry.try_into().unwrap(),
                         parent,
-                    })
+                    }))
                 } else {
-                    parent.map(|x| *x)
+                    Ok(parent.map(|x| unbox_state(x, arena)))
                 }
             }
             Internal {
@@ -67,18 +79,39 @@ impl<'a> RangeIterState<'a> {
                 mut parent,
             } => {
                 let accessor = BranchAccessor::new(&page, fixed_key_size);
-                let child_page = accessor.child_page(child).unwrap();
-                let child_page = manager.get_page(child_page);
                 let direction = if reverse { -1 } else { 1 };
+                let read_ahead = isize::try_from(manager.read_ahead()).unwrap();
+                if read_ahead > 0 {
+                    // Start reading in the following siblings, in the direction of the scan, while
+                    // this child is consumed
+                    for distance in 1..=read_ahead {
+                        let sibling = isize::try_from(child).unwrap() + direction * distance;
+                        if 0 <= sibling && sibling < accessor.count_children().try_into().unwrap() {
+                            manager.prefetch_page(
+                                accessor.child_page(sibling.try_into().unwrap()).unwrap(),
+                            );
+                        }
+                    }
+                }
+                let child_page = read_page(
+                    manager,
+                    accessor.child_page(child).unwrap(),
+                    accessor.child_checksum(child).unwrap(),
+                    fixed_key_size,
+                    fixed_value_size,
+                )?;
                 let next_child = isize::try_from(child).unwrap() + direction;
                 if 0 <= next_child && next_child < accessor.count_children().try_into().unwrap() {
-                    parent = Some(Box::new(Internal {
-                        page,
-                        fixed_key_size,
-                        fixed_value_size,
-                        child: next_child.try_into().unwrap(),
-                        parent,
-                    }));
+                    parent = Some(box_state(
+                        Internal {
+                            page,
+                            fixed_key_size,
+                            fixed_value_size,
+                            child: next_child.try_into().unwrap(),
+                            parent,
+                        },
+                        arena,
+                    ));
                 }
                 match child_page.memory()[0] {
                     LEAF => {
@@ -87,18 +120,24 @@ impl<'a> RangeIterState<'a> {
                             fixed_key_size,
                             fixed_value_size,
                         );
+                        if child_accessor.num_pairs() == 0 {
+                            return Err(Error::corrupted_page(
+                                child_page.get_page_number(),
+                                format!("Leaf page {:?} is empty", child_page.get_page_number()),
+                            ));
+                        }
                         let entry = if reverse {
                             child_accessor.num_pairs() - 1
                         } else {
                             0
                         };
-                        Some(Leaf {
+                        Ok(Some(Leaf {
                             page: child_page,
                             fixed_key_size,
                             fixed_value_size,
                             entry,
                             parent,
-                        })
+                        }))
                     }
                     BRANCH => {
                         let child_accessor = BranchAccessor::new(&child_page, fixed_key_size);
@@ -107,15 +146,22 @@ impl<'a> RangeIterState<'a> {
                         } else {
                             0
                         };
-                        Some(Internal {
+                        Ok(Some(Internal {
                             page: child_page,
                             fixed_key_size,
                             fixed_value_size,
                             child,
                             parent,
-                        })
+                        }))
                     }
-                    _ => unreachable!(),
+                    page_type => Err(Error::corrupted_page(
+                        child_page.get_page_number(),
+                        format!(
+                            "Page {:?} has invalid type {}",
+                            child_page.get_page_number(),
+                            page_type
+                        ),
+                    )),
                 }
             }
         }
@@ -151,8 +197,9 @@ impl<'a> AllPageNumbersBtreeIter<'a> {
         fixed_key_size: Option<usize>,
         fixed_value_size: Option<usize>,
         manager: &'a TransactionalMemory,
-    ) -> Self {
-        let root_page = manager.get_page(root);
+    ) -> Result<Self> {
+        manager.check_page_number(root)?;
+        let root_page = manager.get_page(root)?;
         let node_mem = root_page.memory();
         let start = match node_mem[0] {
             LEAF => Leaf {
@@ -169,17 +216,23 @@ impl<'a> AllPageNumbersBtreeIter<'a> {
                 child: 0,
                 parent: None,
             },
-            _ => unreachable!(),
+            _ => {
+                return Err(Error::corrupted_page(
+                    root,
+                    format!("page {:?} is not a b-tree page", root),
+                ))
+            }
         };
-        Self {
+        Ok(Self {
             next: Some(start),
             manager,
-        }
+        })
     }
 }
 
+// Returns an error, and then ends, if a corrupted page is encountered
 impl<'a> Iterator for AllPageNumbersBtreeIter<'a> {
-    type Item = PageNumber;
+    type Item = Result<PageNumber>;
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
@@ -190,20 +243,64 @@ impl<'a> Iterator for AllPageNumbersBtreeIter<'a> {
                 Leaf { entry, .. } => entry == 0,
                 Internal { child, .. } => child == 0,
             };
-            self.next = state.next(false, self.manager);
+            match state.next(false, self.manager, None) {
+                Ok(next) => self.next = next,
+                Err(err) => return Some(Err(err)),
+            }
             if once {
-                return Some(value);
+                return Some(Ok(value));
             }
         }
     }
 }
 
+// Boxes the parent of a state, in an allocation from the write transaction's arena if there is one
+fn box_state<'a>(state: RangeIterState<'a>, arena: Option<&Arena>) -> Box<RangeIterState<'a>> {
+    match arena {
+        Some(arena) => arena.box_state(state),
+        None => Box::new(state),
+    }
+}
+
+fn unbox_state<'a>(state: Box<RangeIterState<'a>>, arena: Option<&Arena>) -> RangeIterState<'a> {
+    match arena {
+        Some(arena) => arena.unbox_state(state),
+        None => *state,
+    }
+}
+
+pub(super) fn serialize_bound<'r, 'b: 'r, K: RedbKey + ?Sized + 'b, KR>(
+    bound: Bound<&'r KR>,
+) -> Bound<K::AsBytes<'r>>
+where
+    KR: Borrow<K::RefBaseType<'b>> + ?Sized + 'r,
+{
+    match bound {
+        Bound::Included(k) => Bound::Included(K::as_bytes(k.borrow())),
+        Bound::Excluded(k) => Bound::Excluded(K::as_bytes(k.borrow())),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+pub(super) fn as_slice_bound<T: AsRef<[u8]>>(bound: &Bound<T>) -> Bound<&[u8]> {
+    match bound {
+        Bound::Included(x) => Bound::Included(x.as_ref()),
+        Bound::Excluded(x) => Bound::Excluded(x.as_ref()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
 pub struct BtreeRangeIter<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> {
     left: Option<RangeIterState<'a>>, // Exclusive. The previous element returned
     right: Option<RangeIterState<'a>>, // Exclusive. The previous element returned
     include_left: bool,               // left is inclusive, instead of exclusive
     include_right: bool,              // right is inclusive, instead of exclusive
+    // Error encountered while positioning the iterator, which is returned by the next call to
+    // try_next() or try_next_back(). Boxed, since errors are rare and large
+    error: Option<Box<Error>>,
     manager: &'a TransactionalMemory,
+    // Set when iterating within a write transaction
+    arena: Option<&'a Arena>,
     _key_type: PhantomData<K>,
     _value_type: PhantomData<V>,
 }
@@ -215,84 +312,97 @@ impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> BtreeRangeIter<'a
         KR: Borrow<K::RefBaseType<'a0>> + ?Sized + 'a0,
     >(
         query_range: T,
-        table_root: Option<PageNumber>,
+        table_root: Option<(PageNumber, Checksum)>,
         manager: &'a TransactionalMemory,
+        arena: Option<&'a Arena>,
     ) -> Self
     where
         'a: 'a0,
     {
-        if let Some(root) = table_root {
-            let (include_left, left) = match query_range.start_bound() {
-                Bound::Included(k) => find_iter_left::<K, V>(
-                    manager.get_page(root),
-                    None,
-                    K::as_bytes(k.borrow()).as_ref(),
-                    true,
-                    manager,
-                ),
-                Bound::Excluded(k) => find_iter_left::<K, V>(
-                    manager.get_page(root),
-                    None,
-                    K::as_bytes(k.borrow()).as_ref(),
-                    false,
-                    manager,
-                ),
-                Bound::Unbounded => {
-                    let state =
-                        find_iter_unbounded::<K, V>(manager.get_page(root), None, false, manager);
-                    (true, state)
-                }
-            };
-            let (include_right, right) = match query_range.end_bound() {
-                Bound::Included(k) => find_iter_right::<K, V>(
-                    manager.get_page(root),
-                    None,
-                    K::as_bytes(k.borrow()).as_ref(),
-                    true,
-                    manager,
-                ),
-                Bound::Excluded(k) => find_iter_right::<K, V>(
-                    manager.get_page(root),
-                    None,
-                    K::as_bytes(k.borrow()).as_ref(),
-                    false,
-                    manager,
-                ),
-                Bound::Unbounded => {
-                    let state =
-                        find_iter_unbounded::<K, V>(manager.get_page(root), None, true, manager);
-                    (true, state)
-                }
-            };
-            Self {
-                left,
-                right,
-                include_left,
-                include_right,
-                manager,
-                _key_type: Default::default(),
-                _value_type: Default::default(),
-            }
-        } else {
-            Self {
-                left: None,
-                right: None,
-                include_left: false,
-                include_right: false,
-                manager,
-                _key_type: Default::default(),
-                _value_type: Default::default(),
+        let start = serialize_bound::<K, KR>(query_range.start_bound());
+        let end = serialize_bound::<K, KR>(query_range.end_bound());
+        Self::new_serialized(
+            as_slice_bound(&start),
+            as_slice_bound(&end),
+            table_root,
+            manager,
+            arena,
+        )
+    }
+
+    // Like new(), but with bounds that are already serialized
+    pub(crate) fn new_serialized(
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        table_root: Option<(PageNumber, Checksum)>,
+        manager: &'a TransactionalMemory,
+        arena: Option<&'a Arena>,
+    ) -> Self {
+        let mut result = Self {
+            left: None,
+            right: None,
+            include_left: false,
+            include_right: false,
+            error: None,
+            manager,
+            arena,
+            _key_type: Default::default(),
+            _value_type: Default::default(),
+        };
+        if let Some((root, checksum)) = table_root {
+            if let Err(err) = result.position(start, end, root, checksum) {
+                result.left = None;
+                result.right = None;
+                result.error = Some(Box::new(err));
             }
         }
+        result
     }
-}
 
-impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> Iterator
-    for BtreeRangeIter<'a, K, V>
-{
-    type Item = EntryAccessor<'a>;
+    fn position(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        root: PageNumber,
+        checksum: Checksum,
+    ) -> Result {
+        let manager = self.manager;
+        let arena = self.arena;
+        let root_page = || read_page(manager, root, checksum, K::fixed_width(), V::fixed_width());
+        (self.include_left, self.left) = match start {
+            Bound::Included(k) => {
+                find_iter_left::<K, V>(root_page()?, None, k, true, manager, arena)?
+            }
+            Bound::Excluded(k) => {
+                find_iter_left::<K, V>(root_page()?, None, k, false, manager, arena)?
+            }
+            Bound::Unbounded => {
+                let state = find_iter_unbounded::<K, V>(root_page()?, None, false, manager, arena)?;
+                (true, state)
+            }
+        };
+        (self.include_right, self.right) = match end {
+            Bound::Included(k) => {
+                find_iter_right::<K, V>(root_page()?, None, k, true, manager, arena)?
+            }
+            Bound::Excluded(k) => {
+                find_iter_right::<K, V>(root_page()?, None, k, false, manager, arena)?
+            }
+            Bound::Unbounded => {
+                let state = find_iter_unbounded::<K, V>(root_page()?, None, true, manager, arena)?;
+                (true, state)
+            }
+        };
+        Ok(())
+    }
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
+impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> BtreeRangeIter<'a, K, V> {
+    // Returns the next entry, or an error if a corrupted page is encountered
+    pub(crate) fn try_next(&mut self) -> Result<Option<EntryAccessor<'a>>> {
+        if let Some(err) = self.error.take() {
+            return Err(*err);
+        }
         if let (
             Some(Leaf {
                 page: left_page,
@@ -310,16 +420,20 @@ impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> Iterator
                 && (left_entry > right_entry
                     || (left_entry == right_entry && (!self.include_left || !self.include_right)))
             {
-                return None;
+                return Ok(None);
             }
         }
 
         loop {
             if !self.include_left {
-                self.left = self.left.take()?.next(false, self.manager);
+                if let Some(left) = self.left.take() {
+                    self.left = left.next(false, self.manager, self.arena)?;
+                }
             }
             // Return None if the next state is None
-            self.left.as_ref()?;
+            if self.left.is_none() {
+                return Ok(None);
+            }
 
             if let (
                 Some(Leaf {
@@ -338,22 +452,22 @@ impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> Iterator
                     && (left_entry > right_entry
                         || (left_entry == right_entry && !self.include_right))
                 {
-                    return None;
+                    return Ok(None);
                 }
             }
 
             self.include_left = false;
             if self.left.as_ref().unwrap().get_entry().is_some() {
-                return self.left.as_ref().map(|s| s.get_entry().unwrap());
+                return Ok(self.left.as_ref().map(|s| s.get_entry().unwrap()));
             }
         }
     }
-}
 
-impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> DoubleEndedIterator
-    for BtreeRangeIter<'a, K, V>
-{
-    fn next_back(&mut self) -> Option<Self::Item> {
+    // Returns the next entry from the end, or an error if a corrupted page is encountered
+    pub(crate) fn try_next_back(&mut self) -> Result<Option<EntryAccessor<'a>>> {
+        if let Some(err) = self.error.take() {
+            return Err(*err);
+        }
         if let (
             Some(Leaf {
                 page: left_page,
@@ -371,16 +485,20 @@ impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> DoubleEndedIterat
                 && (left_entry > right_entry
                     || (left_entry == right_entry && (!self.include_left || !self.include_right)))
             {
-                return None;
+                return Ok(None);
             }
         }
 
         loop {
             if !self.include_right {
-                self.right = self.right.take()?.next(true, self.manager);
+                if let Some(right) = self.right.take() {
+                    self.right = right.next(true, self.manager, self.arena)?;
+                }
             }
             // Return None if the next state is None
-            self.right.as_ref()?;
+            if self.right.is_none() {
+                return Ok(None);
+            }
 
             if let (
                 Some(Leaf {
@@ -399,16 +517,161 @@ impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> DoubleEndedIterat
                     && (left_entry > right_entry
                         || (left_entry == right_entry && !self.include_left))
                 {
-                    return None;
+                    return Ok(None);
                 }
             }
 
             self.include_right = false;
             if self.right.as_ref().unwrap().get_entry().is_some() {
-                return self.right.as_ref().map(|s| s.get_entry().unwrap());
+                return Ok(self.right.as_ref().map(|s| s.get_entry().unwrap()));
             }
         }
     }
+
+    // Like try_next(), for the implementations of Iterator, which can't return errors. Panics if
+    // a corrupted page is encountered
+    pub(crate) fn next_or_panic(&mut self) -> Option<EntryAccessor<'a>> {
+        self.try_next().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    // Like try_next_back(), for the implementations of DoubleEndedIterator. Panics if a corrupted
+    // page is encountered
+    pub(crate) fn next_back_or_panic(&mut self) -> Option<EntryAccessor<'a>> {
+        self.try_next_back().unwrap_or_else(|err| panic!("{}", err))
+    }
+}
+
+impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> Drop for BtreeRangeIter<'a, K, V> {
+    fn drop(&mut self) {
+        // Returns the allocations of the parent states to the arena, for the next iterator
+        if let Some(arena) = self.arena {
+            for state in [self.left.take(), self.right.take()].into_iter().flatten() {
+                let mut parent = state.into_parent();
+                while let Some(state) = parent {
+                    parent = arena.unbox_state(state).into_parent();
+                }
+            }
+        }
+    }
+}
+
+enum CursorPosition<'a> {
+    Unpositioned,
+    BeforeStart,
+    At(EntryAccessor<'a>),
+    AfterEnd,
+}
+
+pub(crate) struct BtreeCursor<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> {
+    root: Option<(PageNumber, Checksum)>,
+    position: CursorPosition<'a>,
+    // Iterator which continues from position, and whether it runs in reverse. This is rebuilt
+    // when the cursor is repositioned, or changes direction
+    iter: Option<(BtreeRangeIter<'a, K, V>, bool)>,
+    manager: &'a TransactionalMemory,
+    arena: Option<&'a Arena>,
+}
+
+impl<'a, K: RedbKey + ?Sized + 'a, V: RedbValue + ?Sized + 'a> BtreeCursor<'a, K, V> {
+    pub(crate) fn new(
+        table_root: Option<(PageNumber, Checksum)>,
+        manager: &'a TransactionalMemory,
+        arena: Option<&'a Arena>,
+    ) -> Self {
+        Self {
+            root: table_root,
+            position: CursorPosition::Unpositioned,
+            iter: None,
+            manager,
+            arena,
+        }
+    }
+
+    pub(crate) fn current(&self) -> Option<EntryAccessor<'a>> {
+        match &self.position {
+            CursorPosition::At(entry) => Some(entry.clone()),
+            _ => None,
+        }
+    }
+
+    // Positions the cursor at the first key >= key
+    pub(crate) fn seek(&mut self, key: &[u8]) -> Result<Option<EntryAccessor<'a>>> {
+        self.reposition(Bound::Included(key), Bound::Unbounded, false)
+    }
+
+    // Positions the cursor at the last key <= key
+    pub(crate) fn seek_for_prev(&mut self, key: &[u8]) -> Result<Option<EntryAccessor<'a>>> {
+        self.reposition(Bound::Unbounded, Bound::Included(key), true)
+    }
+
+    pub(crate) fn next(&mut self) -> Result<Option<EntryAccessor<'a>>> {
+        if let Some((iter, false)) = self.iter.as_mut() {
+            let entry = iter.try_next()?;
+            return Ok(self.update_position(entry, false));
+        }
+        match &self.position {
+            CursorPosition::Unpositioned | CursorPosition::BeforeStart => {
+                self.reposition(Bound::Unbounded, Bound::Unbounded, false)
+            }
+            CursorPosition::At(entry) => {
+                let key = entry.key();
+                self.reposition(Bound::Excluded(key), Bound::Unbounded, false)
+            }
+            CursorPosition::AfterEnd => Ok(None),
+        }
+    }
+
+    pub(crate) fn prev(&mut self) -> Result<Option<EntryAccessor<'a>>> {
+        if let Some((iter, true)) = self.iter.as_mut() {
+            let entry = iter.try_next_back()?;
+            return Ok(self.update_position(entry, true));
+        }
+        match &self.position {
+            CursorPosition::Unpositioned | CursorPosition::AfterEnd => {
+                self.reposition(Bound::Unbounded, Bound::Unbounded, true)
+            }
+            CursorPosition::At(entry) => {
+                let key = entry.key();
+                self.reposition(Bound::Unbounded, Bound::Excluded(key), true)
+            }
+            CursorPosition::BeforeStart => Ok(None),
+        }
+    }
+
+    fn reposition(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        reverse: bool,
+    ) -> Result<Option<EntryAccessor<'a>>> {
+        let mut iter =
+            BtreeRangeIter::new_serialized(start, end, self.root, self.manager, self.arena);
+        let entry = if reverse {
+            iter.try_next_back()?
+        } else {
+            iter.try_next()?
+        };
+        self.iter = Some((iter, reverse));
+        Ok(self.update_position(entry, reverse))
+    }
+
+    fn update_position(
+        &mut self,
+        entry: Option<EntryAccessor<'a>>,
+        reverse: bool,
+    ) -> Option<EntryAccessor<'a>> {
+        if let Some(ref entry) = entry {
+            self.position = CursorPosition::At(entry.clone());
+        } else {
+            self.iter = None;
+            self.position = if reverse {
+                CursorPosition::BeforeStart
+            } else {
+                CursorPosition::AfterEnd
+            };
+        }
+        entry
+    }
 }
 
 fn find_iter_unbounded<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized>(
@@ -416,19 +679,20 @@ fn find_iter_unbounded<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized>(
     mut parent: Option<Box<RangeIterState<'a>>>,
     reverse: bool,
     manager: &'a TransactionalMemory,
-) -> Option<RangeIterState<'a>> {
+    arena: Option<&Arena>,
+) -> Result<Option<RangeIterState<'a>>> {
     let node_mem = page.memory();
     match node_mem[0] {
         LEAF => {
             let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
             let entry = if reverse { accessor.num_pairs() - 1 } else { 0 };
-            Some(Leaf {
+            Ok(Some(Leaf {
                 page,
                 fixed_key_size: K::fixed_width(),
                 fixed_value_size: V::fixed_width(),
                 entry,
                 parent,
-            })
+            }))
         }
         BRANCH => {
             let accessor = BranchAccessor::new(&page, K::fixed_width());
@@ -437,19 +701,27 @@ fn find_iter_unbounded<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized>(
             } else {
                 0
             };
-            let child_page_number = accessor.child_page(child_index).unwrap();
-            let child_page = manager.get_page(child_page_number);
+            let child_page = read_page(
+                manager,
+                accessor.child_page(child_index).unwrap(),
+                accessor.child_checksum(child_index).unwrap(),
+                K::fixed_width(),
+                V::fixed_width(),
+            )?;
             let direction = if reverse { -1isize } else { 1 };
-            parent = Some(Box::new(Internal {
-                page,
-                fixed_key_size: K::fixed_width(),
-                fixed_value_size: V::fixed_width(),
-                child: (isize::try_from(child_index).unwrap() + direction)
-                    .try_into()
-                    .unwrap(),
-                parent,
-            }));
-            find_iter_unbounded::<K, V>(child_page, parent, reverse, manager)
+            parent = Some(box_state(
+                Internal {
+                    page,
+                    fixed_key_size: K::fixed_width(),
+                    fixed_value_size: V::fixed_width(),
+                    child: (isize::try_from(child_index).unwrap() + direction)
+                        .try_into()
+                        .unwrap(),
+                    parent,
+                },
+                arena,
+            ));
+            find_iter_unbounded::<K, V>(child_page, parent, reverse, manager, arena)
         }
         _ => unreachable!(),
     }
@@ -463,7 +735,8 @@ fn find_iter_left<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized>(
     query: &[u8],
     include_query: bool,
     manager: &'a TransactionalMemory,
-) -> (bool, Option<RangeIterState<'a>>) {
+    arena: Option<&Arena>,
+) -> Result<(bool, Option<RangeIterState<'a>>)> {
     let node_mem = page.memory();
     match node_mem[0] {
         LEAF => {
@@ -484,22 +757,31 @@ fn find_iter_left<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized>(
                 entry: position,
                 parent,
             };
-            (include, Some(result))
+            Ok((include, Some(result)))
         }
         BRANCH => {
             let accessor = BranchAccessor::new(&page, K::fixed_width());
             let (child_index, child_page_number) = accessor.child_for_key::<K>(query);
-            let child_page = manager.get_page(child_page_number);
+            let child_page = read_page(
+                manager,
+                child_page_number,
+                accessor.child_checksum(child_index).unwrap(),
+                K::fixed_width(),
+                V::fixed_width(),
+            )?;
             if child_index < accessor.count_children() - 1 {
-                parent = Some(Box::new(Internal {
-                    page,
-                    fixed_key_size: K::fixed_width(),
-                    fixed_value_size: V::fixed_width(),
-                    child: child_index + 1,
-                    parent,
-                }));
+                parent = Some(box_state(
+                    Internal {
+                        page,
+                        fixed_key_size: K::fixed_width(),
+                        fixed_value_size: V::fixed_width(),
+                        child: child_index + 1,
+                        parent,
+                    },
+                    arena,
+                ));
             }
-            find_iter_left::<K, V>(child_page, parent, query, include_query, manager)
+            find_iter_left::<K, V>(child_page, parent, query, include_query, manager, arena)
         }
         _ => unreachable!(),
     }
@@ -511,7 +793,8 @@ fn find_iter_right<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized>(
     query: &[u8],
     include_query: bool,
     manager: &'a TransactionalMemory,
-) -> (bool, Option<RangeIterState<'a>>) {
+    arena: Option<&Arena>,
+) -> Result<(bool, Option<RangeIterState<'a>>)> {
     let node_mem = page.memory();
     match node_mem[0] {
         LEAF => {
@@ -532,22 +815,31 @@ fn find_iter_right<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized>(
                 entry: position,
                 parent,
             };
-            (include, Some(result))
+            Ok((include, Some(result)))
         }
         BRANCH => {
             let accessor = BranchAccessor::new(&page, K::fixed_width());
             let (child_index, child_page_number) = accessor.child_for_key::<K>(query);
-            let child_page = manager.get_page(child_page_number);
+            let child_page = read_page(
+                manager,
+                child_page_number,
+                accessor.child_checksum(child_index).unwrap(),
+                K::fixed_width(),
+                V::fixed_width(),
+            )?;
             if child_index > 0 && accessor.child_page(child_index - 1).is_some() {
-                parent = Some(Box::new(Internal {
-                    page,
-                    fixed_key_size: K::fixed_width(),
-                    fixed_value_size: V::fixed_width(),
-                    child: child_index - 1,
-                    parent,
-                }));
+                parent = Some(box_state(
+                    Internal {
+                        page,
+                        fixed_key_size: K::fixed_width(),
+                        fixed_value_size: V::fixed_width(),
+                        child: child_index - 1,
+                        parent,
+                    },
+                    arena,
+                ));
             }
-            find_iter_right::<K, V>(child_page, parent, query, include_query, manager)
+            find_iter_right::<K, V>(child_page, parent, query, include_query, manager, arena)
         }
         _ => unreachable!(),
     }
@@ -1,3 +1,4 @@
+use crate::tree_store::btree_base::subtree_entries;
 use crate::tree_store::btree_base::{
     branch_checksum, leaf_checksum, BranchAccessor, BranchBuilder, BranchMutator, Checksum,
     FreePolicy, LeafAccessor, LeafBuilder, LeafMutator, BRANCH, LEAF,
@@ -9,9 +10,12 @@ use crate::tree_store::page_store::{ChecksumType, Page, PageImpl};
 use crate::tree_store::{AccessGuardMut, PageNumber, TransactionalMemory};
 use crate::types::{RedbKey, RedbValue};
 use crate::{AccessGuard, Result};
+use std::borrow::Cow;
 use std::cell::RefCell;
-use std::cmp::{max, min};
+use std::cmp::{max, min, Ordering};
 use std::marker::PhantomData;
+use std::mem;
+use std::ops::Bound;
 use std::rc::Rc;
 
 #[derive(Debug)]
@@ -28,15 +32,65 @@ enum DeletionResult {
     DeletedBranch(PageNumber, Checksum),
 }
 
+enum TruncationResult {
+    // No entries were removed
+    Unchanged,
+    // All entries were removed
+    Empty,
+    // The remaining entries, in a subtree which is `reduced_height` levels shorter than the original
+    Truncated {
+        page: PageNumber,
+        checksum: Checksum,
+        reduced_height: usize,
+    },
+}
+
+enum GraftResult {
+    Single(PageNumber, Checksum),
+    Split(PageNumber, Checksum, Vec<u8>, PageNumber, Checksum),
+}
+
+// The value written by insert_helper()
+#[allow(clippy::type_complexity)]
+enum InsertValue<'v> {
+    // Replaces any existing value
+    Replace(&'v [u8]),
+    // Only written if the key is absent
+    IfAbsent(&'v [u8]),
+    // Only written if the key is absent. It's computed once the leaf has been found, so that the
+    // work can be skipped when the key is already present
+    IfAbsentWith(Box<dyn FnOnce() -> Vec<u8> + 'v>),
+    // Computed from the existing value, if any, once the leaf has been found. If this fails, the
+    // tree is left unchanged
+    Merge(Box<dyn FnOnce(Option<&[u8]>) -> Result<Vec<u8>> + 'v>),
+}
+
+impl<'v> InsertValue<'v> {
+    fn into_bytes(self, existing: Option<&[u8]>) -> Result<Cow<'v, [u8]>> {
+        Ok(match self {
+            InsertValue::Replace(value) | InsertValue::IfAbsent(value) => Cow::Borrowed(value),
+            InsertValue::IfAbsentWith(value_fn) => Cow::Owned(value_fn()),
+            InsertValue::Merge(merge_fn) => Cow::Owned(merge_fn(existing)?),
+        })
+    }
+}
+
 struct InsertionResult<'a, K: RedbKey + ?Sized, V: RedbValue + ?Sized> {
     // the new root page
     new_root: PageNumber,
     // checksum of the root page
     root_checksum: Checksum,
-    // Following sibling, if the root had to be split
-    additional_sibling: Option<(Vec<u8>, PageNumber, Checksum)>,
-    // The inserted value for .insert_reserve() to use
-    inserted_value: AccessGuardMut<'a, K, V>,
+    // Following siblings, if the root had to be split. Each has the key separating it from the
+    // page before it, and its number of entries
+    additional_siblings: Vec<(Vec<u8>, PageNumber, Checksum, u64)>,
+    // Number of entries in new_root and its siblings. Only maintained in counted trees, since
+    // pages holding the inserted value can't be read until it's dropped
+    root_entries: u64,
+    // True if the key was greater than every key in the tree, and so it was placed in a new page
+    // at the right edge, leaving the pages before it full
+    appended: bool,
+    // The inserted value for .insert_reserve() to use, or None if the existing value was kept
+    inserted_value: Option<AccessGuardMut<'a, K, V>>,
     // The previous value, if any
     old_value: Option<AccessGuard<'a, V>>,
 }
@@ -46,6 +100,10 @@ pub(crate) struct MutateHelper<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Size
     free_policy: FreePolicy,
     mem: &'a TransactionalMemory,
     freed: &'b mut Vec<PageNumber>,
+    // Whether branches store the number of entries in the subtree of each child
+    counted: bool,
+    // Set by inserts, when the key was greater than every key in the tree
+    appended_key: bool,
     _key_type: PhantomData<K>,
     _value_type: PhantomData<V>,
 }
@@ -56,17 +114,25 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
         free_policy: FreePolicy,
         mem: &'a TransactionalMemory,
         freed: &'b mut Vec<PageNumber>,
+        counted: bool,
     ) -> Self {
         Self {
             root,
             free_policy,
             mem,
             freed,
+            counted,
+            appended_key: false,
             _key_type: Default::default(),
             _value_type: Default::default(),
         }
     }
 
+    // Returns true if the last insert was of a key greater than every key already in the tree
+    pub(crate) fn appended_key(&self) -> bool {
+        self.appended_key
+    }
+
     pub(crate) fn safe_delete(
         &mut self,
         key: &K::RefBaseType<'_>,
@@ -84,12 +150,12 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
         let root = { *(*self.root.clone()).borrow() };
         if let Some((p, checksum)) = root {
             let (deletion_result, found) =
-                self.delete_helper(self.mem.get_page(p), checksum, K::as_bytes(key).as_ref())?;
+                self.delete_helper(self.mem.get_page(p)?, checksum, K::as_bytes(key).as_ref())?;
             let new_root = match deletion_result {
                 Subtree(page, checksum) => Some((page, checksum)),
                 DeletedLeaf => None,
                 PartialLeaf { deleted_pair } => {
-                    let page = self.mem.get_page(p);
+                    let page = self.mem.get_page(p)?;
                     let accessor =
                         LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
                     let mut builder = LeafBuilder::new(
@@ -112,6 +178,600 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
         }
     }
 
+    // Removes all entries with keys within the given bounds, and returns the number of entries
+    // removed. Subtrees which lie entirely within the bounds are freed, without being rewritten
+    //
+    // Safety: caller must ensure that no references to uncommitted pages in this table exist
+    pub(crate) unsafe fn delete_range(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+    ) -> Result<u64> {
+        if let (
+            Bound::Included(start) | Bound::Excluded(start),
+            Bound::Included(end) | Bound::Excluded(end),
+        ) = (start, end)
+        {
+            if K::compare(start, end) == Ordering::Greater {
+                return Ok(0);
+            }
+        }
+        let root = { *(*self.root.clone()).borrow() };
+        let mut removed = 0;
+        if let Some((p, _)) = root {
+            match self.delete_range_helper(p, start, end, &mut removed)? {
+                TruncationResult::Unchanged => {}
+                TruncationResult::Empty => {
+                    *self.root.borrow_mut() = None;
+                }
+                TruncationResult::Truncated { page, checksum, .. } => {
+                    *self.root.borrow_mut() = Some((page, checksum));
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    // Removes all entries, and frees every page of the tree
+    //
+    // Safety: caller must ensure that no references to uncommitted pages in this table exist
+    pub(crate) unsafe fn clear(&mut self) -> Result {
+        let root = { *(*self.root.clone()).borrow() };
+        if let Some((p, _)) = root {
+            let mut removed = 0;
+            self.free_subtree(p, &mut removed)?;
+            *self.root.borrow_mut() = None;
+        }
+        Ok(())
+    }
+
+    // Safety: caller must ensure that no references to uncommitted pages in this table exist
+    unsafe fn free_subtree(&mut self, page_number: PageNumber, removed: &mut u64) -> Result {
+        let page = self.mem.get_page(page_number)?;
+        match page.memory()[0] {
+            LEAF => {
+                let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
+                *removed += u64::try_from(accessor.num_pairs()).unwrap();
+            }
+            BRANCH => {
+                let accessor = BranchAccessor::new(&page, K::fixed_width());
+                for i in 0..accessor.count_children() {
+                    self.free_subtree(accessor.child_page(i).unwrap(), removed)?;
+                }
+            }
+            _ => unreachable!(),
+        }
+        drop(page);
+        self.free_policy
+            .conditional_free(page_number, self.freed, self.mem)
+    }
+
+    // Safety: caller must ensure that no references to uncommitted pages in this table exist
+    unsafe fn delete_range_helper(
+        &mut self,
+        page_number: PageNumber,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        removed: &mut u64,
+    ) -> Result<TruncationResult> {
+        let page = self.mem.get_page(page_number)?;
+        let result = match page.memory()[0] {
+            LEAF => {
+                let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
+                // Remove the entries in first..last
+                let first = match start {
+                    Bound::Included(key) => accessor.position::<K>(key).0,
+                    Bound::Excluded(key) => match accessor.position::<K>(key) {
+                        (position, true) => position + 1,
+                        (position, false) => position,
+                    },
+                    Bound::Unbounded => 0,
+                };
+                let last = match end {
+                    Bound::Included(key) => match accessor.position::<K>(key) {
+                        (position, true) => position + 1,
+                        (position, false) => position,
+                    },
+                    Bound::Excluded(key) => accessor.position::<K>(key).0,
+                    Bound::Unbounded => accessor.num_pairs(),
+                };
+                if first >= last {
+                    return Ok(TruncationResult::Unchanged);
+                }
+                *removed += u64::try_from(last - first).unwrap();
+                if first == 0 && last == accessor.num_pairs() {
+                    TruncationResult::Empty
+                } else {
+                    let mut builder = LeafBuilder::new(
+                        self.mem,
+                        accessor.num_pairs() - (last - first),
+                        K::fixed_width(),
+                        V::fixed_width(),
+                    );
+                    for i in (0..first).chain(last..accessor.num_pairs()) {
+                        let entry = accessor.entry(i).unwrap();
+                        builder.push(entry.key(), entry.value());
+                    }
+                    let new_page = builder.build()?;
+                    TruncationResult::Truncated {
+                        page: new_page.get_page_number(),
+                        checksum: self.checksum_helper(&new_page),
+                        reduced_height: 0,
+                    }
+                }
+            }
+            BRANCH => {
+                let accessor = BranchAccessor::new(&page, K::fixed_width());
+                let last_child = accessor.count_children() - 1;
+                let first_child = match start {
+                    Bound::Included(key) | Bound::Excluded(key) => {
+                        accessor.child_for_key::<K>(key).0
+                    }
+                    Bound::Unbounded => 0,
+                };
+                let end_child = match end {
+                    Bound::Included(key) | Bound::Excluded(key) => {
+                        accessor.child_for_key::<K>(key).0
+                    }
+                    Bound::Unbounded => last_child,
+                };
+
+                // Children strictly between first_child and end_child lie entirely within the range
+                let (mut first_result, mut end_result) = if first_child == end_child {
+                    let result = self.delete_range_helper(
+                        accessor.child_page(first_child).unwrap(),
+                        start,
+                        end,
+                        removed,
+                    )?;
+                    (result, TruncationResult::Unchanged)
+                } else {
+                    let first_result = self.delete_range_helper(
+                        accessor.child_page(first_child).unwrap(),
+                        start,
+                        Bound::Unbounded,
+                        removed,
+                    )?;
+                    let end_result = self.delete_range_helper(
+                        accessor.child_page(end_child).unwrap(),
+                        Bound::Unbounded,
+                        end,
+                        removed,
+                    )?;
+                    (first_result, end_result)
+                };
+                if end_child <= first_child + 1
+                    && matches!(first_result, TruncationResult::Unchanged)
+                    && matches!(end_result, TruncationResult::Unchanged)
+                {
+                    return Ok(TruncationResult::Unchanged);
+                }
+                for i in (first_child + 1)..end_child {
+                    self.free_subtree(accessor.child_page(i).unwrap(), removed)?;
+                }
+                let results: Vec<TruncationResult> = (0..=last_child)
+                    .map(|i| {
+                        if i == first_child {
+                            mem::replace(&mut first_result, TruncationResult::Unchanged)
+                        } else if i == end_child {
+                            mem::replace(&mut end_result, TruncationResult::Unchanged)
+                        } else if i > first_child && i < end_child {
+                            TruncationResult::Empty
+                        } else {
+                            TruncationResult::Unchanged
+                        }
+                    })
+                    .collect();
+                self.rebuild_branch(&accessor, results)?
+            }
+            _ => unreachable!(),
+        };
+        drop(page);
+        self.finish_truncation(page_number, result)
+    }
+
+    // Builds the replacement for a branch, given the result of truncating each of its children.
+    // Children which were removed entirely, must already have been freed
+    //
+    // Safety: caller must ensure that no references to uncommitted pages in this table exist
+    unsafe fn rebuild_branch<T: Page>(
+        &mut self,
+        accessor: &BranchAccessor<'_, '_, T>,
+        results: Vec<TruncationResult>,
+    ) -> Result<TruncationResult> {
+        let last_child = accessor.count_children() - 1;
+        // The remaining children, how much shorter each is than a child of this branch
+        // should be, and the separator key which follows each of them
+        let mut children: Vec<(PageNumber, Checksum, usize)> = vec![];
+        let mut separators: Vec<Vec<u8>> = vec![];
+        for (i, result) in results.iter().enumerate() {
+            match result {
+                TruncationResult::Unchanged => children.push((
+                    accessor.child_page(i).unwrap(),
+                    accessor.child_checksum(i).unwrap(),
+                    0,
+                )),
+                TruncationResult::Empty => continue,
+                TruncationResult::Truncated {
+                    page,
+                    checksum,
+                    reduced_height,
+                } => children.push((*page, *checksum, *reduced_height)),
+            }
+            if i < last_child {
+                separators.push(accessor.key(i).unwrap().to_vec());
+            } else {
+                separators.push(vec![]);
+            }
+        }
+        self.rebalance_children(&mut children, &mut separators)?;
+
+        Ok(match children.len() {
+            0 => TruncationResult::Empty,
+            1 => TruncationResult::Truncated {
+                page: children[0].0,
+                checksum: children[0].1,
+                reduced_height: children[0].2 + 1,
+            },
+            _ => {
+                let mut builder =
+                    BranchBuilder::new(self.mem, children.len(), K::fixed_width(), self.counted);
+                for (child, checksum, _) in children.iter() {
+                    builder.push_child(*child, *checksum);
+                }
+                for key in separators.iter().take(children.len() - 1) {
+                    builder.push_key(key);
+                }
+                let new_page = builder.build()?;
+                TruncationResult::Truncated {
+                    page: new_page.get_page_number(),
+                    checksum: self.checksum_helper(&new_page),
+                    reduced_height: 0,
+                }
+            }
+        })
+    }
+
+    // Removes all entries for which predicate returns false, and returns the number removed
+    //
+    // Safety: caller must ensure that no references to uncommitted pages in this table exist
+    pub(crate) unsafe fn retain<F>(&mut self, predicate: &mut F) -> Result<u64>
+    where
+        F: for<'f> FnMut(K::SelfType<'f>, V::SelfType<'f>) -> bool,
+    {
+        let root = { *(*self.root.clone()).borrow() };
+        let mut removed = 0;
+        if let Some((p, _)) = root {
+            match self.retain_helper(p, predicate, &mut removed)? {
+                TruncationResult::Unchanged => {}
+                TruncationResult::Empty => {
+                    *self.root.borrow_mut() = None;
+                }
+                TruncationResult::Truncated { page, checksum, .. } => {
+                    *self.root.borrow_mut() = Some((page, checksum));
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    // Safety: caller must ensure that no references to uncommitted pages in this table exist
+    unsafe fn retain_helper<F>(
+        &mut self,
+        page_number: PageNumber,
+        predicate: &mut F,
+        removed: &mut u64,
+    ) -> Result<TruncationResult>
+    where
+        F: for<'f> FnMut(K::SelfType<'f>, V::SelfType<'f>) -> bool,
+    {
+        let page = self.mem.get_page(page_number)?;
+        let result = match page.memory()[0] {
+            LEAF => {
+                let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
+                let kept: Vec<usize> = (0..accessor.num_pairs())
+                    .filter(|i| {
+                        let entry = accessor.entry(*i).unwrap();
+                        predicate(K::from_bytes(entry.key()), V::from_bytes(entry.value()))
+                    })
+                    .collect();
+                if kept.len() == accessor.num_pairs() {
+                    return Ok(TruncationResult::Unchanged);
+                }
+                *removed += u64::try_from(accessor.num_pairs() - kept.len()).unwrap();
+                if kept.is_empty() {
+                    TruncationResult::Empty
+                } else {
+                    let mut builder =
+                        LeafBuilder::new(self.mem, kept.len(), K::fixed_width(), V::fixed_width());
+                    for i in kept {
+                        let entry = accessor.entry(i).unwrap();
+                        builder.push(entry.key(), entry.value());
+                    }
+                    let new_page = builder.build()?;
+                    TruncationResult::Truncated {
+                        page: new_page.get_page_number(),
+                        checksum: self.checksum_helper(&new_page),
+                        reduced_height: 0,
+                    }
+                }
+            }
+            BRANCH => {
+                let accessor = BranchAccessor::new(&page, K::fixed_width());
+                let mut results = vec![];
+                for i in 0..accessor.count_children() {
+                    results.push(self.retain_helper(
+                        accessor.child_page(i).unwrap(),
+                        predicate,
+                        removed,
+                    )?);
+                }
+                if results
+                    .iter()
+                    .all(|x| matches!(x, TruncationResult::Unchanged))
+                {
+                    return Ok(TruncationResult::Unchanged);
+                }
+                self.rebuild_branch(&accessor, results)?
+            }
+            _ => unreachable!(),
+        };
+        drop(page);
+        self.finish_truncation(page_number, result)
+    }
+
+    // Combines children which are shorter than their siblings with a neighbor, until either all
+    // children have the same height, or only one remains
+    //
+    // Safety: caller must ensure that no references to uncommitted pages in this table exist
+    unsafe fn rebalance_children(
+        &mut self,
+        children: &mut Vec<(PageNumber, Checksum, usize)>,
+        separators: &mut Vec<Vec<u8>>,
+    ) -> Result {
+        while children.len() > 1 {
+            // Handle the shortest child first, so that its neighbors are at least as tall
+            let (j, &(page, checksum, reduced)) = children
+                .iter()
+                .enumerate()
+                .max_by_key(|(i, (_, _, reduced))| (*reduced, usize::MAX - *i))
+                .unwrap();
+            if reduced == 0 {
+                break;
+            }
+            let right = children.get(j + 1).map(|x| x.2);
+            let left = if j > 0 { Some(children[j - 1].2) } else { None };
+            if let Some(right_reduced) = right.filter(|x| *x < reduced) {
+                let graft = self.graft_helper(
+                    children[j + 1].0,
+                    (page, checksum),
+                    reduced - right_reduced,
+                    &separators[j],
+                )?;
+                let following = separators.remove(j + 1);
+                children.remove(j + 1);
+                self.replace_with_graft(children, separators, j, graft, right_reduced, following);
+            } else if let Some(left_reduced) = left.filter(|x| *x < reduced) {
+                let graft = self.graft_right_helper(
+                    children[j - 1].0,
+                    (page, checksum),
+                    reduced - left_reduced,
+                    &separators[j - 1],
+                )?;
+                let following = separators.remove(j);
+                children.remove(j);
+                self.replace_with_graft(
+                    children,
+                    separators,
+                    j - 1,
+                    graft,
+                    left_reduced,
+                    following,
+                );
+            } else {
+                // Both children have the same height, so join them with a new branch
+                let k = if right.is_some() { j } else { j - 1 };
+                let mut builder = BranchBuilder::new(self.mem, 2, K::fixed_width(), self.counted);
+                builder.push_child(children[k].0, children[k].1);
+                builder.push_key(&separators[k]);
+                builder.push_child(children[k + 1].0, children[k + 1].1);
+                let new_page = builder.build()?;
+                children[k] = (
+                    new_page.get_page_number(),
+                    self.checksum_helper(&new_page),
+                    reduced - 1,
+                );
+                children.remove(k + 1);
+                separators.remove(k);
+            }
+        }
+        Ok(())
+    }
+
+    // Replaces the child at index with the result of a graft, which is followed by separator
+    fn replace_with_graft(
+        &self,
+        children: &mut Vec<(PageNumber, Checksum, usize)>,
+        separators: &mut Vec<Vec<u8>>,
+        index: usize,
+        graft: GraftResult,
+        reduced: usize,
+        separator: Vec<u8>,
+    ) {
+        match graft {
+            GraftResult::Single(page, checksum) => {
+                children[index] = (page, checksum, reduced);
+                separators[index] = separator;
+            }
+            GraftResult::Split(page1, checksum1, key, page2, checksum2) => {
+                children[index] = (page1, checksum1, reduced);
+                separators[index] = key;
+                children.insert(index + 1, (page2, checksum2, reduced));
+                separators.insert(index + 1, separator);
+            }
+        }
+    }
+
+    // Safety: caller must ensure that no references to uncommitted pages in this table exist
+    unsafe fn finish_truncation(
+        &mut self,
+        page_number: PageNumber,
+        result: TruncationResult,
+    ) -> Result<TruncationResult> {
+        self.free_policy
+            .conditional_free(page_number, self.freed, self.mem)?;
+        Ok(result)
+    }
+
+    // Inserts `subtree` as the first child of the branch `depth` levels down the left edge of the
+    // subtree rooted at `page_number`. All keys in `subtree` must be less than or equal to
+    // `separator`, which must be less than all keys in the existing subtree
+    //
+    // Safety: caller must ensure that no references to uncommitted pages in this table exist
+    unsafe fn graft_helper(
+        &mut self,
+        page_number: PageNumber,
+        subtree: (PageNumber, Checksum),
+        depth: usize,
+        separator: &[u8],
+    ) -> Result<GraftResult> {
+        let page = self.mem.get_page(page_number)?;
+        debug_assert_eq!(page.memory()[0], BRANCH);
+        let accessor = BranchAccessor::new(&page, K::fixed_width());
+        let mut builder = BranchBuilder::new(
+            self.mem,
+            accessor.count_children() + 2,
+            K::fixed_width(),
+            self.counted,
+        );
+        let split_key: Vec<u8>;
+        if depth == 1 {
+            builder.push_child(subtree.0, subtree.1);
+            builder.push_key(separator);
+            builder.push_all(&accessor);
+        } else {
+            let graft = self.graft_helper(
+                accessor.child_page(0).unwrap(),
+                subtree,
+                depth - 1,
+                separator,
+            )?;
+            match graft {
+                GraftResult::Single(page, checksum) => {
+                    builder.push_child(page, checksum);
+                }
+                GraftResult::Split(page1, checksum1, key, page2, checksum2) => {
+                    split_key = key;
+                    builder.push_child(page1, checksum1);
+                    builder.push_key(&split_key);
+                    builder.push_child(page2, checksum2);
+                }
+            }
+            for i in 1..accessor.count_children() {
+                builder.push_child(
+                    accessor.child_page(i).unwrap(),
+                    accessor.child_checksum(i).unwrap(),
+                );
+            }
+            for i in 0..(accessor.count_children() - 1) {
+                builder.push_key(accessor.key(i).unwrap());
+            }
+        }
+
+        let result = if builder.should_split() {
+            let (new_page1, separator, new_page2) = builder.build_split()?;
+            GraftResult::Split(
+                new_page1.get_page_number(),
+                self.checksum_helper(&new_page1),
+                separator.to_vec(),
+                new_page2.get_page_number(),
+                self.checksum_helper(&new_page2),
+            )
+        } else {
+            let new_page = builder.build()?;
+            GraftResult::Single(new_page.get_page_number(), self.checksum_helper(&new_page))
+        };
+        drop(page);
+        self.free_policy
+            .conditional_free(page_number, self.freed, self.mem)?;
+
+        Ok(result)
+    }
+
+    // Inserts `subtree` as the last child of the branch `depth` levels down the right edge of the
+    // subtree rooted at `page_number`. All keys in `subtree` must be greater than `separator`, which
+    // must be greater than or equal to all keys in the existing subtree
+    //
+    // Safety: caller must ensure that no references to uncommitted pages in this table exist
+    unsafe fn graft_right_helper(
+        &mut self,
+        page_number: PageNumber,
+        subtree: (PageNumber, Checksum),
+        depth: usize,
+        separator: &[u8],
+    ) -> Result<GraftResult> {
+        let page = self.mem.get_page(page_number)?;
+        debug_assert_eq!(page.memory()[0], BRANCH);
+        let accessor = BranchAccessor::new(&page, K::fixed_width());
+        let mut builder = BranchBuilder::new(
+            self.mem,
+            accessor.count_children() + 2,
+            K::fixed_width(),
+            self.counted,
+        );
+        let split_key: Vec<u8>;
+        if depth == 1 {
+            builder.push_all(&accessor);
+            builder.push_key(separator);
+            builder.push_child(subtree.0, subtree.1);
+        } else {
+            let last_child = accessor.count_children() - 1;
+            for i in 0..last_child {
+                builder.push_child(
+                    accessor.child_page(i).unwrap(),
+                    accessor.child_checksum(i).unwrap(),
+                );
+                builder.push_key(accessor.key(i).unwrap());
+            }
+            let graft = self.graft_right_helper(
+                accessor.child_page(last_child).unwrap(),
+                subtree,
+                depth - 1,
+                separator,
+            )?;
+            match graft {
+                GraftResult::Single(page, checksum) => {
+                    builder.push_child(page, checksum);
+                }
+                GraftResult::Split(page1, checksum1, key, page2, checksum2) => {
+                    split_key = key;
+                    builder.push_child(page1, checksum1);
+                    builder.push_key(&split_key);
+                    builder.push_child(page2, checksum2);
+                }
+            }
+        }
+
+        let result = if builder.should_split() {
+            let (new_page1, separator, new_page2) = builder.build_split()?;
+            GraftResult::Split(
+                new_page1.get_page_number(),
+                self.checksum_helper(&new_page1),
+                separator.to_vec(),
+                new_page2.get_page_number(),
+                self.checksum_helper(&new_page2),
+            )
+        } else {
+            let new_page = builder.build()?;
+            GraftResult::Single(new_page.get_page_number(), self.checksum_helper(&new_page))
+        };
+        drop(page);
+        self.free_policy
+            .conditional_free(page_number, self.freed, self.mem)?;
+
+        Ok(result)
+    }
+
     // Safety: caller must ensure that no references to uncommitted pages in this tree exist
     #[allow(clippy::type_complexity)]
     pub(crate) unsafe fn insert(
@@ -119,31 +779,93 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
         key: &K::RefBaseType<'_>,
         value: &V::RefBaseType<'_>,
     ) -> Result<(Option<AccessGuard<'a, V>>, AccessGuardMut<'a, K, V>)> {
+        let value_bytes = V::as_bytes(value);
+        let (old_value, guard) =
+            self.insert_value(key, InsertValue::Replace(value_bytes.as_ref()))?;
+        Ok((old_value, guard.unwrap()))
+    }
+
+    // Inserts the value, unless the key is already present.
+    // Returns the existing value if the key was present, and otherwise the inserted value
+    // Safety: caller must ensure that no references to uncommitted pages in this tree exist
+    #[allow(clippy::type_complexity)]
+    pub(crate) unsafe fn insert_if_absent(
+        &mut self,
+        key: &K::RefBaseType<'_>,
+        value: &V::RefBaseType<'_>,
+    ) -> Result<(Option<AccessGuard<'a, V>>, Option<AccessGuardMut<'a, K, V>>)> {
+        let value_bytes = V::as_bytes(value);
+        self.insert_value(key, InsertValue::IfAbsent(value_bytes.as_ref()))
+    }
+
+    // Replaces the value of key with the result of merge_fn, which is passed the existing value
+    // Returns the existing value, if any. If merge_fn fails, the tree is unchanged
+    // Safety: caller must ensure that no references to uncommitted pages in this tree exist
+    pub(crate) unsafe fn merge<F: FnOnce(Option<&[u8]>) -> Result<Vec<u8>>>(
+        &mut self,
+        key: &K::RefBaseType<'_>,
+        merge_fn: F,
+    ) -> Result<Option<AccessGuard<'a, V>>> {
+        let (old_value, _) = self.insert_value(key, InsertValue::Merge(Box::new(merge_fn)))?;
+        Ok(old_value)
+    }
+
+    // Same as insert_if_absent(), except that the value is only computed if the key is absent
+    // Safety: caller must ensure that no references to uncommitted pages in this tree exist
+    #[allow(clippy::type_complexity)]
+    pub(crate) unsafe fn insert_if_absent_with<F: FnOnce() -> Vec<u8>>(
+        &mut self,
+        key: &K::RefBaseType<'_>,
+        value_fn: F,
+    ) -> Result<(Option<AccessGuard<'a, V>>, Option<AccessGuardMut<'a, K, V>>)> {
+        self.insert_value(key, InsertValue::IfAbsentWith(Box::new(value_fn)))
+    }
+
+    // Safety: caller must ensure that no references to uncommitted pages in this tree exist
+    #[allow(clippy::type_complexity)]
+    unsafe fn insert_value(
+        &mut self,
+        key: &K::RefBaseType<'_>,
+        value: InsertValue<'_>,
+    ) -> Result<(Option<AccessGuard<'a, V>>, Option<AccessGuardMut<'a, K, V>>)> {
         let root = { *(*self.root.clone()).borrow() };
         let (new_root, old_value, guard) = if let Some((p, checksum)) = root {
             let result = self.insert_helper(
-                self.mem.get_page(p),
+                self.mem.get_page(p)?,
                 checksum,
                 K::as_bytes(key).as_ref(),
-                V::as_bytes(value).as_ref(),
+                value,
+                true,
             )?;
 
-            let new_root = if let Some((key, page2, page2_checksum)) = result.additional_sibling {
-                let mut builder = BranchBuilder::new(self.mem, 2, K::fixed_width());
-                builder.push_child(result.new_root, result.root_checksum);
-                builder.push_key(&key);
-                builder.push_child(page2, page2_checksum);
+            let new_root = if result.additional_siblings.is_empty() {
+                (result.new_root, result.root_checksum)
+            } else {
+                let mut builder = BranchBuilder::new(
+                    self.mem,
+                    result.additional_siblings.len() + 1,
+                    K::fixed_width(),
+                    self.counted,
+                );
+                builder.push_child_with_entries(
+                    result.new_root,
+                    result.root_checksum,
+                    result.root_entries,
+                );
+                for (key, page, checksum, entries) in result.additional_siblings.iter() {
+                    builder.push_key(key);
+                    builder.push_child_with_entries(*page, *checksum, *entries);
+                }
                 let new_page = builder.build()?;
                 (new_page.get_page_number(), self.checksum_helper(&new_page))
-            } else {
-                (result.new_root, result.root_checksum)
             };
             (new_root, result.old_value, result.inserted_value)
         } else {
             let key_bytes = K::as_bytes(key);
-            let value_bytes = V::as_bytes(value);
+            let value_bytes = value.into_bytes(None)?;
             let key_bytes = key_bytes.as_ref();
             let value_bytes = value_bytes.as_ref();
+            self.appended_key = true;
             let mut builder = LeafBuilder::new(self.mem, 1, K::fixed_width(), V::fixed_width());
             builder.push(key_bytes, value_bytes);
             let page = builder.build()?;
@@ -152,32 +874,360 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
             let offset = accessor.offset_of_first_value();
             let page_num = page.get_page_number();
             let checksum = self.checksum_helper(&page);
-            let guard = AccessGuardMut::new(key_bytes, page, offset, value_bytes.len(), self.mem);
+            let guard = AccessGuardMut::new(page, offset, value_bytes.len(), self.mem);
 
-            ((page_num, checksum), None, guard)
+            ((page_num, checksum), None, Some(guard))
         };
         *self.root.borrow_mut() = Some(new_root);
         Ok((old_value, guard))
     }
 
+    // Inserts the given pairs, which must be sorted by key and contain no duplicate keys. Each page
+    // on the path to an inserted key is only rewritten once, no matter how many keys it receives
+    //
+    // Safety: caller must ensure that no references to uncommitted pages in this tree exist
+    pub(crate) unsafe fn insert_sorted(&mut self, pairs: &[(&[u8], &[u8])]) -> Result {
+        if pairs.is_empty() {
+            return Ok(());
+        }
+        let root = { *(*self.root.clone()).borrow() };
+        let (pages, separators) = if let Some((p, _)) = root {
+            self.insert_sorted_helper(self.mem.get_page(p)?, pairs)?
+        } else {
+            let mut builder =
+                LeafBuilder::new(self.mem, pairs.len(), K::fixed_width(), V::fixed_width());
+            for (key, value) in pairs {
+                builder.push(key, value);
+            }
+            self.build_leaves(builder)?
+        };
+        let new_root = self.build_branches(pages, separators)?;
+        *self.root.borrow_mut() = Some(new_root);
+        Ok(())
+    }
+
+    // Appends the given pairs, which must be sorted by key, after all existing keys. The new pages
+    // are built bottom-up, and then grafted onto the right edge of the tree
+    //
+    // Safety: caller must ensure that no references to uncommitted pages in this tree exist
+    pub(crate) unsafe fn append_sorted<KB: Into<Vec<u8>>, VB: Into<Vec<u8>>>(
+        &mut self,
+        pairs: impl Iterator<Item = (KB, VB)>,
+    ) -> Result {
+        let root = { *(*self.root.clone()).borrow() };
+        let mut previous_key = root.map(|(p, _)| self.last_key(p)).transpose()?;
+        let existing_last_key = previous_key.clone();
+
+        let page_size = self.mem.get_page_size();
+        let mut leaves = vec![];
+        let mut separators = vec![];
+        let mut pending: Vec<(Vec<u8>, Vec<u8>)> = vec![];
+        let mut pending_bytes = 0;
+        for (key, value) in pairs {
+            let (key, value) = (key.into(), value.into());
+            if let Some(ref previous) = previous_key {
+                assert_eq!(
+                    K::compare(previous, &key),
+                    Ordering::Less,
+                    "Keys must be appended in strictly increasing order"
+                );
+            }
+            let pair_bytes = key.len() + value.len();
+            let required =
+                LeafBuilder::required_bytes(pending.len() + 1, pending_bytes + pair_bytes);
+            // Large values are stored in leaves of their own
+            let large_value = self.mem.is_large_value(value.len())
+                || matches!(pending.last(), Some((_, last)) if self.mem.is_large_value(last.len()));
+            if !pending.is_empty() && (required > page_size || large_value) {
+                leaves.push(self.build_leaf(&pending)?);
+                separators.push(K::separator(&pending.last().unwrap().0, &key));
+                pending.clear();
+                pending_bytes = 0;
+            }
+            previous_key = Some(key.clone());
+            pending_bytes += pair_bytes;
+            pending.push((key, value));
+        }
+        if pending.is_empty() {
+            if leaves.is_empty() {
+                return Ok(());
+            }
+            separators.pop();
+        } else {
+            leaves.push(self.build_leaf(&pending)?);
+        }
+        let subtree = self.build_branches(leaves, separators)?;
+
+        let new_root = if let Some((old_root, old_checksum)) = root {
+            let old_height = self.subtree_height(old_root)?;
+            let new_height = self.subtree_height(subtree.0)?;
+            let height = max(old_height, new_height);
+            let mut children = vec![
+                (old_root, old_checksum, height - old_height),
+                (subtree.0, subtree.1, height - new_height),
+            ];
+            let mut separators = vec![existing_last_key.unwrap(), vec![]];
+            self.rebalance_children(&mut children, &mut separators)?;
+            if children.len() == 1 {
+                (children[0].0, children[0].1)
+            } else {
+                let mut builder = BranchBuilder::new(self.mem, 2, K::fixed_width(), self.counted);
+                builder.push_child(children[0].0, children[0].1);
+                builder.push_key(&separators[0]);
+                builder.push_child(children[1].0, children[1].1);
+                let new_page = builder.build()?;
+                (new_page.get_page_number(), self.checksum_helper(&new_page))
+            }
+        } else {
+            subtree
+        };
+        *self.root.borrow_mut() = Some(new_root);
+        Ok(())
+    }
+
+    fn build_leaf(&self, pairs: &[(Vec<u8>, Vec<u8>)]) -> Result<(PageNumber, Checksum)> {
+        let mut builder =
+            LeafBuilder::new(self.mem, pairs.len(), K::fixed_width(), V::fixed_width());
+        for (key, value) in pairs {
+            builder.push(key, value);
+        }
+        let page = builder.build()?;
+        Ok((page.get_page_number(), self.checksum_helper(&page)))
+    }
+
+    fn last_key(&self, mut page_number: PageNumber) -> Result<Vec<u8>> {
+        loop {
+            let page = self.mem.get_page(page_number)?;
+            match page.memory()[0] {
+                LEAF => {
+                    let accessor =
+                        LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
+                    return Ok(accessor.last_entry().key().to_vec());
+                }
+                BRANCH => {
+                    let accessor = BranchAccessor::new(&page, K::fixed_width());
+                    page_number = accessor.child_page(accessor.count_children() - 1).unwrap();
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    fn subtree_height(&self, mut page_number: PageNumber) -> Result<usize> {
+        let mut height = 1;
+        loop {
+            let page = self.mem.get_page(page_number)?;
+            match page.memory()[0] {
+                LEAF => {
+                    return Ok(height);
+                }
+                BRANCH => {
+                    let accessor = BranchAccessor::new(&page, K::fixed_width());
+                    page_number = accessor.child_page(0).unwrap();
+                    height += 1;
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    // Inserts the sorted pairs into the subtree rooted at page, and returns the pages which replace
+    // it, along with the keys that separate them
+    //
+    // Safety: caller must ensure that no references to uncommitted pages in this tree exist
+    #[allow(clippy::type_complexity)]
+    unsafe fn insert_sorted_helper(
+        &mut self,
+        page: PageImpl<'a>,
+        pairs: &[(&[u8], &[u8])],
+    ) -> Result<(Vec<(PageNumber, Checksum)>, Vec<Vec<u8>>)> {
+        let page_number = page.get_page_number();
+        let result = match page.memory()[0] {
+            LEAF => {
+                let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
+                let mut builder = LeafBuilder::new(
+                    self.mem,
+                    accessor.num_pairs() + pairs.len(),
+                    K::fixed_width(),
+                    V::fixed_width(),
+                );
+                // Merge the existing entries with the new ones, which replace any with equal keys
+                let mut i = 0;
+                for (key, value) in pairs {
+                    while let Some(entry) = accessor.entry(i) {
+                        match K::compare(entry.key(), key) {
+                            Ordering::Less => {
+                                builder.push(entry.key(), entry.value());
+                                i += 1;
+                            }
+                            Ordering::Equal => {
+                                i += 1;
+                                break;
+                            }
+                            Ordering::Greater => {
+                                break;
+                            }
+                        }
+                    }
+                    builder.push(key, value);
+                }
+                while let Some(entry) = accessor.entry(i) {
+                    builder.push(entry.key(), entry.value());
+                    i += 1;
+                }
+                self.build_leaves(builder)?
+            }
+            BRANCH => {
+                let accessor = BranchAccessor::new(&page, K::fixed_width());
+                let mut children = vec![];
+                let mut separators = vec![];
+                let mut start = 0;
+                for i in 0..accessor.count_children() {
+                    let end = if let Some(key) = accessor.key(i) {
+                        start
+                            + pairs[start..]
+                                .partition_point(|(k, _)| K::compare(k, key) != Ordering::Greater)
+                    } else {
+                        pairs.len()
+                    };
+                    if i > 0 {
+                        separators.push(accessor.key(i - 1).unwrap().to_vec());
+                    }
+                    let child = accessor.child_page(i).unwrap();
+                    if start == end {
+                        children.push((child, accessor.child_checksum(i).unwrap()));
+                    } else {
+                        let (pages, keys) = self
+                            .insert_sorted_helper(self.mem.get_page(child)?, &pairs[start..end])?;
+                        children.extend(pages);
+                        separators.extend(keys);
+                    }
+                    start = end;
+                }
+
+                let mut builder =
+                    BranchBuilder::new(self.mem, children.len(), K::fixed_width(), self.counted);
+                for (i, (child, checksum)) in children.iter().enumerate() {
+                    if i > 0 {
+                        builder.push_key(&separators[i - 1]);
+                    }
+                    builder.push_child(*child, *checksum);
+                }
+                let (pages, keys) = builder.build_many()?;
+                let keys = keys.iter().map(|key| key.to_vec()).collect();
+                let pages = pages
+                    .iter()
+                    .map(|page| (page.get_page_number(), self.checksum_helper(page)))
+                    .collect();
+                (pages, keys)
+            }
+            _ => unreachable!(),
+        };
+        drop(page);
+        self.free_policy
+            .conditional_free(page_number, self.freed, self.mem)?;
+
+        Ok(result)
+    }
+
+    // Builds the leaves for the pairs in builder, and returns them along with the keys that
+    // separate them
+    #[allow(clippy::type_complexity)]
+    fn build_leaves(
+        &self,
+        builder: LeafBuilder<'_, '_>,
+    ) -> Result<(Vec<(PageNumber, Checksum)>, Vec<Vec<u8>>)> {
+        let leaves = builder.build_many()?;
+        let mut pages = vec![];
+        let mut separators = vec![];
+        for (i, (page, last_key)) in leaves.iter().enumerate() {
+            pages.push((page.get_page_number(), self.checksum_helper(page)));
+            if let Some((next_page, _)) = leaves.get(i + 1) {
+                let accessor =
+                    LeafAccessor::new(next_page.memory(), K::fixed_width(), V::fixed_width());
+                separators.push(K::separator(last_key, accessor.entry(0).unwrap().key()));
+            }
+        }
+        Ok((pages, separators))
+    }
+
+    // Builds levels of branches on top of the given sibling pages, until only a single root remains
+    fn build_branches(
+        &self,
+        mut pages: Vec<(PageNumber, Checksum)>,
+        mut separators: Vec<Vec<u8>>,
+    ) -> Result<(PageNumber, Checksum)> {
+        while pages.len() > 1 {
+            let mut builder =
+                BranchBuilder::new(self.mem, pages.len(), K::fixed_width(), self.counted);
+            for (i, (page, checksum)) in pages.iter().enumerate() {
+                if i > 0 {
+                    builder.push_key(&separators[i - 1]);
+                }
+                builder.push_child(*page, *checksum);
+            }
+            let (new_pages, new_separators) = builder.build_many()?;
+            let new_separators = new_separators.iter().map(|key| key.to_vec()).collect();
+            pages = new_pages
+                .iter()
+                .map(|page| (page.get_page_number(), self.checksum_helper(page)))
+                .collect();
+            separators = new_separators;
+        }
+        Ok(pages[0])
+    }
+
+    // rightmost is true if page is on the right edge of the tree
     // Safety: caller must ensure that no references to uncommitted pages in this table exist
     unsafe fn insert_helper(
         &mut self,
         page: PageImpl<'a>,
         page_checksum: Checksum,
         key: &[u8],
-        value: &[u8],
+        value: InsertValue<'_>,
+        rightmost: bool,
     ) -> Result<InsertionResult<'a, K, V>> {
         let node_mem = page.memory();
         Ok(match node_mem[0] {
             LEAF => {
                 let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
                 let (position, found) = accessor.position::<K>(key);
+                self.appended_key = rightmost && !found && position == accessor.num_pairs();
+
+                if found
+                    && matches!(
+                        value,
+                        InsertValue::IfAbsent(_) | InsertValue::IfAbsentWith(_)
+                    )
+                {
+                    // Keep the existing value, and leave the page unchanged
+                    let (start, end) = accessor.value_range(position).unwrap();
+                    return Ok(InsertionResult {
+                        new_root: page.get_page_number(),
+                        root_checksum: page_checksum,
+                        additional_siblings: vec![],
+                        root_entries: u64::try_from(accessor.num_pairs()).unwrap(),
+                        appended: false,
+                        inserted_value: None,
+                        old_value: Some(AccessGuard::new(
+                            page,
+                            start,
+                            end - start,
+                            false,
+                            self.mem,
+                        )),
+                    });
+                }
+                let existing = if found {
+                    Some(accessor.entry(position).unwrap().value())
+                } else {
+                    None
+                };
+                let value = value.into_bytes(existing)?;
+                let value = value.as_ref();
 
                 // Fast-path to avoid re-building and splitting pages with a single large value
-                let single_large_value = accessor.num_pairs() == 1
-                    && accessor.total_length() >= self.mem.get_page_size();
-                if !found && single_large_value {
+                if !found && self.single_large_value(&accessor) {
                     let mut builder =
                         LeafBuilder::new(self.mem, 1, K::fixed_width(), V::fixed_width());
                     builder.push(key, value);
@@ -188,37 +1238,45 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
                         LeafAccessor::new(new_page.memory(), K::fixed_width(), V::fixed_width());
                     let offset = new_page_accessor.offset_of_first_value();
                     drop(new_page_accessor);
-                    let guard = AccessGuardMut::new(key, new_page, offset, value.len(), self.mem);
+                    let guard = AccessGuardMut::new(new_page, offset, value.len(), self.mem);
                     return if position == 0 {
                         Ok(InsertionResult {
                             new_root: new_page_number,
                             root_checksum: new_page_checksum,
-                            additional_sibling: Some((
-                                key.to_vec(),
+                            additional_siblings: vec![(
+                                K::separator(key, accessor.entry(0).unwrap().key()),
                                 page.get_page_number(),
                                 page_checksum,
-                            )),
-                            inserted_value: guard,
+                                1,
+                            )],
+                            root_entries: 1,
+                            appended: false,
+                            inserted_value: Some(guard),
                             old_value: None,
                         })
                     } else {
-                        let split_key = accessor.last_entry().key().to_vec();
+                        let split_key = K::separator(accessor.last_entry().key(), key);
                         Ok(InsertionResult {
                             new_root: page.get_page_number(),
                             root_checksum: page_checksum,
-                            additional_sibling: Some((
+                            additional_siblings: vec![(
                                 split_key,
                                 new_page_number,
                                 new_page_checksum,
-                            )),
-                            inserted_value: guard,
+                                1,
+                            )],
+                            root_entries: 1,
+                            appended: false,
+                            inserted_value: Some(guard),
                             old_value: None,
                         })
                     };
                 }
 
-                // Fast-path for uncommitted pages, that can be modified in-place
+                // Fast-path for uncommitted pages, that can be modified in-place. Large values are
+                // not inserted in-place, since they are stored in leaves of their own
                 if self.mem.uncommitted(page.get_page_number())
+                    && !self.mem.is_large_value(value.len())
                     && LeafMutator::sufficient_insert_inplace_space(
                         &page,
                         position,
@@ -231,13 +1289,15 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
                 {
                     let page_number = page.get_page_number();
                     let existing_value = if found {
-                        let copied_value = accessor.entry(position).unwrap().value().to_vec();
-                        Some(AccessGuard::with_owned_value(copied_value, self.mem))
+                        let value = accessor.entry(position).unwrap().value();
+                        Some(AccessGuard::with_copied_value(value, self.mem))
                     } else {
                         None
                     };
+                    let entries =
+                        u64::try_from(accessor.num_pairs() + usize::from(!found)).unwrap();
                     drop(page);
-                    let mut page_mut = self.mem.get_page_mut(page_number);
+                    let mut page_mut = self.mem.get_page_mut(page_number)?;
                     let mut mutator =
                         LeafMutator::new(&mut page_mut, K::fixed_width(), V::fixed_width());
                     mutator.insert(position, found, key, value);
@@ -246,12 +1306,14 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
                     let offset = new_page_accessor.offset_of_value(position).unwrap();
                     drop(new_page_accessor);
                     let new_checksum = self.checksum_helper(&page_mut);
-                    let guard = AccessGuardMut::new(key, page_mut, offset, value.len(), self.mem);
+                    let guard = AccessGuardMut::new(page_mut, offset, value.len(), self.mem);
                     return Ok(InsertionResult {
                         new_root: page_number,
                         root_checksum: new_checksum,
-                        additional_sibling: None,
-                        inserted_value: guard,
+                        additional_siblings: vec![],
+                        root_entries: entries,
+                        appended: false,
+                        inserted_value: Some(guard),
                         old_value: existing_value,
                     });
                 }
@@ -274,6 +1336,7 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
                 if accessor.num_pairs() == position {
                     builder.push(key, value);
                 }
+                let entries = u64::try_from(accessor.num_pairs() + usize::from(!found)).unwrap();
                 if !builder.should_split() {
                     let new_page = builder.build()?;
 
@@ -303,18 +1366,74 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
                     let accessor =
                         LeafAccessor::new(new_page.memory(), K::fixed_width(), V::fixed_width());
                     let offset = accessor.offset_of_value(position).unwrap();
-                    let guard = AccessGuardMut::new(key, new_page, offset, value.len(), self.mem);
+                    let guard = AccessGuardMut::new(new_page, offset, value.len(), self.mem);
 
                     InsertionResult {
                         new_root: new_page_number,
                         root_checksum: new_page_checksum,
-                        additional_sibling: None,
-                        inserted_value: guard,
+                        additional_siblings: vec![],
+                        root_entries: entries,
+                        appended: false,
+                        inserted_value: Some(guard),
                         old_value: existing_value,
                     }
+                } else if rightmost
+                    && !found
+                    && position == accessor.num_pairs()
+                    && !self.mem.is_large_value(value.len())
+                {
+                    // Appending past the last key in the tree. Rather than splitting the page in
+                    // half, leave it as it is and start a new page, so that sequential inserts
+                    // produce full pages
+                    let mut builder =
+                        LeafBuilder::new(self.mem, 1, K::fixed_width(), V::fixed_width());
+                    builder.push(key, value);
+                    let new_page = builder.build()?;
+                    let new_page_number = new_page.get_page_number();
+                    let new_page_checksum = self.checksum_helper(&new_page);
+                    let new_page_accessor =
+                        LeafAccessor::new(new_page.memory(), K::fixed_width(), V::fixed_width());
+                    let offset = new_page_accessor.offset_of_first_value();
+                    drop(new_page_accessor);
+                    let guard = AccessGuardMut::new(new_page, offset, value.len(), self.mem);
+                    InsertionResult {
+                        new_root: page.get_page_number(),
+                        root_checksum: page_checksum,
+                        additional_siblings: vec![(
+                            K::separator(accessor.last_entry().key(), key),
+                            new_page_number,
+                            new_page_checksum,
+                            1,
+                        )],
+                        root_entries: u64::try_from(accessor.num_pairs()).unwrap(),
+                        appended: true,
+                        inserted_value: Some(guard),
+                        old_value: None,
+                    }
                 } else {
-                    let (new_page1, split_key, new_page2) = builder.build_split()?;
-                    let split_key = split_key.to_vec();
+                    let (new_pages, separators) = if builder.has_large_value() {
+                        // A large value is split from the pairs on both sides
+                        let new_pages = builder.build_many()?;
+                        let mut separators = vec![];
+                        for i in 1..new_pages.len() {
+                            let next_accessor = LeafAccessor::new(
+                                new_pages[i].0.memory(),
+                                K::fixed_width(),
+                                V::fixed_width(),
+                            );
+                            let next_key = next_accessor.entry(0).unwrap().key();
+                            separators.push(K::separator(new_pages[i - 1].1, next_key));
+                        }
+                        // The last keys borrow from the original page, which is released below
+                        let new_pages: Vec<_> = new_pages
+                            .into_iter()
+                            .map(|(new_page, _)| new_page)
+                            .collect();
+                        (new_pages, separators)
+                    } else {
+                        let (new_page1, separator, new_page2) = builder.build_split::<K>()?;
+                        (vec![new_page1, new_page2], vec![separator])
+                    };
                     let page_number = page.get_page_number();
                     let existing_value = if found {
                         let (start, end) = accessor.value_range(position).unwrap();
@@ -336,35 +1455,40 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
                         None
                     };
 
-                    let new_page_number = new_page1.get_page_number();
-                    let new_page_checksum = self.checksum_helper(&new_page1);
-                    let new_page_number2 = new_page2.get_page_number();
-                    let new_page2_checksum = self.checksum_helper(&new_page2);
-                    let accessor =
-                        LeafAccessor::new(new_page1.memory(), K::fixed_width(), V::fixed_width());
-                    let division = accessor.num_pairs();
-                    let guard = if position < division {
-                        let accessor = LeafAccessor::new(
-                            new_page1.memory(),
-                            K::fixed_width(),
-                            V::fixed_width(),
-                        );
-                        let offset = accessor.offset_of_value(position).unwrap();
-                        AccessGuardMut::new(key, new_page1, offset, value.len(), self.mem)
-                    } else {
+                    let mut pages = vec![];
+                    let mut guard = None;
+                    let mut start = 0;
+                    for new_page in new_pages {
                         let accessor = LeafAccessor::new(
-                            new_page2.memory(),
+                            new_page.memory(),
                             K::fixed_width(),
                             V::fixed_width(),
                         );
-                        let offset = accessor.offset_of_value(position - division).unwrap();
-                        AccessGuardMut::new(key, new_page2, offset, value.len(), self.mem)
-                    };
+                        let num_pairs = accessor.num_pairs();
+                        pages.push((
+                            new_page.get_page_number(),
+                            self.checksum_helper(&new_page),
+                            u64::try_from(num_pairs).unwrap(),
+                        ));
+                        if (start..(start + num_pairs)).contains(&position) {
+                            let offset = accessor.offset_of_value(position - start).unwrap();
+                            guard =
+                                Some(AccessGuardMut::new(new_page, offset, value.len(), self.mem));
+                        }
+                        start += num_pairs;
+                    }
 
+                    let (new_root, root_checksum, root_entries) = pages[0];
                     InsertionResult {
-                        new_root: new_page_number,
-                        root_checksum: new_page_checksum,
-                        additional_sibling: Some((split_key, new_page_number2, new_page2_checksum)),
+                        new_root,
+                        root_checksum,
+                        additional_siblings: separators
+                            .into_iter()
+                            .zip(pages.into_iter().skip(1))
+                            .map(|(key, (page, checksum, entries))| (key, page, checksum, entries))
+                            .collect(),
+                        root_entries,
+                        appended: false,
                         inserted_value: guard,
                         old_value: existing_value,
                     }
@@ -374,20 +1498,30 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
                 let accessor = BranchAccessor::new(&page, K::fixed_width());
                 let (child_index, child_page) = accessor.child_for_key::<K>(key);
                 let child_checksum = accessor.child_checksum(child_index).unwrap();
-                let sub_result =
-                    self.insert_helper(self.mem.get_page(child_page), child_checksum, key, value)?;
+                let last_child = child_index == accessor.count_children() - 1;
+                let sub_result = self.insert_helper(
+                    self.mem.get_page(child_page)?,
+                    child_checksum,
+                    key,
+                    value,
+                    rightmost && last_child,
+                )?;
 
-                if sub_result.additional_sibling.is_none() {
+                if sub_result.additional_siblings.is_empty() {
                     // Check fast-path if no children were added / changed. Generally, this can only happen
                     // when checksums are disabled
+                    // Counted branches must update the entry count of the child, so can't use this
                     if sub_result.new_root == child_page
                         && sub_result.root_checksum == child_checksum
+                        && !self.counted
                     {
                         // NO-OP. One of our descendants is uncommitted, so there was no change
                         return Ok(InsertionResult {
                             new_root: page.get_page_number(),
                             root_checksum: self.checksum_helper(&page),
-                            additional_sibling: None,
+                            additional_siblings: vec![],
+                            root_entries: 0,
+                            appended: false,
                             inserted_value: sub_result.inserted_value,
                             old_value: sub_result.old_value,
                         });
@@ -396,33 +1530,46 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
                         drop(page);
                         // Safety: Since the page is uncommitted, no other transactions could have it open
                         // and we just dropped our reference to it, on the line above
-                        let mut mutpage = self.mem.get_page_mut(page_number);
+                        let mut mutpage = self.mem.get_page_mut(page_number)?;
                         let mut mutator = BranchMutator::new(&mut mutpage);
                         mutator.write_child_page(
                             child_index,
                             sub_result.new_root,
                             sub_result.root_checksum,
                         );
+                        mutator.write_child_entries(child_index, sub_result.root_entries);
                         return Ok(InsertionResult {
                             new_root: mutpage.get_page_number(),
                             root_checksum: self.checksum_helper(&mutpage),
-                            additional_sibling: None,
+                            additional_siblings: vec![],
+                            root_entries: subtree_entries(&mutpage).unwrap_or_default(),
+                            appended: false,
                             inserted_value: sub_result.inserted_value,
                             old_value: sub_result.old_value,
                         });
                     }
                 }
 
+                // A child was appended at the right edge of the tree, so this page should be split
+                // in the same way
+                let appended = sub_result.appended && last_child;
+
                 // A child was added, or we couldn't use the fast-path above
-                let mut builder =
-                    BranchBuilder::new(self.mem, accessor.count_children() + 1, K::fixed_width());
+                let mut builder = BranchBuilder::new(
+                    self.mem,
+                    accessor.count_children() + sub_result.additional_siblings.len(),
+                    K::fixed_width(),
+                    self.counted,
+                );
                 if child_index == 0 {
-                    builder.push_child(sub_result.new_root, sub_result.root_checksum);
-                    if let Some((ref index_key2, page2, page2_checksum)) =
-                        sub_result.additional_sibling
-                    {
-                        builder.push_key(index_key2);
-                        builder.push_child(page2, page2_checksum);
+                    builder.push_child_with_entries(
+                        sub_result.new_root,
+                        sub_result.root_checksum,
+                        sub_result.root_entries,
+                    );
+                    for (key, page, checksum, entries) in sub_result.additional_siblings.iter() {
+                        builder.push_key(key);
+                        builder.push_child_with_entries(*page, *checksum, *entries);
                     }
                 } else {
                     builder.push_child(
@@ -434,12 +1581,16 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
                     if let Some(key) = accessor.key(i - 1) {
                         builder.push_key(key);
                         if i == child_index {
-                            builder.push_child(sub_result.new_root, sub_result.root_checksum);
-                            if let Some((ref index_key2, page2, page2_checksum)) =
-                                sub_result.additional_sibling
+                            builder.push_child_with_entries(
+                                sub_result.new_root,
+                                sub_result.root_checksum,
+                                sub_result.root_entries,
+                            );
+                            for (key, page, checksum, entries) in
+                                sub_result.additional_siblings.iter()
                             {
-                                builder.push_key(index_key2);
-                                builder.push_child(page2, page2_checksum);
+                                builder.push_key(key);
+                                builder.push_child_with_entries(*page, *checksum, *entries);
                             }
                         } else {
                             builder.push_child(
@@ -453,15 +1604,22 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
                 }
 
                 let result = if builder.should_split() {
-                    let (new_page1, split_key, new_page2) = builder.build_split()?;
+                    let (new_page1, split_key, new_page2) = if appended {
+                        builder.build_split_for_append()?
+                    } else {
+                        builder.build_split()?
+                    };
                     InsertionResult {
                         new_root: new_page1.get_page_number(),
                         root_checksum: self.checksum_helper(&new_page1),
-                        additional_sibling: Some((
+                        additional_siblings: vec![(
                             split_key.to_vec(),
                             new_page2.get_page_number(),
                             self.checksum_helper(&new_page2),
-                        )),
+                            subtree_entries(&new_page2).unwrap_or_default(),
+                        )],
+                        root_entries: subtree_entries(&new_page1).unwrap_or_default(),
+                        appended,
                         inserted_value: sub_result.inserted_value,
                         old_value: sub_result.old_value,
                     }
@@ -470,7 +1628,9 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
                     InsertionResult {
                         new_root: new_page.get_page_number(),
                         root_checksum: self.checksum_helper(&new_page),
-                        additional_sibling: None,
+                        additional_siblings: vec![],
+                        root_entries: subtree_entries(&new_page).unwrap_or_default(),
+                        appended: false,
                         inserted_value: sub_result.inserted_value,
                         old_value: sub_result.old_value,
                     }
@@ -507,8 +1667,10 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
             LeafBuilder::required_bytes(accessor.num_pairs() - 1, new_kv_bytes);
         let uncommitted = self.mem.uncommitted(page.get_page_number());
 
-        // Fast-path for dirty pages
+        // Fast-path for dirty pages. The entry is only removed once the returned guard is dropped,
+        // so this can't be used if the parent needs to count the remaining entries
         if uncommitted
+            && !self.counted
             && new_required_bytes >= self.mem.get_page_size() / 2
             && accessor.num_pairs() > 1
         {
@@ -517,7 +1679,7 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
             drop(page);
             // Safety: caller guaranteed that no other references to uncommitted data exist,
             // and we just dropped the reference to page
-            let page_mut = self.mem.get_page_mut(page_number);
+            let page_mut = self.mem.get_page_mut(page_number)?;
 
             // TODO: optimize this!
             // hack to get the checksum after removal
@@ -604,6 +1766,19 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
         Ok(result)
     }
 
+    fn subtree_entries(&self, page_number: PageNumber) -> Result<u64> {
+        Ok(subtree_entries(&self.mem.get_page(page_number)?).unwrap())
+    }
+
+    // Returns true if the leaf holds a single value, which is too large to share its page
+    fn single_large_value(&self, accessor: &LeafAccessor) -> bool {
+        if accessor.num_pairs() != 1 {
+            return false;
+        }
+        let (start, end) = accessor.value_range(0).unwrap();
+        accessor.total_length() >= self.mem.get_page_size() || self.mem.is_large_value(end - start)
+    }
+
     fn checksum_helper<T: Page>(&self, page: &T) -> Checksum {
         if self.mem.checksum_type() == ChecksumType::Unused {
             return 0;
@@ -632,7 +1807,7 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
         let (child_index, child_page_number) = accessor.child_for_key::<K>(key);
         let child_checksum = accessor.child_checksum(child_index).unwrap();
         let (result, found) =
-            self.delete_helper(self.mem.get_page(child_page_number), child_checksum, key)?;
+            self.delete_helper(self.mem.get_page(child_page_number)?, child_checksum, key)?;
         if found.is_none() {
             return Ok((Subtree(original_page_number, checksum), None));
         }
@@ -641,13 +1816,20 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
                 drop(page);
                 // Safety: Caller guarantees there are no references to uncommitted pages,
                 // and we just dropped our reference to it on the line above
-                let mut mutpage = self.mem.get_page_mut(original_page_number);
+                let mut mutpage = self.mem.get_page_mut(original_page_number)?;
                 let mut mutator = BranchMutator::new(&mut mutpage);
                 mutator.write_child_page(child_index, new_child, new_child_checksum);
+                if self.counted {
+                    mutator.write_child_entries(child_index, self.subtree_entries(new_child)?);
+                }
                 (original_page_number, self.checksum_helper(&mutpage))
             } else {
-                let mut builder =
-                    BranchBuilder::new(self.mem, accessor.count_children(), K::fixed_width());
+                let mut builder = BranchBuilder::new(
+                    self.mem,
+                    accessor.count_children(),
+                    K::fixed_width(),
+                    self.counted,
+                );
                 builder.push_all(&accessor);
                 builder.replace_child(child_index, new_child, new_child_checksum);
                 let new_page = builder.build()?;
@@ -659,7 +1841,12 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
         }
 
         // Child is requesting to be merged with a sibling
-        let mut builder = BranchBuilder::new(self.mem, accessor.count_children(), K::fixed_width());
+        let mut builder = BranchBuilder::new(
+            self.mem,
+            accessor.count_children(),
+            K::fixed_width(),
+            self.counted,
+        );
 
         let final_result = match result {
             Subtree(_, _) => {
@@ -691,7 +1878,7 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
                 self.finalize_branch_builder(builder)?
             }
             PartialLeaf { deleted_pair } => {
-                let partial_child_page = self.mem.get_page(child_page_number);
+                let partial_child_page = self.mem.get_page(child_page_number)?;
                 let partial_child_accessor = LeafAccessor::new(
                     partial_child_page.memory(),
                     K::fixed_width(),
@@ -701,14 +1888,14 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
 
                 let merge_with = if child_index == 0 { 1 } else { child_index - 1 };
                 debug_assert!(merge_with < accessor.count_children());
-                let merge_with_page = self.mem.get_page(accessor.child_page(merge_with).unwrap());
+                let merge_with_page = self
+                    .mem
+                    .get_page(accessor.child_page(merge_with).unwrap())?;
                 let merge_with_accessor =
                     LeafAccessor::new(merge_with_page.memory(), K::fixed_width(), V::fixed_width());
 
-                let single_large_value = merge_with_accessor.num_pairs() == 1
-                    && merge_with_accessor.total_length() >= self.mem.get_page_size();
                 // Don't try to merge or rebalance, if the sibling contains a single large value
-                if single_large_value {
+                if self.single_large_value(&merge_with_accessor) {
                     let mut child_builder = LeafBuilder::new(
                         self.mem,
                         partial_child_accessor.num_pairs() - 1,
@@ -716,13 +1903,13 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
                         V::fixed_width(),
                     );
                     child_builder.push_all_except(&partial_child_accessor, Some(deleted_pair));
-                    let new_page = child_builder.build()?;
+                    // The new page must be released before the branch is built, since it may read it
+                    let (new_page_number, new_page_checksum) = {
+                        let new_page = child_builder.build()?;
+                        (new_page.get_page_number(), self.checksum_helper(&new_page))
+                    };
                     builder.push_all(&accessor);
-                    builder.replace_child(
-                        child_index,
-                        new_page.get_page_number(),
-                        self.checksum_helper(&new_page),
-                    );
+                    builder.replace_child(child_index, new_page_number, new_page_checksum);
 
                     let result = self.finalize_branch_builder(builder)?;
 
@@ -738,6 +1925,39 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
                     return Ok((result, found));
                 }
 
+                let mut child_builder = LeafBuilder::new(
+                    self.mem,
+                    partial_child_accessor.num_pairs() - 1 + merge_with_accessor.num_pairs(),
+                    K::fixed_width(),
+                    V::fixed_width(),
+                );
+                if child_index < merge_with {
+                    child_builder.push_all_except(&partial_child_accessor, Some(deleted_pair));
+                }
+                child_builder.push_all_except(&merge_with_accessor, None);
+                if child_index > merge_with {
+                    child_builder.push_all_except(&partial_child_accessor, Some(deleted_pair));
+                }
+                // The new pages must be released before the branch is built, since it may read them
+                let (merged, split) = if child_builder.should_split() {
+                    let (new_page1, split_key, new_page2) = child_builder.build_split::<K>()?;
+                    let new_page2 = (
+                        new_page2.get_page_number(),
+                        self.checksum_helper(&new_page2),
+                    );
+                    let new_page1 = (
+                        new_page1.get_page_number(),
+                        self.checksum_helper(&new_page1),
+                    );
+                    (new_page1, Some((split_key, new_page2)))
+                } else {
+                    let new_page = child_builder.build()?;
+                    (
+                        (new_page.get_page_number(), self.checksum_helper(&new_page)),
+                        None,
+                    )
+                };
+
                 for i in 0..accessor.count_children() {
                     if i == child_index {
                         continue;
@@ -745,39 +1965,10 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
                     let page_number = accessor.child_page(i).unwrap();
                     let page_checksum = accessor.child_checksum(i).unwrap();
                     if i == merge_with {
-                        let mut child_builder = LeafBuilder::new(
-                            self.mem,
-                            partial_child_accessor.num_pairs() - 1
-                                + merge_with_accessor.num_pairs(),
-                            K::fixed_width(),
-                            V::fixed_width(),
-                        );
-                        if child_index < merge_with {
-                            child_builder
-                                .push_all_except(&partial_child_accessor, Some(deleted_pair));
-                        }
-                        child_builder.push_all_except(&merge_with_accessor, None);
-                        if child_index > merge_with {
-                            child_builder
-                                .push_all_except(&partial_child_accessor, Some(deleted_pair));
-                        }
-                        if child_builder.should_split() {
-                            let (new_page1, split_key, new_page2) = child_builder.build_split()?;
+                        builder.push_child(merged.0, merged.1);
+                        if let Some((split_key, new_page2)) = &split {
                             builder.push_key(split_key);
-                            builder.push_child(
-                                new_page1.get_page_number(),
-                                self.checksum_helper(&new_page1),
-                            );
-                            builder.push_child(
-                                new_page2.get_page_number(),
-                                self.checksum_helper(&new_page2),
-                            );
-                        } else {
-                            let new_page = child_builder.build()?;
-                            builder.push_child(
-                                new_page.get_page_number(),
-                                self.checksum_helper(&new_page),
-                            );
+                            builder.push_child(new_page2.0, new_page2.1);
                         }
 
                         let merged_key_index = max(child_index, merge_with);
@@ -805,7 +1996,9 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
             }
             DeletedBranch(only_grandchild, grandchild_checksum) => {
                 let merge_with = if child_index == 0 { 1 } else { child_index - 1 };
-                let merge_with_page = self.mem.get_page(accessor.child_page(merge_with).unwrap());
+                let merge_with_page = self
+                    .mem
+                    .get_page(accessor.child_page(merge_with).unwrap())?;
                 let merge_with_accessor = BranchAccessor::new(&merge_with_page, K::fixed_width());
                 debug_assert!(merge_with < accessor.count_children());
                 for i in 0..accessor.count_children() {
@@ -819,6 +2012,7 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
                             self.mem,
                             merge_with_accessor.count_children() + 1,
                             K::fixed_width(),
+                            self.counted,
                         );
                         let separator_key = accessor.key(min(child_index, merge_with)).unwrap();
                         if child_index < merge_with {
@@ -870,11 +2064,13 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
                 result
             }
             PartialBranch(partial_child, ..) => {
-                let partial_child_page = self.mem.get_page(partial_child);
+                let partial_child_page = self.mem.get_page(partial_child)?;
                 let partial_child_accessor =
                     BranchAccessor::new(&partial_child_page, K::fixed_width());
                 let merge_with = if child_index == 0 { 1 } else { child_index - 1 };
-                let merge_with_page = self.mem.get_page(accessor.child_page(merge_with).unwrap());
+                let merge_with_page = self
+                    .mem
+                    .get_page(accessor.child_page(merge_with).unwrap())?;
                 let merge_with_accessor = BranchAccessor::new(&merge_with_page, K::fixed_width());
                 debug_assert!(merge_with < accessor.count_children());
                 for i in 0..accessor.count_children() {
@@ -889,6 +2085,7 @@ impl<'a, 'b, K: RedbKey + ?Sized, V: RedbValue + ?Sized> MutateHelper<'a, 'b, K,
                             merge_with_accessor.count_children()
                                 + partial_child_accessor.count_children(),
                             K::fixed_width(),
+                            self.counted,
                         );
                         let separator_key = accessor.key(min(child_index, merge_with)).unwrap();
                         if child_index < merge_with {
@@ -0,0 +1,310 @@
+use crate::export::write_json_string;
+use crate::tree_store::btree_base::{
+    read_page, BranchAccessor, Checksum, LeafAccessor, BRANCH, LEAF,
+};
+use crate::tree_store::page_store::{Page, PageNumber, TransactionalMemory};
+use crate::types::{RedbKey, RedbValue};
+use crate::Result;
+use std::io::Write;
+
+/// Formats which the structure of a table's b-tree can be written in, by
+/// [`crate::Table::dump_tree`] and [`crate::ReadOnlyTable::dump_tree`]
+///
+/// Each page is described by its page number, whether it's a branch or a leaf, its number of
+/// children or entries, and the first and last keys stored beneath it, formatted with [`Debug`].
+/// The output is intended for debugging, and its exact contents may change between versions
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TreeDumpFormat {
+    /// A Graphviz digraph, with a node for each page and an edge from each branch to its children
+    Dot,
+    /// A JSON object for the root page, in which each branch contains the objects for its
+    /// children. An empty table is written as `null`
+    Json,
+}
+
+// Writes the structure of the tree with the given root to writer
+pub(crate) fn dump_tree<K: RedbKey + ?Sized, V: RedbValue + ?Sized>(
+    root: Option<(PageNumber, Checksum)>,
+    mem: &TransactionalMemory,
+    format: TreeDumpFormat,
+    mut writer: impl Write,
+) -> Result {
+    let mut output = vec![];
+    match format {
+        TreeDumpFormat::Dot => {
+            output.extend_from_slice(b"digraph btree {\n    node [shape=box];\n");
+            if let Some((root, checksum)) = root {
+                dump_helper::<K, V>(root, checksum, 0, mem, format, &mut output)?;
+            }
+            output.extend_from_slice(b"}\n");
+        }
+        TreeDumpFormat::Json => {
+            if let Some((root, checksum)) = root {
+                dump_helper::<K, V>(root, checksum, 0, mem, format, &mut output)?;
+            } else {
+                output.extend_from_slice(b"null");
+            }
+            output.push(b'\n');
+        }
+    }
+    writer.write_all(&output)?;
+
+    Ok(())
+}
+
+// Writes the page and its subtree, and returns the first and last keys of the subtree
+fn dump_helper<K: RedbKey + ?Sized, V: RedbValue + ?Sized>(
+    page_number: PageNumber,
+    checksum: Checksum,
+    depth: usize,
+    mem: &TransactionalMemory,
+    format: TreeDumpFormat,
+    output: &mut Vec<u8>,
+) -> Result<(String, String)> {
+    let page = read_page(
+        mem,
+        page_number,
+        checksum,
+        K::fixed_width(),
+        V::fixed_width(),
+    )?;
+    let name = format!("{:?}", page_number);
+    match page.memory()[0] {
+        LEAF => {
+            let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
+            let entries = accessor.num_pairs();
+            let first_key = format!("{:?}", K::from_bytes(accessor.entry(0).unwrap().key()));
+            let last_key = format!(
+                "{:?}",
+                K::from_bytes(accessor.entry(entries - 1).unwrap().key())
+            );
+            match format {
+                TreeDumpFormat::Dot => {
+                    let label = format!(
+                        "leaf {}\\nentries: {}\\nkeys: {} ..= {}",
+                        name,
+                        entries,
+                        escape_dot(&first_key),
+                        escape_dot(&last_key)
+                    );
+                    output.extend_from_slice(
+                        format!("    \"{}\" [label=\"{}\"];\n", name, label).as_bytes(),
+                    );
+                }
+                TreeDumpFormat::Json => {
+                    output.extend_from_slice(
+                        format!(
+                            "{{\"page\":\"{}\",\"type\":\"leaf\",\"depth\":{},\"entries\":{},",
+                            name, depth, entries
+                        )
+                        .as_bytes(),
+                    );
+                    write_key_range(&first_key, &last_key, output);
+                    output.push(b'}');
+                }
+            }
+
+            Ok((first_key, last_key))
+        }
+        BRANCH => {
+            let accessor = BranchAccessor::new(&page, K::fixed_width());
+            let children: Vec<(PageNumber, Checksum)> = (0..accessor.count_children())
+                .map(|i| {
+                    (
+                        accessor.child_page(i).unwrap(),
+                        accessor.child_checksum(i).unwrap(),
+                    )
+                })
+                .collect();
+            drop(accessor);
+            drop(page);
+
+            if format == TreeDumpFormat::Json {
+                output.extend_from_slice(
+                    format!(
+                        "{{\"page\":\"{}\",\"type\":\"branch\",\"depth\":{},\"children\":[",
+                        name, depth
+                    )
+                    .as_bytes(),
+                );
+            }
+            let mut first_key = String::new();
+            let mut last_key = String::new();
+            for (i, child) in children.iter().enumerate() {
+                if format == TreeDumpFormat::Json && i > 0 {
+                    output.push(b',');
+                }
+                let (child, checksum) = *child;
+                let (child_first, child_last) =
+                    dump_helper::<K, V>(child, checksum, depth + 1, mem, format, output)?;
+                if i == 0 {
+                    first_key = child_first;
+                }
+                last_key = child_last;
+            }
+            match format {
+                TreeDumpFormat::Dot => {
+                    let label = format!(
+                        "branch {}\\nchildren: {}\\nkeys: {} ..= {}",
+                        name,
+                        children.len(),
+                        escape_dot(&first_key),
+                        escape_dot(&last_key)
+                    );
+                    output.extend_from_slice(
+                        format!("    \"{}\" [label=\"{}\"];\n", name, label).as_bytes(),
+                    );
+                    for (child, _) in children {
+                        output.extend_from_slice(
+                            format!("    \"{}\" -> \"{:?}\";\n", name, child).as_bytes(),
+                        );
+                    }
+                }
+                TreeDumpFormat::Json => {
+                    output.extend_from_slice(b"],");
+                    write_key_range(&first_key, &last_key, output);
+                    output.push(b'}');
+                }
+            }
+
+            Ok((first_key, last_key))
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn write_key_range(first_key: &str, last_key: &str, output: &mut Vec<u8>) {
+    output.extend_from_slice(b"\"first_key\":");
+    write_json_string(first_key, output);
+    output.extend_from_slice(b",\"last_key\":");
+    write_json_string(last_key, output);
+}
+
+fn escape_dot(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Information about a page of a table's b-tree, as returned by [`crate::Table::pages`] and
+/// [`crate::ReadOnlyTable::pages`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PageInfo {
+    pub(crate) page_number: u64,
+    pub(crate) level: usize,
+    pub(crate) leaf: bool,
+    pub(crate) entries: usize,
+    pub(crate) used_bytes: usize,
+    pub(crate) page_size: usize,
+}
+
+impl PageInfo {
+    /// Number which identifies the page in the database file. It is only meaningful for as long
+    /// as the transaction which returned it is open
+    pub fn page_number(&self) -> u64 {
+        self.page_number
+    }
+
+    /// Distance from the root of the tree. The root is at level 0
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    /// Returns `true` if the page stores (key, value) pairs, and `false` if it's a branch
+    pub fn is_leaf(&self) -> bool {
+        self.leaf
+    }
+
+    /// Number of (key, value) pairs in a leaf page, or of children of a branch page
+    pub fn entries(&self) -> usize {
+        self.entries
+    }
+
+    /// Number of bytes of the page which hold entries and page metadata
+    pub fn used_bytes(&self) -> usize {
+        self.used_bytes
+    }
+
+    /// Size of the page in bytes, including the bytes which are unused
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+}
+
+/// Iterator over the pages of a table's b-tree, in depth-first order, starting with the root
+pub struct TreePageIter<'a> {
+    mem: &'a TransactionalMemory,
+    fixed_key_size: Option<usize>,
+    fixed_value_size: Option<usize>,
+    // Pages which have not been visited yet, with their checksums and levels. The next page is at
+    // the end
+    stack: Vec<(PageNumber, Checksum, usize)>,
+}
+
+impl<'a> TreePageIter<'a> {
+    pub(crate) fn new(
+        root: Option<(PageNumber, Checksum)>,
+        mem: &'a TransactionalMemory,
+        fixed_key_size: Option<usize>,
+        fixed_value_size: Option<usize>,
+    ) -> Self {
+        Self {
+            mem,
+            fixed_key_size,
+            fixed_value_size,
+            stack: root
+                .map(|(root, checksum)| (root, checksum, 0))
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+impl<'a> Iterator for TreePageIter<'a> {
+    type Item = Result<PageInfo>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (page_number, checksum, level) = self.stack.pop()?;
+        let page = match read_page(
+            self.mem,
+            page_number,
+            checksum,
+            self.fixed_key_size,
+            self.fixed_value_size,
+        ) {
+            Ok(page) => page,
+            Err(err) => {
+                // Report the error once, and end the iteration
+                self.stack.clear();
+                return Some(Err(err));
+            }
+        };
+        let (leaf, entries, used_bytes) = match page.memory()[0] {
+            LEAF => {
+                let accessor =
+                    LeafAccessor::new(page.memory(), self.fixed_key_size, self.fixed_value_size);
+                (true, accessor.num_pairs(), accessor.total_length())
+            }
+            BRANCH => {
+                let accessor = BranchAccessor::new(&page, self.fixed_key_size);
+                // Push the children in reverse, so that the first child is visited next
+                for i in (0..accessor.count_children()).rev() {
+                    self.stack.push((
+                        accessor.child_page(i).unwrap(),
+                        accessor.child_checksum(i).unwrap(),
+                        level + 1,
+                    ));
+                }
+                (false, accessor.count_children(), accessor.total_length())
+            }
+            _ => unreachable!(),
+        };
+
+        Some(Ok(PageInfo {
+            page_number: u64::from_le_bytes(page_number.to_le_bytes()),
+            level,
+            leaf,
+            entries,
+            used_bytes,
+            page_size: page.memory().len(),
+        }))
+    }
+}
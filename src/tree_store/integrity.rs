@@ -0,0 +1,563 @@
+use crate::multimap_table::parse_subtree_root;
+use crate::tree_store::btree_base::{
+    branch_checksum, leaf_checksum, BranchAccessor, Checksum, LeafAccessor, BRANCH, LEAF,
+};
+use crate::tree_store::page_store::{Page, TransactionalMemory};
+use crate::tree_store::{
+    parse_table_name, FreedTableKey, InternalTableDefinition, PageNumber, TableType,
+};
+use crate::types::{builtin_key_compare, KeyCompare, RedbKey, RedbValue};
+use crate::Result;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::mem::size_of;
+
+/// The kind of an [`IntegrityIssue`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IntegrityIssueKind {
+    /// The checksum of a page does not match the checksum stored in its parent
+    ChecksumMismatch,
+    /// A page is malformed, or is not where it should be in its tree
+    InvalidPage,
+    /// The keys of a page are not in order
+    KeyOrder,
+    /// A page is referenced more than once
+    DuplicateReference,
+    /// A page is referenced, but the allocator considers it free
+    UnallocatedPage,
+    /// Pages are allocated, but are neither referenced, nor waiting to be freed
+    LeakedPages,
+}
+
+/// An inconsistency found by [`Database::check_integrity`](crate::Database::check_integrity)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IntegrityIssue {
+    kind: IntegrityIssueKind,
+    table: Option<String>,
+    offset: Option<u64>,
+    description: String,
+}
+
+impl IntegrityIssue {
+    /// The kind of inconsistency
+    pub fn kind(&self) -> IntegrityIssueKind {
+        self.kind
+    }
+
+    /// Name of the table which contains the page, or `None` if it belongs to one of the internal
+    /// tables, or is not referenced by any table
+    pub fn table(&self) -> Option<&str> {
+        self.table.as_deref()
+    }
+
+    /// Offset of the page in the database file, if it lies within the file
+    pub fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+
+    /// Human readable description of the issue
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+}
+
+impl Display for IntegrityIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(table) = &self.table {
+            write!(f, "table '{}': ", table)?;
+        }
+        write!(f, "{}", self.description)
+    }
+}
+
+/// The result of [`Database::check_integrity`](crate::Database::check_integrity)
+#[derive(Clone, Debug, Default)]
+pub struct IntegrityReport {
+    tables_checked: usize,
+    pages_checked: usize,
+    issues: Vec<IntegrityIssue>,
+}
+
+impl IntegrityReport {
+    /// Returns `true` if no issues were found
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Every inconsistency that was found
+    pub fn issues(&self) -> &[IntegrityIssue] {
+        &self.issues
+    }
+
+    /// Number of user tables that were checked
+    pub fn tables_checked(&self) -> usize {
+        self.tables_checked
+    }
+
+    /// Number of pages that were checked, including those of internal tables
+    pub fn pages_checked(&self) -> usize {
+        self.pages_checked
+    }
+}
+
+struct TreeInfo<'a> {
+    // None for the internal tables
+    table: Option<&'a str>,
+    description: &'a str,
+    fixed_key_size: Option<usize>,
+    fixed_value_size: Option<usize>,
+    compare: Option<KeyCompare>,
+    // Whether the keys, or values, are strs, which must be valid UTF-8
+    str_keys: bool,
+    str_values: bool,
+}
+
+fn is_str(type_name: &str) -> bool {
+    type_name == <&str>::redb_type_name()
+}
+
+struct IntegrityChecker<'a> {
+    mem: &'a TransactionalMemory,
+    // Every allocated page, split into pages of order 0, if the allocator state is checked
+    allocated: Option<HashSet<PageNumber>>,
+    // Every referenced page, split into pages of order 0, and a description of what references it
+    referenced: HashMap<PageNumber, String>,
+    report: IntegrityReport,
+}
+
+// Checks the trees of the latest commit, and the allocator state.
+// Caller must ensure that no write transaction is in progress
+pub(crate) fn check_integrity(mem: &TransactionalMemory) -> Result<IntegrityReport> {
+    let allocated = mem.allocated_pages()?.into_iter().collect();
+    let mut checker = IntegrityChecker::new(mem, Some(allocated));
+    checker.check_tables()?;
+    checker.check_freed_table()?;
+    checker.find_leaked_pages();
+    Ok(checker.report)
+}
+
+// Checks the trees of the commit which is the primary, but not the allocator state, which is
+// rebuilt by a repair. Returns None if the master table is corrupted, and otherwise the names of
+// the corrupted tables
+pub(crate) fn find_corrupted_tables(mem: &TransactionalMemory) -> Result<Option<Vec<String>>> {
+    let mut checker = IntegrityChecker::new(mem, None);
+    checker.check_tables()?;
+    let mut tables: Vec<String> = vec![];
+    for issue in checker.report.issues {
+        let table = if let Some(table) = issue.table {
+            table
+        } else {
+            return Ok(None);
+        };
+        if !tables.contains(&table) {
+            tables.push(table);
+        }
+    }
+    Ok(Some(tables))
+}
+
+impl<'a> IntegrityChecker<'a> {
+    fn new(mem: &'a TransactionalMemory, allocated: Option<HashSet<PageNumber>>) -> Self {
+        Self {
+            mem,
+            allocated,
+            referenced: HashMap::new(),
+            report: Default::default(),
+        }
+    }
+
+    // Checks the master table, and every table that it references
+    fn check_tables(&mut self) -> Result {
+        let mem = self.mem;
+        self.reference(None, mem.get_region_tracker_page(), "the region tracker");
+
+        let master = TreeInfo {
+            table: None,
+            description: "the master table",
+            fixed_key_size: <&str>::fixed_width(),
+            fixed_value_size: InternalTableDefinition::fixed_width(),
+            compare: Some(<&str>::compare),
+            str_keys: true,
+            str_values: false,
+        };
+        let tables = self.check_tree(&master, mem.get_data_root()?, true)?;
+        // The table definitions can't be trusted if the master table is corrupted
+        if !self.report.issues.is_empty() {
+            return Ok(());
+        }
+        for (name, definition) in tables {
+            let name = parse_table_name(&name)?.to_string();
+            let definition = InternalTableDefinition::from_bytes(&definition);
+            if definition.get_type() != TableType::System {
+                self.report.tables_checked += 1;
+            }
+            let description = format!("table '{}'", name);
+            let multimap = definition.get_type() == TableType::Multimap;
+            let tree = TreeInfo {
+                table: Some(&name),
+                description: &description,
+                fixed_key_size: definition.get_fixed_key_size(),
+                // The values of multimap tables reference the collection of values of each key
+                fixed_value_size: if multimap {
+                    None
+                } else {
+                    definition.get_fixed_value_size()
+                },
+                compare: builtin_key_compare(definition.get_key_type()),
+                str_keys: is_str(definition.get_key_type()),
+                str_values: !multimap && is_str(definition.get_value_type()),
+            };
+            let entries = self.check_tree(&tree, definition.get_root(), multimap)?;
+            let subtree = TreeInfo {
+                table: Some(&name),
+                description: &description,
+                fixed_key_size: definition.get_fixed_value_size(),
+                fixed_value_size: <() as RedbValue>::fixed_width(),
+                compare: builtin_key_compare(definition.get_value_type()),
+                str_keys: is_str(definition.get_value_type()),
+                str_values: false,
+            };
+            for (_, value) in entries {
+                if let Some(root) = parse_subtree_root(&value) {
+                    self.check_tree(&subtree, Some(root), false)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Checks the freed table, and records the pages which are waiting to be freed
+    fn check_freed_table(&mut self) -> Result {
+        let freed = TreeInfo {
+            table: None,
+            description: "the freed table",
+            fixed_key_size: FreedTableKey::fixed_width(),
+            fixed_value_size: <&[u8]>::fixed_width(),
+            compare: Some(FreedTableKey::compare),
+            str_keys: false,
+            str_values: false,
+        };
+        for (_, value) in self.check_tree(&freed, self.mem.get_freed_root()?, true)? {
+            // Each value is a length prefixed array of the pages which are waiting to be freed
+            let len = u64::from_le_bytes(value[..size_of::<u64>()].try_into().unwrap());
+            let pages = value[size_of::<u64>()..].chunks_exact(PageNumber::serialized_size());
+            for page in pages.take(len.try_into().unwrap()) {
+                let page = PageNumber::from_le_bytes(page.try_into().unwrap());
+                self.reference(None, page, "the freed table, as waiting to be freed");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn issue(
+        &mut self,
+        kind: IntegrityIssueKind,
+        table: Option<&str>,
+        page: Option<PageNumber>,
+        description: String,
+    ) {
+        self.report.issues.push(IntegrityIssue {
+            kind,
+            table: table.map(|x| x.to_string()),
+            offset: page.and_then(|page| self.mem.page_offset(page)),
+            description,
+        });
+    }
+
+    // Records a reference to the page. Returns false if the page is invalid, or was already
+    // referenced, in which case it must not be checked again
+    fn reference(&mut self, table: Option<&str>, page: PageNumber, referrer: &str) -> bool {
+        if self.mem.page_offset(page).is_none() {
+            self.issue(
+                IntegrityIssueKind::InvalidPage,
+                table,
+                None,
+                format!(
+                    "{} references page {:?}, which is outside the file",
+                    referrer, page
+                ),
+            );
+            return false;
+        }
+        let first = page.page_index << page.page_order;
+        let mut unallocated = false;
+        for i in first..(first + (1 << page.page_order)) {
+            let order0 = PageNumber::new(page.region, i, 0);
+            if let Some(existing) = self.referenced.get(&order0) {
+                let description = format!(
+                    "page {:?} is referenced by {}, and by {}",
+                    page, existing, referrer
+                );
+                self.issue(
+                    IntegrityIssueKind::DuplicateReference,
+                    table,
+                    Some(page),
+                    description,
+                );
+                return false;
+            }
+            self.referenced.insert(order0, referrer.to_string());
+            if let Some(allocated) = &self.allocated {
+                unallocated |= !allocated.contains(&order0);
+            }
+        }
+        if unallocated {
+            self.issue(
+                IntegrityIssueKind::UnallocatedPage,
+                table,
+                Some(page),
+                format!("page {:?}, referenced by {}, is free", page, referrer),
+            );
+        }
+        true
+    }
+
+    // Checks every page of the tree. If collect is true, returns all of its entries
+    fn check_tree(
+        &mut self,
+        tree: &TreeInfo,
+        root: Option<(PageNumber, Checksum)>,
+        collect: bool,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut entries = vec![];
+        if let Some((page, checksum)) = root {
+            if self.reference(tree.table, page, tree.description) {
+                let mut leaf_depth = None;
+                let mut collected = if collect { Some(&mut entries) } else { None };
+                self.check_page(
+                    tree,
+                    page,
+                    checksum,
+                    (None, None),
+                    0,
+                    &mut leaf_depth,
+                    &mut collected,
+                )?;
+            }
+        }
+        Ok(entries)
+    }
+
+    // Checks the page, which must already be referenced, and all of its descendants. Keys must be
+    // greater than the lower bound, and less than or equal to the upper bound.
+    // Returns the number of entries in the subtree, if it is valid
+    #[allow(clippy::too_many_arguments)]
+    fn check_page(
+        &mut self,
+        tree: &TreeInfo,
+        page_number: PageNumber,
+        expected_checksum: Checksum,
+        bounds: (Option<&[u8]>, Option<&[u8]>),
+        depth: usize,
+        leaf_depth: &mut Option<usize>,
+        entries: &mut Option<&mut Vec<(Vec<u8>, Vec<u8>)>>,
+    ) -> Result<Option<u64>> {
+        self.report.pages_checked += 1;
+        let mem = self.mem;
+        let page = mem.get_page(page_number)?;
+        let checksum_type = mem.checksum_type();
+        match page.memory()[0] {
+            LEAF => {
+                let accessor =
+                    LeafAccessor::new(page.memory(), tree.fixed_key_size, tree.fixed_value_size);
+                if !accessor.verify_layout() || accessor.num_pairs() == 0 {
+                    self.invalid_page(tree, page_number, "is an empty or malformed leaf");
+                    return Ok(None);
+                }
+                let checksum = leaf_checksum(
+                    &page,
+                    tree.fixed_key_size,
+                    tree.fixed_value_size,
+                    checksum_type,
+                );
+                self.verify_checksum(tree, page_number, checksum, expected_checksum);
+                let expected_depth = *leaf_depth.get_or_insert(depth);
+                if depth != expected_depth {
+                    let description = format!(
+                        "is a leaf at depth {}, but other leaves are at depth {}",
+                        depth, expected_depth
+                    );
+                    self.invalid_page(tree, page_number, &description);
+                }
+                let keys: Vec<&[u8]> = (0..accessor.num_pairs())
+                    .map(|i| accessor.entry(i).unwrap().key())
+                    .collect();
+                self.verify_keys(tree, page_number, &keys, bounds);
+                let valid_strs = (0..accessor.num_pairs()).all(|i| {
+                    let entry = accessor.entry(i).unwrap();
+                    (!tree.str_keys || std::str::from_utf8(entry.key()).is_ok())
+                        && (!tree.str_values || std::str::from_utf8(entry.value()).is_ok())
+                });
+                if !valid_strs {
+                    self.invalid_page(tree, page_number, "contains a str which is not valid UTF-8");
+                }
+                if let Some(entries) = entries {
+                    for i in 0..accessor.num_pairs() {
+                        let entry = accessor.entry(i).unwrap();
+                        entries.push((entry.key().to_vec(), entry.value().to_vec()));
+                    }
+                }
+                Ok(Some(accessor.num_pairs().try_into().unwrap()))
+            }
+            BRANCH => {
+                let accessor = BranchAccessor::new(&page, tree.fixed_key_size);
+                if !accessor.verify_layout() {
+                    self.invalid_page(tree, page_number, "is a malformed branch");
+                    return Ok(None);
+                }
+                let checksum = branch_checksum(&page, tree.fixed_key_size, checksum_type);
+                self.verify_checksum(tree, page_number, checksum, expected_checksum);
+                let keys: Vec<&[u8]> = (0..(accessor.count_children() - 1))
+                    .map(|i| accessor.key(i).unwrap())
+                    .collect();
+                self.verify_keys(tree, page_number, &keys, bounds);
+
+                let mut total = Some(0);
+                for i in 0..accessor.count_children() {
+                    let child = accessor.child_page(i).unwrap();
+                    let lower = if i == 0 { bounds.0 } else { Some(keys[i - 1]) };
+                    let upper = if i < keys.len() {
+                        Some(keys[i])
+                    } else {
+                        bounds.1
+                    };
+                    if !self.reference(tree.table, child, tree.description) {
+                        total = None;
+                        continue;
+                    }
+                    let counted = self.check_page(
+                        tree,
+                        child,
+                        accessor.child_checksum(i).unwrap(),
+                        (lower, upper),
+                        depth + 1,
+                        leaf_depth,
+                        entries,
+                    )?;
+                    if let (Some(stored), Some(counted)) = (accessor.child_entries(i), counted) {
+                        if stored != counted {
+                            let description = format!(
+                                "records {} entries in child {}, but it has {}",
+                                stored, i, counted
+                            );
+                            self.invalid_page(tree, page_number, &description);
+                        }
+                    }
+                    total = total.zip(counted).map(|(total, counted)| total + counted);
+                }
+                Ok(total)
+            }
+            page_type => {
+                let description = format!("has invalid type {}", page_type);
+                self.invalid_page(tree, page_number, &description);
+                Ok(None)
+            }
+        }
+    }
+
+    fn invalid_page(&mut self, tree: &TreeInfo, page: PageNumber, description: &str) {
+        let description = format!("page {:?} of {} {}", page, tree.description, description);
+        self.issue(
+            IntegrityIssueKind::InvalidPage,
+            tree.table,
+            Some(page),
+            description,
+        );
+    }
+
+    fn verify_checksum(
+        &mut self,
+        tree: &TreeInfo,
+        page: PageNumber,
+        checksum: Checksum,
+        expected: Checksum,
+    ) {
+        if checksum != expected {
+            let description = format!(
+                "page {:?} of {} has checksum {}, but its parent expects {}",
+                page, tree.description, checksum, expected
+            );
+            self.issue(
+                IntegrityIssueKind::ChecksumMismatch,
+                tree.table,
+                Some(page),
+                description,
+            );
+        }
+    }
+
+    // Checks that the keys are strictly increasing, and within the bounds given by the parent.
+    // Only possible if the key type of the tree is known
+    fn verify_keys(
+        &mut self,
+        tree: &TreeInfo,
+        page: PageNumber,
+        keys: &[&[u8]],
+        bounds: (Option<&[u8]>, Option<&[u8]>),
+    ) {
+        let compare = if let Some(compare) = tree.compare {
+            compare
+        } else {
+            return;
+        };
+        let mut out_of_order = (1..keys.len()).any(|i| compare(keys[i - 1], keys[i]).is_ge());
+        if let (Some(lower), Some(first)) = (bounds.0, keys.first()) {
+            out_of_order |= compare(first, lower).is_le();
+        }
+        if let (Some(upper), Some(last)) = (bounds.1, keys.last()) {
+            out_of_order |= compare(last, upper).is_gt();
+        }
+        if out_of_order {
+            let description = format!(
+                "keys of page {:?} of {} are out of order, or outside of the range given by its parent",
+                page, tree.description
+            );
+            self.issue(
+                IntegrityIssueKind::KeyOrder,
+                tree.table,
+                Some(page),
+                description,
+            );
+        }
+    }
+
+    fn find_leaked_pages(&mut self) {
+        let allocated = if let Some(allocated) = &self.allocated {
+            allocated
+        } else {
+            return;
+        };
+        let mut leaked: Vec<PageNumber> = allocated
+            .iter()
+            .filter(|page| !self.referenced.contains_key(page))
+            .copied()
+            .collect();
+        leaked.sort();
+        // Report each run of consecutive pages once
+        let mut i = 0;
+        while i < leaked.len() {
+            let first = leaked[i];
+            let mut end = i + 1;
+            while end < leaked.len()
+                && leaked[end].region == first.region
+                && leaked[end].page_index == first.page_index + u32::try_from(end - i).unwrap()
+            {
+                end += 1;
+            }
+            let description = format!(
+                "{} pages, starting at page {:?}, are allocated but not referenced",
+                end - i,
+                first
+            );
+            self.issue(
+                IntegrityIssueKind::LeakedPages,
+                None,
+                Some(first),
+                description,
+            );
+            i = end;
+        }
+    }
+}
@@ -2,15 +2,35 @@ mod btree;
 mod btree_base;
 mod btree_iters;
 mod btree_mutator;
+mod dump;
+mod integrity;
 mod page_store;
+mod scrub;
 mod table_tree;
 
-pub(crate) use btree::{Btree, BtreeMut, RawBtree};
+#[cfg(feature = "integrity_checks")]
+pub(crate) use btree::verify_tree;
+pub(crate) use btree::{copy_tree, Btree, BtreeMut, RawBtree};
 pub use btree_base::AccessGuard;
 pub(crate) use btree_base::AccessGuardMut;
 pub(crate) use btree_base::Checksum;
-pub(crate) use btree_base::{LeafAccessor, LeafKeyIter, RawLeafBuilder, BRANCH, LEAF};
-pub(crate) use btree_iters::{AllPageNumbersBtreeIter, BtreeRangeIter};
+pub(crate) use btree_base::{
+    EntryAccessor, LeafAccessor, LeafKeyIter, RawLeafBuilder, BRANCH, LEAF,
+};
+pub(crate) use btree_iters::{AllPageNumbersBtreeIter, BtreeCursor, BtreeRangeIter};
+pub(crate) use dump::dump_tree;
+pub use dump::{PageInfo, TreeDumpFormat, TreePageIter};
+pub(crate) use integrity::{check_integrity, find_corrupted_tables};
+pub use integrity::{IntegrityIssue, IntegrityIssueKind, IntegrityReport};
 pub use page_store::Savepoint;
-pub(crate) use page_store::{Page, PageNumber, TransactionalMemory};
-pub(crate) use table_tree::{FreedTableKey, InternalTableDefinition, TableTree, TableType};
+pub(crate) use page_store::{
+    make_sparse, read_file_format_version, upgrade_file_format, AllocationScope,
+    NestedAllocationState, Page, PageNumber, Storage, TransactionalMemory,
+};
+pub use scrub::Scrubber;
+#[cfg(feature = "integrity_checks")]
+pub(crate) use table_tree::IntegrityCheck;
+pub use table_tree::TableSchema;
+pub(crate) use table_tree::{
+    parse_table_name, table_type_name, FreedTableKey, InternalTableDefinition, TableTree, TableType,
+};
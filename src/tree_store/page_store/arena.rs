@@ -0,0 +1,161 @@
+use crate::tree_store::btree_base::Checksum;
+use crate::tree_store::btree_iters::RangeIterState;
+use crate::tree_store::page_store::PageNumber;
+use std::mem;
+use std::mem::MaybeUninit;
+use std::sync::Mutex;
+
+// Number of buffers of each kind which are kept for reuse
+const MAX_POOLED: usize = 64;
+// Byte buffers larger than this are returned to the allocator, rather than kept for reuse
+const MAX_POOLED_BUFFER_BYTES: usize = 64 * 1024;
+
+// Reusable scratch buffers for the write transaction. Building pages, serializing values, copying
+// out the values which an operation replaced, and the parent states of range iterators all need
+// short lived allocations, and making them fresh on every operation puts a lot of pressure on the
+// allocator in tight loops. Buffers are taken from the arena, and returned to it when their user
+// is dropped, so that their allocations are reused.
+//
+// Only the write transaction uses the arena, so that read transactions on other threads never
+// contend for its locks
+//
+// The arena is cleared when the write transaction commits or aborts, so that it only holds memory
+// for as long as the transaction that filled it
+#[derive(Default)]
+#[allow(clippy::type_complexity)]
+pub(crate) struct Arena {
+    bytes: Mutex<Vec<Vec<u8>>>,
+    pairs: Mutex<Vec<Vec<(&'static [u8], &'static [u8])>>>,
+    keys: Mutex<Vec<Vec<&'static [u8]>>>,
+    children: Mutex<Vec<Vec<(PageNumber, Checksum)>>>,
+    entries: Mutex<Vec<Vec<Option<u64>>>>,
+    // Allocations which held a RangeIterState, of any lifetime, which has been moved out. They're
+    // kept boxed, because it's the allocations themselves which are reused
+    #[allow(clippy::vec_box)]
+    states: Mutex<Vec<Box<MaybeUninit<RangeIterState<'static>>>>>,
+}
+
+impl Arena {
+    pub(crate) fn new() -> Self {
+        Default::default()
+    }
+
+    // Returns a zeroed buffer of the given length
+    pub(crate) fn take_bytes(&self, len: usize) -> Vec<u8> {
+        let mut buffer = take(&self.bytes, len);
+        buffer.resize(len, 0);
+        buffer
+    }
+
+    // Returns a copy of data
+    pub(crate) fn copy_bytes(&self, data: &[u8]) -> Vec<u8> {
+        let mut buffer = take(&self.bytes, data.len());
+        buffer.extend_from_slice(data);
+        buffer
+    }
+
+    pub(crate) fn give_bytes(&self, buffer: Vec<u8>) {
+        if buffer.capacity() <= MAX_POOLED_BUFFER_BYTES {
+            give(&self.bytes, buffer);
+        }
+    }
+
+    pub(crate) fn take_pairs<'a>(&self, capacity: usize) -> Vec<(&'a [u8], &'a [u8])> {
+        take(&self.pairs, capacity)
+    }
+
+    pub(crate) fn give_pairs(&self, pairs: Vec<(&[u8], &[u8])>) {
+        give(&self.pairs, recycle(pairs));
+    }
+
+    pub(crate) fn take_keys<'a>(&self, capacity: usize) -> Vec<&'a [u8]> {
+        take(&self.keys, capacity)
+    }
+
+    pub(crate) fn give_keys(&self, keys: Vec<&[u8]>) {
+        give(&self.keys, recycle(keys));
+    }
+
+    pub(crate) fn take_children(&self, capacity: usize) -> Vec<(PageNumber, Checksum)> {
+        take(&self.children, capacity)
+    }
+
+    pub(crate) fn give_children(&self, children: Vec<(PageNumber, Checksum)>) {
+        give(&self.children, children);
+    }
+
+    pub(crate) fn take_entries(&self, capacity: usize) -> Vec<Option<u64>> {
+        take(&self.entries, capacity)
+    }
+
+    pub(crate) fn give_entries(&self, entries: Vec<Option<u64>>) {
+        give(&self.entries, entries);
+    }
+
+    pub(crate) fn box_state<'a>(&self, state: RangeIterState<'a>) -> Box<RangeIterState<'a>> {
+        match self.states.lock().unwrap().pop() {
+            Some(slot) => {
+                let slot = Box::into_raw(slot) as *mut RangeIterState<'a>;
+                // Safety: the allocation is for a RangeIterState, whose layout doesn't depend on
+                // its lifetime, and it's uninitialized, so nothing is dropped by writing to it
+                unsafe {
+                    slot.write(state);
+                    Box::from_raw(slot)
+                }
+            }
+            None => Box::new(state),
+        }
+    }
+
+    pub(crate) fn unbox_state<'a>(&self, state: Box<RangeIterState<'a>>) -> RangeIterState<'a> {
+        let slot = Box::into_raw(state);
+        // Safety: the state is moved out, and its allocation is then only used as uninitialized
+        // memory, so the state is not dropped twice
+        let (state, slot) = unsafe {
+            (
+                slot.read(),
+                Box::from_raw(slot as *mut MaybeUninit<RangeIterState<'static>>),
+            )
+        };
+        let mut pool = self.states.lock().unwrap();
+        if pool.len() < MAX_POOLED {
+            pool.push(slot);
+        }
+        state
+    }
+
+    // Returns all the pooled buffers to the allocator
+    pub(crate) fn clear(&self) {
+        mem::take(&mut *self.bytes.lock().unwrap());
+        mem::take(&mut *self.pairs.lock().unwrap());
+        mem::take(&mut *self.keys.lock().unwrap());
+        mem::take(&mut *self.children.lock().unwrap());
+        mem::take(&mut *self.entries.lock().unwrap());
+        mem::take(&mut *self.states.lock().unwrap());
+    }
+}
+
+fn take<T>(pool: &Mutex<Vec<Vec<T>>>, capacity: usize) -> Vec<T> {
+    let mut buffer = pool.lock().unwrap().pop().unwrap_or_default();
+    buffer.reserve(capacity);
+    buffer
+}
+
+fn give<T>(pool: &Mutex<Vec<Vec<T>>>, mut buffer: Vec<T>) {
+    if buffer.capacity() == 0 {
+        return;
+    }
+    buffer.clear();
+    let mut pool = pool.lock().unwrap();
+    if pool.len() < MAX_POOLED {
+        pool.push(buffer);
+    }
+}
+
+// Clears the vector so that it can be stored with a different element type of the same layout,
+// such as references of a different lifetime. Collecting from the vector's own iterator reuses its
+// allocation
+fn recycle<T, U>(mut buffer: Vec<T>) -> Vec<U> {
+    buffer.clear();
+    buffer.into_iter().map(|_| unreachable!()).collect()
+}
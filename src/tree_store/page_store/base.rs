@@ -1,3 +1,4 @@
+use crate::tree_store::page_store::layout::DatabaseLayout;
 use crate::tree_store::page_store::page_manager::MAX_MAX_PAGE_ORDER;
 #[cfg(debug_assertions)]
 use std::collections::HashMap;
@@ -75,6 +76,15 @@ impl PageNumber {
         start..end
     }
 
+    // Returns true if the page lies within the data section of one of the regions of layout
+    pub(super) fn is_within(&self, layout: &DatabaseLayout) -> bool {
+        if self.region >= layout.num_regions() || self.page_order as usize > MAX_MAX_PAGE_ORDER {
+            return false;
+        }
+        let region_pages = u64::from(layout.region_layout(self.region).num_pages());
+        (u64::from(self.page_index) + 1) << self.page_order <= region_pages
+    }
+
     pub(crate) fn page_size_bytes(&self, page_size: usize) -> usize {
         let pages = 1usize << self.page_order;
         pages * page_size
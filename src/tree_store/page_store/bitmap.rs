@@ -53,6 +53,13 @@ impl<'a> BtreeBitmap<'a> {
         Self { data }
     }
 
+    // Returns true if data has the same length, height, and level offsets as expected, which must
+    // have been initialized by BtreeBitmapMut::init_new(), so that it can be accessed safely
+    pub(crate) fn has_structure_of(data: &[u8], expected: &[u8]) -> bool {
+        let header_len = get_data_start(expected);
+        data.len() == expected.len() && data[..header_len] == expected[..header_len]
+    }
+
     pub(crate) fn count_unset(&self) -> usize {
         self.get_level(self.get_height() - 1).count_unset()
     }
@@ -78,10 +85,13 @@ impl<'a> BtreeBitmap<'a> {
             while height < self.get_height() - 1 {
                 height += 1;
                 entry *= 64;
-                entry = self
-                    .get_level(height)
-                    .first_unset(entry, entry + 64)
-                    .unwrap();
+                // The levels are only inconsistent if the file is corrupted. Treat the
+                // bitmap as full, rather than panicking
+                let level = self.get_level(height);
+                if entry >= level.len() {
+                    return None;
+                }
+                entry = level.first_unset(entry, entry + 64)?;
             }
 
             Some(entry as u64)
@@ -85,6 +85,23 @@ impl<'a> BuddyAllocator<'a> {
         Self { data }
     }
 
+    // Returns true if data has the same structure as expected, which must have been initialized
+    // by BuddyAllocatorMut::init_new() with the same capacity, so that it can be accessed safely.
+    // The number of pages may differ, and the allocation state itself is not checked
+    pub(crate) fn has_structure_of(data: &[u8], expected: &[u8]) -> bool {
+        let header_len = get_data_start(expected);
+        data.len() == expected.len()
+            && data[..NUM_PAGES_OFFSET] == expected[..NUM_PAGES_OFFSET]
+            && data[END_OFFSETS..header_len] == expected[END_OFFSETS..header_len]
+            && get_num_pages(data) <= BuddyAllocator::new(expected).capacity().try_into().unwrap()
+            && (0..=u32::from(get_max_order(expected))).all(|order| {
+                BtreeBitmap::has_structure_of(
+                    get_order_bytes(data, order),
+                    get_order_bytes(expected, order),
+                )
+            })
+    }
+
     #[inline]
     pub(crate) fn highest_free_order(&self) -> Option<usize> {
         (0..=self.get_max_order())
@@ -119,6 +136,13 @@ impl<'a> BuddyAllocator<'a> {
         None
     }
 
+    // Returns the first page, and order, of the free block which contains the given order 0 page,
+    // or None if the page is allocated
+    pub(crate) fn free_block(&self, page: u64) -> Option<(u64, usize)> {
+        let order = self.find_free_order(page)?;
+        Some(((page >> order) << order, order))
+    }
+
     pub(crate) fn trailing_free_pages(&self) -> usize {
         let mut free_pages = 0;
         let mut next_page = self.len() - 1;
@@ -366,6 +390,28 @@ impl<'a> BuddyAllocatorMut<'a> {
         }
     }
 
+    /// data must have been initialized by Self::init_new()
+    ///
+    /// Unlike alloc(), this splits a higher order page if it has a lower address than any free
+    /// page of the requested order
+    pub(crate) fn alloc_lowest(&mut self, order: usize) -> Option<u64> {
+        if order > self.get_max_order() {
+            return None;
+        }
+        let mut lowest = None;
+        for i in order..=self.get_max_order() {
+            let allocator = self.get_order_mut(i.try_into().unwrap());
+            if let Some(page) = allocator.find_first_unset() {
+                let candidate = page << (i - order);
+                lowest = Some(lowest.map_or(candidate, |x: u64| x.min(candidate)));
+            }
+        }
+        let page = lowest?;
+        self.record_alloc(page, order);
+
+        Some(page)
+    }
+
     /// data must have been initialized by Self::init_new(), and page_number must be free
     pub(crate) fn record_alloc(&mut self, page_number: u64, order: usize) {
         assert!(order <= self.get_max_order());
@@ -60,7 +60,7 @@ impl RegionLayout {
         }
     }
 
-    fn full_region_layout(page_capacity: u32, page_size: u32) -> RegionLayout {
+    pub(super) fn full_region_layout(page_capacity: u32, page_size: u32) -> RegionLayout {
         let header_pages = Self::header_pages(page_capacity, page_size);
 
         Self {
@@ -1,33 +1,170 @@
-use crate::{Error, Result};
+use crate::db::{AccessPattern, CacheStats, StorageBackend};
+use crate::error::IoResultExt;
+#[cfg(feature = "fault_injection")]
+use crate::FaultInjector;
+use crate::{Error, IoOperation, Result, StorageDevice};
+use std::fmt::{Debug, Formatter};
 use std::fs::File;
 use std::io;
 use std::io::ErrorKind;
 use std::ops::Range;
+use std::ptr;
 use std::slice;
 use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender};
 use std::sync::Mutex;
+use std::thread::JoinHandle;
+
+mod buffered;
+use buffered::FileBuffer;
 
 #[cfg(unix)]
 mod unix;
 #[cfg(unix)]
 use unix::*;
+#[cfg(unix)]
+pub(crate) use unix::{make_sparse, FileLock};
 
 #[cfg(windows)]
 mod windows;
 use crate::transaction_tracker::TransactionId;
 #[cfg(windows)]
 use windows::*;
+#[cfg(windows)]
+pub(crate) use windows::{make_sparse, FileLock};
+
+// Targets without memory mapping or file locking, such as wasm32, only support the buffered backend
+#[cfg(not(any(unix, windows)))]
+mod fallback;
+#[cfg(not(any(unix, windows)))]
+use fallback::*;
+#[cfg(not(any(unix, windows)))]
+pub(crate) use fallback::{make_sparse, FileLock};
+
+// Where the database is stored
+pub(crate) enum Storage {
+    File(File),
+    Device(Box<dyn StorageDevice>),
+}
+
+impl Storage {
+    pub(crate) fn file(&self) -> Option<&File> {
+        match self {
+            Storage::File(file) => Some(file),
+            Storage::Device(_) => None,
+        }
+    }
+
+    pub(crate) fn len(&self) -> io::Result<u64> {
+        match self {
+            Storage::File(file) => Ok(file.metadata()?.len()),
+            Storage::Device(device) => device.len(),
+        }
+    }
+
+    pub(crate) fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        match self {
+            Storage::File(file) => buffered::read_exact_at(file, buf, offset),
+            Storage::Device(device) => device.read(offset, buf),
+        }
+    }
+
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        match self {
+            Storage::File(file) => buffered::write_all_at(file, buf, offset),
+            Storage::Device(device) => device.write(offset, buf),
+        }
+    }
+
+    pub(crate) fn set_len(&self, len: u64) -> io::Result<()> {
+        match self {
+            Storage::File(file) => file.set_len(len),
+            Storage::Device(device) => device.set_len(len),
+        }
+    }
+
+    fn sync_data(&self) -> io::Result<()> {
+        match self {
+            Storage::File(file) => file.sync_data(),
+            Storage::Device(device) => device.sync_data(),
+        }
+    }
+}
+
+impl Debug for Storage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Storage::File(file) => file.fmt(f),
+            Storage::Device(_) => f.write_str("StorageDevice"),
+        }
+    }
+}
+
+// Starts writing back dirty pages on a background thread, whenever enough memory has been written
+struct BackgroundFlusher {
+    threshold: usize,
+    written: AtomicUsize,
+    sender: Mutex<Option<SyncSender<()>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl BackgroundFlusher {
+    fn new(file: File, threshold: usize) -> Self {
+        // Requests which arrive while a write back is in progress are coalesced
+        let (sender, receiver) = sync_channel(1);
+        let thread = std::thread::spawn(move || {
+            while receiver.recv().is_ok() {
+                // Errors are reported by the fsync of the next commit
+                let _ = start_write_back(&file);
+            }
+        });
+        Self {
+            threshold,
+            written: AtomicUsize::new(0),
+            sender: Mutex::new(Some(sender)),
+            thread: Some(thread),
+        }
+    }
+
+    fn written(&self, bytes: usize) {
+        if self.written.fetch_add(bytes, Ordering::AcqRel) + bytes >= self.threshold {
+            self.written.store(0, Ordering::Release);
+            if let Some(ref sender) = *self.sender.lock().unwrap() {
+                let _ = sender.try_send(());
+            }
+        }
+    }
+}
+
+impl Drop for BackgroundFlusher {
+    fn drop(&mut self) {
+        self.sender.lock().unwrap().take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
 
 pub(crate) struct Mmap {
-    file: File,
-    _lock: FileLock,
+    storage: Storage,
+    _lock: Option<FileLock>,
     old_mmaps: Mutex<Vec<(TransactionId, MmapInner)>>,
-    mmap: Mutex<MmapInner>,
+    // None when the file is accessed through `buffer`, instead of being mapped
+    mmap: Mutex<Option<MmapInner>>,
+    buffer: Option<FileBuffer>,
+    // Allocate disk space whenever the file is extended, instead of leaving it sparse
+    preallocate: bool,
+    access_pattern: AccessPattern,
+    flusher: Option<BackgroundFlusher>,
+    // Base address of the current memory map. Not used by the buffered backend, which tracks the
+    // address of its buffer itself
     current_ptr: AtomicPtr<u8>,
     len: AtomicUsize,
     // TODO: this is an annoying hack and should be removed
     current_transaction_id: AtomicU64,
     fsync_failed: AtomicBool,
+    #[cfg(feature = "fault_injection")]
+    fault_injector: Option<FaultInjector>,
 }
 
 // mmap() is documented as being multi-thread safe
@@ -35,23 +172,50 @@ unsafe impl Send for Mmap {}
 unsafe impl Sync for Mmap {}
 
 impl Mmap {
-    pub(crate) fn new(file: File) -> Result<Self> {
-        let len = file.metadata()?.len();
-        let lock = FileLock::new(&file)?;
+    pub(crate) fn new(
+        storage: Storage,
+        lock: Option<FileLock>,
+        backend: StorageBackend,
+    ) -> Result<Self> {
+        let len = storage.len().during(IoOperation::Open)?;
+        #[cfg(not(any(unix, windows)))]
+        let backend = {
+            let _ = backend;
+            StorageBackend::Buffered
+        };
 
-        let mmap = MmapInner::create_mapping(&file, len)?;
+        let (mmap, buffer) = match (backend, storage.file()) {
+            (StorageBackend::Mmap, Some(file)) => (
+                Some(MmapInner::create_mapping(file, len, AccessPattern::Random)?),
+                None,
+            ),
+            // Only files can be mapped
+            (StorageBackend::Mmap, None) | (StorageBackend::Buffered, _) => {
+                (None, Some(FileBuffer::new(len.try_into().unwrap())?))
+            }
+        };
 
-        let address = mmap.base_addr();
+        let address = if let Some(ref mmap) = mmap {
+            mmap.base_addr()
+        } else {
+            ptr::null_mut()
+        };
 
         let mapping = Self {
-            file,
+            storage,
             _lock: lock,
             old_mmaps: Mutex::new(vec![]),
             mmap: Mutex::new(mmap),
+            buffer,
+            preallocate: false,
+            access_pattern: AccessPattern::Random,
+            flusher: None,
             current_ptr: AtomicPtr::new(address),
             len: AtomicUsize::new(len.try_into().unwrap()),
             current_transaction_id: AtomicU64::new(0),
             fsync_failed: AtomicBool::new(false),
+            #[cfg(feature = "fault_injection")]
+            fault_injector: None,
         };
 
         mapping.flush()?;
@@ -91,13 +255,44 @@ impl Mmap {
         self.check_fsync_failure()?;
 
         let mut mmap = self.mmap.lock().unwrap();
-        self.file.set_len(new_len as u64)?;
+        if let Some(ref buffer) = self.buffer {
+            buffer.check_capacity(new_len)?;
+        }
+        #[cfg(feature = "fault_injection")]
+        if let Some(ref injector) = self.fault_injector {
+            injector
+                .operation(|| self.storage.set_len(new_len as u64))
+                .during(IoOperation::Resize)?;
+        } else {
+            self.storage
+                .set_len(new_len as u64)
+                .during(IoOperation::Resize)?;
+        }
+        #[cfg(not(feature = "fault_injection"))]
+        self.storage
+            .set_len(new_len as u64)
+            .during(IoOperation::Resize)?;
+        if let Some(file) = self.storage.file() {
+            if self.preallocate && new_len > self.len() {
+                preallocate(file, (self.len() as u64)..(new_len as u64))?;
+            }
+        }
+        if let Some(ref buffer) = self.buffer {
+            buffer.resize(self.len(), new_len)?;
+            self.len.store(new_len, Ordering::Release);
+            return Ok(());
+        }
+        let mmap = mmap.as_mut().unwrap();
         if mmap.can_resize(new_len as u64) {
             mmap.resize(new_len as u64)?;
         } else {
             let transaction_id = TransactionId(self.current_transaction_id.load(Ordering::Acquire));
-            let new_mmap = MmapInner::create_mapping(&self.file, new_len as u64)?;
-            let old_mmap = std::mem::replace(&mut *mmap, new_mmap);
+            let new_mmap = MmapInner::create_mapping(
+                self.storage.file().unwrap(),
+                new_len as u64,
+                self.access_pattern,
+            )?;
+            let old_mmap = std::mem::replace(mmap, new_mmap);
             self.old_mmaps
                 .lock()
                 .unwrap()
@@ -110,10 +305,122 @@ impl Mmap {
         Ok(())
     }
 
+    // Allocates disk space for the whole file, and for all future growth. Has no effect on a
+    // StorageDevice
+    pub(crate) fn enable_preallocation(&mut self) -> Result {
+        self.preallocate = true;
+        if let Some(file) = self.storage.file() {
+            preallocate(file, 0..(self.len() as u64))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn set_access_pattern(&mut self, pattern: AccessPattern) -> Result {
+        self.access_pattern = pattern;
+        if let Some(ref mut mmap) = *self.mmap.get_mut().unwrap() {
+            mmap.advise(pattern)?;
+        }
+        Ok(())
+    }
+
+    // Writes back dirty pages in the background, each time that `bytes` of memory have been
+    // written. Only the memory map is written back, since blocks of the buffered backend could
+    // be modified while they are being written
+    pub(crate) fn enable_background_flush(&mut self, bytes: usize) -> Result {
+        if let (None, Some(file)) = (&self.buffer, self.storage.file()) {
+            let file = file.try_clone().during(IoOperation::Open)?;
+            self.flusher = Some(BackgroundFlusher::new(file, bytes));
+        }
+        Ok(())
+    }
+
+    // Hints that the given range will be read soon. Has no effect on the buffered backend, since it
+    // can only read synchronously
+    pub(crate) fn prefetch(&self, range: Range<usize>) {
+        if self.buffer.is_none() && range.end <= self.len() {
+            prefetch(self.current_ptr.load(Ordering::Acquire), range);
+        }
+    }
+
+    // Starts reading the whole file into memory
+    pub(crate) fn populate(&self) -> Result {
+        if let Some(ref buffer) = self.buffer {
+            buffer.load(&self.storage, 0..self.len(), self.len())
+        } else {
+            self.mmap
+                .lock()
+                .unwrap()
+                .as_ref()
+                .unwrap()
+                .will_need(self.len())
+        }
+    }
+
+    // Deallocates the disk space of the given range of the file, which then reads as zeroes.
+    // Has no effect on filesystems that don't support it, or on a StorageDevice
+    pub(crate) fn punch_hole(&self, range: Range<usize>) -> Result {
+        self.check_fsync_failure()?;
+        if let Some(file) = self.storage.file() {
+            punch_hole(file, (range.start as u64)..(range.end as u64))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn set_cache_size(&mut self, bytes: usize) {
+        if let Some(ref mut buffer) = self.buffer {
+            buffer.set_cache_size(bytes);
+        }
+    }
+
+    // Only the buffered backend is supported, since writes to a memory map can't be intercepted
+    #[cfg(feature = "fault_injection")]
+    pub(crate) fn set_fault_injector(&mut self, injector: FaultInjector) {
+        if let Some(ref mut buffer) = self.buffer {
+            buffer.set_fault_injector(injector.clone());
+            self.fault_injector = Some(injector);
+        }
+    }
+
+    pub(crate) fn cache_stats(&self) -> CacheStats {
+        if let Some(ref buffer) = self.buffer {
+            buffer.stats()
+        } else {
+            Default::default()
+        }
+    }
+
+    // Must be called when a transaction begins, before it accesses any memory beyond the header
+    pub(crate) fn pin(&self) {
+        if let Some(ref buffer) = self.buffer {
+            buffer.pin();
+        }
+    }
+
+    // Whether the buffer may be moved to a larger allocation once no transactions are open, which
+    // also moves the header. If so, unpin() must be called while holding the metadata lock
+    pub(crate) fn is_relocatable(&self) -> bool {
+        matches!(self.buffer, Some(ref buffer) if buffer.is_relocatable())
+    }
+
+    // Must be called when a transaction ends, once all its references to memory have been dropped
+    pub(crate) fn unpin(&self) -> Result {
+        if let Some(ref buffer) = self.buffer {
+            self.check_fsync_failure()?;
+            buffer.unpin(&self.storage, self.len())
+        } else {
+            Ok(())
+        }
+    }
+
     #[inline]
     fn check_fsync_failure(&self) -> Result<()> {
         if self.fsync_failed.load(Ordering::Acquire) {
-            Err(Error::Io(io::Error::from(ErrorKind::Other)))
+            Err(Error::io(
+                IoOperation::Sync,
+                io::Error::from(ErrorKind::Other),
+            ))
         } else {
             Ok(())
         }
@@ -128,7 +435,16 @@ impl Mmap {
     pub(crate) fn flush(&self) -> Result<()> {
         self.check_fsync_failure()?;
 
-        let res = self.mmap.lock().unwrap().flush();
+        if let Some(ref buffer) = self.buffer {
+            self.write_back(buffer)?;
+            let res = self.sync_data().during(IoOperation::Sync);
+            if !buffer.end_sync(res.is_ok()) {
+                self.set_fsync_failed(true);
+            }
+            self.check_recoverable(&res);
+            return res;
+        }
+        let res = self.mmap.lock().unwrap().as_ref().unwrap().flush();
         if res.is_err() {
             self.set_fsync_failed(true);
         }
@@ -136,10 +452,38 @@ impl Mmap {
         res
     }
 
+    fn write_back(&self, buffer: &FileBuffer) -> Result {
+        let res = buffer.write_back(&self.storage, self.len());
+        self.check_recoverable(&res);
+
+        res
+    }
+
+    // The buffered backend keeps every block which has not been durably written dirty, so a
+    // flush which failed because the disk was full can be retried once space is freed. After any
+    // other error, it's unknown what reached the file
+    fn check_recoverable(&self, res: &Result) {
+        if matches!(res, Err(ref err) if !matches!(err, Error::StorageFull { .. })) {
+            self.set_fsync_failed(true);
+        }
+    }
+
+    fn sync_data(&self) -> io::Result<()> {
+        #[cfg(feature = "fault_injection")]
+        if let Some(ref injector) = self.fault_injector {
+            return injector.operation(|| self.storage.sync_data());
+        }
+        self.storage.sync_data()
+    }
+
     #[inline]
     pub(crate) fn eventual_flush(&self) -> Result {
         self.check_fsync_failure()?;
-        let res = self.mmap.lock().unwrap().eventual_flush();
+        if let Some(ref buffer) = self.buffer {
+            // The written blocks become durable whenever the OS writes them back
+            return self.write_back(buffer);
+        }
+        let res = self.mmap.lock().unwrap().as_ref().unwrap().eventual_flush();
         if res.is_err() {
             self.set_fsync_failed(true);
         }
@@ -147,26 +491,51 @@ impl Mmap {
         res
     }
 
+    #[inline]
+    fn base_addr(&self) -> *mut u8 {
+        if let Some(ref buffer) = self.buffer {
+            buffer.base_addr()
+        } else {
+            self.current_ptr.load(Ordering::Acquire)
+        }
+    }
+
     // Safety: caller must ensure that [start, end) does not alias any existing references returned
     // from .get_memory_mut()
-    pub(crate) unsafe fn get_memory(&self, range: Range<usize>) -> &[u8] {
+    pub(crate) unsafe fn get_memory(&self, range: Range<usize>) -> Result<&[u8]> {
         assert!(range.end <= self.len());
-        // TODO: propagate the error
-        self.check_fsync_failure()
-            .expect("fsync previously failed. Connection closed");
-        let ptr = self.current_ptr.load(Ordering::Acquire).add(range.start);
-        slice::from_raw_parts(ptr, range.len())
+        // The memory map can write modified pages back to the file at any time, so it must not
+        // be used once a flush has failed. The buffered backend only writes to the file when it's
+        // flushed, which fails instead
+        if self.buffer.is_none() {
+            self.check_fsync_failure()?;
+        }
+        if let Some(ref buffer) = self.buffer {
+            buffer.load(&self.storage, range.clone(), self.len())?;
+        }
+        let ptr = self.base_addr().add(range.start);
+        Ok(slice::from_raw_parts(ptr, range.len()))
     }
 
     // Safety: caller must ensure that [start, end) does not alias any existing references returned
     // from .get_memory() or .get_memory_mut()
     #[allow(clippy::mut_from_ref)]
-    pub(crate) unsafe fn get_memory_mut(&self, range: Range<usize>) -> &mut [u8] {
+    pub(crate) unsafe fn get_memory_mut(&self, range: Range<usize>) -> Result<&mut [u8]> {
         assert!(range.end <= self.len());
-        // TODO: propagate the error
-        self.check_fsync_failure()
-            .expect("fsync previously failed. Connection closed");
-        let ptr = self.current_ptr.load(Ordering::Acquire).add(range.start);
-        slice::from_raw_parts_mut(ptr, range.len())
+        // The memory map can write modified pages back to the file at any time, so it must not
+        // be used once a flush has failed. The buffered backend only writes to the file when it's
+        // flushed, which fails instead
+        if self.buffer.is_none() {
+            self.check_fsync_failure()?;
+        }
+        if let Some(ref buffer) = self.buffer {
+            buffer.load(&self.storage, range.clone(), self.len())?;
+            buffer.mark_dirty(range.clone());
+        }
+        if let Some(ref flusher) = self.flusher {
+            flusher.written(range.len());
+        }
+        let ptr = self.base_addr().add(range.start);
+        Ok(slice::from_raw_parts_mut(ptr, range.len()))
     }
 }
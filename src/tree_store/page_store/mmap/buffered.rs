@@ -0,0 +1,613 @@
+use super::*;
+use crate::tree_store::page_store::page_manager::DB_HEADER_SIZE;
+use crate::tree_store::page_store::utils::get_page_size;
+use std::cmp::{max, min};
+use std::ptr;
+use std::sync::RwLock;
+
+// Granularity at which the file is read into, and written back from, the buffer
+const BLOCK_SIZE: usize = 4096;
+
+// Maximum size of the file, on targets which can reserve address space without allocating memory.
+// This is enough for all the regions that a database can have
+#[cfg(target_pointer_width = "64")]
+const CAPACITY: usize = 4 << 40;
+#[cfg(not(target_pointer_width = "64"))]
+const CAPACITY: usize = 1 << 30;
+
+// Other targets, such as wasm32, allocate all of the buffer. It's sized from the length of the
+// file, and moved to a larger allocation as the file grows
+#[cfg(any(unix, windows))]
+const RELOCATABLE: bool = false;
+#[cfg(not(any(unix, windows)))]
+const RELOCATABLE: bool = true;
+const MIN_RELOCATABLE_CAPACITY: usize = 1 << 20;
+
+// Leaves room for the file to double in size before the buffer must be moved
+fn relocatable_capacity(len: usize) -> usize {
+    max(round_up_to_page(len) * 2, MIN_RELOCATABLE_CAPACITY)
+}
+
+fn block_count(len: usize) -> usize {
+    (len + BLOCK_SIZE - 1) / BLOCK_SIZE
+}
+
+fn round_up_to_page(len: usize) -> usize {
+    let page_size = get_page_size();
+    (len + page_size - 1) / page_size * page_size
+}
+
+#[derive(Default)]
+struct BlockSet {
+    words: Vec<AtomicU64>,
+}
+
+impl BlockSet {
+    fn get(&self, block: usize) -> bool {
+        self.words[block / 64].load(Ordering::Acquire) & (1 << (block % 64)) != 0
+    }
+
+    fn insert(&self, block: usize) {
+        self.words[block / 64].fetch_or(1 << (block % 64), Ordering::Release);
+    }
+
+    fn remove(&self, block: usize) {
+        self.words[block / 64].fetch_and(!(1 << (block % 64)), Ordering::Release);
+    }
+
+    fn len(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.load(Ordering::Acquire).count_ones() as usize)
+            .sum()
+    }
+
+    // Removes all blocks, and returns them in ascending order
+    fn take(&self) -> Vec<usize> {
+        let mut blocks = vec![];
+        for (i, word) in self.words.iter().enumerate() {
+            let mut bits = word.swap(0, Ordering::AcqRel);
+            while bits != 0 {
+                blocks.push(i * 64 + bits.trailing_zeros() as usize);
+                bits &= bits - 1;
+            }
+        }
+        blocks
+    }
+
+    // Grows or shrinks the set to hold the given number of blocks. Blocks beyond the new length
+    // are removed
+    fn resize(&mut self, blocks: usize) {
+        self.words
+            .resize_with((blocks + 63) / 64, || AtomicU64::new(0));
+        let remainder = blocks % 64;
+        if remainder > 0 {
+            if let Some(last) = self.words.last_mut() {
+                *last.get_mut() &= (1 << remainder) - 1;
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Blocks {
+    // Blocks whose contents have been read from the file
+    loaded: BlockSet,
+    // Blocks which may have been modified since they were last written to the file
+    dirty: BlockSet,
+    // Blocks which have been written to the file since it was last synced
+    unsynced: BlockSet,
+    // Tick at which each frame was last accessed, used to evict the least recently used frames
+    last_access: Vec<AtomicU64>,
+}
+
+impl Blocks {
+    fn resize(&mut self, len: usize, frame_size: usize) {
+        self.loaded.resize(block_count(len));
+        self.dirty.resize(block_count(len));
+        self.unsynced.resize(block_count(len));
+        self.last_access
+            .resize_with((len + frame_size - 1) / frame_size, || AtomicU64::new(0));
+    }
+}
+
+// An in-memory copy of the file, which is read with pread() the first time that each block is
+// accessed, and written back with pwrite() when it is flushed.
+//
+// The copy lives in reserved address space, so that it never moves while references into it
+// exist. Where address space can't be reserved, it's moved to a larger allocation once no
+// transactions are open, and a transaction which would grow the file beyond it fails until then.
+// Memory beyond the length of the file is always zeroed
+pub(super) struct FileBuffer {
+    memory: Mutex<ReservedMemory>,
+    // Base address of `memory`, which only changes when it's relocated
+    base: AtomicPtr<u8>,
+    relocatable: bool,
+    // Largest length which the file failed to grow to, since the buffer was last relocated
+    requested_len: AtomicUsize,
+    blocks: RwLock<Blocks>,
+    load_lock: Mutex<()>,
+    // Number of bytes of the file which may be cached, once no transactions are open
+    cache_size: Option<usize>,
+    // Number of open transactions. Blocks are only evicted, and the buffer relocated, when there
+    // are none, since references into the buffer may be held until the transaction which created
+    // them ends
+    pins: Mutex<usize>,
+    // Memory is evicted in frames, since it can only be released a whole OS page at a time
+    frame_size: usize,
+    clock: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    #[cfg(feature = "fault_injection")]
+    fault_injector: Option<FaultInjector>,
+}
+
+impl FileBuffer {
+    pub(super) fn new(len: usize) -> Result<Self> {
+        Self::with_relocation(len, RELOCATABLE)
+    }
+
+    fn with_relocation(len: usize, relocatable: bool) -> Result<Self> {
+        let capacity = if relocatable {
+            relocatable_capacity(len)
+        } else {
+            CAPACITY
+        };
+        if len > capacity {
+            return Err(exceeds_capacity());
+        }
+        let memory = ReservedMemory::reserve(capacity)?;
+        if len > 0 {
+            memory.commit(0..round_up_to_page(len))?;
+        }
+        let frame_size = round_up_to_page(BLOCK_SIZE);
+        let mut blocks: Blocks = Default::default();
+        blocks.resize(len, frame_size);
+
+        Ok(Self {
+            base: AtomicPtr::new(memory.base_addr()),
+            memory: Mutex::new(memory),
+            relocatable,
+            requested_len: AtomicUsize::new(0),
+            blocks: RwLock::new(blocks),
+            load_lock: Mutex::new(()),
+            cache_size: None,
+            pins: Mutex::new(0),
+            frame_size,
+            clock: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            #[cfg(feature = "fault_injection")]
+            fault_injector: None,
+        })
+    }
+
+    #[inline]
+    pub(super) fn base_addr(&self) -> *mut u8 {
+        self.base.load(Ordering::Acquire)
+    }
+
+    pub(super) fn is_relocatable(&self) -> bool {
+        self.relocatable
+    }
+
+    pub(super) fn set_cache_size(&mut self, bytes: usize) {
+        self.cache_size = Some(bytes);
+    }
+
+    #[cfg(feature = "fault_injection")]
+    pub(super) fn set_fault_injector(&mut self, injector: FaultInjector) {
+        self.fault_injector = Some(injector);
+    }
+
+    pub(super) fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            cached_bytes: self.blocks.read().unwrap().loaded.len() * BLOCK_SIZE,
+        }
+    }
+
+    pub(super) fn pin(&self) {
+        if self.cache_size.is_some() || self.relocatable {
+            *self.pins.lock().unwrap() += 1;
+        }
+    }
+
+    // If the buffer is relocatable, the caller must ensure that no references to the header exist
+    // until this returns
+    pub(super) fn unpin(&self, storage: &Storage, len: usize) -> Result {
+        if self.cache_size.is_none() && !self.relocatable {
+            return Ok(());
+        }
+        let mut pins = self.pins.lock().unwrap();
+        *pins -= 1;
+        if *pins > 0 {
+            return Ok(());
+        }
+        // Safety: no transactions are open, and new ones wait for the lock on `pins`
+        if self.relocatable {
+            unsafe { self.relocate(len)? };
+        }
+        match self.cache_size {
+            Some(cache_size)
+                if self.blocks.read().unwrap().loaded.len() * BLOCK_SIZE > cache_size =>
+            unsafe { self.evict(storage, len, cache_size) },
+            _ => Ok(()),
+        }
+    }
+
+    // Moves the buffer to an allocation sized for the current length of the file, if it's too
+    // small for the file to grow into, or mostly unused
+    //
+    // Safety: caller must ensure that no references to the buffer exist
+    unsafe fn relocate(&self, len: usize) -> Result {
+        let _blocks = self.blocks.write().unwrap();
+        let mut memory = self.memory.lock().unwrap();
+        let needed = max(len, self.requested_len.load(Ordering::Acquire));
+        let capacity = relocatable_capacity(needed);
+        if capacity <= memory.capacity() && memory.capacity() < 4 * capacity {
+            return Ok(());
+        }
+        let relocated = ReservedMemory::reserve(capacity)?;
+        if len > 0 {
+            relocated.commit(0..round_up_to_page(len))?;
+        }
+        ptr::copy_nonoverlapping(memory.base_addr(), relocated.base_addr(), len);
+        self.base.store(relocated.base_addr(), Ordering::Release);
+        *memory = relocated;
+        self.requested_len.store(0, Ordering::Release);
+
+        Ok(())
+    }
+
+    // Writes back all dirty blocks, and then evicts the least recently used frames, until no more
+    // than cache_size bytes are cached
+    //
+    // Safety: caller must ensure that no references to memory beyond the header exist
+    unsafe fn evict(&self, storage: &Storage, len: usize, cache_size: usize) -> Result {
+        self.write_back(storage, len)?;
+        let blocks = self.blocks.read().unwrap();
+        let blocks_per_frame = self.frame_size / BLOCK_SIZE;
+        let num_blocks = block_count(len);
+        // The header is read outside of transactions, so it is never evicted
+        let first_frame = round_up_to_page(DB_HEADER_SIZE) / self.frame_size;
+        let mut frames: Vec<(u64, usize)> = (first_frame..blocks.last_access.len())
+            .filter(|frame| {
+                let start = frame * blocks_per_frame;
+                (start..min(start + blocks_per_frame, num_blocks))
+                    .any(|block| blocks.loaded.get(block))
+            })
+            .map(|frame| (blocks.last_access[frame].load(Ordering::Relaxed), frame))
+            .collect();
+        frames.sort_unstable();
+
+        let mut cached = blocks.loaded.len() * BLOCK_SIZE;
+        for (_, frame) in frames {
+            if cached <= cache_size {
+                break;
+            }
+            let start = frame * self.frame_size;
+            let end = min(start + self.frame_size, round_up_to_page(len));
+            let memory = self.memory.lock().unwrap();
+            memory.decommit(start..end)?;
+            memory.commit(start..end)?;
+            for block in (start / BLOCK_SIZE)..min(block_count(end), num_blocks) {
+                if blocks.loaded.get(block) {
+                    blocks.loaded.remove(block);
+                    cached -= BLOCK_SIZE;
+                    self.evictions.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Reads any blocks in the given range which have not been accessed yet from the file
+    pub(super) fn load(&self, storage: &Storage, range: Range<usize>, len: usize) -> Result {
+        if range.is_empty() {
+            return Ok(());
+        }
+        let blocks = self.blocks.read().unwrap();
+        let tick = self.clock.fetch_add(1, Ordering::Relaxed);
+        for frame in (range.start / self.frame_size)..=((range.end - 1) / self.frame_size) {
+            blocks.last_access[frame].store(tick, Ordering::Relaxed);
+        }
+        let block_range = (range.start / BLOCK_SIZE)..block_count(range.end);
+        if block_range.clone().all(|block| blocks.loaded.get(block)) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        let _guard = self.load_lock.lock().unwrap();
+        let mut block = block_range.start;
+        while block < block_range.end {
+            if blocks.loaded.get(block) {
+                block += 1;
+                continue;
+            }
+            // Read each run of missing blocks with a single call
+            let start = block;
+            while block < block_range.end && !blocks.loaded.get(block) {
+                block += 1;
+            }
+            let offset = start * BLOCK_SIZE;
+            let end = min(block * BLOCK_SIZE, len);
+            // Safety: no references to blocks which are not loaded can exist
+            let buf =
+                unsafe { slice::from_raw_parts_mut(self.base_addr().add(offset), end - offset) };
+            storage
+                .read_exact_at(buf, offset as u64)
+                .during(IoOperation::Read)?;
+            for loaded in start..block {
+                blocks.loaded.insert(loaded);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn mark_dirty(&self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        let blocks = self.blocks.read().unwrap();
+        for block in (range.start / BLOCK_SIZE)..block_count(range.end) {
+            blocks.dirty.insert(block);
+        }
+    }
+
+    // Writes all dirty blocks back to the file. If a write fails, the blocks which have not been
+    // written remain dirty
+    pub(super) fn write_back(&self, storage: &Storage, len: usize) -> Result {
+        let blocks = self.blocks.read().unwrap();
+        let dirty = blocks.dirty.take();
+        let mut i = 0;
+        while i < dirty.len() {
+            // Write each run of contiguous blocks with a single call
+            let first = i;
+            let start = dirty[i];
+            let mut end = start + 1;
+            i += 1;
+            while i < dirty.len() && dirty[i] == end {
+                end += 1;
+                i += 1;
+            }
+            if let Err(err) = self.write_blocks(storage, start..end, len) {
+                for block in &dirty[first..] {
+                    blocks.dirty.insert(*block);
+                }
+                return Err(err);
+            }
+            for block in start..end {
+                blocks.unsynced.insert(block);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Must be called after the file is synced. If the sync failed, the OS may have discarded the
+    // blocks which were written since the last sync, so they are marked dirty to be written
+    // again. Returns false if that isn't possible, because some of them have been evicted
+    pub(super) fn end_sync(&self, synced: bool) -> bool {
+        let blocks = self.blocks.read().unwrap();
+        let unsynced = blocks.unsynced.take();
+        if synced {
+            return true;
+        }
+        let mut recovered = true;
+        for block in unsynced {
+            if blocks.loaded.get(block) {
+                blocks.dirty.insert(block);
+            } else {
+                recovered = false;
+            }
+        }
+
+        recovered
+    }
+
+    fn write_blocks(&self, storage: &Storage, blocks: Range<usize>, len: usize) -> Result {
+        let offset = blocks.start * BLOCK_SIZE;
+        let buf = unsafe {
+            slice::from_raw_parts(
+                self.base_addr().add(offset),
+                min(blocks.end * BLOCK_SIZE, len) - offset,
+            )
+        };
+        #[cfg(feature = "fault_injection")]
+        if let Some(ref injector) = self.fault_injector {
+            return injector
+                .write(buf, |buf| storage.write_all_at(buf, offset as u64))
+                .during(IoOperation::Write);
+        }
+        storage
+            .write_all_at(buf, offset as u64)
+            .during(IoOperation::Write)
+    }
+
+    // Must be called before the file is grown to `len`. If the buffer is relocatable, and too
+    // small, the length is remembered so that it's large enough once it's relocated
+    pub(super) fn check_capacity(&self, len: usize) -> Result {
+        if len <= self.memory.lock().unwrap().capacity() {
+            return Ok(());
+        }
+        if self.relocatable {
+            self.requested_len.fetch_max(len, Ordering::AcqRel);
+            Err(Error::StorageFull {
+                operation: IoOperation::Resize,
+                source: io::Error::new(
+                    ErrorKind::OutOfMemory,
+                    "The in-memory copy of the file is full, until no transactions are open",
+                ),
+            })
+        } else {
+            Err(exceeds_capacity())
+        }
+    }
+
+    /// Safety: if `new_len < old_len`, caller must ensure that no references to
+    /// memory in `new_len..old_len` exist
+    pub(super) unsafe fn resize(&self, old_len: usize, new_len: usize) -> Result {
+        self.check_capacity(new_len)?;
+        let mut blocks = self.blocks.write().unwrap();
+        let memory = self.memory.lock().unwrap();
+        let old_committed = round_up_to_page(old_len);
+        let new_committed = round_up_to_page(new_len);
+        if new_len > old_len {
+            if new_committed > old_committed {
+                memory.commit(old_committed..new_committed)?;
+            }
+            blocks.resize(new_len, self.frame_size);
+            // Blocks beyond the old length are zeroed in memory, and in the file
+            for block in block_count(old_len)..block_count(new_len) {
+                blocks.loaded.insert(block);
+            }
+        } else {
+            ptr::write_bytes(
+                self.base_addr().add(new_len),
+                0,
+                min(old_len, new_committed) - new_len,
+            );
+            if old_committed > new_committed {
+                memory.decommit(new_committed..old_committed)?;
+            }
+            blocks.resize(new_len, self.frame_size);
+        }
+
+        Ok(())
+    }
+}
+
+fn exceeds_capacity() -> Error {
+    Error::io(
+        IoOperation::Resize,
+        io::Error::new(
+            ErrorKind::OutOfMemory,
+            "File exceeds the maximum size of the buffered storage backend",
+        ),
+    )
+}
+
+#[cfg(unix)]
+pub(super) fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(unix)]
+pub(super) fn write_all_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(buf, offset)
+}
+
+#[cfg(windows)]
+pub(super) fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+// Positional I/O is not available on other targets, so the file is read and written by seeking.
+// The lock prevents concurrent reads and writes from moving each other's offset
+#[cfg(not(any(unix, windows)))]
+static SEEK_LOCK: Mutex<()> = Mutex::new(());
+
+#[cfg(not(any(unix, windows)))]
+pub(super) fn read_exact_at(mut file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::io::{Read, Seek, SeekFrom};
+    let _guard = SEEK_LOCK.lock().unwrap();
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(super) fn write_all_at(mut file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+    use std::io::{Seek, SeekFrom, Write};
+    let _guard = SEEK_LOCK.lock().unwrap();
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(buf)
+}
+
+#[cfg(windows)]
+pub(super) fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_write(buf, offset) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tree_store::page_store::mmap::buffered::{FileBuffer, MIN_RELOCATABLE_CAPACITY};
+    use crate::tree_store::page_store::mmap::Storage;
+    use crate::{Error, InMemoryStorage};
+    use std::slice;
+
+    #[test]
+    fn relocation() {
+        let len = 64 * 1024;
+        let storage = Storage::Device(Box::new(InMemoryStorage::new()));
+        storage.set_len(len as u64).unwrap();
+        let buffer = FileBuffer::with_relocation(len, true).unwrap();
+        let capacity = || buffer.memory.lock().unwrap().capacity();
+        assert_eq!(capacity(), MIN_RELOCATABLE_CAPACITY);
+
+        buffer.pin();
+        buffer.load(&storage, 0..len, len).unwrap();
+        unsafe { slice::from_raw_parts_mut(buffer.base_addr(), len) }.fill(7);
+        buffer.mark_dirty(0..len);
+        // The buffer can't move while a transaction is open
+        let grown_len = 2 * MIN_RELOCATABLE_CAPACITY;
+        assert!(matches!(
+            unsafe { buffer.resize(len, grown_len) },
+            Err(Error::StorageFull { .. })
+        ));
+        buffer.unpin(&storage, len).unwrap();
+        assert!(capacity() >= grown_len);
+
+        buffer.pin();
+        storage.set_len(grown_len as u64).unwrap();
+        unsafe { buffer.resize(len, grown_len).unwrap() };
+        let contents = unsafe { slice::from_raw_parts(buffer.base_addr(), grown_len) };
+        assert!(contents[..len].iter().all(|x| *x == 7));
+        assert!(contents[len..].iter().all(|x| *x == 0));
+        buffer.write_back(&storage, grown_len).unwrap();
+        let mut written = vec![0; len];
+        storage.read_exact_at(&mut written, 0).unwrap();
+        assert!(written.iter().all(|x| *x == 7));
+
+        // Once the file shrinks, the buffer is reduced to fit it
+        storage.set_len(len as u64).unwrap();
+        unsafe { buffer.resize(grown_len, len).unwrap() };
+        buffer.unpin(&storage, len).unwrap();
+        assert_eq!(capacity(), MIN_RELOCATABLE_CAPACITY);
+        let contents = unsafe { slice::from_raw_parts(buffer.base_addr(), len) };
+        assert!(contents.iter().all(|x| *x == 7));
+    }
+}
@@ -0,0 +1,130 @@
+use super::*;
+use crate::tree_store::page_store::utils::get_page_size;
+use std::alloc::{alloc_zeroed, dealloc, Layout};
+use std::ptr;
+
+// There is no way to lock the file on these targets, so it's the caller's responsibility not to
+// open it more than once
+pub(crate) struct FileLock;
+
+impl FileLock {
+    pub(crate) fn new(_file: &File) -> Result<Self> {
+        Ok(Self)
+    }
+}
+
+// Memory which never moves. Address space can't be reserved without allocating it on these
+// targets, so all of it is allocated up front
+pub(super) struct ReservedMemory {
+    ptr: *mut u8,
+    capacity: usize,
+}
+
+impl ReservedMemory {
+    fn layout(capacity: usize) -> Layout {
+        Layout::from_size_align(capacity, get_page_size()).unwrap()
+    }
+
+    pub(super) fn reserve(capacity: usize) -> Result<Self> {
+        let ptr = unsafe { alloc_zeroed(Self::layout(capacity)) };
+        if ptr.is_null() {
+            Err(Error::io(
+                IoOperation::Resize,
+                io::Error::from(ErrorKind::OutOfMemory),
+            ))
+        } else {
+            Ok(Self { ptr, capacity })
+        }
+    }
+
+    pub(super) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub(super) fn base_addr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    // All the memory is already accessible, and memory which isn't in use is zeroed
+    pub(super) fn commit(&self, range: Range<usize>) -> Result {
+        assert!(range.end <= self.capacity);
+        Ok(())
+    }
+
+    /// Safety: caller must ensure that no references to memory in the given range exist
+    pub(super) unsafe fn decommit(&self, range: Range<usize>) -> Result {
+        assert!(range.end <= self.capacity);
+        // The memory can't be released, but it must be zeroed if it's committed again
+        ptr::write_bytes(self.ptr.add(range.start), 0, range.len());
+        Ok(())
+    }
+}
+
+impl Drop for ReservedMemory {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr, Self::layout(self.capacity)) };
+    }
+}
+
+pub(super) fn preallocate(_file: &File, _range: Range<u64>) -> Result {
+    Ok(())
+}
+
+pub(super) fn start_write_back(_file: &File) -> Result {
+    Ok(())
+}
+
+pub(super) fn prefetch(_base: *mut u8, _range: Range<usize>) {}
+
+pub(crate) fn make_sparse(_file: &File) -> Result {
+    Ok(())
+}
+
+pub(super) fn punch_hole(_file: &File, _range: Range<u64>) -> Result {
+    Ok(())
+}
+
+// Memory mapping is not supported, so this is never constructed. See Mmap::new()
+pub(super) struct MmapInner {
+    _private: (),
+}
+
+impl MmapInner {
+    pub(super) fn create_mapping(_file: &File, _len: u64, _pattern: AccessPattern) -> Result<Self> {
+        Err(Error::io(
+            IoOperation::Open,
+            io::Error::new(
+                ErrorKind::Unsupported,
+                "Memory mapping is not supported on this target",
+            ),
+        ))
+    }
+
+    pub(super) fn can_resize(&self, _new_len: u64) -> bool {
+        unreachable!()
+    }
+
+    pub(super) fn base_addr(&self) -> *mut u8 {
+        unreachable!()
+    }
+
+    pub(super) fn advise(&mut self, _pattern: AccessPattern) -> Result {
+        unreachable!()
+    }
+
+    pub(super) fn will_need(&self, _len: usize) -> Result {
+        unreachable!()
+    }
+
+    pub(super) unsafe fn resize(&self, _len: u64) -> Result<()> {
+        unreachable!()
+    }
+
+    pub(super) fn flush(&self) -> Result {
+        unreachable!()
+    }
+
+    pub(super) fn eventual_flush(&self) -> Result {
+        unreachable!()
+    }
+}
@@ -1,21 +1,23 @@
 use super::*;
+use crate::tree_store::page_store::utils::get_page_size;
+use std::cmp::min;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::ptr;
 
-pub(super) struct FileLock {
+pub(crate) struct FileLock {
     fd: libc::c_int,
 }
 
 impl FileLock {
-    pub(super) fn new(file: &File) -> Result<Self> {
+    pub(crate) fn new(file: &File) -> Result<Self> {
         let fd = file.as_raw_fd();
         let result = unsafe { libc::flock(fd, libc::LOCK_EX | libc::LOCK_NB) };
         if result != 0 {
             let err = io::Error::last_os_error();
             if err.kind() == io::ErrorKind::WouldBlock {
-                Err(Error::DatabaseAlreadyOpen)
+                Err(Error::LockContention)
             } else {
-                Err(Error::Io(err))
+                Err(Error::io(IoOperation::Lock, err))
             }
         } else {
             Ok(Self { fd })
@@ -29,14 +31,199 @@ impl Drop for FileLock {
     }
 }
 
+// A range of address space, which is reserved up front so that it never moves, and is made
+// accessible as it is needed
+pub(super) struct ReservedMemory {
+    ptr: *mut u8,
+    capacity: usize,
+}
+
+impl ReservedMemory {
+    pub(super) fn reserve(capacity: usize) -> Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                capacity as libc::size_t,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            Err(Error::io(IoOperation::Resize, io::Error::last_os_error()))
+        } else {
+            Ok(Self {
+                ptr: ptr as *mut u8,
+                capacity,
+            })
+        }
+    }
+
+    pub(super) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub(super) fn base_addr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    // Makes the given page aligned range readable and writable. Newly committed memory is zeroed
+    pub(super) fn commit(&self, range: Range<usize>) -> Result {
+        assert!(range.end <= self.capacity);
+        let result = unsafe {
+            libc::mprotect(
+                self.ptr.add(range.start) as *mut libc::c_void,
+                range.len() as libc::size_t,
+                libc::PROT_READ | libc::PROT_WRITE,
+            )
+        };
+        if result != 0 {
+            Err(Error::io(IoOperation::Resize, io::Error::last_os_error()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Safety: caller must ensure that no references to memory in the given page aligned range exist
+    pub(super) unsafe fn decommit(&self, range: Range<usize>) -> Result {
+        assert!(range.end <= self.capacity);
+        // Replace the range with a fresh reservation, which releases the memory and ensures that
+        // it is zeroed if it is committed again. MADV_DONTNEED does not guarantee that on all
+        // platforms
+        let ptr = libc::mmap(
+            self.ptr.add(range.start) as *mut libc::c_void,
+            range.len() as libc::size_t,
+            libc::PROT_NONE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED,
+            -1,
+            0,
+        );
+        if ptr == libc::MAP_FAILED {
+            Err(Error::io(IoOperation::Resize, io::Error::last_os_error()))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Drop for ReservedMemory {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.capacity as libc::size_t);
+        }
+    }
+}
+
+// Allocates disk space for the given range of the file, so that it is not left sparse
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+pub(super) fn preallocate(file: &File, range: Range<u64>) -> Result {
+    let result = unsafe {
+        libc::posix_fallocate(
+            file.as_raw_fd(),
+            range.start.try_into().unwrap(),
+            (range.end - range.start).try_into().unwrap(),
+        )
+    };
+    if result != 0 {
+        Err(Error::io(
+            IoOperation::Resize,
+            io::Error::from_raw_os_error(result),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+// posix_fallocate() is not available, so the file is always extended lazily
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+pub(super) fn preallocate(_file: &File, _range: Range<u64>) -> Result {
+    Ok(())
+}
+
+// Starts writing back the dirty pages of the file, without waiting for them to be written
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(super) fn start_write_back(file: &File) -> Result {
+    let result =
+        unsafe { libc::sync_file_range(file.as_raw_fd(), 0, 0, libc::SYNC_FILE_RANGE_WRITE) };
+    if result != 0 {
+        Err(Error::io(IoOperation::Sync, io::Error::last_os_error()))
+    } else {
+        Ok(())
+    }
+}
+
+// Other platforms can only write back pages synchronously, with fsync(), which would consume any
+// write error before the commit's own fsync could report it
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(super) fn start_write_back(_file: &File) -> Result {
+    Ok(())
+}
+
+// Asks the OS to start reading the given range of the mapping at base, without waiting for it
+pub(super) fn prefetch(base: *mut u8, range: Range<usize>) {
+    let page_size = get_page_size();
+    let start = range.start / page_size * page_size;
+    // This is only a hint, so errors are ignored
+    unsafe {
+        libc::madvise(
+            base.add(start) as *mut libc::c_void,
+            (range.end - start) as libc::size_t,
+            libc::MADV_WILLNEED,
+        );
+    }
+}
+
+// Files are already sparse, unless their space is preallocated
+pub(crate) fn make_sparse(_file: &File) -> Result {
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(super) fn punch_hole(file: &File, range: Range<u64>) -> Result {
+    let result = unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            range.start.try_into().unwrap(),
+            (range.end - range.start).try_into().unwrap(),
+        )
+    };
+    if result != 0 {
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() == Some(libc::EOPNOTSUPP) {
+            Ok(())
+        } else {
+            Err(Error::io(IoOperation::Write, err))
+        }
+    } else {
+        Ok(())
+    }
+}
+
+// Hole punching is only supported on Linux, among Unix platforms
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(super) fn punch_hole(_file: &File, _range: Range<u64>) -> Result {
+    Ok(())
+}
+
 pub(super) struct MmapInner {
     mmap: *mut u8,
     capacity: usize,
     fd: RawFd,
+    advice: libc::c_int,
+}
+
+fn advice(pattern: AccessPattern) -> libc::c_int {
+    match pattern {
+        AccessPattern::Random => libc::MADV_RANDOM,
+        AccessPattern::Sequential => libc::MADV_SEQUENTIAL,
+        AccessPattern::Normal => libc::MADV_NORMAL,
+    }
 }
 
 impl MmapInner {
-    pub(super) fn create_mapping(file: &File, len: u64) -> Result<Self> {
+    pub(super) fn create_mapping(file: &File, len: u64, pattern: AccessPattern) -> Result<Self> {
         // Use len * 2, so that there is some room for growth without having to create a new mmap and GC it
         let capacity: usize = (len * 2).try_into().unwrap();
         let mmap = unsafe {
@@ -50,19 +237,18 @@ impl MmapInner {
             )
         };
         if mmap == libc::MAP_FAILED {
-            Err(io::Error::last_os_error().into())
+            Err(Error::io(IoOperation::Resize, io::Error::last_os_error()))
         } else {
-            // Accesses will primarily be jumping between nodes in b-trees, so will be to random pages
-            // Benchmarks show ~2x better performance when the database no longer fits in memory
-            let result =
-                unsafe { libc::madvise(mmap, capacity as libc::size_t, libc::MADV_RANDOM) };
+            let advice = advice(pattern);
+            let result = unsafe { libc::madvise(mmap, capacity as libc::size_t, advice) };
             if result != 0 {
-                Err(io::Error::last_os_error().into())
+                Err(Error::io(IoOperation::Resize, io::Error::last_os_error()))
             } else {
                 Ok(Self {
                     mmap: mmap as *mut u8,
                     capacity,
                     fd: file.as_raw_fd(),
+                    advice,
                 })
             }
         }
@@ -76,6 +262,38 @@ impl MmapInner {
         self.mmap
     }
 
+    pub(super) fn advise(&mut self, pattern: AccessPattern) -> Result {
+        self.advice = advice(pattern);
+        let result = unsafe {
+            libc::madvise(
+                self.mmap as *mut libc::c_void,
+                self.capacity as libc::size_t,
+                self.advice,
+            )
+        };
+        if result != 0 {
+            Err(Error::io(IoOperation::Resize, io::Error::last_os_error()))
+        } else {
+            Ok(())
+        }
+    }
+
+    // Asks the OS to start reading the first len bytes of the file into memory
+    pub(super) fn will_need(&self, len: usize) -> Result {
+        let result = unsafe {
+            libc::madvise(
+                self.mmap as *mut libc::c_void,
+                min(len, self.capacity) as libc::size_t,
+                libc::MADV_WILLNEED,
+            )
+        };
+        if result != 0 {
+            Err(Error::io(IoOperation::Read, io::Error::last_os_error()))
+        } else {
+            Ok(())
+        }
+    }
+
     /// Safety: if new_len < len(), caller must ensure that no references to memory in new_len..len() exist
     #[inline]
     pub(super) unsafe fn resize(&self, new_len: u64) -> Result<()> {
@@ -91,12 +309,12 @@ impl MmapInner {
         );
 
         if mmap == libc::MAP_FAILED {
-            Err(io::Error::last_os_error().into())
+            Err(Error::io(IoOperation::Resize, io::Error::last_os_error()))
         } else {
             assert_eq!(mmap as *mut u8, self.mmap);
-            let result = libc::madvise(mmap, self.capacity as libc::size_t, libc::MADV_RANDOM);
+            let result = libc::madvise(mmap, self.capacity as libc::size_t, self.advice);
             if result != 0 {
-                Err(io::Error::last_os_error().into())
+                Err(Error::io(IoOperation::Resize, io::Error::last_os_error()))
             } else {
                 Ok(())
             }
@@ -118,14 +336,14 @@ impl MmapInner {
                     )
                 };
                 if result != 0 {
-                    return Err(io::Error::last_os_error().into());
+                    return Err(Error::io(IoOperation::Sync, io::Error::last_os_error()));
                 }
             }
             #[cfg(target_os = "macos")]
             {
                 let code = unsafe { libc::fcntl(self.fd, libc::F_FULLFSYNC) };
                 if code == -1 {
-                    return Err(io::Error::last_os_error().into());
+                    return Err(Error::io(IoOperation::Sync, io::Error::last_os_error()));
                 }
             }
         }
@@ -144,7 +362,7 @@ impl MmapInner {
             //       Investigate switching to `write()`
             let code = unsafe { libc::fcntl(self.fd, libc::F_BARRIERFSYNC) };
             if code == -1 {
-                Err(io::Error::last_os_error().into())
+                Err(Error::io(IoOperation::Sync, io::Error::last_os_error()))
             } else {
                 Ok(())
             }
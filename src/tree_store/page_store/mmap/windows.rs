@@ -32,6 +32,11 @@ const LOCKFILE_FAIL_IMMEDIATELY: u32 = 0x00000001;
 const ERROR_LOCK_VIOLATION: i32 = 0x21;
 const ERROR_IO_PENDING: i32 = 997;
 const PAGE_READWRITE: u32 = 0x4;
+const PAGE_NOACCESS: u32 = 0x1;
+const MEM_COMMIT: u32 = 0x1000;
+const MEM_RESERVE: u32 = 0x2000;
+const MEM_DECOMMIT: u32 = 0x4000;
+const MEM_RELEASE: u32 = 0x8000;
 
 const STANDARD_RIGHTS_REQUIRED: u32 = 0x000f0000;
 
@@ -49,6 +54,16 @@ const SECTION_ALL_ACCESS: u32 = STANDARD_RIGHTS_REQUIRED
 
 const FILE_MAP_ALL_ACCESS: u32 = SECTION_ALL_ACCESS;
 
+const FSCTL_SET_SPARSE: u32 = 0x000900c4;
+const FSCTL_SET_ZERO_DATA: u32 = 0x000980c8;
+const FILE_ATTRIBUTE_SPARSE_FILE: u32 = 0x00000200;
+
+#[repr(C)]
+struct FILE_ZERO_DATA_INFORMATION {
+    file_offset: i64,
+    beyond_final_zero: i64,
+}
+
 #[repr(C)]
 struct SECURITY_ATTRIBUTES {
     length: u32,
@@ -105,8 +120,26 @@ extern "system" {
     /// <https://learn.microsoft.com/en-us/windows/win32/api/memoryapi/nf-memoryapi-unmapviewoffile>
     fn UnmapViewOfFile(base_address: *const u8) -> u32;
 
+    /// <https://learn.microsoft.com/en-us/windows/win32/api/ioapiset/nf-ioapiset-deviceiocontrol>
+    fn DeviceIoControl(
+        device: RawHandle,
+        control_code: u32,
+        in_buffer: *const c_void,
+        in_buffer_size: u32,
+        out_buffer: *mut c_void,
+        out_buffer_size: u32,
+        bytes_returned: *mut u32,
+        overlapped: *mut OVERLAPPED,
+    ) -> i32;
+
     /// <https://learn.microsoft.com/en-us/windows/win32/api/handleapi/nf-handleapi-closehandle>
     fn CloseHandle(handle: RawHandle) -> u32;
+
+    /// <https://learn.microsoft.com/en-us/windows/win32/api/memoryapi/nf-memoryapi-virtualalloc>
+    fn VirtualAlloc(address: *mut u8, size: usize, allocation_type: u32, protect: u32) -> *mut u8;
+
+    /// <https://learn.microsoft.com/en-us/windows/win32/api/memoryapi/nf-memoryapi-virtualfree>
+    fn VirtualFree(address: *mut u8, size: usize, free_type: u32) -> u32;
 }
 
 struct AutoHandle {
@@ -121,13 +154,13 @@ impl Drop for AutoHandle {
     }
 }
 
-pub(super) struct FileLock {
+pub(crate) struct FileLock {
     handle: RawHandle,
     overlapped: OVERLAPPED,
 }
 
 impl FileLock {
-    pub(super) fn new(file: &File) -> Result<Self> {
+    pub(crate) fn new(file: &File) -> Result<Self> {
         let handle = file.as_raw_handle();
         let overlapped = unsafe {
             let mut overlapped = std::mem::zeroed();
@@ -145,9 +178,9 @@ impl FileLock {
                 return if err.raw_os_error() == Some(ERROR_IO_PENDING)
                     || err.raw_os_error() == Some(ERROR_LOCK_VIOLATION)
                 {
-                    Err(Error::DatabaseAlreadyOpen)
+                    Err(Error::LockContention)
                 } else {
-                    Err(Error::Io(err))
+                    Err(Error::io(IoOperation::Lock, err))
                 };
             }
 
@@ -164,6 +197,142 @@ impl Drop for FileLock {
     }
 }
 
+// A range of address space, which is reserved up front so that it never moves, and is made
+// accessible as it is needed
+pub(super) struct ReservedMemory {
+    ptr: *mut u8,
+    capacity: usize,
+}
+
+impl ReservedMemory {
+    pub(super) fn reserve(capacity: usize) -> Result<Self> {
+        let ptr = unsafe { VirtualAlloc(ptr::null_mut(), capacity, MEM_RESERVE, PAGE_NOACCESS) };
+        if ptr.is_null() {
+            Err(Error::io(IoOperation::Resize, io::Error::last_os_error()))
+        } else {
+            Ok(Self { ptr, capacity })
+        }
+    }
+
+    pub(super) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub(super) fn base_addr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    // Makes the given page aligned range readable and writable. Newly committed memory is zeroed
+    pub(super) fn commit(&self, range: Range<usize>) -> Result {
+        assert!(range.end <= self.capacity);
+        let ptr = unsafe {
+            VirtualAlloc(
+                self.ptr.add(range.start),
+                range.len(),
+                MEM_COMMIT,
+                PAGE_READWRITE,
+            )
+        };
+        if ptr.is_null() {
+            Err(Error::io(IoOperation::Resize, io::Error::last_os_error()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Safety: caller must ensure that no references to memory in the given page aligned range exist
+    pub(super) unsafe fn decommit(&self, range: Range<usize>) -> Result {
+        assert!(range.end <= self.capacity);
+        if VirtualFree(self.ptr.add(range.start), range.len(), MEM_DECOMMIT) == 0 {
+            Err(Error::io(IoOperation::Resize, io::Error::last_os_error()))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Drop for ReservedMemory {
+    fn drop(&mut self) {
+        unsafe {
+            VirtualFree(self.ptr, 0, MEM_RELEASE);
+        }
+    }
+}
+
+// Extending a file with SetEndOfFile() already allocates its disk space, on NTFS
+pub(super) fn preallocate(_file: &File, _range: Range<u64>) -> Result {
+    Ok(())
+}
+
+// Windows can only flush a mapped view, which the background thread doesn't have
+pub(super) fn start_write_back(_file: &File) -> Result {
+    Ok(())
+}
+
+// PrefetchVirtualMemory() is not available on all supported versions of Windows
+pub(super) fn prefetch(_base: *mut u8, _range: Range<usize>) {}
+
+// Marks the file as sparse, so that extending it does not allocate disk space
+pub(crate) fn make_sparse(file: &File) -> Result {
+    let mut bytes_returned = 0;
+    let result = unsafe {
+        DeviceIoControl(
+            file.as_raw_handle(),
+            FSCTL_SET_SPARSE,
+            ptr::null(),
+            0,
+            ptr::null_mut(),
+            0,
+            &mut bytes_returned,
+            ptr::null_mut(),
+        )
+    };
+    if result == 0 {
+        Err(Error::io(IoOperation::Resize, io::Error::last_os_error()))
+    } else {
+        Ok(())
+    }
+}
+
+pub(super) fn punch_hole(file: &File, range: Range<u64>) -> Result {
+    use std::os::windows::fs::MetadataExt;
+
+    // Zeroing a range only deallocates it in sparse files. In other files, the zeroes are written
+    if file
+        .metadata()
+        .during(IoOperation::Write)?
+        .file_attributes()
+        & FILE_ATTRIBUTE_SPARSE_FILE
+        == 0
+    {
+        return Ok(());
+    }
+    let info = FILE_ZERO_DATA_INFORMATION {
+        file_offset: range.start.try_into().unwrap(),
+        beyond_final_zero: range.end.try_into().unwrap(),
+    };
+    let mut bytes_returned = 0;
+    let result = unsafe {
+        DeviceIoControl(
+            file.as_raw_handle(),
+            FSCTL_SET_ZERO_DATA,
+            &info as *const FILE_ZERO_DATA_INFORMATION as *const c_void,
+            std::mem::size_of::<FILE_ZERO_DATA_INFORMATION>()
+                .try_into()
+                .unwrap(),
+            ptr::null_mut(),
+            0,
+            &mut bytes_returned,
+            ptr::null_mut(),
+        )
+    };
+    if result == 0 {
+        Err(Error::io(IoOperation::Write, io::Error::last_os_error()))
+    } else {
+        Ok(())
+    }
+}
+
 pub(super) struct MmapInner {
     mmap: *mut u8,
     len: usize,
@@ -171,7 +340,7 @@ pub(super) struct MmapInner {
 }
 
 impl MmapInner {
-    pub(super) fn create_mapping(file: &File, len: u64) -> Result<Self> {
+    pub(super) fn create_mapping(file: &File, len: u64, _pattern: AccessPattern) -> Result<Self> {
         // `CreateFileMappingW` documents:
         //
         // https://docs.microsoft.com/en-us/windows/win32/api/memoryapi/nf-memoryapi-createfilemappingw
@@ -197,6 +366,15 @@ impl MmapInner {
         self.mmap
     }
 
+    // Windows has no equivalent of madvise() for file mappings
+    pub(super) fn advise(&mut self, _pattern: AccessPattern) -> Result {
+        Ok(())
+    }
+
+    pub(super) fn will_need(&self, _len: usize) -> Result {
+        Ok(())
+    }
+
     unsafe fn map_file(file: &File, len: u64) -> Result<*mut u8> {
         let handle = file.as_raw_handle();
 
@@ -216,7 +394,7 @@ impl MmapInner {
                 ),
             };
             if mapping.inner.is_null() {
-                return Err(Error::Io(io::Error::last_os_error()));
+                return Err(Error::io(IoOperation::Resize, io::Error::last_os_error()));
             }
 
             MapViewOfFileEx(
@@ -242,7 +420,7 @@ impl MmapInner {
         #[cfg(not(fuzzing))]
         {
             if unsafe { FlushFileBuffers(self.handle) } == 0 {
-                return Err(Error::Io(io::Error::last_os_error()));
+                return Err(Error::io(IoOperation::Sync, io::Error::last_os_error()));
             }
         }
         Ok(())
@@ -256,7 +434,7 @@ impl MmapInner {
             if result != 0 {
                 Ok(())
             } else {
-                Err(Error::Io(io::Error::last_os_error()))
+                Err(Error::io(IoOperation::Sync, io::Error::last_os_error()))
             }
         }
 
@@ -1,3 +1,4 @@
+mod arena;
 mod base;
 mod bitmap;
 mod buddy_allocator;
@@ -10,8 +11,13 @@ mod utils;
 #[allow(dead_code)]
 mod xxh3;
 
+pub(crate) use arena::Arena;
 pub(crate) use base::{Page, PageNumber};
-pub(crate) use page_manager::{ChecksumType, TransactionalMemory};
+pub(crate) use mmap::{make_sparse, Storage};
+pub(crate) use page_manager::{
+    read_file_format_version, upgrade_file_format, AllocationScope, ChecksumType,
+    NestedAllocationState, TransactionalMemory,
+};
 pub use savepoint::Savepoint;
 
 pub(super) use base::{PageImpl, PageMut};
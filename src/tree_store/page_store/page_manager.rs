@@ -1,26 +1,34 @@
-use crate::db::WriteStrategy;
+use crate::db::{
+    AccessPattern, AllocationEvent, AllocationEventKind, AllocationHook, CacheStats,
+    StorageBackend, WriteStrategy,
+};
+use crate::error::IoResultExt;
 use crate::transaction_tracker::TransactionId;
 use crate::tree_store::btree_base::Checksum;
+use crate::tree_store::page_store::arena::Arena;
 use crate::tree_store::page_store::bitmap::{BtreeBitmap, BtreeBitmapMut};
-use crate::tree_store::page_store::buddy_allocator::BuddyAllocator;
+use crate::tree_store::page_store::buddy_allocator::{BuddyAllocator, BuddyAllocatorMut};
 use crate::tree_store::page_store::layout::{DatabaseLayout, RegionLayout};
-use crate::tree_store::page_store::mmap::Mmap;
+use crate::tree_store::page_store::mmap::{FileLock, Mmap, Storage};
 use crate::tree_store::page_store::region::{RegionHeaderAccessor, RegionHeaderMutator};
 use crate::tree_store::page_store::utils::{get_page_size, is_page_aligned};
 use crate::tree_store::page_store::{hash128_with_seed, PageImpl, PageMut};
 use crate::tree_store::PageNumber;
-use crate::Error;
+#[cfg(feature = "fault_injection")]
+use crate::FaultInjector;
 use crate::Result;
-use std::cmp::max;
+use crate::{Error, IoOperation};
+use std::cmp::{max, min, Ordering as CmpOrdering, Reverse};
 #[cfg(debug_assertions)]
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::convert::TryInto;
 use std::fs::File;
-#[cfg(unix)]
 use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::mem;
 use std::mem::size_of;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Mutex, MutexGuard};
 
 // Database layout:
@@ -42,7 +50,8 @@ use std::sync::{Mutex, MutexGuard};
 // 1 byte: != 0 if root page is non-null
 // 1 byte: != 0 if freed table root page is non-null
 // 1 byte: checksum type
-// 4 bytes: padding
+// 1 byte: != 0 if the transaction is prepared, and has not yet been committed
+// 3 bytes: padding
 // 8 bytes: root page
 // 16 bytes: root checksum
 // 8 bytes: freed table root page
@@ -68,7 +77,13 @@ const MIN_DESIRED_USABLE_BYTES: usize = 1024 * 1024;
 const NUM_REGIONS: u32 = 1000;
 
 // TODO: set to 1, when version 1.0 is released
-const FILE_FORMAT_VERSION: u8 = 107;
+const FILE_FORMAT_VERSION: u8 = 109;
+// The oldest file format version which can be upgraded to the current one. The versions since
+// then only added structures which older files can't contain, so upgrading a file only rewrites
+// the version of its commit slots:
+// 108: tables which store the entry count of each child in their branch pages
+// 109: system tables, which store the persistent savepoints
+const MIN_UPGRADABLE_VERSION: u8 = 107;
 
 // Inspired by PNG's magic number
 const MAGICNUMBER: [u8; 9] = [b'r', b'e', b'd', b'b', 0x1A, 0x0A, 0xA9, 0x0D, 0x0A];
@@ -90,8 +105,9 @@ const VERSION_OFFSET: usize = 0;
 const ROOT_NON_NULL_OFFSET: usize = size_of::<u8>();
 const FREED_ROOT_NON_NULL_OFFSET: usize = ROOT_NON_NULL_OFFSET + size_of::<u8>();
 const CHECKSUM_TYPE_OFFSET: usize = FREED_ROOT_NON_NULL_OFFSET + size_of::<u8>();
-const PADDING: usize = 4;
-const ROOT_PAGE_OFFSET: usize = CHECKSUM_TYPE_OFFSET + size_of::<u8>() + PADDING;
+const PREPARED_OFFSET: usize = CHECKSUM_TYPE_OFFSET + size_of::<u8>();
+const PADDING: usize = 3;
+const ROOT_PAGE_OFFSET: usize = PREPARED_OFFSET + size_of::<u8>() + PADDING;
 const ROOT_CHECKSUM_OFFSET: usize = ROOT_PAGE_OFFSET + size_of::<u64>();
 const FREED_ROOT_OFFSET: usize = ROOT_CHECKSUM_OFFSET + size_of::<u128>();
 const FREED_ROOT_CHECKSUM_OFFSET: usize = FREED_ROOT_OFFSET + size_of::<u64>();
@@ -104,6 +120,110 @@ const SLOT_CHECKSUM_OFFSET: usize =
     REGION_TRACKER_PAGE_NUMBER_OFFSET + PageNumber::serialized_size();
 const TRANSACTION_LAST_FIELD: usize = SLOT_CHECKSUM_OFFSET + size_of::<u128>();
 
+// Returns an error if a commit slot with the given file format version can't be opened
+fn check_file_format_version(version: u8) -> Result {
+    match version.cmp(&FILE_FORMAT_VERSION) {
+        CmpOrdering::Less => Err(Error::UpgradeRequired(version)),
+        CmpOrdering::Equal => Ok(()),
+        CmpOrdering::Greater => Err(Error::corrupted(format!(
+            "File format version {} is newer than version {}, which is the latest supported",
+            version, FILE_FORMAT_VERSION
+        ))),
+    }
+}
+
+// Reads the database header from the file, without mapping it. Bytes beyond the end of a shorter
+// file are zero
+fn read_header(mut file: &File) -> Result<[u8; DB_HEADER_SIZE]> {
+    let mut header = [0; DB_HEADER_SIZE];
+    file.seek(SeekFrom::Start(0)).during(IoOperation::Read)?;
+    let mut read = 0;
+    while read < header.len() {
+        match file.read(&mut header[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(Error::io(IoOperation::Read, err)),
+        }
+    }
+
+    Ok(header)
+}
+
+// Returns the offsets of the primary and secondary commit slots in the header
+fn slot_offsets(header: &[u8; DB_HEADER_SIZE]) -> Result<(usize, usize)> {
+    if header[..MAGICNUMBER.len()] != MAGICNUMBER {
+        return Err(Error::io(
+            IoOperation::Open,
+            io::Error::new(io::ErrorKind::InvalidData, "Not a redb database file"),
+        ));
+    }
+    if header[GOD_BYTE_OFFSET] & PRIMARY_BIT == 0 {
+        Ok((TRANSACTION_0_OFFSET, TRANSACTION_1_OFFSET))
+    } else {
+        Ok((TRANSACTION_1_OFFSET, TRANSACTION_0_OFFSET))
+    }
+}
+
+// Reads the file format version of the primary commit slot, without opening the database
+pub(crate) fn read_file_format_version(file: &File) -> Result<u8> {
+    let header = read_header(file)?;
+    let (primary, _) = slot_offsets(&header)?;
+
+    Ok(header[primary + VERSION_OFFSET])
+}
+
+// Upgrades the file in place to the current file format version. Returns false if it already
+// uses it.
+//
+// Every change to the file format must increment FILE_FORMAT_VERSION, and either add a step here
+// which converts files from the previous version, or raise MIN_UPGRADABLE_VERSION
+pub(crate) fn upgrade_file_format(mut file: &File) -> Result<bool> {
+    let _lock = FileLock::new(file)?;
+    let mut header = read_header(file)?;
+    let (primary, secondary) = slot_offsets(&header)?;
+    let mutex = Mutex::new(MetadataGuard);
+    let guard = mutex.lock().unwrap();
+    let mut upgraded = false;
+    // The secondary slot is upgraded first, so that if this is interrupted, the primary slot still
+    // has the old version and the upgrade is retried when the file is next opened
+    for slot_offset in [secondary, primary] {
+        let slot = &mut header[slot_offset..(slot_offset + TRANSACTION_SIZE)];
+        let version = slot[VERSION_OFFSET];
+        if version == FILE_FORMAT_VERSION {
+            continue;
+        }
+        if !TransactionAccessor::new(slot, &guard).verify_checksum() {
+            // A torn slot is never read, but the next commit is written on top of it, so it must
+            // have the new version. Its checksum must not be made valid
+            TransactionMutator::new(slot).set_version(FILE_FORMAT_VERSION);
+        } else {
+            if version < MIN_UPGRADABLE_VERSION {
+                return Err(Error::corrupted(format!(
+                    "No upgrade is available from file format version {}",
+                    version
+                )));
+            }
+            check_file_format_version(version).or_else(|err| match err {
+                Error::UpgradeRequired(_) => Ok(()),
+                err => Err(err),
+            })?;
+            let checksum_type = ChecksumType::try_from(slot[CHECKSUM_TYPE_OFFSET])?;
+            let mut mutator = TransactionMutator::new(slot);
+            mutator.set_version(FILE_FORMAT_VERSION);
+            mutator.update_checksum(checksum_type);
+        }
+        file.seek(SeekFrom::Start(slot_offset.try_into().unwrap()))
+            .during(IoOperation::Write)?;
+        file.write_all(&header[slot_offset..(slot_offset + TRANSACTION_SIZE)])
+            .during(IoOperation::Write)?;
+        file.sync_data().during(IoOperation::Sync)?;
+        upgraded = true;
+    }
+
+    Ok(upgraded)
+}
+
 fn ceil_log2(x: usize) -> usize {
     if x.is_power_of_two() {
         x.trailing_zeros() as usize
@@ -136,12 +256,14 @@ impl From<WriteStrategy> for ChecksumType {
     }
 }
 
-impl From<u8> for ChecksumType {
-    fn from(x: u8) -> Self {
+impl TryFrom<u8> for ChecksumType {
+    type Error = Error;
+
+    fn try_from(x: u8) -> Result<Self> {
         match x {
-            1 => ChecksumType::Unused,
-            2 => ChecksumType::XXH3_128,
-            _ => unimplemented!(),
+            1 => Ok(ChecksumType::Unused),
+            2 => Ok(ChecksumType::XXH3_128),
+            _ => Err(Error::corrupted(format!("Invalid checksum type {}", x))),
         }
     }
 }
@@ -169,13 +291,13 @@ struct MetadataAccessor<'a> {
 impl<'a> MetadataAccessor<'a> {
     // Safety: Caller must ensure that no other references to metadata memory exist, or are created
     // during the lifetime 'a
-    unsafe fn new(mmap: &'a Mmap, guard: MutexGuard<'a, MetadataGuard>) -> Self {
-        let header = mmap.get_memory_mut(0..DB_HEADER_SIZE);
-        Self {
+    unsafe fn new(mmap: &'a Mmap, guard: MutexGuard<'a, MetadataGuard>) -> Result<Self> {
+        let header = mmap.get_memory_mut(0..DB_HEADER_SIZE)?;
+        Ok(Self {
             header,
             mmap,
             guard,
-        }
+        })
     }
 
     fn make_region_layout(&self, num_pages: u32) -> RegionLayout {
@@ -312,7 +434,7 @@ impl<'a> MetadataAccessor<'a> {
         }
     }
 
-    fn get_region(&mut self, region: u32, layout: &DatabaseLayout) -> RegionHeaderAccessor {
+    fn get_region(&mut self, region: u32, layout: &DatabaseLayout) -> Result<RegionHeaderAccessor> {
         let base = layout.region_base_address(region);
         let len = layout.region_layout(region).data_section().start;
         let absolute = base..(base + len);
@@ -320,12 +442,99 @@ impl<'a> MetadataAccessor<'a> {
         // Safety: We own the metadata lock, so there can't be any other references
         // and this function takes &mut self, so the returned lifetime can't overlap with any other
         // calls into MetadataAccessor
-        let mem = unsafe { self.mmap.get_memory(absolute) };
+        let mem = unsafe { self.mmap.get_memory(absolute)? };
+
+        Ok(RegionHeaderAccessor::new(mem))
+    }
+
+    // Returns an error if any of the fields of the header which are written when the database is
+    // created, and never change, are invalid
+    fn validate_header(&self) -> Result {
+        let page_size = self.get_page_size();
+        if !page_size.is_power_of_two() || (page_size as usize) < DB_HEADER_SIZE {
+            return Err(Error::corrupted_at(
+                PAGE_SIZE_OFFSET,
+                format!("Invalid page size {}", page_size),
+            ));
+        }
+        let max_data_pages = self.get_region_max_data_pages();
+        if max_data_pages == 0
+            || u64::from(max_data_pages) * u64::from(page_size) > MAX_USABLE_REGION_SPACE
+        {
+            return Err(Error::corrupted_at(
+                REGION_MAX_DATA_PAGES_OFFSET,
+                format!("Invalid region size of {} pages", max_data_pages),
+            ));
+        }
+        let header_pages = self.get_region_header_pages();
+        let expected = RegionLayout::full_region_layout(max_data_pages, page_size);
+        if header_pages != expected.get_header_pages() {
+            return Err(Error::corrupted_at(
+                REGION_HEADER_PAGES_OFFSET,
+                format!("Invalid region header size of {} pages", header_pages),
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Returns true if the slot's checksum is correct, and its fields are valid for the current
+    // length of the file
+    fn slot_is_intact(&self, slot: &TransactionAccessor) -> bool {
+        slot.verify_checksum()
+            && slot
+                .validate(self, self.mmap.len().try_into().unwrap())
+                .is_ok()
+    }
+
+    // Returns true if the region tracker, and the header of each region, have the structure that
+    // the layout requires, so that they can be accessed safely. The allocation state which they
+    // store is not checked
+    fn allocator_state_is_valid(
+        &self,
+        layout: &DatabaseLayout,
+        tracker_page: PageNumber,
+    ) -> Result<bool> {
+        let page_size = self.get_page_size() as usize;
+        let region_pages_start =
+            (layout.full_region_layout().get_header_pages() as usize) * page_size;
+        let range = tracker_page.address_range(
+            page_size,
+            layout.full_region_layout().len(),
+            region_pages_start,
+            page_size,
+        );
+        let mut expected_tracker =
+            vec![0; RegionTracker::required_bytes(NUM_REGIONS, MAX_MAX_PAGE_ORDER + 1)];
+        RegionTracker::init_new(NUM_REGIONS, MAX_MAX_PAGE_ORDER + 1, &mut expected_tracker);
+        // Safety: We own the metadata lock, and this is only called while the database is opened,
+        // so there can't be any mutable references to the metadata
+        let tracker = unsafe { self.mmap.get_memory(range)? };
+        if !RegionTracker::has_structure_of(tracker, &expected_tracker) {
+            return Ok(false);
+        }
+
+        let capacity = layout.full_region_layout().num_pages() as usize;
+        let mut expected_allocator = vec![0; BuddyAllocatorMut::required_space(capacity)];
+        BuddyAllocatorMut::init_new(&mut expected_allocator, capacity, capacity);
+        for i in 0..layout.num_regions() {
+            let base = layout.region_base_address(i);
+            let len = layout.region_layout(i).data_section().start;
+            // Safety: Same as above
+            let header = unsafe { self.mmap.get_memory(base..(base + len))? };
+            if !RegionHeaderAccessor::is_valid(header, &expected_allocator) {
+                return Ok(false);
+            }
+        }
 
-        RegionHeaderAccessor::new(mem)
+        Ok(true)
     }
 
-    fn initialize_region_tracker(&mut self, layout: &DatabaseLayout, tracker_page: PageNumber) {
+    fn initialize_region_tracker(
+        &mut self,
+        layout: &DatabaseLayout,
+        tracker_page: PageNumber,
+    ) -> Result {
         let page_size = self.get_page_size() as usize;
         let region_pages_start =
             (layout.full_region_layout().get_header_pages() as usize) * page_size;
@@ -340,13 +549,19 @@ impl<'a> MetadataAccessor<'a> {
         // and this function takes &mut self, so the returned lifetime can't overlap with any other
         // calls into MetadataAccessor
         assert!(range.start >= DB_HEADER_SIZE);
-        let mem = unsafe { self.mmap.get_memory_mut(range) };
+        let mem = unsafe { self.mmap.get_memory_mut(range)? };
         RegionTracker::init_new(NUM_REGIONS, MAX_MAX_PAGE_ORDER + 1, mem);
+
+        Ok(())
     }
 
     // Note: It's very important that the lifetime of the returned allocator accessors is the same
     // as self, since self holds the metadata lock
-    fn get_region_mut(&mut self, region: u32, layout: &DatabaseLayout) -> RegionHeaderMutator {
+    fn get_region_mut(
+        &mut self,
+        region: u32,
+        layout: &DatabaseLayout,
+    ) -> Result<RegionHeaderMutator> {
         let base = layout.region_base_address(region);
         let region_header_len = &layout.region_layout(region).data_section().start;
         let absolute = base..(base + region_header_len);
@@ -355,9 +570,9 @@ impl<'a> MetadataAccessor<'a> {
         // and this function takes &mut self, so the returned lifetime can't overlap with any other
         // calls into MetadataAccessor
         assert!(absolute.start >= layout.superheader_bytes());
-        let mem = unsafe { self.mmap.get_memory_mut(absolute) };
+        let mem = unsafe { self.mmap.get_memory_mut(absolute)? };
 
-        RegionHeaderMutator::new(mem)
+        Ok(RegionHeaderMutator::new(mem))
     }
 
     // Note: It's very important that the lifetime of the returned allocator accessors is the same
@@ -386,7 +601,7 @@ impl<'a> MetadataAccessor<'a> {
         // and this function takes &mut self, so the returned lifetime can't overlap with any other
         // calls into MetadataAccessor
         assert!(range.start >= DB_HEADER_SIZE);
-        let mem = unsafe { self.mmap.get_memory_mut(range) };
+        let mem = unsafe { self.mmap.get_memory_mut(range)? };
 
         // Safety: Same as above, and RegionAccessor promises to only access regional metadata,
         // which does not overlap the above
@@ -435,6 +650,21 @@ impl<'a> RegionTracker<'a> {
         result
     }
 
+    // Returns true if data has the same header, and bitmap structure, as expected, which must have
+    // been initialized by init_new()
+    pub(crate) fn has_structure_of(data: &[u8], expected: &[u8]) -> bool {
+        if data.len() < expected.len() || data[..8] != expected[..8] {
+            return false;
+        }
+        let suballocators = u32::from_le_bytes(expected[..4].try_into().unwrap()) as usize;
+        let suballocator_len = u32::from_le_bytes(expected[4..8].try_into().unwrap()) as usize;
+        (0..suballocators).all(|order| {
+            let start = 8 + order * suballocator_len;
+            let end = start + suballocator_len;
+            BtreeBitmap::has_structure_of(&data[start..end], &expected[start..end])
+        })
+    }
+
     pub(crate) fn find_free(&self, order: usize) -> Option<u64> {
         let mem = self.get_order(order);
         let accessor = BtreeBitmap::new(mem);
@@ -493,16 +723,16 @@ struct RegionsAccessor<'a> {
 }
 
 impl<'a> RegionsAccessor<'a> {
-    fn get_region_mut(&mut self, region: u32) -> RegionHeaderMutator {
+    fn get_region_mut(&mut self, region: u32) -> Result<RegionHeaderMutator> {
         // Safety: We have exclusive access to regional metadata
         let base = self.layout.region_base_address(region);
         let region_header_len = &self.layout.region_layout(region).data_section().start;
         let absolute = base..(base + region_header_len);
 
         assert!(absolute.start >= self.layout.superheader_bytes());
-        let mem = unsafe { self.mmap.get_memory_mut(absolute) };
+        let mem = unsafe { self.mmap.get_memory_mut(absolute)? };
 
-        RegionHeaderMutator::new(mem)
+        Ok(RegionHeaderMutator::new(mem))
     }
 }
 
@@ -522,9 +752,80 @@ impl<'a> TransactionAccessor<'a> {
                 .try_into()
                 .unwrap(),
         );
-        self.get_checksum_type()
-            .checksum(&self.mem[..SLOT_CHECKSUM_OFFSET])
-            == checksum
+        if let Ok(checksum_type) = ChecksumType::try_from(self.mem[CHECKSUM_TYPE_OFFSET]) {
+            checksum_type.checksum(&self.mem[..SLOT_CHECKSUM_OFFSET]) == checksum
+        } else {
+            false
+        }
+    }
+
+    // Returns an error if any field of the slot is invalid, or refers to a page outside of a file
+    // of the given length
+    fn validate(&self, metadata: &MetadataAccessor, file_len: u64) -> Result {
+        ChecksumType::try_from(self.mem[CHECKSUM_TYPE_OFFSET])?;
+        let regions = u64::from(self.get_full_regions())
+            + u64::from(self.get_trailing_region_data_pages().is_some());
+        if regions == 0 || regions > NUM_REGIONS.into() {
+            return Err(Error::corrupted(format!(
+                "Transaction {} has {} regions",
+                self.get_last_committed_transaction_id().0,
+                regions
+            )));
+        }
+        if let Some(pages) = self.get_trailing_region_data_pages() {
+            if pages > metadata.get_region_max_data_pages() {
+                return Err(Error::corrupted(format!(
+                    "Transaction {} has a trailing region of {} pages, but regions hold at most {}",
+                    self.get_last_committed_transaction_id().0,
+                    pages,
+                    metadata.get_region_max_data_pages()
+                )));
+            }
+        }
+        // The header is validated first, so this can't overflow
+        let layout = DatabaseLayout::new(
+            self.get_full_regions(),
+            metadata.make_region_layout(metadata.get_region_max_data_pages()),
+            self.get_trailing_region_data_pages()
+                .map(|pages| metadata.make_region_layout(pages)),
+        );
+        if layout.len() > file_len {
+            return Err(Error::corrupted(format!(
+                "Transaction {} requires a file of {} bytes, but the file is {} bytes. It may have been truncated",
+                self.get_last_committed_transaction_id().0,
+                layout.len(),
+                file_len
+            )));
+        }
+
+        let tracker_page = self.get_region_tracker_page();
+        let tracker_bytes = RegionTracker::required_bytes(NUM_REGIONS, MAX_MAX_PAGE_ORDER + 1);
+        if !tracker_page.is_within(&layout)
+            || tracker_page.page_size_bytes(metadata.get_page_size() as usize) < tracker_bytes
+        {
+            return Err(Error::corrupted(format!(
+                "Transaction {} has an invalid region tracker page, {:?}",
+                self.get_last_committed_transaction_id().0,
+                tracker_page
+            )));
+        }
+        for (name, root) in [
+            ("data", self.get_root_page()),
+            ("freed", self.get_freed_root_page()),
+        ] {
+            if let Some((page, _)) = root {
+                if !page.is_within(&layout) {
+                    return Err(Error::corrupted(format!(
+                        "Transaction {} has an invalid {} root page, {:?}",
+                        self.get_last_committed_transaction_id().0,
+                        name,
+                        page
+                    )));
+                }
+            }
+        }
+
+        Ok(())
     }
 
     fn get_root_page(&self) -> Option<(PageNumber, Checksum)> {
@@ -564,8 +865,13 @@ impl<'a> TransactionAccessor<'a> {
         }
     }
 
+    // Must only be called on a slot which has been validated
     fn get_checksum_type(&self) -> ChecksumType {
-        ChecksumType::from(self.mem[CHECKSUM_TYPE_OFFSET])
+        ChecksumType::try_from(self.mem[CHECKSUM_TYPE_OFFSET]).unwrap()
+    }
+
+    fn is_prepared(&self) -> bool {
+        self.mem[PREPARED_OFFSET] != 0
     }
 
     fn get_last_committed_transaction_id(&self) -> TransactionId {
@@ -651,6 +957,14 @@ impl<'a> TransactionMutator<'a> {
         self.mem[CHECKSUM_TYPE_OFFSET] = checksum.into();
     }
 
+    fn set_prepared(&mut self, prepared: bool) {
+        self.mem[PREPARED_OFFSET] = u8::from(prepared);
+    }
+
+    fn copy_from(&mut self, slot: &[u8]) {
+        self.mem.copy_from_slice(slot);
+    }
+
     fn set_last_committed_transaction_id(&mut self, transaction_id: TransactionId) {
         self.mem[TRANSACTION_ID_OFFSET..(TRANSACTION_ID_OFFSET + size_of::<u64>())]
             .copy_from_slice(&transaction_id.0.to_le_bytes());
@@ -691,6 +1005,58 @@ enum AllocationOp {
     FreeUncommitted(PageNumber),
 }
 
+// Undoes the given allocation operations, which must be in reverse order
+fn undo_allocations(
+    region_tracker: &mut RegionTracker,
+    regions: &mut RegionsAccessor,
+    ops: impl Iterator<Item = AllocationOp>,
+) -> Result {
+    for op in ops {
+        match op {
+            AllocationOp::Allocate(page_number) => {
+                let region_index = page_number.region;
+                region_tracker.mark_free(page_number.page_order as usize, region_index as u64);
+                let mut region = regions.get_region_mut(region_index)?;
+                region.allocator_mut().free(
+                    page_number.page_index as u64,
+                    page_number.page_order as usize,
+                );
+            }
+            AllocationOp::Free(page_number) | AllocationOp::FreeUncommitted(page_number) => {
+                let region_index = page_number.region;
+                let mut region = regions.get_region_mut(region_index)?;
+                region.allocator_mut().record_alloc(
+                    page_number.page_index as u64,
+                    page_number.page_order as usize,
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Allocation state at the start of a nested transaction
+pub(crate) struct NestedAllocationState {
+    log_position: usize,
+    // Pages allocated by the enclosing transaction
+    uncommitted: HashSet<PageNumber>,
+}
+
+// Restores the table to which allocations were attributed, when dropped
+pub(crate) struct AllocationScope<'a> {
+    mem: &'a TransactionalMemory,
+    previous: Option<Option<String>>,
+}
+
+impl<'a> Drop for AllocationScope<'a> {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            *self.mem.current_table.lock().unwrap() = previous;
+        }
+    }
+}
+
 // The current layout for the active transaction.
 // May include uncommitted changes to the database layout, if it grew or shrank
 struct InProgressLayout {
@@ -702,8 +1068,12 @@ pub(crate) struct TransactionalMemory {
     // Pages allocated since the last commit
     allocated_since_commit: Mutex<HashSet<PageNumber>>,
     log_since_commit: Mutex<Vec<AllocationOp>>,
+    // Total size of the pages allocated since the last commit, which have not been freed
+    dirty_bytes: AtomicU64,
     // True if the allocator state was corrupted when the file was opened
     needs_recovery: bool,
+    // The checksum type of the primary slot, so that it can be read without accessing the header
+    checksum_type: Mutex<ChecksumType>,
     mmap: Mmap,
     // We use unsafe to access the metadata, and so guard it with this mutex
     // It would be nice if this was a RefCell<&[u8]> on the metadata. However, that would be
@@ -720,6 +1090,26 @@ pub(crate) struct TransactionalMemory {
     read_page_ref_counts: Mutex<HashMap<PageNumber, u64>>,
     // Indicates that a non-durable commit has been made, so reads should be served from the secondary meta page
     read_from_secondary: AtomicBool,
+    // Contents of the secondary slot before a transaction was prepared in it. Set while a
+    // prepared transaction is waiting to be committed or rolled back
+    prepared_secondary_slot: Mutex<Option<Vec<u8>>>,
+    // Number of times that commits have synced the file
+    sync_count: AtomicU64,
+    // Verify the checksum of each page of a btree as it is read
+    verify_checksums_on_read: bool,
+    // Values of at least this many bytes are stored in a leaf of their own, if set
+    large_value_threshold: Option<usize>,
+    // Number of sibling pages which range scans prefetch ahead of the one they are reading
+    read_ahead: usize,
+    // Free blocks of at least this many bytes are punched out of the file
+    hole_punch_threshold: Option<usize>,
+    // Pages freed by commits which have not yet been synced, whose blocks may be punched out
+    hole_candidates: Mutex<Vec<PageNumber>>,
+    // Scratch buffers reused by the write transaction
+    arena: Arena,
+    allocation_hook: Option<AllocationHook>,
+    // The table to which allocations are attributed. Only set if there is an allocation hook
+    current_table: Mutex<Option<String>>,
     page_size: usize,
     // We store these separately from the layout because they're static, and accessed on the get_page()
     // code path where there is no locking
@@ -732,11 +1122,13 @@ pub(crate) struct TransactionalMemory {
 
 impl TransactionalMemory {
     pub(crate) fn new(
-        file: File,
+        storage: Storage,
         requested_page_size: Option<usize>,
         requested_region_size: Option<usize>,
         initial_size: Option<u64>,
         write_strategy: Option<WriteStrategy>,
+        lock_file: bool,
+        storage_backend: StorageBackend,
     ) -> Result<Self> {
         #[allow(clippy::assertions_on_constants)]
         {
@@ -773,18 +1165,36 @@ impl TransactionalMemory {
             page_size.try_into().unwrap(),
         )?;
 
-        {
-            let file_len = file.metadata()?.len();
+        // Take the lock before touching the file, so that a database which is open elsewhere
+        // is never resized or initialized
+        let lock = match storage.file() {
+            Some(file) if lock_file => Some(FileLock::new(file)?),
+            _ => None,
+        };
 
-            if file_len < layout.len() {
-                file.set_len(layout.len())?;
-            }
+        // Only a file which is about to be initialized is extended. An existing database which is
+        // shorter than its layout has been truncated, and is reported as corrupted below
+        let file_len = storage.len().during(IoOperation::Open)?;
+        let mut header = [0; DB_HEADER_SIZE];
+        let header_len: usize = min(file_len, DB_HEADER_SIZE as u64).try_into().unwrap();
+        storage
+            .read_exact_at(&mut header[..header_len], 0)
+            .during(IoOperation::Read)?;
+        let initialized = header[..MAGICNUMBER.len()] == MAGICNUMBER;
+        if initialized && file_len < DB_HEADER_SIZE as u64 {
+            return Err(Error::corrupted(format!(
+                "The file is {} bytes, which is too short for the database header",
+                file_len
+            )));
+        }
+        if !initialized && file_len < layout.len() {
+            storage.set_len(layout.len()).during(IoOperation::Resize)?;
         }
 
-        let mmap = Mmap::new(file)?;
+        let mmap = Mmap::new(storage, lock, storage_backend)?;
 
         let mutex = Mutex::new(MetadataGuard {});
-        let mut metadata = unsafe { MetadataAccessor::new(&mmap, mutex.lock().unwrap()) };
+        let mut metadata = unsafe { MetadataAccessor::new(&mmap, mutex.lock().unwrap())? };
 
         if metadata.get_magic_number() != MAGICNUMBER {
             // Explicitly zero the header
@@ -797,7 +1207,7 @@ impl TransactionalMemory {
 
             // Initialize the zeroth region and allocate the region tracker
             let tracker_page = {
-                let mut region = metadata.get_region_mut(0, &layout);
+                let mut region = metadata.get_region_mut(0, &layout)?;
                 let region_layout = layout.region_layout(0);
                 region.initialize(
                     region_layout.num_pages(),
@@ -816,14 +1226,14 @@ impl TransactionalMemory {
 
             // Initialize the region tracker. Must be done after writing the page_size and other
             // immutable fields, since it relies on those fields.
-            metadata.initialize_region_tracker(&layout, tracker_page);
+            metadata.initialize_region_tracker(&layout, tracker_page)?;
             let (mut region_tracker, mut regions) =
                 metadata.allocators_mut(&layout, tracker_page)?;
 
             let num_regions = layout.num_regions();
             // Initialize all the regional allocators
             for i in 0..num_regions {
-                let mut region = regions.get_region_mut(i);
+                let mut region = regions.get_region_mut(i)?;
                 let region_layout = layout.region_layout(i);
                 // The zeroth region was already initialized above
                 if i > 0 {
@@ -855,6 +1265,8 @@ impl TransactionalMemory {
             );
             mutator.set_version(FILE_FORMAT_VERSION);
             mutator.set_region_tracker_page(tracker_page);
+            // Repair verifies the slots, if the database crashes before its first commit
+            mutator.update_checksum(checksum_type);
             drop(mutator);
             // Make the state we just wrote the primary
             metadata.swap_primary();
@@ -871,6 +1283,7 @@ impl TransactionalMemory {
             );
             mutator.set_version(FILE_FORMAT_VERSION);
             mutator.set_region_tracker_page(tracker_page);
+            mutator.update_checksum(checksum_type);
             drop(mutator);
 
             mmap.flush()?;
@@ -880,6 +1293,29 @@ impl TransactionalMemory {
             mmap.flush()?;
         }
 
+        metadata.validate_header()?;
+        if !metadata.slot_is_intact(&metadata.primary_slot()) {
+            if !metadata.slot_is_intact(&metadata.secondary_slot()) {
+                metadata
+                    .primary_slot()
+                    .validate(&metadata, mmap.len().try_into().unwrap())?;
+                return Err(Error::corrupted(
+                    "Both commit slots are corrupted. Database::repair() can't recover from this",
+                ));
+            }
+            // Recover the previous commit, as though the database had crashed
+            metadata.swap_primary();
+            metadata.set_recovery(true);
+        }
+        if !metadata.get_recovery_required() {
+            let layout = metadata.get_primary_layout();
+            let tracker_page = metadata.primary_slot().get_region_tracker_page();
+            if !metadata.allocator_state_is_valid(&layout, tracker_page)? {
+                // The allocator state is rebuilt when the database is repaired
+                metadata.set_recovery(true);
+            }
+        }
+
         if let Some(requested_strategy) = write_strategy {
             let checksum_type: ChecksumType = requested_strategy.into();
             assert_eq!(checksum_type, metadata.primary_slot().get_checksum_type());
@@ -889,19 +1325,9 @@ impl TransactionalMemory {
         if let Some(size) = requested_page_size {
             assert_eq!(page_size as usize, size);
         }
-        let version = metadata.primary_slot().get_version();
-        if version != FILE_FORMAT_VERSION {
-            return Err(Error::Corrupted(format!(
-                "Expected file format version {}, found {}",
-                FILE_FORMAT_VERSION, version
-            )));
-        }
-        let version = metadata.secondary_slot().get_version();
-        if version != FILE_FORMAT_VERSION {
-            return Err(Error::Corrupted(format!(
-                "Expected file format version {}, found {}",
-                FILE_FORMAT_VERSION, version
-            )));
+        check_file_format_version(metadata.primary_slot().get_version())?;
+        if metadata.slot_is_intact(&metadata.secondary_slot()) {
+            check_file_format_version(metadata.secondary_slot().get_version())?;
         }
         let layout = metadata.get_primary_layout();
         let tracker_page = metadata.primary_slot().get_region_tracker_page();
@@ -909,12 +1335,15 @@ impl TransactionalMemory {
         let region_header_size = layout.full_region_layout().data_section().start;
 
         let needs_recovery = metadata.get_recovery_required();
+        let checksum_type = metadata.primary_slot().get_checksum_type();
         drop(metadata);
 
         Ok(Self {
             allocated_since_commit: Mutex::new(HashSet::new()),
             log_since_commit: Mutex::new(vec![]),
+            dirty_bytes: AtomicU64::new(0),
             needs_recovery,
+            checksum_type: Mutex::new(checksum_type),
             mmap,
             metadata_guard: mutex,
             layout: Mutex::new(InProgressLayout {
@@ -926,6 +1355,16 @@ impl TransactionalMemory {
             #[cfg(debug_assertions)]
             read_page_ref_counts: Mutex::new(HashMap::new()),
             read_from_secondary: AtomicBool::new(false),
+            prepared_secondary_slot: Mutex::new(None),
+            sync_count: AtomicU64::new(0),
+            verify_checksums_on_read: false,
+            large_value_threshold: None,
+            read_ahead: 0,
+            hole_punch_threshold: None,
+            hole_candidates: Mutex::new(vec![]),
+            arena: Arena::new(),
+            allocation_hook: None,
+            current_table: Mutex::new(None),
             page_size: page_size as usize,
             region_size,
             region_header_with_padding_size: region_header_size,
@@ -935,7 +1374,7 @@ impl TransactionalMemory {
     }
 
     pub(crate) fn needs_repair(&self) -> Result<bool> {
-        Ok(self.lock_metadata().get_recovery_required())
+        Ok(self.lock_metadata()?.get_recovery_required())
     }
 
     pub(crate) fn needs_checksum_verification(&self) -> Result<bool> {
@@ -943,26 +1382,53 @@ impl TransactionalMemory {
     }
 
     pub(crate) fn checksum_type(&self) -> ChecksumType {
-        self.lock_metadata().primary_slot().get_checksum_type()
+        *self.checksum_type.lock().unwrap()
+    }
+
+    // Makes the secondary the primary, or returns an error if it's corrupted too
+    pub(crate) fn repair_primary_corrupted(&self) -> Result {
+        let metadata = self.lock_metadata()?;
+        let secondary_intact = metadata.slot_is_intact(&metadata.secondary_slot());
+        drop(metadata);
+        if !secondary_intact {
+            return Err(Error::corrupted(
+                "Both commit slots are corrupted. Database::repair() can't recover from this",
+            ));
+        }
+        self.swap_primary_slot()
     }
 
-    pub(crate) fn repair_primary_corrupted(&self) {
-        let mut metadata = self.lock_metadata();
+    fn swap_primary_slot(&self) -> Result {
+        let mut metadata = self.lock_metadata()?;
         metadata.swap_primary();
+        *self.checksum_type.lock().unwrap() = metadata.primary_slot().get_checksum_type();
         let mut layout = self.layout.lock().unwrap();
         layout.layout = metadata.get_primary_layout();
         layout.tracker_page = metadata.primary_slot().get_region_tracker_page();
+
+        Ok(())
     }
 
-    pub(crate) fn begin_repair(&self) -> Result<()> {
-        let mut metadata = self.lock_metadata();
+    // If the secondary slot holds a newer prepared transaction, it is only recovered if
+    // `commit_prepared` returns true for its id. A newer secondary is never recovered if
+    // `secondary_corrupted` is true, because its pages failed verification
+    pub(crate) fn begin_repair(
+        &self,
+        commit_prepared: impl FnOnce(TransactionId) -> bool,
+        secondary_corrupted: bool,
+    ) -> Result<()> {
+        let mut metadata = self.lock_metadata()?;
 
-        if !metadata.primary_slot().verify_checksum() {
+        if !metadata.slot_is_intact(&metadata.primary_slot()) {
+            if !metadata.slot_is_intact(&metadata.secondary_slot()) {
+                return Err(Error::corrupted(
+                    "Both commit slots are corrupted. Database::repair() can't recover from this",
+                ));
+            }
             metadata.swap_primary();
             let mut layout = self.layout.lock().unwrap();
             layout.layout = metadata.get_primary_layout();
             layout.tracker_page = metadata.primary_slot().get_region_tracker_page();
-            assert!(metadata.primary_slot().verify_checksum());
         } else {
             // If the secondary is a valid commit, verify that the primary is newer. This handles an edge case where:
             // * the primary bit is flipped to the secondary
@@ -971,24 +1437,95 @@ impl TransactionalMemory {
                 .secondary_slot()
                 .get_last_committed_transaction_id()
                 > metadata.primary_slot().get_last_committed_transaction_id();
-            if secondary_newer && metadata.secondary_slot().verify_checksum() {
+            if secondary_newer
+                && !secondary_corrupted
+                && metadata.slot_is_intact(&metadata.secondary_slot())
+                && (!metadata.secondary_slot().is_prepared()
+                    || commit_prepared(
+                        metadata
+                            .secondary_slot()
+                            .get_last_committed_transaction_id(),
+                    ))
+            {
                 metadata.swap_primary();
                 let mut layout = self.layout.lock().unwrap();
                 layout.layout = metadata.get_primary_layout();
                 layout.tracker_page = metadata.primary_slot().get_region_tracker_page();
             }
         }
+        *self.checksum_type.lock().unwrap() = metadata.primary_slot().get_checksum_type();
+
+        self.reset_allocators(&mut metadata)
+    }
+
+    // Makes the most recent commit whose commit slot is intact, and for which is_valid returns
+    // true, the primary. Returns its transaction id, or None if there is no such commit, in which
+    // case the primary is unchanged. Prepared transactions are only considered if they are
+    // already the primary
+    pub(crate) fn select_valid_commit(
+        &self,
+        is_valid: impl Fn(&Self) -> Result<bool>,
+    ) -> Result<Option<TransactionId>> {
+        let mut candidates = vec![];
+        {
+            let metadata = self.lock_metadata()?;
+            let primary = metadata.primary_slot();
+            if metadata.slot_is_intact(&primary) {
+                candidates.push((primary.get_last_committed_transaction_id(), false));
+            }
+            let secondary = metadata.secondary_slot();
+            if metadata.slot_is_intact(&secondary) && !secondary.is_prepared() {
+                candidates.push((secondary.get_last_committed_transaction_id(), true));
+            }
+        }
+        candidates.sort_by_key(|(transaction_id, _)| Reverse(*transaction_id));
+
+        for (transaction_id, swap) in candidates {
+            if swap {
+                self.swap_primary_slot()?;
+            }
+            let valid = is_valid(self);
+            if matches!(valid, Ok(true)) {
+                return Ok(Some(transaction_id));
+            }
+            if swap {
+                self.swap_primary_slot()?;
+            }
+            valid?;
+        }
+        Ok(None)
+    }
+
+    // Returns the id of the most recent commit whose commit slot is intact
+    pub(crate) fn latest_intact_commit(&self) -> Result<Option<TransactionId>> {
+        let metadata = self.lock_metadata()?;
+        Ok([metadata.primary_slot(), metadata.secondary_slot()]
+            .iter()
+            .filter(|slot| metadata.slot_is_intact(slot))
+            .map(|slot| slot.get_last_committed_transaction_id())
+            .max())
+    }
+
+    // Begins a repair of a database which was shutdown cleanly, of the commit which is currently
+    // the primary
+    pub(crate) fn begin_forced_repair(&mut self) -> Result<()> {
+        self.needs_recovery = true;
+        let mut metadata = self.lock_metadata()?;
+        self.reset_allocators(&mut metadata)
+    }
 
+    // Resets the allocator state, so that only the region tracker is allocated
+    fn reset_allocators(&self, metadata: &mut MetadataAccessor) -> Result<()> {
         let layout = self.layout.lock().unwrap();
         let region_tracker_page = layout.tracker_page;
-        metadata.initialize_region_tracker(&layout.layout, region_tracker_page);
+        metadata.initialize_region_tracker(&layout.layout, region_tracker_page)?;
         let (mut region_tracker, mut regions) =
             metadata.allocators_mut(&layout.layout, region_tracker_page)?;
 
         let num_regions = layout.layout.num_regions();
         // Initialize all the regional allocators
         for i in 0..num_regions {
-            let mut region = regions.get_region_mut(i);
+            let mut region = regions.get_region_mut(i)?;
             let region_layout = layout.layout.region_layout(i);
             region.initialize(
                 region_layout.num_pages(),
@@ -1001,7 +1538,7 @@ impl TransactionalMemory {
 
         // Mark the region tracker page as allocated
         regions
-            .get_region_mut(region_tracker_page.region)
+            .get_region_mut(region_tracker_page.region)?
             .allocator_mut()
             .record_alloc(
                 region_tracker_page.page_index.into(),
@@ -1013,15 +1550,16 @@ impl TransactionalMemory {
 
     pub(crate) fn mark_pages_allocated(
         &self,
-        allocated_pages: impl Iterator<Item = PageNumber>,
+        allocated_pages: impl Iterator<Item = Result<PageNumber>>,
     ) -> Result<()> {
-        let mut metadata = self.lock_metadata();
+        let mut metadata = self.lock_metadata()?;
         let layout = self.layout.lock().unwrap();
         let (_, mut regions) = metadata.allocators_mut(&layout.layout, layout.tracker_page)?;
 
         for page_number in allocated_pages {
+            let page_number = page_number?;
             let region_index = page_number.region;
-            let mut region = regions.get_region_mut(region_index);
+            let mut region = regions.get_region_mut(region_index)?;
             region.allocator_mut().record_alloc(
                 page_number.page_index as u64,
                 page_number.page_order as usize,
@@ -1032,7 +1570,7 @@ impl TransactionalMemory {
     }
 
     pub(crate) fn end_repair(&mut self) -> Result<()> {
-        let mut metadata = self.lock_metadata();
+        let mut metadata = self.lock_metadata()?;
         self.mmap.flush()?;
 
         metadata.set_recovery(false);
@@ -1043,16 +1581,16 @@ impl TransactionalMemory {
         result
     }
 
-    pub(crate) fn get_raw_allocator_states(&self) -> Vec<Vec<u8>> {
-        let mut metadata = self.lock_metadata();
+    pub(crate) fn get_raw_allocator_states(&self) -> Result<Vec<Vec<u8>>> {
+        let mut metadata = self.lock_metadata()?;
         let layout = self.layout.lock().unwrap();
 
         let mut regional_allocators = vec![];
         for i in 0..layout.layout.num_regions() {
-            regional_allocators.push(metadata.get_region(i, &layout.layout).allocator_raw());
+            regional_allocators.push(metadata.get_region(i, &layout.layout)?.allocator_raw());
         }
 
-        regional_allocators
+        Ok(regional_allocators)
     }
 
     // Diffs region_states, which must be the result of calling get_raw_allocator_states(), against
@@ -1060,15 +1598,15 @@ impl TransactionalMemory {
     pub(crate) fn pages_allocated_since_raw_state(
         &self,
         region_states: &[Vec<u8>],
-    ) -> Vec<PageNumber> {
+    ) -> Result<Vec<PageNumber>> {
         let mut result = vec![];
-        let mut metadata = self.lock_metadata();
+        let mut metadata = self.lock_metadata()?;
         let layout = self.layout.lock().unwrap();
 
         assert!(region_states.len() <= layout.layout.num_regions() as usize);
 
         for i in 0..layout.layout.num_regions() {
-            let region = metadata.get_region(i, &layout.layout);
+            let region = metadata.get_region(i, &layout.layout)?;
             let current_state = region.allocator();
             if let Some(old_state) = region_states.get(i as usize) {
                 let old_allocated = BuddyAllocator::new(old_state).get_order0_allocated_pages(i);
@@ -1082,10 +1620,10 @@ impl TransactionalMemory {
 
         // TODO: it would be more efficient if we merged all the adjacent order0 pages together
 
-        result
+        Ok(result)
     }
 
-    fn lock_metadata(&self) -> MetadataAccessor {
+    fn lock_metadata(&self) -> Result<MetadataAccessor> {
         // Safety: Access to metadata is only allowed by the owner of the metadata_guard lock
         unsafe { MetadataAccessor::new(&self.mmap, self.metadata_guard.lock().unwrap()) }
     }
@@ -1108,8 +1646,8 @@ impl TransactionalMemory {
         debug_assert!(self.open_dirty_pages.lock().unwrap().is_empty());
         assert!(!self.needs_recovery);
 
-        let mut metadata = self.lock_metadata();
-        let original_checksum_type = metadata.primary_slot().get_checksum_type();
+        let mut metadata = self.lock_metadata()?;
+        let original_checksum_type = self.checksum_type();
         let checksum_type = new_checksum_type.unwrap_or(original_checksum_type);
         let mut layout = self.layout.lock().unwrap();
 
@@ -1118,6 +1656,7 @@ impl TransactionalMemory {
 
         let mut secondary = metadata.secondary_slot_mut();
         secondary.set_checksum_type(checksum_type);
+        secondary.set_prepared(false);
         secondary.set_last_committed_transaction_id(transaction_id);
         secondary.set_root_page(data_root);
         secondary.set_freed_root(freed_root);
@@ -1132,18 +1671,28 @@ impl TransactionalMemory {
 
         // Use 2-phase commit, if checksums are disabled
         if matches!(checksum_type, ChecksumType::Unused) {
-            if eventual {
-                self.mmap.eventual_flush()?;
-            } else {
-                self.mmap.flush()?;
-            }
+            self.sync(eventual)?;
         }
 
         metadata.swap_primary();
-        if eventual {
-            self.mmap.eventual_flush()?;
-        } else {
-            self.mmap.flush()?;
+        if let Err(err) = self.sync(eventual) {
+            // Leave the previous commit as the primary, so that the transaction can be rolled back
+            // and retried, if the flush failed because the disk is full
+            metadata.swap_primary();
+            return Err(err);
+        }
+        *self.checksum_type.lock().unwrap() = checksum_type;
+        if self.hole_punch_threshold.is_some() {
+            let mut candidates = self.hole_candidates.lock().unwrap();
+            for op in self.log_since_commit.lock().unwrap().iter() {
+                if let AllocationOp::Free(page) = op {
+                    candidates.push(*page);
+                }
+            }
+            // Until the commit is on disk, a crash could recover a snapshot which references them
+            if !eventual {
+                self.punch_holes(&mut metadata, &layout.layout, candidates.drain(..))?;
+            }
         }
         drop(metadata);
 
@@ -1158,30 +1707,34 @@ impl TransactionalMemory {
 
         self.log_since_commit.lock().unwrap().clear();
         self.allocated_since_commit.lock().unwrap().clear();
+        self.dirty_bytes.store(0, Ordering::Release);
+        self.arena.clear();
         self.read_from_secondary.store(false, Ordering::Release);
 
         Ok(())
     }
 
-    // Make changes visible, without a durability guarantee
-    pub(crate) fn non_durable_commit(
+    // Durably write all outstanding changes to the secondary slot, marked as prepared, without
+    // making them visible. Must be followed by commit_prepared() or rollback_prepared()
+    pub(crate) fn prepare(
         &self,
         data_root: Option<(PageNumber, Checksum)>,
         freed_root: Option<(PageNumber, Checksum)>,
         transaction_id: TransactionId,
     ) -> Result {
-        // All mutable pages must be dropped, this ensures that when a transaction completes
-        // no more writes can happen to the pages it allocated. Thus it is safe to make them visible
-        // to future read transactions
         #[cfg(debug_assertions)]
         debug_assert!(self.open_dirty_pages.lock().unwrap().is_empty());
         assert!(!self.needs_recovery);
 
-        let mut metadata = self.lock_metadata();
+        let mut metadata = self.lock_metadata()?;
         let checksum_type = metadata.primary_slot().get_checksum_type();
         let layout = self.layout.lock().unwrap();
+        *self.prepared_secondary_slot.lock().unwrap() =
+            Some(metadata.secondary_slot().mem.to_vec());
+
         let mut secondary = metadata.secondary_slot_mut();
         secondary.set_checksum_type(checksum_type);
+        secondary.set_prepared(true);
         secondary.set_last_committed_transaction_id(transaction_id);
         secondary.set_root_page(data_root);
         secondary.set_freed_root(freed_root);
@@ -1195,98 +1748,303 @@ impl TransactionalMemory {
         secondary.set_region_tracker_page(layout.tracker_page);
         secondary.update_checksum(checksum_type);
 
+        // A prepared transaction can only be recovered after a crash by repairing the database
+        if !metadata.get_recovery_required() {
+            metadata.set_recovery(true);
+        }
+        self.sync(false)
+    }
+
+    // Make the prepared transaction visible as the primary
+    pub(crate) fn commit_prepared(&self) -> Result {
+        let mut metadata = self.lock_metadata()?;
+        assert!(self.prepared_secondary_slot.lock().unwrap().is_some());
+        metadata.swap_primary();
+        if let Err(err) = self.sync(false) {
+            // Leave the transaction prepared, so that it can still be rolled back
+            metadata.swap_primary();
+            return Err(err);
+        }
+        drop(metadata);
+
+        *self.prepared_secondary_slot.lock().unwrap() = None;
         self.log_since_commit.lock().unwrap().clear();
         self.allocated_since_commit.lock().unwrap().clear();
-        self.read_from_secondary.store(true, Ordering::Release);
+        self.dirty_bytes.store(0, Ordering::Release);
+        self.arena.clear();
+        self.read_from_secondary.store(false, Ordering::Release);
 
         Ok(())
     }
 
-    pub(crate) fn rollback_uncommitted_writes(&self) -> Result {
-        #[cfg(debug_assertions)]
-        debug_assert!(self.open_dirty_pages.lock().unwrap().is_empty());
-        let mut metadata = self.lock_metadata();
-        // The layout to restore
-        let (restore, restore_tracker_page) = if self.read_from_secondary.load(Ordering::Acquire) {
-            (
-                metadata.get_secondary_layout(),
-                metadata.secondary_slot().get_region_tracker_page(),
-            )
-        } else {
-            (
-                metadata.get_primary_layout(),
-                metadata.primary_slot().get_region_tracker_page(),
-            )
-        };
+    // Restore the secondary slot overwritten by prepare(), if there is a prepared transaction.
+    // The caller must still rollback the uncommitted writes
+    pub(crate) fn rollback_prepared(&self) -> Result {
+        let mut metadata = self.lock_metadata()?;
+        if let Some(slot) = self.prepared_secondary_slot.lock().unwrap().take() {
+            metadata.secondary_slot_mut().copy_from(&slot);
+            self.sync(false)?;
+        }
+        Ok(())
+    }
 
-        let mut layout = self.layout.lock().unwrap();
-        let (mut region_tracker, mut regions) =
-            metadata.allocators_mut(&layout.layout, layout.tracker_page)?;
-        for op in self.log_since_commit.lock().unwrap().drain(..).rev() {
-            match op {
-                AllocationOp::Allocate(page_number) => {
-                    let region_index = page_number.region;
-                    region_tracker.mark_free(page_number.page_order as usize, region_index as u64);
-                    let mut region = regions.get_region_mut(region_index);
-                    region.allocator_mut().free(
-                        page_number.page_index as u64,
-                        page_number.page_order as usize,
-                    );
-                }
-                AllocationOp::Free(page_number) | AllocationOp::FreeUncommitted(page_number) => {
-                    let region_index = page_number.region;
-                    let mut region = regions.get_region_mut(region_index);
-                    region.allocator_mut().record_alloc(
-                        page_number.page_index as u64,
-                        page_number.page_order as usize,
-                    );
-                }
+    // Calls `f` with the slot of the latest visible commit
+    fn with_visible_slot<T>(&self, f: impl FnOnce(&TransactionAccessor) -> T) -> Result<T> {
+        let metadata = self.lock_metadata()?;
+        if self.read_from_secondary.load(Ordering::Acquire) {
+            // A prepared transaction has overwritten the secondary slot, so read the saved copy
+            if let Some(ref slot) = *self.prepared_secondary_slot.lock().unwrap() {
+                Ok(f(&TransactionAccessor::new(slot, &metadata.guard)))
+            } else {
+                Ok(f(&metadata.secondary_slot()))
             }
+        } else {
+            Ok(f(&metadata.primary_slot()))
         }
-        self.allocated_since_commit.lock().unwrap().clear();
+    }
 
-        // Shrinking only happens during commit
-        assert!(restore.len() <= layout.layout.len());
-        // Reset the layout, in case it changed during the writes
-        if restore.len() < layout.layout.len() {
-            // Restore the size of the last region's allocator
-            let last_region_index = restore.num_regions() - 1;
-            let last_region = restore.region_layout(last_region_index);
-            let mut region = regions.get_region_mut(last_region_index);
-            region
-                .allocator_mut()
-                .resize(last_region.num_pages() as usize);
+    fn sync(&self, eventual: bool) -> Result {
+        self.sync_count.fetch_add(1, Ordering::AcqRel);
+        if eventual {
+            self.mmap.eventual_flush()
+        } else {
+            self.mmap.flush()
+        }
+    }
 
-            *layout = InProgressLayout {
-                layout: restore,
-                tracker_page: restore_tracker_page,
-            };
-            // Safety: we've rollbacked the transaction, so any data in that was written into
-            // space that was grown during this transaction no longer exists
-            unsafe {
-                self.mmap.resize(layout.layout.len().try_into().unwrap())?;
+    pub(crate) fn get_sync_count(&self) -> u64 {
+        self.sync_count.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn get_file_len(&self) -> u64 {
+        self.mmap.len().try_into().unwrap()
+    }
+
+    // Returns the total size of the pages allocated since the last commit, which have not been
+    // freed
+    pub(crate) fn get_dirty_bytes(&self) -> u64 {
+        self.dirty_bytes.load(Ordering::Acquire)
+    }
+
+    fn page_bytes(&self, page: PageNumber) -> u64 {
+        page.page_size_bytes(self.page_size).try_into().unwrap()
+    }
+
+    // Returns the number of pages allocated and the number of pages freed since the last commit,
+    // and the total size of the pages allocated since the last commit which are still in use
+    pub(crate) fn uncommitted_allocation_stats(&self) -> (u64, u64, u64) {
+        let mut allocated = 0;
+        let mut freed = 0;
+        for op in self.log_since_commit.lock().unwrap().iter() {
+            match op {
+                AllocationOp::Allocate(_) => allocated += 1,
+                AllocationOp::Free(_) | AllocationOp::FreeUncommitted(_) => freed += 1,
             }
         }
+        let bytes = self
+            .allocated_since_commit
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|page| u64::try_from(page.page_size_bytes(self.page_size)).unwrap())
+            .sum();
 
-        Ok(())
+        (allocated, freed, bytes)
     }
 
-    pub(crate) fn get_page(&self, page_number: PageNumber) -> PageImpl {
-        // We must not retrieve an immutable reference to a page which already has a mutable ref to it
-        #[cfg(debug_assertions)]
-        {
-            debug_assert!(
-                !self.open_dirty_pages.lock().unwrap().contains(&page_number),
-                "{:?}",
-                page_number
-            );
-            *(self
-                .read_page_ref_counts
+    // Make changes visible, without a durability guarantee
+    pub(crate) fn non_durable_commit(
+        &self,
+        data_root: Option<(PageNumber, Checksum)>,
+        freed_root: Option<(PageNumber, Checksum)>,
+        transaction_id: TransactionId,
+    ) -> Result {
+        // All mutable pages must be dropped, this ensures that when a transaction completes
+        // no more writes can happen to the pages it allocated. Thus it is safe to make them visible
+        // to future read transactions
+        #[cfg(debug_assertions)]
+        debug_assert!(self.open_dirty_pages.lock().unwrap().is_empty());
+        assert!(!self.needs_recovery);
+
+        let mut metadata = self.lock_metadata()?;
+        let checksum_type = metadata.primary_slot().get_checksum_type();
+        let layout = self.layout.lock().unwrap();
+        let mut secondary = metadata.secondary_slot_mut();
+        secondary.set_checksum_type(checksum_type);
+        secondary.set_prepared(false);
+        secondary.set_last_committed_transaction_id(transaction_id);
+        secondary.set_root_page(data_root);
+        secondary.set_freed_root(freed_root);
+        secondary.set_data_section_layout(
+            layout.layout.num_full_regions(),
+            layout
+                .layout
+                .trailing_region_layout()
+                .map(|x| x.num_pages()),
+        );
+        secondary.set_region_tracker_page(layout.tracker_page);
+        secondary.update_checksum(checksum_type);
+
+        self.log_since_commit.lock().unwrap().clear();
+        self.allocated_since_commit.lock().unwrap().clear();
+        self.dirty_bytes.store(0, Ordering::Release);
+        self.arena.clear();
+        self.read_from_secondary.store(true, Ordering::Release);
+
+        Ok(())
+    }
+
+    // Begins a nested transaction. Pages allocated before this point become immutable, so that
+    // the nested transaction copies them on write, and its writes can be rolled back
+    pub(crate) fn begin_nested(&self) -> NestedAllocationState {
+        NestedAllocationState {
+            log_position: self.log_since_commit.lock().unwrap().len(),
+            uncommitted: mem::take(&mut *self.allocated_since_commit.lock().unwrap()),
+        }
+    }
+
+    // Commits a nested transaction. The pages allocated before it became mutable again
+    pub(crate) fn commit_nested(&self, state: NestedAllocationState) {
+        self.allocated_since_commit
+            .lock()
+            .unwrap()
+            .extend(state.uncommitted);
+    }
+
+    // Makes the pages allocated since the last commit immutable, so that they are copied on write
+    // rather than modified in place, and are not freed until the transaction commits
+    pub(crate) fn freeze_uncommitted(&self) {
+        self.allocated_since_commit.lock().unwrap().clear();
+    }
+
+    // Rolls back the allocations of a nested transaction, and makes the pages allocated before it
+    // mutable again
+    pub(crate) fn rollback_nested(&self, state: NestedAllocationState) -> Result {
+        #[cfg(debug_assertions)]
+        debug_assert!(self.open_dirty_pages.lock().unwrap().is_empty());
+        let mut metadata = self.lock_metadata()?;
+        let layout = self.layout.lock().unwrap();
+        let (mut region_tracker, mut regions) =
+            metadata.allocators_mut(&layout.layout, layout.tracker_page)?;
+        let undone: Vec<AllocationOp> = self
+            .log_since_commit
+            .lock()
+            .unwrap()
+            .drain(state.log_position..)
+            .collect();
+        for op in undone.iter() {
+            match op {
+                AllocationOp::Allocate(page) => {
+                    self.dirty_bytes
+                        .fetch_sub(self.page_bytes(*page), Ordering::AcqRel);
+                }
+                AllocationOp::FreeUncommitted(page) => {
+                    self.dirty_bytes
+                        .fetch_add(self.page_bytes(*page), Ordering::AcqRel);
+                }
+                AllocationOp::Free(_) => {}
+            }
+        }
+        undo_allocations(
+            &mut region_tracker,
+            &mut regions,
+            undone.into_iter().rev().inspect(|op| self.report_undo(op)),
+        )?;
+        *self.allocated_since_commit.lock().unwrap() = state.uncommitted;
+
+        Ok(())
+    }
+
+    pub(crate) fn rollback_uncommitted_writes(&self) -> Result {
+        #[cfg(debug_assertions)]
+        debug_assert!(self.open_dirty_pages.lock().unwrap().is_empty());
+        let mut metadata = self.lock_metadata()?;
+        // The layout to restore
+        let (restore, restore_tracker_page) = if self.read_from_secondary.load(Ordering::Acquire) {
+            (
+                metadata.get_secondary_layout(),
+                metadata.secondary_slot().get_region_tracker_page(),
+            )
+        } else {
+            (
+                metadata.get_primary_layout(),
+                metadata.primary_slot().get_region_tracker_page(),
+            )
+        };
+
+        let mut layout = self.layout.lock().unwrap();
+        let (mut region_tracker, mut regions) =
+            metadata.allocators_mut(&layout.layout, layout.tracker_page)?;
+        undo_allocations(
+            &mut region_tracker,
+            &mut regions,
+            self.log_since_commit
                 .lock()
                 .unwrap()
-                .entry(page_number)
-                .or_default()) += 1;
+                .drain(..)
+                .rev()
+                .inspect(|op| self.report_undo(op)),
+        )?;
+        self.allocated_since_commit.lock().unwrap().clear();
+        self.dirty_bytes.store(0, Ordering::Release);
+        self.arena.clear();
+
+        // Shrinking only happens during commit
+        assert!(restore.len() <= layout.layout.len());
+        // Reset the layout, in case it changed during the writes
+        if restore.len() < layout.layout.len() {
+            // Restore the size of the last region's allocator
+            let last_region_index = restore.num_regions() - 1;
+            let last_region = restore.region_layout(last_region_index);
+            let mut region = regions.get_region_mut(last_region_index)?;
+            region
+                .allocator_mut()
+                .resize(last_region.num_pages() as usize);
+
+            *layout = InProgressLayout {
+                layout: restore,
+                tracker_page: restore_tracker_page,
+            };
+            // Safety: we've rollbacked the transaction, so any data in that was written into
+            // space that was grown during this transaction no longer exists
+            unsafe {
+                self.mmap.resize(layout.layout.len().try_into().unwrap())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Returns an error if the page does not lie within a region of the file, as it only could if
+    // the page number was read from a corrupted page
+    pub(crate) fn check_page_number(&self, page_number: PageNumber) -> Result {
+        let valid = page_number.page_order as usize <= MAX_MAX_PAGE_ORDER && {
+            let page_bytes = (self.page_size as u64) << page_number.page_order;
+            let regional_end = self.region_header_with_padding_size as u64
+                + (u64::from(page_number.page_index) + 1) * page_bytes;
+            let end = self.db_header_size as u64
+                + u64::from(page_number.region) * self.region_size
+                + regional_end;
+            regional_end <= self.region_size && end <= self.mmap.len() as u64
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(Error::corrupted(format!(
+                "Page {:?} is outside of the database file",
+                page_number
+            )))
         }
+    }
+
+    pub(crate) fn get_page(&self, page_number: PageNumber) -> Result<PageImpl> {
+        // We must not retrieve an immutable reference to a page which already has a mutable ref to it
+        #[cfg(debug_assertions)]
+        debug_assert!(
+            !self.open_dirty_pages.lock().unwrap().contains(&page_number),
+            "{:?}",
+            page_number
+        );
 
         // Safety: we asserted that no mutable references are open
         let mem = unsafe {
@@ -1295,19 +2053,49 @@ impl TransactionalMemory {
                 self.region_size,
                 self.region_header_with_padding_size,
                 self.page_size,
-            ))
+            ))?
         };
+        #[cfg(debug_assertions)]
+        {
+            *(self
+                .read_page_ref_counts
+                .lock()
+                .unwrap()
+                .entry(page_number)
+                .or_default()) += 1;
+        }
 
-        PageImpl {
+        Ok(PageImpl {
             mem,
             page_number,
             #[cfg(debug_assertions)]
             open_pages: &self.read_page_ref_counts,
+        })
+    }
+
+    // Returns a copy of the page, or None if it's outside the database file
+    //
+    // Safety: the caller must ensure that the page is not written to concurrently
+    #[cfg(feature = "raw_page_access")]
+    pub(crate) unsafe fn read_raw_page(&self, page_number: PageNumber) -> Result<Option<Vec<u8>>> {
+        if !page_number.is_within(&self.layout.lock().unwrap().layout) {
+            return Ok(None);
         }
+        let range = page_number.address_range(
+            self.db_header_size,
+            self.region_size,
+            self.region_header_with_padding_size,
+            self.page_size,
+        );
+        if range.end > self.mmap.len() {
+            return Ok(None);
+        }
+
+        Ok(Some(self.mmap.get_memory(range)?.to_vec()))
     }
 
     // Safety: the caller must ensure that no references to the memory in `page` exist
-    pub(crate) unsafe fn get_page_mut(&self, page_number: PageNumber) -> PageMut {
+    pub(crate) unsafe fn get_page_mut(&self, page_number: PageNumber) -> Result<PageMut> {
         #[cfg(debug_assertions)]
         {
             assert!(!self
@@ -1315,7 +2103,7 @@ impl TransactionalMemory {
                 .lock()
                 .unwrap()
                 .contains_key(&page_number));
-            assert!(self.open_dirty_pages.lock().unwrap().insert(page_number));
+            assert!(!self.open_dirty_pages.lock().unwrap().contains(&page_number));
         }
 
         let address_range = page_number.address_range(
@@ -1324,63 +2112,49 @@ impl TransactionalMemory {
             self.region_header_with_padding_size,
             self.page_size,
         );
-        let mem = self.mmap.get_memory_mut(address_range);
+        let mem = self.mmap.get_memory_mut(address_range)?;
+        #[cfg(debug_assertions)]
+        assert!(self.open_dirty_pages.lock().unwrap().insert(page_number));
 
-        PageMut {
+        Ok(PageMut {
             mem,
             page_number,
             #[cfg(debug_assertions)]
             open_pages: &self.open_dirty_pages,
-        }
+        })
     }
 
-    pub(crate) fn get_version(&self) -> u8 {
-        let metadata = self.lock_metadata();
-        if self.read_from_secondary.load(Ordering::Acquire) {
-            metadata.secondary_slot().get_version()
-        } else {
-            metadata.primary_slot().get_version()
-        }
+    pub(crate) fn get_version(&self) -> Result<u8> {
+        self.with_visible_slot(|slot| slot.get_version())
     }
 
-    pub(crate) fn get_data_root(&self) -> Option<(PageNumber, Checksum)> {
-        let metadata = self.lock_metadata();
-        if self.read_from_secondary.load(Ordering::Acquire) {
-            metadata.secondary_slot().get_root_page()
-        } else {
-            metadata.primary_slot().get_root_page()
-        }
+    pub(crate) fn get_data_root(&self) -> Result<Option<(PageNumber, Checksum)>> {
+        self.with_visible_slot(|slot| slot.get_root_page())
     }
 
-    pub(crate) fn get_freed_root(&self) -> Option<(PageNumber, Checksum)> {
-        let metadata = self.lock_metadata();
-        if self.read_from_secondary.load(Ordering::Acquire) {
-            metadata.secondary_slot().get_freed_root_page()
-        } else {
-            metadata.primary_slot().get_freed_root_page()
-        }
+    pub(crate) fn get_freed_root(&self) -> Result<Option<(PageNumber, Checksum)>> {
+        self.with_visible_slot(|slot| slot.get_freed_root_page())
     }
 
     pub(crate) fn get_last_committed_transaction_id(&self) -> Result<TransactionId> {
-        let metadata = self.lock_metadata();
-        if self.read_from_secondary.load(Ordering::Acquire) {
-            Ok(metadata
-                .secondary_slot()
-                .get_last_committed_transaction_id())
-        } else {
-            Ok(metadata.primary_slot().get_last_committed_transaction_id())
-        }
+        self.with_visible_slot(|slot| slot.get_last_committed_transaction_id())
+    }
+
+    // The id of the most recent transaction which was committed durably
+    pub(crate) fn get_last_durable_transaction_id(&self) -> Result<TransactionId> {
+        let metadata = self.lock_metadata()?;
+        Ok(metadata.primary_slot().get_last_committed_transaction_id())
     }
 
     // Safety: the caller must ensure that no references to the memory in `page` exist
     pub(crate) unsafe fn free(&self, page: PageNumber) -> Result {
-        let mut metadata = self.lock_metadata();
+        let mut metadata = self.lock_metadata()?;
         let layout = self.layout.lock().unwrap();
         let (mut region_tracker, mut regions) =
             metadata.allocators_mut(&layout.layout, layout.tracker_page)?;
         let region_index = page.region;
         // Free in the regional allocator
-        let mut region = regions.get_region_mut(region_index);
+        let mut region = regions.get_region_mut(region_index)?;
         region
             .allocator_mut()
             .free(page.page_index as u64, page.page_order as usize);
@@ -1390,6 +2164,7 @@ impl TransactionalMemory {
             .lock()
             .unwrap()
             .push(AllocationOp::Free(page));
+        self.report(AllocationEventKind::Free, page, true);
 
         Ok(())
     }
@@ -1398,12 +2173,12 @@ impl TransactionalMemory {
     // Safety: the caller must ensure that no references to the memory in `page` exist
     pub(crate) unsafe fn free_if_uncommitted(&self, page: PageNumber) -> Result<bool> {
         if self.allocated_since_commit.lock().unwrap().remove(&page) {
-            let mut metadata = self.lock_metadata();
+            let mut metadata = self.lock_metadata()?;
             let layout = self.layout.lock().unwrap();
             let (mut region_tracker, mut regions) =
                 metadata.allocators_mut(&layout.layout, layout.tracker_page)?;
             // Free in the regional allocator
-            let mut region = regions.get_region_mut(page.region);
+            let mut region = regions.get_region_mut(page.region)?;
             region
                 .allocator_mut()
                 .free(page.page_index as u64, page.page_order as usize);
@@ -1414,6 +2189,9 @@ impl TransactionalMemory {
                 .lock()
                 .unwrap()
                 .push(AllocationOp::FreeUncommitted(page));
+            self.dirty_bytes
+                .fetch_sub(self.page_bytes(page), Ordering::AcqRel);
+            self.report(AllocationEventKind::Free, page, true);
 
             Ok(true)
         } else {
@@ -1426,6 +2204,192 @@ impl TransactionalMemory {
         self.allocated_since_commit.lock().unwrap().contains(&page)
     }
 
+    pub(crate) fn set_cache_size(&mut self, bytes: usize) {
+        self.mmap.set_cache_size(bytes);
+    }
+
+    pub(crate) fn set_allocation_hook(&mut self, hook: AllocationHook) {
+        self.allocation_hook = Some(hook);
+    }
+
+    // Attributes the pages allocated and freed until the returned guard is dropped to `table`
+    pub(crate) fn attribute_allocations(&self, table: &str) -> AllocationScope<'_> {
+        let previous = if self.allocation_hook.is_some() {
+            let mut current = self.current_table.lock().unwrap();
+            Some(current.replace(table.to_string()))
+        } else {
+            None
+        };
+        AllocationScope {
+            mem: self,
+            previous,
+        }
+    }
+
+    // Reports an allocation or free to the allocation hook, if there is one
+    fn report(&self, kind: AllocationEventKind, page: PageNumber, attributed: bool) {
+        if let Some(ref hook) = self.allocation_hook {
+            let current_table = self.current_table.lock().unwrap();
+            hook(&AllocationEvent {
+                kind,
+                page_number: u64::from_le_bytes(page.to_le_bytes()),
+                size: self.page_bytes(page),
+                table: if attributed {
+                    current_table.as_deref()
+                } else {
+                    None
+                },
+            });
+        }
+    }
+
+    // Reports the inverse of an operation which is being undone
+    fn report_undo(&self, op: &AllocationOp) {
+        match op {
+            AllocationOp::Allocate(page) => self.report(AllocationEventKind::Free, *page, false),
+            AllocationOp::Free(page) | AllocationOp::FreeUncommitted(page) => {
+                self.report(AllocationEventKind::Allocate, *page, false);
+            }
+        }
+    }
+
+    #[cfg(feature = "fault_injection")]
+    pub(crate) fn set_fault_injector(&mut self, injector: FaultInjector) {
+        self.mmap.set_fault_injector(injector);
+    }
+
+    pub(crate) fn cache_stats(&self) -> CacheStats {
+        self.mmap.cache_stats()
+    }
+
+    pub(crate) fn set_access_pattern(&mut self, pattern: AccessPattern) -> Result {
+        self.mmap.set_access_pattern(pattern)
+    }
+
+    pub(crate) fn enable_background_flush(&mut self, bytes: usize) -> Result {
+        self.mmap.enable_background_flush(bytes)
+    }
+
+    pub(crate) fn set_read_ahead(&mut self, pages: usize) {
+        self.read_ahead = pages;
+    }
+
+    pub(crate) fn read_ahead(&self) -> usize {
+        self.read_ahead
+    }
+
+    pub(crate) fn arena(&self) -> &Arena {
+        &self.arena
+    }
+
+    // Hints that the page will be read soon
+    pub(crate) fn prefetch_page(&self, page_number: PageNumber) {
+        self.mmap.prefetch(page_number.address_range(
+            self.db_header_size,
+            self.region_size,
+            self.region_header_with_padding_size,
+            self.page_size,
+        ));
+    }
+
+    pub(crate) fn populate(&self) -> Result {
+        self.mmap.populate()
+    }
+
+    pub(crate) fn enable_preallocation(&mut self) -> Result {
+        self.mmap.enable_preallocation()
+    }
+
+    pub(crate) fn enable_read_verification(&mut self) {
+        self.verify_checksums_on_read = true;
+    }
+
+    pub(crate) fn set_hole_punch_threshold(&mut self, bytes: usize) {
+        self.hole_punch_threshold = Some(bytes);
+    }
+
+    // Punches out the free blocks containing the given pages, if they are large enough
+    fn punch_holes(
+        &self,
+        metadata: &mut MetadataAccessor,
+        layout: &DatabaseLayout,
+        pages: impl Iterator<Item = PageNumber>,
+    ) -> Result {
+        let threshold = self.hole_punch_threshold.unwrap();
+        let mut punched = HashSet::new();
+        for page in pages {
+            if page.region >= layout.num_regions() {
+                continue;
+            }
+            let region = metadata.get_region(page.region, layout)?;
+            let allocator = region.allocator();
+            let index = u64::from(page.page_index) << page.page_order;
+            if index >= allocator.len() as u64 {
+                continue;
+            }
+            if let Some((start, order)) = allocator.free_block(index) {
+                let block = PageNumber::new(
+                    page.region,
+                    (start >> order).try_into().unwrap(),
+                    order.try_into().unwrap(),
+                );
+                if block.page_size_bytes(self.page_size) < threshold || !punched.insert(block) {
+                    continue;
+                }
+                let range = block.address_range(
+                    self.db_header_size,
+                    self.region_size,
+                    self.region_header_with_padding_size,
+                    self.page_size,
+                );
+                // The last block of the file may extend beyond its end
+                let end = min(range.end, self.mmap.len());
+                if range.start < end {
+                    self.mmap.punch_hole(range.start..end)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn set_large_value_threshold(&mut self, bytes: usize) {
+        self.large_value_threshold = Some(bytes);
+    }
+
+    pub(crate) fn is_large_value(&self, len: usize) -> bool {
+        matches!(self.large_value_threshold, Some(threshold) if len >= threshold)
+    }
+
+    // Returns the type of checksum against which every page read should be verified, or None if
+    // reads are not verified
+    pub(crate) fn read_verification(&self) -> Option<ChecksumType> {
+        if !self.verify_checksums_on_read {
+            return None;
+        }
+        match self.checksum_type() {
+            ChecksumType::Unused => None,
+            checksum_type => Some(checksum_type),
+        }
+    }
+
+    // Must be called when a transaction begins. Cached pages are only evicted when no transactions
+    // are open
+    pub(crate) fn pin(&self) {
+        self.mmap.pin();
+    }
+
+    // Must be called when a transaction ends, once all references to its pages have been dropped
+    pub(crate) fn unpin(&self) -> Result {
+        if self.mmap.is_relocatable() {
+            // The header moves along with the rest of the buffer
+            let _guard = self.metadata_guard.lock().unwrap();
+            self.mmap.unpin()
+        } else {
+            self.mmap.unpin()
+        }
+    }
+
     pub(crate) unsafe fn mark_transaction(&self, id: TransactionId) {
         self.mmap.mark_transaction(id)
     }
@@ -1439,6 +2403,7 @@ impl TransactionalMemory {
         metadata: &mut MetadataAccessor,
         layout: &InProgressLayout,
         required_order: usize,
+        lowest: bool,
     ) -> Result<Option<PageNumber>> {
         let (mut region_tracker, mut regions) =
             metadata.allocators_mut(&layout.layout, layout.tracker_page)?;
@@ -1449,8 +2414,13 @@ impl TransactionalMemory {
             } else {
                 return Ok(None);
             };
-            let mut region = regions.get_region_mut(candidate_region);
-            if let Some(page) = region.allocator_mut().alloc(required_order) {
+            let mut region = regions.get_region_mut(candidate_region)?;
+            let page = if lowest {
+                region.allocator_mut().alloc_lowest(required_order)
+            } else {
+                region.allocator_mut().alloc(required_order)
+            };
+            if let Some(page) = page {
                 return Ok(Some(PageNumber::new(
                     candidate_region,
                     page.try_into().unwrap(),
@@ -1483,7 +2453,7 @@ impl TransactionalMemory {
         );
         let last_region_index = layout.num_regions() - 1;
         let last_region = layout.region_layout(last_region_index);
-        let region = metadata.get_region(last_region_index, layout);
+        let region = metadata.get_region(last_region_index, layout)?;
         let last_allocator = region.allocator();
         let trailing_free = last_allocator.trailing_free_pages();
         let last_allocator_len = last_allocator.len();
@@ -1503,7 +2473,7 @@ impl TransactionalMemory {
             // drop the whole region
             layout.usable_bytes() - last_region.usable_bytes()
         } else {
-            let mut region = regions.get_region_mut(last_region_index);
+            let mut region = regions.get_region_mut(last_region_index)?;
             region.allocator_mut().resize(reduce_to_pages);
             layout.usable_bytes()
                 - ((last_allocator_len - reduce_to_pages) as u64)
@@ -1565,6 +2535,15 @@ impl TransactionalMemory {
             metadata.get_region_max_data_pages(),
             self.page_size.try_into().unwrap(),
         )?;
+        if new_layout.num_regions() > NUM_REGIONS {
+            return Err(Error::io(
+                IoOperation::Resize,
+                io::Error::new(
+                    io::ErrorKind::OutOfMemory,
+                    "Database exceeds the maximum number of regions",
+                ),
+            ));
+        }
         assert!(new_layout.len() >= layout.len());
         assert_eq!(new_layout.superheader_pages(), layout.superheader_pages());
         assert_eq!(new_layout.superheader_bytes(), self.db_header_size);
@@ -1583,7 +2562,7 @@ impl TransactionalMemory {
                 if new_region.len() != old_region.len() {
                     let (mut region_tracker, mut regions) =
                         metadata.allocators_mut(&new_layout, *tracker_page)?;
-                    let mut region = regions.get_region_mut(i);
+                    let mut region = regions.get_region_mut(i)?;
                     let mut allocator = region.allocator_mut();
                     allocator.resize(new_region.num_pages() as usize);
                     let highest_free = allocator.highest_free_order().unwrap();
@@ -1594,7 +2573,7 @@ impl TransactionalMemory {
                 let (mut region_tracker, mut regions) =
                     metadata.allocators_mut(&new_layout, *tracker_page)?;
                 // TODO: check that region_tracker has enough space and grow it if needed
-                let mut region = regions.get_region_mut(i);
+                let mut region = regions.get_region_mut(i)?;
                 region.initialize(
                     new_region.num_pages(),
                     new_layout.full_region_layout().num_pages(),
@@ -1608,19 +2587,33 @@ impl TransactionalMemory {
     }
 
     pub(crate) fn allocate(&self, allocation_size: usize) -> Result<PageMut> {
+        self.allocate_inner(allocation_size, false)
+    }
+
+    // Allocates the page with the lowest address that has enough space, splitting larger free
+    // pages if necessary. This is used to relocate pages toward the start of the file
+    pub(crate) fn allocate_lowest(&self, allocation_size: usize) -> Result<PageMut> {
+        self.allocate_inner(allocation_size, true)
+    }
+
+    fn allocate_inner(&self, allocation_size: usize, lowest: bool) -> Result<PageMut> {
         let required_pages = (allocation_size + self.page_size - 1) / self.page_size;
         let required_order = ceil_log2(required_pages);
 
-        let mut metadata = self.lock_metadata();
+        let mut metadata = self.lock_metadata()?;
+        // A page can't span multiple regions
+        if required_pages > metadata.get_region_max_data_pages() as usize {
+            return Err(Error::ValueTooLarge(allocation_size));
+        }
         let mut layout = self.layout.lock().unwrap();
 
         let page_number = if let Some(page_number) =
-            self.allocate_helper(&mut metadata, &layout, required_order)?
+            self.allocate_helper(&mut metadata, &layout, required_order, lowest)?
         {
             page_number
         } else {
             self.grow(&mut metadata, &mut layout, required_order)?;
-            self.allocate_helper(&mut metadata, &layout, required_order)?
+            self.allocate_helper(&mut metadata, &layout, required_order, lowest)?
                 .unwrap()
         };
 
@@ -1632,6 +2625,9 @@ impl TransactionalMemory {
             .lock()
             .unwrap()
             .push(AllocationOp::Allocate(page_number));
+        self.dirty_bytes
+            .fetch_add(self.page_bytes(page_number), Ordering::AcqRel);
+        self.report(AllocationEventKind::Allocate, page_number, true);
         #[cfg(debug_assertions)]
         {
             assert!(!self
@@ -1639,7 +2635,7 @@ impl TransactionalMemory {
                 .lock()
                 .unwrap()
                 .contains_key(&page_number));
-            assert!(self.open_dirty_pages.lock().unwrap().insert(page_number));
+            assert!(!self.open_dirty_pages.lock().unwrap().contains(&page_number));
         }
 
         let address_range = page_number.address_range(
@@ -1651,8 +2647,10 @@ impl TransactionalMemory {
 
         // Safety:
         // The address range we're returning was just allocated, so no other references exist
-        let mem = unsafe { self.mmap.get_memory_mut(address_range) };
+        let mem = unsafe { self.mmap.get_memory_mut(address_range)? };
         debug_assert!(mem.len() >= allocation_size);
+        #[cfg(debug_assertions)]
+        assert!(self.open_dirty_pages.lock().unwrap().insert(page_number));
 
         #[cfg(unix)]
         {
@@ -1667,7 +2665,7 @@ impl TransactionalMemory {
                     )
                 };
                 if result != 0 {
-                    return Err(io::Error::last_os_error().into());
+                    return Err(Error::io(IoOperation::Read, io::Error::last_os_error()));
                 }
             }
         }
@@ -1681,11 +2679,11 @@ impl TransactionalMemory {
     }
 
     pub(crate) fn count_allocated_pages(&self) -> Result<usize> {
-        let mut metadata = self.lock_metadata();
+        let mut metadata = self.lock_metadata()?;
         let layout = self.layout.lock().unwrap();
         let mut count = 0;
         for i in 0..layout.layout.num_regions() {
-            let region = metadata.get_region(i, &layout.layout);
+            let region = metadata.get_region(i, &layout.layout)?;
             count += region.allocator().count_allocated_pages();
         }
 
@@ -1695,15 +2693,76 @@ impl TransactionalMemory {
     pub(crate) fn get_page_size(&self) -> usize {
         self.page_size
     }
+
+    // Returns the offset of the page in the file, or None if it lies outside of the regions
+    pub(crate) fn page_offset(&self, page_number: PageNumber) -> Option<u64> {
+        let layout = self.layout.lock().unwrap();
+        if page_number.region >= layout.layout.num_regions()
+            || usize::from(page_number.page_order) > MAX_MAX_PAGE_ORDER
+        {
+            return None;
+        }
+        let end = (u64::from(page_number.page_index) + 1) << page_number.page_order;
+        if end
+            > layout
+                .layout
+                .region_layout(page_number.region)
+                .num_pages()
+                .into()
+        {
+            return None;
+        }
+        let range = page_number.address_range(
+            self.db_header_size,
+            self.region_size,
+            self.region_header_with_padding_size,
+            self.page_size,
+        );
+        Some(range.start.try_into().unwrap())
+    }
+
+    // Returns every allocated page, split into pages of order 0
+    pub(crate) fn allocated_pages(&self) -> Result<Vec<PageNumber>> {
+        // Every page is allocated since the state of a database without any regions
+        self.pages_allocated_since_raw_state(&[])
+    }
+
+    pub(crate) fn get_region_tracker_page(&self) -> PageNumber {
+        self.layout.lock().unwrap().tracker_page
+    }
+
+    // Usable bytes of each full region
+    pub(crate) fn get_region_size(&self) -> usize {
+        let layout = self.layout.lock().unwrap();
+        layout
+            .layout
+            .full_region_layout()
+            .usable_bytes()
+            .try_into()
+            .unwrap()
+    }
+
+    pub(crate) fn get_write_strategy(&self) -> WriteStrategy {
+        match self.checksum_type() {
+            ChecksumType::XXH3_128 => WriteStrategy::Checksum,
+            ChecksumType::Unused => WriteStrategy::TwoPhase,
+        }
+    }
+
+    pub(crate) fn get_usable_bytes(&self) -> u64 {
+        self.layout.lock().unwrap().layout.usable_bytes()
+    }
 }
 
 impl Drop for TransactionalMemory {
     fn drop(&mut self) {
         // Commit any non-durable transactions that are outstanding
         if self.read_from_secondary.load(Ordering::Acquire) {
-            if let Ok(non_durable_transaction_id) = self.get_last_committed_transaction_id() {
-                let root = self.get_data_root();
-                let freed_root = self.get_freed_root();
+            if let (Ok(non_durable_transaction_id), Ok(root), Ok(freed_root)) = (
+                self.get_last_committed_transaction_id(),
+                self.get_data_root(),
+                self.get_freed_root(),
+            ) {
                 if self
                     .commit(root, freed_root, non_durable_transaction_id, false, None)
                     .is_err()
@@ -1719,7 +2778,9 @@ impl Drop for TransactionalMemory {
             }
         }
         if self.mmap.flush().is_ok() && !self.needs_recovery {
-            self.lock_metadata().set_recovery(false);
+            if let Ok(mut metadata) = self.lock_metadata() {
+                metadata.set_recovery(false);
+            }
             let _ = self.mmap.flush();
         }
     }
@@ -1729,14 +2790,16 @@ impl Drop for TransactionalMemory {
 mod test {
     use crate::db::TableDefinition;
     use crate::tree_store::page_store::page_manager::{
-        GOD_BYTE_OFFSET, MAGICNUMBER, PRIMARY_BIT, RECOVERY_REQUIRED, ROOT_CHECKSUM_OFFSET,
-        TRANSACTION_0_OFFSET, TRANSACTION_1_OFFSET,
+        FILE_FORMAT_VERSION, GOD_BYTE_OFFSET, MAGICNUMBER, MIN_UPGRADABLE_VERSION, PRIMARY_BIT,
+        RECOVERY_REQUIRED, ROOT_CHECKSUM_OFFSET, TRANSACTION_0_OFFSET, TRANSACTION_1_OFFSET,
+        VERSION_OFFSET,
     };
-    use crate::tree_store::page_store::TransactionalMemory;
-    use crate::{Database, ReadableTable, WriteStrategy};
+    use crate::tree_store::page_store::{Storage, TransactionalMemory};
+    use crate::{Database, Error, ReadableTable, StorageBackend, WriteStrategy};
     use std::fs::OpenOptions;
     use std::io::{Read, Seek, SeekFrom, Write};
     use std::mem::size_of;
+    use std::path::Path;
     use tempfile::NamedTempFile;
 
     const X: TableDefinition<&[u8], &[u8]> = TableDefinition::new("x");
@@ -1744,12 +2807,10 @@ mod test {
     #[test]
     fn repair_allocator_no_checksums() {
         let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-        let db = unsafe {
-            Database::builder()
-                .set_write_strategy(WriteStrategy::TwoPhase)
-                .create(tmpfile.path())
-                .unwrap()
-        };
+        let db = Database::builder()
+            .set_write_strategy(WriteStrategy::TwoPhase)
+            .create(tmpfile.path())
+            .unwrap();
         let write_txn = db.begin_write().unwrap();
         {
             let mut table = write_txn.open_table(X).unwrap();
@@ -1774,19 +2835,23 @@ mod test {
         buffer[0] |= RECOVERY_REQUIRED;
         file.write_all(&buffer).unwrap();
 
-        assert!(
-            TransactionalMemory::new(file, None, None, None, Some(WriteStrategy::TwoPhase))
-                .unwrap()
-                .needs_repair()
-                .unwrap()
-        );
+        assert!(TransactionalMemory::new(
+            Storage::File(file),
+            None,
+            None,
+            None,
+            Some(WriteStrategy::TwoPhase),
+            true,
+            StorageBackend::Mmap
+        )
+        .unwrap()
+        .needs_repair()
+        .unwrap());
 
-        let db2 = unsafe {
-            Database::builder()
-                .set_write_strategy(WriteStrategy::TwoPhase)
-                .create(tmpfile.path())
-                .unwrap()
-        };
+        let db2 = Database::builder()
+            .set_write_strategy(WriteStrategy::TwoPhase)
+            .create(tmpfile.path())
+            .unwrap();
         let write_txn = db2.begin_write().unwrap();
         assert_eq!(
             allocated_pages,
@@ -1802,12 +2867,10 @@ mod test {
     #[test]
     fn repair_allocator_checksums() {
         let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-        let db = unsafe {
-            Database::builder()
-                .set_write_strategy(WriteStrategy::Checksum)
-                .create(tmpfile.path())
-                .unwrap()
-        };
+        let db = Database::builder()
+            .set_write_strategy(WriteStrategy::Checksum)
+            .create(tmpfile.path())
+            .unwrap();
         let write_txn = db.begin_write().unwrap();
         {
             let mut table = write_txn.open_table(X).unwrap();
@@ -1852,14 +2915,20 @@ mod test {
         .unwrap();
         file.write_all(&[0; size_of::<u128>()]).unwrap();
 
-        assert!(
-            TransactionalMemory::new(file, None, None, None, Some(WriteStrategy::Checksum))
-                .unwrap()
-                .needs_repair()
-                .unwrap()
-        );
+        assert!(TransactionalMemory::new(
+            Storage::File(file),
+            None,
+            None,
+            None,
+            Some(WriteStrategy::Checksum),
+            true,
+            StorageBackend::Mmap
+        )
+        .unwrap()
+        .needs_repair()
+        .unwrap());
 
-        let db2 = unsafe { Database::create(tmpfile.path()).unwrap() };
+        let db2 = Database::create(tmpfile.path()).unwrap();
         let write_txn = db2.begin_write().unwrap();
         {
             let mut table = write_txn.open_table(X).unwrap();
@@ -1872,12 +2941,10 @@ mod test {
     #[test]
     fn change_write_strategy_to_2pc() {
         let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-        let db = unsafe {
-            Database::builder()
-                .set_write_strategy(WriteStrategy::Checksum)
-                .create(tmpfile.path())
-                .unwrap()
-        };
+        let db = Database::builder()
+            .set_write_strategy(WriteStrategy::Checksum)
+            .create(tmpfile.path())
+            .unwrap();
         let write_txn = db.begin_write().unwrap();
         {
             let mut table = write_txn.open_table(X).unwrap();
@@ -1903,19 +2970,23 @@ mod test {
         buffer[0] |= RECOVERY_REQUIRED;
         file.write_all(&buffer).unwrap();
 
-        assert!(
-            TransactionalMemory::new(file, None, None, None, Some(WriteStrategy::TwoPhase))
-                .unwrap()
-                .needs_repair()
-                .unwrap()
-        );
+        assert!(TransactionalMemory::new(
+            Storage::File(file),
+            None,
+            None,
+            None,
+            Some(WriteStrategy::TwoPhase),
+            true,
+            StorageBackend::Mmap
+        )
+        .unwrap()
+        .needs_repair()
+        .unwrap());
 
-        let db2 = unsafe {
-            Database::builder()
-                .set_write_strategy(WriteStrategy::TwoPhase)
-                .create(tmpfile.path())
-                .unwrap()
-        };
+        let db2 = Database::builder()
+            .set_write_strategy(WriteStrategy::TwoPhase)
+            .create(tmpfile.path())
+            .unwrap();
         let write_txn = db2.begin_write().unwrap();
         assert_eq!(
             allocated_pages,
@@ -1931,12 +3002,10 @@ mod test {
     #[test]
     fn repair_insert_reserve_regression() {
         let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-        let db = unsafe {
-            Database::builder()
-                .set_write_strategy(WriteStrategy::Checksum)
-                .create(tmpfile.path())
-                .unwrap()
-        };
+        let db = Database::builder()
+            .set_write_strategy(WriteStrategy::Checksum)
+            .create(tmpfile.path())
+            .unwrap();
 
         let write_txn = db.begin_write().unwrap();
         {
@@ -1969,14 +3038,154 @@ mod test {
         buffer[0] |= RECOVERY_REQUIRED;
         file.write_all(&buffer).unwrap();
 
-        assert!(
-            TransactionalMemory::new(file, None, None, None, Some(WriteStrategy::Checksum))
-                .unwrap()
-                .needs_repair()
-                .unwrap()
+        assert!(TransactionalMemory::new(
+            Storage::File(file),
+            None,
+            None,
+            None,
+            Some(WriteStrategy::Checksum),
+            true,
+            StorageBackend::Mmap
+        )
+        .unwrap()
+        .needs_repair()
+        .unwrap());
+
+        Database::open(tmpfile.path()).unwrap();
+    }
+
+    // Sets the version of both commit slots. The slots must not be checksummed
+    fn set_file_format_version(path: &Path, version: u8) {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        for slot_offset in [TRANSACTION_0_OFFSET, TRANSACTION_1_OFFSET] {
+            file.seek(SeekFrom::Start((slot_offset + VERSION_OFFSET) as u64))
+                .unwrap();
+            file.write_all(&[version]).unwrap();
+        }
+    }
+
+    #[test]
+    fn outdated_file_format_version() {
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        // Slots aren't checksummed by this strategy, so their version can be modified in place
+        let db = Database::builder()
+            .set_write_strategy(WriteStrategy::TwoPhase)
+            .create(tmpfile.path())
+            .unwrap();
+        drop(db);
+        assert_eq!(
+            Database::file_format_version(tmpfile.path()).unwrap(),
+            FILE_FORMAT_VERSION
         );
+        assert!(!Database::upgrade(tmpfile.path()).unwrap());
 
-        unsafe { Database::open(tmpfile.path()).unwrap() };
+        set_file_format_version(tmpfile.path(), MIN_UPGRADABLE_VERSION - 1);
+        assert_eq!(
+            Database::file_format_version(tmpfile.path()).unwrap(),
+            MIN_UPGRADABLE_VERSION - 1
+        );
+        assert!(matches!(
+            Database::open(tmpfile.path()),
+            Err(Error::UpgradeRequired(version)) if version == MIN_UPGRADABLE_VERSION - 1
+        ));
+        assert!(matches!(
+            Database::upgrade(tmpfile.path()),
+            Err(Error::Corrupted { .. })
+        ));
+    }
+
+    #[test]
+    fn upgrade_before_counted_tables() {
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        let db = Database::builder()
+            .set_write_strategy(WriteStrategy::TwoPhase)
+            .create(tmpfile.path())
+            .unwrap();
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut table = write_txn.open_table(X).unwrap();
+            table.insert(b"hello", b"world").unwrap();
+        }
+        write_txn.commit().unwrap();
+        drop(db);
+
+        // Files written before counted tables were added are rejected, until they're upgraded
+        set_file_format_version(tmpfile.path(), 107);
+        assert!(matches!(
+            Database::open(tmpfile.path()),
+            Err(Error::UpgradeRequired(107))
+        ));
+        assert!(Database::upgrade(tmpfile.path()).unwrap());
+        assert_eq!(
+            Database::file_format_version(tmpfile.path()).unwrap(),
+            FILE_FORMAT_VERSION
+        );
+        assert!(!Database::upgrade(tmpfile.path()).unwrap());
+
+        let db = Database::open(tmpfile.path()).unwrap();
+        let write_txn = db.begin_write().unwrap();
+        {
+            let table = write_txn.open_table(X).unwrap();
+            assert_eq!(table.get(b"hello").unwrap().unwrap(), b"world");
+            let mut counted = write_txn
+                .open_table(TableDefinition::<u64, u64>::new("counted").with_entry_counts())
+                .unwrap();
+            counted.insert(&0, &0).unwrap();
+        }
+        write_txn.commit().unwrap();
+        drop(db);
+
+        let db = Database::open(tmpfile.path()).unwrap();
+        assert!(db.check_integrity().unwrap().is_ok());
+    }
+
+    #[test]
+    fn upgrade_before_system_tables() {
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        let db = Database::builder()
+            .set_write_strategy(WriteStrategy::TwoPhase)
+            .create(tmpfile.path())
+            .unwrap();
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut table = write_txn.open_table(X).unwrap();
+            table.insert(b"hello", b"world").unwrap();
+        }
+        write_txn.commit().unwrap();
+        drop(db);
+
+        // Files written before persistent savepoints were added are rejected, until they're
+        // upgraded
+        set_file_format_version(tmpfile.path(), 108);
+        assert!(matches!(
+            Database::open(tmpfile.path()),
+            Err(Error::UpgradeRequired(108))
+        ));
+        assert!(Database::upgrade(tmpfile.path()).unwrap());
+        assert_eq!(
+            Database::file_format_version(tmpfile.path()).unwrap(),
+            FILE_FORMAT_VERSION
+        );
+
+        let db = Database::open(tmpfile.path()).unwrap();
+        let write_txn = db.begin_write().unwrap();
+        let id = write_txn.persistent_savepoint().unwrap();
+        write_txn.commit().unwrap();
+        drop(db);
+
+        let db = Database::open(tmpfile.path()).unwrap();
+        let mut write_txn = db.begin_write().unwrap();
+        let savepoint = write_txn.get_persistent_savepoint(id).unwrap();
+        write_txn.restore_savepoint(&savepoint).unwrap();
+        {
+            let table = write_txn.open_table(X).unwrap();
+            assert_eq!(table.get(b"hello").unwrap().unwrap(), b"world");
+        }
+        write_txn.commit().unwrap();
     }
 
     #[test]
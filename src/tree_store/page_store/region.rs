@@ -55,6 +55,22 @@ impl<'a> RegionHeaderAccessor<'a> {
         Self { mem: data }
     }
 
+    // Returns true if the header, and the structure of its allocator state, match those written
+    // by RegionHeaderMutator::initialize(). expected_allocator must be an allocator state
+    // initialized with the same capacity
+    pub(crate) fn is_valid(data: &[u8], expected_allocator: &[u8]) -> bool {
+        let end = ALLOCATOR_OFFSET + expected_allocator.len();
+        data.len() >= end
+            && data[0] == REGION_FORMAT_VERSION
+            && u32::from_le_bytes(
+                data[ALLOCATOR_LENGTH_OFFSET..ALLOCATOR_OFFSET]
+                    .try_into()
+                    .unwrap(),
+            ) as usize
+                == expected_allocator.len()
+            && BuddyAllocator::has_structure_of(&data[ALLOCATOR_OFFSET..end], expected_allocator)
+    }
+
     fn get_allocator_len(&self) -> usize {
         u32::from_le_bytes(
             self.mem[ALLOCATOR_LENGTH_OFFSET..(ALLOCATOR_LENGTH_OFFSET + size_of::<u32>())]
@@ -1,9 +1,28 @@
 use crate::transaction_tracker::{SavepointId, TransactionId, TransactionTracker};
 use crate::tree_store::page_store::ChecksumType;
 use crate::tree_store::{Checksum, PageNumber};
-use crate::Database;
+use crate::{Database, Error, Result};
+use std::mem::size_of;
 use std::sync::{Arc, Mutex};
 
+// Persistent savepoint format:
+// 1 byte: version
+// 1 byte: checksum type
+// 8 bytes: transaction id
+// 1 byte: != 0 if root page is non-null
+// 8 bytes: root page
+// 16 bytes: root checksum
+// 1 byte: != 0 if freed table root page is non-null
+// 8 bytes: freed table root page
+// 16 bytes: freed table root checksum
+// 4 bytes: number of regional allocators
+// repeated for each regional allocator:
+//   4 bytes: length of the allocator state
+//   n bytes: allocator state
+const TRANSACTION_ID_OFFSET: usize = 2 * size_of::<u8>();
+const ROOT_OFFSET: usize = TRANSACTION_ID_OFFSET + size_of::<u64>();
+const ROOT_SIZE: usize = size_of::<u8>() + size_of::<u64>() + size_of::<u128>();
+
 pub struct Savepoint {
     id: SavepointId,
     // Each savepoint has an associated read transaction id to ensure that any pages it references
@@ -14,6 +33,8 @@ pub struct Savepoint {
     root: Option<(PageNumber, Checksum)>,
     freed_root: Option<(PageNumber, Checksum)>,
     regional_allocators: Vec<Vec<u8>>,
+    // Persistent savepoints remain valid after this handle is dropped, until they are deleted
+    persistent: bool,
     transaction_tracker: Arc<Mutex<TransactionTracker>>,
 }
 
@@ -22,6 +43,7 @@ impl Savepoint {
         db: &Database,
         id: SavepointId,
         transaction_id: TransactionId,
+        version: u8,
         root: Option<(PageNumber, Checksum)>,
         freed_root: Option<(PageNumber, Checksum)>,
         regional_allocators: Vec<Vec<u8>>,
@@ -29,15 +51,127 @@ impl Savepoint {
         Self {
             id,
             transaction_id,
-            version: db.get_memory().get_version(),
+            version,
             checksum_type: db.get_memory().checksum_type(),
             root,
             freed_root,
             regional_allocators,
+            persistent: false,
             transaction_tracker: db.transaction_tracker(),
         }
     }
 
+    // Reads a persistent savepoint, which was written by to_bytes()
+    // Caller must register the transaction id of the savepoint as a read transaction, since it
+    // will be deallocated when this handle is dropped
+    pub(crate) fn from_bytes(db: &Database, id: SavepointId, data: &[u8]) -> Result<Self> {
+        let mut offset = 0;
+        let mut read = |len: usize| {
+            let slice = data.get(offset..(offset + len));
+            offset += len;
+            slice.ok_or_else(|| {
+                Error::corrupted(format!("Persistent savepoint {} is truncated", id.0))
+            })
+        };
+        let version = read(1)?[0];
+        let checksum_type = ChecksumType::try_from(read(1)?[0])?;
+        let transaction_id = TransactionId(u64::from_le_bytes(read(8)?.try_into().unwrap()));
+        let mut read_root = || -> Result<Option<(PageNumber, Checksum)>> {
+            let non_null = read(1)?[0] != 0;
+            let page = PageNumber::from_le_bytes(read(8)?.try_into().unwrap());
+            let checksum = Checksum::from_le_bytes(read(16)?.try_into().unwrap());
+            if non_null {
+                Ok(Some((page, checksum)))
+            } else {
+                Ok(None)
+            }
+        };
+        let root = read_root()?;
+        let freed_root = read_root()?;
+        let num_allocators = u32::from_le_bytes(read(4)?.try_into().unwrap());
+        let mut regional_allocators = vec![];
+        for _ in 0..num_allocators {
+            let len = u32::from_le_bytes(read(4)?.try_into().unwrap());
+            regional_allocators.push(read(len.try_into().unwrap())?.to_vec());
+        }
+
+        Ok(Self {
+            id,
+            transaction_id,
+            version,
+            checksum_type,
+            root,
+            freed_root,
+            regional_allocators,
+            persistent: true,
+            transaction_tracker: db.transaction_tracker(),
+        })
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut result = vec![self.version, self.checksum_type.into()];
+        result.extend_from_slice(&self.transaction_id.0.to_le_bytes());
+        for root in [self.root, self.freed_root] {
+            let (page, checksum) = root.unwrap_or((PageNumber::new(0, 0, 0), 0));
+            result.push(u8::from(root.is_some()));
+            result.extend_from_slice(&page.to_le_bytes());
+            result.extend_from_slice(&checksum.to_le_bytes());
+        }
+        let num_allocators: u32 = self.regional_allocators.len().try_into().unwrap();
+        result.extend_from_slice(&num_allocators.to_le_bytes());
+        for state in self.regional_allocators.iter() {
+            let len: u32 = state.len().try_into().unwrap();
+            result.extend_from_slice(&len.to_le_bytes());
+            result.extend_from_slice(state);
+        }
+
+        result
+    }
+
+    // Returns the transaction id of a persistent savepoint, which was written by to_bytes()
+    pub(crate) fn transaction_id_from_bytes(data: &[u8]) -> Result<TransactionId> {
+        let bytes = data
+            .get(TRANSACTION_ID_OFFSET..(TRANSACTION_ID_OFFSET + size_of::<u64>()))
+            .ok_or_else(|| Error::corrupted("Persistent savepoint is truncated"))?;
+        Ok(TransactionId(u64::from_le_bytes(bytes.try_into().unwrap())))
+    }
+
+    // Returns the data root and freed table root of a persistent savepoint, which was written by
+    // to_bytes()
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn roots_from_bytes(
+        data: &[u8],
+    ) -> Result<(
+        Option<(PageNumber, Checksum)>,
+        Option<(PageNumber, Checksum)>,
+    )> {
+        let bytes = data
+            .get(ROOT_OFFSET..(ROOT_OFFSET + 2 * ROOT_SIZE))
+            .ok_or_else(|| Error::corrupted("Persistent savepoint is truncated"))?;
+        let read_root = |bytes: &[u8]| {
+            if bytes[0] != 0 {
+                Some((
+                    PageNumber::from_le_bytes(bytes[1..9].try_into().unwrap()),
+                    Checksum::from_le_bytes(bytes[9..25].try_into().unwrap()),
+                ))
+            } else {
+                None
+            }
+        };
+        Ok((
+            read_root(&bytes[..ROOT_SIZE]),
+            read_root(&bytes[ROOT_SIZE..]),
+        ))
+    }
+
+    pub(crate) fn set_persistent(&mut self) {
+        self.persistent = true;
+    }
+
+    pub(crate) fn is_persistent(&self) -> bool {
+        self.persistent
+    }
+
     pub(crate) fn get_version(&self) -> u8 {
         self.version
     }
@@ -73,9 +207,13 @@ impl Savepoint {
 
 impl Drop for Savepoint {
     fn drop(&mut self) {
-        self.transaction_tracker
-            .lock()
-            .unwrap()
-            .deallocate_savepoint(self);
+        let mut tracker = self.transaction_tracker.lock().unwrap();
+        if self.persistent {
+            // The savepoint remains valid, and its pages are protected by the tracker until it's
+            // deleted
+            tracker.deallocate_read_transaction(self.transaction_id);
+        } else {
+            tracker.deallocate_savepoint(self);
+        }
     }
 }
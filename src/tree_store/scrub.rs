@@ -0,0 +1,200 @@
+use crate::multimap_table::parse_subtree_root;
+use crate::tree_store::btree_base::{verify_checksum, BranchAccessor, Checksum, LeafAccessor};
+use crate::tree_store::btree_base::{BRANCH, LEAF};
+use crate::tree_store::page_store::Page;
+use crate::tree_store::{parse_table_name, InternalTableDefinition, PageNumber, TableType};
+use crate::types::RedbValue;
+use crate::{Database, ReadTransaction, Result};
+
+// A tree which pages of the snapshot belong to
+struct TreeInfo {
+    description: String,
+    fixed_key_size: Option<usize>,
+    fixed_value_size: Option<usize>,
+    // The values of multimap tables reference the subtree of values of each key, which is the
+    // following tree
+    multimap: bool,
+}
+
+/// Incrementally verifies the checksums of every page of a database, so that corruption of data
+/// which is rarely read, such as bit rot, is detected before it's needed
+///
+/// Each pass verifies a snapshot of the database, taken when the pass starts, and visits every
+/// page of every table in it. Like a [`ReadTransaction`], the snapshot prevents the pages it
+/// references from being reused until the pass completes, so a long running pass increases the
+/// size of the file if the database is written to concurrently.
+///
+/// Databases which use [`crate::WriteStrategy::TwoPhase`] do not store checksums, so only the
+/// layout of their pages is verified
+///
+/// Created by [`Database::scrubber`]
+pub struct Scrubber<'db> {
+    db: &'db Database,
+    snapshot: Option<ReadTransaction<'db>>,
+    trees: Vec<TreeInfo>,
+    // Pages of the snapshot which have not been verified yet, with their checksum and the index of
+    // their tree. The next page is at the end
+    pending: Vec<(PageNumber, Checksum, usize)>,
+    pages_verified: u64,
+    passes_completed: u64,
+}
+
+impl<'db> Scrubber<'db> {
+    pub(crate) fn new(db: &'db Database) -> Self {
+        Self {
+            db,
+            snapshot: None,
+            trees: vec![],
+            pending: vec![],
+            pages_verified: 0,
+            passes_completed: 0,
+        }
+    }
+
+    /// Verifies up to `max_pages` pages, continuing the current pass, or starting a new one if
+    /// there is none. This can be called periodically, for example from a background thread, to
+    /// spread the cost of verifying a large database over time
+    ///
+    /// Returns `true` if the pass was completed. The first page which is corrupted is reported as
+    /// an [`Error::Corrupted`] which describes the page, and the table which contains it. The pass
+    /// is then abandoned, and the next call starts a new one
+    pub fn scrub(&mut self, max_pages: usize) -> Result<bool> {
+        if self.snapshot.is_none() {
+            self.start_pass()?;
+        }
+        for _ in 0..max_pages {
+            let (page_number, checksum, tree) = if let Some(next) = self.pending.pop() {
+                next
+            } else {
+                break;
+            };
+            if let Err(err) = self.verify_page(page_number, checksum, tree) {
+                let err = err.with_context(&self.trees[tree].description);
+                self.end_pass()?;
+                return Err(err);
+            }
+            self.pages_verified += 1;
+        }
+        if self.pending.is_empty() {
+            self.end_pass()?;
+            self.passes_completed += 1;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    /// Total number of pages verified by this scrubber, across all passes
+    pub fn pages_verified(&self) -> u64 {
+        self.pages_verified
+    }
+
+    /// Number of passes which have been completed without finding any corruption
+    pub fn passes_completed(&self) -> u64 {
+        self.passes_completed
+    }
+
+    fn start_pass(&mut self) -> Result {
+        let snapshot = self.db.begin_read()?;
+        self.trees = vec![TreeInfo {
+            description: "the master table".to_string(),
+            fixed_key_size: <&str>::fixed_width(),
+            fixed_value_size: InternalTableDefinition::fixed_width(),
+            multimap: false,
+        }];
+        self.pending = snapshot
+            .get_root()
+            .map(|(page, checksum)| (page, checksum, 0))
+            .into_iter()
+            .collect();
+        self.snapshot = Some(snapshot);
+
+        Ok(())
+    }
+
+    fn end_pass(&mut self) -> Result {
+        self.trees.clear();
+        self.pending.clear();
+        if let Some(snapshot) = self.snapshot.take() {
+            snapshot.close()?;
+        }
+
+        Ok(())
+    }
+
+    // Verifies the page, and queues the pages which it references
+    fn verify_page(&mut self, page_number: PageNumber, checksum: Checksum, tree: usize) -> Result {
+        let mem = self.db.get_memory();
+        mem.check_page_number(page_number)?;
+        let page = mem.get_page(page_number)?;
+        let info = &self.trees[tree];
+        verify_checksum(
+            &page,
+            checksum,
+            info.fixed_key_size,
+            info.fixed_value_size,
+            mem.checksum_type(),
+        )?;
+
+        match page.memory()[0] {
+            BRANCH => {
+                let accessor = BranchAccessor::new(&page, info.fixed_key_size);
+                // Push the children in reverse, so that the first child is verified next
+                for i in (0..accessor.count_children()).rev() {
+                    self.pending.push((
+                        accessor.child_page(i).unwrap(),
+                        accessor.child_checksum(i).unwrap(),
+                        tree,
+                    ));
+                }
+            }
+            LEAF => {
+                let accessor =
+                    LeafAccessor::new(page.memory(), info.fixed_key_size, info.fixed_value_size);
+                let multimap = info.multimap;
+                for i in (0..accessor.num_pairs()).rev() {
+                    let entry = accessor.entry(i).unwrap();
+                    if tree == 0 {
+                        let name = parse_table_name(entry.key())?;
+                        let definition = InternalTableDefinition::try_from_bytes(entry.value())?;
+                        self.queue_table(name, &definition);
+                    } else if multimap {
+                        if let Some((root, checksum)) = parse_subtree_root(entry.value()) {
+                            self.pending.push((root, checksum, tree + 1));
+                        }
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    fn queue_table(&mut self, name: &str, definition: &InternalTableDefinition) {
+        let description = format!("table '{}'", name);
+        let multimap = definition.get_type() == TableType::Multimap;
+        let root = definition.get_root();
+        if let Some((root, checksum)) = root {
+            self.pending.push((root, checksum, self.trees.len()));
+        }
+        self.trees.push(TreeInfo {
+            description: description.clone(),
+            fixed_key_size: definition.get_fixed_key_size(),
+            fixed_value_size: if multimap {
+                None
+            } else {
+                definition.get_fixed_value_size()
+            },
+            multimap,
+        });
+        if multimap {
+            self.trees.push(TreeInfo {
+                description,
+                fixed_key_size: definition.get_fixed_value_size(),
+                fixed_value_size: <() as RedbValue>::fixed_width(),
+                multimap: false,
+            });
+        }
+    }
+}
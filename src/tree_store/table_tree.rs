@@ -1,7 +1,8 @@
-use crate::tree_store::btree::btree_stats;
+use crate::multimap_table::copy_subtree_value;
+use crate::tree_store::btree::{btree_stats, copy_tree, relocate_tree};
 use crate::tree_store::btree_base::Checksum;
 use crate::tree_store::btree_iters::AllPageNumbersBtreeIter;
-use crate::tree_store::{BtreeMut, BtreeRangeIter, PageNumber, TransactionalMemory};
+use crate::tree_store::{BtreeMut, PageNumber, TransactionalMemory};
 use crate::types::{RedbKey, RedbValue};
 use crate::{DatabaseStats, Error, Result};
 use std::cell::RefCell;
@@ -73,10 +74,38 @@ impl RedbKey for FreedTableKey {
     }
 }
 
+// Adds the name of the table to the message of a Corrupted error
+fn table_corrupted(name: &str, err: Error) -> Error {
+    err.with_context(format_args!("Table '{}'", name))
+}
+
+// Parses a key of the master table. Names are always stored as valid UTF-8, so this only fails if
+// the file is corrupted
+pub(crate) fn parse_table_name(data: &[u8]) -> Result<&str> {
+    std::str::from_utf8(data).map_err(|_| Error::corrupted("Table name is not valid UTF-8"))
+}
+
 #[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
 pub(crate) enum TableType {
     Normal,
     Multimap,
+    // Internal tables, which are hidden from the user
+    System,
+}
+
+impl TableType {
+    fn name(self) -> &'static str {
+        match self {
+            TableType::Normal => "Table",
+            TableType::Multimap => "MultimapTable",
+            TableType::System => "SystemTable",
+        }
+    }
+}
+
+// Formats the type of a table, for example "Table<u64, &str>"
+pub(crate) fn table_type_name(table_type: TableType, key_type: &str, value_type: &str) -> String {
+    format!("{}<{}, {}>", table_type.name(), key_type, value_type)
 }
 
 #[allow(clippy::from_over_into)]
@@ -85,24 +114,32 @@ impl Into<u8> for TableType {
         match self {
             TableType::Normal => 1,
             TableType::Multimap => 2,
+            TableType::System => 3,
         }
     }
 }
 
-impl From<u8> for TableType {
-    fn from(value: u8) -> Self {
+impl TryFrom<u8> for TableType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
         match value {
-            1 => TableType::Normal,
-            2 => TableType::Multimap,
-            _ => unreachable!(),
+            1 => Ok(TableType::Normal),
+            2 => Ok(TableType::Multimap),
+            3 => Ok(TableType::System),
+            _ => Err(Error::corrupted(format!("Invalid table type {}", value))),
         }
     }
 }
 
+// Set in the table type byte of tables whose branches store the entry count of each child
+const COUNTED_TABLE_FLAG: u8 = 0x80;
+
 #[derive(Clone, Debug)]
 pub(crate) struct InternalTableDefinition {
     table_root: Option<(PageNumber, Checksum)>,
     table_type: TableType,
+    counted: bool,
     fixed_key_size: Option<usize>,
     fixed_value_size: Option<usize>,
     key_type: String,
@@ -125,96 +162,93 @@ impl InternalTableDefinition {
     pub(crate) fn get_type(&self) -> TableType {
         self.table_type
     }
-}
 
-impl RedbValue for InternalTableDefinition {
-    type SelfType<'a> = InternalTableDefinition;
-    type RefBaseType<'a> = InternalTableDefinition;
-    type AsBytes<'a> = Vec<u8>;
+    pub(crate) fn is_counted(&self) -> bool {
+        self.counted
+    }
 
-    fn fixed_width() -> Option<usize> {
-        None
+    pub(crate) fn get_key_type(&self) -> &str {
+        &self.key_type
     }
 
-    fn from_bytes<'a>(data: &'a [u8]) -> Self
-    where
-        Self: 'a,
-    {
-        debug_assert!(data.len() > 22);
-        let mut offset = 0;
-        let table_type = TableType::from(data[offset]);
-        offset += 1;
+    pub(crate) fn get_value_type(&self) -> &str {
+        &self.value_type
+    }
 
-        let non_null = data[offset] != 0;
-        offset += 1;
+    // Parses a definition which was written by as_bytes(), and returns an error if it's
+    // malformed, as it only is if the file is corrupted
+    pub(crate) fn try_from_bytes(data: &[u8]) -> Result<Self> {
+        let mut offset = 0;
+        let mut read = |len: usize| {
+            let slice = data.get(offset..(offset + len));
+            offset += len;
+            slice.ok_or_else(|| Error::corrupted("Table definition is truncated"))
+        };
+        let type_byte = read(1)?[0];
+        let table_type = TableType::try_from(type_byte & !COUNTED_TABLE_FLAG)?;
+        let counted = type_byte & COUNTED_TABLE_FLAG != 0;
+
+        let non_null = read(1)?[0] != 0;
+        let table_root =
+            PageNumber::from_le_bytes(read(PageNumber::serialized_size())?.try_into().unwrap());
+        let checksum = Checksum::from_le_bytes(read(size_of::<Checksum>())?.try_into().unwrap());
         let table_root = if non_null {
-            let table_root = PageNumber::from_le_bytes(
-                data[offset..(offset + PageNumber::serialized_size())]
-                    .try_into()
-                    .unwrap(),
-            );
-            offset += PageNumber::serialized_size();
-            let checksum = Checksum::from_le_bytes(
-                data[offset..(offset + size_of::<Checksum>())]
-                    .try_into()
-                    .unwrap(),
-            );
-            offset += size_of::<Checksum>();
             Some((table_root, checksum))
         } else {
-            offset += PageNumber::serialized_size();
-            offset += size_of::<Checksum>();
             None
         };
 
-        let non_null = data[offset] != 0;
-        offset += 1;
-        let fixed_key_size = if non_null {
-            let fixed = u32::from_le_bytes(
-                data[offset..(offset + size_of::<u32>())]
-                    .try_into()
-                    .unwrap(),
-            ) as usize;
-            Some(fixed)
-        } else {
-            None
+        let mut read_fixed_size = || -> Result<Option<usize>> {
+            let non_null = read(1)?[0] != 0;
+            let fixed = u32::from_le_bytes(read(size_of::<u32>())?.try_into().unwrap()) as usize;
+            Ok(if non_null { Some(fixed) } else { None })
         };
-        offset += size_of::<u32>();
-
-        let non_null = data[offset] != 0;
-        offset += 1;
-        let fixed_value_size = if non_null {
-            let fixed = u32::from_le_bytes(
-                data[offset..(offset + size_of::<u32>())]
-                    .try_into()
-                    .unwrap(),
-            ) as usize;
-            Some(fixed)
-        } else {
-            None
+        let fixed_key_size = read_fixed_size()?;
+        let fixed_value_size = read_fixed_size()?;
+
+        let key_type_len = u32::from_le_bytes(read(size_of::<u32>())?.try_into().unwrap()) as usize;
+        let key_type = read(key_type_len)?;
+        let value_type = &data[offset..];
+        let (key_type, value_type) = match (
+            std::str::from_utf8(key_type),
+            std::str::from_utf8(value_type),
+        ) {
+            (Ok(key_type), Ok(value_type)) => (key_type.to_string(), value_type.to_string()),
+            _ => {
+                return Err(Error::corrupted(
+                    "Table definition has a type name which is not valid UTF-8",
+                ))
+            }
         };
-        offset += size_of::<u32>();
-
-        let key_type_len = u32::from_le_bytes(
-            data[offset..(offset + size_of::<u32>())]
-                .try_into()
-                .unwrap(),
-        ) as usize;
-        offset += size_of::<u32>();
-        let key_type = std::str::from_utf8(&data[offset..(offset + key_type_len)])
-            .unwrap()
-            .to_string();
-        offset += key_type_len;
-        let value_type = std::str::from_utf8(&data[offset..]).unwrap().to_string();
-
-        InternalTableDefinition {
+
+        Ok(InternalTableDefinition {
             table_root,
             table_type,
+            counted,
             fixed_key_size,
             fixed_value_size,
             key_type,
             value_type,
-        }
+        })
+    }
+}
+
+impl RedbValue for InternalTableDefinition {
+    type SelfType<'a> = InternalTableDefinition;
+    type RefBaseType<'a> = InternalTableDefinition;
+    type AsBytes<'a> = Vec<u8>;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    // Definitions which are read from a file that may be corrupted are parsed with
+    // try_from_bytes() instead
+    fn from_bytes<'a>(data: &'a [u8]) -> Self
+    where
+        Self: 'a,
+    {
+        Self::try_from_bytes(data).unwrap()
     }
 
     fn as_bytes<'a, 'b: 'a>(value: &'a Self::RefBaseType<'b>) -> Vec<u8>
@@ -222,7 +256,11 @@ impl RedbValue for InternalTableDefinition {
         Self: 'a,
         Self: 'b,
     {
-        let mut result = vec![value.table_type.into()];
+        let mut table_type: u8 = value.table_type.into();
+        if value.counted {
+            table_type |= COUNTED_TABLE_FLAG;
+        }
+        let mut result = vec![table_type];
         if let Some((root, checksum)) = value.table_root {
             result.push(1);
             result.extend_from_slice(&root.to_le_bytes());
@@ -262,29 +300,67 @@ impl RedbValue for InternalTableDefinition {
     }
 }
 
-pub struct TableNameIter<'a> {
-    inner: BtreeRangeIter<'a, &'static str, InternalTableDefinition>,
-    table_type: TableType,
+/// The name of a table, and the names of its key and value types as they were stored when the
+/// table was created
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TableSchema {
+    name: String,
+    multimap: bool,
+    key_type: String,
+    value_type: String,
 }
 
-impl<'a> Iterator for TableNameIter<'a> {
-    type Item = String;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        for entry in self.inner.by_ref() {
-            if InternalTableDefinition::from_bytes(entry.value()).table_type == self.table_type {
-                return Some(<&str>::from_bytes(entry.key()).to_string());
-            }
+impl TableSchema {
+    fn new(name: &str, definition: InternalTableDefinition) -> Self {
+        Self {
+            name: name.to_string(),
+            multimap: definition.table_type == TableType::Multimap,
+            key_type: definition.key_type,
+            value_type: definition.value_type,
         }
-        None
+    }
+
+    /// Name of the table
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns true if this is a multimap table
+    pub fn is_multimap(&self) -> bool {
+        self.multimap
+    }
+
+    /// Type name of the keys, as returned by [`RedbValue::redb_type_name`]
+    pub fn key_type(&self) -> &str {
+        &self.key_type
+    }
+
+    /// Type name of the values, as returned by [`RedbValue::redb_type_name`]
+    pub fn value_type(&self) -> &str {
+        &self.value_type
+    }
+
+    pub(crate) fn type_name(&self) -> String {
+        let table_type = if self.multimap {
+            TableType::Multimap
+        } else {
+            TableType::Normal
+        };
+        table_type_name(table_type, &self.key_type, &self.value_type)
     }
 }
 
+// Validates the tree with the given root, for tables modified in a transaction
+#[cfg(feature = "integrity_checks")]
+pub(crate) type IntegrityCheck = fn(Option<(PageNumber, Checksum)>, &TransactionalMemory) -> Result;
+
 pub(crate) struct TableTree<'txn> {
     tree: BtreeMut<'txn, &'static str, InternalTableDefinition>,
     mem: &'txn TransactionalMemory,
     // Cached updates from tables that have been closed. These must be flushed to the btree
     pending_table_updates: HashMap<String, Option<(PageNumber, Checksum)>>,
+    #[cfg(feature = "integrity_checks")]
+    pending_integrity_checks: HashMap<String, IntegrityCheck>,
     freed_pages: Rc<RefCell<Vec<PageNumber>>>,
 }
 
@@ -298,6 +374,8 @@ impl<'txn> TableTree<'txn> {
             tree: BtreeMut::new(master_root, mem, freed_pages.clone()),
             mem,
             pending_table_updates: Default::default(),
+            #[cfg(feature = "integrity_checks")]
+            pending_integrity_checks: Default::default(),
             freed_pages,
         }
     }
@@ -312,11 +390,27 @@ impl<'txn> TableTree<'txn> {
             .insert(name.to_string(), table_root);
     }
 
+    // Queues a validation of the table, which is run before its root is flushed
+    #[cfg(feature = "integrity_checks")]
+    pub(crate) fn stage_integrity_check(&mut self, name: &str, check: IntegrityCheck) {
+        self.pending_integrity_checks
+            .insert(name.to_string(), check);
+    }
+
     pub(crate) fn clear_table_root_updates(&mut self) {
         self.pending_table_updates.clear();
+        #[cfg(feature = "integrity_checks")]
+        self.pending_integrity_checks.clear();
     }
 
     pub(crate) fn flush_table_root_updates(&mut self) -> Result<Option<(PageNumber, Checksum)>> {
+        #[cfg(feature = "integrity_checks")]
+        for (name, check) in self.pending_integrity_checks.drain() {
+            let table_root = self.pending_table_updates.get(&name).copied().flatten();
+            check(table_root, self.mem).map_err(|err| {
+                err.with_context(format_args!("Integrity check of table '{}' failed", name))
+            })?;
+        }
         for (name, table_root) in self.pending_table_updates.drain() {
             // Bypass .get_table() since the table types are dynamic
             // TODO: optimize away this get()
@@ -334,44 +428,162 @@ impl<'txn> TableTree<'txn> {
         Ok(self.tree.get_root())
     }
 
+    // Moves the pages of all tables toward the start of the file, copying at most budget bytes
+    // Safety: caller must ensure that no references to uncommitted pages in any table exist
+    pub(crate) unsafe fn relocate_tables(&mut self, budget: &mut usize) -> Result {
+        self.flush_table_root_updates()?;
+        for (name, mut definition) in self.list_definitions()? {
+            if let Some(root) = definition.table_root {
+                let new_root = relocate_tree(
+                    root,
+                    definition.fixed_key_size,
+                    self.mem,
+                    &mut self.freed_pages.borrow_mut(),
+                    budget,
+                )?;
+                if new_root != root {
+                    definition.table_root = Some(new_root);
+                    self.tree.insert(name.as_str(), &definition)?;
+                }
+            }
+        }
+        self.tree.relocate(budget)
+    }
+
+    // Copies all user tables from source, which may belong to a different database, into
+    // newly allocated pages. Tables with the same name are replaced
+    pub(crate) fn copy_tables_from(&mut self, source: &TableTree) -> Result {
+        for (name, mut definition) in source.list_definitions()? {
+            if definition.table_type == TableType::System {
+                continue;
+            }
+            if let Some(root) = definition.table_root {
+                let new_root = if definition.table_type == TableType::Multimap {
+                    let fixed_value_size = definition.fixed_value_size;
+                    let dest = self.mem;
+                    // The values of the outer tree reference the subtree of each key, and have
+                    // no fixed width
+                    copy_tree(
+                        root,
+                        definition.fixed_key_size,
+                        None,
+                        source.mem,
+                        dest,
+                        &mut |value| copy_subtree_value(value, fixed_value_size, source.mem, dest),
+                    )?
+                } else {
+                    copy_tree(
+                        root,
+                        definition.fixed_key_size,
+                        definition.fixed_value_size,
+                        source.mem,
+                        self.mem,
+                        &mut |_| Ok(()),
+                    )?
+                };
+                definition.table_root = Some(new_root);
+            }
+            // Safety: References into the master table are never returned to the user
+            unsafe { self.tree.insert(name.as_str(), &definition)? };
+        }
+        Ok(())
+    }
+
     // root_page: the root of the master table
     pub(crate) fn list_tables(&self, table_type: TableType) -> Result<Vec<String>> {
-        let iter = self.tree.range::<RangeFull, &str>(..)?;
-        let iter = TableNameIter {
-            inner: iter,
-            table_type,
-        };
-        Ok(iter.collect())
+        Ok(self
+            .list_definitions()?
+            .into_iter()
+            .filter(|(_, definition)| definition.table_type == table_type)
+            .map(|(name, _)| name)
+            .collect())
+    }
+
+    // Returns the schema of every user table, of both types
+    pub(crate) fn list_table_schemas(&self) -> Result<Vec<TableSchema>> {
+        Ok(self
+            .list_definitions()?
+            .into_iter()
+            .filter(|(_, definition)| definition.table_type != TableType::System)
+            .map(|(name, definition)| TableSchema::new(&name, definition))
+            .collect())
+    }
+
+    // Returns the name and definition of every table, including system tables
+    fn list_definitions(&self) -> Result<Vec<(String, InternalTableDefinition)>> {
+        let mut iter = self.tree.range::<RangeFull, &str>(..)?;
+        let mut result = vec![];
+        while let Some(entry) = iter.try_next()? {
+            let name = parse_table_name(entry.key())?.to_string();
+            let definition = InternalTableDefinition::try_from_bytes(entry.value())
+                .map_err(|err| table_corrupted(&name, err))?;
+            result.push((name, definition));
+        }
+
+        Ok(result)
+    }
+
+    pub(crate) fn get_table_schema(&self, name: &str) -> Result<Option<TableSchema>> {
+        Ok(self
+            .tree
+            .get(name)?
+            .filter(|definition| definition.table_type != TableType::System)
+            .map(|definition| TableSchema::new(name, definition)))
     }
 
     // root_page: the root of the master table
-    pub(crate) fn get_table<K: RedbKey + ?Sized, V: RedbValue + ?Sized>(
+    // Returns the definition of the table, without checking its key and value types
+    pub(crate) fn get_table_untyped(
         &self,
         name: &str,
         table_type: TableType,
     ) -> Result<Option<InternalTableDefinition>> {
-        if let Some(mut definition) = self.tree.get(name)? {
+        if let Some(data) = self.tree.get_serialized(name.as_bytes())? {
+            let mut definition = InternalTableDefinition::try_from_bytes(&data)
+                .map_err(|err| table_corrupted(name, err))?;
             if definition.get_type() != table_type {
-                return Err(Error::TableTypeMismatch(format!(
-                    "{:?} is not of type {:?}",
-                    name, table_type
-                )));
+                return Err(Error::TableTypeMismatch {
+                    table: name.to_string(),
+                    expected: table_type.name().to_string(),
+                    actual: table_type_name(
+                        definition.get_type(),
+                        &definition.key_type,
+                        &definition.value_type,
+                    ),
+                });
+            }
+            if let Some(updated_root) = self.pending_table_updates.get(name) {
+                definition.table_root = *updated_root;
             }
+
+            Ok(Some(definition))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub(crate) fn get_table<K: RedbKey + ?Sized, V: RedbValue + ?Sized>(
+        &self,
+        name: &str,
+        table_type: TableType,
+    ) -> Result<Option<InternalTableDefinition>> {
+        if let Some(definition) = self.get_table_untyped(name, table_type)? {
             if definition.key_type != K::redb_type_name()
                 || definition.value_type != V::redb_type_name()
             {
-                return Err(Error::TableTypeMismatch(format!(
-                    "{} is of type Table<{}, {}> not Table<{}, {}>",
-                    name,
-                    &definition.key_type,
-                    &definition.value_type,
-                    K::redb_type_name(),
-                    V::redb_type_name()
-                )));
-            }
-
-            if let Some(updated_root) = self.pending_table_updates.get(name) {
-                definition.table_root = *updated_root;
+                return Err(Error::TableTypeMismatch {
+                    table: name.to_string(),
+                    expected: table_type_name(
+                        table_type,
+                        &K::redb_type_name(),
+                        &V::redb_type_name(),
+                    ),
+                    actual: table_type_name(
+                        table_type,
+                        &definition.key_type,
+                        &definition.value_type,
+                    ),
+                });
             }
 
             Ok(Some(definition))
@@ -393,14 +605,16 @@ impl<'txn> TableTree<'txn> {
                     K::fixed_width(),
                     V::fixed_width(),
                     self.mem,
-                );
+                )?;
                 let mut freed_pages = self.freed_pages.borrow_mut();
                 for page_number in iter {
-                    freed_pages.push(page_number);
+                    freed_pages.push(page_number?);
                 }
             }
 
             self.pending_table_updates.remove(name);
+            #[cfg(feature = "integrity_checks")]
+            self.pending_integrity_checks.remove(name);
 
             // Safety: References into the master table are never returned to the user
             let found = unsafe { self.tree.remove(name)?.is_some() };
@@ -410,12 +624,26 @@ impl<'txn> TableTree<'txn> {
         Ok(false)
     }
 
+    // Removes the definition of a table, without freeing its pages, which must not be allocated.
+    // Used to remove tables which were discarded when the database was repaired
+    pub(crate) fn discard_table(&mut self, name: &str) -> Result<bool> {
+        self.pending_table_updates.remove(name);
+        #[cfg(feature = "integrity_checks")]
+        self.pending_integrity_checks.remove(name);
+
+        // Safety: References into the master table are never returned to the user
+        let found = unsafe { self.tree.remove(name)?.is_some() };
+        Ok(found)
+    }
+
     // Returns a tuple of the table id and the new root page
     // root_page: the root of the master table
+    // counted: whether the table stores entry counts in its branches, if it is created
     pub(crate) fn get_or_create_table<K: RedbKey + ?Sized, V: RedbValue + ?Sized>(
         &mut self,
         name: &str,
         table_type: TableType,
+        counted: bool,
     ) -> Result<InternalTableDefinition> {
         if let Some(found) = self.get_table::<K, V>(name, table_type)? {
             return Ok(found);
@@ -424,6 +652,7 @@ impl<'txn> TableTree<'txn> {
         let table = InternalTableDefinition {
             table_root: None,
             table_type,
+            counted,
             fixed_key_size: K::fixed_width(),
             fixed_value_size: V::fixed_width(),
             key_type: K::redb_type_name(),
@@ -435,7 +664,7 @@ impl<'txn> TableTree<'txn> {
     }
 
     pub fn stats(&self) -> Result<DatabaseStats> {
-        let master_tree_stats = self.tree.stats();
+        let master_tree_stats = self.tree.stats()?;
         let mut max_subtree_height = 0;
         let mut total_stored_bytes = 0;
         // Count the master tree leaf pages as branches, since they point to the data trees
@@ -446,12 +675,12 @@ impl<'txn> TableTree<'txn> {
             master_tree_stats.metadata_bytes + master_tree_stats.stored_leaf_bytes;
         let mut total_fragmented = master_tree_stats.fragmented_bytes;
 
-        for entry in self.tree.range::<RangeFull, &str>(..)? {
-            let mut definition = InternalTableDefinition::from_bytes(entry.value());
-            if let Some(updated_root) = self
-                .pending_table_updates
-                .get(<&str>::from_bytes(entry.key()))
-            {
+        let mut iter = self.tree.range::<RangeFull, &str>(..)?;
+        while let Some(entry) = iter.try_next()? {
+            let name = parse_table_name(entry.key())?;
+            let mut definition = InternalTableDefinition::try_from_bytes(entry.value())
+                .map_err(|err| table_corrupted(name, err))?;
+            if let Some(updated_root) = self.pending_table_updates.get(name) {
                 definition.table_root = *updated_root;
             }
             let subtree_stats = btree_stats(
@@ -459,7 +688,7 @@ impl<'txn> TableTree<'txn> {
                 self.mem,
                 definition.fixed_key_size,
                 definition.fixed_value_size,
-            );
+            )?;
             max_subtree_height = max(max_subtree_height, subtree_stats.tree_height);
             total_stored_bytes += subtree_stats.stored_leaf_bytes;
             total_metadata_bytes += subtree_stats.metadata_bytes;
@@ -130,6 +130,50 @@ macro_rules! from_bytes_fixed_impl {
     };
 }
 
+macro_rules! try_from_bytes_variable_impl {
+    ( $data:expr $(,$t:ty, $v:ident, $i:literal )+ | $t_last:ty, $v_last:ident, $i_last:literal ) => {
+        #[allow(clippy::manual_bits)]
+        {
+            let mut offset = $i_last * size_of::<u32>();
+            if $data.len() < offset {
+                return None;
+            }
+            let lens: [usize; $i_last] = parse_lens($data);
+            $(
+                let end = offset.checked_add(lens[$i]).filter(|end| *end <= $data.len())?;
+                let $v = <$t>::try_from_bytes(&$data[offset..end])?;
+                offset = end;
+            )+
+            let $v_last = <$t_last>::try_from_bytes(&$data[offset..])?;
+            ($(
+                $v,
+            )+
+                $v_last
+            )
+        }
+    };
+}
+
+macro_rules! try_from_bytes_fixed_impl {
+    ( $data:expr $(,$t:ty, $v:ident )+ ) => {
+        {
+            let mut offset = 0;
+            $(
+                let len = <$t>::fixed_width().unwrap();
+                let $v = <$t>::try_from_bytes(&$data[offset..(offset + len)])?;
+                #[allow(unused_assignments)]
+                {
+                    offset += len;
+                }
+            )+
+
+            ($(
+                $v,
+            )+)
+        }
+    };
+}
+
 macro_rules! compare_variable_impl {
     ( $data0:expr, $data1:expr $(,$t:ty, $i:literal )+ | $t_last:ty, $i_last:literal ) => {
         #[allow(clippy::manual_bits)]
@@ -216,6 +260,20 @@ macro_rules! tuple_impl {
                 }
             }
 
+            fn try_from_bytes<'a>(data: &'a [u8]) -> Option<Self::SelfType<'a>>
+            where
+                Self: 'a,
+            {
+                if let Some(width) = Self::fixed_width() {
+                    if data.len() != width {
+                        return None;
+                    }
+                    Some(try_from_bytes_fixed_impl!(data $(,$t,$v)+, $t_last, $v_last))
+                } else {
+                    Some(try_from_bytes_variable_impl!(data $(,$t,$v,$i)+ | $t_last, $v_last, $i_last))
+                }
+            }
+
             fn as_bytes<'a, 'b: 'a>(value: &'a Self::RefBaseType<'b>) -> Vec<u8>
             where
                 Self: 'a,
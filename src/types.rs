@@ -1,7 +1,11 @@
+use crate::{Error, Result};
 use std::borrow::Borrow;
 use std::cmp::Ordering;
 use std::convert::TryInto;
-use std::fmt::Debug;
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::Arc;
 
 pub trait RedbValue: Debug {
     /// SelfType<'a> must be the same type as Self with all lifetimes replaced with 'a
@@ -31,6 +35,21 @@ pub trait RedbValue: Debug {
     where
         Self: 'a;
 
+    /// Deserializes data which may not have been returned by [`RedbValue::as_bytes`], such as
+    /// data read from a corrupted file, or imported from elsewhere. Returns `None` if it is not a
+    /// valid value of this type
+    ///
+    /// The default implementation only checks the width of fixed width types
+    fn try_from_bytes<'a>(data: &'a [u8]) -> Option<Self::SelfType<'a>>
+    where
+        Self: 'a,
+    {
+        match Self::fixed_width() {
+            Some(width) if data.len() != width => None,
+            _ => Some(Self::from_bytes(data)),
+        }
+    }
+
     /// Serialize the value to a slice
     fn as_bytes<'a, 'b: 'a>(value: &'a Self::RefBaseType<'b>) -> Self::AsBytes<'a>
     where
@@ -41,9 +60,54 @@ pub trait RedbValue: Debug {
     fn redb_type_name() -> String;
 }
 
+// Deserializes data which was read from the database, which is corrupted if it's not a valid value
+pub(crate) fn from_stored_bytes<'a, T: RedbValue + ?Sized + 'a>(
+    data: &'a [u8],
+) -> Result<T::SelfType<'a>> {
+    T::try_from_bytes(data).ok_or_else(|| {
+        Error::corrupted(format!(
+            "The database contains a {} which is not valid",
+            T::redb_type_name()
+        ))
+    })
+}
+
 pub trait RedbKey: RedbValue {
     /// Compare data1 with data2
     fn compare(data1: &[u8], data2: &[u8]) -> Ordering;
+
+    /// Returns a key `k`, with `lower <= k < upper`, which branch pages store to separate a page
+    /// whose last key is `lower` from one whose first key is `upper`
+    ///
+    /// `k` need not be a valid value of this type, but must be accepted by [`RedbKey::compare`].
+    /// Shorter separators fit more children in each branch page. The default returns `lower`
+    fn separator(lower: &[u8], upper: &[u8]) -> Vec<u8> {
+        let _ = upper;
+        lower.to_vec()
+    }
+}
+
+// Returns the shortest prefix of upper which is greater than lower, if it is shorter than lower.
+// The prefix is extended until boundary() accepts its length
+fn shortest_separator(lower: &[u8], upper: &[u8], boundary: impl Fn(usize) -> bool) -> Vec<u8> {
+    let common = lower.iter().zip(upper).take_while(|(x, y)| x == y).count();
+    let mut end = common + 1;
+    while end < upper.len() && !boundary(end) {
+        end += 1;
+    }
+    // A prefix equal to upper would not be less than it
+    if end < lower.len() && end < upper.len() {
+        upper[..end].to_vec()
+    } else {
+        lower.to_vec()
+    }
+}
+
+/// Keys which can be scanned by prefix, with [`crate::ReadableTable::range_prefix`]
+pub trait PrefixKey: RedbKey {
+    /// Returns the smallest serialized key which is greater than every key starting with `prefix`,
+    /// or `None` if there is no such key
+    fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>>;
 }
 
 impl RedbValue for () {
@@ -121,6 +185,122 @@ impl RedbKey for &[u8] {
     fn compare(data1: &[u8], data2: &[u8]) -> Ordering {
         data1.cmp(data2)
     }
+
+    fn separator(lower: &[u8], upper: &[u8]) -> Vec<u8> {
+        shortest_separator(lower, upper, |_| true)
+    }
+}
+
+impl PrefixKey for &[u8] {
+    fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+        // Trailing 0xFF bytes cannot be incremented, so drop them and increment the byte before
+        let last = prefix.iter().rposition(|x| *x != u8::MAX)?;
+        let mut successor = prefix[..=last].to_vec();
+        successor[last] += 1;
+        Some(successor)
+    }
+}
+
+/// An owned byte string, which can be cloned cheaply and shared between threads
+///
+/// Stored in the same format as `&[u8]`, so a table of `&[u8]` may also be opened with `Bytes`.
+/// Unlike `&[u8]`, values read from the table do not borrow from the transaction.
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bytes(Arc<[u8]>);
+
+impl Bytes {
+    pub fn new(data: &[u8]) -> Self {
+        Self(Arc::from(data))
+    }
+}
+
+impl Debug for Bytes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for Bytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Borrow<[u8]> for Bytes {
+    fn borrow(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<&[u8]> for Bytes {
+    fn from(data: &[u8]) -> Self {
+        Self::new(data)
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(data: Vec<u8>) -> Self {
+        Self(Arc::from(data))
+    }
+}
+
+impl RedbValue for Bytes {
+    type SelfType<'a> = Bytes
+    where
+        Self: 'a;
+    type RefBaseType<'a> = [u8]
+    where
+        Self: 'a;
+    type AsBytes<'a> = &'a [u8]
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Bytes
+    where
+        Self: 'a,
+    {
+        Bytes::new(data)
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::RefBaseType<'b>) -> &'a [u8]
+    where
+        Self: 'a,
+        Self: 'b,
+    {
+        value
+    }
+
+    fn redb_type_name() -> String {
+        <&[u8]>::redb_type_name()
+    }
+}
+
+impl RedbKey for Bytes {
+    fn compare(data1: &[u8], data2: &[u8]) -> Ordering {
+        data1.cmp(data2)
+    }
+
+    fn separator(lower: &[u8], upper: &[u8]) -> Vec<u8> {
+        <&[u8]>::separator(lower, upper)
+    }
+}
+
+impl PrefixKey for Bytes {
+    fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+        <&[u8]>::prefix_successor(prefix)
+    }
 }
 
 impl<const N: usize> RedbValue for &[u8; N] {
@@ -183,7 +363,21 @@ impl RedbValue for &str {
     where
         Self: 'a,
     {
-        std::str::from_utf8(data).unwrap()
+        // Strings are always stored as valid UTF-8, so this only fails if the file is corrupted.
+        // Values which are returned to the caller are read with try_from_bytes(), which rejects
+        // them. Elsewhere, such as when keys are compared, return the valid prefix rather than
+        // panicking
+        match std::str::from_utf8(data) {
+            Ok(value) => value,
+            Err(err) => std::str::from_utf8(&data[..err.valid_up_to()]).unwrap(),
+        }
+    }
+
+    fn try_from_bytes<'a>(data: &'a [u8]) -> Option<&'a str>
+    where
+        Self: 'a,
+    {
+        std::str::from_utf8(data).ok()
     }
 
     fn as_bytes<'a, 'b: 'a>(value: &'a Self::RefBaseType<'b>) -> &'a str
@@ -205,6 +399,105 @@ impl RedbKey for &str {
         let str2 = Self::from_bytes(data2);
         str1.cmp(str2)
     }
+
+    fn separator(lower: &[u8], upper: &[u8]) -> Vec<u8> {
+        // Strings compare in the same order as their UTF-8 encodings, but the separator must
+        // itself be valid UTF-8, so it can only end on a character boundary
+        let upper_str = Self::from_bytes(upper);
+        shortest_separator(lower, upper, |end| upper_str.is_char_boundary(end))
+    }
+}
+
+impl PrefixKey for &str {
+    fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+        // Strings are ordered by code point, so increment the last character which is not
+        // char::MAX. The successor must be valid UTF-8, so surrogates are skipped
+        let mut successor = Self::from_bytes(prefix).to_string();
+        while let Some(c) = successor.pop() {
+            let next = match c {
+                '\u{D7FF}' => Some('\u{E000}'),
+                c => char::from_u32(u32::from(c) + 1),
+            };
+            if let Some(next) = next {
+                successor.push(next);
+                return Some(successor.into_bytes());
+            }
+        }
+        None
+    }
+}
+
+/// Defines a sort order for keys of type `K`, which overrides [`RedbKey::compare`]
+///
+/// Use with [`OrderedKey`] to store keys of type `K` in a table sorted by this order
+pub trait KeyOrder<K: RedbKey + ?Sized>: 'static {
+    /// Compare data1 with data2, which are both serialized values of `K`
+    fn compare(data1: &[u8], data2: &[u8]) -> Ordering;
+
+    /// Globally unique identifier for this order
+    fn name() -> String;
+}
+
+/// Key type which is stored identically to `K`, but sorted according to `O`
+///
+/// The order is part of the table's type, so a table must always be opened with the same order
+pub struct OrderedKey<K: RedbKey + ?Sized, O: KeyOrder<K>> {
+    _key_type: PhantomData<K>,
+    _order: PhantomData<O>,
+}
+
+impl<K: RedbKey + ?Sized, O: KeyOrder<K>> Debug for OrderedKey<K, O> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "OrderedKey<{}>", Self::redb_type_name())
+    }
+}
+
+impl<K: RedbKey + ?Sized, O: KeyOrder<K>> RedbValue for OrderedKey<K, O> {
+    type SelfType<'a> = K::SelfType<'a>
+    where
+        Self: 'a;
+    type RefBaseType<'a> = K::RefBaseType<'a>
+    where
+        Self: 'a;
+    type AsBytes<'a> = K::AsBytes<'a>
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        K::fixed_width()
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
+    where
+        Self: 'a,
+    {
+        K::from_bytes(data)
+    }
+
+    fn try_from_bytes<'a>(data: &'a [u8]) -> Option<Self::SelfType<'a>>
+    where
+        Self: 'a,
+    {
+        K::try_from_bytes(data)
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::RefBaseType<'b>) -> Self::AsBytes<'a>
+    where
+        Self: 'a,
+        Self: 'b,
+    {
+        K::as_bytes(value)
+    }
+
+    fn redb_type_name() -> String {
+        format!("{}[{}]", K::redb_type_name(), O::name())
+    }
+}
+
+impl<K: RedbKey + ?Sized, O: KeyOrder<K>> RedbKey for OrderedKey<K, O> {
+    fn compare(data1: &[u8], data2: &[u8]) -> Ordering {
+        O::compare(data1, data2)
+    }
 }
 
 macro_rules! be_value {
@@ -242,10 +535,36 @@ macro_rules! be_value {
     };
 }
 
+/// Integer value types, which can be used with [`crate::Table::increment`]
+pub trait IntegerValue: RedbValue + Copy + Default + 'static {
+    /// Deserializes a value, as stored by [`RedbValue::as_bytes`]
+    fn from_stored_bytes(data: &[u8]) -> Self;
+
+    /// Serializes the value, in the same format as [`RedbValue::as_bytes`]
+    fn to_stored_bytes(self) -> Vec<u8>;
+
+    /// Returns `self + delta`, or `None` if the result overflows
+    fn checked_add(self, delta: Self) -> Option<Self>;
+}
+
 macro_rules! be_impl {
     ($t:ty) => {
         be_value!($t);
 
+        impl IntegerValue for $t {
+            fn from_stored_bytes(data: &[u8]) -> Self {
+                Self::from_bytes(data)
+            }
+
+            fn to_stored_bytes(self) -> Vec<u8> {
+                Self::as_bytes(&self).to_vec()
+            }
+
+            fn checked_add(self, delta: Self) -> Option<Self> {
+                <$t>::checked_add(self, delta)
+            }
+        }
+
         impl RedbKey for $t {
             fn compare(data1: &[u8], data2: &[u8]) -> Ordering {
                 Self::from_bytes(data1).cmp(&Self::from_bytes(data2))
@@ -266,3 +585,34 @@ be_impl!(i64);
 be_impl!(i128);
 be_value!(f32);
 be_value!(f64);
+
+pub(crate) type KeyCompare = fn(&[u8], &[u8]) -> Ordering;
+
+// Returns the comparison function of the built-in key type with the given name, so that the keys
+// of a table can be compared without knowing its type
+pub(crate) fn builtin_key_compare(type_name: &str) -> Option<KeyCompare> {
+    macro_rules! lookup {
+        ($($t:ty),+) => {
+            $(
+                if type_name == <$t>::redb_type_name() {
+                    return Some(<$t>::compare);
+                }
+            )+
+        };
+    }
+    lookup!(
+        u8,
+        u16,
+        u32,
+        u64,
+        u128,
+        i8,
+        i16,
+        i32,
+        i64,
+        i128,
+        &str,
+        &[u8]
+    );
+    None
+}
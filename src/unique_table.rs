@@ -0,0 +1,138 @@
+use crate::table::{ReadableTable, Table};
+use crate::types::RedbKey;
+use crate::{AccessGuard, Error, Result};
+use std::borrow::Borrow;
+
+/// A table whose values are unique, maintained together with an index table which maps each
+/// value back to its key
+///
+/// Inserting a value which is already stored under a different key fails with
+/// [`Error::UniqueConstraintViolation`], and leaves both tables unchanged. Values and keys are
+/// compared in their serialized form.
+///
+/// The index must only be modified through this type, and must be empty when the table is.
+///
+/// # Examples
+///
+/// ```rust
+/// use redb::*;
+/// # use tempfile::NamedTempFile;
+/// const USERS: TableDefinition<u64, &str> = TableDefinition::new("users");
+/// const USERS_BY_EMAIL: TableDefinition<&str, u64> = TableDefinition::new("users_by_email");
+///
+/// # fn main() -> Result<(), Error> {
+/// # let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+/// # let filename = tmpfile.path();
+/// let db = Database::create(filename)?;
+/// let write_txn = db.begin_write()?;
+/// {
+///     let mut users = UniqueIndexedTable::new(
+///         write_txn.open_table(USERS)?,
+///         write_txn.open_table(USERS_BY_EMAIL)?,
+///     );
+///     users.insert(&1, "alice@example.com")?;
+///     assert!(matches!(
+///         users.insert(&2, "alice@example.com"),
+///         Err(Error::UniqueConstraintViolation(_))
+///     ));
+///     assert_eq!(users.key_for("alice@example.com")?, Some(1));
+/// }
+/// write_txn.commit()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct UniqueIndexedTable<'db, 'txn, K: RedbKey + ?Sized + 'txn, V: RedbKey + ?Sized + 'txn> {
+    table: Table<'db, 'txn, K, V>,
+    index: Table<'db, 'txn, V, K>,
+}
+
+impl<'db, 'txn, K: RedbKey + ?Sized + 'txn, V: RedbKey + ?Sized + 'txn>
+    UniqueIndexedTable<'db, 'txn, K, V>
+{
+    pub fn new(table: Table<'db, 'txn, K, V>, index: Table<'db, 'txn, V, K>) -> Self {
+        Self { table, index }
+    }
+
+    /// Returns the underlying table and index
+    pub fn into_inner(self) -> (Table<'db, 'txn, K, V>, Table<'db, 'txn, V, K>) {
+        (self.table, self.index)
+    }
+
+    /// Insert mapping of the given key to the given value, and index it
+    ///
+    /// Returns the old value, if the key was present in the table, or
+    /// [`Error::UniqueConstraintViolation`] if the value is already stored under another key
+    pub fn insert<'a, 'b: 'a, AK, AV>(
+        &mut self,
+        key: &'a AK,
+        value: &'a AV,
+    ) -> Result<Option<AccessGuard<'_, V>>>
+    where
+        K: 'b,
+        V: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+        AV: Borrow<V::RefBaseType<'b>> + ?Sized,
+    {
+        let key_bytes = K::as_bytes(key.borrow());
+        let value_bytes = V::as_bytes(value.borrow());
+        if let Some(owner) = self.index.get_serialized(value_bytes.as_ref())? {
+            if owner != key_bytes.as_ref() {
+                return Err(Error::UniqueConstraintViolation(
+                    self.index.name().to_string(),
+                ));
+            }
+        }
+        let old = self.table.insert(key, value)?;
+        if let Some(ref old) = old {
+            let old_bytes = old.to_bytes();
+            if old_bytes.as_ref() != value_bytes.as_ref() {
+                self.index.remove_serialized(&old_bytes)?;
+            }
+        }
+        self.index.insert(value, key)?;
+        Ok(old)
+    }
+
+    /// Removes the given key, and its value from the index
+    ///
+    /// Returns the old value, if the key was present in the table
+    pub fn remove<'a, 'b: 'a, AK>(&mut self, key: &'a AK) -> Result<Option<AccessGuard<'_, V>>>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        let old = self.table.remove(key)?;
+        if let Some(ref old) = old {
+            self.index.remove_serialized(&old.to_bytes())?;
+        }
+        Ok(old)
+    }
+
+    /// Returns the value corresponding to the given key
+    pub fn get<'a, 'b: 'a, AK>(&self, key: &'a AK) -> Result<Option<V::SelfType<'_>>>
+    where
+        K: 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        self.table.get(key)
+    }
+
+    /// Returns the key under which the given value is stored
+    pub fn key_for<'a, 'b: 'a, AV>(&self, value: &'a AV) -> Result<Option<K::SelfType<'_>>>
+    where
+        V: 'b,
+        AV: Borrow<V::RefBaseType<'b>> + ?Sized,
+    {
+        self.index.get(value)
+    }
+
+    /// Returns the number of entries in the table
+    pub fn len(&self) -> Result<usize> {
+        self.table.len()
+    }
+
+    /// Returns `true` if the table is empty
+    pub fn is_empty(&self) -> Result<bool> {
+        self.table.is_empty()
+    }
+}
@@ -0,0 +1,152 @@
+use crate::types::{RedbKey, RedbValue};
+use crate::{Result, TableDefinition, WriteTransaction};
+use std::borrow::Borrow;
+
+// Applies the serialized operations to the table with the given name. A value of None removes the key
+type ApplyFn = fn(&WriteTransaction, &str, Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Result;
+
+struct TableOperations {
+    name: String,
+    key_type: String,
+    value_type: String,
+    operations: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    apply: ApplyFn,
+}
+
+/// A set of insertions and removals, across any number of tables, which is applied atomically by
+/// [`Database::apply`](crate::Database::apply) or [`WriteTransaction::apply_batch`]
+///
+/// Keys and values are serialized as they are added, so a batch can be built without holding the
+/// write lock, and then applied in a short write transaction. Operations on each table are
+/// applied in the order they were added
+///
+/// # Examples
+///
+/// ```rust
+/// use redb::*;
+/// # use tempfile::NamedTempFile;
+/// const USERS: TableDefinition<u64, &str> = TableDefinition::new("users");
+/// const EMAILS: TableDefinition<&str, u64> = TableDefinition::new("emails");
+///
+/// # fn main() -> Result<(), Error> {
+/// # let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+/// # let filename = tmpfile.path();
+/// let db = Database::create(filename)?;
+/// let mut batch = WriteBatch::new();
+/// batch.insert(USERS, &1, "alice");
+/// batch.insert(EMAILS, "alice@example.com", &1);
+/// db.apply(batch)?;
+///
+/// let read_txn = db.begin_read()?;
+/// let users = read_txn.open_table(USERS)?;
+/// assert_eq!(users.get(&1)?, Some("alice"));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct WriteBatch {
+    tables: Vec<TableOperations>,
+    len: usize,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Record an insertion of the given key and value into the table
+    pub fn insert<'a, 'b: 'a, K, V, AK, AV>(
+        &mut self,
+        definition: TableDefinition<K, V>,
+        key: &'a AK,
+        value: &'a AV,
+    ) -> &mut Self
+    where
+        K: RedbKey + ?Sized + 'b,
+        V: RedbValue + ?Sized + 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+        AV: Borrow<V::RefBaseType<'b>> + ?Sized,
+    {
+        let key = K::as_bytes(key.borrow()).as_ref().to_vec();
+        let value = V::as_bytes(value.borrow()).as_ref().to_vec();
+        self.table_operations(definition).push((key, Some(value)));
+        self.len += 1;
+        self
+    }
+
+    /// Record a removal of the given key from the table
+    pub fn remove<'a, 'b: 'a, K, V, AK>(
+        &mut self,
+        definition: TableDefinition<K, V>,
+        key: &'a AK,
+    ) -> &mut Self
+    where
+        K: RedbKey + ?Sized + 'b,
+        V: RedbValue + ?Sized + 'b,
+        AK: Borrow<K::RefBaseType<'b>> + ?Sized,
+    {
+        let key = K::as_bytes(key.borrow()).as_ref().to_vec();
+        self.table_operations(definition).push((key, None));
+        self.len += 1;
+        self
+    }
+
+    /// Returns the number of operations in the batch
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the batch has no operations
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn table_operations<K: RedbKey + ?Sized, V: RedbValue + ?Sized>(
+        &mut self,
+        definition: TableDefinition<K, V>,
+    ) -> &mut Vec<(Vec<u8>, Option<Vec<u8>>)> {
+        let key_type = K::redb_type_name();
+        let value_type = V::redb_type_name();
+        let index = if let Some(index) = self.tables.iter().position(|table| {
+            table.name == definition.name()
+                && table.key_type == key_type
+                && table.value_type == value_type
+        }) {
+            index
+        } else {
+            self.tables.push(TableOperations {
+                name: definition.name().to_string(),
+                key_type,
+                value_type,
+                operations: vec![],
+                apply: apply_operations::<K, V>,
+            });
+            self.tables.len() - 1
+        };
+        &mut self.tables[index].operations
+    }
+
+    pub(crate) fn apply(self, txn: &WriteTransaction) -> Result {
+        for table in self.tables {
+            (table.apply)(txn, &table.name, table.operations)?;
+        }
+        Ok(())
+    }
+}
+
+fn apply_operations<K: RedbKey + ?Sized, V: RedbValue + ?Sized>(
+    txn: &WriteTransaction,
+    name: &str,
+    operations: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+) -> Result {
+    let mut table = txn.open_table(TableDefinition::<K, V>::new(name))?;
+    for (key, value) in operations.iter() {
+        let key = K::from_bytes(key);
+        if let Some(value) = value {
+            table.insert(key.borrow(), V::from_bytes(value).borrow())?;
+        } else {
+            table.remove(key.borrow())?;
+        }
+    }
+    Ok(())
+}
@@ -1,4 +1,10 @@
-use redb::{Database, MultimapTableDefinition, RangeIter, ReadableTable, TableDefinition};
+use redb::{
+    BlobReader, BlobTable, Bytes, DataFormat, Database, Error, ExpiringTable, KeyOrder, LogTable,
+    MultimapTableDefinition, OrderedKey, PageInfo, RangeIter, ReadableTable, RedbValue, SetTable,
+    TableDefinition, TableSchema, TimeSeriesTable, TreeDumpFormat, UniqueIndexedTable,
+};
+use std::cmp::Ordering;
+use std::io::{Read, Write};
 use std::sync;
 use tempfile::NamedTempFile;
 
@@ -8,7 +14,7 @@ const U64_TABLE: TableDefinition<u64, u64> = TableDefinition::new("u64");
 #[test]
 fn len() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
     {
         let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
@@ -26,7 +32,7 @@ fn len() {
 #[test]
 fn stored_size() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
     {
         let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
@@ -44,7 +50,7 @@ fn stored_size() {
 #[test]
 fn create_open() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
     {
         let mut table = write_txn.open_table(U64_TABLE).unwrap();
@@ -53,7 +59,7 @@ fn create_open() {
     write_txn.commit().unwrap();
     drop(db);
 
-    let db2 = unsafe { Database::open(tmpfile.path()).unwrap() };
+    let db2 = Database::open(tmpfile.path()).unwrap();
 
     let read_txn = db2.begin_read().unwrap();
     let table = read_txn.open_table(U64_TABLE).unwrap();
@@ -66,7 +72,7 @@ fn multiple_tables() {
     let definition2: TableDefinition<&[u8], &[u8]> = TableDefinition::new("2");
 
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
     {
         let mut table = write_txn.open_table(definition1).unwrap();
@@ -89,7 +95,7 @@ fn multiple_tables() {
 #[test]
 fn list_tables() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let definition_x: TableDefinition<&[u8], &[u8]> = TableDefinition::new("x");
     let definition_y: TableDefinition<&[u8], &[u8]> = TableDefinition::new("y");
@@ -117,6 +123,364 @@ fn list_tables() {
     assert_eq!(multimap_tables, &["mx", "my"]);
 }
 
+#[test]
+fn list_table_schemas() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    let definition_x: TableDefinition<u64, &str> = TableDefinition::new("x");
+    let definition_mx: MultimapTableDefinition<&str, (u8, u16)> =
+        MultimapTableDefinition::new("mx");
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        write_txn.open_table(definition_x).unwrap();
+        write_txn.open_multimap_table(definition_mx).unwrap();
+    }
+    let schemas: Vec<TableSchema> = write_txn.list_table_schemas().unwrap().collect();
+    assert_eq!(schemas.len(), 2);
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let schemas: Vec<TableSchema> = read_txn.list_table_schemas().unwrap().collect();
+    assert_eq!(schemas.len(), 2);
+    assert_eq!(schemas[0].name(), "mx");
+    assert!(schemas[0].is_multimap());
+    assert_eq!(schemas[0].key_type(), <&str>::redb_type_name());
+    assert_eq!(schemas[0].value_type(), <(u8, u16)>::redb_type_name());
+    assert_eq!(schemas[1].name(), "x");
+    assert!(!schemas[1].is_multimap());
+    assert_eq!(schemas[1].key_type(), "u64");
+    assert_eq!(schemas[1].value_type(), <&str>::redb_type_name());
+}
+
+#[test]
+fn table_schema() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    let old_definition: TableDefinition<u64, u32> = TableDefinition::new("x");
+    let new_definition: TableDefinition<u64, u64> = TableDefinition::new("x");
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        write_txn.open_table(old_definition).unwrap();
+    }
+    assert!(write_txn.table_schema("y").unwrap().is_none());
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    assert!(matches!(
+        read_txn.open_table(new_definition),
+        Err(Error::TableTypeMismatch { .. })
+    ));
+    let schema = read_txn.table_schema("x").unwrap().unwrap();
+    assert_eq!(schema.name(), "x");
+    assert_eq!(schema.key_type(), "u64");
+    assert_eq!(schema.value_type(), "u32");
+    assert!(read_txn.table_schema("y").unwrap().is_none());
+}
+
+#[test]
+fn export_table() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    let names: TableDefinition<i32, &str> = TableDefinition::new("names");
+    let scores: TableDefinition<&str, f64> = TableDefinition::new("scores");
+    let multimap: MultimapTableDefinition<u64, u64> = MultimapTableDefinition::new("multimap");
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(names).unwrap();
+        table.insert(&-1, "minus \"one\"").unwrap();
+        table.insert(&2, "two, three").unwrap();
+        let mut table = write_txn.open_table(scores).unwrap();
+        table.insert("a", &0.5).unwrap();
+        table.insert("b", &f64::NAN).unwrap();
+        let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
+        table.insert(b"hi", &[0xAB, 0x01]).unwrap();
+        write_txn.open_multimap_table(multimap).unwrap();
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let mut output = vec![];
+    let entries = read_txn
+        .export_table("names", DataFormat::JsonLines, &mut output)
+        .unwrap();
+    assert_eq!(entries, 2);
+    assert_eq!(
+        std::str::from_utf8(&output).unwrap(),
+        "{\"key\":-1,\"value\":\"minus \\\"one\\\"\"}\n{\"key\":2,\"value\":\"two, three\"}\n"
+    );
+
+    let mut output = vec![];
+    read_txn
+        .export_table("names", DataFormat::Csv, &mut output)
+        .unwrap();
+    assert_eq!(
+        std::str::from_utf8(&output).unwrap(),
+        "key,value\n-1,\"minus \"\"one\"\"\"\n2,\"two, three\"\n"
+    );
+
+    let mut output = vec![];
+    read_txn
+        .export_table("scores", DataFormat::JsonLines, &mut output)
+        .unwrap();
+    assert_eq!(
+        std::str::from_utf8(&output).unwrap(),
+        "{\"key\":\"a\",\"value\":0.5}\n{\"key\":\"b\",\"value\":null}\n"
+    );
+
+    let mut output = vec![];
+    read_txn
+        .export_table("x", DataFormat::Csv, &mut output)
+        .unwrap();
+    assert_eq!(
+        std::str::from_utf8(&output).unwrap(),
+        "key,value\n6869,ab01\n"
+    );
+
+    assert!(matches!(
+        read_txn.export_table("missing", DataFormat::Csv, &mut vec![]),
+        Err(Error::TableDoesNotExist(_))
+    ));
+    assert!(matches!(
+        read_txn.export_table("multimap", DataFormat::Csv, &mut vec![]),
+        Err(Error::TableTypeMismatch { .. })
+    ));
+}
+
+#[test]
+fn import_table() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    let names: TableDefinition<i8, &str> = TableDefinition::new("names");
+    let scores: TableDefinition<&str, f32> = TableDefinition::new("scores");
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(names).unwrap();
+        let json = "{\"key\": -1, \"value\": \"minus \\\"one\\\" \\u00e9\"}\n\n{\"value\":\"two\",\"key\":2}\n";
+        assert_eq!(
+            table
+                .import(DataFormat::JsonLines, json.as_bytes())
+                .unwrap(),
+            2
+        );
+        let csv = "key,value\r\n3,\"three,\nlines\"\r\n4,four\r\n";
+        assert_eq!(table.import(DataFormat::Csv, csv.as_bytes()).unwrap(), 2);
+
+        // Nothing is inserted if any entry is invalid
+        for (format, input) in [
+            (
+                DataFormat::JsonLines,
+                "{\"key\":5,\"value\":\"five\"}\n{\"key\":128,\"value\":\"\"}",
+            ),
+            (DataFormat::JsonLines, "{\"key\":5,\"value\":5}"),
+            (DataFormat::JsonLines, "{\"key\":5}"),
+            (DataFormat::Csv, "5,five\n"),
+            (DataFormat::Csv, "key,value\n5,five\nsix,six\n"),
+            (DataFormat::Csv, "key,value\n5,five,extra\n"),
+            (DataFormat::Csv, "key,value\n5,\"five\n"),
+        ] {
+            assert!(matches!(
+                table.import(format, input.as_bytes()),
+                Err(Error::Io { source: err, .. }) if err.kind() == std::io::ErrorKind::InvalidData
+            ));
+        }
+        assert_eq!(table.len().unwrap(), 4);
+        assert_eq!(table.get(&-1).unwrap().unwrap(), "minus \"one\" \u{e9}");
+        assert_eq!(table.get(&3).unwrap().unwrap(), "three,\nlines");
+        assert!(table.get(&5).unwrap().is_none());
+
+        let mut table = write_txn.open_table(scores).unwrap();
+        table.insert("a", &0.25).unwrap();
+        table.insert("b", &-1.5e10).unwrap();
+    }
+    write_txn.commit().unwrap();
+
+    // Exported tables can be imported into another database
+    let read_txn = db.begin_read().unwrap();
+    let tmpfile2: NamedTempFile = NamedTempFile::new().unwrap();
+    let db2 = Database::create(tmpfile2.path()).unwrap();
+    let write_txn = db2.begin_write().unwrap();
+    {
+        let mut exported = vec![];
+        read_txn
+            .export_table("names", DataFormat::JsonLines, &mut exported)
+            .unwrap();
+        let mut table = write_txn.open_table(names).unwrap();
+        assert_eq!(
+            table
+                .import(DataFormat::JsonLines, exported.as_slice())
+                .unwrap(),
+            4
+        );
+        assert_eq!(table.get(&3).unwrap().unwrap(), "three,\nlines");
+
+        let mut exported = vec![];
+        read_txn
+            .export_table("scores", DataFormat::Csv, &mut exported)
+            .unwrap();
+        let mut table = write_txn.open_table(scores).unwrap();
+        assert_eq!(
+            table.import(DataFormat::Csv, exported.as_slice()).unwrap(),
+            2
+        );
+        assert_eq!(table.get("b").unwrap().unwrap(), -1.5e10);
+    }
+    write_txn.commit().unwrap();
+}
+
+#[test]
+fn dump_tree() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        let mut dump = vec![];
+        table.dump_tree(TreeDumpFormat::Json, &mut dump).unwrap();
+        assert_eq!(String::from_utf8(dump).unwrap(), "null\n");
+        let mut dump = vec![];
+        table.dump_tree(TreeDumpFormat::Dot, &mut dump).unwrap();
+        assert_eq!(
+            String::from_utf8(dump).unwrap(),
+            "digraph btree {\n    node [shape=box];\n}\n"
+        );
+
+        table.insert(&1, &1).unwrap();
+        let mut dump = vec![];
+        table.dump_tree(TreeDumpFormat::Json, &mut dump).unwrap();
+        let dump = String::from_utf8(dump).unwrap();
+        assert!(dump.starts_with("{\"page\":"));
+        assert!(dump.ends_with(
+            "\"type\":\"leaf\",\"depth\":0,\"entries\":1,\"first_key\":\"1\",\"last_key\":\"1\"}\n"
+        ));
+
+        for i in 0..1000 {
+            table.insert(&i, &i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    let mut dump = vec![];
+    table.dump_tree(TreeDumpFormat::Json, &mut dump).unwrap();
+    let dump = String::from_utf8(dump).unwrap();
+    assert!(dump.contains("\"type\":\"branch\",\"depth\":0,\"children\":["));
+    assert!(dump.contains("\"type\":\"leaf\",\"depth\":1,"));
+    assert!(dump.ends_with("],\"first_key\":\"0\",\"last_key\":\"999\"}\n"));
+
+    let mut dump = vec![];
+    table.dump_tree(TreeDumpFormat::Dot, &mut dump).unwrap();
+    let dump = String::from_utf8(dump).unwrap();
+    assert!(dump.starts_with("digraph btree {\n"));
+    assert!(dump.contains("[label=\"branch "));
+    assert!(dump.contains("keys: 0 ..= 999\"];\n"));
+    assert!(dump.contains("\" -> \""));
+    assert!(dump.ends_with("}\n"));
+}
+
+#[test]
+fn tree_pages() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        assert_eq!(table.pages().unwrap().count(), 0);
+        for i in 0..1000 {
+            table.insert(&i, &i).unwrap();
+        }
+        assert!(table.pages().unwrap().count() > 1);
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    let pages: Vec<PageInfo> = table.pages().unwrap().collect::<Result<_, _>>().unwrap();
+    let root = &pages[0];
+    assert_eq!(root.level(), 0);
+    assert!(!root.is_leaf());
+    assert_eq!(root.entries(), pages.len() - 1);
+    let mut page_numbers = vec![];
+    let mut entries = 0;
+    for page in &pages[1..] {
+        assert_eq!(page.level(), 1);
+        assert!(page.is_leaf());
+        assert!(page.used_bytes() <= page.page_size());
+        page_numbers.push(page.page_number());
+        entries += page.entries();
+    }
+    assert_eq!(entries, 1000);
+    page_numbers.push(root.page_number());
+    page_numbers.sort_unstable();
+    page_numbers.dedup();
+    assert_eq!(page_numbers.len(), pages.len());
+}
+
+#[test]
+fn migrate_table() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    let old_definition: TableDefinition<u64, u32> = TableDefinition::new("x");
+    let new_definition: TableDefinition<u64, &str> = TableDefinition::new("x");
+    let renamed_definition: TableDefinition<u64, &str> = TableDefinition::new("y");
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(old_definition).unwrap();
+        for i in 0..1_000u32 {
+            table.insert(&u64::from(i), &(i * 2)).unwrap();
+        }
+    }
+    assert!(matches!(
+        write_txn.migrate_table(renamed_definition, renamed_definition, |value| {
+            value.to_string()
+        }),
+        Err(Error::TableDoesNotExist(_))
+    ));
+    // Keys are not converted, so the key types must match
+    let u32_keys: TableDefinition<u32, &str> = TableDefinition::new("x");
+    assert!(matches!(
+        write_txn.migrate_table(old_definition, u32_keys, |value| value.to_string()),
+        Err(Error::TableTypeMismatch { .. })
+    ));
+    write_txn
+        .migrate_table(old_definition, new_definition, |value| value.to_string())
+        .unwrap();
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let schema = read_txn.table_schema("x").unwrap().unwrap();
+    assert_eq!(schema.value_type(), "str");
+    let table = read_txn.open_table(new_definition).unwrap();
+    assert_eq!(table.len().unwrap(), 1_000);
+    assert_eq!(table.get(&7).unwrap().unwrap(), "14");
+    drop(table);
+    drop(read_txn);
+
+    // Migrate to a new name
+    let write_txn = db.begin_write().unwrap();
+    write_txn.open_table(U64_TABLE).unwrap();
+    assert!(matches!(
+        write_txn.migrate_table(new_definition, U64_TABLE, |_| 0u64),
+        Err(Error::TableTypeMismatch { .. })
+    ));
+    write_txn
+        .migrate_table(new_definition, renamed_definition, |value| {
+            format!("{}!", value)
+        })
+        .unwrap();
+    assert!(write_txn.table_schema("x").unwrap().is_none());
+    let table = write_txn.open_table(renamed_definition).unwrap();
+    assert_eq!(table.get(&7).unwrap().unwrap(), "14!");
+}
+
 #[test]
 // Test that these signatures compile
 fn tuple_type_function_lifetime() {
@@ -138,7 +502,7 @@ fn tuple_type_function_lifetime() {
 #[test]
 fn tuple_type_lifetime() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let table_def: TableDefinition<(&str, u8), (u16, u32)> = TableDefinition::new("table");
 
@@ -159,7 +523,7 @@ fn tuple_type_lifetime() {
 #[test]
 fn tuple2_type() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let table_def: TableDefinition<(&str, u8), (u16, u32)> = TableDefinition::new("table");
 
@@ -178,7 +542,7 @@ fn tuple2_type() {
 #[test]
 fn tuple3_type() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let table_def: TableDefinition<(&str, u8, u16), (u16, u32)> = TableDefinition::new("table");
 
@@ -197,7 +561,7 @@ fn tuple3_type() {
 #[test]
 fn tuple4_type() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let table_def: TableDefinition<(&str, u8, u16, u32), (u16, u32)> =
         TableDefinition::new("table");
@@ -218,7 +582,7 @@ fn tuple4_type() {
 #[allow(clippy::type_complexity)]
 fn tuple5_type() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let table_def: TableDefinition<(&str, u8, u16, u32, u64), (u16, u32)> =
         TableDefinition::new("table");
@@ -242,7 +606,7 @@ fn tuple5_type() {
 #[allow(clippy::type_complexity)]
 fn tuple6_type() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let table_def: TableDefinition<(&str, u8, u16, u32, u64, u128), (u16, u32)> =
         TableDefinition::new("table");
@@ -266,7 +630,7 @@ fn tuple6_type() {
 #[allow(clippy::type_complexity)]
 fn tuple7_type() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let table_def: TableDefinition<(&str, u8, u16, u32, u64, u128, i8), (u16, u32)> =
         TableDefinition::new("table");
@@ -292,7 +656,7 @@ fn tuple7_type() {
 #[allow(clippy::type_complexity)]
 fn tuple8_type() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let table_def: TableDefinition<(&str, u8, u16, u32, u64, u128, i8, i16), (u16, u32)> =
         TableDefinition::new("table");
@@ -321,7 +685,7 @@ fn tuple8_type() {
 #[allow(clippy::type_complexity)]
 fn tuple9_type() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let table_def: TableDefinition<(&str, u8, u16, u32, u64, u128, i8, i16, i32), (u16, u32)> =
         TableDefinition::new("table");
@@ -350,7 +714,7 @@ fn tuple9_type() {
 #[allow(clippy::type_complexity)]
 fn tuple10_type() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let table_def: TableDefinition<(&str, u8, u16, u32, u64, u128, i8, i16, i32, i64), (u16, u32)> =
         TableDefinition::new("table");
@@ -379,7 +743,7 @@ fn tuple10_type() {
 #[allow(clippy::type_complexity)]
 fn tuple11_type() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let table_def: TableDefinition<
         (&str, u8, u16, u32, u64, u128, i8, i16, i32, i64, i128),
@@ -410,7 +774,7 @@ fn tuple11_type() {
 #[allow(clippy::type_complexity)]
 fn tuple12_type() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let table_def: TableDefinition<
         (&str, u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, &str),
@@ -443,7 +807,7 @@ fn tuple12_type() {
 #[test]
 fn is_empty() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let write_txn = db.begin_write().unwrap();
     {
@@ -460,7 +824,7 @@ fn is_empty() {
 #[test]
 fn abort() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let write_txn = db.begin_write().unwrap();
     {
@@ -490,7 +854,7 @@ fn abort() {
 #[test]
 fn insert_overwrite() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
     {
         let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
@@ -518,7 +882,7 @@ fn insert_overwrite() {
 #[test]
 fn insert_reserve() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let value = b"world";
     let write_txn = db.begin_write().unwrap();
     {
@@ -536,7 +900,7 @@ fn insert_reserve() {
 #[test]
 fn delete() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
     {
         let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
@@ -570,7 +934,7 @@ fn delete() {
 #[test]
 fn no_dirty_reads() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
     {
         let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
@@ -590,7 +954,7 @@ fn no_dirty_reads() {
 #[test]
 fn read_isolation() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
     {
         let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
@@ -627,7 +991,7 @@ fn read_isolation() {
 #[test]
 fn read_isolation2() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
     {
         let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
@@ -663,7 +1027,7 @@ fn read_isolation2() {
 #[test]
 fn reopen_table() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
     {
         let mut table = write_txn.open_table(U64_TABLE).unwrap();
@@ -679,7 +1043,7 @@ fn reopen_table() {
 #[test]
 fn u64_type() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
     {
         let mut table = write_txn.open_table(U64_TABLE).unwrap();
@@ -697,7 +1061,7 @@ fn u64_type() {
 #[test]
 fn i128_type() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
 
     let definition: TableDefinition<i128, i128> = TableDefinition::new("x");
@@ -723,7 +1087,7 @@ fn i128_type() {
 #[test]
 fn f32_type() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let definition: TableDefinition<u8, f32> = TableDefinition::new("x");
 
@@ -742,7 +1106,7 @@ fn f32_type() {
 #[test]
 fn str_type() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let definition: TableDefinition<&str, &str> = TableDefinition::new("x");
 
@@ -769,7 +1133,7 @@ fn str_type() {
 #[test]
 fn empty_type() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let definition: TableDefinition<u8, ()> = TableDefinition::new("x");
 
@@ -788,7 +1152,7 @@ fn empty_type() {
 #[test]
 fn array_type() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let definition: TableDefinition<&[u8; 5], &[u8; 9]> = TableDefinition::new("x");
 
@@ -812,7 +1176,7 @@ fn array_type() {
 #[test]
 fn owned_get_signatures() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let definition: TableDefinition<u32, u32> = TableDefinition::new("x");
 
@@ -850,7 +1214,7 @@ fn owned_get_signatures() {
 #[test]
 fn ref_get_signatures() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
     {
         let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
@@ -899,7 +1263,7 @@ fn ref_get_signatures() {
 #[test]
 fn concurrent_write_transactions_block() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = sync::Arc::new(unsafe { Database::create(tmpfile.path()).unwrap() });
+    let db = sync::Arc::new(Database::create(tmpfile.path()).unwrap());
     let wtx = db.begin_write().unwrap();
     let (sender, receiver) = sync::mpsc::channel();
 
@@ -920,7 +1284,7 @@ fn concurrent_write_transactions_block() {
 #[test]
 fn iter() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
     {
         let mut table = write_txn.open_table(U64_TABLE).unwrap();
@@ -938,4 +1302,1359 @@ fn iter() {
         assert_eq!(i, k);
         assert_eq!(i, v);
     }
+    assert!(iter.next().is_none());
+
+    let mut iter = table.iter().unwrap().rev();
+    for i in (0..10).rev() {
+        let (k, v) = iter.next().unwrap();
+        assert_eq!(i, k);
+        assert_eq!(i, v);
+    }
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn iter_empty() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let table = write_txn.open_table(U64_TABLE).unwrap();
+        assert!(table.iter().unwrap().next().is_none());
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert!(table.iter().unwrap().next().is_none());
+    assert!(table.iter().unwrap().next_back().is_none());
+}
+
+#[test]
+fn custom_key_order() {
+    struct Reverse;
+
+    impl KeyOrder<u64> for Reverse {
+        fn compare(data1: &[u8], data2: &[u8]) -> Ordering {
+            u64::from_le_bytes(data2.try_into().unwrap())
+                .cmp(&u64::from_le_bytes(data1.try_into().unwrap()))
+        }
+
+        fn name() -> String {
+            "reverse".to_string()
+        }
+    }
+
+    let definition: TableDefinition<OrderedKey<u64, Reverse>, u64> = TableDefinition::new("x");
+
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(definition).unwrap();
+        for i in 0..10 {
+            table.insert(&i, &i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(definition).unwrap();
+    let keys: Vec<u64> = table.iter().unwrap().map(|(k, _)| k).collect();
+    assert_eq!(keys, (0..10).rev().collect::<Vec<u64>>());
+    assert_eq!(table.get(&3).unwrap().unwrap(), 3);
+    let natural: TableDefinition<u64, u64> = TableDefinition::new("x");
+    assert!(read_txn.open_table(natural).is_err());
+}
+
+redb::tagged_union! {
+    #[derive(Debug, PartialEq)]
+    enum Event<'a> {
+        Created(u64) = 0,
+        Renamed(&'static str) = 1,
+        Deleted(()) = 2,
+    }
+}
+
+redb::tagged_union! {
+    #[derive(Debug, PartialEq)]
+    enum Status {
+        Pending(()) = 0,
+        Done(u32) = 1,
+    }
+}
+
+#[test]
+fn tagged_union_type() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    let events_def: TableDefinition<u64, Event> = TableDefinition::new("events");
+    let status_def: TableDefinition<u64, Status> = TableDefinition::new("status");
+
+    let name = String::from("hello");
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(events_def).unwrap();
+        table.insert(&0, &Event::Created(5)).unwrap();
+        table.insert(&1, &Event::Renamed(name.as_str())).unwrap();
+        table.insert(&2, &Event::Deleted(())).unwrap();
+
+        let mut table = write_txn.open_table(status_def).unwrap();
+        table.insert(&0, &Status::Pending(())).unwrap();
+        table.insert(&1, &Status::Done(7)).unwrap();
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(events_def).unwrap();
+    assert_eq!(table.get(&0).unwrap().unwrap(), Event::Created(5));
+    assert_eq!(table.get(&1).unwrap().unwrap(), Event::Renamed("hello"));
+    assert_eq!(table.get(&2).unwrap().unwrap(), Event::Deleted(()));
+    let table = read_txn.open_table(status_def).unwrap();
+    assert_eq!(table.get(&0).unwrap().unwrap(), Status::Pending(()));
+    assert_eq!(table.get(&1).unwrap().unwrap(), Status::Done(7));
+}
+
+#[test]
+fn export_import() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let str_table: TableDefinition<&str, u64> = TableDefinition::new("str");
+    let str_table2: TableDefinition<&str, u64> = TableDefinition::new("str2");
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(str_table).unwrap();
+        table.insert("a", &0).unwrap();
+        table.insert("b", &1).unwrap();
+        table.insert("c", &2).unwrap();
+    }
+    write_txn.commit().unwrap();
+
+    let mut buffer = vec![];
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(str_table).unwrap();
+    assert_eq!(table.export_range("b".., &mut buffer).unwrap(), 2);
+    assert_eq!(buffer.len(), 2 * (4 + 1 + 4 + 8));
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(str_table2).unwrap();
+        assert_eq!(table.import_records(buffer.as_slice()).unwrap(), 2);
+        let mut iter = table.iter().unwrap();
+        assert_eq!(iter.next().unwrap(), ("b", 1));
+        assert_eq!(iter.next().unwrap(), ("c", 2));
+        assert!(iter.next().is_none());
+    }
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        assert!(table.import_records(buffer.as_slice()).is_err());
+        assert!(table.import_records(&buffer[..5]).is_err());
+    }
+    {
+        // The length prefix is larger than the remaining input
+        let mut truncated = (u32::MAX - 1).to_le_bytes().to_vec();
+        truncated.extend_from_slice(b"abc");
+        let mut table = write_txn.open_table(str_table2).unwrap();
+        assert!(matches!(
+            table.import_records(truncated.as_slice()),
+            Err(Error::Io { source: err, .. }) if err.kind() == std::io::ErrorKind::InvalidData
+        ));
+    }
+    {
+        // The key is not valid UTF-8
+        let mut malformed = vec![];
+        malformed.extend_from_slice(&1u32.to_le_bytes());
+        malformed.push(0xFF);
+        malformed.extend_from_slice(&8u32.to_le_bytes());
+        malformed.extend_from_slice(&3u64.to_le_bytes());
+        let mut table = write_txn.open_table(str_table2).unwrap();
+        assert!(matches!(
+            table.import_records(malformed.as_slice()),
+            Err(Error::Io { source: err, .. }) if err.kind() == std::io::ErrorKind::InvalidData
+        ));
+    }
+    write_txn.abort().unwrap();
+}
+
+#[test]
+fn export_changes() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let backup_table: TableDefinition<u64, u64> = TableDefinition::new("backup");
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..10_000 {
+            table.insert(&i, &i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    // Take a full backup
+    let base_txn = db.begin_read().unwrap();
+    let base = base_txn.open_table(U64_TABLE).unwrap();
+    let mut buffer = vec![];
+    base.export_range::<u64>(.., &mut buffer).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(backup_table).unwrap();
+        assert_eq!(table.import_records(buffer.as_slice()).unwrap(), 10_000);
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    let mut buffer = vec![];
+    assert_eq!(table.export_changes(&base, &mut buffer).unwrap(), 0);
+    assert!(buffer.is_empty());
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        table.insert(&5, &0).unwrap();
+        table.insert(&6000, &0).unwrap();
+        table.insert(&20_000, &0).unwrap();
+        table.remove(&7000).unwrap();
+        // Overwriting a value with itself is not a change
+        table.insert(&8000, &8000).unwrap();
+    }
+    write_txn.commit().unwrap();
+
+    // Take an incremental backup, and apply it to the full backup
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    let mut buffer = vec![];
+    assert_eq!(table.export_changes(&base, &mut buffer).unwrap(), 4);
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut backup = write_txn.open_table(backup_table).unwrap();
+        assert_eq!(backup.import_records(buffer.as_slice()).unwrap(), 4);
+        assert_eq!(backup.len().unwrap(), 10_000);
+        for (expected, actual) in table.iter().unwrap().zip(backup.iter().unwrap()) {
+            assert_eq!(expected, actual);
+        }
+    }
+    write_txn.commit().unwrap();
+
+    // The base must be from the same database
+    let other_file: NamedTempFile = NamedTempFile::new().unwrap();
+    let other_db = Database::create(other_file.path()).unwrap();
+    let write_txn = other_db.begin_write().unwrap();
+    write_txn.open_table(U64_TABLE).unwrap();
+    write_txn.commit().unwrap();
+    let other_txn = other_db.begin_read().unwrap();
+    let other = other_txn.open_table(U64_TABLE).unwrap();
+    assert!(matches!(
+        table.export_changes(&other, &mut vec![]),
+        Err(Error::DatabaseMismatch)
+    ));
+}
+
+#[test]
+fn contains_key() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
+        assert!(!table.contains_key(b"hello").unwrap());
+        for i in 0..1000u32 {
+            table.insert(&i.to_be_bytes(), b"world").unwrap();
+        }
+        assert!(table.contains_key(&7u32.to_be_bytes()).unwrap());
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(SLICE_TABLE).unwrap();
+    for i in 0..1000u32 {
+        assert!(table.contains_key(&i.to_be_bytes()).unwrap());
+    }
+    assert!(!table.contains_key(&1000u32.to_be_bytes()).unwrap());
+    assert!(!table.contains_key(b"").unwrap());
+}
+
+#[test]
+fn first_last() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        assert!(table.first().unwrap().is_none());
+        assert!(table.last().unwrap().is_none());
+        for i in 1..1000u64 {
+            table.insert(&i, &(i * 2)).unwrap();
+        }
+        assert_eq!(table.first().unwrap(), Some((1, 2)));
+        assert_eq!(table.last().unwrap(), Some((999, 1998)));
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.first().unwrap(), Some((1, 2)));
+    assert_eq!(table.last().unwrap(), Some((999, 1998)));
+}
+
+#[test]
+fn time_series() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let definition: TableDefinition<(u64, u64), u64> = TableDefinition::new("samples");
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut samples = TimeSeriesTable::new(write_txn.open_table(definition).unwrap());
+        for timestamp in 0..10_000u64 {
+            samples.append(timestamp, 0, &timestamp).unwrap();
+            samples.append(timestamp, 1, &(timestamp * 2)).unwrap();
+        }
+        assert_eq!(samples.get(5, 1).unwrap(), Some(10));
+        assert_eq!(samples.range(10..12).unwrap().count(), 4);
+        assert_eq!(samples.range(10..=12).unwrap().count(), 6);
+
+        let buckets = samples
+            .downsample(100..300, 100, |_| 0, |sum, _, _, value| *sum += value)
+            .unwrap();
+        let expected: Vec<(u64, u64)> = vec![
+            (100, 3 * (100..200).sum::<u64>()),
+            (200, 3 * (200..300).sum::<u64>()),
+        ];
+        assert_eq!(buckets, expected);
+
+        assert_eq!(samples.remove_before(5000).unwrap(), 10_000);
+        assert_eq!(samples.retain_recent(9000, 1000).unwrap(), 6000);
+        assert!(samples.get(7999, 0).unwrap().is_none());
+        assert_eq!(samples.range(..).unwrap().next().unwrap().0, (8000, 0));
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(definition).unwrap();
+    assert_eq!(table.len().unwrap(), 4000);
+    assert_eq!(table.first().unwrap(), Some(((8000, 0), 8000)));
+}
+
+#[test]
+fn keys_values() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..10u64 {
+            table.insert(&i, &(i * 10)).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    let keys: Vec<u64> = table.keys(3..6).unwrap().collect();
+    assert_eq!(keys, vec![3, 4, 5]);
+    let values: Vec<u64> = table.values(3..6).unwrap().rev().collect();
+    assert_eq!(values, vec![50, 40, 30]);
+    assert_eq!(table.keys::<u64>(..).unwrap().count(), 10);
+}
+
+#[test]
+fn read_only_tables_in_write_transaction() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    assert!(write_txn.open_table_read_only(U64_TABLE).is_err());
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        table.insert(&1, &2).unwrap();
+        assert!(write_txn.open_table_read_only(U64_TABLE).is_err());
+    }
+    {
+        let table1 = write_txn.open_table_read_only(U64_TABLE).unwrap();
+        let table2 = write_txn.open_table_read_only(U64_TABLE).unwrap();
+        assert_eq!(table1.get(&1).unwrap().unwrap(), 2);
+        assert_eq!(table2.get(&1).unwrap().unwrap(), 2);
+        assert!(write_txn.open_table(U64_TABLE).is_err());
+        drop(table1);
+        assert!(write_txn.open_table(U64_TABLE).is_err());
+    }
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        table.insert(&3, &4).unwrap();
+    }
+    write_txn.commit().unwrap();
+}
+
+#[test]
+fn range_prefix() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let str_definition: TableDefinition<&str, u64> = TableDefinition::new("str");
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(str_definition).unwrap();
+        for (i, key) in ["a", "ab", "abc", "ab\u{10FFFF}", "ac", "b"]
+            .iter()
+            .enumerate()
+        {
+            table.insert(key, &(i as u64)).unwrap();
+        }
+        let keys: Vec<&str> = table.range_prefix("ab").unwrap().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec!["ab", "abc", "ab\u{10FFFF}"]);
+        assert_eq!(table.range_prefix("").unwrap().count(), 6);
+
+        let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
+        for key in [
+            &[1u8][..],
+            &[1, 0xFF],
+            &[1, 0xFF, 0xFF],
+            &[2],
+            &[0xFF],
+            &[0xFF, 0],
+        ] {
+            table.insert(key, b"").unwrap();
+        }
+        let keys: Vec<&[u8]> = table
+            .range_prefix(&[1, 0xFF])
+            .unwrap()
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(keys, vec![&[1, 0xFF][..], &[1, 0xFF, 0xFF]]);
+        assert_eq!(table.range_prefix(&[0xFF]).unwrap().count(), 2);
+        assert_eq!(table.range_prefix(&[3]).unwrap().count(), 0);
+    }
+    write_txn.commit().unwrap();
+}
+
+#[test]
+fn try_next() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..100u64 {
+            table.insert(&i, &i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    let mut iter = table.range::<u64>(..).unwrap();
+    let mut expected = 0;
+    while let Some((key, value)) = iter.try_next().unwrap() {
+        assert_eq!(key, expected);
+        assert_eq!(value, expected);
+        expected += 1;
+    }
+    assert_eq!(expected, 100);
+    assert!(iter.try_next_back().unwrap().is_none());
+
+    let mut iter = table.range(10..20u64).unwrap();
+    assert_eq!(iter.try_next_back().unwrap().unwrap().0, 19);
+    assert_eq!(iter.try_next().unwrap().unwrap().0, 10);
+}
+
+#[test]
+fn bytes_value() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let bytes_definition: TableDefinition<Bytes, Bytes> = TableDefinition::new("x");
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
+        table.insert(b"hello", b"world").unwrap();
+    }
+    {
+        // Bytes uses the same format as &[u8]
+        let mut table = write_txn.open_table(bytes_definition).unwrap();
+        table.insert(&Bytes::new(b"hello2"), b"world2").unwrap();
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(bytes_definition).unwrap();
+    let value = table.get(b"hello").unwrap().unwrap();
+    let guard_bytes: Bytes = read_txn
+        .open_table(SLICE_TABLE)
+        .unwrap()
+        .get(b"hello2")
+        .unwrap()
+        .unwrap()
+        .into();
+    drop(table);
+    drop(read_txn);
+
+    let handle = std::thread::spawn(move || {
+        assert_eq!(value.as_ref(), b"world");
+        assert_eq!(&guard_bytes[..], b"world2");
+    });
+    handle.join().unwrap();
+}
+
+#[test]
+fn cursor() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..1000u64 {
+            table.insert(&(i * 2), &i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    let mut cursor = table.cursor().unwrap();
+    assert!(cursor.current().is_none());
+    assert_eq!(cursor.next().unwrap().unwrap().0, 0);
+    assert!(cursor.prev().unwrap().is_none());
+    assert_eq!(cursor.next().unwrap().unwrap().0, 0);
+
+    assert_eq!(cursor.seek(&501).unwrap().unwrap(), (502, 251));
+    assert_eq!(cursor.next().unwrap().unwrap().0, 504);
+    assert_eq!(cursor.prev().unwrap().unwrap().0, 502);
+    assert_eq!(cursor.prev().unwrap().unwrap().0, 500);
+    assert_eq!(cursor.current().unwrap().0, 500);
+
+    assert_eq!(cursor.seek_for_prev(&501).unwrap().unwrap().0, 500);
+    assert_eq!(cursor.seek_for_prev(&500).unwrap().unwrap().0, 500);
+    assert_eq!(cursor.next().unwrap().unwrap().0, 502);
+
+    assert!(cursor.seek(&1999).unwrap().is_none());
+    assert!(cursor.next().unwrap().is_none());
+    assert_eq!(cursor.prev().unwrap().unwrap().0, 1998);
+
+    let mut cursor = table.cursor().unwrap();
+    let mut expected = 1998;
+    while let Some((key, _)) = cursor.prev().unwrap() {
+        assert_eq!(key, expected);
+        expected = expected.wrapping_sub(2);
+    }
+    assert_eq!(expected, u64::MAX - 1);
+}
+
+#[test]
+fn get_multi() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        assert_eq!(table.get_multi(&[1, 2]).unwrap(), vec![None, None]);
+        for i in 0..1000u64 {
+            table.insert(&(i * 2), &i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    let keys = [1998u64, 3, 0, 500, 500, 1500, 2000, 1];
+    let values = table.get_multi(&keys).unwrap();
+    assert_eq!(
+        values,
+        vec![
+            Some(999),
+            None,
+            Some(0),
+            Some(250),
+            Some(250),
+            Some(750),
+            None,
+            None
+        ]
+    );
+    let keys: Vec<u64> = (0..2000).rev().collect();
+    let values = table.get_multi(&keys).unwrap();
+    for (key, value) in keys.iter().zip(values) {
+        assert_eq!(value, table.get(key).unwrap());
+    }
+}
+
+#[test]
+fn nth_and_rank() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        assert!(table.nth(0).unwrap().is_none());
+        assert_eq!(table.rank(&5).unwrap(), 0);
+        for i in 0..1000u64 {
+            table.insert(&(i * 2), &i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    for i in [0u64, 1, 317, 998, 999] {
+        assert_eq!(table.nth(i as usize).unwrap().unwrap(), (i * 2, i));
+        assert_eq!(table.rank(&(i * 2)).unwrap(), i as usize);
+        assert_eq!(table.rank(&(i * 2 + 1)).unwrap(), i as usize + 1);
+    }
+    assert!(table.nth(1000).unwrap().is_none());
+    assert_eq!(table.rank(&u64::MAX).unwrap(), 1000);
+}
+
+#[test]
+fn count_range() {
+    let counted: TableDefinition<u64, u64> = TableDefinition::new("counted").with_entry_counts();
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    for definition in [U64_TABLE, counted] {
+        let mut table = write_txn.open_table(definition).unwrap();
+        assert_eq!(table.count_range::<u64>(..).unwrap(), 0);
+        for i in 0..5000u64 {
+            table.insert(&(i * 2), &i).unwrap();
+        }
+        table.remove_range(1000..2000).unwrap();
+        assert_eq!(table.len().unwrap(), 4500);
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    for definition in [U64_TABLE, counted] {
+        let table = read_txn.open_table(definition).unwrap();
+        assert_eq!(table.len().unwrap(), 4500);
+        assert_eq!(table.count_range::<u64>(..).unwrap(), 4500);
+        assert_eq!(table.count_range(..1000).unwrap(), 500);
+        assert_eq!(table.count_range(1000..2000).unwrap(), 0);
+        assert_eq!(table.count_range(2000..=2010).unwrap(), 6);
+        assert_eq!(table.count_range(5000..).unwrap(), 2500);
+        assert_eq!(
+            table
+                .count_range((std::ops::Bound::Excluded(10), std::ops::Bound::Included(5)))
+                .unwrap(),
+            0
+        );
+        assert_eq!(table.nth(500).unwrap().unwrap(), (2000, 1000));
+        assert_eq!(table.rank(&2000).unwrap(), 500);
+    }
+}
+
+#[test]
+fn retain() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..5000u64 {
+            table.insert(&i, &(i % 7)).unwrap();
+        }
+        assert_eq!(table.retain(|key, _| key % 2 == 0).unwrap(), 2500);
+        assert_eq!(table.retain(|_, value| value != 3).unwrap(), 357);
+        assert_eq!(table.retain(|_, _| true).unwrap(), 0);
+        // Removes whole subtrees from the middle of the table
+        assert_eq!(
+            table.retain(|key, _| !(1000..4000).contains(&key)).unwrap(),
+            1286
+        );
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 857);
+    for (key, value) in table.iter().unwrap() {
+        assert_eq!(key % 2, 0);
+        assert_ne!(value, 3);
+        assert!(!(1000..4000).contains(&key));
+    }
+}
+
+#[test]
+fn snapshot_range() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    let mut table = write_txn.open_table(U64_TABLE).unwrap();
+    for i in 0..2000u64 {
+        table.insert(&i, &i).unwrap();
+    }
+    let mut iter = table.snapshot_range::<u64>(..).unwrap();
+    let mut expected = 0;
+    while let Some((key, value)) = iter.try_next().unwrap() {
+        assert_eq!(key, expected);
+        assert_eq!(value, expected);
+        table.remove(&key).unwrap();
+        table.insert(&(key + 10_000), &(value * 2)).unwrap();
+        if key == 1000 {
+            // The snapshot is also unaffected by reopening the table
+            drop(table);
+            table = write_txn.open_table(U64_TABLE).unwrap();
+        }
+        expected += 1;
+    }
+    assert_eq!(expected, 2000);
+    drop(iter);
+    assert_eq!(table.len().unwrap(), 2000);
+    assert_eq!(table.first().unwrap(), Some((10_000, 0)));
+    drop(table);
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 2000);
+    assert_eq!(table.get(&11_999).unwrap(), Some(3998));
+}
+
+#[test]
+fn drain() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..1000u64 {
+            table.insert(&i, &(i * 2)).unwrap();
+        }
+        let drained: Vec<(u64, u64)> = table
+            .drain(100..200u64)
+            .unwrap()
+            .map(|(k, v)| (k.to_value(), v.to_value()))
+            .collect();
+        assert_eq!(drained.len(), 100);
+        for (i, (key, value)) in drained.into_iter().enumerate() {
+            assert_eq!(key, i as u64 + 100);
+            assert_eq!(value, key * 2);
+        }
+        // Entries are removed even if the iterator is dropped
+        drop(table.drain(900..).unwrap());
+        assert_eq!(table.len().unwrap(), 800);
+        assert_eq!(table.last().unwrap().unwrap().0, 899);
+        assert!(table.get(&150).unwrap().is_none());
+        assert_eq!(table.drain(100..200u64).unwrap().count(), 0);
+    }
+    write_txn.commit().unwrap();
+}
+
+#[test]
+fn remove_range() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..10_000u64 {
+            table.insert(&i, &i).unwrap();
+        }
+        assert_eq!(table.remove_range(1000..9000u64).unwrap(), 8000);
+        assert_eq!(table.remove_range(1000..9000u64).unwrap(), 0);
+        assert_eq!(table.remove_range(..=10u64).unwrap(), 11);
+        assert_eq!(table.len().unwrap(), 1989);
+        assert!(table.get(&10).unwrap().is_none());
+        assert_eq!(table.get(&11).unwrap().unwrap(), 11);
+        assert_eq!(table.get(&9000).unwrap().unwrap(), 9000);
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    let keys: Vec<u64> = table.range::<u64>(..).unwrap().map(|(k, _)| k).collect();
+    let expected: Vec<u64> = (11..1000).chain(9000..10_000).collect();
+    assert_eq!(keys, expected);
+}
+
+#[test]
+fn get_or_insert_with() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let definition: TableDefinition<&str, &str> = TableDefinition::new("x");
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(definition).unwrap();
+        let value = table
+            .get_or_insert_with("hello", || "world".to_string())
+            .unwrap();
+        assert_eq!(value.to_value(), "world");
+        drop(value);
+        let value = table
+            .get_or_insert_with("hello", || -> String { panic!() })
+            .unwrap();
+        assert_eq!(value.to_value(), "world");
+    }
+    write_txn.commit().unwrap();
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..1000u64 {
+            table.insert(&(i * 2), &i).unwrap();
+        }
+        for i in 0..2000u64 {
+            let value = table.get_or_insert_with(&i, || i + 10_000).unwrap();
+            if i % 2 == 0 {
+                assert_eq!(value.to_value(), i / 2);
+            } else {
+                assert_eq!(value.to_value(), i + 10_000);
+            }
+        }
+        assert_eq!(table.len().unwrap(), 2000);
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.get(&1).unwrap().unwrap(), 10_001);
+    assert_eq!(table.get(&2).unwrap().unwrap(), 1);
+}
+
+#[test]
+fn insert_if_absent() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
+        assert!(table
+            .insert_if_absent(b"hello", b"world")
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            table
+                .insert_if_absent(b"hello", b"world2")
+                .unwrap()
+                .unwrap()
+                .to_value(),
+            b"world"
+        );
+    }
+    write_txn.commit().unwrap();
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
+        assert_eq!(
+            table
+                .insert_if_absent(b"hello", b"world3")
+                .unwrap()
+                .unwrap()
+                .to_value(),
+            b"world"
+        );
+        assert!(table
+            .insert_if_absent(b"hello2", b"world3")
+            .unwrap()
+            .is_none());
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(SLICE_TABLE).unwrap();
+    assert_eq!(table.get(b"hello").unwrap().unwrap(), b"world");
+    assert_eq!(table.get(b"hello2").unwrap().unwrap(), b"world3");
+    assert_eq!(table.len().unwrap(), 2);
+}
+
+#[test]
+fn extend() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..100u64 {
+            table.insert(&(i * 1000), &0).unwrap();
+        }
+        let entries: Vec<(u64, u64)> = (0..100_000u64).rev().map(|i| (i, i + 1)).collect();
+        table.extend(entries.iter().map(|(k, v)| (k, v))).unwrap();
+        // The last value given for a key is kept
+        table.extend([(&5u64, &0u64), (&5, &6)]).unwrap();
+        assert_eq!(table.len().unwrap(), 100_000);
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    for (i, (key, value)) in table.iter().unwrap().enumerate() {
+        assert_eq!(key, i as u64);
+        assert_eq!(value, key + 1);
+    }
+}
+
+#[test]
+fn load_sorted() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        let entries: Vec<(u64, u64)> = (0..100_000u64).map(|i| (i, i + 1)).collect();
+        table
+            .load_sorted(entries.iter().map(|(k, v)| (k, v)))
+            .unwrap();
+        table.load_sorted([(&100_000u64, &0u64)]).unwrap();
+        table.insert(&100_001, &0).unwrap();
+        assert_eq!(table.len().unwrap(), 100_002);
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    for (i, (key, value)) in table.range(..100_000).unwrap().enumerate() {
+        assert_eq!(key, i as u64);
+        assert_eq!(value, key + 1);
+    }
+    assert_eq!(table.get(&100_000).unwrap().unwrap(), 0);
+}
+
+#[test]
+fn load_sorted_out_of_order() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    let mut table = write_txn.open_table(U64_TABLE).unwrap();
+    table.insert(&5, &0).unwrap();
+    assert!(matches!(
+        table.load_sorted([(&1u64, &0u64)]),
+        Err(Error::KeysNotSorted(_))
+    ));
+    assert!(matches!(
+        table.load_sorted([(&6u64, &0u64), (&8, &0), (&7, &0)]),
+        Err(Error::KeysNotSorted(_))
+    ));
+    assert!(matches!(
+        table.load_sorted([(&6u64, &0u64), (&6, &0)]),
+        Err(Error::KeysNotSorted(_))
+    ));
+    assert_eq!(table.len().unwrap(), 1);
+    table.load_sorted([(&6u64, &0u64), (&7, &0)]).unwrap();
+    assert_eq!(table.len().unwrap(), 3);
+}
+
+#[test]
+fn merge_in_place() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::builder()
+        .set_merge_operator("x", |_, existing, operand| {
+            let mut value = existing.unwrap_or_default().to_vec();
+            value.extend_from_slice(operand);
+            value
+        })
+        .create(tmpfile.path())
+        .unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
+        table.merge_in_place(b"hello", b"wor").unwrap();
+        table.merge_in_place(b"hello", b"ld").unwrap();
+        assert_eq!(table.get(b"hello").unwrap().unwrap(), b"world");
+    }
+    write_txn.commit().unwrap();
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
+        table.merge_in_place(b"hello", b"!").unwrap();
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(SLICE_TABLE).unwrap();
+    assert_eq!(table.get(b"hello").unwrap().unwrap(), b"world!");
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        assert!(matches!(
+            table.merge_in_place(&0, &1),
+            Err(Error::MergeOperatorNotRegistered(_))
+        ));
+        assert!(table.is_empty().unwrap());
+    }
+    write_txn.commit().unwrap();
+}
+
+#[test]
+fn compare_and_swap() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        // Insert only if absent
+        assert!(table.compare_and_swap(&1, None, Some(&10)).unwrap());
+        assert!(!table.compare_and_swap(&1, None, Some(&20)).unwrap());
+        assert_eq!(table.get(&1).unwrap().unwrap(), 10);
+        // Replace only if equal
+        assert!(!table.compare_and_swap(&1, Some(&11), Some(&20)).unwrap());
+        assert!(table.compare_and_swap(&1, Some(&10), Some(&20)).unwrap());
+        assert_eq!(table.get(&1).unwrap().unwrap(), 20);
+        // Remove only if equal
+        assert!(!table.compare_and_swap(&1, Some(&10), None).unwrap());
+        assert!(table.compare_and_swap(&1, Some(&20), None).unwrap());
+        assert!(table.get(&1).unwrap().is_none());
+        assert!(!table.compare_and_swap(&2, Some(&0), Some(&1)).unwrap());
+        assert!(table.get(&2).unwrap().is_none());
+    }
+    write_txn.commit().unwrap();
+}
+
+#[test]
+fn increment() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let definition: TableDefinition<&str, i32> = TableDefinition::new("x");
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(definition).unwrap();
+        assert_eq!(table.increment("a", 5).unwrap(), 5);
+        assert_eq!(table.increment("a", -7).unwrap(), -2);
+        assert_eq!(table.increment("b", 1).unwrap(), 1);
+        assert!(matches!(
+            table.increment("b", i32::MAX),
+            Err(Error::IntegerOverflow(_))
+        ));
+        assert_eq!(table.get("b").unwrap().unwrap(), 1);
+    }
+    write_txn.commit().unwrap();
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..1000u64 {
+            table.increment(&(i % 10), i).unwrap();
+        }
+        assert_eq!(
+            table.get(&3).unwrap().unwrap(),
+            (0..100).map(|i| i * 10 + 3).sum()
+        );
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(definition).unwrap();
+    assert_eq!(table.get("a").unwrap().unwrap(), -2);
+    assert_eq!(table.get("b").unwrap().unwrap(), 1);
+}
+
+#[test]
+fn clear() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..10_000u64 {
+            table.insert(&i, &i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        table.insert(&10_000, &0).unwrap();
+        table.clear().unwrap();
+        assert!(table.is_empty().unwrap());
+        table.insert(&1, &1).unwrap();
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 1);
+    assert_eq!(table.get(&1).unwrap().unwrap(), 1);
+}
+
+#[test]
+fn copy_table() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let tmpfile2: NamedTempFile = NamedTempFile::new().unwrap();
+    let db2 = Database::create(tmpfile2.path()).unwrap();
+    let definition2: TableDefinition<u64, u64> = TableDefinition::new("u64_copy");
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..10_000u64 {
+            table.insert(&i, &(i * 3)).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    // Copy into another database
+    let read_txn = db.begin_read().unwrap();
+    let source = read_txn.open_table(U64_TABLE).unwrap();
+    let write_txn = db2.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        table.insert(&20_000, &0).unwrap();
+        table.copy_from(&source).unwrap();
+    }
+    write_txn.commit().unwrap();
+
+    // Copy within the same database
+    let write_txn = db.begin_write().unwrap();
+    {
+        let source = write_txn.open_table_read_only(U64_TABLE).unwrap();
+        let mut table = write_txn.open_table(definition2).unwrap();
+        table.copy_from(&source).unwrap();
+    }
+    write_txn.commit().unwrap();
+
+    let expected: Vec<(u64, u64)> = (0..10_000u64).map(|i| (i, i * 3)).collect();
+    let read_txn = db2.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.iter().unwrap().collect::<Vec<_>>(), expected);
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(definition2).unwrap();
+    assert_eq!(table.iter().unwrap().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn set_table() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let definition: TableDefinition<u64, ()> = TableDefinition::new("set");
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut set = SetTable::new(write_txn.open_table(definition).unwrap());
+        assert!(set.is_empty().unwrap());
+        assert!(set.insert(&5).unwrap());
+        assert!(!set.insert(&5).unwrap());
+        set.extend(&[1, 3, 9, 7]).unwrap();
+        assert!(set.remove(&3).unwrap());
+        assert!(!set.remove(&3).unwrap());
+    }
+    write_txn.commit().unwrap();
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let set = SetTable::new(write_txn.open_table(definition).unwrap());
+        assert_eq!(set.len().unwrap(), 4);
+        assert!(set.contains(&7).unwrap());
+        assert!(!set.contains(&3).unwrap());
+        assert_eq!(set.iter().unwrap().collect::<Vec<_>>(), vec![1, 5, 7, 9]);
+        assert_eq!(
+            set.range(2..8).unwrap().rev().collect::<Vec<_>>(),
+            vec![7, 5]
+        );
+        assert_eq!(set.first().unwrap(), Some(1));
+        assert_eq!(set.last().unwrap(), Some(9));
+    }
+    write_txn.abort().unwrap();
+}
+
+#[test]
+fn log_table() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let definition: TableDefinition<u64, u64> = TableDefinition::new("log");
+    let sequences: TableDefinition<&str, u64> = TableDefinition::new("sequences");
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut log = LogTable::new(
+            write_txn.open_table(definition).unwrap(),
+            write_txn.open_table(sequences).unwrap(),
+        );
+        assert_eq!(log.next_sequence().unwrap(), 0);
+        assert_eq!(log.append(&100).unwrap(), 0);
+        assert_eq!(log.append(&101).unwrap(), 1);
+        let values: Vec<u64> = (102..1_000).collect();
+        assert_eq!(log.extend(&values).unwrap(), 2..900);
+    }
+    write_txn.commit().unwrap();
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut log = LogTable::new(
+            write_txn.open_table(definition).unwrap(),
+            write_txn.open_table(sequences).unwrap(),
+        );
+        assert_eq!(log.append(&1_000).unwrap(), 900);
+        assert_eq!(log.len().unwrap(), 901);
+        assert_eq!(log.get(5).unwrap(), Some(105));
+        assert_eq!(log.last().unwrap(), Some((900, 1_000)));
+        assert_eq!(log.truncate_before(800).unwrap(), 800);
+        assert_eq!(
+            log.range(..803).unwrap().collect::<Vec<_>>(),
+            vec![(800, 900), (801, 901), (802, 902)]
+        );
+        assert_eq!(log.remove(900).unwrap().unwrap().to_value(), 1_000);
+        // Sequence numbers are not reused after the most recent entry is removed
+        assert_eq!(log.append(&0).unwrap(), 901);
+        assert_eq!(log.extend(&[] as &[u64]).unwrap(), 902..902);
+        assert_eq!(log.truncate_before(u64::MAX).unwrap(), 101);
+    }
+    write_txn.commit().unwrap();
+
+    // Nor after every entry is removed, in a later transaction
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut log = LogTable::new(
+            write_txn.open_table(definition).unwrap(),
+            write_txn.open_table(sequences).unwrap(),
+        );
+        assert!(log.is_empty().unwrap());
+        assert_eq!(log.append(&1).unwrap(), 902);
+    }
+    write_txn.commit().unwrap();
+}
+
+#[test]
+fn unique_index() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let users: TableDefinition<u64, &str> = TableDefinition::new("users");
+    let index: TableDefinition<&str, u64> = TableDefinition::new("users_by_email");
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = UniqueIndexedTable::new(
+            write_txn.open_table(users).unwrap(),
+            write_txn.open_table(index).unwrap(),
+        );
+        table.insert(&1, "a@example.com").unwrap();
+        table.insert(&2, "b@example.com").unwrap();
+        // Reinserting the same mapping is allowed
+        table.insert(&1, "a@example.com").unwrap();
+        match table.insert(&3, "a@example.com") {
+            Err(Error::UniqueConstraintViolation(name)) => assert_eq!(name, "users_by_email"),
+            _ => unreachable!(),
+        }
+        assert!(table.get(&3).unwrap().is_none());
+        // Changing a value releases the old one
+        let old = table.insert(&1, "c@example.com").unwrap();
+        assert_eq!(old.unwrap().to_value(), "a@example.com");
+        table.insert(&3, "a@example.com").unwrap();
+        assert_eq!(
+            table.remove(&2).unwrap().unwrap().to_value(),
+            "b@example.com"
+        );
+        assert!(table.key_for("b@example.com").unwrap().is_none());
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(index).unwrap();
+    let entries: Vec<(&str, u64)> = table.iter().unwrap().collect();
+    assert_eq!(entries, vec![("a@example.com", 3), ("c@example.com", 1)]);
+    let table = read_txn.open_table(users).unwrap();
+    assert_eq!(table.len().unwrap(), 2);
+}
+
+#[test]
+fn expiring_table() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let definition: TableDefinition<u64, &[u8]> = TableDefinition::new("cache");
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table: ExpiringTable<u64, &str> =
+            ExpiringTable::new(write_txn.open_table(definition).unwrap());
+        for i in 0..100u64 {
+            table.insert(&i, "value", i * 10).unwrap();
+        }
+        table.insert(&5, "updated", 1_000).unwrap();
+    }
+    write_txn.commit().unwrap();
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table: ExpiringTable<u64, &str> =
+            ExpiringTable::new(write_txn.open_table(definition).unwrap());
+        assert_eq!(table.get(&10, 99).unwrap(), Some("value"));
+        assert_eq!(table.get(&10, 100).unwrap(), None);
+        assert_eq!(table.get(&5, 500).unwrap(), Some("updated"));
+        assert_eq!(table.expiration(&3).unwrap(), Some(30));
+        assert_eq!(table.expiration(&100).unwrap(), None);
+        assert_eq!(table.purge_expired(500).unwrap(), 50);
+        assert_eq!(table.expiration(&3).unwrap(), None);
+        assert!(table.remove(&60).unwrap());
+        assert!(!table.remove(&60).unwrap());
+        let mut table = table.into_inner();
+        assert_eq!(table.len().unwrap(), 49);
+
+        // A value written directly to the underlying table, which is too short to be valid
+        table.insert(&200, [1u8, 2, 3].as_slice()).unwrap();
+        let mut table: ExpiringTable<u64, &str> = ExpiringTable::new(table);
+        assert!(matches!(table.get(&200, 0), Err(Error::Corrupted { .. })));
+        assert!(matches!(
+            table.expiration(&200),
+            Err(Error::Corrupted { .. })
+        ));
+        assert!(matches!(
+            table.purge_expired(0),
+            Err(Error::Corrupted { .. })
+        ));
+    }
+    write_txn.commit().unwrap();
+}
+
+#[test]
+fn blob_table() {
+    let index: TableDefinition<&str, u64> = TableDefinition::new("blobs");
+    let chunks: TableDefinition<(u64, u64), &[u8]> = TableDefinition::new("blob_chunks");
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut blobs = BlobTable::new(
+            write_txn.open_table(index).unwrap(),
+            write_txn.open_table(chunks).unwrap(),
+        );
+        blobs.set_chunk_size(4000);
+        let mut writer = blobs.writer("big").unwrap();
+        for piece in data.chunks(777) {
+            writer.write_all(piece).unwrap();
+        }
+        writer.finish().unwrap();
+        assert_eq!(blobs.insert("empty", [].as_slice()).unwrap(), 0);
+        assert_eq!(blobs.insert("small", b"hello".as_slice()).unwrap(), 5);
+        assert_eq!(blobs.blob_len("big").unwrap(), Some(100_000));
+        assert_eq!(blobs.blob_len("empty").unwrap(), Some(0));
+        assert_eq!(blobs.blob_len("missing").unwrap(), None);
+
+        let mut contents = vec![];
+        blobs
+            .reader("big")
+            .unwrap()
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents, data);
+        assert!(blobs.reader("missing").unwrap().is_none());
+
+        // Replace a blob with a shorter one
+        assert_eq!(blobs.insert("small", b"hi".as_slice()).unwrap(), 2);
+        assert!(blobs.remove("empty").unwrap());
+        assert!(!blobs.remove("empty").unwrap());
+        assert_eq!(blobs.len().unwrap(), 2);
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let index = read_txn.open_table(index).unwrap();
+    let chunks = read_txn.open_table(chunks).unwrap();
+    let mut contents = vec![];
+    BlobReader::new(&index, &chunks, "big")
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut contents)
+        .unwrap();
+    assert_eq!(contents, data);
+    let mut contents = String::new();
+    BlobReader::new(&index, &chunks, "small")
+        .unwrap()
+        .unwrap()
+        .read_to_string(&mut contents)
+        .unwrap();
+    assert_eq!(contents, "hi");
+    assert_eq!(chunks.len().unwrap(), 26);
 }
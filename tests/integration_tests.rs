@@ -1,13 +1,23 @@
+use std::collections::HashMap;
 use std::fs;
+use std::io;
 use std::io::ErrorKind;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 use tempfile::NamedTempFile;
 
 use rand::prelude::SliceRandom;
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use redb::ReadableMultimapTable;
 use redb::{
-    Builder, Database, Durability, Error, MultimapTableDefinition, ReadableTable, TableDefinition,
-    WriteStrategy,
+    AccessPattern, AllocationEventKind, Builder, Database, Durability, Error, InMemoryStorage,
+    IntegrityIssueKind, IoOperation, KeyChange, MultimapTableDefinition, OwnedReadTransaction,
+    OwnedWriteTransaction, ReadableTable, RetentionPolicy, StorageBackend, StorageDevice,
+    TableDefinition, WriteBatch, WriteStrategy,
 };
 
 const ELEMENTS: usize = 100;
@@ -16,13 +26,19 @@ const SLICE_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("x");
 const SLICE_TABLE2: TableDefinition<&[u8], &[u8]> = TableDefinition::new("y");
 const U64_TABLE: TableDefinition<u64, u64> = TableDefinition::new("u64");
 
+// Tests use a fixed seed, so that failures can be reproduced
+fn make_rng() -> StdRng {
+    StdRng::seed_from_u64(0)
+}
+
 /// Returns pairs of key, value
 fn gen_data(count: usize, key_size: usize, value_size: usize) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut rng = make_rng();
     let mut pairs = vec![];
 
     for _ in 0..count {
-        let key: Vec<u8> = (0..key_size).map(|_| rand::thread_rng().gen()).collect();
-        let value: Vec<u8> = (0..value_size).map(|_| rand::thread_rng().gen()).collect();
+        let key: Vec<u8> = (0..key_size).map(|_| rng.gen()).collect();
+        let value: Vec<u8> = (0..value_size).map(|_| rng.gen()).collect();
         pairs.push((key, value));
     }
 
@@ -33,7 +49,7 @@ fn gen_data(count: usize, key_size: usize, value_size: usize) -> Vec<(Vec<u8>, V
 fn mixed_durable_commit() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let mut txn = db.begin_write().unwrap();
     txn.set_durability(Durability::None);
     {
@@ -50,7 +66,7 @@ fn mixed_durable_commit() {
 fn non_durable_commit_persistence() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let mut txn = db.begin_write().unwrap();
     txn.set_durability(Durability::None);
     let pairs = gen_data(100, 16, 20);
@@ -65,12 +81,12 @@ fn non_durable_commit_persistence() {
 
     // Check that cleanly closing the database persists the non-durable commit
     drop(db);
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let txn = db.begin_read().unwrap();
     let table = txn.open_table(SLICE_TABLE).unwrap();
 
     let mut key_order: Vec<usize> = (0..ELEMENTS).collect();
-    key_order.shuffle(&mut rand::thread_rng());
+    key_order.shuffle(&mut make_rng());
 
     {
         for i in &key_order {
@@ -84,7 +100,7 @@ fn non_durable_commit_persistence() {
 fn test_persistence(durability: Durability) {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let mut txn = db.begin_write().unwrap();
     txn.set_durability(durability);
     let pairs = gen_data(100, 16, 20);
@@ -98,12 +114,12 @@ fn test_persistence(durability: Durability) {
     txn.commit().unwrap();
 
     drop(db);
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let txn = db.begin_read().unwrap();
     let table = txn.open_table(SLICE_TABLE).unwrap();
 
     let mut key_order: Vec<usize> = (0..ELEMENTS).collect();
-    key_order.shuffle(&mut rand::thread_rng());
+    key_order.shuffle(&mut make_rng());
 
     {
         for i in &key_order {
@@ -124,11 +140,16 @@ fn immediate_persistence() {
     test_persistence(Durability::Immediate);
 }
 
+#[test]
+fn grouped_persistence() {
+    test_persistence(Durability::Grouped);
+}
+
 #[test]
 fn free() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let txn = db.begin_write().unwrap();
     {
         let _table = txn.open_table(SLICE_TABLE).unwrap();
@@ -195,7 +216,7 @@ fn free() {
 fn large_values() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let txn = db.begin_write().unwrap();
 
     let mut key = vec![0u8; 1024];
@@ -224,7 +245,7 @@ fn large_values() {
 fn large_keys() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let txn = db.begin_write().unwrap();
 
     let mut key = vec![0u8; 1024];
@@ -256,7 +277,7 @@ fn dynamic_growth() {
     let big_value = vec![0u8; 1024];
 
     let expected_size = 10 * 1024 * 1024;
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let txn = db.begin_write().unwrap();
     {
         let mut table = txn.open_table(table_definition).unwrap();
@@ -281,13 +302,472 @@ fn dynamic_growth() {
     assert!(file_size > initial_file_size);
 }
 
+#[test]
+fn buffered_storage_backend() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let table_definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+    let big_value = vec![7u8; 1024];
+
+    let db = Builder::new()
+        .set_storage_backend(StorageBackend::Buffered)
+        .create(tmpfile.path())
+        .unwrap();
+    let initial_file_size = tmpfile.as_file().metadata().unwrap().len();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(table_definition).unwrap();
+        for i in 0..2048 {
+            table.insert(&i, &big_value).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+    assert!(tmpfile.as_file().metadata().unwrap().len() > initial_file_size);
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(table_definition).unwrap();
+        for i in 10..2048 {
+            table.remove(&i).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+    drop(db);
+
+    // The file can be read with either backend
+    for backend in [StorageBackend::Mmap, StorageBackend::Buffered] {
+        let db = Builder::new()
+            .set_storage_backend(backend)
+            .create(tmpfile.path())
+            .unwrap();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_definition).unwrap();
+        assert_eq!(table.len().unwrap(), 10);
+        for i in 0..10 {
+            assert_eq!(table.get(&i).unwrap().unwrap(), big_value.as_slice());
+        }
+    }
+}
+
+// A device whose contents outlive the database, so that it can be opened again
+#[derive(Clone, Default)]
+struct SharedStorage(Arc<Mutex<Vec<u8>>>);
+
+impl StorageDevice for SharedStorage {
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.0.lock().unwrap().len() as u64)
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let offset = offset as usize;
+        buf.copy_from_slice(&self.0.lock().unwrap()[offset..(offset + buf.len())]);
+        Ok(())
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let offset = offset as usize;
+        self.0.lock().unwrap()[offset..(offset + data.len())].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        self.0.lock().unwrap().resize(len as usize, 0);
+        Ok(())
+    }
+
+    fn sync_data(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn storage_device() {
+    let table_definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+    let big_value = vec![7u8; 1024];
+
+    let db = Builder::new()
+        .create_with_storage(InMemoryStorage::new())
+        .unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(table_definition).unwrap();
+        for i in 0..2048 {
+            table.insert(&i, big_value.as_slice()).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(table_definition).unwrap();
+    assert_eq!(table.len().unwrap(), 2048);
+    assert_eq!(table.get(&1000).unwrap().unwrap(), big_value.as_slice());
+    drop(table);
+    drop(txn);
+    drop(db);
+
+    // Committed data is written to the device, and read back when it's opened again
+    let storage = SharedStorage::default();
+    let db = Builder::new().create_with_storage(storage.clone()).unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(table_definition).unwrap();
+        for i in 0..2048 {
+            table.insert(&i, big_value.as_slice()).unwrap();
+        }
+        for i in 10..2048 {
+            table.remove(&i).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+    drop(db);
+    assert!(!storage.0.lock().unwrap().is_empty());
+
+    let db = Builder::new().create_with_storage(storage.clone()).unwrap();
+    let report = db.check_integrity().unwrap();
+    assert!(report.is_ok(), "{:?}", report.issues());
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(table_definition).unwrap();
+    assert_eq!(table.len().unwrap(), 10);
+    for i in 0..10 {
+        assert_eq!(table.get(&i).unwrap().unwrap(), big_value.as_slice());
+    }
+}
+
+#[test]
+fn buffered_cache_size() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let table_definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+
+    let db = Builder::new()
+        .set_storage_backend(StorageBackend::Buffered)
+        .set_cache_size(64 * 1024)
+        .create(tmpfile.path())
+        .unwrap();
+    for round in 0..10u64 {
+        let mut txn = db.begin_write().unwrap();
+        // Pages of non-durable commits must be written back before they are evicted
+        txn.set_durability(if round % 2 == 0 {
+            Durability::None
+        } else {
+            Durability::Immediate
+        });
+        {
+            let mut table = txn.open_table(table_definition).unwrap();
+            for i in 0..100 {
+                let value = vec![round as u8; 1024];
+                table.insert(&(round * 100 + i), value.as_slice()).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_definition).unwrap();
+        assert_eq!(table.len().unwrap(), (round as usize + 1) * 100);
+        for key in 0..(round + 1) * 100 {
+            let value = table.get(&key).unwrap().unwrap();
+            assert_eq!(value, vec![(key / 100) as u8; 1024].as_slice());
+        }
+    }
+}
+
+#[test]
+fn buffered_cache_stats() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let table_definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+
+    let db = Builder::new()
+        .set_storage_backend(StorageBackend::Buffered)
+        .set_cache_size(256 * 1024)
+        .create(tmpfile.path())
+        .unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(table_definition).unwrap();
+        for i in 0..1000 {
+            table.insert(&i, [i as u8; 1024].as_slice()).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+    let stats = db.cache_stats();
+    assert!(stats.evictions() > 0);
+    assert!(stats.cached_bytes() <= 256 * 1024);
+
+    // Read everything, finishing with the first key, so that its pages and those of the table
+    // of tables are the most recently used
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(table_definition).unwrap();
+    for i in (0..1000).rev() {
+        assert_eq!(table.get(&i).unwrap().unwrap(), [i as u8; 1024].as_slice());
+    }
+    drop(table);
+    txn.open_table(table_definition).unwrap();
+    txn.close().unwrap();
+    let stats = db.cache_stats();
+    assert!(stats.misses() > 0);
+    assert!(stats.cached_bytes() <= 256 * 1024);
+
+    // Only the least recently used pages were evicted
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(table_definition).unwrap();
+    assert_eq!(table.get(&0).unwrap().unwrap(), [0; 1024].as_slice());
+    let after = db.cache_stats();
+    assert_eq!(after.misses(), stats.misses());
+    assert!(after.hits() > stats.hits());
+    assert!(db.metrics().unwrap().cache_hit_rate().unwrap() > 0.0);
+}
+
+#[test]
+fn buffered_read_error() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let table_definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+
+    let db = Database::create(tmpfile.path()).unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(table_definition).unwrap();
+        for i in 0..1000 {
+            table.insert(&i, [i as u8; 1024].as_slice()).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+    drop(db);
+
+    let db = Builder::new()
+        .set_storage_backend(StorageBackend::Buffered)
+        .open(tmpfile.path())
+        .unwrap();
+    // Pages beyond the end of the file can't be read, which is reported instead of panicking
+    let len = tmpfile.as_file().metadata().unwrap().len();
+    tmpfile.as_file().set_len(len / 2).unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(table_definition).unwrap();
+    let errors = (0..1000)
+        .filter(|i| {
+            matches!(
+                table.get(i),
+                Err(Error::Io {
+                    operation: IoOperation::Read,
+                    ..
+                })
+            )
+        })
+        .count();
+    assert!(errors > 0);
+}
+
+#[test]
+fn access_pattern_and_populate() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let table_definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+
+    let db = Builder::new()
+        .set_access_pattern(AccessPattern::Sequential)
+        .create(tmpfile.path())
+        .unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(table_definition).unwrap();
+        // Enough to grow the file, which maps it again with the same hint
+        for i in 0..10_000 {
+            table.insert(&i, [i as u8; 1024].as_slice()).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+    drop(db);
+
+    for backend in [StorageBackend::Mmap, StorageBackend::Buffered] {
+        let db = Builder::new()
+            .set_storage_backend(backend)
+            .set_access_pattern(AccessPattern::Normal)
+            .set_populate_on_open(true)
+            .open(tmpfile.path())
+            .unwrap();
+        let misses = db.cache_stats().misses();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_definition).unwrap();
+        assert_eq!(table.len().unwrap(), 10_000);
+        for (i, (key, value)) in table.iter().unwrap().enumerate() {
+            assert_eq!(key, i as u64);
+            assert_eq!(value, [i as u8; 1024].as_slice());
+        }
+        // The buffered backend read the whole file when it was opened
+        assert_eq!(db.cache_stats().misses(), misses);
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn hole_punching() {
+    use std::os::unix::fs::MetadataExt;
+
+    let big: TableDefinition<u64, &[u8]> = TableDefinition::new("big");
+    let small: TableDefinition<u64, u64> = TableDefinition::new("small");
+
+    // Returns the number of bytes of the file allocated on disk
+    let allocated = |punch_holes: bool| {
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        let mut builder = Builder::new();
+        if punch_holes {
+            builder.set_hole_punch_threshold(64 * 1024);
+        }
+        let db = builder.create(tmpfile.path()).unwrap();
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(big).unwrap();
+            for i in 0..1000 {
+                table.insert(&i, [i as u8; 4000].as_slice()).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+        // Keep some data after the big table, so that the file can't be shrunk instead
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(small).unwrap();
+            for i in 0..1000 {
+                table.insert(&i, &i).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+        let txn = db.begin_write().unwrap();
+        assert!(txn.delete_table(big).unwrap());
+        txn.commit().unwrap();
+        // The pages are only freed by the next durable commit
+        for _ in 0..2 {
+            db.begin_write().unwrap().commit().unwrap();
+        }
+
+        let report = db.check_integrity().unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues());
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(small).unwrap();
+        assert_eq!(table.len().unwrap(), 1000);
+        assert_eq!(table.get(&999).unwrap().unwrap(), 999);
+
+        drop(table);
+        txn.close().unwrap();
+        let allocated = tmpfile.as_file().metadata().unwrap().blocks() * 512;
+
+        // The punched space is reused
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(big).unwrap();
+            for i in 0..1000 {
+                table.insert(&i, [i as u8; 4000].as_slice()).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(big).unwrap();
+        for (i, (key, value)) in table.iter().unwrap().enumerate() {
+            assert_eq!(key, i as u64);
+            assert_eq!(value, [i as u8; 4000].as_slice());
+        }
+
+        allocated
+    };
+
+    assert!(allocated(true) < allocated(false) / 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn sparse_file() {
+    use std::os::unix::fs::MetadataExt;
+
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let table_definition: TableDefinition<u64, u64> = TableDefinition::new("x");
+    let db = Builder::new()
+        .set_sparse(true)
+        .set_initial_size(256 << 20)
+        .create(tmpfile.path())
+        .unwrap();
+    // Only the pages which have been written take up disk space
+    let metadata = tmpfile.as_file().metadata().unwrap();
+    assert!(metadata.len() >= 256 << 20);
+    assert!(metadata.blocks() * 512 < 16 << 20);
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(table_definition).unwrap();
+        for i in 0..1000 {
+            table.insert(&i, &i).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(table_definition).unwrap();
+    assert_eq!(table.get(&999).unwrap().unwrap(), 999);
+}
+
+#[test]
+fn background_flush() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let table_definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+
+    let db = Builder::new()
+        .set_background_flush_threshold(256 * 1024)
+        .create(tmpfile.path())
+        .unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(table_definition).unwrap();
+        for i in 0..10_000 {
+            table.insert(&i, [i as u8; 1024].as_slice()).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+    drop(db);
+
+    let db = Database::open(tmpfile.path()).unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(table_definition).unwrap();
+    assert_eq!(table.len().unwrap(), 10_000);
+    for (i, (key, value)) in table.iter().unwrap().enumerate() {
+        assert_eq!(key, i as u64);
+        assert_eq!(value, [i as u8; 1024].as_slice());
+    }
+}
+
+#[test]
+fn read_ahead() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let table_definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+
+    let db = Database::create(tmpfile.path()).unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(table_definition).unwrap();
+        for i in 0..10_000 {
+            table.insert(&i, [i as u8; 100].as_slice()).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+    drop(db);
+
+    let db = Builder::new()
+        .set_read_ahead(4)
+        .open(tmpfile.path())
+        .unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(table_definition).unwrap();
+    for (i, (key, value)) in table.iter().unwrap().enumerate() {
+        assert_eq!(key, i as u64);
+        assert_eq!(value, [i as u8; 100].as_slice());
+    }
+    let keys: Vec<u64> = table
+        .range(1234..5678)
+        .unwrap()
+        .rev()
+        .map(|(k, _)| k)
+        .collect();
+    assert_eq!(keys, (1234..5678).rev().collect::<Vec<u64>>());
+}
+
 #[test]
 fn multi_page_kv() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
     let elements = 4;
     let page_size = 4096;
 
-    let db = unsafe { Builder::new().create(tmpfile.path()).unwrap() };
+    let db = Builder::new().create(tmpfile.path()).unwrap();
     let txn = db.begin_write().unwrap();
 
     let mut key = vec![0u8; page_size + 1];
@@ -326,7 +806,7 @@ fn multi_page_kv() {
 fn regression() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let txn = db.begin_write().unwrap();
     {
         let mut table = txn.open_table(U64_TABLE).unwrap();
@@ -387,7 +867,7 @@ fn regression() {
 fn regression2() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let tx = db.begin_write().unwrap();
 
     let a_def: TableDefinition<&[u8], &[u8]> = TableDefinition::new("a");
@@ -407,7 +887,7 @@ fn regression2() {
 fn regression3() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let tx = db.begin_write().unwrap();
     {
         let mut t = tx.open_table(SLICE_TABLE).unwrap();
@@ -429,7 +909,7 @@ fn regression3() {
 fn regression7() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
 
@@ -472,7 +952,7 @@ fn regression7() {
 fn regression8() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
 
@@ -521,7 +1001,7 @@ fn regression8() {
 fn regression9() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
 
@@ -539,7 +1019,7 @@ fn regression9() {
 fn regression10() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
 
@@ -571,7 +1051,7 @@ fn regression10() {
 fn regression11() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
 
@@ -608,7 +1088,7 @@ fn regression11() {
 fn regression12() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let table_def: TableDefinition<u64, u64> = TableDefinition::new("x");
 
@@ -629,7 +1109,7 @@ fn regression12() {
 fn regression13() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let table_def: MultimapTableDefinition<u64, &[u8]> = MultimapTableDefinition::new("x");
 
@@ -649,7 +1129,7 @@ fn regression13() {
 fn regression14() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let table_def: MultimapTableDefinition<u64, &[u8]> = MultimapTableDefinition::new("x");
 
@@ -688,12 +1168,10 @@ fn regression14() {
 fn regression17() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe {
-        Database::builder()
-            .set_write_strategy(WriteStrategy::Checksum)
-            .create(tmpfile.path())
-            .unwrap()
-    };
+    let db = Database::builder()
+        .set_write_strategy(WriteStrategy::Checksum)
+        .create(tmpfile.path())
+        .unwrap();
 
     let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
 
@@ -714,12 +1192,10 @@ fn regression17() {
 fn regression18() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe {
-        Database::builder()
-            .set_write_strategy(WriteStrategy::Checksum)
-            .create(tmpfile.path())
-            .unwrap()
-    };
+    let db = Database::builder()
+        .set_write_strategy(WriteStrategy::Checksum)
+        .create(tmpfile.path())
+        .unwrap();
 
     let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
 
@@ -785,12 +1261,10 @@ fn regression18() {
 fn regression19() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe {
-        Database::builder()
-            .set_write_strategy(WriteStrategy::Checksum)
-            .create(tmpfile.path())
-            .unwrap()
-    };
+    let db = Database::builder()
+        .set_write_strategy(WriteStrategy::Checksum)
+        .create(tmpfile.path())
+        .unwrap();
 
     let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
 
@@ -831,12 +1305,10 @@ fn regression19() {
 fn change_invalidate_savepoint() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe {
-        Database::builder()
-            .set_write_strategy(WriteStrategy::Checksum)
-            .create(tmpfile.path())
-            .unwrap()
-    };
+    let db = Database::builder()
+        .set_write_strategy(WriteStrategy::Checksum)
+        .create(tmpfile.path())
+        .unwrap();
     let tx = db.begin_write().unwrap();
     let savepoint = tx.savepoint().unwrap();
     tx.abort().unwrap();
@@ -853,39 +1325,33 @@ fn change_invalidate_savepoint() {
 fn create_open_mismatch() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe {
-        Database::builder()
-            .set_write_strategy(WriteStrategy::TwoPhase)
-            .create(tmpfile.path())
-            .unwrap()
-    };
+    let db = Database::builder()
+        .set_write_strategy(WriteStrategy::TwoPhase)
+        .create(tmpfile.path())
+        .unwrap();
     drop(db);
 
-    unsafe { Database::create(tmpfile.path()).unwrap() };
+    Database::create(tmpfile.path()).unwrap();
 
-    unsafe { Database::builder().create(tmpfile.path()).unwrap() };
+    Database::builder().create(tmpfile.path()).unwrap();
 }
 
 #[test]
 fn twophase_open() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe {
-        Database::builder()
-            .set_write_strategy(WriteStrategy::TwoPhase)
-            .create(tmpfile.path())
-            .unwrap()
-    };
+    let db = Database::builder()
+        .set_write_strategy(WriteStrategy::TwoPhase)
+        .create(tmpfile.path())
+        .unwrap();
     drop(db);
-    unsafe {
-        Database::open(tmpfile.path()).unwrap();
-    }
+    Database::open(tmpfile.path()).unwrap();
 }
 
 #[test]
 fn non_durable_read_isolation() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let mut write_txn = db.begin_write().unwrap();
     write_txn.set_durability(Durability::None);
     {
@@ -924,7 +1390,7 @@ fn non_durable_read_isolation() {
 #[test]
 fn range_query() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
     {
         let mut table = write_txn.open_table(U64_TABLE).unwrap();
@@ -959,7 +1425,7 @@ fn range_query() {
 #[test]
 fn range_query_reversed() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
     {
         let mut table = write_txn.open_table(U64_TABLE).unwrap();
@@ -1000,7 +1466,7 @@ fn range_query_reversed() {
 #[test]
 fn alias_table() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let write_txn = db.begin_write().unwrap();
     let table = write_txn.open_table(SLICE_TABLE).unwrap();
@@ -1015,7 +1481,7 @@ fn alias_table() {
 #[test]
 fn delete_table() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let y_def: MultimapTableDefinition<&[u8], &[u8]> = MultimapTableDefinition::new("y");
 
@@ -1045,7 +1511,7 @@ fn delete_table() {
 #[test]
 fn dropped_write() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let write_txn = db.begin_write().unwrap();
     {
@@ -1062,7 +1528,7 @@ fn dropped_write() {
 fn non_page_size_multiple() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let txn = db.begin_write().unwrap();
     let key = vec![0u8; 1024];
     let value = vec![0u8; 1];
@@ -1081,17 +1547,18 @@ fn non_page_size_multiple() {
 fn does_not_exist() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
     fs::remove_file(tmpfile.path()).unwrap();
-    let result = unsafe { Database::open(tmpfile.path()) };
-    if let Err(Error::Io(e)) = result {
-        assert!(matches!(e.kind(), ErrorKind::NotFound));
+    let result = Database::open(tmpfile.path());
+    if let Err(Error::Io { operation, source }) = result {
+        assert_eq!(operation, IoOperation::Open);
+        assert!(matches!(source.kind(), ErrorKind::NotFound));
     } else {
         panic!();
     }
 
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
 
-    let result = unsafe { Database::open(tmpfile.path()) };
-    if let Err(Error::Io(e)) = result {
+    let result = Database::open(tmpfile.path());
+    if let Err(Error::Io { source: e, .. }) = result {
         assert!(matches!(e.kind(), ErrorKind::InvalidData));
     } else {
         panic!();
@@ -1099,158 +1566,2579 @@ fn does_not_exist() {
 }
 
 #[test]
-fn wrong_types() {
+fn create_new() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
-
-    let definition: TableDefinition<u32, u32> = TableDefinition::new("x");
-    let wrong_definition: TableDefinition<u64, u64> = TableDefinition::new("x");
+    let result = Database::create_new(tmpfile.path());
+    if let Err(Error::Io { source: e, .. }) = result {
+        assert!(matches!(e.kind(), ErrorKind::AlreadyExists));
+    } else {
+        panic!();
+    }
 
+    fs::remove_file(tmpfile.path()).unwrap();
+    let db = Builder::new().create_new(tmpfile.path()).unwrap();
     let txn = db.begin_write().unwrap();
-    txn.open_table(definition).unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        table.insert(&0, &1).unwrap();
+    }
     txn.commit().unwrap();
+    drop(db);
 
-    let txn = db.begin_write().unwrap();
-    assert!(matches!(
-        txn.open_table(wrong_definition),
-        Err(Error::TableTypeMismatch(_))
-    ));
-    txn.abort().unwrap();
+    let db = Builder::new().open(tmpfile.path()).unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.get(&0).unwrap(), Some(1));
+    drop(table);
+    drop(txn);
+    drop(db);
 
+    let db = Database::open_or_create(tmpfile.path()).unwrap();
     let txn = db.begin_read().unwrap();
-    txn.open_table(definition).unwrap();
-    assert!(matches!(
-        txn.open_table(wrong_definition),
-        Err(Error::TableTypeMismatch(_))
-    ));
+    let table = txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.get(&0).unwrap(), Some(1));
 }
 
 #[test]
-fn tree_balance() {
-    const EXPECTED_ORDER: usize = 9;
-    fn expected_height(mut elements: usize) -> usize {
-        // Root may have only 2 entries
-        let mut height = 1;
-        elements /= 2;
+// The file is not truncated on Windows
+#[cfg(unix)]
+fn compact() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let mut db = Database::create(tmpfile.path()).unwrap();
+    let value = vec![0u8; 1024];
 
-        // Leaves may have only a single entry
-        height += 1;
-
-        // Each internal node half-full, plus 1 to round up
-        height += (elements as f32).log((EXPECTED_ORDER / 2) as f32) as usize + 1;
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        let mut slice_table = txn.open_table(SLICE_TABLE).unwrap();
+        for i in 0..10_000u64 {
+            table.insert(&i, &i).unwrap();
+            slice_table
+                .insert(i.to_le_bytes().as_slice(), value.as_slice())
+                .unwrap();
+        }
+    }
+    txn.commit().unwrap();
 
-        height
+    // Remove most of the data, leaving live pages scattered through the file
+    let txn = db.begin_write().unwrap();
+    {
+        let mut slice_table = txn.open_table(SLICE_TABLE).unwrap();
+        for i in 0..10_000u64 {
+            if i % 100 != 0 {
+                slice_table.remove(i.to_le_bytes().as_slice()).unwrap();
+            }
+        }
     }
+    txn.commit().unwrap();
+    let txn = db.begin_write().unwrap();
+    txn.commit().unwrap();
+
+    let file_size = tmpfile.as_file().metadata().unwrap().len();
+    assert!(db.compact().unwrap());
+    let compacted_size = tmpfile.as_file().metadata().unwrap().len();
+    assert!(compacted_size < file_size / 4);
+    // Everything has already been compacted
+    assert!(!db.compact().unwrap());
+
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 10_000);
+    assert_eq!(table.get(&1234).unwrap().unwrap(), 1234);
+    let slice_table = txn.open_table(SLICE_TABLE).unwrap();
+    assert_eq!(slice_table.len().unwrap(), 100);
+    assert_eq!(
+        slice_table
+            .get(1200u64.to_le_bytes().as_slice())
+            .unwrap()
+            .unwrap(),
+        value.as_slice()
+    );
+}
 
+#[test]
+#[cfg(unix)]
+fn vacuum() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let value = vec![0u8; 1024];
 
-    // One for the last table id counter, and one for the "x" -> TableDefinition entry
-    let num_internal_entries = 2;
+    let txn = db.begin_write().unwrap();
+    {
+        let mut slice_table = txn.open_table(SLICE_TABLE).unwrap();
+        for i in 0..10_000u64 {
+            slice_table
+                .insert(i.to_le_bytes().as_slice(), value.as_slice())
+                .unwrap();
+        }
+    }
+    txn.commit().unwrap();
 
-    // Pages are 4kb, so use a key size such that 9 keys will fit
-    let key_size = 410;
-    let db = unsafe { Database::builder().create(tmpfile.path()).unwrap() };
     let txn = db.begin_write().unwrap();
+    {
+        let mut slice_table = txn.open_table(SLICE_TABLE).unwrap();
+        for i in 0..10_000u64 {
+            if i % 100 != 0 {
+                slice_table.remove(i.to_le_bytes().as_slice()).unwrap();
+            }
+        }
+    }
+    txn.commit().unwrap();
+    let txn = db.begin_write().unwrap();
+    txn.commit().unwrap();
 
-    let elements = (EXPECTED_ORDER / 2).pow(2) as usize - num_internal_entries;
+    // Each call relocates a bounded number of pages, and the file shrinks gradually
+    let file_size = tmpfile.as_file().metadata().unwrap().len();
+    let mut relocated = 0;
+    for _ in 0..100 {
+        let pages = db.vacuum(4).unwrap();
+        assert!(pages <= 4);
+        relocated += pages;
+    }
+    assert!(relocated > 4);
+    let vacuumed_size = tmpfile.as_file().metadata().unwrap().len();
+    assert!(vacuumed_size < file_size / 4);
+
+    let txn = db.begin_read().unwrap();
+    let slice_table = txn.open_table(SLICE_TABLE).unwrap();
+    assert_eq!(slice_table.len().unwrap(), 100);
+    assert_eq!(
+        slice_table
+            .get(1200u64.to_le_bytes().as_slice())
+            .unwrap()
+            .unwrap(),
+        value.as_slice()
+    );
+}
 
+#[test]
+fn region_size() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Builder::new()
+        .set_region_size(64 * 1024)
+        .create(tmpfile.path())
+        .unwrap();
+
+    // Values larger than a region can't be stored
+    let txn = db.begin_write().unwrap();
     {
         let mut table = txn.open_table(SLICE_TABLE).unwrap();
-        for i in (0..elements).rev() {
-            let mut key = vec![0u8; key_size];
-            key[0..8].copy_from_slice(&(i as u64).to_le_bytes());
-            table.insert(&key, b"").unwrap();
+        let big_value = vec![0u8; 128 * 1024];
+        assert!(matches!(
+            table.insert(b"big".as_slice(), big_value.as_slice()),
+            Err(Error::ValueTooLarge(_))
+        ));
+    }
+    txn.abort().unwrap();
+
+    // Fill several regions
+    let value = vec![1u8; 10_000];
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        for i in 0..100 {
+            table.insert(&i, &i).unwrap();
+        }
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        for i in 0..100u64 {
+            table
+                .insert(i.to_le_bytes().as_slice(), value.as_slice())
+                .unwrap();
         }
     }
     txn.commit().unwrap();
+    drop(db);
 
-    let expected = expected_height(elements + num_internal_entries);
-    let txn = db.begin_write().unwrap();
-    let height = txn.stats().unwrap().tree_height();
-    assert!(
-        height <= expected,
-        "height={} expected={}",
-        height,
-        expected
+    // The region size is read from the file when it's reopened
+    let db = Database::open(tmpfile.path()).unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(SLICE_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 100);
+    assert_eq!(
+        table.get(7u64.to_le_bytes().as_slice()).unwrap().unwrap(),
+        value.as_slice()
     );
+}
 
-    let reduce_to = EXPECTED_ORDER / 2 - num_internal_entries;
+#[test]
+fn preallocate() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Builder::new()
+        .set_preallocate(true)
+        .create(tmpfile.path())
+        .unwrap();
+
+    let txn = db.begin_write().unwrap();
     {
         let mut table = txn.open_table(SLICE_TABLE).unwrap();
-        for i in 0..(elements - reduce_to) {
-            let mut key = vec![0u8; key_size];
-            key[0..8].copy_from_slice(&(i as u64).to_le_bytes());
-            table.remove(&key).unwrap();
+        for i in 0..100u64 {
+            table
+                .insert(i.to_le_bytes().as_slice(), [0u8; 100_000].as_slice())
+                .unwrap();
         }
     }
     txn.commit().unwrap();
 
-    let expected = expected_height(reduce_to + num_internal_entries);
-    let txn = db.begin_write().unwrap();
-    let height = txn.stats().unwrap().tree_height();
-    txn.abort().unwrap();
-    assert!(
-        height <= expected,
-        "height={} expected={}",
-        height,
-        expected
-    );
+    // All the space in the file is allocated, even though most of it has not been written
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = fs::metadata(tmpfile.path()).unwrap();
+        assert!(metadata.blocks() * 512 >= metadata.len());
+    }
 }
 
 #[test]
-fn database_lock() {
+fn check_integrity() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let result = unsafe { Database::create(tmpfile.path()) };
-    assert!(result.is_ok());
-    let result2 = unsafe { Database::open(tmpfile.path()) };
-    assert!(
-        matches!(result2, Err(Error::DatabaseAlreadyOpen)),
-        "{:?}",
-        result2
-    );
-    drop(result);
-    let result = unsafe { Database::open(tmpfile.path()) };
-    assert!(result.is_ok());
+    let db = Database::create(tmpfile.path()).unwrap();
+    let multimap_def: MultimapTableDefinition<u64, u64> = MultimapTableDefinition::new("m");
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        for i in 0..10_000u64 {
+            table.insert(&i, &i).unwrap();
+        }
+        let mut multimap = txn.open_multimap_table(multimap_def).unwrap();
+        for i in 0..1_000u64 {
+            multimap.insert(&0, &i).unwrap();
+        }
+        let mut slice_table = txn.open_table(SLICE_TABLE).unwrap();
+        slice_table
+            .insert(
+                b"key".as_slice(),
+                b"value which will be corrupted".as_slice(),
+            )
+            .unwrap();
+    }
+    txn.commit().unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        for i in 0..5_000u64 {
+            table.remove(&i).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+
+    let report = db.check_integrity().unwrap();
+    assert!(report.is_ok(), "{:?}", report.issues());
+    assert_eq!(report.tables_checked(), 3);
+    assert!(report.pages_checked() > 10);
+    drop(db);
+
+    // Flip a bit of the stored value
+    let mut data = fs::read(tmpfile.path()).unwrap();
+    let pattern = b"value which will be corrupted";
+    let offset = data
+        .windows(pattern.len())
+        .position(|window| window == pattern)
+        .unwrap();
+    data[offset] ^= 1;
+    fs::write(tmpfile.path(), data).unwrap();
+
+    let db = Database::open(tmpfile.path()).unwrap();
+    let report = db.check_integrity().unwrap();
+    assert_eq!(report.issues().len(), 1);
+    let issue = &report.issues()[0];
+    assert_eq!(issue.kind(), IntegrityIssueKind::ChecksumMismatch);
+    assert_eq!(issue.table(), Some("x"));
+    assert!(issue.offset().unwrap() <= offset as u64);
 }
 
 #[test]
-fn savepoint() {
+fn invalid_utf8() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
-    let definition: TableDefinition<u32, &str> = TableDefinition::new("x");
-
+    // Without checksums, so that only the invalid UTF-8 is detected
+    let db = Database::builder()
+        .set_write_strategy(WriteStrategy::TwoPhase)
+        .create(tmpfile.path())
+        .unwrap();
+    let definition: TableDefinition<&str, &str> = TableDefinition::new("table which is renamed");
     let txn = db.begin_write().unwrap();
     {
         let mut table = txn.open_table(definition).unwrap();
-        table.insert(&0, "hello").unwrap();
+        table.insert("key", "value which is corrupted").unwrap();
     }
     txn.commit().unwrap();
+    drop(db);
+
+    let overwrite = |pattern: &[u8]| {
+        let mut data = fs::read(tmpfile.path()).unwrap();
+        let offset = data
+            .windows(pattern.len())
+            .position(|window| window == pattern)
+            .unwrap();
+        data[offset] = 0xFF;
+        fs::write(tmpfile.path(), data).unwrap();
+    };
+
+    overwrite(b"value which is corrupted");
+    let db = Database::open(tmpfile.path()).unwrap();
+    let report = db.check_integrity().unwrap();
+    assert_eq!(report.issues().len(), 1);
+    let issue = &report.issues()[0];
+    assert_eq!(issue.kind(), IntegrityIssueKind::InvalidPage);
+    assert_eq!(issue.table(), Some("table which is renamed"));
+    assert!(issue.description().contains("UTF-8"));
+
+    // Reads of the corrupted value fail, instead of returning part of it
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(definition).unwrap();
+    assert!(matches!(table.get("key"), Err(Error::Corrupted { .. })));
+    assert!(matches!(table.first(), Err(Error::Corrupted { .. })));
+    assert!(matches!(
+        table.iter().unwrap().try_next(),
+        Err(Error::Corrupted { .. })
+    ));
+    drop(table);
+    drop(txn);
+    drop(db);
 
+    overwrite(b"table which is renamed");
+    let db = Database::open(tmpfile.path()).unwrap();
+    let txn = db.begin_read().unwrap();
+    match txn.list_tables() {
+        Err(Error::Corrupted { message, .. }) => assert!(message.contains("UTF-8"), "{}", message),
+        other => panic!("expected corruption, got {:?}", other.err()),
+    }
+}
+
+fn corrupt_value(path: &std::path::Path, pattern: &[u8]) {
+    let mut data = fs::read(path).unwrap();
+    let offset = data
+        .windows(pattern.len())
+        .position(|window| window == pattern)
+        .unwrap();
+    data[offset] ^= 1;
+    fs::write(path, data).unwrap();
+}
+
+#[test]
+fn repair() {
+    // Corruption of the latest commit is repaired by rolling back to the previous one
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
     let txn = db.begin_write().unwrap();
-    let savepoint = txn.savepoint().unwrap();
     {
-        let mut table = txn.open_table(definition).unwrap();
-        table.remove(&0).unwrap();
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        table.insert(b"a".as_slice(), b"first".as_slice()).unwrap();
+    }
+    txn.commit().unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        table
+            .insert(b"b".as_slice(), b"value of the second commit".as_slice())
+            .unwrap();
     }
     txn.commit().unwrap();
+    drop(db);
 
-    let mut txn = db.begin_write().unwrap();
-    let savepoint2 = txn.savepoint().unwrap();
+    corrupt_value(tmpfile.path(), b"value of the second commit");
+    let report = Database::repair(tmpfile.path()).unwrap();
+    assert!(!report.is_lossless());
+    assert!(report.discarded_transaction_id().unwrap() > report.transaction_id());
+    assert!(report.discarded_tables().is_empty());
 
-    txn.restore_savepoint(&savepoint).unwrap();
+    let db = Database::open(tmpfile.path()).unwrap();
+    assert!(db.check_integrity().unwrap().is_ok());
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(SLICE_TABLE).unwrap();
+    assert_eq!(table.get(b"a".as_slice()).unwrap().unwrap(), b"first");
+    assert!(table.get(b"b".as_slice()).unwrap().is_none());
+    drop(table);
+    drop(txn);
+    drop(db);
+
+    // A table which is corrupted in every commit is discarded, and the others are salvaged,
+    // including the subtrees of multimap tables, whose pages remain allocated
+    let multimap_def: MultimapTableDefinition<u64, u64> = MultimapTableDefinition::new("m");
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut multimap = txn.open_multimap_table(multimap_def).unwrap();
+        for i in 0..1_000u64 {
+            multimap.insert(&0, &i).unwrap();
+        }
+        let mut table = txn.open_table(SLICE_TABLE2).unwrap();
+        table
+            .insert(b"a".as_slice(), b"value which will be corrupted".as_slice())
+            .unwrap();
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        table.insert(b"a".as_slice(), b"first".as_slice()).unwrap();
+    }
+    txn.commit().unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        table.insert(b"b".as_slice(), b"second".as_slice()).unwrap();
+    }
+    txn.commit().unwrap();
+    drop(db);
 
+    corrupt_value(tmpfile.path(), b"value which will be corrupted");
+    let report = Database::repair(tmpfile.path()).unwrap();
+    assert!(report.discarded_transaction_id().is_none());
+    assert_eq!(report.discarded_tables(), ["y".to_string()]);
+
+    let db = Database::open(tmpfile.path()).unwrap();
+    assert!(db.check_integrity().unwrap().is_ok());
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(SLICE_TABLE).unwrap();
+    assert_eq!(table.get(b"b".as_slice()).unwrap().unwrap(), b"second");
     assert!(matches!(
-        txn.restore_savepoint(&savepoint2).err().unwrap(),
-        Error::InvalidSavepoint
+        txn.open_table(SLICE_TABLE2),
+        Err(Error::TableDoesNotExist(_))
     ));
+    let multimap = txn.open_multimap_table(multimap_def).unwrap();
+    assert_eq!(multimap.get(&0).unwrap().count(), 1_000);
+
+    // An intact database is repaired without losing anything
+    drop(table);
+    drop(txn);
+    drop(db);
+    assert!(Database::repair(tmpfile.path()).unwrap().is_lossless());
+}
+
+#[test]
+fn verify_checksums_on_read() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        table
+            .insert(b"a".as_slice(), b"value which will be corrupted".as_slice())
+            .unwrap();
+    }
     txn.commit().unwrap();
+    drop(db);
+
+    corrupt_value(tmpfile.path(), b"value which will be corrupted");
 
+    // By default, the corruption is not detected
+    let db = Database::open(tmpfile.path()).unwrap();
     let txn = db.begin_read().unwrap();
-    let table = txn.open_table(definition).unwrap();
-    assert_eq!(table.get(&0).unwrap().unwrap(), "hello");
+    let table = txn.open_table(SLICE_TABLE).unwrap();
+    assert!(table.get(b"a".as_slice()).unwrap().is_some());
+    drop(table);
+    drop(txn);
+    drop(db);
 
-    // Test that savepoints can be used multiple times
-    let mut txn = db.begin_write().unwrap();
-    txn.restore_savepoint(&savepoint).unwrap();
+    let db = Database::builder()
+        .set_verify_checksums_on_read(true)
+        .open(tmpfile.path())
+        .unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(SLICE_TABLE).unwrap();
+    assert!(matches!(
+        table.get(b"a".as_slice()),
+        Err(Error::Corrupted { .. })
+    ));
+    assert!(matches!(table.first(), Err(Error::Corrupted { .. })));
+    let mut iter = table.iter().unwrap();
+    assert!(matches!(iter.try_next(), Err(Error::Corrupted { .. })));
+}
+
+#[test]
+fn scrub() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let multimap_def: MultimapTableDefinition<u64, u64> = MultimapTableDefinition::new("m");
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        for i in 0..10_000 {
+            table.insert(&i, &i).unwrap();
+        }
+        // Enough values to be stored in a subtree
+        let mut multimap = txn.open_multimap_table(multimap_def).unwrap();
+        for i in 0..1_000 {
+            multimap.insert(&0, &i).unwrap();
+        }
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        table
+            .insert(b"a".as_slice(), b"value which will be corrupted".as_slice())
+            .unwrap();
+    }
     txn.commit().unwrap();
+    let txn = db.begin_write().unwrap();
+    let stats = txn.stats().unwrap();
+    let pages = stats.leaf_pages() + stats.branch_pages();
+    txn.abort().unwrap();
+
+    let mut scrubber = db.scrubber();
+    let mut calls = 1;
+    while !scrubber.scrub(10).unwrap() {
+        calls += 1;
+    }
+    assert!(calls > 1);
+    // The stats don't include the subtree of the multimap table
+    let verified = scrubber.pages_verified();
+    assert!(verified > pages as u64);
+    assert_eq!(scrubber.passes_completed(), 1);
+    assert!(scrubber.scrub(usize::MAX).unwrap());
+    assert_eq!(scrubber.pages_verified(), 2 * verified);
+    assert_eq!(scrubber.passes_completed(), 2);
+    drop(scrubber);
+    drop(db);
+
+    corrupt_value(tmpfile.path(), b"value which will be corrupted");
+    let db = Database::open(tmpfile.path()).unwrap();
+    let mut scrubber = db.scrubber();
+    match scrubber.scrub(usize::MAX) {
+        Err(Error::Corrupted { message, page, .. }) => {
+            assert!(message.contains("table 'x'"), "{}", message);
+            assert!(page.is_some());
+        }
+        _ => panic!(),
+    }
+    assert_eq!(scrubber.passes_completed(), 0);
+}
+
+// Opens and reads the database, returning any error instead of panicking
+fn read_untrusted(path: &std::path::Path) -> Result<(), Error> {
+    let db = Database::builder()
+        .set_verify_checksums_on_read(true)
+        .open(path)?;
+    let txn = db.begin_read()?;
+    for name in txn.list_tables()? {
+        if name == U64_TABLE.name() {
+            let table = txn.open_table(U64_TABLE)?;
+            let mut iter = table.iter()?;
+            while iter.try_next()?.is_some() {}
+        }
+    }
+    drop(txn);
+    let txn = db.begin_write()?;
+    {
+        let mut table = txn.open_table(U64_TABLE)?;
+        table.insert(&0, &0)?;
+    }
+    txn.commit()?;
+
+    Ok(())
+}
+
+#[test]
+fn open_corrupted() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    for i in 0..3 {
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(U64_TABLE).unwrap();
+            for j in 0..1000 {
+                table.insert(&(i * 1000 + j), &j).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+    }
+    drop(db);
+    let original = fs::read(tmpfile.path()).unwrap();
+
+    // Truncated file
+    fs::write(tmpfile.path(), &original[..original.len() / 2]).unwrap();
+    assert!(matches!(
+        Database::open(tmpfile.path()),
+        Err(Error::Corrupted { .. })
+    ));
+    fs::write(tmpfile.path(), &original[..20]).unwrap();
+    assert!(matches!(
+        Database::open(tmpfile.path()),
+        Err(Error::Corrupted { .. })
+    ));
+
+    // Invalid page size in the header
+    let mut data = original.clone();
+    data[12..16].copy_from_slice(&3u32.to_le_bytes());
+    fs::write(tmpfile.path(), &data).unwrap();
+    match Database::open(tmpfile.path()) {
+        Err(Error::Corrupted {
+            message, offset, ..
+        }) => {
+            assert!(message.contains("page size"), "{}", message);
+            assert_eq!(offset, Some(12));
+        }
+        _ => panic!(),
+    }
+
+    // Arbitrary corruption must never cause a panic
+    let mut rng = make_rng();
+    for _ in 0..100 {
+        let mut data = original.clone();
+        for _ in 0..rng.gen_range(1..4) {
+            let i = rng.gen_range(0..data.len());
+            data[i] = rng.gen();
+        }
+        fs::write(tmpfile.path(), &data).unwrap();
+        let _ = read_untrusted(tmpfile.path());
+    }
+}
+
+#[test]
+fn copy_to() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let multimap_def: MultimapTableDefinition<u64, u64> = MultimapTableDefinition::new("m");
+    let value = vec![0u8; 1024];
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        for i in 0..10_000u64 {
+            table
+                .insert(i.to_le_bytes().as_slice(), value.as_slice())
+                .unwrap();
+        }
+        // Enough values to be stored in a subtree
+        let mut multimap = txn.open_multimap_table(multimap_def).unwrap();
+        for i in 0..1_000u64 {
+            multimap.insert(&0, &i).unwrap();
+        }
+        multimap.insert(&1, &1).unwrap();
+    }
+    txn.commit().unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        for i in 100..10_000u64 {
+            table.remove(i.to_le_bytes().as_slice()).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+
+    let copy = NamedTempFile::new().unwrap();
+    // The destination must not exist
+    assert!(matches!(
+        db.copy_to(copy.path()),
+        Err(Error::Io { source: err, .. }) if err.kind() == ErrorKind::AlreadyExists
+    ));
+    let copy_path = copy.path().with_extension("copy");
+    db.copy_to(&copy_path).unwrap();
+    drop(db);
+    assert!(
+        fs::metadata(&copy_path).unwrap().len() < tmpfile.as_file().metadata().unwrap().len() / 4
+    );
+
+    let db = Database::open(&copy_path).unwrap();
+    {
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(SLICE_TABLE).unwrap();
+        assert_eq!(table.len().unwrap(), 100);
+        assert_eq!(
+            table.get(99u64.to_le_bytes().as_slice()).unwrap().unwrap(),
+            value.as_slice()
+        );
+        let multimap = txn.open_multimap_table(multimap_def).unwrap();
+        let values: Vec<u64> = multimap.get(&0).unwrap().collect();
+        assert_eq!(values, (0..1_000u64).collect::<Vec<u64>>());
+        assert_eq!(multimap.get(&1).unwrap().count(), 1);
+    }
+
+    // The copy can be modified
+    let txn = db.begin_write().unwrap();
+    {
+        let mut multimap = txn.open_multimap_table(multimap_def).unwrap();
+        multimap.insert(&0, &1_000).unwrap();
+    }
+    txn.commit().unwrap();
+    drop(db);
+    fs::remove_file(&copy_path).unwrap();
+}
+
+#[test]
+fn truncated_separators() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let str_definition: TableDefinition<&str, u64> = TableDefinition::new("str");
+
+    // Keys differ within their first few bytes, so branch pages only need to store that much of
+    // each separator
+    let slice_key = |i: u64| {
+        let mut key = vec![0u8; 400];
+        key[0..8].copy_from_slice(&i.to_be_bytes());
+        key
+    };
+    // Multi-byte characters, so that separators must be extended to a character boundary
+    let str_key = |i: u32| format!("{}{}", char::from_u32(0x4E00 + i).unwrap(), "x".repeat(400));
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        for i in (0..2_000u64).rev() {
+            table.insert(&slice_key(i), b"").unwrap();
+        }
+        let mut table = txn.open_table(str_definition).unwrap();
+        for i in 0..2_000u32 {
+            table.insert(str_key(i).as_str(), &u64::from(i)).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+
+    let txn = db.begin_write().unwrap();
+    // Only about 9 separators of 400 bytes would fit in a branch page, which would require a height
+    // of 6, including the table tree
+    assert!(txn.stats().unwrap().tree_height() <= 4);
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        for i in (0..2_000u64).step_by(3) {
+            table.remove(slice_key(i).as_slice()).unwrap();
+        }
+        let mut table = txn.open_table(str_definition).unwrap();
+        for i in (0..2_000u32).step_by(3) {
+            table.remove(str_key(i).as_str()).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+
+    let report = db.check_integrity().unwrap();
+    assert!(report.is_ok(), "{:?}", report.issues());
+
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(SLICE_TABLE).unwrap();
+    for i in 0..2_000u64 {
+        assert_eq!(
+            table.get(slice_key(i).as_slice()).unwrap().is_some(),
+            i % 3 != 0
+        );
+    }
+    let table = txn.open_table(str_definition).unwrap();
+    let values: Vec<u64> = table.iter().unwrap().map(|(_, value)| value).collect();
+    let expected: Vec<u64> = (0..2_000u64).filter(|i| i % 3 != 0).collect();
+    assert_eq!(values, expected);
+    for i in 0..2_000u32 {
+        assert_eq!(
+            table.get(str_key(i).as_str()).unwrap().is_some(),
+            i % 3 != 0
+        );
+    }
+}
+
+#[test]
+fn large_value_threshold() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::builder()
+        .set_large_value_threshold(1000)
+        .create(tmpfile.path())
+        .unwrap();
+    let sequential: TableDefinition<u64, &[u8]> = TableDefinition::new("sequential");
+    let shuffled: TableDefinition<u64, &[u8]> =
+        TableDefinition::new("shuffled").with_entry_counts();
+    let value = |i: u64| {
+        if i % 10 == 5 {
+            vec![i as u8; 2000]
+        } else {
+            vec![i as u8; 8]
+        }
+    };
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(sequential).unwrap();
+        for i in 0..100 {
+            table.insert(&i, value(i).as_slice()).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+
+    // Each large value has a leaf of its own, and the small values around them fill 11 more
+    let txn = db.begin_write().unwrap();
+    assert_eq!(txn.stats().unwrap().leaf_pages(), 21);
+
+    let mut keys: Vec<u64> = (0..100).collect();
+    keys.shuffle(&mut make_rng());
+    {
+        let mut table = txn.open_table(shuffled).unwrap();
+        for i in keys.iter() {
+            table.insert(i, value(*i).as_slice()).unwrap();
+        }
+        for i in keys.iter().filter(|i| *i % 3 == 0) {
+            table.remove(i).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+
+    let report = db.check_integrity().unwrap();
+    assert!(report.is_ok(), "{:?}", report.issues());
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(sequential).unwrap();
+    for (i, (key, stored)) in table.iter().unwrap().enumerate() {
+        assert_eq!(key, i as u64);
+        assert_eq!(stored, value(key).as_slice());
+    }
+    let table = txn.open_table(shuffled).unwrap();
+    let expected: Vec<u64> = (0..100).filter(|i| i % 3 != 0).collect();
+    let mut stored_keys = vec![];
+    for (key, stored) in table.iter().unwrap() {
+        assert_eq!(stored, value(key).as_slice());
+        stored_keys.push(key);
+    }
+    assert_eq!(stored_keys, expected);
+    assert_eq!(table.nth(60).unwrap().unwrap().0, expected[60]);
+}
+
+#[test]
+fn sequential_inserts_fill_pages() {
+    let definition: TableDefinition<u64, u64> = TableDefinition::new("x").with_entry_counts();
+
+    let loaded_file: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(loaded_file.path()).unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(definition).unwrap();
+        let entries: Vec<(u64, u64)> = (0..100_000).map(|i| (i, i)).collect();
+        table
+            .load_sorted(entries.iter().map(|(k, v)| (k, v)))
+            .unwrap();
+    }
+    txn.commit().unwrap();
+    let txn = db.begin_write().unwrap();
+    let packed_leaves = txn.stats().unwrap().leaf_pages();
+    txn.abort().unwrap();
+
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    for chunk in 0..10 {
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(definition).unwrap();
+            for i in (chunk * 10_000)..((chunk + 1) * 10_000) {
+                table.insert(&i, &i).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+    }
+
+    // Each leaf is left full when the next one is started, so the tree is as small as if it had
+    // been bulk loaded
+    let txn = db.begin_write().unwrap();
+    assert!(txn.stats().unwrap().leaf_pages() <= packed_leaves + 10);
+    txn.abort().unwrap();
+
+    let report = db.check_integrity().unwrap();
+    assert!(report.is_ok(), "{:?}", report.issues());
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(definition).unwrap();
+    assert_eq!(table.len().unwrap(), 100_000);
+    for (i, (key, value)) in table.iter().unwrap().enumerate() {
+        assert_eq!(key, i as u64);
+        assert_eq!(value, i as u64);
+    }
+    assert_eq!(table.nth(54_321).unwrap().unwrap().0, 54_321);
+}
+
+#[test]
+fn append() {
+    let definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x").with_entry_counts();
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let value = vec![7u8; 50];
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(definition).unwrap();
+        for i in 0..10_000u64 {
+            table.append(&(2 * i), value.as_slice()).unwrap();
+            // Reads in between appends see all the keys
+            if i % 1000 == 0 {
+                assert_eq!(table.len().unwrap(), i as usize + 1);
+                assert_eq!(table.last().unwrap().unwrap().0, 2 * i);
+                assert_eq!(table.nth(i as usize).unwrap().unwrap().0, 2 * i);
+            }
+        }
+        assert!(matches!(
+            table.append(&19_998, value.as_slice()),
+            Err(Error::KeysNotSorted(_))
+        ));
+        assert!(matches!(
+            table.append(&5, value.as_slice()),
+            Err(Error::KeysNotSorted(_))
+        ));
+        assert_eq!(table.len().unwrap(), 10_000);
+
+        // Keys which aren't appended, and snapshots, end the appends until the next one
+        table.insert(&5, value.as_slice()).unwrap();
+        let snapshot = table.snapshot_range::<u64>(..).unwrap();
+        for i in 10_000..11_000u64 {
+            table.insert(&(2 * i), value.as_slice()).unwrap();
+        }
+        assert_eq!(snapshot.count(), 10_001);
+    }
+    {
+        let mut table = txn.open_table(definition).unwrap();
+        assert_eq!(table.len().unwrap(), 11_001);
+        for i in 11_000..12_000u64 {
+            table.append(&(2 * i), value.as_slice()).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+
+    let report = db.check_integrity().unwrap();
+    assert!(report.is_ok(), "{:?}", report.issues());
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(definition).unwrap();
+    assert_eq!(table.len().unwrap(), 12_001);
+    assert_eq!(table.nth(3).unwrap().unwrap().0, 5);
+    assert_eq!(table.nth(11_000).unwrap().unwrap().0, 21_998);
+    assert_eq!(table.last().unwrap().unwrap().0, 23_998);
+    assert_eq!(table.get(&12_000).unwrap().unwrap(), value.as_slice());
+}
+
+// Iterators and replaced values in a write transaction reuse allocations from earlier ones
+#[test]
+fn write_txn_reuses_allocations() {
+    let definition: TableDefinition<u64, u64> = TableDefinition::new("x");
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(definition).unwrap();
+        for i in 0..10_000u64 {
+            table.insert(&i, &i).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(definition).unwrap();
+        for i in (0..10_000u64).step_by(7) {
+            // Iterators which are dropped part way through, in both directions, and overlapping
+            let mut forward = table.range(i..).unwrap();
+            let mut backward = table.range(..=i).unwrap().rev();
+            assert_eq!(forward.next().unwrap().1, i);
+            assert_eq!(backward.next().unwrap().1, i);
+            if i > 0 {
+                assert_eq!(backward.next().unwrap().1, i - 1);
+            }
+            drop(forward);
+            drop(backward);
+            let old = table.insert(&i, &(i + 1)).unwrap().unwrap();
+            assert_eq!(old.to_value(), i);
+        }
+        let drained: Vec<(u64, u64)> = table
+            .drain(5_000..5_010)
+            .unwrap()
+            .map(|(k, v)| (k.to_value(), v.to_value()))
+            .collect();
+        assert_eq!(drained.len(), 10);
+        assert_eq!(drained[1], (5_001, 5_001));
+        assert_eq!(drained[5], (5_005, 5_006));
+        assert_eq!(table.len().unwrap(), 9_990);
+    }
+    txn.commit().unwrap();
+
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(definition).unwrap();
+    assert_eq!(table.get(&7).unwrap().unwrap(), 8);
+    assert_eq!(table.get(&8).unwrap().unwrap(), 8);
+}
+
+#[test]
+fn wrong_types() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    let definition: TableDefinition<u32, u32> = TableDefinition::new("x");
+    let wrong_definition: TableDefinition<u64, u64> = TableDefinition::new("x");
+
+    let txn = db.begin_write().unwrap();
+    txn.open_table(definition).unwrap();
+    txn.commit().unwrap();
+
+    let txn = db.begin_write().unwrap();
+    match txn.open_table(wrong_definition) {
+        Err(Error::TableTypeMismatch {
+            table,
+            expected,
+            actual,
+        }) => {
+            assert_eq!(table, "x");
+            assert_eq!(expected, "Table<u64, u64>");
+            assert_eq!(actual, "Table<u32, u32>");
+        }
+        _ => panic!(),
+    }
+    txn.abort().unwrap();
+
+    let txn = db.begin_read().unwrap();
+    txn.open_table(definition).unwrap();
+    assert!(matches!(
+        txn.open_table(wrong_definition),
+        Err(Error::TableTypeMismatch { .. })
+    ));
+}
+
+#[test]
+fn tree_balance() {
+    const EXPECTED_ORDER: usize = 9;
+    fn expected_height(mut elements: usize) -> usize {
+        // Root may have only 2 entries
+        let mut height = 1;
+        elements /= 2;
+
+        // Leaves may have only a single entry
+        height += 1;
+
+        // Each internal node half-full, plus 1 to round up
+        height += (elements as f32).log((EXPECTED_ORDER / 2) as f32) as usize + 1;
+
+        height
+    }
+
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+
+    // One for the last table id counter, and one for the "x" -> TableDefinition entry
+    let num_internal_entries = 2;
+
+    // Pages are 4kb, so use a key size such that 9 keys will fit
+    let key_size = 410;
+    let db = Database::builder().create(tmpfile.path()).unwrap();
+    let txn = db.begin_write().unwrap();
+
+    let elements = (EXPECTED_ORDER / 2).pow(2) as usize - num_internal_entries;
+
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        for i in (0..elements).rev() {
+            let mut key = vec![0u8; key_size];
+            key[0..8].copy_from_slice(&(i as u64).to_le_bytes());
+            table.insert(&key, b"").unwrap();
+        }
+    }
+    txn.commit().unwrap();
+
+    let expected = expected_height(elements + num_internal_entries);
+    let txn = db.begin_write().unwrap();
+    let height = txn.stats().unwrap().tree_height();
+    assert!(
+        height <= expected,
+        "height={} expected={}",
+        height,
+        expected
+    );
+
+    let reduce_to = EXPECTED_ORDER / 2 - num_internal_entries;
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        for i in 0..(elements - reduce_to) {
+            let mut key = vec![0u8; key_size];
+            key[0..8].copy_from_slice(&(i as u64).to_le_bytes());
+            table.remove(&key).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+
+    let expected = expected_height(reduce_to + num_internal_entries);
+    let txn = db.begin_write().unwrap();
+    let height = txn.stats().unwrap().tree_height();
+    txn.abort().unwrap();
+    assert!(
+        height <= expected,
+        "height={} expected={}",
+        height,
+        expected
+    );
+}
+
+#[test]
+fn database_lock() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let result = Database::create(tmpfile.path());
+    assert!(result.is_ok());
+    let result2 = Database::open(tmpfile.path());
+    assert!(
+        matches!(result2, Err(Error::LockContention)),
+        "{:?}",
+        result2
+    );
+    // A failed open must not resize the file
+    let len = fs::metadata(tmpfile.path()).unwrap().len();
+    let result3 = Builder::new()
+        .set_initial_size(len * 4)
+        .create(tmpfile.path());
+    assert!(matches!(result3, Err(Error::LockContention)));
+    assert_eq!(fs::metadata(tmpfile.path()).unwrap().len(), len);
+    drop(result);
+    let result = Database::open(tmpfile.path());
+    assert!(result.is_ok());
+}
+
+#[test]
+fn savepoint() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let definition: TableDefinition<u32, &str> = TableDefinition::new("x");
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(definition).unwrap();
+        table.insert(&0, "hello").unwrap();
+    }
+    txn.commit().unwrap();
+
+    let txn = db.begin_write().unwrap();
+    let savepoint = txn.savepoint().unwrap();
+    {
+        let mut table = txn.open_table(definition).unwrap();
+        table.remove(&0).unwrap();
+    }
+    txn.commit().unwrap();
+
+    let mut txn = db.begin_write().unwrap();
+    let savepoint2 = txn.savepoint().unwrap();
+
+    txn.restore_savepoint(&savepoint).unwrap();
+
+    assert!(matches!(
+        txn.restore_savepoint(&savepoint2).err().unwrap(),
+        Error::InvalidSavepoint
+    ));
+    txn.commit().unwrap();
+
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(definition).unwrap();
+    assert_eq!(table.get(&0).unwrap().unwrap(), "hello");
+
+    // Test that savepoints can be used multiple times
+    let mut txn = db.begin_write().unwrap();
+    txn.restore_savepoint(&savepoint).unwrap();
+    txn.commit().unwrap();
+}
+
+#[test]
+fn persistent_savepoint() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let definition: TableDefinition<u32, &str> = TableDefinition::new("x");
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(definition).unwrap();
+        table.insert(&0, "hello").unwrap();
+    }
+    txn.commit().unwrap();
+
+    let txn = db.begin_write().unwrap();
+    let id = txn.persistent_savepoint().unwrap();
+    let discarded_id = txn.persistent_savepoint().unwrap();
+    {
+        let mut table = txn.open_table(definition).unwrap();
+        table.remove(&0).unwrap();
+        table.insert(&1, "world").unwrap();
+    }
+    txn.commit().unwrap();
+
+    // The savepoint table is internal
+    let txn = db.begin_write().unwrap();
+    assert_eq!(txn.list_tables().unwrap().count(), 1);
+    assert!(txn.delete_persistent_savepoint(discarded_id).unwrap());
+    assert!(!txn.delete_persistent_savepoint(discarded_id).unwrap());
+    txn.commit().unwrap();
+    drop(db);
+
+    let db = Database::open(tmpfile.path()).unwrap();
+    let mut txn = db.begin_write().unwrap();
+    assert_eq!(
+        txn.list_persistent_savepoints()
+            .unwrap()
+            .collect::<Vec<_>>(),
+        vec![id]
+    );
+    assert!(matches!(
+        txn.get_persistent_savepoint(discarded_id).err().unwrap(),
+        Error::InvalidSavepoint
+    ));
+    let savepoint = txn.get_persistent_savepoint(id).unwrap();
+    txn.restore_savepoint(&savepoint).unwrap();
+    txn.commit().unwrap();
+    drop(savepoint);
+
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(definition).unwrap();
+    assert_eq!(table.get(&0).unwrap().unwrap(), "hello");
+    assert!(table.get(&1).unwrap().is_none());
+    drop(table);
+    drop(txn);
+
+    // The savepoint survives being restored, and can be used again
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(definition).unwrap();
+        table.insert(&2, "again").unwrap();
+    }
+    txn.commit().unwrap();
+    let mut txn = db.begin_write().unwrap();
+    let savepoint = txn.get_persistent_savepoint(id).unwrap();
+    txn.restore_savepoint(&savepoint).unwrap();
+    assert!(txn.delete_persistent_savepoint(id).unwrap());
+    txn.commit().unwrap();
+    drop(savepoint);
+
+    let txn = db.begin_write().unwrap();
+    assert_eq!(txn.list_persistent_savepoints().unwrap().count(), 0);
+    {
+        let table = txn.open_table(definition).unwrap();
+        assert_eq!(table.len().unwrap(), 1);
+    }
+    txn.commit().unwrap();
+}
+
+#[test]
+fn upgrade_file_format_107() {
+    // Written by redb 0.10.0, which used file format version 107. It contains a table mapping each
+    // i in 0..100 to "value {i}", and a multimap table mapping "even" and "odd" to the even and
+    // odd numbers less than 20
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    fs::write(tmpfile.path(), include_bytes!("fixtures/format_107.redb")).unwrap();
+    let table_definition: TableDefinition<u64, &str> = TableDefinition::new("table");
+    let multimap_definition: MultimapTableDefinition<&str, u64> =
+        MultimapTableDefinition::new("multimap");
+
+    assert_eq!(Database::file_format_version(tmpfile.path()).unwrap(), 107);
+    assert!(matches!(
+        Database::open(tmpfile.path()),
+        Err(Error::UpgradeRequired(107))
+    ));
+    assert!(Database::upgrade(tmpfile.path()).unwrap());
+    assert!(!Database::upgrade(tmpfile.path()).unwrap());
+
+    let db = Database::open(tmpfile.path()).unwrap();
+    assert!(db.check_integrity().unwrap().is_ok());
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(table_definition).unwrap();
+        assert_eq!(table.len().unwrap(), 100);
+        for (key, value) in table.iter().unwrap() {
+            assert_eq!(value, format!("value {}", key));
+        }
+        table.insert(&100, "value 100").unwrap();
+
+        let multimap = txn.open_multimap_table(multimap_definition).unwrap();
+        let even: Vec<u64> = multimap.get("even").unwrap().collect();
+        assert_eq!(even, (0..20).step_by(2).collect::<Vec<_>>());
+        let odd: Vec<u64> = multimap.get("odd").unwrap().collect();
+        assert_eq!(odd, (1..20).step_by(2).collect::<Vec<_>>());
+    }
+    txn.commit().unwrap();
+    drop(db);
+
+    let db = Database::open(tmpfile.path()).unwrap();
+    assert!(db.check_integrity().unwrap().is_ok());
+}
+
+#[test]
+fn persistent_savepoint_survives_repair() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        for i in 0..1000u64 {
+            table.insert(&i, &i).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+
+    let txn = db.begin_write().unwrap();
+    let id = txn.persistent_savepoint().unwrap();
+    txn.commit().unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        table.clear().unwrap();
+    }
+    txn.commit().unwrap();
+
+    // Copying the file while the database is open simulates a crash
+    let crashed: NamedTempFile = NamedTempFile::new().unwrap();
+    fs::copy(tmpfile.path(), crashed.path()).unwrap();
+    drop(db);
+
+    let db = Database::open(crashed.path()).unwrap();
+    assert!(db.check_integrity().unwrap().is_ok());
+    // The pages of the savepoint's snapshot must not be reused
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        for i in 0..1000u64 {
+            table.insert(&i, &(i + 1)).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+
+    let mut txn = db.begin_write().unwrap();
+    assert_eq!(
+        txn.list_persistent_savepoints()
+            .unwrap()
+            .collect::<Vec<_>>(),
+        vec![id]
+    );
+    let savepoint = txn.get_persistent_savepoint(id).unwrap();
+    txn.restore_savepoint(&savepoint).unwrap();
+    txn.commit().unwrap();
+    drop(savepoint);
+
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 1000);
+    for (key, value) in table.iter().unwrap() {
+        assert_eq!(key, value);
+    }
+    drop(table);
+    drop(txn);
+
+    // Once the savepoint is deleted, the pages which only it used are freed
+    let txn = db.begin_write().unwrap();
+    assert!(txn.delete_persistent_savepoint(id).unwrap());
+    txn.commit().unwrap();
+    db.begin_write().unwrap().commit().unwrap();
+    assert!(db.check_integrity().unwrap().is_ok());
+}
+
+#[test]
+fn owned_transactions() {
+    struct Snapshot {
+        db: Arc<Database>,
+        txn: OwnedReadTransaction,
+    }
+
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Arc::new(Database::create(tmpfile.path()).unwrap());
+    let definition: TableDefinition<u32, &str> = TableDefinition::new("x");
+
+    let mut txn: OwnedWriteTransaction = db.begin_write_owned().unwrap();
+    txn.set_durability(Durability::Immediate);
+    {
+        let mut table = txn.open_table(definition).unwrap();
+        table.insert(&0, "hello").unwrap();
+    }
+    txn.commit().unwrap();
+
+    let snapshot = Snapshot {
+        db: db.clone(),
+        txn: db.begin_read_owned().unwrap(),
+    };
+    drop(db);
+
+    let txn = snapshot.db.begin_write_owned().unwrap();
+    {
+        let mut table = txn.open_table(definition).unwrap();
+        table.insert(&0, "world").unwrap();
+    }
+    txn.commit().unwrap();
+
+    let table = snapshot.txn.open_table(definition).unwrap();
+    assert_eq!(table.get(&0).unwrap().unwrap(), "hello");
+    drop(table);
+
+    // A clone keeps the database alive, after the original and every other reference are dropped
+    let cloned = snapshot.txn.clone();
+    drop(snapshot);
+    let table = cloned.open_table(definition).unwrap();
+    assert_eq!(table.get(&0).unwrap().unwrap(), "hello");
+    drop(table);
+    cloned.close().unwrap();
+}
+
+#[test]
+fn shared_read_transaction() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let definition: TableDefinition<u32, u32> = TableDefinition::new("x");
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(definition).unwrap();
+        for i in 0..100 {
+            table.insert(&i, &i).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let cloned = read_txn.clone();
+
+    let txn = db.begin_write().unwrap();
+    txn.delete_table(definition).unwrap();
+    txn.commit().unwrap();
+
+    thread::scope(|s| {
+        for i in 0..4 {
+            let read_txn = &read_txn;
+            let cloned = cloned.clone();
+            s.spawn(move || {
+                let table = read_txn.open_table(definition).unwrap();
+                assert_eq!(table.get(&i).unwrap().unwrap(), i);
+                let table = cloned.open_table(definition).unwrap();
+                assert_eq!(table.len().unwrap(), 100);
+            });
+        }
+    });
+
+    // The snapshot remains valid until every handle has been released
+    read_txn.close().unwrap();
+    for i in 0..3 {
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(definition).unwrap();
+            for j in 0..100 {
+                table.insert(&j, &(i + 1000)).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+    }
+    let table = cloned.open_table(definition).unwrap();
+    assert_eq!(table.get(&99).unwrap().unwrap(), 99);
+}
+
+#[test]
+fn commit_stats() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::builder()
+        .set_write_strategy(WriteStrategy::TwoPhase)
+        .create(tmpfile.path())
+        .unwrap();
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        for i in 0..1000 {
+            table.insert(&i, &i).unwrap();
+        }
+    }
+    let stats = txn.commit_with_stats().unwrap();
+    assert!(stats.pages_allocated() > 1);
+    // Each entry is 16 bytes
+    assert!(stats.bytes_written() >= 1000 * 16);
+    // Two-phase commit syncs before and after updating the header
+    assert_eq!(stats.syncs(), 2);
+    assert!(stats.total_time() >= stats.write_time());
+
+    let mut txn = db.begin_write().unwrap();
+    txn.set_durability(Durability::None);
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        table.insert(&0, &1).unwrap();
+    }
+    let stats = txn.commit_with_stats().unwrap();
+    assert_eq!(stats.syncs(), 0);
+    assert_eq!(stats.pages_freed(), 0);
+}
+
+#[test]
+fn metrics() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let initial = db.metrics().unwrap();
+    assert_eq!(initial.commits(), 0);
+    assert_eq!(initial.aborts(), 0);
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        for i in 0..1000 {
+            table.insert(&i, &i).unwrap();
+        }
+    }
+    let stats = txn.commit_with_stats().unwrap();
+    db.begin_write().unwrap().abort().unwrap();
+    drop(db.begin_write().unwrap());
+    let txn = db.begin_read().unwrap();
+
+    let metrics = db.metrics().unwrap();
+    assert_eq!(metrics.commits(), 1);
+    assert_eq!(metrics.aborts(), 2);
+    assert_eq!(metrics.pages_allocated(), stats.pages_allocated());
+    assert_eq!(metrics.bytes_written(), stats.bytes_written());
+    assert_eq!(metrics.syncs(), initial.syncs() + stats.syncs());
+    assert_eq!(metrics.open_read_transactions(), 1);
+    assert_eq!(
+        metrics.file_size(),
+        fs::metadata(tmpfile.path()).unwrap().len()
+    );
+    assert!(metrics.allocated_bytes() > initial.allocated_bytes());
+    assert!(metrics.allocated_bytes() < metrics.file_size());
+    // The memory mapped backend doesn't have a cache
+    assert_eq!(metrics.cache_hit_rate(), None);
+    txn.close().unwrap();
+    assert_eq!(db.metrics().unwrap().open_read_transactions(), 0);
+}
+
+#[test]
+fn transaction_memory_limit() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Builder::new()
+        .set_write_transaction_memory_limit(1024 * 1024)
+        .create(tmpfile.path())
+        .unwrap();
+    let value = vec![0u8; 1000];
+
+    let txn = db.begin_write().unwrap();
+    let initial = txn.memory_usage();
+    let mut inserted = 0usize;
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        loop {
+            match table.insert(&inserted.to_le_bytes(), value.as_slice()) {
+                Ok(_) => inserted += 1,
+                Err(Error::TransactionMemoryLimitExceeded(bytes)) => {
+                    assert!(bytes > 1024 * 1024);
+                    break;
+                }
+                Err(err) => panic!("{}", err),
+            }
+            assert!(inserted < 10_000);
+        }
+        assert_eq!(table.len().unwrap(), inserted);
+    }
+    assert!(txn.memory_usage() > initial);
+    txn.commit().unwrap();
+
+    // The limit applies to each transaction separately, and can be exceeded by one operation
+    let txn = db.begin_write().unwrap();
+    assert!(txn.memory_usage() < 1024 * 1024);
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        let keys: Vec<[u8; 8]> = (0..2000u64).map(|i| i.to_be_bytes()).collect();
+        table
+            .extend(keys.iter().map(|key| (key.as_slice(), value.as_slice())))
+            .unwrap();
+        assert!(txn.memory_usage() > 1024 * 1024);
+        assert!(matches!(
+            table.insert(b"x".as_slice(), value.as_slice()),
+            Err(Error::TransactionMemoryLimitExceeded(_))
+        ));
+        // Removals are not limited
+        table.remove(keys[0].as_slice()).unwrap();
+    }
+    txn.abort().unwrap();
+
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(SLICE_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), inserted);
+}
+
+#[test]
+fn poisoned_transaction() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        for i in 0..100 {
+            table.insert(&i, &i).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+
+    // A panic during an operation poisons the transaction, and its handles
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            table.retain(|key, _| {
+                assert!(key < 50);
+                false
+            })
+        }));
+        assert!(result.is_err());
+        assert!(matches!(
+            table.insert(&0, &0),
+            Err(Error::TransactionPoisoned)
+        ));
+        // Reads would observe the partially applied retain()
+        assert!(matches!(table.get(&99), Err(Error::TransactionPoisoned)));
+        assert!(matches!(table.len(), Err(Error::TransactionPoisoned)));
+        assert!(matches!(table.range(0..), Err(Error::TransactionPoisoned)));
+    }
+    assert!(matches!(
+        txn.open_table(U64_TABLE),
+        Err(Error::TransactionPoisoned)
+    ));
+    assert!(matches!(txn.commit(), Err(Error::TransactionPoisoned)));
+
+    // A transaction which is dropped by the panic is rolled back
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let txn = db.begin_write().unwrap();
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        table.insert(&1000, &1000).unwrap();
+        panic!();
+    }));
+    assert!(result.is_err());
+
+    let txn = db.begin_write().unwrap();
+    {
+        let table = txn.open_table(U64_TABLE).unwrap();
+        assert_eq!(table.len().unwrap(), 100);
+        assert_eq!(table.get(&0).unwrap(), Some(0));
+    }
+    txn.commit().unwrap();
+}
+
+#[test]
+fn allocation_hook() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let events = Arc::new(Mutex::new(vec![]));
+    let events2 = events.clone();
+    let db = Builder::new()
+        .set_allocation_hook(move |event| {
+            events2.lock().unwrap().push((
+                event.kind(),
+                event.page_number(),
+                event.size(),
+                event.table().map(|table| table.to_string()),
+            ));
+        })
+        .create(tmpfile.path())
+        .unwrap();
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        for i in 0..1000 {
+            table.insert(&i, &i).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+    let committed = mem::take(&mut *events.lock().unwrap());
+    let table_pages = committed
+        .iter()
+        .filter(|(kind, _, _, table)| {
+            *kind == AllocationEventKind::Allocate && table.as_deref() == Some("u64")
+        })
+        .count();
+    assert!(table_pages > 1);
+    // The table of tables is not attributed to a table
+    assert!(committed
+        .iter()
+        .any(|(kind, _, _, table)| *kind == AllocationEventKind::Allocate && table.is_none()));
+    assert!(committed.iter().all(|(_, _, size, _)| *size == 4096));
+
+    // Aborting reports the inverse of each event, so the allocations of each page cancel out
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        for i in 1000..2000 {
+            table.insert(&i, &i).unwrap();
+        }
+        table.remove(&0).unwrap();
+    }
+    txn.abort().unwrap();
+    let mut balance: HashMap<u64, i64> = HashMap::new();
+    for (kind, page, _, _) in events.lock().unwrap().iter() {
+        *balance.entry(*page).or_default() += match kind {
+            AllocationEventKind::Allocate => 1,
+            AllocationEventKind::Free => -1,
+        };
+    }
+    assert!(!balance.is_empty());
+    assert!(balance.values().all(|count| *count == 0));
+}
+
+#[test]
+fn long_read_transaction_hook() {
+    fn commit(db: &Database) {
+        let write_txn = db.begin_write().unwrap();
+        write_txn.open_table(U64_TABLE).unwrap();
+        write_txn.commit().unwrap();
+    }
+
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let reported = Arc::new(Mutex::new(vec![]));
+    let reported2 = reported.clone();
+    let db = Builder::new()
+        .set_simulation_seed(7)
+        .set_long_read_transaction_hook(Duration::from_secs(10), move |info| {
+            assert!(info.age() >= Duration::from_secs(10));
+            reported2.lock().unwrap().push(info.transaction_id());
+        })
+        .create(tmpfile.path())
+        .unwrap();
+    commit(&db);
+
+    let read_txn = db.begin_read().unwrap();
+    commit(&db);
+    assert!(reported.lock().unwrap().is_empty());
+
+    db.advance_simulated_time(Duration::from_secs(11));
+    commit(&db);
+    assert_eq!(*reported.lock().unwrap(), vec![read_txn.transaction_id()]);
+    // Each transaction is only reported once
+    commit(&db);
+    assert_eq!(reported.lock().unwrap().len(), 1);
+    drop(read_txn);
+
+    let read_txn = db.begin_read().unwrap();
+    db.advance_simulated_time(Duration::from_secs(11));
+    commit(&db);
+    assert_eq!(reported.lock().unwrap().len(), 2);
+    assert_eq!(reported.lock().unwrap()[1], read_txn.transaction_id());
+}
+
+#[test]
+fn transaction_ids() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    let initial = db.begin_read().unwrap();
+    let txn = db.begin_write().unwrap();
+    let id = txn.transaction_id();
+    assert!(id > initial.transaction_id());
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        table.insert(&0, &0).unwrap();
+    }
+    txn.commit().unwrap();
+    assert_eq!(db.begin_read().unwrap().transaction_id(), id);
+
+    let mut txn = db.begin_write().unwrap();
+    txn.set_durability(Durability::None);
+    let non_durable_id = txn.transaction_id();
+    assert!(non_durable_id > id);
+    txn.commit().unwrap();
+    assert_eq!(db.begin_read().unwrap().transaction_id(), non_durable_id);
+
+    // Aborted transactions do not change the snapshot
+    let txn = db.begin_write().unwrap();
+    assert!(txn.transaction_id() > non_durable_id);
+    txn.abort().unwrap();
+    assert_eq!(db.begin_read().unwrap().transaction_id(), non_durable_id);
+    drop(initial);
+    drop(db);
+
+    let db = Database::open(tmpfile.path()).unwrap();
+    assert!(db.begin_read().unwrap().transaction_id() >= id);
+    assert!(db.begin_write().unwrap().transaction_id() > non_durable_id);
+}
+
+#[test]
+fn open_read_transactions() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    assert!(db.open_read_transactions().is_empty());
+
+    let old = db.begin_read().unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        table.insert(&0, &0).unwrap();
+    }
+    txn.commit().unwrap();
+    let new = db.begin_read().unwrap();
+    let cloned = new.clone();
+
+    let open = db.open_read_transactions();
+    assert_eq!(open.len(), 3);
+    assert_eq!(open[0].transaction_id(), old.transaction_id());
+    assert_eq!(open[1].transaction_id(), new.transaction_id());
+    assert_eq!(open[2].transaction_id(), new.transaction_id());
+    assert!(open[0].age() >= open[1].age());
+    assert_eq!(
+        open[0].backtrace().is_some(),
+        cfg!(feature = "read_transaction_backtraces")
+    );
+
+    drop(old);
+    new.close().unwrap();
+    let open = db.open_read_transactions();
+    assert_eq!(open.len(), 1);
+    assert_eq!(open[0].transaction_id(), cloned.transaction_id());
+    drop(cloned);
+    assert!(db.open_read_transactions().is_empty());
+}
+
+#[test]
+fn snapshot_retention() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::builder()
+        .set_retention_policy(RetentionPolicy {
+            commits: 2,
+            duration: Duration::ZERO,
+        })
+        .create(tmpfile.path())
+        .unwrap();
+
+    let mut ids = vec![];
+    for i in 0..6 {
+        let txn = db.begin_write().unwrap();
+        ids.push(txn.transaction_id());
+        {
+            let mut table = txn.open_table(U64_TABLE).unwrap();
+            for key in 0..1000 {
+                table.insert(&key, &i).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+    }
+
+    // The latest snapshot, and the two before it, are retained
+    for (i, id) in ids.iter().enumerate() {
+        let txn = db.begin_read_at(*id).unwrap();
+        if i < 3 {
+            assert!(txn.is_none());
+            continue;
+        }
+        let txn = txn.unwrap();
+        assert_eq!(txn.transaction_id(), *id);
+        let table = txn.open_table(U64_TABLE).unwrap();
+        for key in 0..1000 {
+            assert_eq!(table.get(&key).unwrap().unwrap(), i as u64);
+        }
+    }
+}
+
+#[test]
+fn simulated_time() {
+    fn run() -> (Vec<Duration>, Vec<bool>) {
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        let db = Database::builder()
+            .set_simulation_seed(42)
+            .set_retention_policy(RetentionPolicy {
+                commits: 0,
+                duration: Duration::from_secs(60),
+            })
+            .create(tmpfile.path())
+            .unwrap();
+
+        let mut ids = vec![];
+        let mut readers = vec![];
+        for i in 0..3 {
+            let txn = db.begin_write().unwrap();
+            ids.push(txn.transaction_id());
+            {
+                let mut table = txn.open_table(U64_TABLE).unwrap();
+                table.insert(&i, &i).unwrap();
+            }
+            txn.commit().unwrap();
+            readers.push(db.begin_read().unwrap());
+            db.advance_simulated_time(Duration::from_secs(45));
+        }
+        let ages = db
+            .open_read_transactions()
+            .iter()
+            .map(|info| info.age())
+            .collect();
+        drop(readers);
+
+        // When the last commit completed, 90 seconds of simulated time had passed since the first
+        // one, so only its snapshot had expired
+        let retained = ids
+            .iter()
+            .map(|id| db.begin_read_at(*id).unwrap().is_some())
+            .collect();
+
+        (ages, retained)
+    }
+
+    let (ages, retained) = run();
+    assert_eq!(ages.len(), 3);
+    assert!(ages[0] > Duration::from_secs(135));
+    assert!(ages[2] > Duration::from_secs(45));
+    assert!(ages[2] < Duration::from_secs(46));
+    assert_eq!(retained, vec![false, true, true]);
+    assert_eq!(run(), (ages, retained));
+}
+
+#[test]
+fn write_batch() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        table.insert(&1, &1).unwrap();
+        table.insert(&2, &2).unwrap();
+    }
+    write_txn.commit().unwrap();
+
+    let mut batch = WriteBatch::new();
+    assert!(batch.is_empty());
+    batch.insert(U64_TABLE, &3, &3);
+    batch.remove(U64_TABLE, &1);
+    batch.insert(SLICE_TABLE, b"hello", b"world");
+    // Operations on the same table are applied in order
+    batch.insert(U64_TABLE, &2, &20);
+    batch.remove(U64_TABLE, &3);
+    assert_eq!(batch.len(), 5);
+
+    // Building the batch does not need the write lock
+    let write_txn = db.begin_write().unwrap();
+    write_txn.abort().unwrap();
+    db.apply(batch).unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.get(&1).unwrap(), None);
+    assert_eq!(table.get(&2).unwrap(), Some(20));
+    assert_eq!(table.get(&3).unwrap(), None);
+    let table = read_txn.open_table(SLICE_TABLE).unwrap();
+    assert_eq!(table.get(b"hello").unwrap(), Some(b"world".as_slice()));
+
+    // A failed batch is not applied
+    let mut batch = WriteBatch::new();
+    batch.insert(U64_TABLE, &4, &4);
+    batch.insert(TableDefinition::<u64, &str>::new("u64"), &5, "five");
+    assert!(matches!(
+        db.apply(batch),
+        Err(Error::TableTypeMismatch { .. })
+    ));
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.get(&4).unwrap(), None);
+}
+
+#[test]
+fn two_phase_commit() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        table.insert(&1, &1).unwrap();
+    }
+    let prepared = write_txn.prepare().unwrap();
+    // Prepared writes are not visible
+    let read_txn = db.begin_read().unwrap();
+    assert!(read_txn.open_table(U64_TABLE).is_err());
+    drop(read_txn);
+    prepared.commit().unwrap();
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        table.insert(&2, &2).unwrap();
+    }
+    write_txn.prepare().unwrap().rollback().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.get(&1).unwrap(), Some(1));
+    assert_eq!(table.get(&2).unwrap(), None);
+    drop(table);
+    drop(read_txn);
+
+    // Copying the file while a transaction is prepared simulates a crash
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        table.insert(&3, &3).unwrap();
+    }
+    let prepared = write_txn.prepare().unwrap();
+    let prepared_id = prepared.transaction_id();
+    let crashed1: NamedTempFile = NamedTempFile::new().unwrap();
+    let crashed2: NamedTempFile = NamedTempFile::new().unwrap();
+    fs::copy(tmpfile.path(), crashed1.path()).unwrap();
+    fs::copy(tmpfile.path(), crashed2.path()).unwrap();
+    drop(prepared);
+
+    // By default, the prepared transaction is rolled back
+    let db = Database::open(crashed1.path()).unwrap();
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.get(&1).unwrap(), Some(1));
+    assert_eq!(table.get(&3).unwrap(), None);
+
+    let db = Builder::new()
+        .set_prepared_transaction_resolver(move |id| {
+            assert_eq!(id, prepared_id);
+            true
+        })
+        .create(crashed2.path())
+        .unwrap();
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.get(&1).unwrap(), Some(1));
+    assert_eq!(table.get(&3).unwrap(), Some(3));
+}
+
+#[test]
+fn commit_callbacks() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Arc::new(Database::create(tmpfile.path()).unwrap());
+    let calls = Arc::new(std::sync::Mutex::new(vec![]));
+
+    let write_txn = db.begin_write().unwrap();
+    let calls2 = calls.clone();
+    write_txn.on_commit(move || calls2.lock().unwrap().push(1));
+    write_txn.abort().unwrap();
+    assert!(calls.lock().unwrap().is_empty());
+
+    let mut write_txn = db.begin_write().unwrap();
+    write_txn.set_durability(Durability::None);
+    let calls2 = calls.clone();
+    write_txn.on_commit(move || calls2.lock().unwrap().push(2));
+    write_txn.commit().unwrap();
+    // Not yet durable
+    assert!(calls.lock().unwrap().is_empty());
+
+    let write_txn = db.begin_write().unwrap();
+    let calls2 = calls.clone();
+    let db2 = db.clone();
+    write_txn.on_commit(move || {
+        // The write lock has been released
+        db2.begin_write().unwrap().abort().unwrap();
+        calls2.lock().unwrap().push(3);
+    });
+    let calls2 = calls.clone();
+    write_txn.on_commit(move || calls2.lock().unwrap().push(4));
+    write_txn.commit().unwrap();
+    assert_eq!(*calls.lock().unwrap(), vec![2, 3, 4]);
+}
+
+#[test]
+fn nested_transactions() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    let mut write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        table.insert(&1, &1).unwrap();
+    }
+    {
+        let nested = write_txn.begin_nested().unwrap();
+        {
+            let mut table = nested.open_table(U64_TABLE).unwrap();
+            table.insert(&1, &10).unwrap();
+            table.insert(&2, &2).unwrap();
+        }
+        nested.commit().unwrap();
+    }
+    {
+        let mut nested = write_txn.begin_nested().unwrap();
+        {
+            let mut table = nested.open_table(U64_TABLE).unwrap();
+            for i in 0..1000 {
+                table.insert(&i, &i).unwrap();
+            }
+            table.remove(&2).unwrap();
+        }
+        nested.open_table(SLICE_TABLE).unwrap();
+        {
+            let inner = nested.begin_nested().unwrap();
+            inner
+                .open_table(U64_TABLE)
+                .unwrap()
+                .insert(&3000, &3000)
+                .unwrap();
+            inner.commit().unwrap();
+        }
+        nested.abort().unwrap();
+    }
+    {
+        // Dropping a nested transaction aborts it
+        let nested = write_txn.begin_nested().unwrap();
+        nested.open_table(U64_TABLE).unwrap().remove(&1).unwrap();
+    }
+    {
+        let nested = write_txn.begin_nested().unwrap();
+        {
+            let mut inner = nested;
+            let inner2 = inner.begin_nested().unwrap();
+            inner2
+                .open_table(U64_TABLE)
+                .unwrap()
+                .insert(&3, &3)
+                .unwrap();
+            inner2.commit().unwrap();
+            inner.commit().unwrap();
+        }
+    }
+    {
+        let table = write_txn.open_table(U64_TABLE).unwrap();
+        assert_eq!(table.len().unwrap(), 3);
+        assert_eq!(table.get(&1).unwrap(), Some(10));
+        assert_eq!(table.get(&2).unwrap(), Some(2));
+        assert_eq!(table.get(&3).unwrap(), Some(3));
+    }
+    assert!(write_txn
+        .list_tables()
+        .unwrap()
+        .all(|name| name != SLICE_TABLE.name()));
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 3);
+    assert_eq!(table.get(&1).unwrap(), Some(10));
+}
+
+#[test]
+fn nested_commit_reuses_pages() {
+    let value = vec![0u8; 64 * 1024];
+
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    for i in 0..100u64 {
+        write_txn
+            .open_table(SLICE_TABLE)
+            .unwrap()
+            .insert(&[], &value[(i as usize)..])
+            .unwrap();
+    }
+    write_txn.commit().unwrap();
+    let expected_size = tmpfile.as_file().metadata().unwrap().len();
+
+    // Pages written before a nested transaction, and replaced within it, are reused after it
+    // commits, just as they are without nesting
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let mut write_txn = db.begin_write().unwrap();
+    for i in 0..100u64 {
+        let nested = write_txn.begin_nested().unwrap();
+        nested
+            .open_table(SLICE_TABLE)
+            .unwrap()
+            .insert(&[], &value[(i as usize)..])
+            .unwrap();
+        nested.commit().unwrap();
+    }
+    write_txn.commit().unwrap();
+    assert!(tmpfile.as_file().metadata().unwrap().len() <= expected_size);
+}
+
+#[test]
+fn group_commit() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    thread::scope(|s| {
+        for t in 0..4 {
+            let db = &db;
+            s.spawn(move || {
+                for i in 0..50 {
+                    let mut txn = db.begin_write().unwrap();
+                    txn.set_durability(Durability::Grouped);
+                    {
+                        let mut table = txn.open_table(U64_TABLE).unwrap();
+                        table.insert(&(t * 1000 + i), &i).unwrap();
+                    }
+                    txn.commit().unwrap();
+                }
+            });
+        }
+    });
+    drop(db);
+
+    let db = Database::open(tmpfile.path()).unwrap();
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 200);
+    assert_eq!(table.get(&3049).unwrap(), Some(49));
+}
+
+#[test]
+fn key_change_notifications() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let receiver = db.subscribe_key_changes(3);
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        table.insert(&1, &1).unwrap();
+    }
+    write_txn.abort().unwrap();
+    assert!(receiver.try_recv().is_none());
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        table.insert(&1, &1).unwrap();
+        table.remove(&2).unwrap();
+    }
+    write_txn.commit().unwrap();
+    let change = receiver.try_recv().unwrap();
+    assert_eq!(change.table(), "u64");
+    assert_eq!(change.key_hash(), Some(KeyChange::hash_key::<u64>(&1)));
+    let change = receiver.try_recv().unwrap();
+    assert_eq!(change.key_hash(), Some(KeyChange::hash_key::<u64>(&2)));
+    assert!(receiver.try_recv().is_none());
+    assert_eq!(receiver.take_missed(), 0);
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..5 {
+            table.insert(&i, &i).unwrap();
+        }
+    }
+    write_txn.delete_table(U64_TABLE).unwrap();
+    write_txn.commit().unwrap();
+    // Only the most recent notifications are retained
+    assert_eq!(receiver.take_missed(), 3);
+    assert_eq!(
+        receiver.try_recv().unwrap().key_hash(),
+        Some(KeyChange::hash_key::<u64>(&3))
+    );
+    assert_eq!(
+        receiver.try_recv().unwrap().key_hash(),
+        Some(KeyChange::hash_key::<u64>(&4))
+    );
+    assert_eq!(receiver.try_recv().unwrap().key_hash(), None);
+}
+
+#[test]
+fn key_change_notifications_failed_mutation() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Builder::new()
+        .set_region_size(64 * 1024)
+        .create(tmpfile.path())
+        .unwrap();
+    let receiver = db.subscribe_key_changes(3);
+
+    // A mutation which fails is not reported
+    let big_value = vec![0u8; 128 * 1024];
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
+        assert!(table
+            .insert(b"big".as_slice(), big_value.as_slice())
+            .is_err());
+        table
+            .insert(b"small".as_slice(), b"value".as_slice())
+            .unwrap();
+    }
+    write_txn.commit().unwrap();
+    assert_eq!(
+        receiver.try_recv().unwrap().key_hash(),
+        Some(KeyChange::hash_key::<&[u8]>(b"small".as_slice()))
+    );
+    assert!(receiver.try_recv().is_none());
+}
+
+#[cfg(feature = "integrity_checks")]
+#[test]
+fn integrity_check_detects_inconsistent_key_order() {
+    use redb::{KeyOrder, OrderedKey};
+    use std::cmp::Ordering;
+    use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+
+    static REVERSED: AtomicBool = AtomicBool::new(false);
+
+    // A buggy order, which changes between transactions
+    struct Unstable;
+
+    impl KeyOrder<u64> for Unstable {
+        fn compare(data1: &[u8], data2: &[u8]) -> Ordering {
+            let ordering = u64::from_le_bytes(data1.try_into().unwrap())
+                .cmp(&u64::from_le_bytes(data2.try_into().unwrap()));
+            if REVERSED.load(AtomicOrdering::SeqCst) {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        }
+
+        fn name() -> String {
+            "unstable".to_string()
+        }
+    }
+
+    let definition: TableDefinition<OrderedKey<u64, Unstable>, u64> = TableDefinition::new("x");
+
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(definition).unwrap();
+        for i in 0..10u64 {
+            table.insert(&i, &i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    REVERSED.store(true, AtomicOrdering::SeqCst);
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(definition).unwrap();
+        table.insert(&100, &100).unwrap();
+    }
+    match write_txn.commit() {
+        Err(Error::Corrupted { message, .. }) => {
+            assert!(message.contains("out of order"), "{}", message)
+        }
+        other => panic!("expected integrity check failure, got {:?}", other.err()),
+    }
+}
+
+#[cfg(feature = "integrity_checks")]
+#[test]
+fn integrity_check_detects_inconsistent_multimap_value_order() {
+    use redb::{KeyOrder, OrderedKey};
+    use std::cmp::Ordering;
+    use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+
+    static REVERSED: AtomicBool = AtomicBool::new(false);
+
+    // A buggy order, which changes between transactions
+    struct Unstable;
+
+    impl KeyOrder<u64> for Unstable {
+        fn compare(data1: &[u8], data2: &[u8]) -> Ordering {
+            let ordering = u64::from_le_bytes(data1.try_into().unwrap())
+                .cmp(&u64::from_le_bytes(data2.try_into().unwrap()));
+            if REVERSED.load(AtomicOrdering::SeqCst) {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        }
+
+        fn name() -> String {
+            "unstable".to_string()
+        }
+    }
+
+    let definition: MultimapTableDefinition<u64, OrderedKey<u64, Unstable>> =
+        MultimapTableDefinition::new("x");
+
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_multimap_table(definition).unwrap();
+        for i in 0..10u64 {
+            table.insert(&1, &i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    REVERSED.store(true, AtomicOrdering::SeqCst);
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_multimap_table(definition).unwrap();
+        table.insert(&1, &100).unwrap();
+    }
+    match write_txn.commit() {
+        Err(Error::Corrupted { message, .. }) => {
+            assert!(message.contains("out of order"), "{}", message)
+        }
+        other => panic!("expected integrity check failure, got {:?}", other.err()),
+    }
+}
+
+#[cfg(feature = "raw_page_access")]
+#[test]
+fn read_raw_page() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..1000 {
+            table.insert(&i, &i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    for page in table.pages().unwrap() {
+        let page = page.unwrap();
+        // Safety: no write transaction is open
+        let data = unsafe { read_txn.read_raw_page(page.page_number()) }
+            .unwrap()
+            .unwrap();
+        assert_eq!(data.len(), page.page_size());
+        // The first byte of a page is its type: 1 for a leaf, or 2 for a branch
+        assert_eq!(data[0], if page.is_leaf() { 1 } else { 2 });
+    }
+
+    // A page beyond the end of the file
+    assert!(unsafe { read_txn.read_raw_page(0xF_FFFF) }
+        .unwrap()
+        .is_none());
+}
+
+#[cfg(all(unix, feature = "fault_injection"))]
+#[test]
+fn fault_injection() {
+    use redb::{Fault, FaultInjector};
+
+    // Commits five transactions of 100 entries each, and returns the number which succeeded
+    fn workload(db: &Database) -> u64 {
+        for i in 0..5 {
+            let result = (|| {
+                let write_txn = db.begin_write()?;
+                {
+                    let mut table = write_txn.open_table(U64_TABLE)?;
+                    for j in (i * 100)..((i + 1) * 100) {
+                        table.insert(&j, &j)?;
+                    }
+                }
+                write_txn.commit()
+            })();
+            if result.is_err() {
+                return i;
+            }
+        }
+        5
+    }
+
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let injector = FaultInjector::new();
+    let db = Builder::new()
+        .set_fault_injector(injector.clone())
+        .create(tmpfile.path())
+        .unwrap();
+    assert_eq!(workload(&db), 5);
+    let operations = injector.operations();
+    assert!(operations > 0);
+    drop(db);
+
+    // Every crash leaves the database with the entries of the transactions which committed, and
+    // possibly the one which was committing
+    for operation in 0..operations {
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        let injector = FaultInjector::new();
+        let db = Builder::new()
+            .set_fault_injector(injector.clone())
+            .create(tmpfile.path())
+            .unwrap();
+        injector.inject(operation, Fault::Crash(1000));
+        let committed = workload(&db);
+        assert!(injector.has_crashed());
+        drop(db);
+
+        let db = Database::open(tmpfile.path()).unwrap();
+        assert!(db.check_integrity().unwrap().is_ok());
+        let read_txn = db.begin_read().unwrap();
+        let len = match read_txn.open_table(U64_TABLE) {
+            Ok(table) => table.len().unwrap() as u64,
+            Err(Error::TableDoesNotExist(_)) => 0,
+            Err(err) => panic!("{}", err),
+        };
+        assert!(len == committed * 100 || len == (committed + 1) * 100);
+    }
+
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let injector = FaultInjector::new();
+    let db = Builder::new()
+        .set_fault_injector(injector.clone())
+        .create(tmpfile.path())
+        .unwrap();
+    injector.inject(0, Fault::StorageFull);
+    let result = (|| {
+        let write_txn = db.begin_write()?;
+        write_txn.open_table(U64_TABLE)?;
+        write_txn.commit()
+    })();
+    assert!(matches!(
+        result,
+        Err(Error::StorageFull { source: err, .. }) if err.raw_os_error() == Some(libc::ENOSPC)
+    ));
+    assert!(!injector.has_crashed());
+}
+
+#[cfg(feature = "fault_injection")]
+#[test]
+fn grouped_commit_not_durable() {
+    use redb::{Fault, FaultInjector};
+
+    fn grouped_commit(db: &Database) -> Result<(), Error> {
+        let mut write_txn = db.begin_write()?;
+        write_txn.set_durability(Durability::Grouped);
+        write_txn.open_table(U64_TABLE)?.insert(&0, &0)?;
+        write_txn.commit()
+    }
+
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let injector = FaultInjector::new();
+    let db = Builder::new()
+        .set_fault_injector(injector.clone())
+        .create(tmpfile.path())
+        .unwrap();
+    grouped_commit(&db).unwrap();
+    let operations = injector.operations();
+    assert!(operations > 0);
+    drop(db);
+
+    // The last operation is part of the sync, which happens after the commit is visible
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let injector = FaultInjector::new();
+    let db = Builder::new()
+        .set_fault_injector(injector.clone())
+        .create(tmpfile.path())
+        .unwrap();
+    injector.inject(operations - 1, Fault::Error(ErrorKind::Other));
+    assert!(matches!(
+        grouped_commit(&db),
+        Err(Error::NotDurable(err)) if matches!(*err, Error::Io { .. })
+    ));
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.get(&0).unwrap(), Some(0));
+}
+
+#[cfg(all(unix, feature = "fault_injection"))]
+#[test]
+fn storage_full() {
+    use redb::{Fault, FaultInjector};
+
+    fn insert_batch(db: &Database, batch: u64) -> Result<(), Error> {
+        let write_txn = db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(U64_TABLE)?;
+            for i in (batch * 100)..((batch + 1) * 100) {
+                table.insert(&i, &i)?;
+            }
+        }
+        write_txn.commit()
+    }
+
+    fn len(db: &Database) -> u64 {
+        let read_txn = db.begin_read().unwrap();
+        let table = read_txn.open_table(U64_TABLE).unwrap();
+        table.len().unwrap() as u64
+    }
+
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let injector = FaultInjector::new();
+    let db = Builder::new()
+        .set_fault_injector(injector.clone())
+        .create(tmpfile.path())
+        .unwrap();
+    insert_batch(&db, 0).unwrap();
+    let start = injector.operations();
+    insert_batch(&db, 1).unwrap();
+    let operations = injector.operations() - start;
+    assert!(operations > 0);
+    drop(db);
+
+    // Whichever write or resize runs out of space, the database remains at the previous commit,
+    // and the transaction succeeds once it's retried
+    for operation in 0..operations {
+        let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+        let injector = FaultInjector::new();
+        let db = Builder::new()
+            .set_fault_injector(injector.clone())
+            .create(tmpfile.path())
+            .unwrap();
+        insert_batch(&db, 0).unwrap();
+        injector.inject(injector.operations() + operation, Fault::StorageFull);
+        assert!(matches!(
+            insert_batch(&db, 1),
+            Err(Error::StorageFull { .. })
+        ));
+        assert_eq!(len(&db), 100);
+
+        insert_batch(&db, 1).unwrap();
+        assert_eq!(len(&db), 200);
+        assert!(db.check_integrity().unwrap().is_ok());
+        drop(db);
+
+        let db = Database::open(tmpfile.path()).unwrap();
+        assert_eq!(len(&db), 200);
+        assert!(db.check_integrity().unwrap().is_ok());
+    }
 }
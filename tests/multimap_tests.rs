@@ -25,7 +25,7 @@ fn get_vec(
 #[test]
 fn len() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
     {
         let mut table = write_txn.open_multimap_table(STR_TABLE).unwrap();
@@ -43,7 +43,7 @@ fn len() {
 #[test]
 fn is_empty() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let write_txn = db.begin_write().unwrap();
     {
@@ -60,7 +60,7 @@ fn is_empty() {
 #[test]
 fn insert() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
     {
         let mut table = write_txn.open_multimap_table(STR_TABLE).unwrap();
@@ -82,7 +82,7 @@ fn insert() {
 #[test]
 fn range_query() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
     {
         let mut table = write_txn.open_multimap_table(SLICE_U64_TABLE).unwrap();
@@ -132,7 +132,7 @@ fn range_query() {
 #[test]
 fn delete() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
     {
         let mut table = write_txn.open_multimap_table(STR_TABLE).unwrap();
@@ -186,10 +186,50 @@ fn delete() {
     assert_eq!(empty, get_vec(&table, "hello"));
 }
 
+#[test]
+fn remove_all_subtree() {
+    let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_multimap_table(U64_TABLE).unwrap();
+        // Enough values that they are stored in a subtree, rather than inline
+        for i in (0..10_000u64).rev() {
+            table.insert(&1, &i).unwrap();
+        }
+        table.insert(&0, &0).unwrap();
+        table.insert(&2, &2).unwrap();
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_multimap_table(U64_TABLE).unwrap();
+    assert_eq!(
+        table.get(&1).unwrap().collect::<Vec<_>>(),
+        (0..10_000u64).collect::<Vec<_>>()
+    );
+    assert_eq!(table.get(&1).unwrap().next_back(), Some(9_999));
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_multimap_table(U64_TABLE).unwrap();
+        let removed: Vec<u64> = table.remove_all(&1).unwrap().collect();
+        assert_eq!(removed, (0..10_000u64).collect::<Vec<_>>());
+        assert_eq!(table.get(&1).unwrap().count(), 0);
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_multimap_table(U64_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 2);
+    assert_eq!(table.get(&0).unwrap().collect::<Vec<_>>(), vec![0]);
+    assert_eq!(table.get(&2).unwrap().collect::<Vec<_>>(), vec![2]);
+}
+
 #[test]
 fn wrong_types() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
 
     let definition: MultimapTableDefinition<u32, u32> = MultimapTableDefinition::new("x");
     let wrong_definition: MultimapTableDefinition<u64, u64> = MultimapTableDefinition::new("x");
@@ -201,7 +241,7 @@ fn wrong_types() {
     let txn = db.begin_write().unwrap();
     assert!(matches!(
         txn.open_multimap_table(wrong_definition),
-        Err(Error::TableTypeMismatch(_))
+        Err(Error::TableTypeMismatch { .. })
     ));
     txn.abort().unwrap();
 
@@ -209,7 +249,7 @@ fn wrong_types() {
     txn.open_multimap_table(definition).unwrap();
     assert!(matches!(
         txn.open_multimap_table(wrong_definition),
-        Err(Error::TableTypeMismatch(_))
+        Err(Error::TableTypeMismatch { .. })
     ));
 }
 
@@ -220,7 +260,7 @@ fn efficient_storage() {
     // Write enough values that big_key.len() * entries > db_size to check that duplicate key data is not stored
     // and entries * sizeof(u32) > page_size to validate that large numbers of values can be stored per key
     let entries = 10000;
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let table_def: MultimapTableDefinition<&[u8], u32> = MultimapTableDefinition::new("x");
     let write_txn = db.begin_write().unwrap();
     {
@@ -241,7 +281,7 @@ fn efficient_storage() {
 #[test]
 fn reopen_table() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
     {
         let mut table = write_txn.open_multimap_table(STR_TABLE).unwrap();
@@ -257,7 +297,7 @@ fn reopen_table() {
 #[test]
 fn iter() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let write_txn = db.begin_write().unwrap();
     {
         let mut table = write_txn.open_multimap_table(U64_TABLE).unwrap();
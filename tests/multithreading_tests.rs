@@ -8,7 +8,7 @@ const TABLE: TableDefinition<&str, &str> = TableDefinition::new("x");
 #[test]
 fn len() {
     let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
-    let db = unsafe { Database::create(tmpfile.path()).unwrap() };
+    let db = Database::create(tmpfile.path()).unwrap();
     let db = Arc::new(db);
     let write_txn = db.begin_write().unwrap();
     {